@@ -0,0 +1,222 @@
+//! Python bindings for the 9ladies library, so dataset pipelines can describe images without
+//! running the CLI in a subprocess and parsing its JSONL.
+//!
+//! ```python
+//! import nineladies
+//!
+//! nineladies.validate("a.jpg")
+//! record = nineladies.describe_image("a.jpg", "prompts/describe.json", model="llava:13b")
+//! for record in nineladies.run_batch(["a.jpg", "b.png"], "prompts/describe.json", model="llava:13b"):
+//!     print(record["file"], record.get("response"), record.get("error"))
+//! ```
+//!
+//! Records are the same dictionaries as a line of `9ladies run` output. The GIL is released
+//! while waiting for the model, so other Python threads keep running.
+
+// The `#[pyfunction]` expansion converts `PyErr` into itself
+#![allow(clippy::useless_conversion)]
+
+use std::path::Path;
+
+use pyo3::exceptions::{PyConnectionError, PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+use nineladies::{
+    load_prompt_config, validate_image_file, Api, Backend, BatchRunner, CallError, ItemError, OutputRecord,
+    DEFAULT_URL,
+};
+
+fn parse_api(api: &str) -> PyResult<Api> {
+    match api {
+        "ollama" => Ok(Api::Ollama),
+        "openai" => Ok(Api::Openai),
+        _ => Err(PyValueError::new_err(format!(
+            "Unknown api '{}' (expected 'ollama' or 'openai')",
+            api
+        ))),
+    }
+}
+
+fn build_runner(prompt: &str, model: Option<String>, url: &str, api: &str, timeout: Option<u64>) -> PyResult<BatchRunner> {
+    let api = parse_api(api)?;
+    let config = load_prompt_config(prompt).map_err(PyValueError::new_err)?;
+    let model = model.or_else(|| config.model.clone());
+    if model.is_none() && api == Api::Ollama {
+        return Err(PyValueError::new_err("model is required (or set 'model' in the prompt config)"));
+    }
+    Ok(BatchRunner::new(Backend::new(api, url, model, timeout), config))
+}
+
+/// Bad images raise `ValueError`, an unreachable server `ConnectionError`, and other model or
+/// script failures `RuntimeError`.
+fn item_error(e: ItemError) -> PyErr {
+    match e {
+        ItemError::Invalid(e) => PyValueError::new_err(e),
+        ItemError::Call(e @ CallError::Unreachable(_)) => PyConnectionError::new_err(e.to_string()),
+        e => PyRuntimeError::new_err(e.to_string()),
+    }
+}
+
+/// The record as a Python dict, with keys in the same order as the CLI's output.
+fn record_to_py(py: Python<'_>, record: &OutputRecord) -> PyResult<PyObject> {
+    let json = serde_json::to_string(record).expect("records serialize");
+    Ok(py.import_bound("json")?.call_method1("loads", (json,))?.unbind())
+}
+
+/// Check that `path` is a readable JPEG, PNG, WebP or GIF, raising `ValueError` if not.
+#[pyfunction]
+fn validate(py: Python<'_>, path: &str) -> PyResult<()> {
+    py.allow_threads(|| validate_image_file(Path::new(path)))
+        .map(|_| ())
+        .map_err(PyValueError::new_err)
+}
+
+/// Describe one image with the prompt config at `prompt`, returning its record.
+#[pyfunction]
+#[pyo3(signature = (path, prompt, *, model=None, url=DEFAULT_URL, api="ollama", timeout=None))]
+fn describe_image(
+    py: Python<'_>,
+    path: &str,
+    prompt: &str,
+    model: Option<String>,
+    url: &str,
+    api: &str,
+    timeout: Option<u64>,
+) -> PyResult<PyObject> {
+    let runner = build_runner(prompt, model, url, api, timeout)?;
+    let described = py.allow_threads(|| runner.describe(path)).map_err(item_error)?;
+    record_to_py(py, &described.record)
+}
+
+/// Describe images one at a time as the batch is iterated.
+#[pyclass]
+struct Batch {
+    runner: BatchRunner,
+    paths: std::vec::IntoIter<String>,
+}
+
+#[pymethods]
+impl Batch {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    /// The next record, or `{"file": ..., "error": ...}` for an image that failed. An
+    /// unreachable server raises `ConnectionError`, since every later image would fail too.
+    fn __next__(&mut self, py: Python<'_>) -> PyResult<Option<PyObject>> {
+        let Some(path) = self.paths.next() else {
+            return Ok(None);
+        };
+        let runner = &self.runner;
+        match py.allow_threads(|| runner.describe(&path)) {
+            Ok(described) => record_to_py(py, &described.record).map(Some),
+            Err(ItemError::Call(e @ CallError::Unreachable(_))) => Err(PyConnectionError::new_err(e.to_string())),
+            Err(e) => {
+                let failure = PyDict::new_bound(py);
+                failure.set_item("file", &path)?;
+                failure.set_item("error", e.to_string())?;
+                Ok(Some(failure.into_any().unbind()))
+            }
+        }
+    }
+}
+
+/// Describe each of `paths` in turn, yielding a record (or an error dict) per image.
+#[pyfunction]
+#[pyo3(signature = (paths, prompt, *, model=None, url=DEFAULT_URL, api="ollama", timeout=None))]
+fn run_batch(
+    paths: Vec<String>,
+    prompt: &str,
+    model: Option<String>,
+    url: &str,
+    api: &str,
+    timeout: Option<u64>,
+) -> PyResult<Batch> {
+    Ok(Batch {
+        runner: build_runner(prompt, model, url, api, timeout)?,
+        paths: paths.into_iter(),
+    })
+}
+
+#[pymodule]
+#[pyo3(name = "nineladies")]
+fn nineladies_python(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add("__version__", env!("CARGO_PKG_VERSION"))?;
+    module.add("DEFAULT_URL", DEFAULT_URL)?;
+    module.add_function(wrap_pyfunction!(validate, module)?)?;
+    module.add_function(wrap_pyfunction!(describe_image, module)?)?;
+    module.add_function(wrap_pyfunction!(run_batch, module)?)?;
+    module.add_class::<Batch>()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nineladies::PromptConfig;
+
+    fn runner() -> BatchRunner {
+        let config = PromptConfig {
+            system: String::new(),
+            prompt: "Describe this.".to_string(),
+            temperature: 0.0,
+            model: None,
+            schema: None,
+            seed: None,
+            think: None,
+            schema_via: None,
+        };
+        // Nothing listens on port 9
+        BatchRunner::new(Backend::new(Api::Ollama, "http://127.0.0.1:9", None, Some(1)), config)
+    }
+
+    #[test]
+    fn test_parse_api() {
+        pyo3::prepare_freethreaded_python();
+        assert_eq!(parse_api("ollama").unwrap(), Api::Ollama);
+        assert_eq!(parse_api("openai").unwrap(), Api::Openai);
+        Python::with_gil(|py| {
+            let err = parse_api("claude").unwrap_err();
+            assert!(err.is_instance_of::<PyValueError>(py));
+            assert!(err.to_string().contains("Unknown api 'claude'"), "{}", err);
+        });
+    }
+
+    #[test]
+    fn test_item_error() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let invalid = item_error(ItemError::Invalid("File not found: a.jpg".to_string()));
+            assert!(invalid.is_instance_of::<PyValueError>(py));
+            let unreachable = item_error(ItemError::Call(CallError::Unreachable("connection refused".to_string())));
+            assert!(unreachable.is_instance_of::<PyConnectionError>(py));
+            let failed = item_error(ItemError::Call(CallError::Failed("HTTP 500".to_string())));
+            assert!(failed.is_instance_of::<PyRuntimeError>(py));
+        });
+    }
+
+    #[test]
+    fn test_batch_next() {
+        pyo3::prepare_freethreaded_python();
+        let red = concat!(env!("CARGO_MANIFEST_DIR"), "/../tests/fixtures/red.png");
+        let mut batch = Batch {
+            runner: runner(),
+            paths: vec!["missing.png".to_string(), red.to_string()].into_iter(),
+        };
+        Python::with_gil(|py| {
+            // A bad image gives an error dict and the batch goes on
+            let failure = batch.__next__(py).unwrap().unwrap();
+            let failure = failure.downcast_bound::<PyDict>(py).unwrap();
+            assert_eq!(failure.len(), 2);
+            assert_eq!(failure.get_item("file").unwrap().unwrap().extract::<String>().unwrap(), "missing.png");
+            let error: String = failure.get_item("error").unwrap().unwrap().extract().unwrap();
+            assert!(error.contains("missing.png"), "{}", error);
+
+            // Every later image would fail too, so a down server raises
+            let err = batch.__next__(py).unwrap_err();
+            assert!(err.is_instance_of::<PyConnectionError>(py));
+            assert!(batch.__next__(py).unwrap().is_none());
+        });
+    }
+}
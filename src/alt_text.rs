@@ -0,0 +1,621 @@
+//! `9ladies alt-text`: finding images without alt text in HTML and Markdown files, asking
+//! the model for some, and patching the files or printing a diff; with `--audit`, reporting
+//! on the alt text already there.
+//!
+//! An `<img>` with no `alt` attribute and a Markdown `![](...)` with empty brackets count
+//! as missing. `alt=""` is left alone when patching, since it is how HTML marks decorative
+//! images, but audits list it for a person to check.
+
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use nineladies::backend::parse_model_content;
+use nineladies::PromptConfig;
+
+const SYSTEM: &str = "You write alt text for images on web pages, for readers using screen readers.";
+
+const PROMPT: &str = "Write alt text for this image: one concise sentence, under 125 characters, \
+describing what someone who can't see it needs to know. Don't start with \"Image of\" or \
+\"Picture of\", and don't describe the image's style unless it matters. Reply with only the alt text.";
+
+/// The prompt used unless `--prompt` names another.
+pub fn default_config() -> PromptConfig {
+    PromptConfig {
+        system: SYSTEM.to_string(),
+        prompt: PROMPT.to_string(),
+        temperature: 0.2,
+        model: None,
+        schema: None,
+        seed: None,
+        think: None,
+        schema_via: None,
+    }
+}
+
+/// An image in a document.
+#[derive(Debug, PartialEq)]
+pub struct ImageRef {
+    /// The image path or URL as written
+    pub src: String,
+    /// 1-based line number, for messages
+    pub line: usize,
+    /// The alt text, `None` when missing
+    pub alt: Option<String>,
+    /// Where alt text goes when it's missing: bytes to replace (empty for a new HTML attribute)
+    range: Range<usize>,
+    html: bool,
+}
+
+impl ImageRef {
+    /// What to put at `range` for this alt text.
+    fn replacement(&self, alt: &str) -> String {
+        if self.html {
+            let escaped = alt
+                .replace('&', "&amp;")
+                .replace('"', "&quot;")
+                .replace('<', "&lt;")
+                .replace('>', "&gt;");
+            format!(" alt=\"{}\"", escaped)
+        } else {
+            alt.replace('\\', "\\\\").replace('[', "\\[").replace(']', "\\]")
+        }
+    }
+}
+
+/// Whether a file is Markdown, by extension. Everything else is scanned as HTML.
+pub fn is_markdown(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|e| e.to_str()).map(str::to_ascii_lowercase).as_deref(),
+        Some("md" | "markdown" | "mdx")
+    )
+}
+
+/// Add every HTML and Markdown file under `dir` to `documents`.
+pub fn find_documents(dir: &Path, documents: &mut Vec<PathBuf>) -> Result<(), String> {
+    let entries = fs::read_dir(dir).map_err(|e| format!("Cannot read directory '{}': {}", dir.display(), e))?;
+    let mut paths = entries
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Cannot read directory '{}': {}", dir.display(), e))?;
+    paths.sort();
+    for path in paths {
+        let is_html = matches!(
+            path.extension().and_then(|e| e.to_str()).map(str::to_ascii_lowercase).as_deref(),
+            Some("html" | "htm")
+        );
+        if path.is_dir() {
+            find_documents(&path, documents)?;
+        } else if is_html || is_markdown(&path) {
+            documents.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Every image, in the order they appear. Markdown files are scanned for both `![](...)`
+/// and inline `<img>` tags, skipping fenced code blocks.
+pub fn find_images(text: &str, markdown: bool) -> Vec<ImageRef> {
+    if !markdown {
+        return find_html(text, &[]);
+    }
+    let code = code_blocks(text);
+    let mut images = find_html(text, &code);
+    images.extend(find_markdown(text, &code));
+    images.sort_by_key(|image| image.range.start);
+    images
+}
+
+/// Images without alt text, in the order they appear.
+pub fn find_missing(text: &str, markdown: bool) -> Vec<ImageRef> {
+    find_images(text, markdown).into_iter().filter(|image| image.alt.is_none()).collect()
+}
+
+/// Byte ranges of fenced code blocks, which show images' syntax rather than images.
+fn code_blocks(text: &str) -> Vec<Range<usize>> {
+    let mut blocks = Vec::new();
+    let mut open: Option<(usize, &str)> = None;
+    let mut offset = 0;
+    for line in text.split_inclusive('\n') {
+        let fence = line.trim_start();
+        let marker = ["```", "~~~"].into_iter().find(|m| fence.starts_with(m));
+        match (open, marker) {
+            (None, Some(marker)) => open = Some((offset, marker)),
+            (Some((start, opened)), Some(marker)) if marker == opened => {
+                blocks.push(start..offset + line.len());
+                open = None;
+            }
+            _ => {}
+        }
+        offset += line.len();
+    }
+    if let Some((start, _)) = open {
+        blocks.push(start..text.len());
+    }
+    blocks
+}
+
+fn line_of(text: &str, offset: usize) -> usize {
+    text[..offset].matches('\n').count() + 1
+}
+
+fn find_markdown(text: &str, skip: &[Range<usize>]) -> Vec<ImageRef> {
+    let mut missing = Vec::new();
+    let mut from = 0;
+    while let Some(found) = text[from..].find("![") {
+        let start = from + found;
+        from = start + 2;
+        if skip.iter().any(|block| block.contains(&start)) {
+            continue;
+        }
+        let Some(close) = closing_bracket(&text[from..]).map(|i| from + i) else {
+            break;
+        };
+        let alt = &text[from..close];
+        let rest = &text[close + 1..];
+        if alt.contains('\n') || !rest.starts_with('(') {
+            continue;
+        }
+        let destination = rest[1..].trim_start();
+        let src = match destination.strip_prefix('<') {
+            Some(bracketed) => bracketed.split('>').next(),
+            None => destination.split(|c: char| c.is_whitespace() || c == ')').next(),
+        };
+        if let Some(src) = src.filter(|src| !src.is_empty()) {
+            // Markdown has no way to mark an image decorative, so empty brackets are missing
+            let alt = alt.trim();
+            missing.push(ImageRef {
+                src: src.to_string(),
+                line: line_of(text, start),
+                alt: (!alt.is_empty()).then(|| alt.replace("\\[", "[").replace("\\]", "]")),
+                range: from..close,
+                html: false,
+            });
+        }
+    }
+    missing
+}
+
+/// The offset of the first `]` that isn't escaped with a backslash.
+fn closing_bracket(text: &str) -> Option<usize> {
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        match c {
+            ']' if !escaped => return Some(i),
+            '\\' => escaped = !escaped,
+            _ => escaped = false,
+        }
+    }
+    None
+}
+
+fn find_html(text: &str, skip: &[Range<usize>]) -> Vec<ImageRef> {
+    let lower = text.to_ascii_lowercase();
+    let mut missing = Vec::new();
+    let mut from = 0;
+    while let Some(found) = lower[from..].find("<img") {
+        let start = from + found;
+        let name_end = start + "<img".len();
+        from = name_end;
+        let boundary = text[name_end..].chars().next();
+        if skip.iter().any(|block| block.contains(&start))
+            || !matches!(boundary, Some(c) if c.is_whitespace() || c == '/' || c == '>')
+        {
+            continue;
+        }
+        let Some((attributes, end)) = tag_attributes(&text[name_end..]) else {
+            break;
+        };
+        from = name_end + end;
+        let value = |wanted: &str| {
+            attributes
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(wanted))
+                .map(|(_, value)| value.clone().unwrap_or_default())
+        };
+        if let Some(src) = value("src").filter(|src| !src.is_empty()) {
+            missing.push(ImageRef {
+                src,
+                line: line_of(text, start),
+                alt: value("alt"),
+                range: name_end..name_end,
+                html: true,
+            });
+        }
+    }
+    missing
+}
+
+/// An attribute's name and value, `None` for bare attributes like `hidden`.
+type Attribute = (String, Option<String>);
+
+/// The attributes of a tag, from just after its name, and the offset just past its `>`.
+/// `None` if the tag isn't closed.
+fn tag_attributes(tag: &str) -> Option<(Vec<Attribute>, usize)> {
+    let bytes = tag.as_bytes();
+    let mut attributes = Vec::new();
+    let mut i = 0;
+    loop {
+        while i < bytes.len() && (bytes[i].is_ascii_whitespace() || bytes[i] == b'/') {
+            i += 1;
+        }
+        match bytes.get(i)? {
+            b'>' => return Some((attributes, i + 1)),
+            _ => {
+                let name_start = i;
+                while i < bytes.len() && !bytes[i].is_ascii_whitespace() && !b"=>/".contains(&bytes[i]) {
+                    i += 1;
+                }
+                let name = tag[name_start..i].to_string();
+                while i < bytes.len() && bytes[i].is_ascii_whitespace() {
+                    i += 1;
+                }
+                if bytes.get(i) != Some(&b'=') {
+                    attributes.push((name, None));
+                    continue;
+                }
+                i += 1;
+                while i < bytes.len() && bytes[i].is_ascii_whitespace() {
+                    i += 1;
+                }
+                let value = match bytes.get(i)? {
+                    quote @ (b'"' | b'\'') => {
+                        let close = tag[i + 1..].find(*quote as char)? + i + 1;
+                        let value = &tag[i + 1..close];
+                        i = close + 1;
+                        value
+                    }
+                    _ => {
+                        let value_start = i;
+                        while i < bytes.len() && !bytes[i].is_ascii_whitespace() && bytes[i] != b'>' {
+                            i += 1;
+                        }
+                        &tag[value_start..i]
+                    }
+                };
+                attributes.push((name, Some(decode_entities(value))));
+            }
+        }
+    }
+}
+
+fn decode_entities(value: &str) -> String {
+    value
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+/// The file an image reference points to. Paths are relative to the document, or to
+/// `root` when they start with `/`. Remote and inline images are errors.
+pub fn resolve(document: &Path, src: &str, root: Option<&Path>) -> Result<PathBuf, String> {
+    if src.contains("://") || src.starts_with("//") || src.starts_with("data:") {
+        return Err(format!("'{}' is not a local file", src));
+    }
+    let path = src.split(['?', '#']).next().unwrap_or(src);
+    let path = percent_decode(path);
+    match path.strip_prefix('/') {
+        Some(from_root) => match root {
+            Some(root) => Ok(root.join(from_root)),
+            None => Err(format!("'{}' is relative to the site root; pass --root", src)),
+        },
+        None => Ok(document.parent().unwrap_or(Path::new("")).join(path)),
+    }
+}
+
+fn percent_decode(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|h| std::str::from_utf8(h).ok());
+        match (bytes[i], hex.and_then(|h| u8::from_str_radix(h, 16).ok())) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// The model's reply as one line of alt text: quotes, labels and line breaks removed.
+pub fn clean_alt(reply: &str) -> String {
+    let reply = reply.trim();
+    let reply = ["Alt text:", "Alt:", "alt text:"]
+        .iter()
+        .find_map(|label| reply.strip_prefix(label))
+        .unwrap_or(reply)
+        .trim();
+    let reply = reply
+        .strip_prefix('"')
+        .and_then(|r| r.strip_suffix('"'))
+        .unwrap_or(reply);
+    reply.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// The text with alt text filled in for each image, which must have none.
+pub fn apply(text: &str, fills: &[(&ImageRef, String)]) -> String {
+    let mut patched = String::with_capacity(text.len());
+    let mut at = 0;
+    let mut fills: Vec<_> = fills.iter().collect();
+    fills.sort_by_key(|(missing, _)| missing.range.start);
+    for (missing, alt) in fills {
+        patched.push_str(&text[at..missing.range.start]);
+        patched.push_str(&missing.replacement(alt));
+        at = missing.range.end;
+    }
+    patched.push_str(&text[at..]);
+    patched
+}
+
+/// What an audit found wrong with an image's alt text.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AltIssue {
+    Missing,
+    /// `alt=""`: right for decorative images, wrong for everything else
+    Empty,
+    /// A file name or a word like "image" that says nothing about the picture
+    Placeholder,
+    /// The model scored the alt text below `--mismatch-below`
+    Mismatched,
+}
+
+/// Alt text that only ever stands in for a description.
+const PLACEHOLDERS: [&str; 17] = [
+    "image", "img", "photo", "picture", "pic", "graphic", "icon", "logo", "banner", "thumbnail", "screenshot",
+    "alt", "alt text", "placeholder", "todo", "untitled", "undefined",
+];
+
+/// Whether alt text is a placeholder rather than a description: a word from a CMS default,
+/// the image's own file name, or a camera file name like `IMG_1234`.
+pub fn is_placeholder(alt: &str, src: &str) -> bool {
+    let alt = alt.trim().trim_end_matches('.').to_ascii_lowercase();
+    let file_name = src.rsplit('/').next().unwrap_or(src).to_ascii_lowercase();
+    let stem = file_name.rsplit_once('.').map_or(file_name.as_str(), |(stem, _)| stem);
+    let camera_name = ["img", "dsc", "dscn", "dscf", "pxl", "image", "photo", "screenshot"].iter().any(|prefix| {
+        alt.strip_prefix(prefix).is_some_and(|rest| {
+            let digits = rest.trim_start_matches(['_', '-', ' ']);
+            !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit() || b == b'_' || b == b'-')
+        })
+    });
+    let has_image_extension = [".jpg", ".jpeg", ".png", ".gif", ".webp", ".svg"].iter().any(|ext| alt.ends_with(ext));
+    PLACEHOLDERS.contains(&alt.as_str()) || alt == file_name || alt == stem || camera_name || has_image_extension
+}
+
+/// What's wrong with an image's alt text without asking the model.
+pub fn structural_issue(image: &ImageRef) -> Option<AltIssue> {
+    match image.alt.as_deref().map(str::trim) {
+        None => Some(AltIssue::Missing),
+        Some("") => Some(AltIssue::Empty),
+        Some(alt) if is_placeholder(alt, &image.src) => Some(AltIssue::Placeholder),
+        Some(_) => None,
+    }
+}
+
+/// The question an audit asks about an image that already has alt text.
+pub fn audit_config(alt: &str, base: &PromptConfig) -> PromptConfig {
+    PromptConfig {
+        prompt: format!(
+            "This image's alt text is: \"{}\"\n\nScore from 0 to 10 how well it tells someone who can't see \
+             the image what they need to know (0: wrong or unrelated, 10: accurate and complete), and write \
+             better alt text: one concise sentence, under 125 characters. Reply with only a JSON object: \
+             {{\"score\": <0 to 10>, \"suggested\": \"<alt text>\"}}",
+            alt
+        ),
+        ..base.clone()
+    }
+}
+
+/// The score and suggestion from a reply to `audit_config`'s question.
+pub fn parse_audit_reply(reply: &str) -> Result<(f64, String), String> {
+    let json = parse_model_content(reply.trim().to_string());
+    let score = json.get("score").and_then(|score| score.as_f64()).filter(|score| (0.0..=10.0).contains(score));
+    let suggested = json.get("suggested").and_then(|s| s.as_str()).map(clean_alt);
+    match (score, suggested) {
+        (Some(score), Some(suggested)) => Ok((score, suggested)),
+        _ => Err(format!("Expected a score and suggested alt text, got: {}", reply.trim())),
+    }
+}
+
+/// One line of an audit report.
+#[derive(Debug, Serialize)]
+pub struct AuditEntry {
+    pub file: String,
+    pub line: usize,
+    pub src: String,
+    pub alt: Option<String>,
+    pub issue: Option<AltIssue>,
+    /// The model's 0–10 score for the existing alt text
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub score: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub suggested: Option<String>,
+    /// Why the image couldn't be checked with the model
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Counts for the end of an audit.
+#[derive(Debug, Default, PartialEq)]
+pub struct AuditSummary {
+    pub images: usize,
+    pub missing: usize,
+    pub empty: usize,
+    pub placeholder: usize,
+    pub mismatched: usize,
+    pub errors: usize,
+}
+
+impl AuditSummary {
+    pub fn add(&mut self, entry: &AuditEntry) {
+        self.images += 1;
+        match entry.issue {
+            Some(AltIssue::Missing) => self.missing += 1,
+            Some(AltIssue::Empty) => self.empty += 1,
+            Some(AltIssue::Placeholder) => self.placeholder += 1,
+            Some(AltIssue::Mismatched) => self.mismatched += 1,
+            None => {}
+        }
+        if entry.error.is_some() {
+            self.errors += 1;
+        }
+    }
+}
+
+/// Lines of context around each change
+const CONTEXT: usize = 3;
+
+/// A unified diff between two versions of `path`, for `git apply` or `patch -p1`. Alt text
+/// never adds lines, so the versions are compared line by line.
+pub fn unified_diff(path: &str, old: &str, new: &str) -> String {
+    let old_lines: Vec<&str> = old.split_inclusive('\n').collect();
+    let new_lines: Vec<&str> = new.split_inclusive('\n').collect();
+    debug_assert_eq!(old_lines.len(), new_lines.len());
+    let changed: Vec<usize> = (0..old_lines.len()).filter(|&i| old_lines[i] != new_lines[i]).collect();
+    if changed.is_empty() {
+        return String::new();
+    }
+
+    // Changes closer together than twice the context share a hunk
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for &line in &changed {
+        let start = line.saturating_sub(CONTEXT);
+        let end = (line + CONTEXT + 1).min(old_lines.len());
+        match hunks.last_mut() {
+            Some(hunk) if start <= hunk.1 => hunk.1 = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+
+    let mut diff = format!("--- a/{}\n+++ b/{}\n", path, path);
+    let push_line = |diff: &mut String, prefix: char, line: &str| {
+        diff.push(prefix);
+        diff.push_str(line);
+        if !line.ends_with('\n') {
+            diff.push_str("\n\\ No newline at end of file\n");
+        }
+    };
+    for (start, end) in hunks {
+        let len = end - start;
+        diff.push_str(&format!("@@ -{},{} +{},{} @@\n", start + 1, len, start + 1, len));
+        for i in start..end {
+            if old_lines[i] == new_lines[i] {
+                push_line(&mut diff, ' ', old_lines[i]);
+            } else {
+                push_line(&mut diff, '-', old_lines[i]);
+                push_line(&mut diff, '+', new_lines[i]);
+            }
+        }
+    }
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_markdown_images() {
+        let text = "# Title\n\n![](img/cat.jpg)\n![A dog](dog.png)\n![ ](<my photo.png> \"Title\")\n\n```\n![](not-an-image.png)\n```\n";
+        let missing = find_missing(text, true);
+        let found: Vec<_> = missing.iter().map(|m| (m.src.as_str(), m.line)).collect();
+        assert_eq!(found, vec![("img/cat.jpg", 3), ("my photo.png", 5)]);
+
+        let fills: Vec<_> = missing.iter().zip(["A [grey] cat", "Me"]).map(|(m, alt)| (m, alt.to_string())).collect();
+        let patched = apply(text, &fills);
+        assert!(patched.contains("![A \\[grey\\] cat](img/cat.jpg)\n"));
+        assert!(patched.contains("![Me](<my photo.png> \"Title\")\n"));
+        assert!(patched.contains("![](not-an-image.png)"));
+    }
+
+    #[test]
+    fn test_find_html_images() {
+        let text = "<p><IMG src=\"a.jpg\" width=10></p>\n<img alt=\"\" src=\"spacer.gif\">\n<img src='b.png' alt='B'/>\n<img\n  src=c.webp />\n<imgs src=\"x\">";
+        let missing = find_missing(text, false);
+        let found: Vec<_> = missing.iter().map(|m| (m.src.as_str(), m.line)).collect();
+        assert_eq!(found, vec![("a.jpg", 1), ("c.webp", 4)]);
+
+        let fills: Vec<_> = missing.iter().zip(["Tom & \"Jerry\"", "C"]).map(|(m, alt)| (m, alt.to_string())).collect();
+        let patched = apply(text, &fills);
+        assert!(patched.starts_with("<p><IMG alt=\"Tom &amp; &quot;Jerry&quot;\" src=\"a.jpg\" width=10></p>\n"));
+        assert!(patched.contains("<img alt=\"C\"\n  src=c.webp />"));
+    }
+
+    #[test]
+    fn test_structural_issues() {
+        let text = "<img src=\"a.jpg\">\n<img src=\"b.jpg\" alt=\"\">\n<img src=\"c.jpg\" alt=\"c.jpg\">\n\
+                    ![Photo](d.png)\n![A lighthouse at dusk](e.png)\n![\\[draft\\] IMG_2041](f.png)\n";
+        let issues: Vec<_> = find_images(text, true).iter().map(|image| (image.alt.clone(), structural_issue(image))).collect();
+        assert_eq!(
+            issues,
+            vec![
+                (None, Some(AltIssue::Missing)),
+                (Some(String::new()), Some(AltIssue::Empty)),
+                (Some("c.jpg".to_string()), Some(AltIssue::Placeholder)),
+                (Some("Photo".to_string()), Some(AltIssue::Placeholder)),
+                (Some("A lighthouse at dusk".to_string()), None),
+                (Some("[draft] IMG_2041".to_string()), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_is_placeholder() {
+        assert!(is_placeholder("image", "x.png"));
+        assert!(is_placeholder("Screenshot.", "x.png"));
+        assert!(is_placeholder("hero-banner", "/static/hero-banner.webp"));
+        assert!(is_placeholder("IMG_2041", "x.jpg"));
+        assert!(is_placeholder("DSC-0042", "x.jpg"));
+        assert!(is_placeholder("sunset.JPG", "x.jpg"));
+        assert!(!is_placeholder("Image of the team at the 2023 offsite", "team.jpg"));
+        assert!(!is_placeholder("Imgur logo", "imgur.png"));
+    }
+
+    #[test]
+    fn test_parse_audit_reply() {
+        let (score, suggested) = parse_audit_reply("{\"score\": 2, \"suggested\": \" A cat on a sofa. \"}").unwrap();
+        assert_eq!((score, suggested.as_str()), (2.0, "A cat on a sofa."));
+        assert!(parse_audit_reply("{\"score\": 12, \"suggested\": \"x\"}").is_err());
+        assert!(parse_audit_reply("Looks fine").is_err());
+    }
+
+    #[test]
+    fn test_resolve() {
+        let doc = Path::new("site/posts/trip.md");
+        assert_eq!(resolve(doc, "img/a%20b.jpg?v=2", None).unwrap(), PathBuf::from("site/posts/img/a b.jpg"));
+        assert_eq!(resolve(doc, "/static/c.png", Some(Path::new("site"))).unwrap(), PathBuf::from("site/static/c.png"));
+        assert!(resolve(doc, "/static/c.png", None).unwrap_err().contains("--root"));
+        assert!(resolve(doc, "https://example.com/d.png", None).is_err());
+        assert!(resolve(doc, "data:image/png;base64,AAAA", None).is_err());
+    }
+
+    #[test]
+    fn test_clean_alt() {
+        assert_eq!(clean_alt("  \"A red square on white.\"\n"), "A red square on white.");
+        assert_eq!(clean_alt("Alt text: Two cats\nasleep on a sofa"), "Two cats asleep on a sofa");
+    }
+
+    #[test]
+    fn test_unified_diff() {
+        let old = "1\n2\n3\n4\n![](a.png)\n6\n7\n8\n9\n10\n11\n12\n13\n![](b.png)";
+        let new = old.replace("![](a.png)", "![A](a.png)").replace("![](b.png)", "![B](b.png)");
+        let diff = unified_diff("doc.md", old, &new);
+        assert_eq!(
+            diff,
+            "--- a/doc.md\n+++ b/doc.md\n\
+             @@ -2,7 +2,7 @@\n 2\n 3\n 4\n-![](a.png)\n+![A](a.png)\n 6\n 7\n 8\n\
+             @@ -11,4 +11,4 @@\n 11\n 12\n 13\n-![](b.png)\n\\ No newline at end of file\n+![B](b.png)\n\\ No newline at end of file\n"
+        );
+        assert_eq!(unified_diff("doc.md", old, old), "");
+    }
+}
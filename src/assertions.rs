@@ -0,0 +1,164 @@
+//! `9ladies assert`: checking screenshots against expectations, for UI smoke tests in CI.
+//!
+//! Each check names an image and a `condition` in plain words ("the login button is
+//! visible") that the model must agree holds, and/or `expect`ed values the response to the
+//! prompt config must contain.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::backend::parse_model_content;
+use crate::prompt::PromptConfig;
+
+/// One line of a checks file.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct Assertion {
+    pub file: String,
+    /// A statement the model must agree is true of the image
+    #[serde(default)]
+    pub condition: Option<String>,
+    /// Values the response to `--prompt` must contain; objects may have more fields
+    #[serde(default)]
+    pub expect: Option<Value>,
+}
+
+impl Assertion {
+    /// Parse a line of a checks file, which must have a condition, expected values or both.
+    pub fn parse(line: &str) -> Result<Assertion, String> {
+        let assertion: Assertion = serde_json::from_str(line).map_err(|e| format!("Invalid check: {}", e))?;
+        if assertion.condition.is_none() && assertion.expect.is_none() {
+            return Err(format!("Check for '{}' has neither a 'condition' nor 'expect'", assertion.file));
+        }
+        Ok(assertion)
+    }
+}
+
+/// The outcome of one check, as printed.
+#[derive(Debug, Serialize)]
+pub struct AssertionResult {
+    pub file: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub condition: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expect: Option<Value>,
+    pub passed: bool,
+    /// The model's explanation of its verdict on the condition
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// Expected values the response didn't have
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub mismatches: Vec<String>,
+    /// The response to `--prompt`, when values were expected of it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response: Option<Value>,
+    /// Why the check couldn't be made; it counts as neither passed nor failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// The question asked to check a condition, with the reply held to a pass/fail verdict.
+pub fn condition_config(condition: &str) -> PromptConfig {
+    PromptConfig {
+        system: "You check screenshots and photos for automated tests. Judge strictly from what is visible."
+            .to_string(),
+        prompt: format!(
+            "Is this statement true of the image?\n\n\"{}\"\n\nReply with only a JSON object: \
+             {{\"pass\": true or false, \"reason\": \"<one short sentence>\"}}",
+            condition
+        ),
+        temperature: 0.0,
+        model: None,
+        schema: Some(json!({
+            "type": "object",
+            "properties": {"pass": {"type": "boolean"}, "reason": {"type": "string"}},
+            "required": ["pass", "reason"],
+        })),
+        seed: None,
+        think: None,
+        schema_via: None,
+    }
+}
+
+/// The verdict and reason from a reply to `condition_config`'s question.
+pub fn parse_verdict(reply: &str) -> Result<(bool, String), String> {
+    let json = parse_model_content(reply.trim().to_string());
+    let passed = match json.get("pass") {
+        Some(Value::Bool(passed)) => *passed,
+        _ => return Err(format!("Expected {{\"pass\": true or false}}, got: {}", reply.trim())),
+    };
+    let reason = json.get("reason").and_then(Value::as_str).unwrap_or_default().trim().to_string();
+    Ok((passed, reason))
+}
+
+/// Where `actual` falls short of `expected`, one message per difference, each starting with
+/// the path to the value. Objects need only the expected fields, text is compared ignoring
+/// case and surrounding space, and lists must match item by item.
+pub fn mismatches(expected: &Value, actual: &Value, path: &str) -> Vec<String> {
+    match (expected, actual) {
+        (Value::Object(expected), Value::Object(actual)) => expected
+            .iter()
+            .flat_map(|(key, value)| {
+                let path = format!("{}.{}", path, key);
+                match actual.get(key) {
+                    Some(actual) => mismatches(value, actual, &path),
+                    None => vec![format!("{}: expected {}, but it is missing", path, value)],
+                }
+            })
+            .collect(),
+        (Value::Array(expected_items), Value::Array(actual_items)) if expected_items.len() == actual_items.len() => {
+            expected_items
+                .iter()
+                .zip(actual_items)
+                .enumerate()
+                .flat_map(|(i, (expected, actual))| mismatches(expected, actual, &format!("{}[{}]", path, i)))
+                .collect()
+        }
+        (Value::String(expected), Value::String(actual)) if expected.trim().eq_ignore_ascii_case(actual.trim()) => {
+            Vec::new()
+        }
+        (Value::Number(expected), Value::Number(actual)) if expected.as_f64() == actual.as_f64() => Vec::new(),
+        (expected, actual) if expected == actual => Vec::new(),
+        (expected, actual) => vec![format!("{}: expected {}, got {}", path, expected, actual)],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_assertion() {
+        let check = Assertion::parse(r#"{"file": "login.png", "condition": "the login button is visible"}"#).unwrap();
+        assert_eq!(check.condition.as_deref(), Some("the login button is visible"));
+        assert_eq!(check.expect, None);
+        assert!(Assertion::parse(r#"{"file": "login.png"}"#).unwrap_err().contains("neither"));
+        assert!(Assertion::parse("login.png").is_err());
+    }
+
+    #[test]
+    fn test_parse_verdict() {
+        assert_eq!(
+            parse_verdict(r#"{"pass": false, "reason": "No button is shown."}"#).unwrap(),
+            (false, "No button is shown.".to_string())
+        );
+        assert!(parse_verdict("yes").is_err());
+    }
+
+    #[test]
+    fn test_mismatches() {
+        let actual = json!({"title": "Sign in ", "errors": 0, "buttons": ["OK", "Cancel"], "extra": true});
+        assert!(mismatches(&json!({"title": "sign in", "errors": 0.0}), &actual, "response").is_empty());
+        assert_eq!(
+            mismatches(&json!({"errors": 1, "banner": "Welcome", "buttons": ["OK", "Close"]}), &actual, "response"),
+            vec![
+                "response.errors: expected 1, got 0",
+                "response.banner: expected \"Welcome\", but it is missing",
+                "response.buttons[1]: expected \"Close\", got \"Cancel\"",
+            ]
+        );
+        assert_eq!(
+            mismatches(&json!(["OK"]), &json!(["OK", "Cancel"]), "response"),
+            vec!["response: expected [\"OK\"], got [\"OK\",\"Cancel\"]"]
+        );
+    }
+}
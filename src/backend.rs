@@ -0,0 +1,1875 @@
+//! Model servers: the Ollama and OpenAI-compatible chat APIs behind one `Backend`.
+
+use std::fmt;
+use std::io::{BufRead, BufReader};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use base64::display::Base64Display;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use bytes::Bytes;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::confidence::TokenLogprob;
+use crate::cost::TokenUsage;
+use crate::grounding::image_dimensions;
+use crate::images::detect_image_format;
+use crate::latency::{self, ServerTiming};
+use crate::prompt::{PromptConfig, SchemaVia};
+use crate::replay::{Fixtures, ReplayMode};
+use crate::secrets::api_key;
+use crate::throttle::Throttle;
+use crate::trace::Tracer;
+
+/// Environment variable holding a bearer token for `--api openai` servers that need one;
+/// without it the key comes from the config file's `api_key_command` or `keychain`
+pub const API_KEY_ENV: &str = "NINELADIES_API_KEY";
+
+/// Server used when neither `--url` nor the config file names one
+pub const DEFAULT_URL: &str = "http://localhost:11434";
+
+/// Request timeout used when neither `--timeout` nor the config file sets one
+const DEFAULT_TIMEOUT_SECS: u64 = 120;
+
+/// Which HTTP API the model server speaks.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Api {
+    /// Ollama's native API
+    Ollama,
+    /// OpenAI-compatible chat completions (llama.cpp, vLLM, LM Studio, hosted APIs)
+    Openai,
+}
+
+/// A failed call to the model server. Connection failures are kept apart from other errors
+/// so a run can tell an unreachable server from bad files.
+#[derive(Debug)]
+pub enum CallError {
+    Unreachable(String),
+    Failed(String),
+}
+
+impl fmt::Display for CallError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CallError::Unreachable(message) | CallError::Failed(message) => f.write_str(message),
+        }
+    }
+}
+
+impl From<String> for CallError {
+    fn from(message: String) -> Self {
+        CallError::Failed(message)
+    }
+}
+
+impl From<reqwest::Error> for CallError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_connect() {
+            CallError::Unreachable(format!("Cannot connect to server: {}", e))
+        } else {
+            CallError::Failed(format!("Request failed: {}", e))
+        }
+    }
+}
+
+// Ollama native API types
+#[derive(Serialize)]
+struct OllamaChatRequest<'a> {
+    model: String,
+    messages: Vec<OllamaChatMessage<'a>>,
+    stream: bool,
+    options: OllamaOptions,
+    /// A JSON schema the reply must follow
+    #[serde(skip_serializing_if = "Option::is_none")]
+    format: Option<serde_json::Value>,
+    /// Whether a reasoning model should think first, with its thinking sent apart from the reply
+    #[serde(skip_serializing_if = "Option::is_none")]
+    think: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tools: Option<Vec<Tool>>,
+    /// Asks for the log probability of each token of the reply
+    #[serde(skip_serializing_if = "Option::is_none")]
+    logprobs: Option<bool>,
+}
+
+#[derive(Serialize)]
+struct OllamaOptions {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct OllamaChatMessage<'a> {
+    role: String,
+    content: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    images: Option<Vec<EncodedImage<'a>>>,
+}
+
+#[derive(Serialize)]
+struct OllamaEmbedRequest<'a> {
+    model: &'a str,
+    input: &'a str,
+}
+
+#[derive(Deserialize)]
+struct OllamaEmbedResponse {
+    embeddings: Vec<Vec<f32>>,
+}
+
+/// OpenAI-compatible embeddings request: `input` for text, or chat-style `messages` for
+/// images, which vLLM accepts for multimodal embedding models.
+#[derive(Serialize)]
+struct OpenAiEmbedRequest<'a> {
+    model: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    input: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    messages: Option<Vec<OpenAiChatMessage<'a>>>,
+    encoding_format: &'static str,
+}
+
+#[derive(Deserialize)]
+struct OpenAiEmbedResponse {
+    data: Vec<OpenAiEmbedding>,
+}
+
+#[derive(Deserialize)]
+struct OpenAiEmbedding {
+    embedding: Vec<f32>,
+}
+
+/// What to embed with `Backend::embed`.
+#[derive(Clone, Copy, Debug)]
+pub enum EmbedInput<'a> {
+    Text(&'a str),
+    Image(&'a [u8]),
+}
+
+#[derive(Deserialize)]
+struct OllamaTagsResponse {
+    models: Vec<OllamaModelInfo>,
+}
+
+#[derive(Deserialize)]
+struct OllamaModelInfo {
+    name: String,
+    #[serde(default)]
+    size: u64,
+    #[serde(default)]
+    digest: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct OllamaChatResponse {
+    message: OllamaMessageResponse,
+    #[serde(default)]
+    prompt_eval_count: Option<u64>,
+    #[serde(default)]
+    eval_count: Option<u64>,
+    // Durations in nanoseconds
+    #[serde(default)]
+    load_duration: Option<u64>,
+    #[serde(default)]
+    prompt_eval_duration: Option<u64>,
+    #[serde(default)]
+    eval_duration: Option<u64>,
+    #[serde(default)]
+    logprobs: Option<Vec<TokenLogprob>>,
+}
+
+#[derive(Deserialize)]
+struct OllamaMessageResponse {
+    content: String,
+    #[serde(default)]
+    thinking: Option<String>,
+    #[serde(default)]
+    tool_calls: Vec<OllamaToolCall>,
+}
+
+#[derive(Deserialize)]
+struct OllamaToolCall {
+    function: OllamaFunctionCall,
+}
+
+#[derive(Deserialize)]
+struct OllamaFunctionCall {
+    /// An object, where OpenAI sends a string of JSON
+    arguments: serde_json::Value,
+}
+
+/// A tool the model can call, in the form both APIs take:
+/// `{"type": "function", "function": {"name": ..., "description": ..., "parameters": ...}}`
+#[derive(Serialize)]
+struct Tool {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    function: ToolFunction,
+}
+
+#[derive(Serialize)]
+struct ToolFunction {
+    name: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parameters: Option<serde_json::Value>,
+}
+
+/// The name of the tool a schema is sent as with `SchemaVia::Tool`
+const REPLY_TOOL: &str = "reply";
+
+/// `schema` as the one tool of a request.
+fn reply_tools(schema: &serde_json::Value) -> Vec<Tool> {
+    vec![Tool {
+        kind: "function",
+        function: ToolFunction {
+            name: REPLY_TOOL,
+            description: Some("Give your answer"),
+            parameters: Some(schema.clone()),
+        },
+    }]
+}
+
+// OpenAI-compatible chat completions API types
+#[derive(Serialize)]
+struct OpenAiChatRequest<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    model: Option<String>,
+    messages: Vec<OpenAiChatMessage<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response_format: Option<OpenAiResponseFormat>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tools: Option<Vec<Tool>>,
+    /// The tool the model must call: `{"type": "function", "function": {"name": ...}}`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_choice: Option<Tool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    logprobs: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    n: Option<usize>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    stream: bool,
+    /// Asks for token usage in a last chunk of a streamed reply
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stream_options: Option<OpenAiStreamOptions>,
+}
+
+#[derive(Serialize)]
+struct OpenAiStreamOptions {
+    include_usage: bool,
+}
+
+/// Structured output: `{"type": "json_schema", "json_schema": {"name": ..., "schema": ...}}`
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum OpenAiResponseFormat {
+    JsonSchema { json_schema: OpenAiJsonSchema },
+}
+
+#[derive(Serialize)]
+struct OpenAiJsonSchema {
+    name: &'static str,
+    schema: serde_json::Value,
+}
+
+#[derive(Serialize)]
+struct OpenAiChatMessage<'a> {
+    role: String,
+    content: OpenAiContent<'a>,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum OpenAiContent<'a> {
+    Text(String),
+    Parts(Vec<OpenAiContentPart<'a>>),
+}
+
+#[derive(Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum OpenAiContentPart<'a> {
+    Text { text: String },
+    ImageUrl { image_url: OpenAiImageUrl<'a> },
+}
+
+#[derive(Clone, Serialize)]
+struct OpenAiImageUrl<'a> {
+    url: EncodedImage<'a>,
+}
+
+#[derive(Deserialize)]
+struct OpenAiChatResponse {
+    choices: Vec<OpenAiChoice>,
+    #[serde(default)]
+    usage: Option<OpenAiUsage>,
+    /// llama.cpp's server extension to the OpenAI response
+    #[serde(default)]
+    timings: Option<LlamaCppTimings>,
+}
+
+#[derive(Deserialize)]
+struct LlamaCppTimings {
+    prompt_ms: f64,
+    predicted_ms: f64,
+}
+
+#[derive(Deserialize)]
+struct OpenAiUsage {
+    prompt_tokens: u64,
+    completion_tokens: u64,
+}
+
+#[derive(Deserialize)]
+struct OpenAiChoice {
+    message: OpenAiMessageResponse,
+    #[serde(default)]
+    logprobs: Option<OpenAiLogprobs>,
+}
+
+#[derive(Deserialize)]
+struct OpenAiLogprobs {
+    #[serde(default)]
+    content: Option<Vec<TokenLogprob>>,
+}
+
+#[derive(Deserialize)]
+struct OpenAiMessageResponse {
+    #[serde(default)]
+    content: Option<String>,
+    /// The thinking of a reasoning model, from servers that send it apart (vLLM, llama.cpp,
+    /// DeepSeek)
+    #[serde(default)]
+    reasoning_content: Option<String>,
+    #[serde(default)]
+    tool_calls: Vec<OpenAiToolCall>,
+}
+
+/// A tool call, or in a streamed reply the next piece of one
+#[derive(Deserialize)]
+struct OpenAiToolCall {
+    #[serde(default)]
+    function: OpenAiFunctionCall,
+}
+
+#[derive(Default, Deserialize)]
+struct OpenAiFunctionCall {
+    /// A string of JSON, sent a piece at a time in a streamed reply
+    #[serde(default)]
+    arguments: String,
+}
+
+/// One `data:` event of a streamed chat completion.
+#[derive(Deserialize)]
+struct OpenAiStreamChunk {
+    #[serde(default)]
+    choices: Vec<OpenAiStreamChoice>,
+    #[serde(default)]
+    usage: Option<OpenAiUsage>,
+    #[serde(default)]
+    timings: Option<LlamaCppTimings>,
+}
+
+#[derive(Deserialize)]
+struct OpenAiStreamChoice {
+    delta: OpenAiMessageResponse,
+    #[serde(default)]
+    logprobs: Option<OpenAiLogprobs>,
+}
+
+#[derive(Deserialize)]
+struct OpenAiModelsResponse {
+    data: Vec<OpenAiModelInfo>,
+}
+
+#[derive(Deserialize)]
+struct OpenAiModelInfo {
+    id: String,
+}
+
+/// The megapixels in `images`, counting any whose size can't be read as none.
+fn megapixels(images: &[&[u8]]) -> f64 {
+    images
+        .iter()
+        .filter_map(|image_data| image_dimensions(image_data).ok())
+        .map(|(width, height)| f64::from(width) * f64::from(height) / 1_000_000.0)
+        .sum()
+}
+
+/// Build an endpoint URL for an OpenAI-compatible server, accepting base URLs given with
+/// or without the `/v1` suffix.
+fn openai_endpoint(base_url: &str, path: &str) -> String {
+    let base = base_url.trim_end_matches('/');
+    let base = base.strip_suffix("/v1").unwrap_or(base);
+    format!("{}/v1/{}", base, path)
+}
+
+/// An image in a request body. It is base64-encoded straight into the body as that is
+/// written, rather than into a string of its own first, so a big image isn't held in memory
+/// once more as base64 for every place it is copied on the way.
+#[derive(Clone, Copy)]
+struct EncodedImage<'a> {
+    data: &'a [u8],
+    /// Inlined as a `data:` URL, for OpenAI-compatible servers, rather than bare base64
+    data_url: bool,
+}
+
+impl<'a> EncodedImage<'a> {
+    fn base64(data: &'a [u8]) -> Self {
+        EncodedImage { data, data_url: false }
+    }
+
+    fn data_url(data: &'a [u8]) -> Self {
+        EncodedImage { data, data_url: true }
+    }
+}
+
+impl Serialize for EncodedImage<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let base64 = Base64Display::new(self.data, &BASE64);
+        match self.data_url {
+            true => serializer.collect_str(&format_args!("data:{};base64,{}", image_mime_type(self.data), base64)),
+            false => serializer.collect_str(&base64),
+        }
+    }
+}
+
+/// The MIME type of an image from its magic bytes. Some servers reject a data URL whose
+/// type doesn't match the data, so nothing is assumed: formats `detect_image_format` doesn't
+/// know are named by the `image` crate's wider list, or sent as plain bytes.
+fn image_mime_type(image_data: &[u8]) -> String {
+    match detect_image_format(image_data) {
+        Some(format) => format!("image/{}", format),
+        None => image::guess_format(image_data)
+            .map(|format| format.to_mime_type())
+            .unwrap_or("application/octet-stream")
+            .to_string(),
+    }
+}
+
+/// Parse the model's reply as JSON, otherwise keep it as a string; see `extract_model_content`.
+pub fn parse_model_content(content: String) -> serde_json::Value {
+    extract_model_content(content).0
+}
+
+/// Parse the model's reply as JSON. Many models wrap it in a ```` ```json ```` fence or put a
+/// sentence before or after it, so a reply that isn't JSON as a whole gives the first JSON
+/// object or array in it, with `true` to say it was extracted. Anything else is kept as a
+/// string.
+pub fn extract_model_content(content: String) -> (serde_json::Value, bool) {
+    if let Ok(json) = serde_json::from_str::<serde_json::Value>(&content) {
+        return (json, false);
+    }
+    for (start, _) in content.match_indices(['{', '[']) {
+        let mut values = serde_json::Deserializer::from_str(&content[start..]).into_iter::<serde_json::Value>();
+        match values.next() {
+            Some(Ok(json)) => return (json, true),
+            // The rest is inside a value the reply never finished, such as one cut off by the
+            // timeout, and a smaller value in there isn't the answer
+            Some(Err(e)) if e.is_eof() => break,
+            _ => {}
+        }
+    }
+    (serde_json::Value::String(content), false)
+}
+
+/// `reply` with a reasoning model's `<think>...</think>` taken out of its content and added
+/// to its thinking, so it doesn't get in the way of parsing the answer. Some chat templates
+/// open the tag in the prompt, so a reply with only the closing tag thought up to there, and
+/// one cut off before the closing tag is all thinking.
+fn split_thinking(mut reply: ChatReply) -> ChatReply {
+    reply.alternatives = std::mem::take(&mut reply.alternatives)
+        .into_iter()
+        .map(|alternative| match think_tags(&alternative) {
+            Some((_, answer)) => answer.to_string(),
+            None => alternative,
+        })
+        .collect();
+    let Some((thought, answer)) = think_tags(&reply.content) else {
+        return reply;
+    };
+    let answer = answer.to_string();
+    if !thought.is_empty() {
+        let thinking = reply.thinking.get_or_insert_with(String::new);
+        if !thinking.is_empty() {
+            thinking.push_str("\n\n");
+        }
+        thinking.push_str(thought);
+    }
+    reply.content = answer;
+    reply
+}
+
+/// The thinking and the answer in `content`, if it has `<think>` tags; see `split_thinking`.
+fn think_tags(content: &str) -> Option<(&str, &str)> {
+    const OPEN: &str = "<think>";
+    const CLOSE: &str = "</think>";
+    let content = content.trim_start();
+    let (thought, answer) = match (content.strip_prefix(OPEN), content.find(CLOSE)) {
+        (Some(rest), _) => match rest.find(CLOSE) {
+            Some(end) => (&rest[..end], &rest[end + CLOSE.len()..]),
+            None => (rest, ""),
+        },
+        (None, Some(end)) if !content[..end].contains(OPEN) => (&content[..end], &content[end + CLOSE.len()..]),
+        _ => return None,
+    };
+    Some((thought.trim(), answer.trim_start()))
+}
+
+/// One turn of a conversation with the model.
+pub struct ChatTurn {
+    /// `system`, `user` or `assistant`
+    pub role: &'static str,
+    pub content: String,
+}
+
+impl ChatTurn {
+    pub fn new(role: &'static str, content: impl Into<String>) -> Self {
+        ChatTurn {
+            role,
+            content: content.into(),
+        }
+    }
+}
+
+/// A chat request independent of the server API. The images are attached to the first user
+/// turn, so follow-up questions in a conversation don't re-send them.
+struct ChatRequest<'a> {
+    model: Option<&'a str>,
+    temperature: Option<f32>,
+    seed: Option<u64>,
+    turns: &'a [ChatTurn],
+    images: &'a [&'a [u8]],
+    /// JSON schema to constrain the reply to, for servers that support structured output
+    schema: Option<&'a serde_json::Value>,
+    think: Option<bool>,
+    schema_via: Option<SchemaVia>,
+    /// How many answers to ask for in one request, from OpenAI-compatible servers
+    n: Option<usize>,
+}
+
+/// A model's reply, with the token counts and timings the server reported, if any.
+pub struct ChatReply {
+    /// The reply text; see `parse_model_content`
+    pub content: String,
+    pub usage: Option<TokenUsage>,
+    pub timing: ServerTiming,
+    /// The reply was cut off and `content` is what had arrived; see `Backend::with_partial_replies`
+    pub truncated: bool,
+    /// What a reasoning model thought before replying, kept out of `content`; see `split_thinking`
+    pub thinking: Option<String>,
+    /// The reply's tokens and their log probabilities, when asked for with
+    /// `Backend::with_logprobs` and the server sends them
+    pub logprobs: Option<Vec<TokenLogprob>>,
+    /// Further answers to the same request; see `Backend::describe_alternatives`
+    pub alternatives: Vec<String>,
+}
+
+impl ChatRequest<'_> {
+    fn image_turn(&self) -> Option<usize> {
+        self.turns.iter().position(|turn| turn.role == "user")
+    }
+
+    /// The schema to send as the structured output format, unless it goes as a tool.
+    fn format(&self) -> Option<&serde_json::Value> {
+        self.schema.filter(|_| self.schema_via != Some(SchemaVia::Tool))
+    }
+
+    /// The schema to send as a tool, with `SchemaVia::Tool`.
+    fn tool_schema(&self) -> Option<&serde_json::Value> {
+        self.schema.filter(|_| self.schema_via == Some(SchemaVia::Tool))
+    }
+}
+
+/// Receives a reply's text as the server streams it, for `--stream`.
+pub trait TokenSink: Send + Sync {
+    /// The next piece of the reply.
+    fn token(&self, text: &str);
+
+    /// The reply is complete.
+    fn done(&self) {}
+}
+
+/// A model server, the API it speaks and the model to ask.
+#[derive(Clone)]
+pub struct Backend {
+    client: reqwest::blocking::Client,
+    api: Api,
+    url: String,
+    model: Option<String>,
+    throttle: Option<Arc<Throttle>>,
+    tracer: Option<Arc<Tracer>>,
+    fixtures: Option<Arc<Fixtures>>,
+    stream: Option<Arc<dyn TokenSink>>,
+    keep_partial: bool,
+    stall_timeout: Option<Duration>,
+    timeout: Duration,
+    timeout_per_megapixel: Option<Duration>,
+    logprobs: bool,
+}
+
+impl Backend {
+    /// `model` may be `None` for OpenAI-compatible servers that host a single model.
+    /// `timeout` is in seconds.
+    pub fn new(api: Api, url: &str, model: Option<String>, timeout: Option<u64>) -> Backend {
+        Backend {
+            timeout: Duration::from_secs(timeout.unwrap_or(DEFAULT_TIMEOUT_SECS)),
+            ..Backend::with_client(build_client(timeout), api, url, model)
+        }
+    }
+
+    /// Like `new`, reusing an existing HTTP client.
+    pub fn with_client(client: reqwest::blocking::Client, api: Api, url: &str, model: Option<String>) -> Backend {
+        Backend {
+            client,
+            api,
+            url: url.to_string(),
+            model,
+            throttle: None,
+            tracer: None,
+            fixtures: None,
+            stream: None,
+            keep_partial: false,
+            stall_timeout: None,
+            timeout: Duration::from_secs(DEFAULT_TIMEOUT_SECS),
+            timeout_per_megapixel: None,
+            logprobs: false,
+        }
+    }
+
+    /// The same backend, with its client, throttle and tracer, asking `model` on `url`.
+    pub fn at(&self, url: &str, model: Option<String>) -> Backend {
+        Backend {
+            url: url.to_string(),
+            model,
+            ..self.clone()
+        }
+    }
+
+    /// Hold model requests to `throttle`'s rate, which may be shared with other backends.
+    pub fn with_throttle(mut self, throttle: Option<Arc<Throttle>>) -> Backend {
+        self.throttle = throttle;
+        self
+    }
+
+    /// Write each chat request and its raw response to `tracer`.
+    pub fn with_tracer(mut self, tracer: Option<Arc<Tracer>>) -> Backend {
+        self.tracer = tracer;
+        self
+    }
+
+    /// Save each chat reply to `fixtures`, or answer chat requests from them without the
+    /// server, as their mode says.
+    pub fn with_fixtures(mut self, fixtures: Option<Arc<Fixtures>>) -> Backend {
+        self.fixtures = fixtures;
+        self
+    }
+
+    /// Ask for chat replies to be streamed, passing the text to `sink` as it arrives. The
+    /// reply returned is the same as without streaming.
+    pub fn with_stream(mut self, sink: Option<Arc<dyn TokenSink>>) -> Backend {
+        self.stream = sink;
+        self
+    }
+
+    /// Give up on a reply once the server has sent nothing for `stall_timeout`, and with
+    /// `keep` return what a reply had said when it is cut off, by that or by the timeout,
+    /// rather than an error. Both need the reply streamed.
+    pub fn with_partial_replies(mut self, keep: bool, stall_timeout: Option<Duration>) -> Backend {
+        self.keep_partial = keep;
+        self.stall_timeout = stall_timeout;
+        self
+    }
+
+    /// Give each chat request `per_megapixel` more than the timeout for every megapixel of
+    /// its images, so a panorama gets the time it needs while a thumbnail still fails fast.
+    pub fn with_timeout_per_megapixel(mut self, per_megapixel: Option<Duration>) -> Backend {
+        self.timeout_per_megapixel = per_megapixel;
+        self
+    }
+
+    /// Ask for the log probability of each token of a reply, for `--confidence logprobs`.
+    /// OpenAI-compatible servers and recent Ollama versions send them.
+    pub fn with_logprobs(mut self, logprobs: bool) -> Backend {
+        self.logprobs = logprobs;
+        self
+    }
+
+    pub fn api(&self) -> Api {
+        self.api
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn model(&self) -> Option<&str> {
+        self.model.as_deref()
+    }
+
+    /// The HTTP client, for other requests that should share its timeout and connections.
+    pub fn client(&self) -> &reqwest::blocking::Client {
+        &self.client
+    }
+
+    /// Describe one image with a prompt config, constrained to its schema if it has one.
+    /// Parse the reply with `parse_model_content`.
+    pub fn describe(&self, config: &PromptConfig, image_data: &[u8]) -> Result<ChatReply, CallError> {
+        self.describe_images(config, &[image_data])
+    }
+
+    /// Ask a prompt config's question about several images at once, in order, e.g. to
+    /// compare two, or about none for a text-only request. Not every model accepts more
+    /// than one image.
+    pub fn describe_images(&self, config: &PromptConfig, images: &[&[u8]]) -> Result<ChatReply, CallError> {
+        let turns = [ChatTurn::new("system", &config.system), ChatTurn::new("user", &config.prompt)];
+        self.send(&ChatRequest {
+            model: self.model.as_deref(),
+            temperature: Some(config.temperature),
+            seed: config.seed,
+            turns: &turns,
+            images,
+            schema: config.schema.as_ref(),
+            think: config.think,
+            schema_via: config.schema_via,
+            n: None,
+        })
+    }
+
+    /// `count` more answers to a prompt config's question, for `--candidates`, with the
+    /// tokens they took in all. OpenAI-compatible servers are asked for them in one request
+    /// (`n`); Ollama has no such option, so it is asked again for each, with the next seed
+    /// after the config's when it sets one.
+    pub fn describe_alternatives(
+        &self,
+        config: &PromptConfig,
+        images: &[&[u8]],
+        count: usize,
+    ) -> Result<(Vec<String>, Option<TokenUsage>), CallError> {
+        let turns = [ChatTurn::new("system", &config.system), ChatTurn::new("user", &config.prompt)];
+        let request = |seed, n| ChatRequest {
+            model: self.model.as_deref(),
+            temperature: Some(config.temperature),
+            seed,
+            turns: &turns,
+            images,
+            schema: config.schema.as_ref(),
+            think: config.think,
+            schema_via: config.schema_via,
+            n,
+        };
+        match self.api {
+            Api::Openai => {
+                let reply = self.send(&request(config.seed, Some(count)))?;
+                let answers = std::iter::once(reply.content).chain(reply.alternatives).collect();
+                Ok((answers, reply.usage))
+            }
+            Api::Ollama => {
+                let mut answers = Vec::with_capacity(count);
+                let mut usage: Option<TokenUsage> = None;
+                for i in 1..=count as u64 {
+                    let reply = self.send(&request(config.seed.map(|seed| seed.wrapping_add(i)), None))?;
+                    answers.push(reply.content);
+                    usage = match (usage, reply.usage) {
+                        (Some(mut total), Some(extra)) => {
+                            total += extra;
+                            Some(total)
+                        }
+                        (total, extra) => total.or(extra),
+                    };
+                }
+                Ok((answers, usage))
+            }
+        }
+    }
+
+    /// Send a conversation about an image. The image is attached to the first user turn.
+    pub fn chat(&self, turns: &[ChatTurn], temperature: Option<f32>, image_data: &[u8]) -> Result<ChatReply, CallError> {
+        self.send(&ChatRequest {
+            model: self.model.as_deref(),
+            temperature,
+            seed: None,
+            turns,
+            images: &[image_data],
+            schema: None,
+            think: None,
+            schema_via: None,
+            n: None,
+        })
+    }
+
+    /// Send a chat request. For OpenAI-compatible servers the model may be omitted, for
+    /// servers that only host one (e.g. llama.cpp).
+    fn send(&self, request: &ChatRequest) -> Result<ChatReply, CallError> {
+        // The answers to a request for several come interleaved when streamed
+        let streamed = request.n.is_none() && (self.stream.is_some() || self.keep_partial || self.stall_timeout.is_some());
+        let (url, body) = match self.api {
+            Api::Ollama => (
+                format!("{}/api/chat", self.url.trim_end_matches('/')),
+                serde_json::to_vec(&OllamaChatRequest {
+                    stream: streamed,
+                    logprobs: self.logprobs.then_some(true),
+                    ..build_ollama_request(request)
+                }),
+            ),
+            Api::Openai => (
+                openai_endpoint(&self.url, "chat/completions"),
+                serde_json::to_vec(&OpenAiChatRequest {
+                    stream: streamed,
+                    stream_options: streamed.then_some(OpenAiStreamOptions { include_usage: true }),
+                    logprobs: self.logprobs.then_some(true),
+                    ..build_openai_request(request)
+                }),
+            ),
+        };
+        // One copy of the body is shared by the request, the fixture and the trace
+        let body = Bytes::from(body.expect("chat requests serialize"));
+
+        let timeout = self.timeout_per_megapixel.map(|per_megapixel| {
+            let timeout = self.timeout + per_megapixel.mul_f64(megapixels(request.images));
+            debug!(timeout_secs = timeout.as_secs_f64(), "Timeout scaled to the image size");
+            timeout
+        });
+
+        let replay = self.fixtures.as_deref().filter(|fixtures| fixtures.mode() == ReplayMode::Replay);
+        let started = Instant::now();
+        let result = match (replay, streamed) {
+            (Some(fixtures), _) => self.replay(fixtures, &body, streamed),
+            (None, true) => {
+                self.wait_for_throttle();
+                self.post_streamed(&url, &body, timeout)
+            }
+            (None, false) => {
+                self.wait_for_throttle();
+                self.post(&url, &body, timeout).map(|(status, text)| (status, text, false)).map_err(CallError::from)
+            }
+        };
+        if let (Some(fixtures), None, Ok((status, text, false))) = (&self.fixtures, replay, &result) {
+            if let Err(e) = fixtures.save(&url, &body, request.images, status.as_u16(), text) {
+                warn!("{}", e);
+            }
+        }
+        if let Some(tracer) = &self.tracer {
+            let error = result.as_ref().err().map(ToString::to_string);
+            let traced = match (&result, &error) {
+                (Ok((status, text, _)), _) => Ok((status.as_u16(), text.as_str())),
+                (Err(_), error) => Err(error.as_deref().unwrap_or_default()),
+            };
+            if let Err(e) = tracer.write(&url, &body, request.images, traced, started.elapsed()) {
+                warn!("{}", e);
+            }
+        }
+
+        let (status, text, truncated) = result?;
+        if !status.is_success() {
+            return Err(format!("Server returned {}: {}", status, text).into());
+        }
+        let reply = match (self.api, streamed) {
+            (Api::Ollama, false) => parse_ollama_reply(&text),
+            (Api::Openai, false) => parse_openai_reply(&text),
+            (Api::Ollama, true) => parse_ollama_stream(&text),
+            (Api::Openai, true) => parse_openai_stream(&text),
+        };
+        reply.map(|reply| ChatReply { truncated, ..split_thinking(reply) })
+    }
+
+    /// POST `body` as JSON, with the API key for OpenAI-compatible servers, returning the
+    /// response status and body.
+    fn post(
+        &self,
+        url: &str,
+        body: &Bytes,
+        timeout: Option<Duration>,
+    ) -> Result<(reqwest::StatusCode, String), reqwest::Error> {
+        let response = self.request(url, body, timeout).send()?;
+        let status = response.status();
+        Ok((status, response.text()?))
+    }
+
+    /// Like `post` for a streamed reply, passing each piece of text to the `--stream` sink as
+    /// its line arrives. The body returned is every line, to be parsed whole once it is
+    /// complete, and whether it was cut off short of that.
+    fn post_streamed(
+        &self,
+        url: &str,
+        body: &Bytes,
+        timeout: Option<Duration>,
+    ) -> Result<(reqwest::StatusCode, String, bool), CallError> {
+        let response = self.request(url, body, timeout).send()?;
+        let status = response.status();
+        if !status.is_success() {
+            return Ok((status, response.text()?, false));
+        }
+
+        // Lines are read on another thread, so a stalled server can be given up on
+        let (sender, lines) = mpsc::channel();
+        std::thread::spawn(move || {
+            for line in BufReader::new(response).lines() {
+                let failed = line.is_err();
+                if sender.send(line).is_err() || failed {
+                    break;
+                }
+            }
+        });
+        let mut text = String::new();
+        let mut said_something = false;
+        let mut cut_off = None;
+        loop {
+            let line = match self.stall_timeout {
+                Some(stall) => lines.recv_timeout(stall),
+                None => lines.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            match line {
+                Ok(Ok(line)) => {
+                    if let Some(piece) = stream_piece(self.api, &line).filter(|piece| !piece.is_empty()) {
+                        said_something = true;
+                        if let Some(sink) = &self.stream {
+                            sink.token(&piece);
+                        }
+                    }
+                    text.push_str(&line);
+                    text.push('\n');
+                }
+                Ok(Err(e)) => {
+                    cut_off = Some(format!("Request failed mid-reply: {}", e));
+                    break;
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    let stall = self.stall_timeout.unwrap_or_default();
+                    cut_off = Some(format!("The server sent nothing more for {}s mid-reply", stall.as_secs_f64()));
+                    break;
+                }
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+        if let Some(sink) = &self.stream {
+            sink.done();
+        }
+
+        match cut_off {
+            Some(e) if self.keep_partial && said_something => {
+                warn!("{}; keeping the part of the reply that arrived", e);
+                Ok((status, text, true))
+            }
+            Some(e) => Err(CallError::Failed(e)),
+            None => Ok((status, text, false)),
+        }
+    }
+
+    /// The recorded reply to `body`, shown on the `--stream` sink as if it were arriving.
+    fn replay(
+        &self,
+        fixtures: &Fixtures,
+        body: &Bytes,
+        streamed: bool,
+    ) -> Result<(reqwest::StatusCode, String, bool), CallError> {
+        let (status, text) = fixtures.load(body).map_err(CallError::Failed)?;
+        let status = reqwest::StatusCode::from_u16(status)
+            .map_err(|e| CallError::Failed(format!("Invalid status in a recorded reply: {}", e)))?;
+        if let (true, Some(sink)) = (streamed, &self.stream) {
+            for piece in text.lines().filter_map(|line| stream_piece(self.api, line)) {
+                sink.token(&piece);
+            }
+            sink.done();
+        }
+        Ok((status, text, false))
+    }
+
+    /// A POST of `body`, with the API key for OpenAI-compatible servers, and `timeout` in
+    /// place of the client's if given.
+    fn request(&self, url: &str, body: &Bytes, timeout: Option<Duration>) -> reqwest::blocking::RequestBuilder {
+        let builder = self
+            .client
+            .post(url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body.clone());
+        let builder = match timeout {
+            Some(timeout) => builder.timeout(timeout),
+            None => builder,
+        };
+        match (self.api, api_key()) {
+            (Api::Openai, Some(key)) => builder.bearer_auth(key),
+            _ => builder,
+        }
+    }
+
+    /// Embed text or an image with an embedding model on the same server. Ollama's
+    /// `/api/embed` takes text only; images need an OpenAI-compatible server with a
+    /// multimodal embedding model.
+    pub fn embed(&self, model: &str, input: EmbedInput) -> Result<Vec<f32>, CallError> {
+        self.wait_for_throttle();
+        match (self.api, input) {
+            (Api::Ollama, EmbedInput::Text(text)) => ollama_embed(&self.client, &self.url, model, text),
+            (Api::Ollama, EmbedInput::Image(_)) => {
+                Err(CallError::Failed("Ollama's embedding API does not take images".to_string()))
+            }
+            (Api::Openai, input) => openai_embed(&self.client, &self.url, model, input),
+        }
+    }
+
+    /// The models the server has available.
+    pub fn list_models(&self) -> Result<Vec<ServerModel>, CallError> {
+        list_models(&self.client, self.api, &self.url)
+    }
+
+    fn wait_for_throttle(&self) {
+        if let Some(throttle) = &self.throttle {
+            throttle.wait();
+        }
+    }
+}
+
+fn build_ollama_request<'a>(request: &ChatRequest<'a>) -> OllamaChatRequest<'a> {
+    let image_turn = request.image_turn();
+
+    OllamaChatRequest {
+        model: request.model.unwrap_or_default().to_string(),
+        messages: request
+            .turns
+            .iter()
+            .enumerate()
+            .map(|(i, turn)| OllamaChatMessage {
+                role: turn.role.to_string(),
+                content: turn.content.clone(),
+                images: (Some(i) == image_turn && !request.images.is_empty())
+                    .then(|| request.images.iter().map(|image_data| EncodedImage::base64(image_data)).collect()),
+            })
+            .collect(),
+        stream: false,
+        options: OllamaOptions {
+            temperature: request.temperature,
+            seed: request.seed,
+        },
+        format: request.format().cloned(),
+        think: request.think,
+        tools: request.tool_schema().map(reply_tools),
+        logprobs: None,
+    }
+}
+
+/// The reply in an Ollama `/api/chat` response body.
+fn parse_ollama_reply(body: &str) -> Result<ChatReply, CallError> {
+    let chat_response: OllamaChatResponse =
+        serde_json::from_str(body).map_err(|e| format!("Failed to parse response: {}", e))?;
+    Ok(ollama_reply(chat_response))
+}
+
+/// The text in one line of a streamed reply: an Ollama chunk or an OpenAI `data:` event.
+fn stream_piece(api: Api, line: &str) -> Option<String> {
+    match api {
+        Api::Ollama => serde_json::from_str::<OllamaChatResponse>(line).ok().map(|chunk| chunk.message.content),
+        Api::Openai => {
+            let data = line.strip_prefix("data:")?.trim();
+            let chunk = serde_json::from_str::<OpenAiStreamChunk>(data).ok()?;
+            chunk.choices.into_iter().next().and_then(|choice| choice.delta.content)
+        }
+    }
+}
+
+/// The reply in a streamed Ollama `/api/chat` response: one chunk per line, the last with
+/// the token counts and timings.
+fn parse_ollama_stream(body: &str) -> Result<ChatReply, CallError> {
+    let mut content = String::new();
+    let mut thinking: Option<String> = None;
+    let mut logprobs: Option<Vec<TokenLogprob>> = None;
+    let mut last = None;
+    for line in body.lines().filter(|line| !line.trim().is_empty()) {
+        let mut chunk: OllamaChatResponse = match serde_json::from_str(line) {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                let error = serde_json::from_str::<serde_json::Value>(line).ok();
+                return Err(match error.as_ref().and_then(|value| value["error"].as_str()) {
+                    Some(error) => format!("Server returned an error mid-reply: {}", error),
+                    None => format!("Failed to parse response: {}", e),
+                }
+                .into());
+            }
+        };
+        content.push_str(&chunk.message.content);
+        // Ollama sends a tool call whole, in one chunk
+        if let Some(call) = chunk.message.tool_calls.first() {
+            content = call.function.arguments.to_string();
+        }
+        if let Some(piece) = &chunk.message.thinking {
+            thinking.get_or_insert_with(String::new).push_str(piece);
+        }
+        if let Some(tokens) = chunk.logprobs.take() {
+            logprobs.get_or_insert_with(Vec::new).extend(tokens);
+        }
+        last = Some(chunk);
+    }
+    let last = last.ok_or_else(|| "Response contained no message content".to_string())?;
+    Ok(ChatReply {
+        content,
+        thinking,
+        logprobs,
+        ..ollama_reply(last)
+    })
+}
+
+fn ollama_reply(chat_response: OllamaChatResponse) -> ChatReply {
+    let usage = match (chat_response.prompt_eval_count, chat_response.eval_count) {
+        (None, None) => None,
+        (input, output) => Some(TokenUsage {
+            input: input.unwrap_or(0),
+            output: output.unwrap_or(0),
+        }),
+    };
+    let timing = ServerTiming {
+        load_ms: chat_response.load_duration.map(latency::nanos_to_ms),
+        prompt_ms: chat_response.prompt_eval_duration.map(latency::nanos_to_ms),
+        generation_ms: chat_response.eval_duration.map(latency::nanos_to_ms),
+    };
+    let content = match chat_response.message.tool_calls.first() {
+        // The arguments of the reply tool are the reply, with `SchemaVia::Tool`
+        Some(call) => call.function.arguments.to_string(),
+        None => chat_response.message.content,
+    };
+    ChatReply {
+        content,
+        usage,
+        timing,
+        truncated: false,
+        thinking: chat_response.message.thinking,
+        logprobs: chat_response.logprobs,
+        alternatives: Vec::new(),
+    }
+}
+
+fn build_openai_request<'a>(request: &ChatRequest<'a>) -> OpenAiChatRequest<'a> {
+    let image_turn = request.image_turn();
+    let image_parts: Vec<OpenAiContentPart> = request
+        .images
+        .iter()
+        .map(|image_data| OpenAiContentPart::ImageUrl {
+            image_url: OpenAiImageUrl {
+                url: EncodedImage::data_url(image_data),
+            },
+        })
+        .collect();
+
+    OpenAiChatRequest {
+        model: request.model.map(str::to_string),
+        messages: request
+            .turns
+            .iter()
+            .enumerate()
+            .map(|(i, turn)| OpenAiChatMessage {
+                role: turn.role.to_string(),
+                content: if Some(i) == image_turn && !image_parts.is_empty() {
+                    let text = OpenAiContentPart::Text {
+                        text: turn.content.clone(),
+                    };
+                    OpenAiContent::Parts(std::iter::once(text).chain(image_parts.iter().cloned()).collect())
+                } else {
+                    OpenAiContent::Text(turn.content.clone())
+                },
+            })
+            .collect(),
+        temperature: request.temperature,
+        seed: request.seed,
+        response_format: request.format().map(|schema| OpenAiResponseFormat::JsonSchema {
+            json_schema: OpenAiJsonSchema {
+                name: "reply",
+                schema: schema.clone(),
+            },
+        }),
+        tools: request.tool_schema().map(reply_tools),
+        tool_choice: request.tool_schema().map(|_| Tool {
+            kind: "function",
+            function: ToolFunction {
+                name: REPLY_TOOL,
+                description: None,
+                parameters: None,
+            },
+        }),
+        logprobs: None,
+        n: request.n,
+        stream: false,
+        stream_options: None,
+    }
+}
+
+/// The reply in an OpenAI-compatible chat completions response body.
+fn parse_openai_reply(body: &str) -> Result<ChatReply, CallError> {
+    let chat_response: OpenAiChatResponse =
+        serde_json::from_str(body).map_err(|e| format!("Failed to parse response: {}", e))?;
+
+    let mut choices = chat_response.choices.into_iter();
+    let OpenAiChoice { mut message, logprobs } =
+        choices.next().ok_or_else(|| "Response contained no message content".to_string())?;
+    let thinking = message.reasoning_content.take();
+    let content = message_content(message).ok_or_else(|| "Response contained no message content".to_string())?;
+
+    Ok(ChatReply {
+        thinking,
+        logprobs: logprobs.and_then(|logprobs| logprobs.content),
+        alternatives: choices.filter_map(|choice| message_content(choice.message)).collect(),
+        ..openai_reply(content, chat_response.usage, chat_response.timings)
+    })
+}
+
+/// The text of a reply: the arguments of the reply tool with `SchemaVia::Tool`, otherwise
+/// the message content.
+fn message_content(message: OpenAiMessageResponse) -> Option<String> {
+    match message.tool_calls.into_iter().next() {
+        Some(call) => Some(call.function.arguments),
+        None => message.content,
+    }
+}
+
+/// The reply in a streamed chat completion: server-sent `data:` events, each with the next
+/// piece of text, ending with `data: [DONE]`. Usage comes in a last event of its own.
+fn parse_openai_stream(body: &str) -> Result<ChatReply, CallError> {
+    let mut content = None;
+    let mut thinking: Option<String> = None;
+    let mut arguments: Option<String> = None;
+    let mut logprobs: Option<Vec<TokenLogprob>> = None;
+    let (mut usage, mut timings) = (None, None);
+    for data in body.lines().filter_map(|line| line.strip_prefix("data:")).map(str::trim) {
+        if data == "[DONE]" {
+            break;
+        }
+        let chunk: OpenAiStreamChunk =
+            serde_json::from_str(data).map_err(|e| format!("Failed to parse response: {}", e))?;
+        if let Some(choice) = chunk.choices.into_iter().next() {
+            if let Some(tokens) = choice.logprobs.and_then(|logprobs| logprobs.content) {
+                logprobs.get_or_insert_with(Vec::new).extend(tokens);
+            }
+            let delta = choice.delta;
+            if let Some(piece) = delta.content {
+                content.get_or_insert_with(String::new).push_str(&piece);
+            }
+            if let Some(piece) = delta.reasoning_content {
+                thinking.get_or_insert_with(String::new).push_str(&piece);
+            }
+            if let Some(call) = delta.tool_calls.into_iter().next() {
+                arguments.get_or_insert_with(String::new).push_str(&call.function.arguments);
+            }
+        }
+        usage = chunk.usage.or(usage);
+        timings = chunk.timings.or(timings);
+    }
+    let content = arguments.or(content).ok_or_else(|| "Response contained no message content".to_string())?;
+    Ok(ChatReply {
+        thinking,
+        logprobs,
+        ..openai_reply(content, usage, timings)
+    })
+}
+
+fn openai_reply(content: String, usage: Option<OpenAiUsage>, timings: Option<LlamaCppTimings>) -> ChatReply {
+    let usage = usage.map(|usage| TokenUsage {
+        input: usage.prompt_tokens,
+        output: usage.completion_tokens,
+    });
+    let timing = timings.map_or_else(ServerTiming::default, |timings| ServerTiming {
+        load_ms: None,
+        prompt_ms: Some(timings.prompt_ms as u64),
+        generation_ms: Some(timings.predicted_ms as u64),
+    });
+    ChatReply {
+        content,
+        usage,
+        timing,
+        truncated: false,
+        thinking: None,
+        logprobs: None,
+        alternatives: Vec::new(),
+    }
+}
+
+/// Embed a piece of text with an Ollama embedding model.
+pub fn call_embed(
+    client: &reqwest::blocking::Client,
+    base_url: &str,
+    model: &str,
+    text: &str,
+) -> Result<Vec<f32>, String> {
+    ollama_embed(client, base_url, model, text).map_err(|e| e.to_string())
+}
+
+fn ollama_embed(client: &reqwest::blocking::Client, base_url: &str, model: &str, text: &str) -> Result<Vec<f32>, CallError> {
+    let url = format!("{}/api/embed", base_url.trim_end_matches('/'));
+
+    let response = client.post(&url).json(&OllamaEmbedRequest { model, input: text }).send()?;
+
+    if !response.status().is_success() {
+        let status = response.status();
+        let body = response.text().unwrap_or_default();
+        return Err(format!("Server returned {}: {}", status, body).into());
+    }
+
+    let embed_response: OllamaEmbedResponse = response
+        .json()
+        .map_err(|e| format!("Failed to parse embedding response: {}", e))?;
+
+    Ok(embed_response
+        .embeddings
+        .into_iter()
+        .next()
+        .ok_or_else(|| "Embedding response contained no vectors".to_string())?)
+}
+
+fn build_openai_embed_request<'a>(model: &'a str, input: EmbedInput<'a>) -> OpenAiEmbedRequest<'a> {
+    let (input, messages) = match input {
+        EmbedInput::Text(text) => (Some(text), None),
+        EmbedInput::Image(image_data) => {
+            let url = EncodedImage::data_url(image_data);
+            let message = OpenAiChatMessage {
+                role: "user".to_string(),
+                content: OpenAiContent::Parts(vec![OpenAiContentPart::ImageUrl {
+                    image_url: OpenAiImageUrl { url },
+                }]),
+            };
+            (None, Some(vec![message]))
+        }
+    };
+    OpenAiEmbedRequest {
+        model,
+        input,
+        messages,
+        encoding_format: "float",
+    }
+}
+
+fn openai_embed(client: &reqwest::blocking::Client, base_url: &str, model: &str, input: EmbedInput) -> Result<Vec<f32>, CallError> {
+    let mut builder = client
+        .post(openai_endpoint(base_url, "embeddings"))
+        .json(&build_openai_embed_request(model, input));
+    if let Some(key) = api_key() {
+        builder = builder.bearer_auth(key);
+    }
+
+    let response = builder.send()?;
+
+    if !response.status().is_success() {
+        let status = response.status();
+        let body = response.text().unwrap_or_default();
+        return Err(format!("Server returned {}: {}", status, body).into());
+    }
+
+    let embed_response: OpenAiEmbedResponse = response
+        .json()
+        .map_err(|e| format!("Failed to parse embedding response: {}", e))?;
+
+    Ok(embed_response
+        .data
+        .into_iter()
+        .next()
+        .map(|data| data.embedding)
+        .ok_or_else(|| "Embedding response contained no vectors".to_string())?)
+}
+
+/// A model reported by the server. The size and digest are only known for Ollama.
+pub struct ServerModel {
+    pub name: String,
+    /// Bytes on disk
+    pub size: Option<u64>,
+    /// SHA-256 of the model's manifest, which changes whenever the model is re-pulled
+    pub digest: Option<String>,
+}
+
+fn list_models(client: &reqwest::blocking::Client, api: Api, base_url: &str) -> Result<Vec<ServerModel>, CallError> {
+    let request = match api {
+        Api::Ollama => client.get(format!("{}/api/tags", base_url.trim_end_matches('/'))),
+        Api::Openai => {
+            let request = client.get(openai_endpoint(base_url, "models"));
+            match api_key() {
+                Some(key) => request.bearer_auth(key),
+                None => request,
+            }
+        }
+    };
+
+    let response = request.send()?;
+    if !response.status().is_success() {
+        return Err(format!("Server returned {}", response.status()).into());
+    }
+
+    let models = match api {
+        Api::Ollama => response.json::<OllamaTagsResponse>().map(|tags| {
+            tags.models
+                .into_iter()
+                .map(|m| ServerModel {
+                    name: m.name,
+                    size: Some(m.size),
+                    digest: m.digest,
+                })
+                .collect()
+        }),
+        Api::Openai => response.json::<OpenAiModelsResponse>().map(|models| {
+            models
+                .data
+                .into_iter()
+                .map(|m| ServerModel {
+                    name: m.id,
+                    size: None,
+                    digest: None,
+                })
+                .collect()
+        }),
+    };
+    models.map_err(|e| format!("Failed to parse response: {}", e).into())
+}
+
+/// Whether `model` is in the server's list. Ollama treats a name without a tag as `:latest`.
+pub fn model_available(models: &[ServerModel], model: &str) -> bool {
+    find_model(models, model).is_some()
+}
+
+/// `model` in the server's list, named as in `model_available`.
+pub fn find_model<'a>(models: &'a [ServerModel], model: &str) -> Option<&'a ServerModel> {
+    models
+        .iter()
+        .find(|m| m.name == model || (!model.contains(':') && m.name == format!("{}:latest", model)))
+}
+
+/// An HTTP client with a request timeout in seconds, two minutes by default.
+pub fn build_client(timeout: Option<u64>) -> reqwest::blocking::Client {
+    reqwest::blocking::Client::builder()
+        .timeout(Duration::from_secs(timeout.unwrap_or(DEFAULT_TIMEOUT_SECS)))
+        .build()
+        .expect("Failed to create HTTP client")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn fixtures_dir() -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")
+    }
+
+    #[test]
+    fn test_ollama_request_serialization() {
+        let request = OllamaChatRequest {
+            model: "qwen2.5vl:32b".to_string(),
+            messages: vec![
+                OllamaChatMessage {
+                    role: "system".to_string(),
+                    content: "You are helpful.".to_string(),
+                    images: None,
+                },
+                OllamaChatMessage {
+                    role: "user".to_string(),
+                    content: "Describe this.".to_string(),
+                    images: Some(vec![EncodedImage::base64(b"abc")]),
+                },
+            ],
+            stream: false,
+            options: OllamaOptions { temperature: Some(0.7), seed: Some(42) },
+            format: None,
+            think: None,
+            tools: None,
+            logprobs: None,
+        };
+
+        let json = serde_json::to_string(&request).unwrap();
+
+        // Verify structure
+        assert!(json.contains("\"model\":\"qwen2.5vl:32b\""));
+        assert!(json.contains("\"role\":\"system\""));
+        assert!(json.contains("\"role\":\"user\""));
+        assert!(json.contains("\"images\":[\"YWJj\"]"));
+        assert!(json.contains("\"stream\":false"));
+        assert!(json.contains("\"temperature\":0.7"));
+        assert!(json.contains("\"seed\":42"));
+        assert!(!json.contains("\"format\""));
+        assert!(!json.contains("\"think\""));
+    }
+
+    #[test]
+    fn test_openai_request_serialization() {
+        let turns = [ChatTurn::new("system", "You are helpful."), ChatTurn::new("user", "Describe this.")];
+        let png = fs::read(fixtures_dir().join("red.png")).unwrap();
+        let request = ChatRequest {
+            model: None,
+            temperature: Some(0.5),
+            seed: None,
+            turns: &turns,
+            images: &[&png],
+            schema: None,
+            think: None,
+            schema_via: None,
+            n: None,
+        };
+
+        let json = serde_json::to_value(build_openai_request(&request)).unwrap();
+
+        assert!(json.get("model").is_none());
+        assert_eq!(json["messages"][0]["content"], "You are helpful.");
+        assert_eq!(json["messages"][1]["content"][0], serde_json::json!({"type": "text", "text": "Describe this."}));
+        assert_eq!(json["messages"][1]["content"][1]["type"], "image_url");
+        let url = json["messages"][1]["content"][1]["image_url"]["url"].as_str().unwrap();
+        assert!(url.starts_with("data:image/png;base64,"));
+        assert!(json.get("response_format").is_none());
+        assert!(json.get("stream").is_none() && json.get("stream_options").is_none());
+    }
+
+    #[test]
+    fn test_several_images_share_the_first_user_turn() {
+        let turns = [ChatTurn::new("user", "What changed?"), ChatTurn::new("user", "And why?")];
+        let png = fs::read(fixtures_dir().join("red.png")).unwrap();
+        let jpeg = fs::read(fixtures_dir().join("red.jpg")).unwrap();
+        let request = ChatRequest {
+            model: Some("llava"),
+            temperature: None,
+            seed: None,
+            turns: &turns,
+            images: &[&png, &jpeg],
+            schema: None,
+            think: None,
+            schema_via: None,
+            n: None,
+        };
+
+        let ollama = serde_json::to_value(build_ollama_request(&request)).unwrap();
+        assert_eq!(ollama["messages"][0]["images"].as_array().unwrap().len(), 2);
+        assert!(ollama["messages"][1].get("images").is_none());
+
+        let openai = serde_json::to_value(build_openai_request(&request)).unwrap();
+        let parts = openai["messages"][0]["content"].as_array().unwrap();
+        assert_eq!(parts.len(), 3);
+        assert!(parts[1]["image_url"]["url"].as_str().unwrap().starts_with("data:image/png;"));
+        assert!(parts[2]["image_url"]["url"].as_str().unwrap().starts_with("data:image/jpeg;"));
+        assert_eq!(openai["messages"][1]["content"], "And why?");
+    }
+
+    #[test]
+    fn test_image_mime_type() {
+        let fixtures = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+        for (name, mime) in [("red.png", "image/png"), ("red.jpg", "image/jpeg"), ("red.gif", "image/gif"), ("red.webp", "image/webp")] {
+            assert_eq!(image_mime_type(&std::fs::read(fixtures.join(name)).unwrap()), mime);
+        }
+        assert_eq!(image_mime_type(b"BM\x3a\0\0\0\0\0\0\0\x36\0\0\0\x28\0"), "image/bmp");
+        assert_eq!(image_mime_type(b"not an image at all"), "application/octet-stream");
+    }
+
+    #[test]
+    fn test_text_only_request() {
+        let turns = [ChatTurn::new("user", "Summarize these captions.")];
+        let request = ChatRequest {
+            model: Some("llava"),
+            temperature: None,
+            seed: None,
+            turns: &turns,
+            images: &[],
+            schema: None,
+            think: None,
+            schema_via: None,
+            n: None,
+        };
+
+        let ollama = serde_json::to_value(build_ollama_request(&request)).unwrap();
+        assert!(ollama["messages"][0].get("images").is_none());
+        let openai = serde_json::to_value(build_openai_request(&request)).unwrap();
+        assert_eq!(openai["messages"][0]["content"], "Summarize these captions.");
+    }
+
+    #[test]
+    fn test_schema_constrains_both_apis() {
+        let turns = [ChatTurn::new("user", "Which animal?")];
+        let png = fs::read(fixtures_dir().join("red.png")).unwrap();
+        let schema = serde_json::json!({"type": "object", "properties": {"label": {"enum": ["cat", "dog"]}}});
+        let request = ChatRequest {
+            model: Some("llava"),
+            temperature: None,
+            seed: None,
+            turns: &turns,
+            images: &[&png],
+            schema: Some(&schema),
+            think: None,
+            schema_via: None,
+            n: None,
+        };
+
+        let ollama = serde_json::to_value(build_ollama_request(&request)).unwrap();
+        assert_eq!(ollama["format"], schema);
+        let openai = serde_json::to_value(build_openai_request(&request)).unwrap();
+        assert_eq!(
+            openai["response_format"],
+            serde_json::json!({"type": "json_schema", "json_schema": {"name": "reply", "schema": schema}})
+        );
+    }
+
+    #[test]
+    fn test_schema_as_tool() {
+        let turns = [ChatTurn::new("user", "Which animal?")];
+        let schema = serde_json::json!({"type": "object", "properties": {"label": {"enum": ["cat", "dog"]}}});
+        let request = ChatRequest {
+            model: Some("llava"),
+            temperature: None,
+            seed: None,
+            turns: &turns,
+            images: &[],
+            schema: Some(&schema),
+            think: None,
+            schema_via: Some(SchemaVia::Tool),
+            n: None,
+        };
+        let tool = serde_json::json!([{
+            "type": "function",
+            "function": {"name": "reply", "description": "Give your answer", "parameters": schema}
+        }]);
+
+        let ollama = serde_json::to_value(build_ollama_request(&request)).unwrap();
+        assert!(ollama.get("format").is_none());
+        assert_eq!(ollama["tools"], tool);
+        let openai = serde_json::to_value(build_openai_request(&request)).unwrap();
+        assert!(openai.get("response_format").is_none());
+        assert_eq!(openai["tools"], tool);
+        assert_eq!(openai["tool_choice"], serde_json::json!({"type": "function", "function": {"name": "reply"}}));
+
+        // The tool's arguments are the reply
+        let reply = parse_ollama_reply(
+            r#"{"message":{"content":"","tool_calls":[{"function":{"name":"reply","arguments":{"label":"cat"}}}]}}"#,
+        )
+        .unwrap();
+        assert_eq!(reply.content, r#"{"label":"cat"}"#);
+        let reply = parse_openai_reply(
+            r#"{"choices":[{"message":{"content":null,"tool_calls":[{"id":"call_1","type":"function","function":{"name":"reply","arguments":"{\"label\": \"dog\"}"}}]}}]}"#,
+        )
+        .unwrap();
+        assert_eq!(reply.content, r#"{"label": "dog"}"#);
+        let streamed = concat!(
+            "data: {\"choices\":[{\"delta\":{\"tool_calls\":[{\"index\":0,\"id\":\"call_1\",\"function\":{\"name\":\"reply\",\"arguments\":\"\"}}]}}]}\n\n",
+            "data: {\"choices\":[{\"delta\":{\"tool_calls\":[{\"index\":0,\"function\":{\"arguments\":\"{\\\"label\\\": \"}}]}}]}\n\n",
+            "data: {\"choices\":[{\"delta\":{\"tool_calls\":[{\"index\":0,\"function\":{\"arguments\":\"\\\"cat\\\"}\"}}]}}]}\n\n",
+            "data: [DONE]\n\n",
+        );
+        assert_eq!(parse_openai_stream(streamed).unwrap().content, r#"{"label": "cat"}"#);
+    }
+
+    #[test]
+    fn test_openai_embed_request_serialization() {
+        let text = serde_json::to_value(build_openai_embed_request("e5", EmbedInput::Text("a cat"))).unwrap();
+        assert_eq!(text, serde_json::json!({"model": "e5", "input": "a cat", "encoding_format": "float"}));
+
+        let png = fs::read(fixtures_dir().join("red.png")).unwrap();
+        let image = serde_json::to_value(build_openai_embed_request("clip", EmbedInput::Image(&png))).unwrap();
+        assert!(image.get("input").is_none());
+        assert_eq!(image["messages"][0]["role"], "user");
+        let url = image["messages"][0]["content"][0]["image_url"]["url"].as_str().unwrap();
+        assert!(url.starts_with("data:image/png;base64,"));
+    }
+
+    #[test]
+    fn test_megapixels() {
+        let mut png = Vec::new();
+        image::RgbImage::new(2000, 1500)
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        let pixel = fs::read(fixtures_dir().join("red.png")).unwrap();
+        assert!((megapixels(&[&png, &pixel, b"not an image"]) - 3.000001).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_openai_endpoint() {
+        assert_eq!(openai_endpoint("http://localhost:8080", "models"), "http://localhost:8080/v1/models");
+        assert_eq!(
+            openai_endpoint("http://localhost:8080/v1/", "chat/completions"),
+            "http://localhost:8080/v1/chat/completions"
+        );
+    }
+
+    #[test]
+    fn test_extract_model_content() {
+        let extract = |content: &str| extract_model_content(content.to_string());
+        assert_eq!(extract(r#"{"cats": 2}"#), (serde_json::json!({"cats": 2}), false));
+        assert_eq!(extract("```json\n{\"cats\": 2}\n```"), (serde_json::json!({"cats": 2}), true));
+        assert_eq!(
+            extract("Here is the JSON [as asked]:\n[\"cat\", \"sofa\"]\nLet me know if you need more."),
+            (serde_json::json!(["cat", "sofa"]), true)
+        );
+        // A reply cut off mid-object is kept as text, not mined for the objects inside it
+        let cut = r#"{"vendor": {"name": "Aldi"}, "total": "#;
+        assert_eq!(extract(cut), (serde_json::json!(cut), false));
+        assert_eq!(extract("A cat on a sofa"), (serde_json::json!("A cat on a sofa"), false));
+    }
+
+    #[test]
+    fn test_split_thinking() {
+        let split = |content: &str, thinking: Option<&str>| {
+            let reply = split_thinking(ChatReply {
+                thinking: thinking.map(str::to_string),
+                ..openai_reply(content.to_string(), None, None)
+            });
+            (reply.content, reply.thinking)
+        };
+        let thought = |text: &str| Some(text.to_string());
+        assert_eq!(
+            split("<think>\nTwo cats, one asleep.\n</think>\n\n{\"cats\": 2}", None),
+            ("{\"cats\": 2}".to_string(), thought("Two cats, one asleep."))
+        );
+        // The template opened the tag, and the server sent other thinking apart
+        assert_eq!(
+            split("Count them.</think>{\"cats\": 2}", Some("Two cats.")),
+            ("{\"cats\": 2}".to_string(), thought("Two cats.\n\nCount them."))
+        );
+        assert_eq!(split("<think>Still counting", None), (String::new(), thought("Still counting")));
+        assert_eq!(split("A cat", None), ("A cat".to_string(), None));
+        assert_eq!(split("Write <think></think> literally", None), ("Write <think></think> literally".to_string(), None));
+
+        let ollama = parse_ollama_reply(r#"{"message":{"content":"{}","thinking":"One cat."}}"#).unwrap();
+        assert_eq!(ollama.thinking.as_deref(), Some("One cat."));
+        let openai = parse_openai_reply(r#"{"choices":[{"message":{"content":"{}","reasoning_content":"One cat."}}]}"#).unwrap();
+        assert_eq!(openai.thinking.as_deref(), Some("One cat."));
+    }
+
+    #[test]
+    fn test_parse_logprobs() {
+        let tokens = |reply: ChatReply| reply.logprobs.unwrap().into_iter().map(|token| token.token).collect::<Vec<_>>();
+        let openai = parse_openai_reply(
+            r#"{"choices":[{"message":{"content":"A cat"},"logprobs":{"content":[{"token":"A","logprob":-0.1,"bytes":[65],"top_logprobs":[]},{"token":" cat","logprob":-0.5}]}}]}"#,
+        )
+        .unwrap();
+        assert_eq!(tokens(openai), ["A", " cat"]);
+        let ollama = concat!(
+            r#"{"message":{"content":"A"},"logprobs":[{"token":"A","logprob":-0.1}],"done":false}"#, "\n",
+            r#"{"message":{"content":" cat"},"logprobs":[{"token":" cat","logprob":-0.5}],"done":false}"#, "\n",
+            r#"{"message":{"content":""},"done":true}"#, "\n",
+        );
+        assert_eq!(tokens(parse_ollama_stream(ollama).unwrap()), ["A", " cat"]);
+        assert!(parse_ollama_reply(r#"{"message":{"content":"A cat"}}"#).unwrap().logprobs.is_none());
+    }
+
+    #[test]
+    fn test_several_answers() {
+        let turns = [ChatTurn::new("user", "Caption this.")];
+        let request = ChatRequest {
+            model: None,
+            temperature: Some(0.8),
+            seed: None,
+            turns: &turns,
+            images: &[],
+            schema: None,
+            think: None,
+            schema_via: None,
+            n: Some(2),
+        };
+        assert_eq!(serde_json::to_value(build_openai_request(&request)).unwrap()["n"], 2);
+        assert!(serde_json::to_value(build_ollama_request(&request)).unwrap().get("n").is_none());
+
+        let reply = parse_openai_reply(
+            r#"{"choices":[{"message":{"content":"A cat"}},{"message":{"content":"<think>Hm.</think>A sleeping cat"}}]}"#,
+        )
+        .unwrap();
+        let reply = split_thinking(reply);
+        assert_eq!((reply.content.as_str(), reply.alternatives), ("A cat", vec!["A sleeping cat".to_string()]));
+    }
+
+    #[test]
+    fn test_response_token_usage() {
+        let openai: OpenAiChatResponse = serde_json::from_str(
+            r#"{"choices":[{"message":{"content":"hi"}}],"usage":{"prompt_tokens":812,"completion_tokens":40,"total_tokens":852}}"#,
+        )
+        .unwrap();
+        let usage = openai.usage.unwrap();
+        assert_eq!((usage.prompt_tokens, usage.completion_tokens), (812, 40));
+
+        let ollama: OllamaChatResponse =
+            serde_json::from_str(r#"{"message":{"content":"hi"},"prompt_eval_count":20,"eval_count":7}"#).unwrap();
+        assert_eq!((ollama.prompt_eval_count, ollama.eval_count), (Some(20), Some(7)));
+
+        let bare: OllamaChatResponse = serde_json::from_str(r#"{"message":{"content":"hi"}}"#).unwrap();
+        assert_eq!(bare.eval_count, None);
+    }
+
+    #[test]
+    fn test_parse_streamed_replies() {
+        let ollama = concat!(
+            r#"{"message":{"role":"assistant","content":"A red"},"done":false}"#, "\n",
+            r#"{"message":{"role":"assistant","content":" square"},"done":false}"#, "\n",
+            r#"{"message":{"role":"assistant","content":""},"done":true,"prompt_eval_count":20,"eval_count":2,"eval_duration":5000000}"#, "\n",
+        );
+        assert_eq!(stream_piece(Api::Ollama, ollama.lines().nth(1).unwrap()).as_deref(), Some(" square"));
+        let reply = parse_ollama_stream(ollama).unwrap();
+        assert_eq!(reply.content, "A red square");
+        assert_eq!(reply.usage.map(|usage| (usage.input, usage.output)), Some((20, 2)));
+        assert_eq!(reply.timing.generation_ms, Some(5));
+        let Err(error) = parse_ollama_stream("{\"error\":\"model ran out of memory\"}\n") else {
+            panic!("an error line should fail the reply");
+        };
+        assert!(error.to_string().ends_with("mid-reply: model ran out of memory"));
+
+        let openai = concat!(
+            "data: {\"choices\":[{\"delta\":{\"role\":\"assistant\"}}]}\n\n",
+            "data: {\"choices\":[{\"delta\":{\"content\":\"A red\"}}]}\n\n",
+            "data: {\"choices\":[{\"delta\":{\"content\":\" square\"}}]}\n\n",
+            "data: {\"choices\":[],\"usage\":{\"prompt_tokens\":812,\"completion_tokens\":2}}\n\n",
+            "data: [DONE]\n\n",
+        );
+        assert_eq!(stream_piece(Api::Openai, openai.lines().nth(2).unwrap()).as_deref(), Some("A red"));
+        assert_eq!(stream_piece(Api::Openai, "data: [DONE]"), None);
+        let reply = parse_openai_stream(openai).unwrap();
+        assert_eq!(reply.content, "A red square");
+        assert_eq!(reply.usage.map(|usage| (usage.input, usage.output)), Some((812, 2)));
+        assert!(parse_openai_stream("data: [DONE]\n").is_err());
+    }
+
+    #[test]
+    fn test_keep_partial_reply_after_stall() {
+        use std::io::{Read, Write};
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for stream in listener.incoming().take(2) {
+                let mut stream = stream.unwrap();
+                let mut request = [0u8; 65536];
+                let _ = stream.read(&mut request);
+                let chunk = r#"{"message":{"content":"A red"},"done":false}"#;
+                let _ = write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: application/x-ndjson\r\n\r\n{}\n", chunk);
+                let _ = stream.flush();
+                std::thread::sleep(Duration::from_millis(1500));
+            }
+        });
+        let config = PromptConfig {
+            system: "s".to_string(),
+            prompt: "p".to_string(),
+            temperature: 0.0,
+            model: None,
+            schema: None,
+            seed: None,
+            think: None,
+            schema_via: None,
+        };
+        let stall = Some(Duration::from_millis(200));
+
+        let backend = Backend::new(Api::Ollama, &url, Some("llava".to_string()), Some(10));
+        let reply = backend.clone().with_partial_replies(true, stall).describe(&config, b"").unwrap();
+        assert_eq!((reply.content.as_str(), reply.truncated), ("A red", true));
+        let Err(error) = backend.with_partial_replies(false, stall).describe(&config, b"") else {
+            panic!("a stalled reply should fail without --keep-partial");
+        };
+        assert!(error.to_string().contains("sent nothing more for 0.2s"));
+    }
+
+    #[test]
+    fn test_model_available() {
+        let models = vec![
+            ServerModel { name: "llava:latest".to_string(), size: None, digest: None },
+            ServerModel { name: "qwen2.5vl:32b".to_string(), size: None, digest: None },
+        ];
+
+        assert!(model_available(&models, "llava"));
+        assert!(model_available(&models, "llava:latest"));
+        assert!(model_available(&models, "qwen2.5vl:32b"));
+        assert!(!model_available(&models, "qwen2.5vl"));
+        assert!(!model_available(&models, "llava:13b"));
+    }
+}
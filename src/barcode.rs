@@ -0,0 +1,67 @@
+//! Reading barcodes and QR codes locally, since vision models are unreliable at copying the
+//! digits of a barcode exactly.
+
+use serde::{Deserialize, Serialize};
+
+/// A barcode or QR code found in an image.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Barcode {
+    /// Symbology in snake case, e.g. `ean_13`, `code_128` or `qrcode`
+    pub format: String,
+    /// The decoded contents
+    pub value: String,
+}
+
+/// Every barcode and QR code rxing can find in the image, in the order found. An image
+/// without any gives an empty list; only an image that can't be decoded is an error.
+#[cfg(feature = "barcode")]
+pub fn decode_barcodes(image_data: &[u8]) -> Result<Vec<Barcode>, String> {
+    let luma = image::load_from_memory(image_data)
+        .map_err(|e| format!("Failed to decode image for barcode reading: {}", e))?
+        .to_luma8();
+    let (width, height) = luma.dimensions();
+
+    match rxing::helpers::detect_multiple_in_luma(luma.into_raw(), width, height) {
+        Ok(results) => Ok(results
+            .iter()
+            .map(|result| Barcode {
+                format: result.getBarcodeFormat().to_string().replace(' ', "_"),
+                value: result.getText().to_string(),
+            })
+            .collect()),
+        Err(rxing::Exceptions::NotFoundException(_)) => Ok(Vec::new()),
+        Err(e) => Err(format!("Barcode reading failed: {}", e)),
+    }
+}
+
+#[cfg(all(test, feature = "barcode"))]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn fixture(name: &str) -> Vec<u8> {
+        std::fs::read(PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name)).unwrap()
+    }
+
+    #[test]
+    fn test_decode_barcodes_reads_ean13() {
+        let barcodes = decode_barcodes(&fixture("ean13.png")).unwrap();
+        assert_eq!(
+            barcodes,
+            vec![Barcode {
+                format: "ean_13".to_string(),
+                value: "4006381333931".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_decode_barcodes_without_any() {
+        assert!(decode_barcodes(&fixture("red.png")).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_decode_barcodes_rejects_garbage() {
+        assert!(decode_barcodes(b"not an image at all").is_err());
+    }
+}
@@ -0,0 +1,1481 @@
+//! The batch engine: describing a list of images one after another.
+
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tracing::{debug, error, info, warn};
+
+use crate::backend::{extract_model_content, parse_model_content, Backend, CallError, ChatReply, ChatTurn, EmbedInput};
+use crate::confidence::{self, Confidence, ConfidenceMethod};
+use crate::cost::TokenUsage;
+use crate::download::{self, Downloader};
+use crate::exif::ExifData;
+use crate::field_map::FieldMap;
+use crate::followup;
+use crate::grounding;
+use crate::image_stats::ImageStats;
+use crate::images::{detect_image_format, sha256_hex, validate_image_file, ImageInfo};
+use crate::input_manifest::InputManifest;
+use crate::labels::LabelSet;
+use crate::language::{self, Language};
+use crate::latency::{LatencyStats, ServerTimeTotals, ServerTiming, Timing};
+use crate::memory::{self, MemoryBudget, Reservation};
+use crate::nsfw::{NsfwAction, NsfwFilter, NsfwOutcome};
+use crate::ocr::{self, Ocr};
+use crate::oversize::{OversizePolicy, SizeLimit};
+use crate::paths;
+use crate::products::{self, Product};
+use crate::prompt::PromptConfig;
+use crate::record::{ContextMode, EmbedSource, OutputRecord};
+use crate::run_window::RunWindow;
+use crate::sidecar;
+use crate::sinks::embedding_text;
+
+/// How often a paused run checks whether the run window has opened
+const WINDOW_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// An image for `run_inputs`: a file to read, or bytes received under a name.
+pub struct Input {
+    pub file: String,
+    pub data: Option<Vec<u8>>,
+}
+
+/// A successfully described image, or one the NSFW filter kept from the model.
+pub struct Described {
+    pub record: OutputRecord,
+    /// Tokens used, including any retry, when the server reports them
+    pub usage: Option<TokenUsage>,
+    /// Wall time of the model request(s)
+    pub elapsed: Duration,
+}
+
+impl Described {
+    /// The NSFW filter kept the image from the model, so there is no response.
+    pub fn skipped(&self) -> bool {
+        self.record.nsfw.is_some_and(|verdict| verdict.outcome == NsfwOutcome::Skipped)
+    }
+}
+
+/// Why an image could not be described.
+#[derive(Debug)]
+pub enum ItemError {
+    /// Missing, unreadable or not a supported image
+    Invalid(String),
+    /// The model request failed
+    Call(CallError),
+    /// A `Transform` rejected the image or failed
+    Transform(String),
+    /// A local pass before the model (OCR, the NSFW classifier) failed
+    Local(String),
+    /// A URL input could not be downloaded
+    Download(String),
+    /// The file is not the one the input manifest lists
+    Unverified(String),
+}
+
+impl std::fmt::Display for ItemError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ItemError::Invalid(message)
+            | ItemError::Transform(message)
+            | ItemError::Local(message)
+            | ItemError::Download(message)
+            | ItemError::Unverified(message) => f.write_str(message),
+            ItemError::Call(e) => e.fmt(f),
+        }
+    }
+}
+
+/// Changes to the request or the record for each image, for cleanup and enrichment that
+/// belongs inside the pipeline (see `script::LuaScript`).
+pub trait Transform: Send + Sync {
+    /// The prompt config for one image; called before each request.
+    fn transform_prompt(&self, _file: &str, config: PromptConfig) -> Result<PromptConfig, String> {
+        Ok(config)
+    }
+
+    /// Adjust a record after the model replied, before it is handed to the hooks.
+    fn transform_response(&self, _record: &mut OutputRecord) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// What a caller does with each image in a batch: writing records out, updating progress,
+/// stopping early.
+pub trait BatchHooks {
+    /// Checked before each image; return true to stop the batch there (e.g. on Ctrl+C).
+    fn stop_requested(&self) -> bool {
+        false
+    }
+
+    /// An image is about to be processed.
+    fn started(&mut self, _file: &str) {}
+
+    /// An image was described. Returning an error (from writing the record somewhere, say)
+    /// counts the image as failed; the error should already have been reported.
+    fn described(&mut self, described: &mut Described) -> Result<(), String>;
+
+    /// An image is done, with the first error if it failed.
+    fn finished(&mut self, _file: &str, _error: Option<&str>) {}
+
+    /// The run window has closed, with no image in progress; nothing more is sent until
+    /// `resumed`. `opens` is when the window opens again, as `HH:MM`.
+    fn paused(&mut self, _opens: &str) {}
+
+    /// The run window has opened again.
+    fn resumed(&mut self) {}
+}
+
+/// Totals for a batch.
+#[derive(Debug, Default)]
+pub struct BatchSummary {
+    pub total: usize,
+    /// Images attempted; the rest were skipped by stopping early
+    pub processed: usize,
+    /// Images the model described and the hooks took
+    pub succeeded: usize,
+    /// Images with any error, including ones described but then not written out; these
+    /// aren't also counted as succeeded
+    pub failed: usize,
+    /// Images the NSFW filter kept from the model; also counted as succeeded
+    pub nsfw_skipped: usize,
+    /// Model requests that could not connect to the server
+    pub connect_failures: usize,
+    /// Whether any model request got a reply
+    pub server_reached: bool,
+    pub usage: TokenUsage,
+    /// Replies that did not report token usage
+    pub replies_without_usage: usize,
+    pub latency: LatencyStats,
+    /// Where the server says the time went, for servers that report it
+    pub server_time: ServerTimeTotals,
+    pub elapsed: Duration,
+}
+
+impl BatchSummary {
+    /// Every failure was a connection failure, so the server is probably down.
+    pub fn unreachable(&self) -> bool {
+        self.connect_failures > 0 && !self.server_reached
+    }
+}
+
+/// Add the instruction to answer in `language` to the system prompt.
+fn add_language_instruction(config: &mut PromptConfig, language: &Language) {
+    if !config.system.is_empty() {
+        config.system.push_str("\n\n");
+    }
+    config.system.push_str(&language.instruction());
+}
+
+/// Describes images with one backend and prompt config.
+///
+/// ```no_run
+/// use nineladies::{load_prompt_config, Api, Backend, BatchRunner};
+///
+/// let config = load_prompt_config("prompts/describe.json")?;
+/// let backend = Backend::new(Api::Ollama, "http://localhost:11434", Some("llava:13b".to_string()), None);
+/// let runner = BatchRunner::new(backend, config);
+/// let described = runner.describe("photo.jpg").map_err(|e| e.to_string())?;
+/// println!("{}", described.record.response);
+/// # Ok::<(), String>(())
+/// ```
+pub struct BatchRunner {
+    backend: Backend,
+    config: PromptConfig,
+    language: Option<Language>,
+    error_limit: Option<usize>,
+    run_window: Option<RunWindow>,
+    downloader: Option<Arc<Downloader>>,
+    memory: Option<MemoryBudget>,
+    manifest: Option<InputManifest>,
+    transform: Option<Box<dyn Transform>>,
+    ocr: Option<Ocr>,
+    nsfw: Option<NsfwFilter>,
+    size_limit: Option<SizeLimit>,
+    image_info: bool,
+    image_stats: Option<ContextMode>,
+    exif: Option<ContextMode>,
+    detections: bool,
+    embedding: Option<(String, EmbedSource)>,
+    labels: Option<(LabelSet, Option<usize>)>,
+    /// The method, and how many answers to compare for `ConfidenceMethod::Agreement`
+    confidence: Option<(ConfidenceMethod, usize)>,
+    /// Answers to record for each image, for `--candidates`
+    candidates: usize,
+    translations: Vec<Language>,
+    field_map: FieldMap,
+    /// Servers to spread the run over, each with how many requests it takes at once
+    endpoints: Vec<(Backend, usize)>,
+    #[cfg(feature = "barcode")]
+    barcodes: bool,
+    #[cfg(feature = "faces")]
+    face_blur: Option<crate::faces::FaceBlur>,
+}
+
+impl BatchRunner {
+    pub fn new(backend: Backend, config: PromptConfig) -> BatchRunner {
+        BatchRunner {
+            backend,
+            config,
+            language: None,
+            error_limit: None,
+            run_window: None,
+            downloader: None,
+            memory: None,
+            manifest: None,
+            transform: None,
+            ocr: None,
+            nsfw: None,
+            size_limit: None,
+            image_info: false,
+            image_stats: None,
+            exif: None,
+            detections: false,
+            embedding: None,
+            labels: None,
+            confidence: None,
+            candidates: 1,
+            translations: Vec::new(),
+            field_map: FieldMap::default(),
+            endpoints: Vec::new(),
+            #[cfg(feature = "barcode")]
+            barcodes: false,
+            #[cfg(feature = "faces")]
+            face_blur: None,
+        }
+    }
+
+    /// Ask for replies in `language`, adding the instruction to the system prompt and
+    /// asking again once if a reply comes back in another language.
+    pub fn with_language(mut self, language: Option<Language>) -> BatchRunner {
+        if let Some(language) = &language {
+            add_language_instruction(&mut self.config, language);
+        }
+        self.language = language;
+        self
+    }
+
+    /// Rename and move fields in each JSON response with `field_map`, before anything else
+    /// looks at it.
+    pub fn with_field_map(mut self, field_map: FieldMap) -> BatchRunner {
+        self.field_map = field_map;
+        self
+    }
+
+    /// Stop the batch once this many images have failed.
+    pub fn with_error_limit(mut self, limit: Option<usize>) -> BatchRunner {
+        self.error_limit = limit;
+        self
+    }
+
+    /// Only send requests while `window` is open, pausing between images outside it.
+    pub fn with_run_window(mut self, window: Option<RunWindow>) -> BatchRunner {
+        self.run_window = window;
+        self
+    }
+
+    /// Download URL inputs with `downloader`; without one they fail.
+    pub fn with_downloader(mut self, downloader: Option<Downloader>) -> BatchRunner {
+        self.downloader = downloader.map(Arc::new);
+        self
+    }
+
+    /// Hold back images while those in progress are expected to take more than `budget`.
+    pub fn with_memory_budget(mut self, budget: Option<MemoryBudget>) -> BatchRunner {
+        self.memory = budget;
+        self
+    }
+
+    /// Check each file against `manifest` as it is read, failing any it doesn't list or
+    /// whose contents differ.
+    pub fn with_input_manifest(mut self, manifest: Option<InputManifest>) -> BatchRunner {
+        self.manifest = manifest;
+        self
+    }
+
+    /// Run each image's prompt and record through `transform`.
+    pub fn with_transform(mut self, transform: Option<Box<dyn Transform>>) -> BatchRunner {
+        self.transform = transform;
+        self
+    }
+
+    /// Read each image's text with `ocr` and add it to the question, so the model corrects
+    /// and structures it rather than reading the text itself.
+    pub fn with_ocr(mut self, ocr: Option<Ocr>) -> BatchRunner {
+        self.ocr = ocr;
+        self
+    }
+
+    /// Score each image with `nsfw` before anything is sent, skipping, flagging or routing
+    /// the ones over its threshold.
+    pub fn with_nsfw(mut self, nsfw: Option<NsfwFilter>) -> BatchRunner {
+        self.nsfw = nsfw;
+        self
+    }
+
+    /// Reject, warn about or scale down images over `limit` before they are sent.
+    pub fn with_size_limit(mut self, limit: Option<SizeLimit>) -> BatchRunner {
+        self.size_limit = limit;
+        self
+    }
+
+    /// Add each image's dimensions, format and file size, read from its header, to its record.
+    pub fn with_image_info(mut self, image_info: bool) -> BatchRunner {
+        self.image_info = image_info;
+        self
+    }
+
+    /// Measure each image and add the statistics to its record, and with
+    /// `ContextMode::Prompt` to the question too.
+    pub fn with_image_stats(mut self, mode: Option<ContextMode>) -> BatchRunner {
+        self.image_stats = mode;
+        self
+    }
+
+    /// Read each image's EXIF capture time, camera and location into its record, and with
+    /// `ContextMode::Prompt` into the question too.
+    pub fn with_exif(mut self, mode: Option<ContextMode>) -> BatchRunner {
+        self.exif = mode;
+        self
+    }
+
+    /// Parse bounding boxes in each response into the record's `detections`.
+    pub fn with_detections(mut self, detections: bool) -> BatchRunner {
+        self.detections = detections;
+        self
+    }
+
+    /// Add a vector from `model`, on the same server, to each record. With
+    /// `EmbedSource::Image` the image is embedded instead of described.
+    pub fn with_embedding(mut self, model: Option<String>, source: EmbedSource) -> BatchRunner {
+        self.embedding = model.map(|model| (model, source));
+        self
+    }
+
+    /// Classify each image into one of `labels` instead of describing it: the response is
+    /// the label, checked against the set, and with `top_k` the record's `top_labels` lists
+    /// the likeliest few.
+    pub fn with_labels(mut self, labels: Option<LabelSet>, top_k: Option<usize>) -> BatchRunner {
+        self.labels = labels.map(|labels| (labels, top_k));
+        self
+    }
+
+    /// Score each response's confidence with `method`, comparing `samples` answers in all
+    /// for `ConfidenceMethod::Agreement`.
+    pub fn with_confidence(mut self, method: Option<ConfidenceMethod>, samples: usize) -> BatchRunner {
+        self.confidence = method.map(|method| (method, samples));
+        self
+    }
+
+    /// Ask for `count` answers about each image and record them all as `candidates`, the
+    /// first also as the response.
+    pub fn with_candidates(mut self, count: usize) -> BatchRunner {
+        self.candidates = count.max(1);
+        self
+    }
+
+    /// Translate each response into `languages` with a text-only request per language,
+    /// adding them as `response.translations`.
+    pub fn with_translations(mut self, languages: Vec<Language>) -> BatchRunner {
+        self.translations = languages;
+        self
+    }
+
+    /// Spread `run` over several servers, each given up to its count of images at once,
+    /// instead of describing one image at a time with the runner's own backend. Records then
+    /// arrive in the order they finish.
+    pub fn with_endpoints(mut self, endpoints: Vec<(Backend, usize)>) -> BatchRunner {
+        self.endpoints = endpoints.into_iter().filter(|(_, concurrency)| *concurrency > 0).collect();
+        self
+    }
+
+    /// Read barcodes and QR codes locally and add them to each record.
+    #[cfg(feature = "barcode")]
+    pub fn with_barcodes(mut self, barcodes: bool) -> BatchRunner {
+        self.barcodes = barcodes;
+        self
+    }
+
+    /// Blur faces found by `face_blur` in the copy of each image sent to the model.
+    #[cfg(feature = "faces")]
+    pub fn with_face_blur(mut self, face_blur: Option<crate::faces::FaceBlur>) -> BatchRunner {
+        self.face_blur = face_blur;
+        self
+    }
+
+    pub fn backend(&self) -> &Backend {
+        &self.backend
+    }
+
+    pub fn config(&self) -> &PromptConfig {
+        &self.config
+    }
+
+    /// Read, check and describe one image.
+    pub fn describe(&self, path: &str) -> Result<Described, ItemError> {
+        self.describe_on(&self.backend, path)
+    }
+
+    fn describe_on(&self, backend: &Backend, path: &str) -> Result<Described, ItemError> {
+        if download::is_url(path) {
+            let Some(downloader) = &self.downloader else {
+                return Err(ItemError::Invalid(format!("'{}' is a URL, and URL inputs aren't enabled", path)));
+            };
+            let image_data = downloader.take(path).map_err(ItemError::Download)?;
+            let _reservation = self.reserve_memory(path, image_data.len() as u64, None);
+            self.verify(path, &image_data)?;
+            return self.describe_with(backend, path, &image_data, self.config.clone());
+        }
+        let _reservation = match &self.memory {
+            Some(_) => {
+                let bytes = fs::metadata(paths::long_path(Path::new(path))).map_or(0, |metadata| metadata.len());
+                let resize = self.size_limit.as_ref().is_some_and(|limit| limit.policy == OversizePolicy::Resize);
+                let pixels = resize
+                    .then(|| image::image_dimensions(paths::long_path(Path::new(path))).ok())
+                    .flatten()
+                    .map(|(width, height)| u64::from(width) * u64::from(height));
+                self.reserve_memory(path, bytes, pixels)
+            }
+            None => None,
+        };
+        let image_data = validate_image_file(Path::new(path)).map_err(ItemError::Invalid)?;
+        self.verify(path, &image_data)?;
+        self.describe_file_with(backend, path, &image_data)
+    }
+
+    /// Describe the file at `path`, already read into `image_data`, with its sidecar's
+    /// overrides if it has one.
+    fn describe_file_with(&self, backend: &Backend, path: &str, image_data: &[u8]) -> Result<Described, ItemError> {
+        let Some((sidecar_path, sidecar)) = sidecar::find(Path::new(path)).map_err(ItemError::Invalid)? else {
+            return self.describe_with(backend, path, image_data, self.config.clone());
+        };
+        let config = match sidecar.prompt_config(&sidecar_path).map_err(ItemError::Invalid)? {
+            Some(mut config) => {
+                if let Some(language) = &self.language {
+                    add_language_instruction(&mut config, language);
+                }
+                config
+            }
+            None => self.config.clone(),
+        };
+        debug!(file = path, sidecar = %sidecar_path.display(), "Applying sidecar overrides");
+        let mut described = self.describe_with(backend, path, image_data, sidecar.apply(config))?;
+        described.record.sidecar = Some(paths::normalize(&sidecar_path.to_string_lossy()));
+        Ok(described)
+    }
+
+    /// Check `image_data`, read for `file`, against the input manifest if there is one.
+    fn verify(&self, file: &str, image_data: &[u8]) -> Result<(), ItemError> {
+        match &self.manifest {
+            Some(manifest) => manifest.check(file, &sha256_hex(image_data)).map_err(ItemError::Unverified),
+            None => Ok(()),
+        }
+    }
+
+    /// Reserve what describing `file` is expected to take from the memory budget, if there
+    /// is one, waiting for room.
+    fn reserve_memory(&self, file: &str, bytes: u64, pixels: Option<u64>) -> Option<Reservation<'_>> {
+        let budget = self.memory.as_ref()?;
+        let estimate = memory::image_estimate(bytes, pixels);
+        let waited = Instant::now();
+        let reservation = budget.reserve(estimate);
+        debug!(file, estimate, waited_ms = waited.elapsed().as_millis() as u64, "Memory reserved");
+        Some(reservation)
+    }
+
+    /// Describe an image already in memory, e.g. one uploaded to `9ladies serve`. `file`
+    /// names it in the record.
+    pub fn describe_data(&self, file: &str, image_data: &[u8]) -> Result<Described, ItemError> {
+        self.describe_with(&self.backend, file, image_data, self.config.clone())
+    }
+
+    /// Like `describe`, for a file at `path` that has already been read into `image_data`:
+    /// the input manifest and the file's sidecar still apply.
+    pub fn describe_file_data(&self, path: &str, image_data: &[u8]) -> Result<Described, ItemError> {
+        self.verify(path, image_data)?;
+        self.describe_file_with(&self.backend, path, image_data)
+    }
+
+    /// Like `describe_data`, asking `prompt` instead of the prompt config's question. The
+    /// system prompt, temperature, language and transform still apply.
+    pub fn ask(&self, file: &str, image_data: &[u8], prompt: &str) -> Result<Described, ItemError> {
+        let config = PromptConfig {
+            prompt: prompt.to_string(),
+            ..self.config.clone()
+        };
+        self.describe_with(&self.backend, file, image_data, config)
+    }
+
+    fn describe_with(
+        &self,
+        backend: &Backend,
+        file: &str,
+        image_data: &[u8],
+        config: PromptConfig,
+    ) -> Result<Described, ItemError> {
+        if detect_image_format(image_data).is_none() {
+            return Err(ItemError::Invalid(format!(
+                "Not a valid image format (expected JPEG, PNG, WebP, or GIF): {}",
+                file
+            )));
+        }
+        let resize = match &self.size_limit {
+            Some(limit) => limit.check(file, image_data).map_err(ItemError::Invalid)?,
+            None => false,
+        };
+        let nsfw = match &self.nsfw {
+            Some(filter) => Some(filter.check(image_data).map_err(ItemError::Local)?),
+            None => None,
+        };
+        let mut routed_backend = None;
+        let mut config = config;
+        if let Some(verdict) = &nsfw {
+            debug!(file, score = verdict.score, outcome = ?verdict.outcome, "NSFW check finished");
+            match (verdict.outcome, self.nsfw.as_ref().map(NsfwFilter::action)) {
+                (NsfwOutcome::Skipped, _) => {
+                    let mut record = OutputRecord::new(file, &sha256_hex(image_data), serde_json::Value::Null);
+                    record.nsfw = nsfw;
+                    return Ok(Described {
+                        record,
+                        usage: None,
+                        elapsed: Duration::ZERO,
+                    });
+                }
+                (NsfwOutcome::Routed, Some(NsfwAction::Route { config: route_config, model, url })) => {
+                    if let Some(route_config) = route_config {
+                        config = PromptConfig::clone(route_config);
+                        if let Some(language) = &self.language {
+                            add_language_instruction(&mut config, language);
+                        }
+                    }
+                    if model.is_some() || url.is_some() {
+                        routed_backend = Some(backend.at(
+                            url.as_deref().unwrap_or(backend.url()),
+                            model.clone().or_else(|| backend.model().map(str::to_string)),
+                        ));
+                    }
+                }
+                _ => {}
+            }
+        }
+        let backend = routed_backend.as_ref().unwrap_or(backend);
+
+        let config = match &self.transform {
+            Some(transform) => transform.transform_prompt(file, config).map_err(ItemError::Transform)?,
+            None => config,
+        };
+        let (mut config, ocr_text) = match &self.ocr {
+            Some(ocr) => {
+                let text = ocr.recognize(image_data).map_err(ItemError::Local)?;
+                debug!(file, chars = text.len(), "OCR finished");
+                let config = PromptConfig {
+                    prompt: ocr::prompt_with_text(&config.prompt, &text),
+                    ..config
+                };
+                (config, Some(text))
+            }
+            None => (config, None),
+        };
+
+        let image_info = match self.image_info {
+            true => ImageInfo::read(image_data).map_err(|e| warn!(file, "{}", e)).ok(),
+            false => None,
+        };
+        let image_stats = match self.image_stats {
+            Some(mode) => match ImageStats::measure(image_data) {
+                Ok(stats) => {
+                    if mode == ContextMode::Prompt {
+                        config.prompt = format!("{}\n\n{}", config.prompt, stats.prompt_context());
+                    }
+                    Some(stats)
+                }
+                Err(e) => {
+                    warn!(file, "{}", e);
+                    None
+                }
+            },
+            None => None,
+        };
+
+        let exif = self.exif.and_then(|mode| {
+            let exif = ExifData::read(image_data)?;
+            if mode == ContextMode::Prompt {
+                config.prompt = format!("{}\n\n{}", config.prompt, exif.prompt_context());
+            }
+            Some(exif)
+        });
+
+        if let Some((labels, top_k)) = &self.labels {
+            config = labels.constrain(config, *top_k);
+        }
+
+        #[cfg(feature = "faces")]
+        let (model_image, faces_blurred) = match &self.face_blur {
+            Some(face_blur) => {
+                let (blurred, faces) = face_blur.blur(image_data).map_err(ItemError::Local)?;
+                debug!(file, faces, "Face detection finished");
+                (std::borrow::Cow::Owned(blurred), Some(faces))
+            }
+            None => (std::borrow::Cow::Borrowed(image_data), None),
+        };
+        #[cfg(not(feature = "faces"))]
+        let (model_image, faces_blurred) = (std::borrow::Cow::Borrowed(image_data), None);
+        let (model_image, resized) = match (resize, &self.size_limit) {
+            (true, Some(limit)) => {
+                let smaller = limit.downscale(&model_image).map_err(ItemError::Local)?;
+                let info = ImageInfo::read(&smaller).map_err(ItemError::Local)?;
+                debug!(file, width = info.width, height = info.height, bytes = info.bytes, "Image resized");
+                (std::borrow::Cow::Owned(smaller), Some(info))
+            }
+            _ => (model_image, None),
+        };
+
+        let started = Instant::now();
+        let result = match &self.embedding {
+            Some((model, EmbedSource::Image)) => backend.embed(model, EmbedInput::Image(&model_image)).map(|vector| {
+                let reply = ChatReply {
+                    content: String::new(),
+                    usage: None,
+                    timing: ServerTiming::default(),
+                    truncated: false,
+                    thinking: None,
+                    logprobs: None,
+                    alternatives: Vec::new(),
+                };
+                (reply, Some(vector))
+            }),
+            _ => self.call_in_language(backend, &config, &[&model_image]).map(|reply| (reply, None)),
+        };
+        let elapsed = started.elapsed();
+        debug!(
+            file,
+            elapsed_ms = elapsed.as_millis() as u64,
+            ok = result.is_ok(),
+            "Model request finished"
+        );
+        let (mut reply, image_embedding) = result.map_err(ItemError::Call)?;
+        let confidence = match self.confidence {
+            Some((method, samples)) if image_embedding.is_none() => {
+                self.score_confidence(backend, &config, &model_image, &mut reply, method, samples)
+                    .map_err(ItemError::Call)?
+            }
+            _ => None,
+        };
+        let alternatives = match self.candidates {
+            count if count > 1 && image_embedding.is_none() => {
+                let (answers, usage) = backend
+                    .describe_alternatives(&config, &[&model_image], count - 1)
+                    .map_err(ItemError::Call)?;
+                if let (Some(total), Some(extra)) = (reply.usage.as_mut(), usage) {
+                    *total += extra;
+                }
+                answers
+            }
+            _ => Vec::new(),
+        };
+        let truncated = reply.truncated;
+        let (response, json_extracted) = match image_embedding {
+            Some(_) => (serde_json::Value::Null, false),
+            None => self.response(reply.content),
+        };
+        let (response, top_labels) = match &self.labels {
+            Some((labels, top_k)) if image_embedding.is_none() => {
+                let classified = labels
+                    .classify(&response, *top_k)
+                    .map_err(|e| ItemError::Call(CallError::Failed(e)))?;
+                (serde_json::Value::String(classified.label), classified.top)
+            }
+            _ => (response, None),
+        };
+
+        let mut record = OutputRecord::new(file, &sha256_hex(image_data), response);
+        record.embedding = image_embedding;
+        record.top_labels = top_labels;
+        if !alternatives.is_empty() {
+            let others = alternatives.into_iter().map(|answer| self.response(answer).0);
+            record.candidates = Some(std::iter::once(record.response.clone()).chain(others).collect());
+        }
+        record.confidence = confidence;
+        record.truncated = truncated.then_some(true);
+        record.json_extracted = json_extracted.then_some(true);
+        record.thinking = reply.thinking;
+        record.language = self.detected_language(&record.response);
+        record.ocr_text = ocr_text;
+        record.nsfw = nsfw;
+        record.faces_blurred = faces_blurred;
+        record.resized = resized;
+        record.image = image_info;
+        record.image_stats = image_stats;
+        record.exif = exif;
+        if self.detections {
+            // Boxes are fractions of the image the model saw, which may be a smaller copy
+            record.detections = match grounding::image_dimensions(&model_image) {
+                Ok((width, height)) => Some(grounding::parse_detections(&record.response, width, height)),
+                Err(e) => {
+                    warn!(file, "{}", e);
+                    None
+                }
+            };
+        }
+        record.timing = Some(Timing {
+            wall_ms: elapsed.as_millis() as u64,
+            server: reply.timing,
+        });
+        #[cfg(feature = "barcode")]
+        if self.barcodes {
+            record.barcodes = match crate::barcode::decode_barcodes(image_data) {
+                Ok(barcodes) => Some(barcodes),
+                Err(e) => {
+                    warn!(file, "{}", e);
+                    None
+                }
+            };
+        }
+        if let Some(transform) = &self.transform {
+            transform.transform_response(&mut record).map_err(ItemError::Transform)?;
+        }
+        // Captions are embedded last, so they match any changes the transform made
+        if let Some((model, EmbedSource::Caption)) = &self.embedding {
+            let text = embedding_text(&record.response);
+            record.embedding = Some(backend.embed(model, EmbedInput::Text(&text)).map_err(ItemError::Call)?);
+        }
+        let mut usage = reply.usage;
+        if !self.translations.is_empty() && !record.response.is_null() {
+            let response = std::mem::take(&mut record.response);
+            let mut translations = serde_json::Map::new();
+            for language in &self.translations {
+                let translation = PromptConfig {
+                    system: "You are a translator.".to_string(),
+                    prompt: language.translation_prompt(&embedding_text(&response)),
+                    schema: None,
+                    seed: None,
+                    ..config.clone()
+                };
+                let reply = backend.describe_images(&translation, &[]).map_err(ItemError::Call)?;
+                if let (Some(total), Some(extra)) = (usage.as_mut(), reply.usage) {
+                    *total += extra;
+                }
+                translations.insert(language.key(), parse_model_content(reply.content));
+            }
+            record.response = language::add_translations(response, translations);
+        }
+        Ok(Described { record, usage, elapsed })
+    }
+
+    /// Ask the prompt about two images in one request, e.g. before and after photos. The
+    /// record is for `first`, with `second` as its `compared_with`. The language and
+    /// transform apply; the per-image extras (OCR, statistics, labels and so on) don't.
+    pub fn compare(&self, first: &str, second: &str) -> Result<Described, ItemError> {
+        let first_data = validate_image_file(Path::new(first)).map_err(ItemError::Invalid)?;
+        let second_data = validate_image_file(Path::new(second)).map_err(ItemError::Invalid)?;
+        let config = match &self.transform {
+            Some(transform) => transform.transform_prompt(first, self.config.clone()).map_err(ItemError::Transform)?,
+            None => self.config.clone(),
+        };
+
+        let started = Instant::now();
+        let result = self.call_in_language(&self.backend, &config, &[&first_data, &second_data]);
+        let elapsed = started.elapsed();
+        debug!(first, second, elapsed_ms = elapsed.as_millis() as u64, ok = result.is_ok(), "Model request finished");
+        let reply = result.map_err(ItemError::Call)?;
+
+        let (response, json_extracted) = self.response(reply.content);
+        let mut record = OutputRecord::new(first, &sha256_hex(&first_data), response);
+        record.json_extracted = json_extracted.then_some(true);
+        record.thinking = reply.thinking;
+        record.language = self.detected_language(&record.response);
+        record.compared_with = Some(second.to_string());
+        record.timing = Some(Timing {
+            wall_ms: elapsed.as_millis() as u64,
+            server: reply.timing,
+        });
+        if let Some(transform) = &self.transform {
+            transform.transform_response(&mut record).map_err(ItemError::Transform)?;
+        }
+        Ok(Described {
+            record,
+            usage: reply.usage,
+            elapsed,
+        })
+    }
+
+    /// Describe a product from all of its shots, sent together or, with `per_request`, that
+    /// many at a time with each request given the answer so far. The record is for the first
+    /// shot, with the SKU and every shot listed. The language and transform apply; the
+    /// per-image extras don't.
+    pub fn describe_product(&self, product: &Product, per_request: Option<usize>) -> Result<Described, ItemError> {
+        let Some(first) = product.files.first() else {
+            return Err(ItemError::Invalid(format!("Product '{}' has no shots", product.sku)));
+        };
+        let shots = product
+            .files
+            .iter()
+            .map(|file| validate_image_file(Path::new(file)))
+            .collect::<Result<Vec<Vec<u8>>, String>>()
+            .map_err(ItemError::Invalid)?;
+        let config = match &self.transform {
+            Some(transform) => transform.transform_prompt(first, self.config.clone()).map_err(ItemError::Transform)?,
+            None => self.config.clone(),
+        };
+
+        let mut elapsed = Duration::ZERO;
+        let mut usage: Option<TokenUsage> = None;
+        let mut answer: Option<ChatReply> = None;
+        for chunk in shots.chunks(per_request.unwrap_or(shots.len()).max(1)) {
+            let images: Vec<&[u8]> = chunk.iter().map(Vec::as_slice).collect();
+            let config = match &answer {
+                None => products::product_config(&config, images.len()),
+                Some(previous) => products::continuation_config(&config, images.len(), previous.content.trim()),
+            };
+            let started = Instant::now();
+            let result = self.call_in_language(&self.backend, &config, &images);
+            elapsed += started.elapsed();
+            debug!(sku = product.sku, shots = images.len(), ok = result.is_ok(), "Model request finished");
+            let reply = result.map_err(ItemError::Call)?;
+            usage = match (usage, reply.usage) {
+                (Some(mut total), Some(extra)) => {
+                    total += extra;
+                    Some(total)
+                }
+                (total, extra) => total.or(extra),
+            };
+            answer = Some(reply);
+        }
+        let reply = answer.expect("a product has at least one shot");
+
+        let (response, json_extracted) = self.response(reply.content);
+        let mut record = OutputRecord::new(first, &sha256_hex(&shots[0]), response);
+        record.json_extracted = json_extracted.then_some(true);
+        record.thinking = reply.thinking;
+        record.language = self.detected_language(&record.response);
+        record.sku = Some(product.sku.clone());
+        record.shots = Some(product.files.clone());
+        record.timing = Some(Timing {
+            wall_ms: elapsed.as_millis() as u64,
+            server: reply.timing,
+        });
+        if let Some(transform) = &self.transform {
+            transform.transform_response(&mut record).map_err(ItemError::Transform)?;
+        }
+        Ok(Described { record, usage, elapsed })
+    }
+
+    /// Ask the prompt config's question about the image(s) of an earlier record, given the
+    /// answer it got then, and return the record with its response enriched by the new
+    /// answer. The image is read from where the record says it is now, along with the
+    /// other image of a comparison or every shot of a product.
+    pub fn follow_up(&self, previous: &OutputRecord) -> Result<Described, ItemError> {
+        let files: Vec<&str> = match (&previous.shots, &previous.compared_with) {
+            (Some(shots), _) => shots.iter().map(String::as_str).collect(),
+            (None, Some(other)) => vec![previous.current_path(), other],
+            (None, None) => vec![previous.current_path()],
+        };
+        let images = files
+            .iter()
+            .map(|file| validate_image_file(Path::new(file)))
+            .collect::<Result<Vec<Vec<u8>>, String>>()
+            .map_err(ItemError::Invalid)?;
+        let sha256 = sha256_hex(&images[0]);
+        if !previous.sha256.is_empty() && previous.sha256 != sha256 {
+            warn!(file = files[0], "The image has changed since the earlier answer");
+        }
+        let config = match &self.transform {
+            Some(transform) => transform.transform_prompt(files[0], self.config.clone()).map_err(ItemError::Transform)?,
+            None => self.config.clone(),
+        };
+        let config = followup::followup_config(&config, &previous.response);
+
+        let started = Instant::now();
+        let images: Vec<&[u8]> = images.iter().map(Vec::as_slice).collect();
+        let result = self.call_in_language(&self.backend, &config, &images);
+        let elapsed = started.elapsed();
+        debug!(file = files[0], elapsed_ms = elapsed.as_millis() as u64, ok = result.is_ok(), "Model request finished");
+        let reply = result.map_err(ItemError::Call)?;
+
+        let mut record = previous.clone();
+        record.sha256 = sha256;
+        let (answer, json_extracted) = self.response(reply.content);
+        record.response = followup::enrich(&previous.response, answer);
+        record.json_extracted = json_extracted.then_some(true);
+        record.thinking = reply.thinking;
+        record.language = self.detected_language(&record.response);
+        // These were about the earlier answer
+        record.confidence = None;
+        record.review = None;
+        record.timing = Some(Timing {
+            wall_ms: elapsed.as_millis() as u64,
+            server: reply.timing,
+        });
+        if let Some(transform) = &self.transform {
+            transform.transform_response(&mut record).map_err(ItemError::Transform)?;
+        }
+        Ok(Described {
+            record,
+            usage: reply.usage,
+            elapsed,
+        })
+    }
+
+    /// The response in a reply's text, with the fields mapped, and whether it was extracted
+    /// from other text (see `extract_model_content`).
+    fn response(&self, content: String) -> (serde_json::Value, bool) {
+        let (response, extracted) = extract_model_content(content);
+        (self.field_map.apply(response), extracted)
+    }
+
+    /// The language `response` is in, to record with `--language`.
+    fn detected_language(&self, response: &serde_json::Value) -> Option<String> {
+        self.language.as_ref()?;
+        language::detect(&language::response_text(response)).map(|detected| detected.key())
+    }
+
+    /// Ask the model, once more with a reminder if the reply is not in the wanted language.
+    fn call_in_language(&self, backend: &Backend, config: &PromptConfig, images: &[&[u8]]) -> Result<ChatReply, CallError> {
+        let reply = backend.describe_images(config, images)?;
+        let Some(language) = &self.language else {
+            return Ok(reply);
+        };
+        let in_language =
+            |reply: &ChatReply| language.matches(&language::response_text(&parse_model_content(reply.content.clone())));
+        if in_language(&reply) {
+            return Ok(reply);
+        }
+
+        let detected = language::detect(&language::response_text(&parse_model_content(reply.content.clone())));
+        debug!(
+            detected = detected.as_ref().map(|detected| detected.name.as_str()),
+            "Reply is not in {}, asking again",
+            language.name
+        );
+        let reminded = PromptConfig {
+            prompt: format!("{}\n\n{}", config.prompt, language.reminder(detected.as_ref())),
+            ..config.clone()
+        };
+        let mut retry = backend.describe_images(&reminded, images)?;
+        if let (Some(first), Some(second)) = (reply.usage, retry.usage.as_mut()) {
+            *second += first;
+        }
+        if !in_language(&retry) {
+            warn!("Reply is still not in {} after asking again; keeping it", language.name);
+        }
+        Ok(retry)
+    }
+
+    /// Score `reply` with `method`, adding the tokens the extra requests used to its usage.
+    /// A self-rating that can't be read leaves the response unscored rather than failing it.
+    fn score_confidence(
+        &self,
+        backend: &Backend,
+        config: &PromptConfig,
+        image_data: &[u8],
+        reply: &mut ChatReply,
+        method: ConfidenceMethod,
+        samples: usize,
+    ) -> Result<Option<Confidence>, CallError> {
+        let mut add_usage = |extra: Option<TokenUsage>| {
+            if let (Some(total), Some(extra)) = (reply.usage.as_mut(), extra) {
+                *total += extra;
+            }
+        };
+        match method {
+            ConfidenceMethod::Agreement => {
+                let sampling = PromptConfig {
+                    temperature: config.temperature.max(confidence::SAMPLE_TEMPERATURE),
+                    // A fixed seed would give the same answer every time
+                    seed: None,
+                    ..config.clone()
+                };
+                let mut others = Vec::new();
+                for _ in 1..samples {
+                    let sample = backend.describe(&sampling, image_data)?;
+                    add_usage(sample.usage);
+                    others.push(parse_model_content(sample.content));
+                }
+                let first = parse_model_content(reply.content.clone());
+                Ok(Some(Confidence::new(confidence::agreement(&first, &others), method)))
+            }
+            ConfidenceMethod::SelfRating => {
+                let turns = [
+                    ChatTurn::new("system", &config.system),
+                    ChatTurn::new("user", &config.prompt),
+                    ChatTurn::new("assistant", &reply.content),
+                    ChatTurn::new("user", confidence::RATING_PROMPT),
+                ];
+                let rating = backend.chat(&turns, Some(config.temperature), image_data)?;
+                add_usage(rating.usage);
+                match confidence::parse_rating(&rating.content) {
+                    Ok(score) => Ok(Some(Confidence::new(score, method))),
+                    Err(e) => {
+                        warn!("Could not read the model's confidence rating: {}", e);
+                        Ok(None)
+                    }
+                }
+            }
+            ConfidenceMethod::Logprobs => {
+                let confidence = reply.logprobs.as_deref().and_then(confidence::from_logprobs);
+                if confidence.is_none() {
+                    warn!("The server sent no token probabilities to score the reply by");
+                }
+                Ok(confidence)
+            }
+        }
+    }
+
+    /// Describe each image in turn, handing results to `hooks`, until the list ends, the
+    /// hooks ask to stop or the error limit is reached. With `with_endpoints`, images are
+    /// described on all the endpoints at once.
+    pub fn run(&self, paths: &[String], hooks: &mut dyn BatchHooks) -> BatchSummary {
+        let started = Instant::now();
+        let mut summary = BatchSummary {
+            total: paths.len(),
+            ..BatchSummary::default()
+        };
+        if let Some(downloader) = &self.downloader {
+            downloader.prefetch(paths);
+        }
+
+        if self.endpoints.is_empty() {
+            for path in paths {
+                if hooks.stop_requested() || self.error_limit_reached(&summary) || !self.wait_for_window(hooks) {
+                    break;
+                }
+                summary.processed += 1;
+                hooks.started(path);
+                let result = self.describe(path);
+                self.tally(&mut summary, hooks, path, result);
+            }
+        } else {
+            self.run_on_endpoints(paths, hooks, &mut summary);
+        }
+
+        summary.elapsed = started.elapsed();
+        summary
+    }
+
+    /// Describe images as they arrive, e.g. from `--listen-input`, until `inputs` ends, the
+    /// hooks ask to stop or the error limit is reached. The total counts the images seen, and
+    /// they are described one at a time whatever the endpoints.
+    pub fn run_inputs(&self, inputs: impl IntoIterator<Item = Input>, hooks: &mut dyn BatchHooks) -> BatchSummary {
+        let started = Instant::now();
+        let mut summary = BatchSummary::default();
+        for input in inputs {
+            if hooks.stop_requested() || self.error_limit_reached(&summary) || !self.wait_for_window(hooks) {
+                break;
+            }
+            summary.total += 1;
+            summary.processed += 1;
+            hooks.started(&input.file);
+            let result = match &input.data {
+                Some(data) => self.describe_data(&input.file, data),
+                None => self.describe(&input.file),
+            };
+            self.tally(&mut summary, hooks, &input.file, result);
+        }
+        summary.elapsed = started.elapsed();
+        summary
+    }
+
+    fn error_limit_reached(&self, summary: &BatchSummary) -> bool {
+        self.error_limit.is_some_and(|limit| summary.failed >= limit)
+    }
+
+    /// Wait for the run window to open, if it is closed; false if the hooks ask to stop
+    /// meanwhile.
+    fn wait_for_window(&self, hooks: &mut dyn BatchHooks) -> bool {
+        let Some(window) = self.run_window.filter(|window| !window.is_open()) else {
+            return true;
+        };
+        info!("Outside the run window {}: pausing until {}", window, window.opens());
+        hooks.paused(&window.opens());
+        while !window.is_open() {
+            if hooks.stop_requested() {
+                return false;
+            }
+            thread::sleep(WINDOW_POLL_INTERVAL);
+        }
+        info!("The run window {} is open: resuming", window);
+        hooks.resumed();
+        true
+    }
+
+    /// One worker thread per request each endpoint takes at once, all taking the next image
+    /// from a shared list. Results come back here, where the hooks run.
+    fn run_on_endpoints(&self, paths: &[String], hooks: &mut dyn BatchHooks, summary: &mut BatchSummary) {
+        let next = AtomicUsize::new(0);
+        let stop = AtomicBool::new(false);
+        // Set while the run window is closed, so no worker starts another image
+        let hold = AtomicBool::new(false);
+        // Workers between claiming an image and having it described. Each counts itself in
+        // before it looks at `hold` again, so once `hold` is set and this is zero nothing
+        // more is sent until the window opens
+        let busy = AtomicUsize::new(0);
+        let (sender, receiver) = mpsc::channel();
+        thread::scope(|scope| {
+            for (backend, concurrency) in &self.endpoints {
+                for _ in 0..*concurrency {
+                    let (sender, next, stop, hold, busy) = (sender.clone(), &next, &stop, &hold, &busy);
+                    scope.spawn(move || {
+                        while !stop.load(Ordering::Relaxed) {
+                            if hold.load(Ordering::SeqCst) {
+                                thread::sleep(Duration::from_millis(100));
+                                continue;
+                            }
+                            busy.fetch_add(1, Ordering::SeqCst);
+                            if hold.load(Ordering::SeqCst) {
+                                busy.fetch_sub(1, Ordering::SeqCst);
+                                continue;
+                            }
+                            let Some(path) = paths.get(next.fetch_add(1, Ordering::Relaxed)) else {
+                                busy.fetch_sub(1, Ordering::SeqCst);
+                                break;
+                            };
+                            if sender.send(Progress::Started(path)).is_err() {
+                                busy.fetch_sub(1, Ordering::SeqCst);
+                                break;
+                            }
+                            let result = self.describe_on(backend, path);
+                            busy.fetch_sub(1, Ordering::SeqCst);
+                            if sender.send(Progress::Finished(path, Box::new(result))).is_err() {
+                                break;
+                            }
+                        }
+                    });
+                }
+            }
+            drop(sender);
+
+            loop {
+                if hooks.stop_requested() || self.error_limit_reached(summary) {
+                    stop.store(true, Ordering::Relaxed);
+                }
+                if self.run_window.is_some_and(|window| !window.is_open()) {
+                    hold.store(true, Ordering::SeqCst);
+                }
+                // Pause once the images already sent are done
+                if hold.load(Ordering::SeqCst) && busy.load(Ordering::SeqCst) == 0 {
+                    if !self.wait_for_window(hooks) {
+                        stop.store(true, Ordering::Relaxed);
+                    }
+                    hold.store(false, Ordering::SeqCst);
+                }
+                match receiver.recv_timeout(Duration::from_millis(100)) {
+                    Ok(Progress::Started(path)) => {
+                        summary.processed += 1;
+                        hooks.started(path);
+                    }
+                    Ok(Progress::Finished(path, result)) => self.tally(summary, hooks, path, *result),
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+            }
+        });
+    }
+
+    /// Count one image's outcome and hand it to the hooks.
+    fn tally(
+        &self,
+        summary: &mut BatchSummary,
+        hooks: &mut dyn BatchHooks,
+        path: &str,
+        result: Result<Described, ItemError>,
+    ) {
+        let error = match result {
+            Ok(mut described) if described.skipped() => {
+                let taken = hooks.described(&mut described);
+                if taken.is_ok() {
+                    summary.succeeded += 1;
+                    summary.nsfw_skipped += 1;
+                }
+                taken.err()
+            }
+            Ok(mut described) => {
+                summary.server_reached = true;
+                match described.usage {
+                    Some(usage) => summary.usage += usage,
+                    None => summary.replies_without_usage += 1,
+                }
+                if let Some(timing) = &described.record.timing {
+                    summary.server_time.add(&timing.server);
+                }
+                if let Some(median) = summary.latency.record(described.elapsed) {
+                    let secs = described.elapsed.as_secs_f64();
+                    warn!(
+                        "Slow response for '{}': {:.1}s, over {:.0}x the recent median of {:.1}s",
+                        path,
+                        secs,
+                        secs / median.as_secs_f64(),
+                        median.as_secs_f64()
+                    );
+                }
+                let taken = hooks.described(&mut described);
+                if taken.is_ok() {
+                    summary.succeeded += 1;
+                }
+                taken.err()
+            }
+            Err(ItemError::Transform(e)) => {
+                error!(code = "transform_failed", path, "Error processing '{}': {}", path, e);
+                Some(e)
+            }
+            Err(ItemError::Local(e)) => {
+                error!(code = "local_check_failed", path, "Error processing '{}': {}", path, e);
+                Some(e)
+            }
+            Err(ItemError::Invalid(e)) => {
+                error!(code = "invalid_image", path, "{}", e);
+                Some(e)
+            }
+            Err(ItemError::Download(e)) => {
+                error!(code = "download_failed", path, "{}", e);
+                Some(e)
+            }
+            Err(ItemError::Unverified(e)) => {
+                error!(code = "manifest_mismatch", path, "{}", e);
+                Some(e)
+            }
+            Err(ItemError::Call(e)) => {
+                let code = match e {
+                    CallError::Unreachable(_) => "unreachable",
+                    CallError::Failed(_) => "request_failed",
+                };
+                error!(code, path, "Error processing '{}': {}", path, e);
+                if matches!(e, CallError::Unreachable(_)) {
+                    summary.connect_failures += 1;
+                }
+                Some(e.to_string())
+            }
+        };
+
+        if error.is_some() {
+            summary.failed += 1;
+        }
+        hooks.finished(path, error.as_deref());
+    }
+}
+
+/// What a worker in `run_on_endpoints` reports.
+enum Progress<'a> {
+    Started(&'a str),
+    Finished(&'a str, Box<Result<Described, ItemError>>),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::Api;
+
+    #[derive(Default)]
+    struct Recorder {
+        stop_after: Option<usize>,
+        finished: Vec<(String, bool)>,
+        /// Set by a closed run window, which also stops the run rather than wait for it
+        paused: bool,
+        /// Fail every described image, as a record that can't be written would
+        reject: bool,
+    }
+
+    impl BatchHooks for Recorder {
+        fn stop_requested(&self) -> bool {
+            self.paused || self.stop_after.is_some_and(|n| self.finished.len() >= n)
+        }
+
+        fn paused(&mut self, _opens: &str) {
+            self.paused = true;
+        }
+
+        fn described(&mut self, _described: &mut Described) -> Result<(), String> {
+            match self.reject {
+                true => Err("disk full".to_string()),
+                false => Ok(()),
+            }
+        }
+
+        fn finished(&mut self, file: &str, error: Option<&str>) {
+            self.finished.push((file.to_string(), error.is_some()));
+        }
+    }
+
+    fn runner() -> BatchRunner {
+        let config = PromptConfig {
+            system: String::new(),
+            prompt: "Describe this.".to_string(),
+            temperature: 0.0,
+            model: None,
+            schema: None,
+            seed: None,
+            think: None,
+            schema_via: None,
+        };
+        // Nothing listens on port 9: only invalid files are used, so no request is sent
+        BatchRunner::new(Backend::new(Api::Ollama, "http://127.0.0.1:9", None, Some(1)), config)
+    }
+
+    fn paths(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    #[test]
+    fn test_run_counts_invalid_files() {
+        let mut hooks = Recorder::default();
+        let summary = runner().run(&paths(&["missing-a.jpg", "missing-b.jpg"]), &mut hooks);
+
+        assert_eq!((summary.total, summary.processed, summary.failed, summary.succeeded), (2, 2, 2, 0));
+        assert!(!summary.unreachable());
+        assert_eq!(hooks.finished, vec![("missing-a.jpg".to_string(), true), ("missing-b.jpg".to_string(), true)]);
+    }
+
+    #[test]
+    fn test_run_stops_early() {
+        let files = paths(&["a.jpg", "b.jpg", "c.jpg"]);
+
+        let summary = runner().with_error_limit(Some(2)).run(&files, &mut Recorder::default());
+        assert_eq!((summary.processed, summary.failed), (2, 2));
+
+        let mut hooks = Recorder {
+            stop_after: Some(1),
+            ..Recorder::default()
+        };
+        assert_eq!(runner().run(&files, &mut hooks).processed, 1);
+    }
+
+    #[test]
+    fn test_run_inputs_counts_as_they_arrive() {
+        let inputs = vec![
+            Input {
+                file: "missing.jpg".to_string(),
+                data: None,
+            },
+            Input {
+                file: "upload".to_string(),
+                data: Some(b"not an image".to_vec()),
+            },
+        ];
+        let mut hooks = Recorder::default();
+        let summary = runner().run_inputs(inputs, &mut hooks);
+
+        assert_eq!((summary.total, summary.processed, summary.failed), (2, 2, 2));
+        assert_eq!(hooks.finished, vec![("missing.jpg".to_string(), true), ("upload".to_string(), true)]);
+    }
+
+    #[test]
+    fn test_run_on_endpoints_describes_every_file_once() {
+        let fixture = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/red.png");
+        let endpoint = |port: u16| Backend::new(Api::Ollama, &format!("http://127.0.0.1:{}", port), None, Some(1));
+        let runner = runner().with_endpoints(vec![(endpoint(9), 2), (endpoint(10), 1), (endpoint(11), 0)]);
+        let files = paths(&[fixture, "missing.jpg", fixture, fixture]);
+        let mut hooks = Recorder::default();
+        let summary = runner.run(&files, &mut hooks);
+
+        // Neither port has a server, so the images fail as unreachable
+        assert_eq!((summary.processed, summary.failed, summary.connect_failures), (4, 4, 3));
+        let mut finished: Vec<&str> = hooks.finished.iter().map(|(file, _)| file.as_str()).collect();
+        finished.sort();
+        assert_eq!(finished, [fixture, fixture, fixture, "missing.jpg"]);
+    }
+
+    #[test]
+    fn test_closed_run_window_pauses_before_the_first_image() {
+        use chrono::Timelike;
+        let now = chrono::Local::now();
+        let hour = |offset: u32| format!("{:02}:00", (now.hour() + offset) % 24);
+        let window = RunWindow::parse(&format!("{}-{}", hour(2), hour(3))).unwrap();
+        let fixture = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/red.png").to_string();
+
+        let mut hooks = Recorder::default();
+        let summary = runner().with_run_window(Some(window)).run(&[fixture], &mut hooks);
+        assert!(hooks.paused);
+        assert_eq!(summary.processed, 0);
+        assert!(!summary.server_reached);
+    }
+
+    #[test]
+    fn test_language_instruction_joins_system_prompt() {
+        let runner = runner().with_language(Some(Language::parse("de").unwrap()));
+        assert!(runner.config().system.starts_with("Answer in German."));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_nsfw_skip_never_calls_the_model() {
+        let fixture = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/red.png").to_string();
+        let filter = NsfwFilter::new("echo 0.9", 0.5, NsfwAction::Skip);
+        let summary = runner().with_nsfw(Some(filter)).run(&[fixture], &mut Recorder::default());
+
+        // The server on port 9 would have failed the image had it been asked
+        assert_eq!((summary.succeeded, summary.failed, summary.nsfw_skipped), (1, 0, 1));
+        assert!(!summary.server_reached);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_image_not_written_counts_only_as_failed() {
+        let fixture = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/red.png").to_string();
+        let filter = NsfwFilter::new("echo 0.9", 0.5, NsfwAction::Skip);
+        let mut hooks = Recorder {
+            reject: true,
+            ..Recorder::default()
+        };
+        let summary = runner().with_nsfw(Some(filter)).run(&[fixture], &mut hooks);
+        assert_eq!((summary.processed, summary.succeeded, summary.failed, summary.nsfw_skipped), (1, 0, 1, 0));
+    }
+
+    #[test]
+    fn test_invalid_sidecar_fails_the_image() {
+        let dir = std::env::temp_dir().join(format!("nineladies_batch_sidecar_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let image = dir.join("red.png");
+        std::fs::copy(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/red.png"), &image).unwrap();
+        std::fs::write(dir.join("red.9ladies.json"), r#"{"preset": "haiku"}"#).unwrap();
+
+        match runner().describe(&image.to_string_lossy()) {
+            Err(ItemError::Invalid(e)) => assert!(e.contains("Unknown preset 'haiku'"), "{}", e),
+            _ => panic!("expected the sidecar to be refused"),
+        }
+        let data = std::fs::read(&image).unwrap();
+        match runner().describe_file_data(&image.to_string_lossy(), &data) {
+            Err(ItemError::Invalid(e)) => assert!(e.contains("Unknown preset 'haiku'"), "{}", e),
+            _ => panic!("expected the sidecar of a file read beforehand to be refused too"),
+        }
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_file_changed_since_the_manifest_fails() {
+        let fixture = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/red.png");
+        let manifest = InputManifest::parse(&format!("{}  {}\n", sha256_hex(b"another image"), fixture)).unwrap();
+        let runner = runner().with_input_manifest(Some(manifest));
+
+        match runner.describe(fixture) {
+            Err(ItemError::Unverified(e)) => assert!(e.contains("has changed"), "{}", e),
+            _ => panic!("expected the changed file to fail before it was sent"),
+        }
+    }
+
+    #[test]
+    fn test_memory_is_given_back_when_an_image_fails() {
+        let fixture = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/red.png");
+        let runner = runner().with_memory_budget(Some(MemoryBudget::new(1)));
+
+        // The image is over the whole budget, so it goes ahead on its own
+        assert!(matches!(runner.describe(fixture), Err(ItemError::Call(_))));
+        assert!(matches!(runner.describe(fixture), Err(ItemError::Call(_))));
+        assert_eq!(runner.memory.as_ref().unwrap().used(), 0);
+    }
+
+    #[test]
+    fn test_image_embedding_replaces_the_description() {
+        let fixture = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/red.png");
+        let runner = runner().with_embedding(Some("clip".to_string()), EmbedSource::Image);
+
+        // Asking the chat endpoint on port 9 would fail as unreachable instead
+        match runner.describe(fixture) {
+            Err(ItemError::Call(CallError::Failed(e))) => assert!(e.contains("does not take images"), "{}", e),
+            _ => panic!("expected Ollama to refuse an image embedding"),
+        }
+    }
+}
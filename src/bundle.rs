@@ -0,0 +1,228 @@
+//! `--repro-bundle`: everything needed to reproduce a run, in one tar archive that can be
+//! attached to a dataset release.
+//!
+//! The archive holds `manifest.json` (the 9ladies version, command line, server, model and
+//! its digest, sampling settings and counts), `prompt.json` (the prompt config as sent) and
+//! `inputs.sha256` (each input's hash, in `sha256sum` format, so `sha256sum -c` checks a copy
+//! of the inputs against it).
+
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+use serde_json::Value;
+use tracing::warn;
+
+use crate::backend::Api;
+use crate::images::sha256_file;
+use crate::prompt::PromptConfig;
+
+/// `manifest.json`: how a run was made and what it produced.
+#[derive(Debug, Serialize)]
+pub struct Manifest {
+    pub nineladies_version: &'static str,
+    /// When the bundle was written, in UTC
+    pub created: String,
+    /// The command line, as given
+    pub command: Vec<String>,
+    pub api: Api,
+    /// Each server the run sent requests to
+    pub urls: Vec<String>,
+    pub model: Option<String>,
+    /// The digest the server reported for the model, which changes when it is re-pulled
+    pub model_digest: Option<String>,
+    pub preset: Option<String>,
+    pub temperature: f32,
+    pub seed: Option<u64>,
+    pub files: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+    /// The output file and its SHA-256, if the run wrote one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<OutputFile>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OutputFile {
+    pub path: String,
+    pub sha256: String,
+}
+
+impl OutputFile {
+    /// The output file at `path`, hashed as it is now.
+    pub fn hash(path: &str) -> Result<OutputFile, String> {
+        Ok(OutputFile {
+            path: path.to_string(),
+            sha256: sha256_file(Path::new(path))?,
+        })
+    }
+}
+
+/// Write a bundle of `manifest`, `config` and the hashes of `inputs` to `path`. Inputs that
+/// can't be read, such as URLs, are left out of `inputs.sha256` with a warning.
+pub fn write_bundle(path: &Path, manifest: &Manifest, config: &PromptConfig, inputs: &[String]) -> Result<(), String> {
+    let mut hashes = String::new();
+    for input in inputs {
+        match sha256_file(Path::new(input)) {
+            Ok(hash) => hashes.push_str(&format!("{}  {}\n", hash, input)),
+            Err(e) => warn!("{}; it is left out of the bundle's input hashes", e),
+        }
+    }
+
+    // Everything goes in a folder named after the archive, as release archives usually are
+    let folder = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or("repro");
+    let mtime = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let mut archive = Vec::new();
+    let entries = [
+        ("manifest.json", serde_json::to_string_pretty(manifest).expect("manifests serialize")),
+        ("prompt.json", serde_json::to_string_pretty(config).expect("prompt configs serialize")),
+        ("inputs.sha256", hashes),
+    ];
+    for (name, content) in entries {
+        append_entry(&mut archive, &format!("{}/{}", folder, name), content.as_bytes(), mtime)?;
+    }
+    // Two empty blocks end a tar archive
+    archive.resize(archive.len() + 1024, 0);
+
+    fs::write(path, archive).map_err(|e| format!("Failed to write bundle '{}': {}", path.display(), e))
+}
+
+/// The manifest of a bundle written by `write_bundle`, or of a `manifest.json` taken out
+/// of one.
+pub fn read_manifest(path: &Path) -> Result<Value, String> {
+    let data = fs::read(path).map_err(|e| format!("Failed to read manifest '{}': {}", path.display(), e))?;
+    let json = match data.first() {
+        Some(b'{') => &data[..],
+        _ => find_entry(&data, "manifest.json")
+            .ok_or_else(|| format!("'{}' is neither a bundle with a manifest.json nor a manifest", path.display()))?,
+    };
+    serde_json::from_slice(json).map_err(|e| format!("Failed to parse manifest in '{}': {}", path.display(), e))
+}
+
+/// The data of the first entry in a tar archive whose name ends with `/name`.
+fn find_entry<'a>(archive: &'a [u8], name: &str) -> Option<&'a [u8]> {
+    let mut offset = 0;
+    while offset + 512 <= archive.len() && archive[offset] != 0 {
+        let header = &archive[offset..offset + 512];
+        let text = |range: std::ops::Range<usize>| {
+            String::from_utf8_lossy(&header[range]).trim_end_matches(['\0', ' ']).to_string()
+        };
+        let size = usize::from_str_radix(&text(124..136), 8).ok()?;
+        let start = offset + 512;
+        let entry = text(0..100);
+        if entry == name || entry.ends_with(&format!("/{}", name)) {
+            return archive.get(start..start + size);
+        }
+        offset = start + size.div_ceil(512) * 512;
+    }
+    None
+}
+
+/// Add a regular file to a ustar archive: a 512-byte header, then the data padded to a
+/// multiple of 512 bytes.
+fn append_entry(archive: &mut Vec<u8>, name: &str, data: &[u8], mtime: u64) -> Result<(), String> {
+    if name.len() > 100 {
+        return Err(format!("Bundle entry name '{}' is longer than 100 bytes", name));
+    }
+    let mut header = [0u8; 512];
+    let mut field = |offset: usize, value: &[u8]| header[offset..offset + value.len()].copy_from_slice(value);
+    field(0, name.as_bytes());
+    field(100, b"0000644\0");
+    field(108, b"0000000\0");
+    field(116, b"0000000\0");
+    field(124, format!("{:011o}\0", data.len()).as_bytes());
+    field(136, format!("{:011o}\0", mtime).as_bytes());
+    // The checksum is summed with its own field as spaces
+    field(148, b"        ");
+    field(156, b"0");
+    field(257, b"ustar\0");
+    field(263, b"00");
+    let checksum: u32 = header.iter().map(|&b| u32::from(b)).sum();
+    header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+
+    archive.extend_from_slice(&header);
+    archive.extend_from_slice(data);
+    archive.resize(archive.len().div_ceil(512) * 512, 0);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The entries of a ustar archive, as name and content.
+    fn read_entries(archive: &[u8]) -> Vec<(String, String)> {
+        let mut entries = Vec::new();
+        let mut offset = 0;
+        while archive[offset] != 0 {
+            let header = &archive[offset..offset + 512];
+            let text = |range: std::ops::Range<usize>| {
+                String::from_utf8_lossy(&header[range]).trim_end_matches(['\0', ' ']).to_string()
+            };
+            let size = usize::from_str_radix(&text(124..136), 8).unwrap();
+            let checksum = u32::from_str_radix(&text(148..154), 8).unwrap();
+            let summed: u32 = header.iter().enumerate().map(|(i, &b)| if (148..156).contains(&i) { 32 } else { u32::from(b) }).sum();
+            assert_eq!(checksum, summed);
+            assert_eq!(&header[257..263], b"ustar\0");
+
+            let data = &archive[offset + 512..offset + 512 + size];
+            entries.push((text(0..100), String::from_utf8(data.to_vec()).unwrap()));
+            offset += 512 + size.div_ceil(512) * 512;
+        }
+        assert_eq!(archive.len(), offset + 1024);
+        entries
+    }
+
+    #[test]
+    fn test_write_bundle() {
+        let dir = std::env::temp_dir().join(format!("nineladies_bundle_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("a.png");
+        fs::write(&input, b"x").unwrap();
+        let manifest = Manifest {
+            nineladies_version: env!("CARGO_PKG_VERSION"),
+            created: "2026-01-01T00:00:00.000Z".to_string(),
+            command: vec!["9ladies".to_string(), "run".to_string()],
+            api: Api::Ollama,
+            urls: vec!["http://localhost:11434".to_string()],
+            model: Some("llava".to_string()),
+            model_digest: Some("abc123".to_string()),
+            preset: None,
+            temperature: 0.0,
+            seed: Some(7),
+            files: 2,
+            succeeded: 1,
+            failed: 1,
+            output: None,
+        };
+        let config = PromptConfig {
+            system: "s".to_string(),
+            prompt: "p".to_string(),
+            temperature: 0.0,
+            model: None,
+            schema: None,
+            seed: Some(7),
+            think: None,
+            schema_via: None,
+        };
+        let inputs = [input.display().to_string(), "https://example.com/b.png".to_string()];
+
+        let path = dir.join("release-1.tar");
+        write_bundle(&path, &manifest, &config, &inputs).unwrap();
+        let entries = read_entries(&fs::read(&path).unwrap());
+        let names: Vec<&str> = entries.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["release-1/manifest.json", "release-1/prompt.json", "release-1/inputs.sha256"]);
+
+        let manifest: serde_json::Value = serde_json::from_str(&entries[0].1).unwrap();
+        assert_eq!((manifest["api"].as_str(), manifest["model_digest"].as_str()), (Some("ollama"), Some("abc123")));
+        assert!(entries[1].1.contains("\"seed\": 7"));
+        assert_eq!(read_manifest(&path).unwrap()["seed"], 7);
+        // SHA-256 of "x"; the URL can't be hashed
+        assert_eq!(
+            entries[2].1,
+            format!("2d711642b726b04401627ca9fbac32f5c8530fb1903cc4db02258717921a4881  {}\n", inputs[0])
+        );
+        fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,266 @@
+//! `--caption-report`: clusters of identical and near-identical descriptions given to
+//! different images. A model that has collapsed into one generic caption ("A close-up of an
+//! object on a table.") for much of a batch shows up as one big cluster, long before anyone
+//! reads through the output.
+//!
+//! Captions are compared as sets of lowercase words, and two are near-identical when the
+//! words they share make up at least the given share of all the distinct words in the pair
+//! (their Jaccard similarity). Pairs are found by prefix filtering: only captions that share
+//! one of their rarest words are compared, so a big run isn't compared caption by caption.
+
+use std::collections::{HashMap, HashSet};
+
+use serde::Serialize;
+use serde_json::Value;
+
+/// The share of words, in percent, near-identical captions have in common unless
+/// `--caption-similarity` says otherwise.
+pub const DEFAULT_SIMILARITY: u32 = 80;
+
+/// The clusters found in a run.
+#[derive(Debug, Serialize)]
+pub struct CaptionReport {
+    /// Captions compared
+    pub captions: usize,
+    pub similarity: u32,
+    /// Images in a cluster, across all of them
+    pub images_in_clusters: usize,
+    /// Largest first
+    pub clusters: Vec<CaptionCluster>,
+}
+
+/// Captions given to two or more different images that match or nearly match.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct CaptionCluster {
+    pub images: usize,
+    /// Whether every caption has the same words, rather than only nearly
+    pub exact: bool,
+    /// The caption given most often in the cluster
+    pub caption: String,
+    pub files: Vec<String>,
+}
+
+/// The text of a response to compare: a plain reply as it is, or the values in a JSON
+/// reply, without the field names every reply shares.
+pub fn caption_text(response: &Value) -> String {
+    fn collect(value: &Value, text: &mut Vec<String>) {
+        match value {
+            Value::String(s) => text.push(s.clone()),
+            Value::Number(n) => text.push(n.to_string()),
+            Value::Bool(b) => text.push(b.to_string()),
+            Value::Array(items) => items.iter().for_each(|item| collect(item, text)),
+            Value::Object(fields) => fields.values().for_each(|field| collect(field, text)),
+            Value::Null => {}
+        }
+    }
+    let mut text = Vec::new();
+    collect(response, &mut text);
+    text.join(" ")
+}
+
+/// Cluster `captions`, each a file and its caption text, whose words overlap by at least
+/// `similarity` percent.
+pub fn report(captions: &[(String, String)], similarity: u32) -> CaptionReport {
+    let threshold = f64::from(similarity.clamp(1, 100)) / 100.0;
+
+    // Captions with the same words are compared once
+    let mut distinct: Vec<Vec<String>> = Vec::new();
+    let mut members: Vec<Vec<usize>> = Vec::new();
+    let mut by_words: HashMap<Vec<String>, usize> = HashMap::new();
+    for (i, (_, text)) in captions.iter().enumerate() {
+        let words = words(text);
+        if words.is_empty() {
+            continue;
+        }
+        let id = *by_words.entry(words.clone()).or_insert_with(|| {
+            distinct.push(words);
+            members.push(Vec::new());
+            distinct.len() - 1
+        });
+        members[id].push(i);
+    }
+
+    // Each word as its place in one order, rarest first, which the prefixes are taken in
+    let mut frequency: HashMap<&str, usize> = HashMap::new();
+    for word in distinct.iter().flatten() {
+        *frequency.entry(word).or_default() += 1;
+    }
+    let mut order: Vec<&str> = frequency.keys().copied().collect();
+    order.sort_by(|a, b| frequency[a].cmp(&frequency[b]).then_with(|| a.cmp(b)));
+    let rank: HashMap<&str, u32> = order.iter().enumerate().map(|(i, word)| (*word, i as u32)).collect();
+    let sets: Vec<Vec<u32>> = distinct
+        .iter()
+        .map(|words| {
+            let mut set: Vec<u32> = words.iter().map(|word| rank[word.as_str()]).collect();
+            set.sort_unstable();
+            set
+        })
+        .collect();
+
+    let mut parent: Vec<usize> = (0..sets.len()).collect();
+    if threshold < 1.0 {
+        // Two sets overlapping by at least `threshold` share a word in the first
+        // `len - ceil(threshold * len) + 1` of each
+        let mut index: HashMap<u32, Vec<usize>> = HashMap::new();
+        for (x, set) in sets.iter().enumerate() {
+            let overlap = (threshold * set.len() as f64 - 1e-9).ceil() as usize;
+            let prefix = &set[..(set.len() + 1).saturating_sub(overlap).min(set.len())];
+            let candidates: HashSet<usize> = prefix.iter().filter_map(|word| index.get(word)).flatten().copied().collect();
+            for y in candidates {
+                if jaccard(set, &sets[y]) >= threshold - 1e-9 {
+                    union(&mut parent, x, y);
+                }
+            }
+            for word in prefix {
+                index.entry(*word).or_default().push(x);
+            }
+        }
+    }
+
+    let mut groups: HashMap<usize, Vec<usize>> = HashMap::new();
+    for id in 0..sets.len() {
+        let root = find(&mut parent, id);
+        groups.entry(root).or_default().push(id);
+    }
+    let mut clusters: Vec<CaptionCluster> = groups
+        .into_values()
+        .filter_map(|ids| cluster(captions, &members, &ids))
+        .collect();
+    clusters.sort_by(|a, b| b.images.cmp(&a.images).then_with(|| a.caption.cmp(&b.caption)));
+
+    CaptionReport {
+        captions: captions.len(),
+        similarity,
+        images_in_clusters: clusters.iter().map(|cluster| cluster.images).sum(),
+        clusters,
+    }
+}
+
+/// The cluster of the distinct captions `ids`, if they were given to two or more images.
+fn cluster(captions: &[(String, String)], members: &[Vec<usize>], ids: &[usize]) -> Option<CaptionCluster> {
+    let mut indices: Vec<usize> = ids.iter().flat_map(|&id| members[id].iter().copied()).collect();
+    indices.sort_unstable();
+    let mut seen = HashSet::new();
+    let files: Vec<String> = indices
+        .iter()
+        .map(|&i| &captions[i].0)
+        .filter(|file| seen.insert(file.as_str()))
+        .cloned()
+        .collect();
+    if files.len() < 2 {
+        return None;
+    }
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for &i in &indices {
+        *counts.entry(captions[i].1.trim()).or_default() += 1;
+    }
+    let caption = counts
+        .into_iter()
+        .max_by(|a, b| a.1.cmp(&b.1).then_with(|| b.0.cmp(a.0)))
+        .map(|(caption, _)| caption.to_string())
+        .unwrap_or_default();
+    Some(CaptionCluster {
+        images: files.len(),
+        exact: ids.len() == 1,
+        caption,
+        files,
+    })
+}
+
+/// The distinct lowercase words of `text`, sorted.
+fn words(text: &str) -> Vec<String> {
+    let mut words: Vec<String> = text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect();
+    words.sort_unstable();
+    words.dedup();
+    words
+}
+
+/// Shared words over all words, for two sorted sets.
+fn jaccard(a: &[u32], b: &[u32]) -> f64 {
+    let (mut i, mut j, mut shared) = (0, 0, 0);
+    while i < a.len() && j < b.len() {
+        match a[i].cmp(&b[j]) {
+            std::cmp::Ordering::Less => i += 1,
+            std::cmp::Ordering::Greater => j += 1,
+            std::cmp::Ordering::Equal => {
+                shared += 1;
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    shared as f64 / (a.len() + b.len() - shared) as f64
+}
+
+fn find(parent: &mut [usize], mut id: usize) -> usize {
+    while parent[id] != id {
+        parent[id] = parent[parent[id]];
+        id = parent[id];
+    }
+    id
+}
+
+fn union(parent: &mut [usize], a: usize, b: usize) {
+    let (a, b) = (find(parent, a), find(parent, b));
+    parent[a.max(b)] = a.min(b);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn captions(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs.iter().map(|(file, text)| (file.to_string(), text.to_string())).collect()
+    }
+
+    #[test]
+    fn test_exact_and_near_clusters() {
+        let captions = captions(&[
+            ("a.jpg", "A close-up of an object on a table."),
+            ("b.jpg", "a close up of an object on a table"),
+            ("c.jpg", "A close-up of an object on the table."),
+            ("d.jpg", "Two children fishing from a wooden jetty at dusk."),
+            ("e.jpg", "A red tractor in a ploughed field."),
+            ("f.jpg", "A red tractor in a ploughed field."),
+            ("a.jpg", "A close-up of an object on a table."),
+            ("g.jpg", ""),
+            ("h.jpg", ""),
+        ]);
+
+        let report = report(&captions, DEFAULT_SIMILARITY);
+        assert_eq!((report.captions, report.images_in_clusters), (9, 5));
+        assert_eq!(
+            report.clusters[0],
+            CaptionCluster {
+                images: 3,
+                exact: false,
+                caption: "A close-up of an object on a table.".to_string(),
+                files: vec!["a.jpg".to_string(), "b.jpg".to_string(), "c.jpg".to_string()],
+            }
+        );
+        assert_eq!(report.clusters[1].caption, "A red tractor in a ploughed field.");
+        assert!(report.clusters[1].exact);
+
+        // At 100% only captions with the same words cluster
+        let report = super::report(&captions, 100);
+        assert_eq!(report.clusters.iter().map(|cluster| cluster.images).collect::<Vec<_>>(), [2, 2]);
+    }
+
+    #[test]
+    fn test_one_image_described_twice_is_not_a_cluster() {
+        let captions = captions(&[("a.jpg", "A harbour at dawn."), ("a.jpg", "A harbour at dawn.")]);
+        assert!(report(&captions, DEFAULT_SIMILARITY).clusters.is_empty());
+    }
+
+    #[test]
+    fn test_caption_text_leaves_out_field_names() {
+        let response = json!({"description": "A barn", "tags": ["farm", "red"], "people": 0, "missing": null});
+        assert_eq!(caption_text(&response), "A barn farm red 0");
+        assert_eq!(caption_text(&json!("A barn")), "A barn");
+    }
+}
@@ -0,0 +1,179 @@
+//! Interactive conversation about a single image for `9ladies chat`.
+
+use std::io::{self, BufRead, Write};
+
+use tracing::error;
+
+use nineladies::{CallError, ChatTurn, PromptConfig};
+
+const HELP: &str = "Commands: /reset forgets the conversation, /prompt prints a prompt config \
+for the last question, /quit exits";
+
+/// What a line typed at the prompt asks for.
+#[derive(Debug, PartialEq)]
+enum Input<'a> {
+    Ask(&'a str),
+    Reset,
+    ShowPrompt,
+    Help,
+    Quit,
+    Empty,
+}
+
+fn parse_input(line: &str) -> Input<'_> {
+    match line.trim() {
+        "" => Input::Empty,
+        "/quit" | "/exit" => Input::Quit,
+        "/reset" => Input::Reset,
+        "/prompt" => Input::ShowPrompt,
+        "/help" | "?" => Input::Help,
+        question => Input::Ask(question),
+    }
+}
+
+/// The conversation so far. The image stays attached to the first question, so follow-ups
+/// are answered with it in context.
+pub struct Session {
+    system: Option<String>,
+    temperature: Option<f32>,
+    model: Option<String>,
+    turns: Vec<ChatTurn>,
+    last_question: Option<String>,
+}
+
+impl Session {
+    pub fn new(system: Option<String>, temperature: Option<f32>, model: Option<String>) -> Self {
+        let mut session = Session {
+            system,
+            temperature,
+            model,
+            turns: Vec::new(),
+            last_question: None,
+        };
+        session.reset();
+        session
+    }
+
+    fn reset(&mut self) {
+        self.turns.clear();
+        if let Some(system) = &self.system {
+            self.turns.push(ChatTurn::new("system", system));
+        }
+    }
+
+    /// Ask a question with `send`, keeping the exchange in the history only if it succeeds.
+    fn ask<F>(&mut self, question: &str, send: &mut F) -> Result<String, CallError>
+    where
+        F: FnMut(&[ChatTurn]) -> Result<String, CallError>,
+    {
+        self.turns.push(ChatTurn::new("user", question));
+        match send(&self.turns) {
+            Ok(reply) => {
+                self.turns.push(ChatTurn::new("assistant", &reply));
+                self.last_question = Some(question.to_string());
+                Ok(reply)
+            }
+            Err(e) => {
+                self.turns.pop();
+                Err(e)
+            }
+        }
+    }
+
+    /// A prompt config that asks the last question, ready to save for a batch run.
+    fn prompt_config(&self) -> Option<String> {
+        let config = PromptConfig {
+            system: self.system.clone().unwrap_or_default(),
+            prompt: self.last_question.clone()?,
+            temperature: self.temperature.unwrap_or_default(),
+            model: self.model.clone(),
+            schema: None,
+            seed: None,
+            think: None,
+            schema_via: None,
+        };
+        Some(serde_json::to_string_pretty(&config).expect("prompt config serializes"))
+    }
+}
+
+/// Read questions from `input` until EOF or `/quit`, writing replies to `out`. `interactive`
+/// shows a `> ` prompt before each question.
+pub fn repl<R, W, F>(session: &mut Session, input: R, mut out: W, interactive: bool, mut send: F) -> io::Result<()>
+where
+    R: BufRead,
+    W: Write,
+    F: FnMut(&[ChatTurn]) -> Result<String, CallError>,
+{
+    let mut lines = input.lines();
+    loop {
+        if interactive {
+            write!(out, "> ")?;
+            out.flush()?;
+        }
+        let Some(line) = lines.next().transpose()? else {
+            break;
+        };
+
+        match parse_input(&line) {
+            Input::Empty => {}
+            Input::Quit => break,
+            Input::Help => writeln!(out, "{}", HELP)?,
+            Input::Reset => session.reset(),
+            Input::ShowPrompt => match session.prompt_config() {
+                Some(config) => writeln!(out, "{}", config)?,
+                None => writeln!(out, "Ask a question first")?,
+            },
+            Input::Ask(question) => match session.ask(question, &mut send) {
+                Ok(reply) => writeln!(out, "{}\n", reply.trim_end())?,
+                Err(e) => error!("{}", e),
+            },
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_input() {
+        assert_eq!(parse_input("  What color is it? "), Input::Ask("What color is it?"));
+        assert_eq!(parse_input("/quit"), Input::Quit);
+        assert_eq!(parse_input("/reset"), Input::Reset);
+        assert_eq!(parse_input(""), Input::Empty);
+    }
+
+    #[test]
+    fn test_repl_keeps_history() {
+        let mut session = Session::new(Some("Be brief.".to_string()), Some(0.2), None);
+        let mut seen = Vec::new();
+        let mut out = Vec::new();
+
+        let input = "What is it?\nWhat color?\n/prompt\n/reset\nAgain?\n/quit\nignored\n";
+        repl(&mut session, input.as_bytes(), &mut out, false, |turns: &[ChatTurn]| {
+            seen.push(turns.iter().map(|t| t.role).collect::<Vec<_>>().join(","));
+            Ok(format!("answer {}", seen.len()))
+        })
+        .unwrap();
+
+        assert_eq!(
+            seen,
+            vec!["system,user", "system,user,assistant,user", "system,user"]
+        );
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("answer 1\n\nanswer 2\n"));
+        assert!(out.contains("\"prompt\": \"What color?\""));
+        assert!(out.contains("answer 3"));
+    }
+
+    #[test]
+    fn test_failed_question_is_not_kept() {
+        let mut session = Session::new(None, None, None);
+        let mut send = |_: &[ChatTurn]| Err(CallError::Failed("boom".to_string()));
+
+        assert!(session.ask("Hello?", &mut send).is_err());
+        assert!(session.turns.is_empty());
+        assert!(session.prompt_config().is_none());
+    }
+}
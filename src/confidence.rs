@@ -0,0 +1,353 @@
+//! `--confidence`: a score from 0 to 1 for each response, so doubtful ones can be sent for
+//! human review (`9ladies review --below`).
+//!
+//! Two ways to get one cost extra requests: asking the same question again at a higher
+//! temperature and measuring how well the answers agree, or asking the model to rate its own
+//! answer. Agreement is the more trustworthy of the two; models tend to rate themselves
+//! highly whatever they said. The third is free but needs a server that reports the
+//! probability of each token it chose (token logprobs), and scores each field of a JSON
+//! reply as well as the whole.
+
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::ops::Range;
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::backend::parse_model_content;
+
+/// Temperature the extra samples are taken at, at least, so they can disagree
+pub const SAMPLE_TEMPERATURE: f32 = 0.7;
+
+/// The follow-up question for `ConfidenceMethod::SelfRating`.
+pub const RATING_PROMPT: &str = "How confident are you that your answer above is accurate and complete? \
+Reply with only a JSON object: {\"confidence\": <0 to 10>}";
+
+/// How `--confidence` scores a response.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum ConfidenceMethod {
+    /// Ask again and measure how well the answers agree
+    Agreement,
+    /// Ask the model how sure it is of its answer
+    SelfRating,
+    /// Use the probabilities the model gave the tokens of its answer
+    Logprobs,
+}
+
+/// A response's confidence score and how it was worked out.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Confidence {
+    /// 0 (no confidence) to 1
+    pub score: f64,
+    pub method: ConfidenceMethod,
+    /// Scores for each top-level field of a JSON response, with `ConfidenceMethod::Logprobs`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fields: Option<BTreeMap<String, f64>>,
+}
+
+impl Confidence {
+    pub fn new(score: f64, method: ConfidenceMethod) -> Confidence {
+        Confidence {
+            score: round(score),
+            method,
+            fields: None,
+        }
+    }
+}
+
+fn round(score: f64) -> f64 {
+    (score.clamp(0.0, 1.0) * 100.0).round() / 100.0
+}
+
+/// A token of a reply and the log probability the model gave it.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct TokenLogprob {
+    pub token: String,
+    pub logprob: f64,
+}
+
+/// The confidence in a reply from the probabilities of its tokens: the geometric mean of
+/// their probabilities, overall and for the tokens of each top-level field's value when the
+/// reply is a JSON object. `None` for a reply without tokens.
+pub fn from_logprobs(tokens: &[TokenLogprob]) -> Option<Confidence> {
+    let mean = |logprobs: &mut dyn Iterator<Item = f64>| {
+        let (sum, count) = logprobs.fold((0.0, 0), |(sum, count), logprob| (sum + logprob, count + 1));
+        (count > 0).then(|| (sum / count as f64).exp())
+    };
+    let score = mean(&mut tokens.iter().map(|token| token.logprob))?;
+
+    // Where each token is in the reply
+    let mut offsets = Vec::with_capacity(tokens.len());
+    let mut text = String::new();
+    for token in tokens {
+        let start = text.len();
+        text.push_str(&token.token);
+        offsets.push(start..text.len());
+    }
+    let fields: BTreeMap<String, f64> = field_spans(&text)
+        .into_iter()
+        .filter_map(|(field, span)| {
+            let mut overlapping = tokens
+                .iter()
+                .zip(&offsets)
+                .filter(|(_, offset)| offset.start < span.end && offset.end > span.start)
+                .map(|(token, _)| token.logprob);
+            Some((field, round(mean(&mut overlapping)?)))
+        })
+        .collect();
+    Some(Confidence {
+        fields: (!fields.is_empty()).then_some(fields),
+        ..Confidence::new(score, ConfidenceMethod::Logprobs)
+    })
+}
+
+/// The top-level fields of the first JSON object in `text`, with where each one's value is.
+/// A field the text ends in the middle of is left out.
+fn field_spans(text: &str) -> Vec<(String, Range<usize>)> {
+    let Some(start) = text.find('{') else {
+        return Vec::new();
+    };
+    let mut spans = Vec::new();
+    let mut depth = 0;
+    let (mut in_string, mut escaped) = (false, false);
+    let mut string_start = 0;
+    let mut expecting_key = false;
+    let mut key: Option<String> = None;
+    let mut value_start = None;
+    let mut close_value = |key: &mut Option<String>, value_start: &mut Option<usize>, end: usize| {
+        if let (Some(key), Some(value_start)) = (key.take(), value_start.take()) {
+            let value = &text[value_start..end];
+            let start = value_start + (value.len() - value.trim_start().len());
+            spans.push((key, start..value_start + value.trim_end().len()));
+        }
+    };
+    for (i, c) in text.char_indices().skip_while(|&(i, _)| i < start) {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => {
+                    in_string = false;
+                    if depth == 1 && expecting_key {
+                        key = serde_json::from_str(&text[string_start..=i]).ok();
+                        expecting_key = false;
+                    }
+                }
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' => {
+                in_string = true;
+                string_start = i;
+            }
+            '{' | '[' => {
+                depth += 1;
+                expecting_key = depth == 1;
+            }
+            '}' | ']' => {
+                depth -= 1;
+                if depth == 0 {
+                    close_value(&mut key, &mut value_start, i);
+                    break;
+                }
+            }
+            ',' if depth == 1 => {
+                close_value(&mut key, &mut value_start, i);
+                expecting_key = true;
+            }
+            ':' if depth == 1 => value_start = Some(i + 1),
+            _ => {}
+        }
+    }
+    spans
+}
+
+/// How well `first` agrees with each of the other samples on average, 0 to 1.
+pub fn agreement(first: &Value, others: &[Value]) -> f64 {
+    if others.is_empty() {
+        return 1.0;
+    }
+    others.iter().map(|other| similarity(first, other)).sum::<f64>() / others.len() as f64
+}
+
+/// Similarity of two responses, 0 to 1. Objects are compared field by field, lists of
+/// strings as sets, text by the words it shares and numbers by how close they are.
+pub fn similarity(a: &Value, b: &Value) -> f64 {
+    match (a, b) {
+        (Value::Object(a), Value::Object(b)) => {
+            let keys: BTreeSet<&String> = a.keys().chain(b.keys()).collect();
+            if keys.is_empty() {
+                return 1.0;
+            }
+            let total: f64 = keys
+                .iter()
+                .map(|&key| match (a.get(key), b.get(key)) {
+                    (Some(a), Some(b)) => similarity(a, b),
+                    _ => 0.0,
+                })
+                .sum();
+            total / keys.len() as f64
+        }
+        (Value::Array(a), Value::Array(b)) if a.iter().chain(b).all(Value::is_string) => {
+            let set = |items: &[Value]| -> HashSet<String> {
+                items.iter().filter_map(Value::as_str).map(|s| s.trim().to_lowercase()).collect()
+            };
+            jaccard(&set(a), &set(b))
+        }
+        (Value::Array(a), Value::Array(b)) => {
+            let longest = a.len().max(b.len());
+            if longest == 0 {
+                return 1.0;
+            }
+            a.iter().zip(b).map(|(a, b)| similarity(a, b)).sum::<f64>() / longest as f64
+        }
+        (Value::String(a), Value::String(b)) => jaccard(&words(a), &words(b)),
+        (Value::Number(a), Value::Number(b)) => {
+            let (a, b) = (a.as_f64().unwrap_or_default(), b.as_f64().unwrap_or_default());
+            let largest = a.abs().max(b.abs());
+            if largest == 0.0 {
+                1.0
+            } else {
+                (1.0 - (a - b).abs() / largest).max(0.0)
+            }
+        }
+        (a, b) => {
+            if a == b {
+                1.0
+            } else {
+                0.0
+            }
+        }
+    }
+}
+
+fn words(text: &str) -> HashSet<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+fn jaccard(a: &HashSet<String>, b: &HashSet<String>) -> f64 {
+    let union = a.union(b).count();
+    if union == 0 {
+        return 1.0;
+    }
+    a.intersection(b).count() as f64 / union as f64
+}
+
+/// The score from a reply to `RATING_PROMPT`, scaled to 0 to 1.
+pub fn parse_rating(reply: &str) -> Result<f64, String> {
+    let rating = match parse_model_content(reply.trim().to_string()) {
+        Value::Object(fields) => fields.get("confidence").and_then(Value::as_f64),
+        Value::Number(n) => n.as_f64(),
+        _ => None,
+    };
+    match rating {
+        Some(rating) if (0.0..=10.0).contains(&rating) => Ok(rating / 10.0),
+        _ => Err(format!("Expected {{\"confidence\": <0 to 10>}}, got: {}", reply.trim())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_similarity() {
+        assert_eq!(similarity(&json!("A red car"), &json!("a red car.")), 1.0);
+        assert_eq!(similarity(&json!("a red car"), &json!("a blue car")), 0.5);
+        assert_eq!(similarity(&json!(["dog", "Beach"]), &json!(["beach", "dog"])), 1.0);
+        assert_eq!(similarity(&json!(4), &json!(5)), 0.8);
+        assert_eq!(similarity(&json!(true), &json!(false)), 0.0);
+
+        let a = json!({"count": 2, "tags": ["cat"], "indoors": true});
+        let b = json!({"count": 2, "tags": ["cat", "sofa"], "outdoors": false});
+        // count 1, tags 0.5, indoors and outdoors each missing from one side
+        assert_eq!(similarity(&a, &b), 1.5 / 4.0);
+    }
+
+    #[test]
+    fn test_agreement() {
+        let first = json!({"label": "cat"});
+        assert_eq!(agreement(&first, &[json!({"label": "cat"}), json!({"label": "dog"})]), 0.5);
+        assert_eq!(agreement(&first, &[]), 1.0);
+    }
+
+    #[test]
+    fn test_parse_rating() {
+        assert_eq!(parse_rating(r#"{"confidence": 8}"#).unwrap(), 0.8);
+        assert_eq!(parse_rating("7").unwrap(), 0.7);
+        assert!(parse_rating(r#"{"confidence": 80}"#).is_err());
+        assert!(parse_rating("very").is_err());
+    }
+
+    fn tokens(pieces: &[(&str, f64)]) -> Vec<TokenLogprob> {
+        pieces
+            .iter()
+            .map(|&(token, probability)| TokenLogprob {
+                token: token.to_string(),
+                logprob: f64::ln(probability),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_field_spans() {
+        let text = r#"```json
+{"label": "cat", "tags": ["a,b", {"x": 1}], "note": "say \"hi\"", "count": 2 }"#;
+        let values: Vec<(String, &str)> = field_spans(text).into_iter().map(|(field, span)| (field, &text[span])).collect();
+        assert_eq!(
+            values,
+            [
+                ("label".to_string(), r#""cat""#),
+                ("tags".to_string(), r#"["a,b", {"x": 1}]"#),
+                ("note".to_string(), r#""say \"hi\"""#),
+                ("count".to_string(), "2"),
+            ]
+        );
+        assert_eq!(field_spans(r#"{"label": "cat", "count": 2"#).len(), 1);
+        assert!(field_spans("A cat").is_empty());
+    }
+
+    #[test]
+    fn test_from_logprobs() {
+        let reply = tokens(&[
+            ("{\"", 1.0),
+            ("label", 1.0),
+            ("\":", 1.0),
+            (" \"", 1.0),
+            ("cat", 0.25),
+            ("\",", 1.0),
+            (" \"count\": ", 1.0),
+            ("2", 0.81),
+            ("}", 1.0),
+        ]);
+        let confidence = from_logprobs(&reply).unwrap();
+        assert_eq!(confidence.method, ConfidenceMethod::Logprobs);
+        assert_eq!(confidence.score, 0.84);
+        // The label's value is in the tokens ` "`, `cat` and `",`
+        let fields = confidence.fields.unwrap();
+        assert_eq!(fields["label"], 0.63);
+        assert_eq!(fields["count"], 0.81);
+
+        let text = from_logprobs(&tokens(&[("A", 0.5), (" cat", 0.5)])).unwrap();
+        assert_eq!((text.score, text.fields), (0.5, None));
+        assert_eq!(from_logprobs(&[]), None);
+    }
+
+    #[test]
+    fn test_confidence_is_rounded_and_clamped() {
+        assert_eq!(Confidence::new(0.8333, ConfidenceMethod::Agreement).score, 0.83);
+        assert_eq!(Confidence::new(1.2, ConfidenceMethod::SelfRating).score, 1.0);
+        assert_eq!(
+            serde_json::to_value(Confidence::new(0.5, ConfidenceMethod::SelfRating)).unwrap(),
+            json!({"score": 0.5, "method": "self_rating"})
+        );
+    }
+}
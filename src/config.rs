@@ -0,0 +1,301 @@
+//! User defaults and named profiles from `~/.config/9ladies/config.toml`.
+//!
+//! ```toml
+//! [defaults]
+//! url = "http://localhost:11434"
+//! model = "llava:13b"
+//!
+//! [profiles.work]
+//! url = "http://gpu-box:8080"
+//! api = "openai"
+//! model = "qwen2.5vl:32b"
+//! timeout = 300
+//!
+//! [profiles.farm]
+//! model = "qwen2.5vl:7b"
+//! endpoints = [
+//!     { url = "http://gpu-box:11434", concurrency = 4 },
+//!     { url = "http://laptop:11434" },
+//! ]
+//! ```
+//!
+//! Flags given on the command line always win; a selected profile overrides `[defaults]`.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+use clap::Args;
+use serde::Deserialize;
+
+use crate::field_map::FieldMap;
+use crate::secrets::KeySource;
+use crate::Api;
+
+#[derive(Args)]
+pub struct ConfigArgs {
+    /// Use the named profile from the config file
+    #[arg(long, global = true, env = "NINELADIES_PROFILE")]
+    pub profile: Option<String>,
+
+    /// Config file to read instead of ~/.config/9ladies/config.toml
+    #[arg(long, global = true, env = "NINELADIES_CONFIG")]
+    pub config: Option<String>,
+}
+
+/// Values that can be set in the config file, all optional.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Settings {
+    pub url: Option<String>,
+    pub model: Option<String>,
+    pub api: Option<Api>,
+    /// Request timeout in seconds
+    pub timeout: Option<u64>,
+    /// USD per million input tokens, for cost estimates on paid APIs
+    pub input_price: Option<f64>,
+    /// USD per million output tokens
+    pub output_price: Option<f64>,
+    /// Command run after each image, see `--exec`
+    pub exec: Option<String>,
+    /// Most model requests a minute, see `--rpm`
+    pub rpm: Option<f64>,
+    /// Servers to spread a run over, see `--endpoint`
+    pub endpoints: Option<Vec<Endpoint>>,
+    /// Shell command printing the API key for `--api openai` servers, e.g. `pass show openai`
+    pub api_key_command: Option<String>,
+    /// Name of the OS keychain entry holding the API key, used if there is no `api_key_command`
+    pub keychain: Option<String>,
+    /// Response fields to rename or move, see `crate::field_map`
+    pub fields: Option<FieldMap>,
+}
+
+impl Settings {
+    /// Where the API key comes from when `NINELADIES_API_KEY` isn't set.
+    pub fn key_source(&self) -> Option<KeySource> {
+        match (&self.api_key_command, &self.keychain) {
+            (Some(command), _) => Some(KeySource::Command(command.clone())),
+            (None, Some(name)) => Some(KeySource::Keychain(name.clone())),
+            (None, None) => None,
+        }
+    }
+
+    /// The `fields` mapping, empty if there is none.
+    pub fn field_map(&self) -> FieldMap {
+        self.fields.clone().unwrap_or_default()
+    }
+
+    /// Fill anything unset in `self` from `fallback`.
+    fn or(self, fallback: Settings) -> Settings {
+        Settings {
+            url: self.url.or(fallback.url),
+            model: self.model.or(fallback.model),
+            api: self.api.or(fallback.api),
+            timeout: self.timeout.or(fallback.timeout),
+            input_price: self.input_price.or(fallback.input_price),
+            output_price: self.output_price.or(fallback.output_price),
+            exec: self.exec.or(fallback.exec),
+            rpm: self.rpm.or(fallback.rpm),
+            endpoints: self.endpoints.or(fallback.endpoints),
+            api_key_command: self.api_key_command.or(fallback.api_key_command),
+            keychain: self.keychain.or(fallback.keychain),
+            fields: self.fields.or(fallback.fields),
+        }
+    }
+}
+
+/// A server to send requests to, and how many it takes at once.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Endpoint {
+    pub url: String,
+    #[serde(default = "one")]
+    pub concurrency: usize,
+}
+
+fn one() -> usize {
+    1
+}
+
+impl Endpoint {
+    /// Parse `--endpoint`: a URL, optionally followed by `=` and how many requests it takes
+    /// at once (default 1), e.g. `http://gpu-box:11434=4`.
+    pub fn parse(spec: &str) -> Result<Endpoint, String> {
+        let (url, concurrency) = match spec.rsplit_once('=') {
+            Some((url, count)) if !url.contains('?') => {
+                let concurrency = count
+                    .trim()
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|n| *n > 0)
+                    .ok_or_else(|| format!("Expected a URL and a number of requests above 0, got '{}'", spec))?;
+                (url, concurrency)
+            }
+            _ => (spec, 1),
+        };
+        if url.trim().is_empty() {
+            return Err(format!("Expected a URL, got '{}'", spec));
+        }
+        Ok(Endpoint {
+            url: url.trim().to_string(),
+            concurrency,
+        })
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    #[serde(default)]
+    defaults: Settings,
+    #[serde(default)]
+    profiles: BTreeMap<String, Settings>,
+}
+
+/// `$XDG_CONFIG_HOME/9ladies/config.toml`, falling back to `~/.config`.
+fn default_config_path() -> Option<PathBuf> {
+    let base = match std::env::var_os("XDG_CONFIG_HOME").filter(|v| !v.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("9ladies").join("config.toml"))
+}
+
+fn parse_config(content: &str, path: &str) -> Result<ConfigFile, String> {
+    toml::from_str(content).map_err(|e| format!("Invalid config file '{}': {}", path, e))
+}
+
+/// Resolve `[defaults]` plus the selected profile into one set of settings.
+fn select_profile(file: ConfigFile, profile: Option<&str>, path: &str) -> Result<Settings, String> {
+    let Some(name) = profile else {
+        return Ok(file.defaults);
+    };
+    let mut profiles = file.profiles;
+    match profiles.remove(name) {
+        Some(settings) => Ok(settings.or(file.defaults)),
+        None => {
+            let known: Vec<&str> = profiles.keys().map(String::as_str).collect();
+            Err(format!(
+                "Profile '{}' not found in '{}' (available: {})",
+                name,
+                path,
+                if known.is_empty() { "none".to_string() } else { known.join(", ") }
+            ))
+        }
+    }
+}
+
+/// Load settings for this invocation. A missing default config file is fine (everything
+/// comes from flags), but an explicit `--config` or `--profile` needs the file to exist.
+pub fn load(args: &ConfigArgs) -> Result<Settings, String> {
+    let path = match (&args.config, default_config_path()) {
+        (Some(path), _) => PathBuf::from(path),
+        (None, Some(path)) => path,
+        (None, None) if args.profile.is_none() => return Ok(Settings::default()),
+        (None, None) => return Err("--profile needs a config file, but HOME is not set".to_string()),
+    };
+    let display = path.display().to_string();
+
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound && args.config.is_none() && args.profile.is_none() => {
+            return Ok(Settings::default());
+        }
+        Err(e) => return Err(format!("Failed to read config file '{}': {}", display, e)),
+    };
+
+    select_profile(parse_config(&content, &display)?, args.profile.as_deref(), &display)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+[defaults]
+url = "http://localhost:11434"
+model = "llava:13b"
+timeout = 60
+
+[profiles.work]
+url = "http://gpu-box:8080"
+api = "openai"
+input_price = 0.15
+keychain = "openai"
+
+[profiles.pass]
+api_key_command = "pass show openai"
+
+[profiles.pass.fields]
+desc = "description"
+"#;
+
+    #[test]
+    fn test_profile_overrides_defaults() {
+        let file = parse_config(CONFIG, "config.toml").unwrap();
+        let settings = select_profile(file, Some("work"), "config.toml").unwrap();
+
+        assert_eq!(settings.url.as_deref(), Some("http://gpu-box:8080"));
+        assert_eq!(settings.api, Some(Api::Openai));
+        assert_eq!(settings.model.as_deref(), Some("llava:13b"));
+        assert_eq!(settings.timeout, Some(60));
+        assert_eq!(settings.input_price, Some(0.15));
+        assert_eq!(settings.output_price, None);
+        assert_eq!(settings.key_source(), Some(KeySource::Keychain("openai".to_string())));
+    }
+
+    #[test]
+    fn test_profile_fields() {
+        let file = parse_config(CONFIG, "config.toml").unwrap();
+        let settings = select_profile(file, Some("pass"), "config.toml").unwrap();
+        let response = settings.field_map().apply(serde_json::json!({"desc": "A cat"}));
+        assert_eq!(response, serde_json::json!({"description": "A cat"}));
+
+        let err = parse_config("[defaults.fields]\n\"vendor.\" = \"vendor\"\n", "config.toml").unwrap_err();
+        assert!(err.contains("Invalid field 'vendor.'"), "{}", err);
+    }
+
+    #[test]
+    fn test_no_profile_uses_defaults() {
+        let file = parse_config(CONFIG, "config.toml").unwrap();
+        let settings = select_profile(file, None, "config.toml").unwrap();
+
+        assert_eq!(settings.url.as_deref(), Some("http://localhost:11434"));
+        assert_eq!(settings.api, None);
+    }
+
+    #[test]
+    fn test_unknown_profile_lists_available() {
+        let file = parse_config(CONFIG, "config.toml").unwrap();
+        let err = select_profile(file, Some("home"), "config.toml").unwrap_err();
+        assert!(err.contains("'home'") && err.contains("available: pass, work"));
+    }
+
+    #[test]
+    fn test_endpoints() {
+        let config = "[defaults]\nendpoints = [{ url = \"http://gpu:11434\", concurrency = 4 }, { url = \"http://laptop:11434\" }]\n";
+        let settings = select_profile(parse_config(config, "config.toml").unwrap(), None, "config.toml").unwrap();
+        assert_eq!(
+            settings.endpoints.unwrap(),
+            vec![Endpoint::parse("http://gpu:11434=4").unwrap(), Endpoint::parse("http://laptop:11434").unwrap()]
+        );
+        assert_eq!(Endpoint::parse("http://laptop:11434").unwrap().concurrency, 1);
+        assert!(Endpoint::parse("http://gpu:11434=0").is_err());
+        assert!(Endpoint::parse("http://gpu:11434=four").is_err());
+    }
+
+    #[test]
+    fn test_config_rejects_unknown_keys() {
+        let err = parse_config("[defaults]\nmodle = \"llava\"\n", "config.toml").unwrap_err();
+        assert!(err.contains("modle"));
+    }
+
+    #[test]
+    fn test_explicit_config_must_exist() {
+        let args = ConfigArgs {
+            profile: None,
+            config: Some("/nonexistent/9ladies.toml".to_string()),
+        };
+        assert!(load(&args).is_err());
+    }
+}
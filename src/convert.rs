@@ -0,0 +1,257 @@
+//! Reshaping existing output files for `9ladies convert`: CSV for spreadsheets, HTML and
+//! Markdown tables for reading and sharing, and Parquet for data tools.
+//!
+//! Each record is one row: `file`, `sha256`, then a column per top-level field of the JSON
+//! responses, in the order they first appear, or a `response` column for text responses.
+//! Values that are objects or lists are written as JSON, unless the responses are flattened
+//! into a column per nested field (see `crate::flatten`).
+
+use std::collections::HashSet;
+use std::fmt::Write as _;
+
+use clap::ValueEnum;
+use serde_json::{Map, Value};
+
+use crate::flatten::{text as cell_text, Flatten};
+use crate::OutputRecord;
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum ConvertFormat {
+    /// Comma-separated values with a header row
+    Csv,
+    /// A standalone HTML page with a table and a thumbnail of each image
+    Html,
+    /// A Markdown table
+    Md,
+    /// Apache Parquet, with column types inferred (needs the `duckdb` feature)
+    Parquet,
+}
+
+/// Records as rows of named columns.
+#[derive(Debug, PartialEq)]
+pub struct Table {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<Value>>,
+}
+
+impl Table {
+    /// The table of `records`, with the responses flattened by `flatten` if given.
+    pub fn from_records(records: &[OutputRecord], flatten: Option<&Flatten>) -> Table {
+        let fields: Vec<Map<String, Value>> = records
+            .iter()
+            .map(|record| match (flatten, &record.response) {
+                (Some(flatten), response) => flatten.fields(response).into_iter().collect(),
+                (None, Value::Object(object)) => object.clone(),
+                (None, response) => Map::from_iter([("response".to_string(), response.clone())]),
+            })
+            .collect();
+
+        let mut columns = vec!["file".to_string(), "sha256".to_string()];
+        let mut seen: HashSet<String> = columns.iter().cloned().collect();
+        for field in fields.iter().flat_map(Map::keys) {
+            if seen.insert(field.clone()) {
+                columns.push(field.clone());
+            }
+        }
+
+        let rows = records
+            .iter()
+            .zip(&fields)
+            .map(|(record, fields)| {
+                columns
+                    .iter()
+                    .map(|column| match column.as_str() {
+                        "file" => Value::String(record.file.clone()),
+                        "sha256" => Value::String(record.sha256.clone()),
+                        column => fields.get(column).cloned().unwrap_or(Value::Null),
+                    })
+                    .collect()
+            })
+            .collect();
+        Table { columns, rows }
+    }
+
+    /// Each row as a JSON object, for tools that infer column types from JSON.
+    pub fn objects(&self) -> impl Iterator<Item = Map<String, Value>> + '_ {
+        self.rows.iter().map(|row| self.columns.iter().cloned().zip(row.iter().cloned()).collect())
+    }
+
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::new();
+        let line = |cells: Vec<String>| cells.iter().map(|cell| csv_field(cell)).collect::<Vec<_>>().join(",") + "\r\n";
+        csv.push_str(&line(self.columns.clone()));
+        for row in &self.rows {
+            csv.push_str(&line(row.iter().map(cell_text).collect()));
+        }
+        csv
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let line = |cells: Vec<String>| format!("| {} |\n", cells.join(" | "));
+        let mut markdown = line(self.columns.iter().map(|column| markdown_cell(column)).collect());
+        markdown.push_str(&line(self.columns.iter().map(|_| "---".to_string()).collect()));
+        for row in &self.rows {
+            markdown.push_str(&line(row.iter().map(|value| markdown_cell(&cell_text(value))).collect()));
+        }
+        markdown
+    }
+
+    /// A page titled `title`. The `file` column shows the image, loaded from its path as
+    /// recorded, so open the page from the folder the run was made in.
+    pub fn to_html(&self, title: &str) -> String {
+        let mut html = String::new();
+        let _ = write!(
+            html,
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>\n\
+             body {{ font-family: sans-serif; }}\ntable {{ border-collapse: collapse; }}\n\
+             th, td {{ border: 1px solid #ccc; padding: 4px 8px; text-align: left; vertical-align: top; }}\n\
+             td.file img {{ display: block; max-width: 160px; max-height: 160px; }}\n</style>\n</head>\n<body>\n\
+             <table>\n<thead>\n<tr>",
+            escape_html(title)
+        );
+        for column in &self.columns {
+            let _ = write!(html, "<th>{}</th>", escape_html(column));
+        }
+        html.push_str("</tr>\n</thead>\n<tbody>\n");
+        for row in &self.rows {
+            html.push_str("<tr>");
+            for (column, value) in self.columns.iter().zip(row) {
+                let text = escape_html(&cell_text(value));
+                if column == "file" {
+                    let _ = write!(html, "<td class=\"file\"><img src=\"{0}\" alt=\"\" loading=\"lazy\">{0}</td>", text);
+                } else {
+                    let _ = write!(html, "<td>{}</td>", text);
+                }
+            }
+            html.push_str("</tr>\n");
+        }
+        html.push_str("</tbody>\n</table>\n</body>\n</html>\n");
+        html
+    }
+}
+
+/// A CSV field, quoted if it has a comma, quote or line break.
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+/// A Markdown table cell, with pipes escaped and line breaks as `<br>`.
+fn markdown_cell(text: &str) -> String {
+    text.replace('|', "\\|").replace("\r\n", "<br>").replace('\n', "<br>")
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// Write `table` as a Parquet file at `path`, by way of DuckDB's JSON reader, which infers
+/// each column's type.
+#[cfg(feature = "duckdb")]
+pub fn write_parquet(table: &Table, path: &str) -> Result<(), String> {
+    let rows = std::env::temp_dir().join(format!("nineladies_convert_{}.jsonl", std::process::id()));
+    let mut jsonl = String::new();
+    for object in table.objects() {
+        jsonl.push_str(&Value::Object(object).to_string());
+        jsonl.push('\n');
+    }
+    std::fs::write(&rows, jsonl).map_err(|e| format!("Failed to write '{}': {}", rows.display(), e))?;
+
+    let quote = |text: &str| format!("'{}'", text.replace('\'', "''"));
+    let result = duckdb::Connection::open_in_memory()
+        .and_then(|conn| {
+            conn.execute_batch(&format!(
+                "COPY (SELECT * FROM read_json_auto({}, format = 'newline_delimited')) TO {} (FORMAT PARQUET)",
+                quote(&rows.to_string_lossy()),
+                quote(path)
+            ))
+        })
+        .map_err(|e| format!("Failed to write Parquet file '{}': {}", path, e));
+    std::fs::remove_file(&rows).ok();
+    result
+}
+
+#[cfg(not(feature = "duckdb"))]
+pub fn write_parquet(_table: &Table, _path: &str) -> Result<(), String> {
+    Err("Parquet output needs 9ladies built with the 'duckdb' feature".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flatten::ListPolicy;
+    use serde_json::json;
+
+    fn table() -> Table {
+        Table::from_records(&[
+            OutputRecord::new("a.jpg", "1", json!({"caption": "A cat, asleep", "tags": ["cat", "sofa"]})),
+            OutputRecord::new("b.jpg", "2", json!({"caption": "Say \"hi\"\nthen | go", "count": 2})),
+            OutputRecord::new("c.jpg", "3", json!("A <b>red</b> square")),
+        ], None)
+    }
+
+    #[test]
+    fn test_table_columns() {
+        let table = table();
+        assert_eq!(table.columns, ["file", "sha256", "caption", "tags", "count", "response"]);
+        assert_eq!(table.rows[0][3], json!(["cat", "sofa"]));
+        assert_eq!(table.rows[0][4], Value::Null);
+        assert_eq!(table.rows[2][5], json!("A <b>red</b> square"));
+        assert_eq!(table.objects().next().unwrap()["caption"], json!("A cat, asleep"));
+    }
+
+    #[test]
+    fn test_flattened_table() {
+        let records = [
+            OutputRecord::new("a.jpg", "1", json!({"vendor": {"name": "Aldi"}, "items": [{"name": "milk"}, {"name": "eggs"}]})),
+            OutputRecord::new("b.jpg", "2", json!("No receipt")),
+        ];
+        let flatten = Flatten {
+            lists: ListPolicy::Join,
+            ..Flatten::default()
+        };
+        let table = Table::from_records(&records, Some(&flatten));
+        assert_eq!(table.columns, ["file", "sha256", "vendor.name", "items.name", "response"]);
+        assert_eq!(table.rows[0], [json!("a.jpg"), json!("1"), json!("Aldi"), json!("milk; eggs"), Value::Null]);
+        assert_eq!(table.rows[1][4], json!("No receipt"));
+    }
+
+    #[test]
+    fn test_csv_markdown_and_html() {
+        let table = table();
+        let csv = table.to_csv();
+        let lines: Vec<&str> = csv.split("\r\n").collect();
+        assert_eq!(lines[0], "file,sha256,caption,tags,count,response");
+        assert_eq!(lines[1], "a.jpg,1,\"A cat, asleep\",\"[\"\"cat\"\",\"\"sofa\"\"]\",,");
+        assert_eq!(lines[2], "b.jpg,2,\"Say \"\"hi\"\"\nthen | go\",,2,");
+
+        let markdown = table.to_markdown();
+        assert!(markdown.starts_with("| file | sha256 | caption | tags | count | response |\n| --- |"));
+        assert!(markdown.contains("| b.jpg | 2 | Say \"hi\"<br>then \\| go |  | 2 |  |"));
+
+        let html = table.to_html("results.jsonl");
+        assert!(html.contains("<title>results.jsonl</title>"));
+        assert!(html.contains("<td class=\"file\"><img src=\"a.jpg\" alt=\"\" loading=\"lazy\">a.jpg</td>"));
+        assert!(html.contains("<td>A &lt;b&gt;red&lt;/b&gt; square</td>"));
+    }
+
+    #[cfg(feature = "duckdb")]
+    #[test]
+    fn test_write_parquet() {
+        let path = std::env::temp_dir().join(format!("nineladies_convert_{}.parquet", std::process::id()));
+        let path = path.to_str().unwrap();
+        write_parquet(&table(), path).unwrap();
+
+        let conn = duckdb::Connection::open_in_memory().unwrap();
+        let (rows, count): (i64, Option<i64>) = conn
+            .query_row(&format!("SELECT count(*), sum(count) FROM read_parquet('{}')", path), [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert_eq!((rows, count), (3, Some(2)));
+        std::fs::remove_file(path).ok();
+    }
+}
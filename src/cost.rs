@@ -0,0 +1,113 @@
+//! Token counts and cost estimates for paid, token-priced backends.
+
+use std::io::Cursor;
+use std::ops::AddAssign;
+
+/// Rough characters per token of prompt text
+const CHARS_PER_TOKEN: usize = 4;
+
+/// Assumed reply length when estimating cost before a run, since it depends on the prompt
+pub const ESTIMATED_OUTPUT_TOKENS: u64 = 300;
+
+/// Tokens used by a request, as reported by the server.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TokenUsage {
+    pub input: u64,
+    pub output: u64,
+}
+
+impl AddAssign for TokenUsage {
+    fn add_assign(&mut self, other: TokenUsage) {
+        self.input += other.input;
+        self.output += other.output;
+    }
+}
+
+/// Prices in USD per million tokens.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Prices {
+    pub input: f64,
+    pub output: f64,
+}
+
+impl Prices {
+    /// Prices are only known when at least one is configured; the other counts as free.
+    pub fn configured(input: Option<f64>, output: Option<f64>) -> Option<Prices> {
+        if input.is_none() && output.is_none() {
+            return None;
+        }
+        Some(Prices {
+            input: input.unwrap_or(0.0),
+            output: output.unwrap_or(0.0),
+        })
+    }
+
+    pub fn cost(&self, usage: TokenUsage) -> f64 {
+        (usage.input as f64 * self.input + usage.output as f64 * self.output) / 1e6
+    }
+}
+
+pub fn estimate_text_tokens(text_len: usize) -> u64 {
+    text_len.div_ceil(CHARS_PER_TOKEN) as u64
+}
+
+/// Estimate the input tokens an image costs, using OpenAI's published high-detail rule:
+/// fit within 2048x2048, scale the short side down to 768, then 170 tokens per 512px
+/// tile plus 85. Other providers count differently, so treat this as a ballpark.
+pub fn estimate_image_tokens(width: u32, height: u32) -> u64 {
+    if width == 0 || height == 0 {
+        return 85;
+    }
+    let (mut w, mut h) = (width as f64, height as f64);
+
+    let fit = (2048.0 / w.max(h)).min(1.0);
+    w *= fit;
+    h *= fit;
+    let short = (768.0 / w.min(h)).min(1.0);
+    w *= short;
+    h *= short;
+
+    let tiles = (w / 512.0).ceil() * (h / 512.0).ceil();
+    85 + 170 * tiles as u64
+}
+
+/// Read an image's dimensions from its header, without decoding the pixels.
+pub fn image_dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    image::ImageReader::new(Cursor::new(data))
+        .with_guessed_format()
+        .ok()?
+        .into_dimensions()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimate_image_tokens() {
+        // OpenAI's worked examples: 1024x1024 is 4 tiles, 2048x4096 becomes 768x1536 (6 tiles)
+        assert_eq!(estimate_image_tokens(1024, 1024), 765);
+        assert_eq!(estimate_image_tokens(2048, 4096), 1105);
+        assert_eq!(estimate_image_tokens(100, 100), 255);
+    }
+
+    #[test]
+    fn test_prices() {
+        assert_eq!(Prices::configured(None, None), None);
+
+        let prices = Prices::configured(Some(2.5), None).unwrap();
+        let usage = TokenUsage { input: 1_000_000, output: 500 };
+        assert_eq!(prices.cost(usage), 2.5);
+
+        let prices = Prices::configured(Some(1.0), Some(4.0)).unwrap();
+        assert!((prices.cost(TokenUsage { input: 1000, output: 1000 }) - 0.005).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_image_dimensions() {
+        let png = std::fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/red.png")).unwrap();
+        assert_eq!(image_dimensions(&png), Some((1, 1)));
+        assert_eq!(image_dimensions(b"not an image"), None);
+    }
+}
@@ -0,0 +1,306 @@
+//! `9ladies daemon`: watch directories and describe every image that appears or changes,
+//! keeping each file's status in a SQLite state database so a restart picks up where the
+//! last run stopped.
+//!
+//! Directories are rescanned on an interval rather than through filesystem events, which
+//! works the same on network shares and in containers. A file is only looked at once its
+//! modification time has settled, so half-copied uploads aren't described.
+//!
+//! Files can also be queued in the state database directly, by `--listen`'s `POST /jobs`;
+//! each scan describes those wherever they are, and a queued job starts the next scan early.
+
+use std::collections::HashSet;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+use tracing::{debug, info, warn};
+
+use nineladies::images::{has_image_extension, sha256_hex};
+use nineladies::paths::{self, long_path};
+use nineladies::store::{FileStamp, FileState, JobStatus, JobStore};
+use nineladies::{validate_image_file, BatchRunner, CallError, ItemError};
+
+use crate::shutdown::Shutdown;
+
+/// How long a file must go unmodified before it is described
+const SETTLE_TIME: Duration = Duration::from_secs(2);
+
+/// How often the wait between scans checks for Ctrl+C and queued jobs
+const WAIT_STEP: Duration = Duration::from_millis(250);
+
+pub struct DaemonOptions {
+    pub dirs: Vec<PathBuf>,
+    pub interval: Duration,
+    /// Signaled when a job is queued, to scan without waiting out the interval
+    pub queued: Option<Receiver<()>>,
+}
+
+/// Counts for one pass over the directories.
+#[derive(Debug, Default, PartialEq)]
+struct ScanSummary {
+    described: usize,
+    failed: usize,
+    unchanged: usize,
+}
+
+/// Add every image under `dir` to `images`.
+pub fn find_images(dir: &Path, images: &mut Vec<PathBuf>) -> Result<(), String> {
+    let entries = fs::read_dir(long_path(dir)).map_err(|e| format!("Cannot read directory '{}': {}", dir.display(), e))?;
+    for entry in entries {
+        let entry = entry.map_err(|e| format!("Cannot read directory '{}': {}", dir.display(), e))?;
+        let path = entry.path();
+        let file_type = entry
+            .file_type()
+            .map_err(|e| format!("Cannot read '{}': {}", path.display(), e))?;
+        if file_type.is_dir() {
+            find_images(&path, images)?;
+        } else if has_image_extension(&path) {
+            images.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// What to do with a file, given what the store knows about it.
+#[derive(Debug, PartialEq)]
+enum Action {
+    Skip,
+    /// Only the modification time changed; record it without describing again
+    Touch,
+    Describe,
+}
+
+fn action(known: Option<&FileState>, stamp: FileStamp, sha256: impl FnOnce() -> String) -> Action {
+    match known {
+        None => Action::Describe,
+        Some(state) if !matches!(state.status, JobStatus::Done | JobStatus::Failed) => Action::Describe,
+        Some(state) if state.size == stamp.size && state.modified_ms == stamp.modified_ms => Action::Skip,
+        Some(state) if state.status == JobStatus::Done && state.sha256 == sha256() => Action::Touch,
+        Some(_) => Action::Describe,
+    }
+}
+
+/// The image bytes, and their hash (empty when the file can't be used).
+fn read_image(path: &Path) -> (Result<Vec<u8>, String>, String) {
+    let data = validate_image_file(path);
+    let sha256 = data.as_ref().map(|d| sha256_hex(d)).unwrap_or_default();
+    (data, sha256)
+}
+
+/// One pass over the directories. Stops early when the model server is unreachable, leaving
+/// the rest for the next pass.
+fn scan(
+    runner: &BatchRunner,
+    store: &JobStore,
+    options: &DaemonOptions,
+    output: &mut dyn Write,
+    shutdown: &Shutdown,
+) -> Result<ScanSummary, String> {
+    let mut images = Vec::new();
+    for dir in &options.dirs {
+        find_images(dir, &mut images)?;
+    }
+    // Queued files were complete when they were queued, so they don't wait to settle
+    let mut queued = HashSet::new();
+    for entry in store.list(JobStatus::Pending)? {
+        let path = PathBuf::from(&entry.path);
+        if !long_path(&path).exists() {
+            store.start(&entry.path, FileStamp::default())?;
+            store.mark_failed(&entry.path, "", &format!("File not found: {}", entry.path))?;
+            continue;
+        }
+        if !images.contains(&path) {
+            images.push(path.clone());
+        }
+        queued.insert(path);
+    }
+    images.sort();
+
+    let mut summary = ScanSummary::default();
+    for path in images {
+        if shutdown.requested() {
+            break;
+        }
+        let file = paths::normalize(&path.to_string_lossy());
+        let Ok(metadata) = fs::metadata(&path) else {
+            // Deleted since the directory was read
+            continue;
+        };
+        let stamp = FileStamp::of(&metadata);
+        let settled = metadata.modified().ok().and_then(|t| t.elapsed().ok()).is_some_and(|age| age >= SETTLE_TIME);
+        if !settled && !queued.contains(&path) {
+            debug!(file = file.as_str(), "Still being written; leaving it for the next scan");
+            continue;
+        }
+
+        let known = store.get(&file)?;
+        let mut read = None;
+        match action(known.as_ref(), stamp, || read.insert(read_image(&path)).1.clone()) {
+            Action::Skip => {
+                summary.unchanged += 1;
+                continue;
+            }
+            Action::Touch => {
+                store.touch(&file, stamp)?;
+                summary.unchanged += 1;
+                continue;
+            }
+            Action::Describe => {}
+        }
+
+        let (data, sha256) = read.unwrap_or_else(|| read_image(&path));
+        store.start(&file, stamp)?;
+        let described = data.map_err(ItemError::Invalid).and_then(|data| runner.describe_file_data(&file, &data));
+        match described {
+            Ok(described) => {
+                let line = serde_json::to_string(&described.record).expect("records serialize");
+                writeln!(output, "{}", line)
+                    .and_then(|_| output.flush())
+                    .map_err(|e| format!("Failed to write output: {}", e))?;
+                store.mark_done(&file, &described.record)?;
+                summary.described += 1;
+            }
+            Err(ItemError::Call(CallError::Unreachable(e))) => {
+                // Pending again, so a queued file outside the watched folders isn't stranded
+                store.reset(&file)?;
+                warn!("{}; trying again on the next scan", e);
+                break;
+            }
+            Err(e) => {
+                warn!(file = file.as_str(), "{}", e);
+                store.mark_failed(&file, &sha256, &e.to_string())?;
+                summary.failed += 1;
+            }
+        }
+    }
+    Ok(summary)
+}
+
+/// Scan until a shutdown is requested.
+pub fn watch(
+    runner: &BatchRunner,
+    store: &JobStore,
+    options: &DaemonOptions,
+    output: &mut dyn Write,
+    shutdown: &Shutdown,
+) -> Result<(), String> {
+    let interrupted = store.reset_in_progress()?;
+    if interrupted > 0 {
+        info!("{} file(s) were interrupted by the last shutdown and will be described again", interrupted);
+    }
+    info!(
+        dirs = options.dirs.len(),
+        interval_secs = options.interval.as_secs(),
+        "Watching for images"
+    );
+    while !shutdown.requested() {
+        let summary = scan(runner, store, options, output, shutdown)?;
+        if summary.described > 0 || summary.failed > 0 {
+            info!(
+                described = summary.described,
+                failed = summary.failed,
+                unchanged = summary.unchanged,
+                "Scan finished"
+            );
+        }
+        wait(options, shutdown);
+    }
+    Ok(())
+}
+
+/// Wait out the interval between scans, or until a job is queued or a shutdown requested.
+fn wait(options: &DaemonOptions, shutdown: &Shutdown) {
+    let Some(queued) = &options.queued else {
+        shutdown.wait(options.interval);
+        return;
+    };
+    let started = Instant::now();
+    while !shutdown.requested() && started.elapsed() < options.interval {
+        match queued.recv_timeout(WAIT_STEP) {
+            Ok(()) => {
+                // One scan takes every job queued so far
+                while queued.try_recv().is_ok() {}
+                return;
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => {
+                shutdown.wait(options.interval.saturating_sub(started.elapsed()));
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nineladies::{Api, Backend, PromptConfig};
+
+    fn known(status: JobStatus) -> FileState {
+        FileState {
+            size: 10,
+            modified_ms: 1000,
+            sha256: "abc".to_string(),
+            status,
+            attempts: 1,
+        }
+    }
+
+    #[test]
+    fn test_action() {
+        let same = FileStamp { size: 10, modified_ms: 1000 };
+        let touched = FileStamp { size: 10, modified_ms: 2000 };
+        let sha = || "abc".to_string();
+
+        assert_eq!(action(None, same, sha), Action::Describe);
+        assert_eq!(action(Some(&known(JobStatus::Done)), same, || unreachable!()), Action::Skip);
+        assert_eq!(action(Some(&known(JobStatus::Failed)), same, sha), Action::Skip);
+        assert_eq!(action(Some(&known(JobStatus::Done)), touched, sha), Action::Touch);
+        assert_eq!(action(Some(&known(JobStatus::Done)), touched, || "def".to_string()), Action::Describe);
+        assert_eq!(action(Some(&known(JobStatus::Failed)), touched, sha), Action::Describe);
+        // Interrupted before it finished
+        assert_eq!(action(Some(&known(JobStatus::InProgress)), same, sha), Action::Describe);
+    }
+
+    #[test]
+    fn test_find_images() {
+        let fixtures = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+        let mut images = Vec::new();
+        find_images(&fixtures, &mut images).unwrap();
+        let names: Vec<_> = images.iter().map(|p| p.file_name().unwrap().to_string_lossy().into_owned()).collect();
+        assert!(names.contains(&"red.png".to_string()));
+        assert!(!names.contains(&"not-an-image.txt".to_string()));
+    }
+
+    #[test]
+    fn test_queued_file_is_pending_again_when_the_server_is_down() {
+        let config = PromptConfig {
+            system: String::new(),
+            prompt: "Describe this.".to_string(),
+            temperature: 0.0,
+            model: None,
+            schema: None,
+            seed: None,
+            think: None,
+            schema_via: None,
+        };
+        // Nothing listens on port 9
+        let runner = BatchRunner::new(Backend::new(Api::Ollama, "http://127.0.0.1:9", None, Some(1)), config);
+        let store = JobStore::in_memory().unwrap();
+        let file = paths::normalize(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/red.png"));
+        store.submit(&file).unwrap();
+
+        let options = DaemonOptions {
+            dirs: Vec::new(),
+            interval: Duration::from_secs(60),
+            queued: None,
+        };
+        let summary = scan(&runner, &store, &options, &mut Vec::new(), &Shutdown::default()).unwrap();
+        assert_eq!(summary, ScanSummary::default());
+        let state = store.get(&file).unwrap().unwrap();
+        assert_eq!((state.status, state.attempts), (JobStatus::Pending, 0));
+    }
+}
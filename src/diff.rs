@@ -0,0 +1,236 @@
+//! Comparing two output files for `9ladies diff`, e.g. runs with different models or
+//! prompt revisions over the same images.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use clap::ValueEnum;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::OutputRecord;
+
+/// How records in the two files are matched up.
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum MatchBy {
+    /// The image path as given
+    #[default]
+    File,
+    /// The image's SHA-256, so renamed or moved images still match; records without one
+    /// fall back to their path
+    Sha256,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Diff {
+    /// Files only in the new output
+    pub added: Vec<String>,
+    /// Files only in the old output
+    pub removed: Vec<String>,
+    pub changed: Vec<Change>,
+    pub unchanged: usize,
+}
+
+/// A file whose response differs between the two outputs.
+#[derive(Debug, Serialize)]
+pub struct Change {
+    pub file: String,
+    pub fields: Vec<FieldChange>,
+}
+
+/// One differing value, by its path in the record, e.g. `response.items[2].price`. A
+/// missing old or new value means the field was added or removed.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct FieldChange {
+    pub path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new: Option<Value>,
+}
+
+/// Match the records of two outputs and compare their responses. A file that appears more
+/// than once in an output, as in one appended to by `--resume`, is compared by its last
+/// record. Files are listed in the order of the output they were found in.
+pub fn diff_outputs(old: &[OutputRecord], new: &[OutputRecord], by: MatchBy) -> Diff {
+    let old_index = last_by_key(old, by);
+    let new_index = last_by_key(new, by);
+    let mut diff = Diff::default();
+
+    for (i, record) in old.iter().enumerate() {
+        let key = match_key(record, by);
+        if old_index[&key] != i {
+            continue;
+        }
+        match new_index.get(&key) {
+            None => diff.removed.push(record.file.clone()),
+            Some(&j) => {
+                let fields = diff_values(&record.response, &new[j].response, "response");
+                if fields.is_empty() {
+                    diff.unchanged += 1;
+                } else {
+                    diff.changed.push(Change {
+                        file: new[j].file.clone(),
+                        fields,
+                    });
+                }
+            }
+        }
+    }
+    for (j, record) in new.iter().enumerate() {
+        let key = match_key(record, by);
+        if new_index[&key] == j && !old_index.contains_key(&key) {
+            diff.added.push(record.file.clone());
+        }
+    }
+    diff
+}
+
+/// What identifies a record: the image, plus the second image or video frame for records
+/// from `compare` and `video`, which make several records per file.
+pub(crate) fn match_key(record: &OutputRecord, by: MatchBy) -> String {
+    let image = match by {
+        MatchBy::Sha256 if !record.sha256.is_empty() => &record.sha256,
+        _ => &record.file,
+    };
+    let mut key = image.clone();
+    if let Some(other) = &record.compared_with {
+        key.push_str(&format!("\0{}", other));
+    }
+    if let Some(time) = record.frame_time {
+        key.push_str(&format!("\0{}", time));
+    }
+    key
+}
+
+/// The index of each key's last record.
+fn last_by_key(records: &[OutputRecord], by: MatchBy) -> HashMap<String, usize> {
+    records.iter().enumerate().map(|(i, record)| (match_key(record, by), i)).collect()
+}
+
+/// The differences between two values, field by field for objects and item by item for
+/// lists of the same length; anything else that differs is one change.
+pub fn diff_values(old: &Value, new: &Value, path: &str) -> Vec<FieldChange> {
+    match (old, new) {
+        (Value::Object(old_fields), Value::Object(new_fields)) => {
+            let mut changes = Vec::new();
+            for (key, old_value) in old_fields {
+                let path = format!("{}.{}", path, key);
+                match new_fields.get(key) {
+                    Some(new_value) => changes.extend(diff_values(old_value, new_value, &path)),
+                    None => changes.push(FieldChange {
+                        path,
+                        old: Some(old_value.clone()),
+                        new: None,
+                    }),
+                }
+            }
+            for (key, new_value) in new_fields.iter().filter(|(key, _)| !old_fields.contains_key(*key)) {
+                changes.push(FieldChange {
+                    path: format!("{}.{}", path, key),
+                    old: None,
+                    new: Some(new_value.clone()),
+                });
+            }
+            changes
+        }
+        (Value::Array(old_items), Value::Array(new_items)) if old_items.len() == new_items.len() => old_items
+            .iter()
+            .zip(new_items)
+            .enumerate()
+            .flat_map(|(i, (old, new))| diff_values(old, new, &format!("{}[{}]", path, i)))
+            .collect(),
+        (Value::Number(old), Value::Number(new)) if old.as_f64() == new.as_f64() => Vec::new(),
+        (old, new) if old == new => Vec::new(),
+        (old, new) => vec![FieldChange {
+            path: path.to_string(),
+            old: Some(old.clone()),
+            new: Some(new.clone()),
+        }],
+    }
+}
+
+impl fmt::Display for Diff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Added:     {}", self.added.len())?;
+        writeln!(f, "Removed:   {}", self.removed.len())?;
+        writeln!(f, "Changed:   {}", self.changed.len())?;
+        writeln!(f, "Unchanged: {}", self.unchanged)?;
+
+        let shown = |value: &Option<Value>| value.as_ref().map_or("(missing)".to_string(), Value::to_string);
+        for file in &self.added {
+            writeln!(f, "+ {}", file)?;
+        }
+        for file in &self.removed {
+            writeln!(f, "- {}", file)?;
+        }
+        for change in &self.changed {
+            writeln!(f, "~ {}", change.file)?;
+            for field in &change.fields {
+                writeln!(f, "    {}: {} -> {}", field.path, shown(&field.old), shown(&field.new))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn record(file: &str, sha256: &str, response: Value) -> OutputRecord {
+        OutputRecord::new(file, sha256, response)
+    }
+
+    #[test]
+    fn test_diff_values() {
+        let old = json!({"category": "receipt", "total": 12, "items": ["milk", "eggs"], "notes": "x"});
+        let new = json!({"category": "invoice", "total": 12.0, "items": ["milk", "bread"], "vendor": "Aldi"});
+        assert_eq!(
+            diff_values(&old, &new, "response"),
+            vec![
+                FieldChange { path: "response.category".to_string(), old: Some(json!("receipt")), new: Some(json!("invoice")) },
+                FieldChange { path: "response.items[1]".to_string(), old: Some(json!("eggs")), new: Some(json!("bread")) },
+                FieldChange { path: "response.notes".to_string(), old: Some(json!("x")), new: None },
+                FieldChange { path: "response.vendor".to_string(), old: None, new: Some(json!("Aldi")) },
+            ]
+        );
+        assert_eq!(diff_values(&json!("A cat"), &json!({"subject": "cat"}), "response").len(), 1);
+        assert!(diff_values(&json!([1, 2]), &json!([1, 2]), "response").is_empty());
+    }
+
+    #[test]
+    fn test_diff_outputs_by_file() {
+        let old = vec![
+            record("a.jpg", "1", json!({"n": 1})),
+            record("b.jpg", "2", json!("a dog")),
+            record("c.jpg", "3", json!("gone")),
+            record("a.jpg", "1", json!({"n": 2})),
+        ];
+        let new = vec![record("a.jpg", "1", json!({"n": 2})), record("b.jpg", "2", json!("a cat")), record("d.jpg", "4", json!("new"))];
+
+        let diff = diff_outputs(&old, &new, MatchBy::File);
+        assert_eq!(diff.added, ["d.jpg"]);
+        assert_eq!(diff.removed, ["c.jpg"]);
+        assert_eq!(diff.unchanged, 1);
+        assert_eq!(diff.changed.len(), 1);
+        assert_eq!(diff.changed[0].file, "b.jpg");
+        assert_eq!(diff.changed[0].fields[0].path, "response");
+
+        let text = diff.to_string();
+        assert!(text.contains("Changed:   1"));
+        assert!(text.contains("~ b.jpg\n    response: \"a dog\" -> \"a cat\""));
+    }
+
+    #[test]
+    fn test_diff_outputs_by_hash_follows_renames() {
+        let old = vec![record("IMG_0001.jpg", "abc", json!("a beach"))];
+        let new = vec![record("beach.jpg", "abc", json!("a beach"))];
+
+        let by_file = diff_outputs(&old, &new, MatchBy::File);
+        assert_eq!((by_file.added.len(), by_file.removed.len()), (1, 1));
+        let by_hash = diff_outputs(&old, &new, MatchBy::Sha256);
+        assert_eq!((by_hash.added.len(), by_hash.removed.len(), by_hash.unchanged), (0, 0, 1));
+    }
+}
@@ -0,0 +1,356 @@
+//! URL inputs: an `http://` or `https://` input is downloaded before it is described, under
+//! limits that keep a crawled list of half a million images from saturating the local
+//! uplink or hammering the servers the images come from.
+//!
+//! During a batch, the next URLs are fetched ahead of the image being described, several at
+//! once, and each is handed over as the batch reaches it. The bandwidth cap is shared by all
+//! of them, and requests to the same host are spaced out whichever download sends them.
+
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tracing::debug;
+
+/// How long a download may take before it fails
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// How often a fetcher waiting for the batch to catch up checks whether the run is over
+const WAIT_STEP: Duration = Duration::from_millis(250);
+
+/// The most bytes read at a time
+const CHUNK_BYTES: usize = 16 * 1024;
+
+/// Whether an input names a URL to download rather than a file.
+pub fn is_url(input: &str) -> bool {
+    let lower = input.get(..8).unwrap_or(input).to_ascii_lowercase();
+    lower.starts_with("http://") || lower.starts_with("https://")
+}
+
+/// Limits on downloading URL inputs.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DownloadOptions {
+    /// Downloads at once, ahead of the image being described
+    pub concurrency: usize,
+    /// The largest image to download; bigger ones fail without the rest being read
+    pub max_bytes: Option<u64>,
+    /// The most bytes per second read across every download
+    pub bytes_per_sec: Option<u64>,
+    /// The least time between requests to the same host
+    pub host_delay: Duration,
+}
+
+impl Default for DownloadOptions {
+    fn default() -> Self {
+        DownloadOptions {
+            concurrency: 4,
+            max_bytes: None,
+            bytes_per_sec: None,
+            host_delay: Duration::ZERO,
+        }
+    }
+}
+
+/// Downloads fetched ahead, waiting for the batch to take them.
+#[derive(Default)]
+struct Prefetched {
+    results: HashMap<String, Result<Vec<u8>, String>>,
+    in_flight: HashSet<String>,
+    /// URLs the batch got to before a fetcher did, which it downloads itself
+    claimed: HashSet<String>,
+    /// How many URLs the batch has taken, so fetchers stay only a few ahead of it
+    taken: usize,
+}
+
+/// Fetches URL inputs within the limits of its `DownloadOptions`.
+pub struct Downloader {
+    client: reqwest::blocking::Client,
+    options: DownloadOptions,
+    /// When each host may next be sent a request
+    hosts: Mutex<HashMap<String, Instant>>,
+    /// When the bandwidth cap lets the next chunk be read
+    next_read: Mutex<Option<Instant>>,
+    prefetched: Mutex<Prefetched>,
+    ready: Condvar,
+}
+
+impl Downloader {
+    pub fn new(options: DownloadOptions) -> Result<Downloader, String> {
+        if options.concurrency == 0 {
+            return Err("Download concurrency must be at least 1".to_string());
+        }
+        if options.bytes_per_sec == Some(0) {
+            return Err("The download bandwidth limit must be above 0".to_string());
+        }
+        let client = reqwest::blocking::Client::builder()
+            .timeout(DOWNLOAD_TIMEOUT)
+            .user_agent(concat!("9ladies/", env!("CARGO_PKG_VERSION")))
+            .build()
+            .map_err(|e| format!("Failed to create the download client: {}", e))?;
+        Ok(Downloader {
+            client,
+            options,
+            hosts: Mutex::new(HashMap::new()),
+            next_read: Mutex::new(None),
+            prefetched: Mutex::new(Prefetched::default()),
+            ready: Condvar::new(),
+        })
+    }
+
+    pub fn options(&self) -> &DownloadOptions {
+        &self.options
+    }
+
+    /// Start fetching the URLs among `inputs` in order, on `concurrency` threads that keep a
+    /// few downloads ahead of `take`. The threads end once the downloader is dropped.
+    pub fn prefetch(self: &Arc<Self>, inputs: &[String]) {
+        let urls: Arc<Vec<String>> = Arc::new(inputs.iter().filter(|input| is_url(input)).cloned().collect());
+        if urls.is_empty() {
+            return;
+        }
+        let next = Arc::new(AtomicUsize::new(0));
+        let ahead = self.options.concurrency * 2;
+        for _ in 0..self.options.concurrency.min(urls.len()) {
+            let (downloader, urls, next) = (Arc::downgrade(self), Arc::clone(&urls), Arc::clone(&next));
+            thread::spawn(move || prefetch_urls(downloader, &urls, &next, ahead));
+        }
+    }
+
+    /// The image at `url`: fetched ahead if it was, once it arrives, or downloaded now.
+    pub fn take(&self, url: &str) -> Result<Vec<u8>, String> {
+        let mut prefetched = self.lock_prefetched();
+        prefetched.taken += 1;
+        self.ready.notify_all();
+        loop {
+            if let Some(result) = prefetched.results.remove(url) {
+                return result;
+            }
+            if !prefetched.in_flight.contains(url) {
+                prefetched.claimed.insert(url.to_string());
+                break;
+            }
+            prefetched = self.ready.wait(prefetched).unwrap_or_else(|e| e.into_inner());
+        }
+        drop(prefetched);
+        self.download(url)
+    }
+
+    fn lock_prefetched(&self) -> MutexGuard<'_, Prefetched> {
+        self.prefetched.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Download `url` now, within the limits.
+    pub fn download(&self, url: &str) -> Result<Vec<u8>, String> {
+        let parsed = reqwest::Url::parse(url).map_err(|e| format!("Invalid URL '{}': {}", url, e))?;
+        if let Some(host) = parsed.host_str() {
+            self.wait_for_host(host);
+        }
+        let started = Instant::now();
+        let mut response = self
+            .client
+            .get(parsed)
+            .send()
+            .map_err(|e| format!("Failed to download '{}': {}", url, e))?;
+        if !response.status().is_success() {
+            return Err(format!("Failed to download '{}': HTTP {}", url, response.status()));
+        }
+        if let (Some(max), Some(length)) = (self.options.max_bytes, response.content_length()) {
+            if length > max {
+                return Err(too_big(url, max));
+            }
+        }
+
+        let mut data = Vec::with_capacity(response.content_length().unwrap_or_default().min(64 << 20) as usize);
+        let mut chunk = vec![0; CHUNK_BYTES];
+        loop {
+            let read = response
+                .read(&mut chunk)
+                .map_err(|e| format!("Failed to download '{}': {}", url, e))?;
+            if read == 0 {
+                break;
+            }
+            self.wait_for_bandwidth(read);
+            data.extend_from_slice(&chunk[..read]);
+            // Servers don't always say how big the image is, or say so truthfully
+            if self.options.max_bytes.is_some_and(|max| data.len() as u64 > max) {
+                return Err(too_big(url, self.options.max_bytes.unwrap_or_default()));
+            }
+        }
+        debug!(url, bytes = data.len(), elapsed_ms = started.elapsed().as_millis() as u64, "Downloaded");
+        Ok(data)
+    }
+
+    /// Wait until `host` may be sent another request, and book the slot after it.
+    fn wait_for_host(&self, host: &str) {
+        if self.options.host_delay.is_zero() {
+            return;
+        }
+        let now = Instant::now();
+        let at = {
+            let mut hosts = self.hosts.lock().unwrap_or_else(|e| e.into_inner());
+            let at = hosts.get(host).map_or(now, |&next| next.max(now));
+            hosts.insert(host.to_string(), at + self.options.host_delay);
+            at
+        };
+        thread::sleep(at - now);
+    }
+
+    /// Wait until the bandwidth cap allows for `bytes` just read, booking the time they take.
+    fn wait_for_bandwidth(&self, bytes: usize) {
+        let Some(rate) = self.options.bytes_per_sec else {
+            return;
+        };
+        let now = Instant::now();
+        let at = {
+            let mut next = self.next_read.lock().unwrap_or_else(|e| e.into_inner());
+            let at = next.map_or(now, |next| next.max(now));
+            *next = Some(at + Duration::from_secs_f64(bytes as f64 / rate as f64));
+            at
+        };
+        thread::sleep(at - now);
+    }
+}
+
+/// A fetcher thread: take the next URL, wait until the batch is near enough to it, and
+/// download it unless the batch got there first.
+fn prefetch_urls(downloader: Weak<Downloader>, urls: &[String], next: &AtomicUsize, ahead: usize) {
+    loop {
+        let index = next.fetch_add(1, Ordering::Relaxed);
+        let Some(url) = urls.get(index) else {
+            return;
+        };
+        // Held only for a step at a time, so the downloader can be dropped while this waits
+        let downloader = loop {
+            let Some(downloader) = downloader.upgrade() else {
+                return;
+            };
+            let mut prefetched = downloader.lock_prefetched();
+            if index < prefetched.taken + ahead {
+                if prefetched.claimed.remove(url) || prefetched.results.contains_key(url) {
+                    break None;
+                }
+                prefetched.in_flight.insert(url.clone());
+                drop(prefetched);
+                break Some(downloader);
+            }
+            let _ = downloader.ready.wait_timeout(prefetched, WAIT_STEP);
+        };
+        let Some(downloader) = downloader else {
+            continue;
+        };
+        let result = downloader.download(url);
+        let mut prefetched = downloader.lock_prefetched();
+        prefetched.in_flight.remove(url);
+        prefetched.results.insert(url.clone(), result);
+        downloader.ready.notify_all();
+    }
+}
+
+fn too_big(url: &str, max: u64) -> String {
+    format!("'{}' is over the download limit of {:.1} MB", url, max as f64 / 1_000_000.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+
+    /// Serve `body` to every request on a local port, counting the requests.
+    fn serve(body: Vec<u8>, content_length: bool) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&requests);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    line.clear();
+                }
+                counter.fetch_add(1, Ordering::SeqCst);
+                let header = match content_length {
+                    true => format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", body.len()),
+                    false => "HTTP/1.1 200 OK\r\nConnection: close\r\n\r\n".to_string(),
+                };
+                let _ = stream.write_all(header.as_bytes()).and_then(|_| stream.write_all(&body));
+            }
+        });
+        (url, requests)
+    }
+
+    #[test]
+    fn test_is_url() {
+        assert!(is_url("https://example.com/a.jpg"));
+        assert!(is_url("HTTP://example.com/a.jpg"));
+        assert!(!is_url("photos/http.jpg"));
+        assert!(!is_url("ftp://example.com/a.jpg"));
+    }
+
+    #[test]
+    fn test_size_cap() {
+        let (url, _) = serve(vec![7; 5000], true);
+        let capped = |max_bytes| {
+            Downloader::new(DownloadOptions {
+                max_bytes,
+                ..DownloadOptions::default()
+            })
+            .unwrap()
+        };
+        assert_eq!(capped(Some(5000)).download(&format!("{}/a.jpg", url)).unwrap().len(), 5000);
+        assert!(capped(Some(4999)).download(&format!("{}/a.jpg", url)).unwrap_err().contains("over the download limit"));
+
+        // Without a Content-Length the cap applies as the body is read
+        let (url, _) = serve(vec![7; 50_000], false);
+        assert!(capped(Some(20_000)).download(&format!("{}/a.jpg", url)).unwrap_err().contains("over the download limit"));
+    }
+
+    #[test]
+    fn test_host_delay_and_bandwidth() {
+        let (url, _) = serve(vec![7; 100], true);
+        let downloader = Downloader::new(DownloadOptions {
+            host_delay: Duration::from_millis(300),
+            ..DownloadOptions::default()
+        })
+        .unwrap();
+        let started = Instant::now();
+        downloader.download(&format!("{}/a.jpg", url)).unwrap();
+        downloader.download(&format!("{}/b.jpg", url)).unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(300));
+
+        let (url, _) = serve(vec![7; 40_000], true);
+        let downloader = Downloader::new(DownloadOptions {
+            bytes_per_sec: Some(100_000),
+            ..DownloadOptions::default()
+        })
+        .unwrap();
+        let started = Instant::now();
+        downloader.download(&format!("{}/a.jpg", url)).unwrap();
+        // Only the last chunk read can arrive without waiting for the ones before it
+        assert!(started.elapsed() >= Duration::from_millis(200), "{:?}", started.elapsed());
+    }
+
+    #[test]
+    fn test_prefetched_downloads_are_taken_once() {
+        let (url, requests) = serve(vec![7; 100], true);
+        let inputs: Vec<String> = (0..6).map(|i| format!("{}/{}.jpg", url, i)).collect();
+        let downloader = Arc::new(
+            Downloader::new(DownloadOptions {
+                concurrency: 2,
+                ..DownloadOptions::default()
+            })
+            .unwrap(),
+        );
+        downloader.prefetch(&inputs);
+        for input in &inputs {
+            assert_eq!(downloader.take(input).unwrap().len(), 100);
+        }
+        assert_eq!(requests.load(Ordering::SeqCst), 6);
+        assert!(downloader.take("http://127.0.0.1:9/missing.jpg").unwrap_err().contains("Failed to download"));
+    }
+}
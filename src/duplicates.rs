@@ -0,0 +1,260 @@
+//! Finding duplicate and near-duplicate photos for `9ladies duplicates`: a perceptual hash
+//! of each image groups lookalikes cheaply, and the model can be asked to confirm each pair
+//! shows the same scene before anything is suggested for deletion.
+
+use std::io::Cursor;
+
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, ImageFormat, RgbImage};
+use serde::Serialize;
+
+use crate::backend::parse_model_content;
+use crate::prompt::PromptConfig;
+
+/// Height of each half of a side-by-side comparison image
+const COMPARE_HEIGHT: u32 = 512;
+
+/// The question asked about a side-by-side pair with `--confirm`.
+pub const CONFIRM_PROMPT: &str = "The left and right halves of this image are two separate photos. \
+Are they the same scene: the same subject and place at about the same moment, possibly cropped, resized, \
+re-edited or taken a second apart? Reply with only a JSON object: {\"same\": true or false, \"reason\": \"<a few words>\"}";
+
+pub fn confirm_config() -> PromptConfig {
+    PromptConfig {
+        system: "You compare photos to find duplicates in a photo library.".to_string(),
+        prompt: CONFIRM_PROMPT.to_string(),
+        temperature: 0.0,
+        model: None,
+        schema: None,
+        seed: None,
+        think: None,
+        schema_via: None,
+    }
+}
+
+/// A 64-bit difference hash: each bit says whether a pixel of a 9x8 grayscale thumbnail is
+/// brighter than its right-hand neighbour. Resizing, recompression and small edits change
+/// few bits.
+pub fn perceptual_hash(image: &DynamicImage) -> u64 {
+    let small = image.resize_exact(9, 8, FilterType::Triangle).to_luma8();
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            hash <<= 1;
+            if small.get_pixel(x, y)[0] > small.get_pixel(x + 1, y)[0] {
+                hash |= 1;
+            }
+        }
+    }
+    hash
+}
+
+/// Bits that differ between two hashes, 0 to 64.
+pub fn distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+/// A photo that was hashed, with what's needed to pick which copy to keep.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Photo {
+    pub file: String,
+    pub hash: u64,
+    pub width: u32,
+    pub height: u32,
+    pub bytes: u64,
+}
+
+impl Photo {
+    /// Decode and hash an image.
+    pub fn read(file: &str, image_data: &[u8]) -> Result<Photo, String> {
+        let image = image::load_from_memory(image_data).map_err(|e| format!("Failed to decode '{}': {}", file, e))?;
+        Ok(Photo {
+            file: file.to_string(),
+            hash: perceptual_hash(&image),
+            width: image.width(),
+            height: image.height(),
+            bytes: image_data.len() as u64,
+        })
+    }
+}
+
+/// Groups of photos whose hashes are within `threshold` bits of another in the group, as
+/// indices into `photos`, each with at least two members and in input order.
+pub fn cluster(photos: &[Photo], threshold: u32) -> Vec<Vec<usize>> {
+    let mut parent: Vec<usize> = (0..photos.len()).collect();
+    fn root(parent: &mut [usize], mut i: usize) -> usize {
+        while parent[i] != i {
+            parent[i] = parent[parent[i]];
+            i = parent[i];
+        }
+        i
+    }
+
+    for i in 0..photos.len() {
+        for j in i + 1..photos.len() {
+            if distance(photos[i].hash, photos[j].hash) <= threshold {
+                let (a, b) = (root(&mut parent, i), root(&mut parent, j));
+                parent[a.max(b)] = a.min(b);
+            }
+        }
+    }
+
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    let mut group_of = vec![usize::MAX; photos.len()];
+    for i in 0..photos.len() {
+        let r = root(&mut parent, i);
+        if group_of[r] == usize::MAX {
+            group_of[r] = groups.len();
+            groups.push(Vec::new());
+        }
+        groups[group_of[r]].push(i);
+    }
+    groups.retain(|group| group.len() > 1);
+    groups
+}
+
+/// The photo to keep from a group: the most pixels, then the largest file, then the first.
+pub fn best(photos: &[Photo], group: &[usize]) -> usize {
+    *group
+        .iter()
+        .min_by_key(|&&i| {
+            let photo = &photos[i];
+            (std::cmp::Reverse(photo.width as u64 * photo.height as u64), std::cmp::Reverse(photo.bytes), i)
+        })
+        .expect("groups are not empty")
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Suggestion {
+    Keep,
+    Discard,
+}
+
+/// One photo in a duplicate group.
+#[derive(Debug, Serialize)]
+pub struct GroupMember {
+    pub file: String,
+    pub width: u32,
+    pub height: u32,
+    pub bytes: u64,
+    /// Hash bits that differ from the photo to keep
+    pub distance: u32,
+    pub suggestion: Suggestion,
+    /// Why the model thinks this is the same scene as the photo to keep, with `--confirm`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+/// A group of duplicates, the photo to keep first.
+#[derive(Debug, Serialize)]
+pub struct DuplicateGroup {
+    pub group: usize,
+    /// Whether the model confirmed each member shows the same scene
+    pub confirmed: bool,
+    pub photos: Vec<GroupMember>,
+}
+
+/// The two images scaled to the same height and placed side by side, as a JPEG, so a
+/// single-image model can compare them.
+pub fn side_by_side(left: &[u8], right: &[u8]) -> Result<Vec<u8>, String> {
+    let scaled = |data: &[u8]| -> Result<RgbImage, String> {
+        let image = image::load_from_memory(data).map_err(|e| format!("Failed to decode image to compare: {}", e))?;
+        Ok(image.resize(COMPARE_HEIGHT * 4, COMPARE_HEIGHT, FilterType::Triangle).to_rgb8())
+    };
+    let (left, right) = (scaled(left)?, scaled(right)?);
+    // A white gap, so the halves read as two photos
+    let gap = COMPARE_HEIGHT / 32;
+    let mut canvas = RgbImage::from_pixel(
+        left.width() + gap + right.width(),
+        left.height().max(right.height()),
+        image::Rgb([255, 255, 255]),
+    );
+    imageops::replace(&mut canvas, &left, 0, 0);
+    imageops::replace(&mut canvas, &right, (left.width() + gap) as i64, 0);
+
+    let mut jpeg = Vec::new();
+    canvas
+        .write_to(&mut Cursor::new(&mut jpeg), ImageFormat::Jpeg)
+        .map_err(|e| format!("Failed to encode comparison image: {}", e))?;
+    Ok(jpeg)
+}
+
+/// The model's answer to `CONFIRM_PROMPT`: whether it's the same scene, and why.
+pub fn parse_confirmation(reply: &str) -> Result<(bool, String), String> {
+    let json = parse_model_content(reply.trim().to_string());
+    let same = match json.get("same") {
+        Some(serde_json::Value::Bool(same)) => *same,
+        _ => return Err(format!("Expected {{\"same\": true or false}}, got: {}", reply.trim())),
+    };
+    let reason = json.get("reason").and_then(|r| r.as_str()).unwrap_or_default().trim().to_string();
+    Ok((same, reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    fn gradient(width: u32, height: u32, flip: bool) -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, y| {
+            let v = ((x * 255 / width) as u8).wrapping_add((y * 64 / height) as u8);
+            let v = if flip { 255 - v } else { v };
+            Rgb([v, v / 2, 255 - v])
+        }))
+    }
+
+    fn png(image: &DynamicImage) -> Vec<u8> {
+        let mut data = Vec::new();
+        image.write_to(&mut Cursor::new(&mut data), ImageFormat::Png).unwrap();
+        data
+    }
+
+    #[test]
+    fn test_hash_survives_resizing() {
+        let large = gradient(400, 300, false);
+        let small = large.resize(100, 75, FilterType::Triangle);
+        let different = gradient(400, 300, true);
+        assert!(distance(perceptual_hash(&large), perceptual_hash(&small)) <= 4);
+        assert!(distance(perceptual_hash(&large), perceptual_hash(&different)) > 20);
+    }
+
+    #[test]
+    fn test_cluster_and_best() {
+        let photo = |file: &str, hash: u64, width: u32, bytes: u64| Photo {
+            file: file.to_string(),
+            hash,
+            width,
+            height: 100,
+            bytes,
+        };
+        let photos = vec![
+            photo("a.jpg", 0b1111, 100, 10),
+            photo("b.jpg", u64::MAX, 100, 10),
+            photo("c.jpg", 0b0111, 200, 5),
+            photo("d.jpg", 0b0011, 200, 9),
+        ];
+        let groups = cluster(&photos, 1);
+        assert_eq!(groups, vec![vec![0, 2, 3]]);
+        assert_eq!(best(&photos, &groups[0]), 3);
+        assert!(cluster(&photos, 0).is_empty());
+    }
+
+    #[test]
+    fn test_side_by_side() {
+        let left = png(&gradient(200, 100, false));
+        let right = png(&gradient(50, 100, true));
+        let pair = image::load_from_memory(&side_by_side(&left, &right).unwrap()).unwrap();
+        assert_eq!((pair.width(), pair.height()), (1024 + 16 + 256, 512));
+    }
+
+    #[test]
+    fn test_parse_confirmation() {
+        assert_eq!(
+            parse_confirmation(r#"{"same": true, "reason": "same beach, recropped"}"#).unwrap(),
+            (true, "same beach, recropped".to_string())
+        );
+        assert_eq!(parse_confirmation(r#"{"same": false}"#).unwrap(), (false, String::new()));
+        assert!(parse_confirmation("yes").is_err());
+    }
+}
@@ -0,0 +1,207 @@
+//! `daemon --listen`: an HTTP endpoint for queuing images into the daemon's state database,
+//! for requests that don't arrive as files in a watched directory.
+//!
+//! `POST /jobs` takes a path (`{"path": "a.jpg"}`, under `--path-root`) or an upload in any
+//! of the forms `serve`'s `/describe` accepts, saved to `--upload-dir`, and answers with the
+//! job's ID. `GET /jobs/<id>` reports the job's status, and its record once it is done.
+
+use std::fs;
+use std::path::PathBuf;
+use std::sync::mpsc::Sender;
+use std::thread;
+
+use tracing::{info, warn};
+
+use nineladies::images::{detect_image_format, sha256_hex};
+use nineladies::paths;
+use nineladies::store::JobStore;
+
+use crate::serve::{self, error_reply, ImageSource, Reply};
+use crate::shutdown::Shutdown;
+
+/// Where jobs may come from.
+pub struct EnqueueOptions {
+    /// The daemon's state database
+    pub state: String,
+    pub max_upload_bytes: usize,
+    /// Directory that `{"path": ...}` jobs may name files in; paths are refused without it
+    pub path_root: Option<PathBuf>,
+    /// Directory uploads are saved to; uploads are refused without it
+    pub upload_dir: Option<PathBuf>,
+}
+
+/// Answer requests on `listen` on a thread of its own until Ctrl+C, signaling `queued` for
+/// each job queued.
+pub fn spawn(listen: &str, options: EnqueueOptions, queued: Sender<()>, shutdown: Shutdown) -> Result<(), String> {
+    // Its own connection, since the daemon's is busy on the scanning thread
+    let store = JobStore::open(&options.state)?;
+    if let Some(dir) = &options.upload_dir {
+        fs::create_dir_all(dir).map_err(|e| format!("Cannot create --upload-dir '{}': {}", dir.display(), e))?;
+    }
+    let address = serve::listen_address(listen);
+    let server = tiny_http::Server::http(&address).map_err(|e| format!("Cannot listen on {}: {}", address, e))?;
+    info!("Taking jobs on http://{} (POST /jobs, GET /jobs/<id>)", address);
+
+    thread::spawn(move || {
+        while !shutdown.requested() {
+            match server.recv_timeout(serve::POLL_INTERVAL) {
+                Ok(Some(request)) => handle(&store, &options, &queued, request),
+                Ok(None) => {}
+                Err(e) => warn!("Failed to accept a request: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+fn handle(store: &JobStore, options: &EnqueueOptions, queued: &Sender<()>, mut request: tiny_http::Request) {
+    let method = request.method().as_str().to_string();
+    let url = request.url().to_string();
+    let content_type = request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv("Content-Type"))
+        .map(|h| h.value.as_str().to_string());
+
+    let (status, body) = match serve::read_body(&mut request, options.max_upload_bytes) {
+        Ok(body) => route(store, options, queued, &method, &url, content_type.as_deref(), body),
+        Err(e) => error_reply(400, format!("Failed to read the request body: {}", e)),
+    };
+    info!(method = method.as_str(), url = url.as_str(), status, "Request handled");
+
+    let response = tiny_http::Response::from_string(body)
+        .with_status_code(status)
+        .with_header(tiny_http::Header::from_bytes("Content-Type", "application/json").expect("valid header"));
+    if let Err(e) = request.respond(response) {
+        warn!("Failed to send the response to {} {}: {}", method, url, e);
+    }
+}
+
+/// Answer one request. `body` is `None` when it was larger than the upload limit.
+fn route(
+    store: &JobStore,
+    options: &EnqueueOptions,
+    queued: &Sender<()>,
+    method: &str,
+    url: &str,
+    content_type: Option<&str>,
+    body: Option<Vec<u8>>,
+) -> Reply {
+    let path = url.split('?').next().unwrap_or_default();
+    match (method, path) {
+        ("POST", "/jobs") => {
+            let Some(body) = body else {
+                return error_reply(413, format!("Uploads are limited to {} bytes", options.max_upload_bytes));
+            };
+            let file = match serve::parse_describe_body(url, content_type, body) {
+                Ok(source) => file_to_queue(options, source),
+                Err(e) => Err(error_reply(400, e)),
+            };
+            match file.and_then(|file| store.submit(&file).map(|id| (id, file)).map_err(|e| error_reply(500, e))) {
+                Ok((id, file)) => {
+                    let _ = queued.send(());
+                    (202, serde_json::json!({"id": id, "file": file, "status": "pending"}).to_string())
+                }
+                Err(reply) => reply,
+            }
+        }
+        (_, "/jobs") => error_reply(405, "Use POST for /jobs"),
+        ("GET", _) if path.starts_with("/jobs/") => match path["/jobs/".len()..].parse::<i64>() {
+            Ok(id) => match store.job(id) {
+                Ok(Some(job)) => (200, serde_json::to_string(&job).expect("jobs serialize")),
+                Ok(None) => error_reply(404, format!("No job {}", id)),
+                Err(e) => error_reply(500, e),
+            },
+            Err(_) => error_reply(400, format!("Invalid job ID '{}'", &path["/jobs/".len()..])),
+        },
+        ("GET", "/healthz") => (200, serde_json::json!({"status": "ok"}).to_string()),
+        _ => error_reply(404, format!("No such endpoint: {}", path)),
+    }
+}
+
+/// The file a job names: the path as the store keys it, or where the upload was saved.
+fn file_to_queue(options: &EnqueueOptions, source: ImageSource) -> Result<String, Reply> {
+    match source {
+        ImageSource::Path(path) => {
+            let resolved = serve::resolve_path(&path, options.path_root.as_deref())?;
+            Ok(paths::normalize(&resolved.to_string_lossy()))
+        }
+        ImageSource::Upload { name, data } => {
+            let Some(dir) = &options.upload_dir else {
+                return Err(error_reply(403, "Uploads are disabled (start the daemon with --upload-dir)"));
+            };
+            if detect_image_format(&data).is_none() {
+                return Err(error_reply(400, format!("'{}' is not a supported image", name)));
+            }
+            // Named by content as well, so uploads with the same name don't replace each other
+            let name = PathBuf::from(&name)
+                .file_name()
+                .map_or_else(|| "upload".to_string(), |name| name.to_string_lossy().into_owned());
+            let path = dir.join(format!("{}-{}", &sha256_hex(&data)[..12], name));
+            fs::write(&path, &data)
+                .map_err(|e| error_reply(500, format!("Failed to save the upload to '{}': {}", path.display(), e)))?;
+            Ok(paths::normalize(&path.to_string_lossy()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nineladies::store::JobStatus;
+    use std::sync::mpsc;
+
+    fn options(upload_dir: Option<PathBuf>) -> EnqueueOptions {
+        EnqueueOptions {
+            state: String::new(),
+            max_upload_bytes: 1_000_000,
+            path_root: Some(PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")),
+            upload_dir,
+        }
+    }
+
+    fn json(reply: &Reply) -> serde_json::Value {
+        serde_json::from_str(&reply.1).unwrap()
+    }
+
+    #[test]
+    fn test_queue_a_path_and_look_it_up() {
+        let store = JobStore::in_memory().unwrap();
+        let (queued, wake) = mpsc::channel();
+        let body = br#"{"path": "red.png"}"#.to_vec();
+        let reply = route(&store, &options(None), &queued, "POST", "/jobs", Some("application/json"), Some(body));
+        assert_eq!(reply.0, 202, "{}", reply.1);
+        assert!(wake.try_recv().is_ok());
+        let id = json(&reply)["id"].as_i64().unwrap();
+        assert!(json(&reply)["file"].as_str().unwrap().ends_with("red.png"));
+
+        let job = route(&store, &options(None), &queued, "GET", &format!("/jobs/{}", id), None, None);
+        assert_eq!(job.0, 200);
+        assert_eq!(json(&job)["status"], JobStatus::Pending.as_str());
+        assert_eq!(route(&store, &options(None), &queued, "GET", "/jobs/999", None, None).0, 404);
+        assert_eq!(route(&store, &options(None), &queued, "GET", "/jobs/abc", None, None).0, 400);
+    }
+
+    #[test]
+    fn test_uploads_are_saved_to_the_upload_dir() {
+        let store = JobStore::in_memory().unwrap();
+        let (queued, _wake) = mpsc::channel();
+        let png = fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/red.png")).unwrap();
+        let upload = |options: &EnqueueOptions, data: &[u8]| {
+            route(&store, options, &queued, "POST", "/jobs?name=../cat.png", Some("image/png"), Some(data.to_vec()))
+        };
+        assert_eq!(upload(&options(None), &png).0, 403);
+
+        let dir = std::env::temp_dir().join(format!("nineladies_enqueue_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let reply = upload(&options(Some(dir.clone())), &png);
+        assert_eq!(reply.0, 202, "{}", reply.1);
+        let file = PathBuf::from(json(&reply)["file"].as_str().unwrap());
+        assert_eq!(file.parent(), Some(dir.as_path()));
+        assert!(file.file_name().unwrap().to_string_lossy().ends_with("-cat.png"));
+        assert_eq!(fs::read(&file).unwrap(), png);
+
+        assert_eq!(upload(&options(Some(dir.clone())), b"not an image").0, 400);
+        fs::remove_dir_all(dir).ok();
+    }
+}
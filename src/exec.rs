@@ -0,0 +1,133 @@
+//! `--exec`: a user command run after each image, for side effects 9ladies doesn't do
+//! itself (thumbnails, database updates, notifications).
+//!
+//! Placeholders are replaced with shell-quoted values, and the same values are passed in
+//! `NINELADIES_*` environment variables for scripts that would rather not parse arguments.
+//! Braces that aren't placeholders are left alone, so shell syntax like `${HOME}` works.
+
+use std::process::{Command, Stdio};
+
+use nineladies::OutputRecord;
+
+/// The image a hook runs for, and how it went.
+pub struct HookEvent<'a> {
+    pub file: &'a str,
+    /// Present when the model described the image, even if a later step failed
+    pub record: Option<&'a OutputRecord>,
+    /// The first error for the image, if any
+    pub error: Option<&'a str>,
+}
+
+impl HookEvent<'_> {
+    /// Placeholder name, environment variable and value for each field.
+    fn fields(&self) -> [(&'static str, &'static str, String); 6] {
+        let json = |value: Option<String>| value.unwrap_or_else(|| "null".to_string());
+        [
+            ("file", "NINELADIES_FILE", self.file.to_string()),
+            ("path", "NINELADIES_PATH", self.record.map_or(self.file, |r| r.current_path()).to_string()),
+            ("status", "NINELADIES_STATUS", if self.error.is_some() { "error" } else { "ok" }.to_string()),
+            ("error", "NINELADIES_ERROR", self.error.unwrap_or_default().to_string()),
+            ("response_json", "NINELADIES_RESPONSE", json(self.record.map(|r| r.response.to_string()))),
+            (
+                "record_json",
+                "NINELADIES_RECORD",
+                json(self.record.map(|r| serde_json::to_string(r).expect("records serialize"))),
+            ),
+        ]
+    }
+}
+
+/// Quote a value so the shell passes it through as one word.
+#[cfg(unix)]
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+#[cfg(not(unix))]
+fn shell_quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('"', "\\\""))
+}
+
+/// The command line with placeholders filled in.
+pub fn render(command: &str, event: &HookEvent) -> String {
+    let mut rendered = command.to_string();
+    for (name, _, value) in event.fields() {
+        rendered = rendered.replace(&format!("{{{}}}", name), &shell_quote(&value));
+    }
+    rendered
+}
+
+/// Run the hook through the shell and wait for it. Its stdout goes to stderr, so the JSONL
+/// on stdout stays clean.
+pub fn run(command: &str, event: &HookEvent) -> Result<(), String> {
+    let rendered = render(command, event);
+    let mut shell = if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+    shell.arg(&rendered).stdin(Stdio::null()).stdout(std::io::stderr());
+    for (_, env, value) in event.fields() {
+        shell.env(env, value);
+    }
+
+    let status = shell
+        .status()
+        .map_err(|e| format!("Failed to run --exec hook for '{}': {}", event.file, e))?;
+    if !status.success() {
+        return Err(format!("--exec hook for '{}' exited with {}", event.file, status));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_render_quotes_values() {
+        let mut record = OutputRecord::new("it's.jpg", "", serde_json::json!({"subject": "a cat"}));
+        record.renamed_to = Some("cat.jpg".to_string());
+        let event = HookEvent {
+            file: "it's.jpg",
+            record: Some(&record),
+            error: None,
+        };
+        assert_eq!(
+            render("thumb {file} {path} {status} {response_json} ${HOME}", &event),
+            r#"thumb 'it'\''s.jpg' 'cat.jpg' 'ok' '{"subject":"a cat"}' ${HOME}"#
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_render_failure() {
+        let event = HookEvent {
+            file: "a.jpg",
+            record: None,
+            error: Some("Server returned 500"),
+        };
+        assert_eq!(
+            render("notify {status} {error} {response_json}", &event),
+            "notify 'error' 'Server returned 500' 'null'"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_reports_exit_status() {
+        let event = HookEvent {
+            file: "a.jpg",
+            record: None,
+            error: None,
+        };
+        assert!(run("test \"$NINELADIES_FILE\" = a.jpg", &event).is_ok());
+        let e = run("exit 3", &event).unwrap_err();
+        assert!(e.contains("exited with"), "{}", e);
+    }
+}
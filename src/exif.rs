@@ -0,0 +1,176 @@
+//! `--exif`: when and where a photo was taken, and with what, from its EXIF metadata.
+
+use std::io::Cursor;
+
+use ::exif::{In, Tag, Value};
+use serde::{Deserialize, Serialize};
+
+/// The EXIF fields 9ladies uses. Missing fields are left out.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ExifData {
+    /// When the photo was taken, e.g. `2023-07-14T18:22:05+01:00` (the offset only when recorded)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub taken_at: Option<String>,
+    /// Make and model, e.g. `Canon EOS R6`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub camera: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gps: Option<Gps>,
+    /// EXIF orientation, 1 to 8; 6 and 8 mean the image is stored rotated 90 degrees
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub orientation: Option<u32>,
+}
+
+/// Decimal degrees, negative for south and west.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Gps {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+impl ExifData {
+    /// Read the fields from an image's EXIF block. `None` when it has none of them.
+    pub fn read(image_data: &[u8]) -> Option<ExifData> {
+        let exif = ::exif::Reader::new().read_from_container(&mut Cursor::new(image_data)).ok()?;
+        let text = |tag: Tag| -> Option<String> {
+            match &exif.get_field(tag, In::PRIMARY)?.value {
+                Value::Ascii(parts) => {
+                    let text = String::from_utf8_lossy(parts.first()?).trim().to_string();
+                    (!text.is_empty()).then_some(text)
+                }
+                _ => None,
+            }
+        };
+
+        let taken_at = text(Tag::DateTimeOriginal).or_else(|| text(Tag::DateTime)).and_then(|taken| {
+            let taken = parse_exif_datetime(&taken)?;
+            Some(match text(Tag::OffsetTimeOriginal) {
+                Some(offset) => format!("{}{}", taken, offset),
+                None => taken,
+            })
+        });
+        let camera = match (text(Tag::Make), text(Tag::Model)) {
+            // Models usually repeat the make ("Canon" / "Canon EOS R6")
+            (Some(make), Some(model)) if model.starts_with(&make) => Some(model),
+            (Some(make), Some(model)) => Some(format!("{} {}", make, model)),
+            (make, model) => make.or(model),
+        };
+        let coordinate = |tag: Tag, reference: Tag, negative: &str| -> Option<f64> {
+            let Value::Rational(parts) = &exif.get_field(tag, In::PRIMARY)?.value else {
+                return None;
+            };
+            let [degrees, minutes, seconds] = parts.as_slice() else {
+                return None;
+            };
+            let value = degrees.to_f64() + minutes.to_f64() / 60.0 + seconds.to_f64() / 3600.0;
+            Some(if text(reference).as_deref() == Some(negative) { -value } else { value })
+        };
+        let gps = match (
+            coordinate(Tag::GPSLatitude, Tag::GPSLatitudeRef, "S"),
+            coordinate(Tag::GPSLongitude, Tag::GPSLongitudeRef, "W"),
+        ) {
+            (Some(latitude), Some(longitude)) => Some(Gps {
+                latitude: round6(latitude),
+                longitude: round6(longitude),
+            }),
+            _ => None,
+        };
+        let orientation = exif.get_field(Tag::Orientation, In::PRIMARY).and_then(|f| f.value.get_uint(0));
+
+        let data = ExifData {
+            taken_at,
+            camera,
+            gps,
+            orientation,
+        };
+        (data != ExifData::default()).then_some(data)
+    }
+
+    /// The date the photo was taken, `YYYY-MM-DD`.
+    pub fn date(&self) -> Option<&str> {
+        self.taken_at.as_deref().map(|taken| &taken[..10])
+    }
+
+    /// A one-line summary to add to the question, so descriptions can say when and where.
+    pub fn prompt_context(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(taken_at) = &self.taken_at {
+            parts.push(format!("taken {}", taken_at));
+        }
+        if let Some(camera) = &self.camera {
+            parts.push(format!("with a {}", camera));
+        }
+        if let Some(gps) = &self.gps {
+            parts.push(format!("at latitude {}, longitude {}", gps.latitude, gps.longitude));
+        }
+        format!("Photo metadata: {}.", parts.join(", "))
+    }
+
+    /// A field by name, for `{exif.<field>}` templates: `taken_at`, `date`, `camera`,
+    /// `latitude`, `longitude` or `orientation`.
+    pub fn field(&self, name: &str) -> Option<String> {
+        match name {
+            "taken_at" => self.taken_at.clone(),
+            "date" => self.date().map(str::to_string),
+            "camera" => self.camera.clone(),
+            "latitude" => self.gps.map(|gps| gps.latitude.to_string()),
+            "longitude" => self.gps.map(|gps| gps.longitude.to_string()),
+            "orientation" => self.orientation.map(|o| o.to_string()),
+            _ => None,
+        }
+    }
+}
+
+fn round6(value: f64) -> f64 {
+    (value * 1_000_000.0).round() / 1_000_000.0
+}
+
+/// `2023:07:14 18:22:05` to `2023-07-14T18:22:05`.
+fn parse_exif_datetime(value: &str) -> Option<String> {
+    let (date, time) = value.split_once(' ')?;
+    let date: Vec<&str> = date.split(':').collect();
+    let valid = date.len() == 3
+        && date.iter().all(|part| part.bytes().all(|b| b.is_ascii_digit()))
+        && date[0].len() == 4
+        && time.len() == 8;
+    // Unset dates are written as zeros or blanks
+    (valid && date[0] != "0000").then(|| format!("{}-{}-{}T{}", date[0], date[1], date[2], time))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn fixture(name: &str) -> Vec<u8> {
+        std::fs::read(PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name)).unwrap()
+    }
+
+    #[test]
+    fn test_read_exif_fields() {
+        let exif = ExifData::read(&fixture("exif.jpg")).unwrap();
+        assert_eq!(exif.taken_at.as_deref(), Some("2023-07-14T18:22:05+01:00"));
+        assert_eq!(exif.date(), Some("2023-07-14"));
+        assert_eq!(exif.camera.as_deref(), Some("Canon EOS R6"));
+        assert_eq!(exif.gps, Some(Gps { latitude: 51.5, longitude: -0.125 }));
+        assert_eq!(exif.orientation, Some(6));
+        assert_eq!(exif.field("longitude").as_deref(), Some("-0.125"));
+        assert_eq!(
+            exif.prompt_context(),
+            "Photo metadata: taken 2023-07-14T18:22:05+01:00, with a Canon EOS R6, at latitude 51.5, longitude -0.125."
+        );
+    }
+
+    #[test]
+    fn test_read_without_exif() {
+        assert_eq!(ExifData::read(&fixture("red.jpg")), None);
+        assert_eq!(ExifData::read(&fixture("red.png")), None);
+    }
+
+    #[test]
+    fn test_parse_exif_datetime() {
+        assert_eq!(parse_exif_datetime("2023:07:14 18:22:05").as_deref(), Some("2023-07-14T18:22:05"));
+        assert_eq!(parse_exif_datetime("0000:00:00 00:00:00"), None);
+        assert_eq!(parse_exif_datetime("    :  :     :  :  "), None);
+    }
+}
@@ -0,0 +1,132 @@
+//! `--blur-faces`: finding faces with a local SeetaFace detector and blurring them in the
+//! copy of the image sent to the model, so faces never leave the machine. The record keeps
+//! the original's checksum.
+
+use std::io::Cursor;
+
+use image::{imageops, DynamicImage, ImageFormat, RgbImage};
+use rustface::{ImageData, Model};
+
+/// A face, in pixels.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FaceBox {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// A loaded face detection model.
+#[derive(Clone)]
+pub struct FaceBlur {
+    model: Model,
+}
+
+impl FaceBlur {
+    /// Load a SeetaFace frontal model, e.g. `seeta_fd_frontal_v1.0.bin` from rustface.
+    pub fn load(path: &str) -> Result<FaceBlur, String> {
+        let model = rustface::load_model(path).map_err(|e| format!("Failed to load face model '{}': {}", path, e))?;
+        Ok(FaceBlur { model })
+    }
+
+    /// Faces found in the image.
+    pub fn detect(&self, image: &DynamicImage) -> Vec<FaceBox> {
+        let gray = image.to_luma8();
+        let (width, height) = gray.dimensions();
+        if width == 0 || height == 0 {
+            return Vec::new();
+        }
+        // The detector keeps per-image state, so each image gets its own
+        let mut detector = rustface::create_detector_with_model(self.model.clone());
+        detector.set_min_face_size(20);
+        detector.set_score_thresh(2.0);
+        detector.set_pyramid_scale_factor(0.8);
+        detector.set_slide_window_step(4, 4);
+
+        detector
+            .detect(&ImageData::new(gray.as_raw(), width, height))
+            .iter()
+            .filter_map(|face| {
+                let bbox = face.bbox();
+                clip(bbox.x(), bbox.y(), bbox.width(), bbox.height(), width, height)
+            })
+            .collect()
+    }
+
+    /// A JPEG copy of the image with every face blurred, and how many there were. Images
+    /// without faces are returned as they are.
+    pub fn blur(&self, image_data: &[u8]) -> Result<(Vec<u8>, usize), String> {
+        let image = image::load_from_memory(image_data)
+            .map_err(|e| format!("Failed to decode image for face detection: {}", e))?;
+        let faces = self.detect(&image);
+        if faces.is_empty() {
+            return Ok((image_data.to_vec(), 0));
+        }
+
+        let mut rgb = image.to_rgb8();
+        blur_regions(&mut rgb, &faces);
+        let mut jpeg = Vec::new();
+        rgb.write_to(&mut Cursor::new(&mut jpeg), ImageFormat::Jpeg)
+            .map_err(|e| format!("Failed to encode blurred image: {}", e))?;
+        Ok((jpeg, faces.len()))
+    }
+}
+
+/// A detector box grown by a fifth on each side, to cover hair and chin, and clipped to
+/// the image. `None` if nothing is left.
+fn clip(x: i32, y: i32, width: u32, height: u32, image_width: u32, image_height: u32) -> Option<FaceBox> {
+    let (margin_x, margin_y) = (width as i64 / 5, height as i64 / 5);
+    let x1 = (x as i64 - margin_x).clamp(0, image_width as i64);
+    let y1 = (y as i64 - margin_y).clamp(0, image_height as i64);
+    let x2 = (x as i64 + width as i64 + margin_x).clamp(0, image_width as i64);
+    let y2 = (y as i64 + height as i64 + margin_y).clamp(0, image_height as i64);
+    (x2 > x1 && y2 > y1).then(|| FaceBox {
+        x: x1 as u32,
+        y: y1 as u32,
+        width: (x2 - x1) as u32,
+        height: (y2 - y1) as u32,
+    })
+}
+
+/// Blur each region in place, strongly enough that the face can't be recognized.
+pub fn blur_regions(image: &mut RgbImage, regions: &[FaceBox]) {
+    for region in regions {
+        let sigma = (region.width.max(region.height) as f32 / 4.0).max(4.0);
+        let patch = imageops::crop_imm(image, region.x, region.y, region.width, region.height).to_image();
+        let blurred = imageops::blur(&patch, sigma);
+        imageops::replace(image, &blurred, region.x as i64, region.y as i64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgb;
+
+    #[test]
+    fn test_clip_grows_and_clamps() {
+        assert_eq!(
+            clip(50, 50, 100, 100, 400, 300),
+            Some(FaceBox { x: 30, y: 30, width: 140, height: 140 })
+        );
+        assert_eq!(
+            clip(-10, 250, 100, 100, 400, 300),
+            Some(FaceBox { x: 0, y: 230, width: 110, height: 70 })
+        );
+        assert_eq!(clip(500, 10, 20, 20, 400, 300), None);
+    }
+
+    #[test]
+    fn test_blur_regions_only_touches_regions() {
+        // A checkerboard, so blurring visibly averages the pixels
+        let mut image = RgbImage::from_fn(40, 40, |x, y| {
+            if (x + y) % 2 == 0 { Rgb([255, 255, 255]) } else { Rgb([0, 0, 0]) }
+        });
+        blur_regions(&mut image, &[FaceBox { x: 0, y: 0, width: 20, height: 20 }]);
+
+        let mid = image.get_pixel(10, 10)[0];
+        assert!((64..=192).contains(&mid), "face pixel is still {}", mid);
+        assert_eq!(*image.get_pixel(30, 30), Rgb([255, 255, 255]));
+        assert_eq!(*image.get_pixel(31, 30), Rgb([0, 0, 0]));
+    }
+}
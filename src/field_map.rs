@@ -0,0 +1,131 @@
+//! Renaming and moving response fields into one output schema, so prompts and models that
+//! word their JSON differently can feed the same downstream tables. The mapping is the
+//! `fields` table of the config file, from the field as the model gives it to the field as
+//! records should have it, with dots for nested fields:
+//!
+//! ```toml
+//! [profiles.llava.fields]
+//! desc = "description"
+//! vendor_name = "vendor.name"
+//! "meta.tags" = "tags"
+//! ```
+
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+/// Fields to move, as paths from and to.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(try_from = "BTreeMap<String, String>")]
+pub struct FieldMap {
+    moves: Vec<(Vec<String>, Vec<String>)>,
+}
+
+impl FieldMap {
+    pub fn new(fields: &BTreeMap<String, String>) -> Result<FieldMap, String> {
+        let path = |field: &str| {
+            let path: Vec<String> = field.split('.').map(str::to_string).collect();
+            match path.iter().any(String::is_empty) {
+                true => Err(format!("Invalid field '{}' in the config's fields (use names joined by dots)", field)),
+                false => Ok(path),
+            }
+        };
+        let moves = fields
+            .iter()
+            .map(|(from, to)| Ok((path(from)?, path(to)?)))
+            .collect::<Result<_, String>>()?;
+        Ok(FieldMap { moves })
+    }
+
+    /// `response` with the mapped fields moved. Fields the mapping doesn't name are kept,
+    /// and a response that isn't an object is left as it is.
+    pub fn apply(&self, response: Value) -> Value {
+        let Value::Object(mut object) = response else {
+            return response;
+        };
+        // Everything is taken out before anything is put back, so two fields can swap names
+        let taken: Vec<(&[String], Value)> = self
+            .moves
+            .iter()
+            .filter_map(|(from, to)| take(&mut object, from).map(|value| (to.as_slice(), value)))
+            .collect();
+        for (to, value) in taken {
+            put(&mut object, to, value);
+        }
+        Value::Object(object)
+    }
+}
+
+impl TryFrom<BTreeMap<String, String>> for FieldMap {
+    type Error = String;
+
+    fn try_from(fields: BTreeMap<String, String>) -> Result<Self, Self::Error> {
+        FieldMap::new(&fields)
+    }
+}
+
+/// Remove the value at `path`, and any object it leaves empty.
+fn take(object: &mut Map<String, Value>, path: &[String]) -> Option<Value> {
+    let (first, rest) = path.split_first()?;
+    if rest.is_empty() {
+        return object.shift_remove(first);
+    }
+    let inner = object.get_mut(first)?.as_object_mut()?;
+    let value = take(inner, rest)?;
+    if inner.is_empty() {
+        object.shift_remove(first);
+    }
+    Some(value)
+}
+
+/// Set the value at `path`, making objects on the way; anything in the way that isn't an
+/// object is replaced.
+fn put(object: &mut Map<String, Value>, path: &[String], value: Value) {
+    let Some((first, rest)) = path.split_first() else {
+        return;
+    };
+    if rest.is_empty() {
+        object.insert(first.clone(), value);
+        return;
+    }
+    let inner = object.entry(first.clone()).or_insert_with(|| Value::Object(Map::new()));
+    if !inner.is_object() {
+        *inner = Value::Object(Map::new());
+    }
+    put(inner.as_object_mut().expect("made an object above"), rest, value);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn field_map(fields: &[(&str, &str)]) -> Result<FieldMap, String> {
+        FieldMap::new(&fields.iter().map(|(from, to)| (from.to_string(), to.to_string())).collect())
+    }
+
+    #[test]
+    fn test_apply() {
+        let map = field_map(&[("desc", "description"), ("vendor_name", "vendor.name"), ("meta.tags", "tags")]).unwrap();
+        assert_eq!(
+            map.apply(json!({"desc": "A receipt", "vendor_name": "Aldi", "meta": {"tags": ["food"]}, "total": 2.3})),
+            json!({"description": "A receipt", "vendor": {"name": "Aldi"}, "tags": ["food"], "total": 2.3})
+        );
+        // Missing fields are skipped, and text responses are untouched
+        assert_eq!(map.apply(json!({"total": 1})), json!({"total": 1}));
+        assert_eq!(map.apply(json!("A receipt")), json!("A receipt"));
+    }
+
+    #[test]
+    fn test_fields_can_swap() {
+        let map = field_map(&[("a", "b"), ("b", "a")]).unwrap();
+        assert_eq!(map.apply(json!({"a": 1, "b": 2})), json!({"a": 2, "b": 1}));
+    }
+
+    #[test]
+    fn test_invalid_field() {
+        assert!(field_map(&[("vendor..name", "vendor")]).unwrap_err().contains("'vendor..name'"));
+        assert!(field_map(&[("desc", "")]).is_err());
+    }
+}
@@ -0,0 +1,170 @@
+//! Flattening JSON responses into columns for tabular output (`convert` and the SQL sinks),
+//! so a response of any shape gives columns that can be filtered and sorted.
+//!
+//! Nested objects become dotted keys: `{"vendor": {"name": "Aldi"}}` gives a `vendor.name`
+//! column. Lists are handled by a `ListPolicy`, since no one way suits both a list of tags
+//! and a list of line items.
+
+use clap::ValueEnum;
+use serde_json::Value;
+
+/// What to do with a list in a response.
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum ListPolicy {
+    /// Keep the list as JSON in one column
+    #[default]
+    Json,
+    /// Join the items into one column of text; lists of objects give a column per field,
+    /// with that field of each item joined
+    Join,
+    /// A column per item, numbered from 0: `tags.0`, `tags.1`, `items.0.name`
+    Index,
+}
+
+/// How to flatten responses.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Flatten {
+    pub lists: ListPolicy,
+    /// Put between joined items with `ListPolicy::Join`
+    pub separator: String,
+}
+
+impl Default for Flatten {
+    fn default() -> Self {
+        Flatten {
+            lists: ListPolicy::Json,
+            separator: "; ".to_string(),
+        }
+    }
+}
+
+impl Flatten {
+    /// The columns of `response`, in order, as dotted keys and values that are not objects
+    /// (nor lists, unless they are kept as JSON). A response that isn't an object is one
+    /// `response` column.
+    pub fn fields(&self, response: &Value) -> Vec<(String, Value)> {
+        let mut fields = Vec::new();
+        match response {
+            Value::Object(object) => {
+                for (key, value) in object {
+                    self.push(key.clone(), value, &mut fields);
+                }
+            }
+            other => self.push("response".to_string(), other, &mut fields),
+        }
+        fields
+    }
+
+    fn push(&self, key: String, value: &Value, fields: &mut Vec<(String, Value)>) {
+        match value {
+            Value::Object(object) => {
+                for (field, value) in object {
+                    self.push(format!("{}.{}", key, field), value, fields);
+                }
+            }
+            Value::Array(items) => match self.lists {
+                ListPolicy::Json => fields.push((key, value.clone())),
+                ListPolicy::Index => {
+                    for (i, item) in items.iter().enumerate() {
+                        self.push(format!("{}.{}", key, i), item, fields);
+                    }
+                }
+                ListPolicy::Join => {
+                    // Each item's columns, then each column with the items' values joined;
+                    // an item without it contributes an empty string, so positions line up
+                    let flattened: Vec<Vec<(String, Value)>> = items
+                        .iter()
+                        .map(|item| {
+                            let mut item_fields = Vec::new();
+                            self.push(key.clone(), item, &mut item_fields);
+                            item_fields
+                        })
+                        .collect();
+                    let mut columns: Vec<&String> = Vec::new();
+                    for (column, _) in flattened.iter().flatten() {
+                        if !columns.contains(&column) {
+                            columns.push(column);
+                        }
+                    }
+                    for column in columns {
+                        let joined: Vec<String> = flattened
+                            .iter()
+                            .map(|item_fields| {
+                                item_fields
+                                    .iter()
+                                    .find(|(field, _)| field == column)
+                                    .map(|(_, value)| text(value))
+                                    .unwrap_or_default()
+                            })
+                            .collect();
+                        fields.push((column.clone(), Value::String(joined.join(&self.separator))));
+                    }
+                }
+            },
+            other => fields.push((key, other.clone())),
+        }
+    }
+}
+
+/// A value as text: strings as they are, nothing for null, anything else as JSON.
+pub fn text(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        Value::Null => String::new(),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn response() -> Value {
+        json!({
+            "vendor": {"name": "Aldi", "address": {"city": "Leeds"}},
+            "tags": ["food", "receipt"],
+            "items": [{"name": "milk", "price": 1.1}, {"name": "eggs"}],
+            "total": 2.3
+        })
+    }
+
+    fn keys(fields: &[(String, Value)]) -> Vec<&str> {
+        fields.iter().map(|(key, _)| key.as_str()).collect()
+    }
+
+    #[test]
+    fn test_flatten_keeps_lists_as_json() {
+        let fields = Flatten::default().fields(&response());
+        assert_eq!(keys(&fields), ["vendor.name", "vendor.address.city", "tags", "items", "total"]);
+        assert_eq!(fields[2].1, json!(["food", "receipt"]));
+        assert_eq!(Flatten::default().fields(&json!("A cat")), [("response".to_string(), json!("A cat"))]);
+    }
+
+    #[test]
+    fn test_flatten_joins_lists() {
+        let flatten = Flatten {
+            lists: ListPolicy::Join,
+            ..Flatten::default()
+        };
+        let fields = flatten.fields(&response());
+        assert_eq!(keys(&fields), ["vendor.name", "vendor.address.city", "tags", "items.name", "items.price", "total"]);
+        assert_eq!(fields[2].1, json!("food; receipt"));
+        assert_eq!(fields[3].1, json!("milk; eggs"));
+        assert_eq!(fields[4].1, json!("1.1; "));
+    }
+
+    #[test]
+    fn test_flatten_indexes_lists() {
+        let flatten = Flatten {
+            lists: ListPolicy::Index,
+            ..Flatten::default()
+        };
+        let fields = flatten.fields(&response());
+        assert_eq!(
+            keys(&fields),
+            ["vendor.name", "vendor.address.city", "tags.0", "tags.1", "items.0.name", "items.0.price", "items.1.name", "total"]
+        );
+        assert_eq!(fields[3].1, json!("receipt"));
+    }
+}
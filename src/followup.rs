@@ -0,0 +1,69 @@
+//! `9ladies followup`: a further question about each image in an existing output file, asked
+//! with the answer it got before, for refining or extending a run without redoing it.
+
+use serde_json::Value;
+
+use crate::prompt::PromptConfig;
+
+/// `config` asked about an image with the answer a previous run gave for it.
+pub fn followup_config(config: &PromptConfig, previous: &Value) -> PromptConfig {
+    let previous = match previous {
+        Value::String(text) => text.trim().to_string(),
+        other => serde_json::to_string_pretty(other).expect("JSON values serialize"),
+    };
+    PromptConfig {
+        prompt: format!(
+            "An earlier look at this image gave this answer:\n\n{}\n\nCheck it against the image where it matters.\n\n{}",
+            previous, config.prompt
+        ),
+        ..config.clone()
+    }
+}
+
+/// The record's new response: the follow-up answer, with any fields of an earlier JSON
+/// object answer that it doesn't give again, so a follow-up can add fields or correct some
+/// without repeating the rest.
+pub fn enrich(previous: &Value, answer: Value) -> Value {
+    match (previous, answer) {
+        (Value::Object(previous), Value::Object(answer)) => {
+            let mut merged = previous.clone();
+            merged.extend(answer);
+            Value::Object(merged)
+        }
+        (_, answer) => answer,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_followup_config() {
+        let config = PromptConfig {
+            system: "s".to_string(),
+            prompt: "Is anyone wearing a hat? Reply {\"hats\": <count>}".to_string(),
+            temperature: 0.0,
+            model: None,
+            schema: None,
+            seed: None,
+            think: None,
+            schema_via: None,
+        };
+        let asked = followup_config(&config, &json!({"people": 3}));
+        assert!(asked.prompt.starts_with("An earlier look at this image gave this answer:\n\n{\n  \"people\": 3\n}\n\n"));
+        assert!(asked.prompt.ends_with(&config.prompt));
+        assert!(followup_config(&config, &json!(" Three people. ")).prompt.contains("answer:\n\nThree people.\n\n"));
+    }
+
+    #[test]
+    fn test_enrich() {
+        assert_eq!(
+            enrich(&json!({"people": 3, "setting": "park"}), json!({"people": 4, "hats": 1})),
+            json!({"people": 4, "setting": "park", "hats": 1})
+        );
+        assert_eq!(enrich(&json!("Three people"), json!({"hats": 1})), json!({"hats": 1}));
+        assert_eq!(enrich(&json!({"people": 3}), json!("Four people")), json!("Four people"));
+    }
+}
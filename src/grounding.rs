@@ -0,0 +1,209 @@
+//! Bounding boxes from grounding models (Qwen2.5-VL, Gemini and the like), normalized so
+//! every model's boxes read the same way, and drawn onto copies of the images for checking.
+//!
+//! Boxes are found anywhere in a JSON response, as objects with one of:
+//!
+//! - `bbox_2d` or `bbox`: `[x1, y1, x2, y2]` in pixels (Qwen2.5-VL), or as fractions when
+//!   every value is at most 1
+//! - `box_2d`: `[y1, x1, y2, x2]` scaled to 0–1000 (Gemini)
+//!
+//! and a `label`, `name`, `class` or `text` naming what was found.
+
+use std::io::Cursor;
+use std::path::Path;
+
+use image::{ImageReader, Rgb, RgbImage};
+use serde::{Deserialize, Serialize};
+
+/// Something the model located, with its box as fractions of the image width and height.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Detection {
+    #[serde(default)]
+    pub label: String,
+    /// `[x_min, y_min, x_max, y_max]`, each from 0 to 1
+    #[serde(rename = "box")]
+    pub bbox: [f64; 4],
+}
+
+const LABEL_KEYS: [&str; 4] = ["label", "name", "class", "text"];
+
+/// Width and height of an image from its header, without decoding it.
+pub fn image_dimensions(image_data: &[u8]) -> Result<(u32, u32), String> {
+    ImageReader::new(Cursor::new(image_data))
+        .with_guessed_format()
+        .map_err(|e| e.to_string())?
+        .into_dimensions()
+        .map_err(|e| format!("Cannot read image dimensions: {}", e))
+}
+
+/// Every box in the response, in the order they appear. `width` and `height` are the
+/// image's, for converting pixel coordinates.
+pub fn parse_detections(response: &serde_json::Value, width: u32, height: u32) -> Vec<Detection> {
+    let mut detections = Vec::new();
+    collect(response, width as f64, height as f64, &mut detections);
+    detections
+}
+
+fn collect(value: &serde_json::Value, width: f64, height: f64, detections: &mut Vec<Detection>) {
+    match value {
+        serde_json::Value::Array(items) => {
+            for item in items {
+                collect(item, width, height, detections);
+            }
+        }
+        serde_json::Value::Object(object) => {
+            if let Some(detection) = detection(object, width, height) {
+                detections.push(detection);
+                return;
+            }
+            for value in object.values() {
+                collect(value, width, height, detections);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn detection(object: &serde_json::Map<String, serde_json::Value>, width: f64, height: f64) -> Option<Detection> {
+    let coords = |key: &str| -> Option<[f64; 4]> {
+        let values = object.get(key)?.as_array()?;
+        let numbers: Vec<f64> = values.iter().filter_map(|v| v.as_f64()).collect();
+        <[f64; 4]>::try_from(numbers).ok().filter(|_| values.len() == 4)
+    };
+
+    let [x1, y1, x2, y2] = if let Some(coords) = coords("bbox_2d").or_else(|| coords("bbox")) {
+        if coords.iter().all(|&v| v <= 1.0) {
+            coords
+        } else {
+            [coords[0] / width, coords[1] / height, coords[2] / width, coords[3] / height]
+        }
+    } else if let Some([y1, x1, y2, x2]) = coords("box_2d") {
+        [x1 / 1000.0, y1 / 1000.0, x2 / 1000.0, y2 / 1000.0]
+    } else {
+        return None;
+    };
+
+    let label = LABEL_KEYS
+        .iter()
+        .find_map(|key| object.get(*key)?.as_str())
+        .unwrap_or_default()
+        .to_string();
+    let clamp = |v: f64| v.clamp(0.0, 1.0);
+    Some(Detection {
+        label,
+        bbox: [clamp(x1.min(x2)), clamp(y1.min(y2)), clamp(x1.max(x2)), clamp(y1.max(y2))],
+    })
+}
+
+/// Outline colors, picked per label so the same kind of thing gets the same color.
+const COLORS: [Rgb<u8>; 6] = [
+    Rgb([230, 25, 75]),
+    Rgb([60, 180, 75]),
+    Rgb([0, 130, 200]),
+    Rgb([245, 130, 48]),
+    Rgb([145, 30, 180]),
+    Rgb([255, 225, 25]),
+];
+
+fn label_color(label: &str) -> Rgb<u8> {
+    let hash = label.bytes().fold(0usize, |hash, b| hash.wrapping_mul(31).wrapping_add(b as usize));
+    COLORS[hash % COLORS.len()]
+}
+
+/// A copy of the image with each detection outlined.
+pub fn annotate(image_data: &[u8], detections: &[Detection]) -> Result<RgbImage, String> {
+    let mut image = image::load_from_memory(image_data)
+        .map_err(|e| format!("Failed to decode image for annotation: {}", e))?
+        .to_rgb8();
+    let (width, height) = image.dimensions();
+    if width == 0 || height == 0 {
+        return Ok(image);
+    }
+    let thickness = (width.max(height) / 300).max(2);
+
+    for detection in detections {
+        let color = label_color(&detection.label);
+        let [x1, y1, x2, y2] = detection.bbox;
+        let to_px = |v: f64, size: u32| ((v * size as f64).round() as u32).min(size - 1);
+        let (x1, x2) = (to_px(x1, width), to_px(x2, width));
+        let (y1, y2) = (to_px(y1, height), to_px(y2, height));
+
+        for t in 0..thickness {
+            for x in x1..=x2 {
+                image.put_pixel(x, (y1 + t).min(height - 1), color);
+                image.put_pixel(x, y2.saturating_sub(t), color);
+            }
+            for y in y1..=y2 {
+                image.put_pixel((x1 + t).min(width - 1), y, color);
+                image.put_pixel(x2.saturating_sub(t), y, color);
+            }
+        }
+    }
+    Ok(image)
+}
+
+/// Draw the detections onto a copy of the image at `file` and save it in `dir` as a PNG
+/// named after the image, returning where it went.
+pub fn write_annotated(file: &Path, image_data: &[u8], detections: &[Detection], dir: &Path) -> Result<String, String> {
+    let annotated = annotate(image_data, detections)?;
+    let stem = file.file_stem().and_then(|s| s.to_str()).unwrap_or("image");
+    std::fs::create_dir_all(dir).map_err(|e| format!("Cannot create '{}': {}", dir.display(), e))?;
+    let target = dir.join(format!("{}.png", stem));
+    annotated
+        .save(&target)
+        .map_err(|e| format!("Failed to write annotated image '{}': {}", target.display(), e))?;
+    Ok(target.to_string_lossy().into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_qwen_pixel_boxes() {
+        let response = json!([
+            {"bbox_2d": [10, 20, 110, 70], "label": "cat"},
+            {"bbox_2d": [0, 0, 200, 100], "label": "sofa"}
+        ]);
+        let detections = parse_detections(&response, 200, 100);
+        assert_eq!(
+            detections,
+            vec![
+                Detection { label: "cat".to_string(), bbox: [0.05, 0.2, 0.55, 0.7] },
+                Detection { label: "sofa".to_string(), bbox: [0.0, 0.0, 1.0, 1.0] },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_gemini_boxes_and_nested_objects() {
+        let response = json!({"objects": [{"box_2d": [100, 250, 500, 750], "name": "dog"}], "count": 1});
+        let detections = parse_detections(&response, 640, 480);
+        assert_eq!(detections, vec![Detection { label: "dog".to_string(), bbox: [0.25, 0.1, 0.75, 0.5] }]);
+    }
+
+    #[test]
+    fn test_parse_fractional_and_reversed_boxes() {
+        let response = json!({"bbox": [0.9, 0.8, 0.1, 0.2]});
+        let detections = parse_detections(&response, 640, 480);
+        assert_eq!(detections, vec![Detection { label: String::new(), bbox: [0.1, 0.2, 0.9, 0.8] }]);
+    }
+
+    #[test]
+    fn test_parse_ignores_non_boxes() {
+        assert!(parse_detections(&json!("A cat on a sofa"), 10, 10).is_empty());
+        assert!(parse_detections(&json!({"bbox": [1, 2, 3]}), 10, 10).is_empty());
+        assert!(parse_detections(&json!({"bbox_2d": ["a", 1, 2, 3]}), 10, 10).is_empty());
+    }
+
+    #[test]
+    fn test_annotate_draws_outline() {
+        let data = std::fs::read(std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/red.png")).unwrap();
+        let (width, height) = image_dimensions(&data).unwrap();
+        let detection = Detection { label: "x".to_string(), bbox: [0.0, 0.0, 1.0, 1.0] };
+        let annotated = annotate(&data, &[detection]).unwrap();
+        assert_eq!(annotated.dimensions(), (width, height));
+        assert_eq!(*annotated.get_pixel(0, 0), label_color("x"));
+    }
+}
@@ -0,0 +1,150 @@
+//! `--image-stats`: cheap measurements of each image for filtering results and for giving
+//! the model some context.
+
+use image::imageops::FilterType;
+use image::DynamicImage;
+use serde::{Deserialize, Serialize};
+
+/// Colors reported in `dominant_colors`
+const DOMINANT_COLORS: usize = 3;
+/// Longest side images are shrunk to before measuring sharpness, so big photos and small
+/// thumbnails score on a similar scale
+const SHARPNESS_SIZE: u32 = 512;
+
+/// Basic statistics for one image.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ImageStats {
+    pub width: u32,
+    pub height: u32,
+    pub megapixels: f64,
+    /// The most common colors, most common first, as `#rrggbb`
+    pub dominant_colors: Vec<String>,
+    /// Variance of the Laplacian: higher is sharper, and under about 100 is usually blurry
+    pub sharpness: f64,
+}
+
+impl ImageStats {
+    /// Decode and measure an image.
+    pub fn measure(image_data: &[u8]) -> Result<ImageStats, String> {
+        let image = image::load_from_memory(image_data)
+            .map_err(|e| format!("Failed to decode image for statistics: {}", e))?;
+        let (width, height) = (image.width(), image.height());
+        Ok(ImageStats {
+            width,
+            height,
+            megapixels: ((width as f64 * height as f64) / 10_000.0).round() / 100.0,
+            dominant_colors: dominant_colors(&image),
+            sharpness: sharpness(&image),
+        })
+    }
+
+    /// A one-line summary to add to the question.
+    pub fn prompt_context(&self) -> String {
+        format!(
+            "Image details: {}x{} pixels, dominant colors {}, sharpness {:.0} (under 100 is usually blurry).",
+            self.width,
+            self.height,
+            self.dominant_colors.join(", "),
+            self.sharpness
+        )
+    }
+}
+
+/// The average color of each of the most populated buckets, with each channel split into
+/// four levels. Pixels are sampled rather than blended, so edges don't invent colors.
+fn dominant_colors(image: &DynamicImage) -> Vec<String> {
+    let small = image.resize(64, 64, FilterType::Nearest).to_rgb8();
+    let mut buckets = [(0u32, [0u64; 3]); 64];
+    for pixel in small.pixels() {
+        let [r, g, b] = pixel.0;
+        let bucket = &mut buckets[(r as usize >> 6) << 4 | (g as usize >> 6) << 2 | b as usize >> 6];
+        bucket.0 += 1;
+        for (sum, value) in bucket.1.iter_mut().zip(pixel.0) {
+            *sum += value as u64;
+        }
+    }
+
+    let mut buckets: Vec<_> = buckets.iter().filter(|(count, _)| *count > 0).collect();
+    buckets.sort_by_key(|(count, _)| std::cmp::Reverse(*count));
+    buckets
+        .iter()
+        .take(DOMINANT_COLORS)
+        .map(|(count, sums)| {
+            let [r, g, b] = sums.map(|sum| (sum / *count as u64) as u8);
+            format!("#{:02x}{:02x}{:02x}", r, g, b)
+        })
+        .collect()
+}
+
+/// Variance of the 4-neighbour Laplacian over the grayscale image.
+fn sharpness(image: &DynamicImage) -> f64 {
+    let gray = if image.width().max(image.height()) > SHARPNESS_SIZE {
+        image.resize(SHARPNESS_SIZE, SHARPNESS_SIZE, FilterType::Triangle).to_luma8()
+    } else {
+        image.to_luma8()
+    };
+    let (width, height) = gray.dimensions();
+    if width < 3 || height < 3 {
+        return 0.0;
+    }
+
+    let at = |x: u32, y: u32| gray.get_pixel(x, y)[0] as f64;
+    let mut sum = 0.0;
+    let mut sum_sq = 0.0;
+    for y in 1..height - 1 {
+        for x in 1..width - 1 {
+            let laplacian = at(x - 1, y) + at(x + 1, y) + at(x, y - 1) + at(x, y + 1) - 4.0 * at(x, y);
+            sum += laplacian;
+            sum_sq += laplacian * laplacian;
+        }
+    }
+    let n = ((width - 2) * (height - 2)) as f64;
+    let mean = sum / n;
+    ((sum_sq / n - mean * mean) * 10.0).round() / 10.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{imageops, ImageFormat, Rgb, RgbImage};
+    use std::io::Cursor;
+
+    fn png(image: RgbImage) -> Vec<u8> {
+        let mut data = Vec::new();
+        image.write_to(&mut Cursor::new(&mut data), ImageFormat::Png).unwrap();
+        data
+    }
+
+    #[test]
+    fn test_measure_flat_image() {
+        let stats = ImageStats::measure(&png(RgbImage::from_pixel(200, 100, Rgb([200, 10, 10])))).unwrap();
+        assert_eq!((stats.width, stats.height, stats.megapixels), (200, 100, 0.02));
+        assert_eq!(stats.dominant_colors, vec!["#c80a0a"]);
+        assert_eq!(stats.sharpness, 0.0);
+    }
+
+    #[test]
+    fn test_sharp_edges_score_higher() {
+        let stripes = RgbImage::from_fn(100, 100, |x, _| if x % 4 < 2 { Rgb([255, 255, 255]) } else { Rgb([0, 0, 0]) });
+        let blurred = imageops::blur(&stripes, 3.0);
+        let sharp = ImageStats::measure(&png(stripes)).unwrap();
+        let soft = ImageStats::measure(&png(blurred)).unwrap();
+        assert!(sharp.sharpness > soft.sharpness * 10.0, "{} vs {}", sharp.sharpness, soft.sharpness);
+        assert_eq!(sharp.dominant_colors.len(), 2);
+    }
+
+    #[test]
+    fn test_prompt_context() {
+        let stats = ImageStats {
+            width: 640,
+            height: 480,
+            megapixels: 0.31,
+            dominant_colors: vec!["#ffffff".to_string(), "#000000".to_string()],
+            sharpness: 412.34,
+        };
+        assert_eq!(
+            stats.prompt_context(),
+            "Image details: 640x480 pixels, dominant colors #ffffff, #000000, sharpness 412 (under 100 is usually blurry)."
+        );
+    }
+}
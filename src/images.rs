@@ -0,0 +1,294 @@
+//! Reading and checking image files.
+
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::grounding::image_dimensions;
+use crate::paths::long_path;
+
+/// The image format from its magic bytes, as used in a MIME type (`jpeg`, `png`, `gif`, `webp`).
+pub fn detect_image_format(data: &[u8]) -> Option<&'static str> {
+    if data.len() < 12 {
+        return None;
+    }
+
+    // JPEG: starts with FF D8 FF
+    if data.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        return Some("jpeg");
+    }
+
+    // PNG: starts with 89 50 4E 47 0D 0A 1A 0A
+    if data.starts_with(&[0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A]) {
+        return Some("png");
+    }
+
+    // GIF: starts with GIF87a or GIF89a
+    if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+        return Some("gif");
+    }
+
+    // WebP: starts with RIFF....WEBP
+    if data.starts_with(b"RIFF") && &data[8..12] == b"WEBP" {
+        return Some("webp");
+    }
+
+    None
+}
+
+/// The size and format of an image, read from its header without decoding it.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ImageInfo {
+    pub width: u32,
+    pub height: u32,
+    /// As from `detect_image_format`
+    pub format: String,
+    /// File size
+    pub bytes: u64,
+}
+
+impl ImageInfo {
+    pub fn read(image_data: &[u8]) -> Result<ImageInfo, String> {
+        let format = detect_image_format(image_data).ok_or("Cannot tell the image's format")?;
+        let (width, height) = image_dimensions(image_data)?;
+        Ok(ImageInfo {
+            width,
+            height,
+            format: format.to_string(),
+            bytes: image_data.len() as u64,
+        })
+    }
+}
+
+/// Hex-encoded SHA-256 of the image bytes, so records can be joined by content
+/// rather than by a path that may later change.
+pub fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Like `sha256_hex` for a file, read in pieces, for files too big to hold in memory.
+pub fn sha256_file(path: &Path) -> Result<String, String> {
+    let mut file = fs::File::open(long_path(path)).map_err(|e| format!("Cannot read file '{}': {}", path.display(), e))?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher).map_err(|e| format!("Cannot read file '{}': {}", path.display(), e))?;
+    Ok(hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Whether a path has the extension of a supported image format, for picking images out of
+/// a directory without reading every file.
+pub fn has_image_extension(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ["jpg", "jpeg", "png", "gif", "webp"].contains(&ext.to_ascii_lowercase().as_str()))
+}
+
+/// Read a file and check that it is a supported image, returning its bytes.
+pub fn validate_image_file(path: &Path) -> Result<Vec<u8>, String> {
+    let long = long_path(path);
+    if !long.exists() {
+        return Err(format!("File not found: {}", path.display()));
+    }
+
+    let data = fs::read(&long).map_err(|e| format!("Cannot read file '{}': {}", path.display(), e))?;
+
+    if detect_image_format(&data).is_none() {
+        return Err(format!(
+            "Not a valid image format (expected JPEG, PNG, WebP, or GIF): {}",
+            path.display()
+        ));
+    }
+
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+    use crate::prompt::load_prompt_config;
+    use std::path::PathBuf;
+
+    fn fixtures_dir() -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")
+    }
+
+    #[test]
+    fn test_has_image_extension() {
+        assert!(has_image_extension(Path::new("photos/IMG_001.JPG")));
+        assert!(has_image_extension(Path::new("a.webp")));
+        assert!(!has_image_extension(Path::new("notes.txt")));
+        assert!(!has_image_extension(Path::new("jpg")));
+    }
+
+    #[test]
+    fn test_detect_png() {
+        let data = fs::read(fixtures_dir().join("red.png")).unwrap();
+        assert_eq!(detect_image_format(&data), Some("png"));
+    }
+
+    #[test]
+    fn test_detect_jpeg() {
+        let data = fs::read(fixtures_dir().join("red.jpg")).unwrap();
+        assert_eq!(detect_image_format(&data), Some("jpeg"));
+    }
+
+    #[test]
+    fn test_detect_gif() {
+        let data = fs::read(fixtures_dir().join("red.gif")).unwrap();
+        assert_eq!(detect_image_format(&data), Some("gif"));
+    }
+
+    #[test]
+    fn test_detect_webp() {
+        let data = fs::read(fixtures_dir().join("red.webp")).unwrap();
+        assert_eq!(detect_image_format(&data), Some("webp"));
+    }
+
+    #[test]
+    fn test_image_info() {
+        let data = fs::read(fixtures_dir().join("red.png")).unwrap();
+        let info = ImageInfo::read(&data).unwrap();
+        assert_eq!((info.width, info.height, info.format.as_str()), (1, 1, "png"));
+        assert_eq!(info.bytes, data.len() as u64);
+        assert!(ImageInfo::read(b"This is not an image file").is_err());
+    }
+
+    #[test]
+    fn test_detect_invalid_format() {
+        let data = b"This is not an image file";
+        assert_eq!(detect_image_format(data), None);
+    }
+
+    #[test]
+    fn test_detect_too_short() {
+        let data = b"short";
+        assert_eq!(detect_image_format(data), None);
+    }
+
+    #[test]
+    fn test_detect_empty() {
+        let data: &[u8] = &[];
+        assert_eq!(detect_image_format(data), None);
+    }
+
+    #[test]
+    fn test_validate_png_image() {
+        let path = fixtures_dir().join("red.png");
+        let result = validate_image_file(&path);
+
+        assert!(result.is_ok());
+        let data = result.unwrap();
+        assert!(!data.is_empty());
+        assert_eq!(detect_image_format(&data), Some("png"));
+    }
+
+    #[test]
+    fn test_validate_jpeg_image() {
+        let path = fixtures_dir().join("red.jpg");
+        let result = validate_image_file(&path);
+
+        assert!(result.is_ok());
+        let data = result.unwrap();
+        assert_eq!(detect_image_format(&data), Some("jpeg"));
+    }
+
+    #[test]
+    fn test_validate_gif_image() {
+        let path = fixtures_dir().join("red.gif");
+        let result = validate_image_file(&path);
+
+        assert!(result.is_ok());
+        let data = result.unwrap();
+        assert_eq!(detect_image_format(&data), Some("gif"));
+    }
+
+    #[test]
+    fn test_validate_webp_image() {
+        let path = fixtures_dir().join("red.webp");
+        let result = validate_image_file(&path);
+
+        assert!(result.is_ok());
+        let data = result.unwrap();
+        assert_eq!(detect_image_format(&data), Some("webp"));
+    }
+
+    #[test]
+    fn test_validate_nonexistent_file() {
+        let path = Path::new("/nonexistent/image.png");
+        let result = validate_image_file(path);
+
+        assert!(result.is_err());
+        assert!(result.unwrap_err().contains("File not found"));
+    }
+
+    #[test]
+    fn test_validate_non_image_file() {
+        let path = fixtures_dir().join("not-an-image.txt");
+        let result = validate_image_file(&path);
+
+        assert!(result.is_err());
+        assert!(result.unwrap_err().contains("Not a valid image format"));
+    }
+
+    #[test]
+    fn test_sha256_hex() {
+        assert_eq!(
+            sha256_hex(b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            sha256_hex(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+
+        let data = fs::read(fixtures_dir().join("red.png")).unwrap();
+        assert_eq!(sha256_hex(&data).len(), 64);
+        assert_eq!(sha256_hex(&data), sha256_hex(&data));
+    }
+
+    #[test]
+    fn test_full_validation_pipeline_with_known_images() {
+        let fixtures = fixtures_dir();
+        let prompt_path = fixtures.join("test-prompt.json");
+
+        // Load config
+        let config = load_prompt_config(prompt_path.to_str().unwrap()).unwrap();
+        assert_eq!(config.system, "You are a test assistant.");
+
+        // Validate all test images
+        let test_images = ["red.png", "red.jpg", "red.gif", "red.webp"];
+
+        for image_name in test_images {
+            let image_path = fixtures.join(image_name);
+            let data = validate_image_file(&image_path).unwrap();
+            let format = detect_image_format(&data).unwrap();
+
+            // Verify we can encode to base64 for API call
+            let encoded = BASE64.encode(&data);
+            assert!(!encoded.is_empty());
+
+            // Verify data URL format
+            let data_url = format!("data:image/{};base64,{}", format, encoded);
+            assert!(data_url.starts_with("data:image/"));
+        }
+    }
+
+    #[test]
+    fn test_dry_run_detects_invalid_files() {
+        let fixtures = fixtures_dir();
+
+        // Valid image should pass
+        let valid_path = fixtures.join("red.png");
+        assert!(validate_image_file(&valid_path).is_ok());
+
+        // Invalid image should fail
+        let invalid_path = fixtures.join("not-an-image.txt");
+        assert!(validate_image_file(&invalid_path).is_err());
+
+        // Nonexistent file should fail
+        let missing_path = fixtures.join("does-not-exist.png");
+        assert!(validate_image_file(&missing_path).is_err());
+    }
+}
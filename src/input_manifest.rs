@@ -0,0 +1,207 @@
+//! `--input-manifest`: the files a run is meant to describe and the SHA-256 of each, for
+//! archival and regulated pipelines that have to show exactly what was processed. The inputs
+//! are checked against it before the run starts (the same files, as many as it says, none
+//! missing or changed), and each file again as it is read, so one altered mid-run fails
+//! rather than being described.
+//!
+//! A manifest is `sha256sum` output, so the `inputs.sha256` in a `--repro-bundle` archive
+//! works as one, or JSON that states the count as well:
+//!
+//! ```json
+//! {"count": 2, "files": [{"path": "scans/001.jpg", "sha256": "9f86d0..."}, {"path": "scans/002.jpg", "sha256": "60303a..."}]}
+//! ```
+
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::download;
+use crate::images::sha256_file;
+use crate::paths;
+
+/// The expected inputs of a run.
+#[derive(Debug, PartialEq)]
+pub struct InputManifest {
+    /// Each file's SHA-256, by path as `key` writes it
+    files: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JsonManifest {
+    count: usize,
+    files: Vec<JsonEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JsonEntry {
+    path: String,
+    sha256: String,
+}
+
+impl InputManifest {
+    /// Read a manifest file.
+    pub fn load(path: &Path) -> Result<InputManifest, String> {
+        let text =
+            fs::read_to_string(path).map_err(|e| format!("Failed to read input manifest '{}': {}", path.display(), e))?;
+        InputManifest::parse(&text).map_err(|e| format!("Invalid input manifest '{}': {}", path.display(), e))
+    }
+
+    /// Parse a manifest as JSON or `sha256sum` lines.
+    pub fn parse(text: &str) -> Result<InputManifest, String> {
+        let entries: Vec<(String, String)> = match text.trim_start().starts_with('{') {
+            true => {
+                let manifest: JsonManifest = serde_json::from_str(text).map_err(|e| e.to_string())?;
+                if manifest.count != manifest.files.len() {
+                    return Err(format!("it says {} files but lists {}", manifest.count, manifest.files.len()));
+                }
+                manifest.files.into_iter().map(|entry| (entry.path, entry.sha256)).collect()
+            }
+            false => text
+                .lines()
+                .enumerate()
+                .filter(|(_, line)| !line.trim().is_empty() && !line.starts_with('#'))
+                .map(|(i, line)| {
+                    // `sha256sum -b` marks each path with a `*`
+                    let (hash, path) = line
+                        .split_once("  ")
+                        .or_else(|| line.split_once(" *"))
+                        .ok_or_else(|| format!("line {} is not '<sha256>  <path>'", i + 1))?;
+                    Ok((path.to_string(), hash.to_string()))
+                })
+                .collect::<Result<_, String>>()?,
+        };
+
+        let mut files = BTreeMap::new();
+        for (path, hash) in entries {
+            let hash = hash.trim().to_ascii_lowercase();
+            if hash.len() != 64 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(format!("'{}' has an invalid SHA-256 '{}'", path, hash));
+            }
+            if files.insert(key(&path), hash).is_some() {
+                return Err(format!("'{}' is listed twice", path));
+            }
+        }
+        Ok(InputManifest { files })
+    }
+
+    /// How many files the manifest lists.
+    pub fn count(&self) -> usize {
+        self.files.len()
+    }
+
+    /// Check the inputs of a run against the manifest, hashing each file. Returns every
+    /// problem found, none if they match. URL inputs are only checked once downloaded.
+    pub fn verify_inputs(&self, inputs: &[String]) -> Vec<String> {
+        let mut problems = Vec::new();
+        if inputs.len() != self.count() {
+            problems.push(format!("Expected {} inputs, as the manifest lists, but got {}", self.count(), inputs.len()));
+        }
+        let mut seen = HashSet::new();
+        for input in inputs {
+            let key = key(input);
+            let Some(expected) = self.files.get(&key) else {
+                problems.push(format!("'{}' is not in the input manifest", input));
+                continue;
+            };
+            if !seen.insert(key) {
+                problems.push(format!("'{}' is given more than once", input));
+                continue;
+            }
+            if download::is_url(input) {
+                continue;
+            }
+            match sha256_file(Path::new(input)) {
+                Ok(actual) if &actual != expected => problems.push(changed(input, &actual, expected)),
+                Ok(_) => {}
+                Err(e) => problems.push(format!("'{}' is missing or unreadable: {}", input, e)),
+            }
+        }
+        for path in self.files.keys().filter(|path| !seen.contains(*path)) {
+            problems.push(format!("'{}' is in the input manifest but not among the inputs", path));
+        }
+        problems
+    }
+
+    /// Check the SHA-256 of `file` as it was read for describing.
+    pub fn check(&self, file: &str, sha256: &str) -> Result<(), String> {
+        match self.files.get(&key(file)) {
+            Some(expected) if expected == sha256 => Ok(()),
+            Some(expected) => Err(changed(file, sha256, expected)),
+            None => Err(format!("'{}' is not in the input manifest", file)),
+        }
+    }
+}
+
+/// A path as the manifest is looked up by: without `\\?\` or a leading `./`.
+fn key(path: &str) -> String {
+    let path = paths::normalize(path.trim());
+    match path.strip_prefix("./") {
+        Some(rest) => rest.to_string(),
+        None => path,
+    }
+}
+
+fn changed(file: &str, actual: &str, expected: &str) -> String {
+    format!("'{}' has changed: its SHA-256 is {}, the input manifest has {}", file, actual, expected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::images::sha256_hex;
+
+    #[test]
+    fn test_parse() {
+        let (a, b) = (sha256_hex(b"a"), sha256_hex(b"b"));
+        let text = format!("# inputs\n{}  ./scans/a.png\n{} *scans/b.png\n", a, b.to_uppercase());
+        let manifest = InputManifest::parse(&text).unwrap();
+        assert_eq!(manifest.count(), 2);
+        assert_eq!(manifest.check("scans/a.png", &a), Ok(()));
+        assert_eq!(manifest.check("./scans/b.png", &b), Ok(()));
+        assert!(manifest.check("scans/a.png", &b).unwrap_err().contains("has changed"));
+        assert!(manifest.check("scans/c.png", &a).unwrap_err().contains("not in the input manifest"));
+
+        let json = format!(r#"{{"count": 2, "files": [{{"path": "scans/a.png", "sha256": "{}"}}, {{"path": "scans/b.png", "sha256": "{}"}}]}}"#, a, b);
+        assert_eq!(InputManifest::parse(&json).unwrap(), manifest);
+        let short = json.replace("\"count\": 2", "\"count\": 3");
+        assert!(InputManifest::parse(&short).unwrap_err().contains("says 3 files but lists 2"));
+        assert!(InputManifest::parse(&format!("{}  a.png\n{}  ./a.png\n", a, b)).unwrap_err().contains("listed twice"));
+        assert!(InputManifest::parse("abc  a.png").unwrap_err().contains("invalid SHA-256"));
+    }
+
+    #[test]
+    fn test_verify_inputs() {
+        let dir = std::env::temp_dir().join(format!("nineladies_input_manifest_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = |name: &str| dir.join(name).to_string_lossy().to_string();
+        fs::write(file("a.png"), b"a").unwrap();
+        fs::write(file("b.png"), b"changed").unwrap();
+        let text = format!(
+            "{}  {}\n{}  {}\n{}  {}\n",
+            sha256_hex(b"a"),
+            file("a.png"),
+            sha256_hex(b"b"),
+            file("b.png"),
+            sha256_hex(b"c"),
+            file("c.png")
+        );
+        let manifest = InputManifest::parse(&text).unwrap();
+
+        let all = [file("a.png"), file("b.png"), file("c.png")];
+        let problems = manifest.verify_inputs(&all);
+        assert_eq!(problems.len(), 2, "{:?}", problems);
+        assert!(problems[0].contains("b.png' has changed"));
+        assert!(problems[1].contains("c.png' is missing"));
+
+        let problems = manifest.verify_inputs(&[file("a.png"), file("a.png"), file("d.png")]);
+        assert!(problems.iter().any(|problem| problem.contains("given more than once")), "{:?}", problems);
+        assert!(problems.iter().any(|problem| problem.contains("d.png' is not in the input manifest")));
+        assert!(problems.iter().any(|problem| problem.contains("b.png' is in the input manifest but not among")));
+        assert_eq!(manifest.verify_inputs(&all[..1]).first().unwrap(), "Expected 3 inputs, as the manifest lists, but got 1");
+        fs::remove_dir_all(dir).ok();
+    }
+}
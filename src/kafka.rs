@@ -0,0 +1,122 @@
+//! `9ladies kafka`: consume jobs from a Kafka topic and publish a result for each to another.
+//!
+//! Delivery is at-least-once: a job's offset is committed only after its result has been
+//! acknowledged by the brokers, so a crash between the two replays the job rather than
+//! losing it. While the model server is unreachable the consumer keeps retrying the same
+//! job instead of failing it. See `queue` for the message formats.
+
+use std::time::Duration;
+
+use kafka::consumer::{Consumer, FetchOffset, GroupOffsetStorage};
+use kafka::producer::{Producer, Record, RequiredAcks};
+use tracing::{debug, info, warn};
+
+use nineladies::BatchRunner;
+
+use crate::queue::{self, Outcome};
+use crate::shutdown::Shutdown;
+
+/// Where to read jobs from and write results to.
+pub struct KafkaOptions {
+    pub brokers: Vec<String>,
+    pub input_topic: String,
+    pub output_topic: String,
+    /// Consumer group whose committed offsets track progress through the input topic
+    pub group: String,
+}
+
+/// What happens to a job message once it has been tried.
+#[derive(Debug, PartialEq)]
+enum Next {
+    /// Publish the result, then mark the message consumed
+    Publish(String),
+    /// The model server is down: leave the message uncommitted and try it again after a wait
+    Wait(String),
+}
+
+/// What to do with a job message whose attempt ended in `outcome`. A job that fails is
+/// published as an error rather than retried, since Kafka can't put it back on the topic.
+fn after_attempt(outcome: Outcome) -> Next {
+    match outcome {
+        Outcome::Described(result) | Outcome::Rejected(result) | Outcome::Failed(result) => Next::Publish(result),
+        Outcome::Unreachable(e) => Next::Wait(e),
+    }
+}
+
+/// Consume jobs until a shutdown is requested.
+pub fn consume(runner: &BatchRunner, options: &KafkaOptions, shutdown: &Shutdown) -> Result<(), String> {
+    let mut consumer = Consumer::from_hosts(options.brokers.clone())
+        .with_topic(options.input_topic.clone())
+        .with_group(options.group.clone())
+        .with_fallback_offset(FetchOffset::Earliest)
+        .with_offset_storage(Some(GroupOffsetStorage::Kafka))
+        .create()
+        .map_err(|e| format!("Failed to connect to Kafka at {}: {}", options.brokers.join(","), e))?;
+    let mut producer = Producer::from_hosts(options.brokers.clone())
+        .with_required_acks(RequiredAcks::All)
+        .with_ack_timeout(Duration::from_secs(10))
+        .create()
+        .map_err(|e| format!("Failed to create Kafka producer: {}", e))?;
+    info!(
+        input = options.input_topic.as_str(),
+        output = options.output_topic.as_str(),
+        group = options.group.as_str(),
+        "Consuming jobs from Kafka"
+    );
+
+    while !shutdown.requested() {
+        let sets = consumer.poll().map_err(|e| format!("Failed to fetch jobs from Kafka: {}", e))?;
+        for set in sets.iter() {
+            for message in set.messages() {
+                let key = (!message.key.is_empty()).then_some(message.key);
+                let result = loop {
+                    match after_attempt(queue::process(runner, message.value, key)) {
+                        Next::Publish(result) => break result,
+                        Next::Wait(e) => {
+                            warn!("{}; retrying in {}s", e, queue::RETRY_DELAY.as_secs());
+                            if !shutdown.wait(queue::RETRY_DELAY) {
+                                // Leave this job uncommitted, so it is redelivered
+                                return commit(&mut consumer);
+                            }
+                        }
+                    }
+                };
+
+                producer
+                    .send(&Record::from_key_value(&options.output_topic, message.key, result))
+                    .map_err(|e| format!("Failed to publish result to '{}': {}", options.output_topic, e))?;
+                consumer
+                    .consume_message(set.topic(), set.partition(), message.offset)
+                    .map_err(|e| format!("Failed to mark job as consumed: {}", e))?;
+                debug!(partition = set.partition(), offset = message.offset, "Job finished");
+
+                if shutdown.requested() {
+                    return commit(&mut consumer);
+                }
+            }
+        }
+        commit(&mut consumer)?;
+    }
+    Ok(())
+}
+
+fn commit(consumer: &mut Consumer) -> Result<(), String> {
+    consumer
+        .commit_consumed()
+        .map_err(|e| format!("Failed to commit Kafka offsets: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_after_attempt() {
+        let publish = |result: &str| Next::Publish(result.to_string());
+        assert_eq!(after_attempt(Outcome::Described("ok".into())), publish("ok"));
+        assert_eq!(after_attempt(Outcome::Rejected("bad".into())), publish("bad"));
+        assert_eq!(after_attempt(Outcome::Failed("err".into())), publish("err"));
+        // Nothing is published or committed, so the job is tried again
+        assert_eq!(after_attempt(Outcome::Unreachable("down".into())), Next::Wait("down".to_string()));
+    }
+}
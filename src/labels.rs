@@ -0,0 +1,230 @@
+//! `--labels`: classifying each image into one of a fixed set of labels instead of
+//! describing it in free text.
+//!
+//! The labels go into the question and, as a JSON schema, to the server, which holds the
+//! reply to them where it supports structured output. Replies are checked against the set
+//! either way, so a server that ignores the schema can't slip in a label of its own.
+
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::prompt::PromptConfig;
+
+/// A label and how confident the model is in it, for `--top-k`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LabelScore {
+    pub label: String,
+    /// 0 to 1, as the model judged it; not a calibrated probability
+    pub confidence: f64,
+}
+
+/// The model's answer: one label from the set, and with `--top-k` the likeliest few.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Classification {
+    pub label: String,
+    pub top: Option<Vec<LabelScore>>,
+}
+
+/// The taxonomy to classify into.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LabelSet {
+    labels: Vec<String>,
+}
+
+impl LabelSet {
+    /// At least two distinct labels, compared ignoring case.
+    pub fn new(labels: Vec<String>) -> Result<LabelSet, String> {
+        let mut distinct: Vec<String> = Vec::new();
+        for label in labels.iter().map(|label| label.trim()).filter(|label| !label.is_empty()) {
+            if !distinct.iter().any(|seen| seen.eq_ignore_ascii_case(label)) {
+                distinct.push(label.to_string());
+            }
+        }
+        if distinct.len() < 2 {
+            return Err("--labels needs at least two different labels".to_string());
+        }
+        Ok(LabelSet { labels: distinct })
+    }
+
+    /// Labels from a file with one per line (blank lines and `#` comments are skipped), or
+    /// from a comma-separated list.
+    pub fn parse(spec: &str) -> Result<LabelSet, String> {
+        if Path::new(spec).is_file() {
+            let content = fs::read_to_string(spec).map_err(|e| format!("Failed to read labels file '{}': {}", spec, e))?;
+            let lines = content.lines().filter(|line| !line.trim_start().starts_with('#'));
+            return LabelSet::new(lines.map(str::to_string).collect());
+        }
+        LabelSet::new(spec.split(',').map(str::to_string).collect())
+    }
+
+    pub fn labels(&self) -> &[String] {
+        &self.labels
+    }
+
+    /// The label in the set matching `label`, ignoring case and surrounding punctuation.
+    pub fn find(&self, label: &str) -> Option<&str> {
+        let label = label.trim_matches(|c: char| c.is_whitespace() || matches!(c, '"' | '\'' | '.' | '`'));
+        self.labels.iter().find(|known| known.eq_ignore_ascii_case(label)).map(String::as_str)
+    }
+
+    /// The reply format: `{"label": ...}`, plus a `top` list of scored labels with `top_k`.
+    pub fn schema(&self, top_k: Option<usize>) -> Value {
+        let label = json!({"type": "string", "enum": self.labels});
+        match top_k {
+            None => json!({
+                "type": "object",
+                "properties": {"label": label},
+                "required": ["label"],
+                "additionalProperties": false,
+            }),
+            Some(k) => json!({
+                "type": "object",
+                "properties": {
+                    "label": label,
+                    "top": {
+                        "type": "array",
+                        "items": {
+                            "type": "object",
+                            "properties": {
+                                "label": label,
+                                "confidence": {"type": "number", "minimum": 0, "maximum": 1},
+                            },
+                            "required": ["label", "confidence"],
+                            "additionalProperties": false,
+                        },
+                        "minItems": 1,
+                        "maxItems": k,
+                    },
+                },
+                "required": ["label", "top"],
+                "additionalProperties": false,
+            }),
+        }
+    }
+
+    /// `config` with the labels added to the question and the reply constrained to them.
+    pub fn constrain(&self, config: PromptConfig, top_k: Option<usize>) -> PromptConfig {
+        let choices = self.labels.iter().map(|label| format!("- {}", label)).collect::<Vec<_>>().join("\n");
+        let reply = match top_k {
+            None => "Reply with only a JSON object: {\"label\": \"<label>\"}".to_string(),
+            Some(k) => format!(
+                "Reply with only a JSON object: {{\"label\": \"<label>\", \"top\": [{{\"label\": \"<label>\", \
+                 \"confidence\": <0 to 1>}}, ...]}}, listing up to {} likeliest labels, most likely first.",
+                k
+            ),
+        };
+        PromptConfig {
+            prompt: format!(
+                "{}\n\nChoose exactly one of these labels, spelled as written:\n{}\n\n{}",
+                config.prompt, choices, reply
+            ),
+            schema: Some(self.schema(top_k)),
+            ..config
+        }
+    }
+
+    /// Check a parsed reply. The label must be one of the set; entries in `top` that aren't
+    /// are dropped, and the rest ordered by confidence and cut to `top_k`.
+    pub fn classify(&self, response: &Value, top_k: Option<usize>) -> Result<Classification, String> {
+        let chosen = match response {
+            Value::Object(fields) => fields.get("label").and_then(Value::as_str),
+            // Servers without structured output sometimes answer with the bare label
+            Value::String(text) => Some(text.as_str()),
+            _ => None,
+        }
+        .ok_or_else(|| format!("Expected a JSON object with a 'label', got: {}", response))?;
+        let label = self
+            .find(chosen)
+            .ok_or_else(|| format!("Reply '{}' is not one of the labels", chosen))?
+            .to_string();
+
+        let top = top_k.map(|k| {
+            let mut top: Vec<LabelScore> = Vec::new();
+            for entry in response.get("top").and_then(Value::as_array).into_iter().flatten() {
+                let (Some(label), Some(confidence)) = (
+                    entry.get("label").and_then(Value::as_str).and_then(|l| self.find(l)),
+                    entry.get("confidence").and_then(Value::as_f64),
+                ) else {
+                    continue;
+                };
+                if !top.iter().any(|seen| seen.label == label) {
+                    top.push(LabelScore {
+                        label: label.to_string(),
+                        confidence: confidence.clamp(0.0, 1.0),
+                    });
+                }
+            }
+            top.sort_by(|a, b| b.confidence.total_cmp(&a.confidence));
+            top.truncate(k);
+            top
+        });
+        Ok(Classification { label, top })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn animals() -> LabelSet {
+        LabelSet::parse("cat, dog,Bird").unwrap()
+    }
+
+    #[test]
+    fn test_parse_labels() {
+        assert_eq!(animals().labels(), ["cat", "dog", "Bird"]);
+        assert!(LabelSet::parse("cat,CAT, ").is_err());
+
+        let path = std::env::temp_dir().join(format!("nineladies_labels_{}.txt", std::process::id()));
+        fs::write(&path, "# Animals\ncat\n\ndog\n  bird  \n").unwrap();
+        assert_eq!(LabelSet::parse(path.to_str().unwrap()).unwrap().labels(), ["cat", "dog", "bird"]);
+        fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_classify_validates_the_label() {
+        let labels = animals();
+        let classified = labels.classify(&json!({"label": "DOG"}), None).unwrap();
+        assert_eq!(classified, Classification { label: "dog".to_string(), top: None });
+        assert_eq!(labels.classify(&json!("bird."), None).unwrap().label, "Bird");
+        assert!(labels.classify(&json!({"label": "horse"}), None).unwrap_err().contains("not one of the labels"));
+        assert!(labels.classify(&json!({"animal": "cat"}), None).is_err());
+    }
+
+    #[test]
+    fn test_classify_top_k() {
+        let reply = json!({"label": "cat", "top": [
+            {"label": "dog", "confidence": 0.2},
+            {"label": "cat", "confidence": 0.7},
+            {"label": "horse", "confidence": 0.05},
+            {"label": "bird", "confidence": 0.1},
+        ]});
+        let top = animals().classify(&reply, Some(2)).unwrap().top.unwrap();
+        let labels: Vec<&str> = top.iter().map(|score| score.label.as_str()).collect();
+        assert_eq!(labels, ["cat", "dog"]);
+        assert_eq!(animals().classify(&json!({"label": "cat"}), Some(3)).unwrap().top, Some(vec![]));
+    }
+
+    #[test]
+    fn test_constrain_adds_labels_and_schema() {
+        let config = PromptConfig {
+            system: String::new(),
+            prompt: "What animal is this?".to_string(),
+            temperature: 0.0,
+            model: None,
+            schema: None,
+            seed: None,
+            think: None,
+            schema_via: None,
+        };
+        let constrained = animals().constrain(config, Some(2));
+        assert!(constrained.prompt.starts_with("What animal is this?\n\nChoose exactly one"));
+        assert!(constrained.prompt.contains("- Bird\n"));
+        let schema = constrained.schema.unwrap();
+        assert_eq!(schema["properties"]["label"]["enum"], json!(["cat", "dog", "Bird"]));
+        assert_eq!(schema["properties"]["top"]["maxItems"], 2);
+    }
+}
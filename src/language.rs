@@ -0,0 +1,401 @@
+//! `--language`: asking for replies in a given language, checking that they are and
+//! recording the language they came back in.
+//!
+//! The check is a heuristic, not a language identifier: non-Latin languages are recognized
+//! by their script, and Latin-script languages by counting common short words. Replies too
+//! short to judge are given the benefit of the doubt.
+
+/// Fewer words than this and a reply is not judged, so a one-word tag list isn't rejected.
+const MIN_WORDS: usize = 6;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Script {
+    Latin,
+    Cyrillic,
+    Greek,
+    Arabic,
+    Hebrew,
+    Devanagari,
+    Thai,
+    Hangul,
+    /// Hiragana and katakana; Japanese text also uses Han characters
+    Kana,
+    Han,
+}
+
+fn script_of(c: char) -> Option<Script> {
+    Some(match c as u32 {
+        0x41..=0x5A | 0x61..=0x7A | 0xC0..=0x24F => Script::Latin,
+        0x370..=0x3FF => Script::Greek,
+        0x400..=0x4FF => Script::Cyrillic,
+        0x590..=0x5FF => Script::Hebrew,
+        0x600..=0x6FF | 0x750..=0x77F => Script::Arabic,
+        0x900..=0x97F => Script::Devanagari,
+        0xE00..=0xE7F => Script::Thai,
+        0x3040..=0x30FF => Script::Kana,
+        0x4E00..=0x9FFF | 0x3400..=0x4DBF => Script::Han,
+        0xAC00..=0xD7AF | 0x1100..=0x11FF => Script::Hangul,
+        _ => return None,
+    })
+}
+
+#[derive(Debug)]
+struct KnownLanguage {
+    code: &'static str,
+    name: &'static str,
+    script: Script,
+    /// Frequent words that are rare in the other languages listed, for Latin-script checks
+    common_words: &'static [&'static str],
+}
+
+const KNOWN: &[KnownLanguage] = &[
+    KnownLanguage {
+        code: "en",
+        name: "English",
+        script: Script::Latin,
+        common_words: &["the", "and", "of", "is", "with", "in", "a", "on", "are", "this", "to", "it"],
+    },
+    KnownLanguage {
+        code: "de",
+        name: "German",
+        script: Script::Latin,
+        common_words: &["der", "die", "das", "und", "ist", "mit", "ein", "eine", "auf", "im", "von", "nicht"],
+    },
+    KnownLanguage {
+        code: "fr",
+        name: "French",
+        script: Script::Latin,
+        common_words: &["le", "la", "les", "et", "est", "une", "des", "du", "sur", "avec", "dans", "au"],
+    },
+    KnownLanguage {
+        code: "es",
+        name: "Spanish",
+        script: Script::Latin,
+        common_words: &["el", "los", "las", "y", "es", "una", "del", "con", "en", "por", "sobre", "muy"],
+    },
+    KnownLanguage {
+        code: "it",
+        name: "Italian",
+        script: Script::Latin,
+        common_words: &["il", "di", "che", "è", "gli", "della", "con", "un", "sono", "nel", "delle", "una"],
+    },
+    KnownLanguage {
+        code: "pt",
+        name: "Portuguese",
+        script: Script::Latin,
+        common_words: &["o", "os", "e", "do", "da", "um", "uma", "em", "com", "são", "não", "sobre"],
+    },
+    KnownLanguage {
+        code: "nl",
+        name: "Dutch",
+        script: Script::Latin,
+        common_words: &["de", "het", "een", "en", "is", "van", "op", "met", "zijn", "niet", "ook", "bij"],
+    },
+    KnownLanguage {
+        code: "sv",
+        name: "Swedish",
+        script: Script::Latin,
+        common_words: &["och", "är", "en", "ett", "det", "som", "på", "med", "av", "till", "inte", "har"],
+    },
+    KnownLanguage {
+        code: "pl",
+        name: "Polish",
+        script: Script::Latin,
+        common_words: &["i", "w", "na", "jest", "się", "z", "nie", "do", "to", "oraz", "są", "przy"],
+    },
+    KnownLanguage { code: "ru", name: "Russian", script: Script::Cyrillic, common_words: &[] },
+    KnownLanguage { code: "uk", name: "Ukrainian", script: Script::Cyrillic, common_words: &[] },
+    KnownLanguage { code: "el", name: "Greek", script: Script::Greek, common_words: &[] },
+    KnownLanguage { code: "ar", name: "Arabic", script: Script::Arabic, common_words: &[] },
+    KnownLanguage { code: "he", name: "Hebrew", script: Script::Hebrew, common_words: &[] },
+    KnownLanguage { code: "hi", name: "Hindi", script: Script::Devanagari, common_words: &[] },
+    KnownLanguage { code: "th", name: "Thai", script: Script::Thai, common_words: &[] },
+    KnownLanguage { code: "ko", name: "Korean", script: Script::Hangul, common_words: &[] },
+    KnownLanguage { code: "ja", name: "Japanese", script: Script::Kana, common_words: &[] },
+    KnownLanguage { code: "zh", name: "Chinese", script: Script::Han, common_words: &[] },
+];
+
+/// The language replies are wanted in, as given to `--language`.
+#[derive(Clone, Debug)]
+pub struct Language {
+    /// What the model is told, e.g. "German"
+    pub name: String,
+    known: Option<&'static KnownLanguage>,
+}
+
+impl Language {
+    /// Accepts an English name ("German") or ISO 639-1 code ("de"), case-insensitively.
+    /// Other names are passed to the model as given, without checking replies.
+    pub fn parse(value: &str) -> Result<Language, String> {
+        let value = value.trim();
+        if value.is_empty() {
+            return Err("--language must not be empty".to_string());
+        }
+        let known = KNOWN
+            .iter()
+            .find(|l| l.code.eq_ignore_ascii_case(value) || l.name.eq_ignore_ascii_case(value));
+        Ok(Language {
+            name: known.map_or_else(|| value.to_string(), |l| l.name.to_string()),
+            known,
+        })
+    }
+
+    /// Whether replies can be checked for this language.
+    pub fn checkable(&self) -> bool {
+        self.known.is_some()
+    }
+
+    /// Added to the system prompt. Field names stay as the prompt defines them so the
+    /// output schema doesn't change with the language.
+    pub fn instruction(&self) -> String {
+        format!(
+            "Answer in {name}. If you answer in JSON, keep the field names exactly as specified and write the values in {name}.",
+            name = self.name
+        )
+    }
+
+    /// Added to the question when a reply came back in the wrong language, naming the
+    /// language it came back in when that is known.
+    pub fn reminder(&self, detected: Option<&Language>) -> String {
+        match detected {
+            Some(detected) => format!(
+                "Your last answer was in {wrong}. Write all of your answer in {name}, not in {wrong} or any other \
+                 language, even if the image or the question is in another language.",
+                wrong = detected.name,
+                name = self.name
+            ),
+            None => format!("Your answer must be written in {}, not in any other language.", self.name),
+        }
+    }
+
+    /// The key for this language in `response.translations`: its ISO 639-1 code when known,
+    /// otherwise the name as given.
+    pub fn key(&self) -> String {
+        self.known.map_or_else(|| self.name.clone(), |l| l.code.to_string())
+    }
+
+    /// The question for a `--translate` pass over a reply, plain text or JSON.
+    pub fn translation_prompt(&self, text: &str) -> String {
+        format!(
+            "Translate the following into {name}. If it is JSON, keep the structure and field names exactly \
+             and translate only the text values. Reply with only the translation.\n\n{text}",
+            name = self.name,
+            text = text
+        )
+    }
+
+    /// Whether `text` looks like it is in this language. Short or unrecognizable text passes.
+    pub fn matches(&self, text: &str) -> bool {
+        let Some(target) = self.known else {
+            return true;
+        };
+
+        let words = words(text);
+        let Some(share) = script_shares(text) else {
+            return true;
+        };
+
+        match target.script {
+            Script::Latin => {
+                if share(Script::Latin) < 0.5 {
+                    return false;
+                }
+                if words.len() < MIN_WORDS {
+                    return true;
+                }
+                let hits = |lang: &KnownLanguage| common_word_hits(lang, &words);
+                let target_hits = hits(target);
+                let best_other = KNOWN
+                    .iter()
+                    .filter(|l| l.script == Script::Latin && l.code != target.code)
+                    .map(hits)
+                    .max()
+                    .unwrap_or(0);
+                target_hits >= best_other || best_other < 2
+            }
+            // Japanese mixes kana with Han characters; Chinese has no kana at all
+            Script::Kana => share(Script::Kana) + share(Script::Han) >= 0.5 && share(Script::Kana) > 0.0,
+            Script::Han => share(Script::Han) >= 0.5 && share(Script::Kana) == 0.0,
+            script => share(script) >= 0.5,
+        }
+    }
+}
+
+/// The language `text` is in, of those that can be checked, or `None` if it is too short or
+/// not clearly in any of them.
+pub fn detect(text: &str) -> Option<Language> {
+    let share = script_shares(text)?;
+    let code = if share(Script::Kana) > 0.0 && share(Script::Kana) + share(Script::Han) >= 0.5 {
+        "ja"
+    } else if share(Script::Latin) >= 0.5 {
+        let words = words(text);
+        if words.len() < MIN_WORDS {
+            return None;
+        }
+        // The most common words, by a clear margin
+        let mut hits: Vec<(usize, &'static str)> = KNOWN
+            .iter()
+            .filter(|l| l.script == Script::Latin)
+            .map(|l| (common_word_hits(l, &words), l.code))
+            .collect();
+        hits.sort_by_key(|&(hits, _)| std::cmp::Reverse(hits));
+        match hits.as_slice() {
+            [(best, code), (second, _), ..] if *best >= 2 && best > second => *code,
+            _ => return None,
+        }
+    } else if share(Script::Cyrillic) >= 0.5 {
+        // Letters Ukrainian has and Russian doesn't
+        match text.chars().any(|c| matches!(c, 'і' | 'ї' | 'є' | 'ґ' | 'І' | 'Ї' | 'Є' | 'Ґ')) {
+            true => "uk",
+            false => "ru",
+        }
+    } else {
+        KNOWN
+            .iter()
+            .find(|l| !matches!(l.script, Script::Latin | Script::Cyrillic | Script::Kana) && share(l.script) >= 0.5)?
+            .code
+    };
+    Language::parse(code).ok()
+}
+
+/// The share of `text`'s letters in each script, or `None` if it has no letters.
+fn script_shares(text: &str) -> Option<impl Fn(Script) -> f64> {
+    let mut script_counts: Vec<(Script, usize)> = Vec::new();
+    for script in text.chars().filter_map(script_of) {
+        match script_counts.iter_mut().find(|(s, _)| *s == script) {
+            Some((_, n)) => *n += 1,
+            None => script_counts.push((script, 1)),
+        }
+    }
+    let letters: usize = script_counts.iter().map(|(_, n)| n).sum();
+    (letters > 0).then_some(move |script: Script| {
+        script_counts.iter().find(|(s, _)| *s == script).map_or(0, |(_, n)| *n) as f64 / letters as f64
+    })
+}
+
+fn words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+fn common_word_hits(language: &KnownLanguage, words: &[String]) -> usize {
+    words.iter().filter(|w| language.common_words.contains(&w.as_str())).count()
+}
+
+/// The text of a response worth checking: strings anywhere in it, but not JSON field names.
+pub fn response_text(value: &serde_json::Value) -> String {
+    fn collect(value: &serde_json::Value, out: &mut Vec<String>) {
+        match value {
+            serde_json::Value::String(s) => out.push(s.clone()),
+            serde_json::Value::Array(items) => items.iter().for_each(|v| collect(v, out)),
+            serde_json::Value::Object(fields) => fields.values().for_each(|v| collect(v, out)),
+            _ => {}
+        }
+    }
+    let mut parts = Vec::new();
+    collect(value, &mut parts);
+    parts.join(" ")
+}
+
+/// `response` with `translations` (keyed by `Language::key`) added as its `translations`
+/// field. A plain-text response becomes `{"text": ..., "translations": ...}`.
+pub fn add_translations(
+    response: serde_json::Value,
+    translations: serde_json::Map<String, serde_json::Value>,
+) -> serde_json::Value {
+    let mut fields = match response {
+        serde_json::Value::Object(fields) => fields,
+        other => serde_json::Map::from_iter([("text".to_string(), other)]),
+    };
+    fields.insert("translations".to_string(), serde_json::Value::Object(translations));
+    serde_json::Value::Object(fields)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_language() {
+        assert_eq!(Language::parse("de").unwrap().name, "German");
+        assert_eq!(Language::parse("german").unwrap().name, "German");
+        let welsh = Language::parse("Welsh").unwrap();
+        assert_eq!(welsh.name, "Welsh");
+        assert!(!welsh.checkable());
+        assert!(welsh.matches("anything at all goes through here"));
+        assert!(Language::parse(" ").is_err());
+    }
+
+    #[test]
+    fn test_matches_latin_languages() {
+        let german = Language::parse("de").unwrap();
+        assert!(german.matches("Eine graue Katze schläft auf dem Sofa und die Sonne scheint"));
+        assert!(!german.matches("A grey cat is asleep on the sofa and the sun is shining"));
+        // Too short to judge
+        assert!(german.matches("cat, sofa"));
+
+        let french = Language::parse("French").unwrap();
+        assert!(french.matches("Un chat gris dort sur le canapé dans la lumière du soleil"));
+        assert!(!french.matches("Un gato gris duerme en el sofá con los cojines y la manta"));
+    }
+
+    #[test]
+    fn test_matches_by_script() {
+        let russian = Language::parse("ru").unwrap();
+        assert!(russian.matches("Серая кошка спит на диване"));
+        assert!(!russian.matches("A grey cat is asleep on the sofa"));
+
+        let japanese = Language::parse("ja").unwrap();
+        let chinese = Language::parse("zh").unwrap();
+        assert!(japanese.matches("ソファの上で灰色の猫が寝ています"));
+        assert!(!chinese.matches("ソファの上で灰色の猫が寝ています"));
+        assert!(chinese.matches("一只灰色的猫在沙发上睡觉"));
+    }
+
+    #[test]
+    fn test_detect() {
+        let detect = |text| detect(text).map(|language| language.key());
+        assert_eq!(detect("Eine graue Katze schläft auf dem Sofa und die Sonne scheint").as_deref(), Some("de"));
+        assert_eq!(detect("A grey cat is asleep on the sofa and the sun is shining").as_deref(), Some("en"));
+        assert_eq!(detect("Un gato gris duerme en el sofá con los cojines y la manta").as_deref(), Some("es"));
+        assert_eq!(detect("Серая кошка спит на диване").as_deref(), Some("ru"));
+        assert_eq!(detect("Сіра кішка спить на дивані").as_deref(), Some("uk"));
+        assert_eq!(detect("ソファの上で灰色の猫が寝ています").as_deref(), Some("ja"));
+        assert_eq!(detect("一只灰色的猫在沙发上睡觉").as_deref(), Some("zh"));
+        assert_eq!(detect("Μια γκρίζα γάτα κοιμάται").as_deref(), Some("el"));
+        // Too short to tell, or no words at all
+        assert_eq!(detect("cat, sofa"), None);
+        assert_eq!(detect("42"), None);
+    }
+
+    #[test]
+    fn test_reminder_names_the_wrong_language() {
+        let german = Language::parse("de").unwrap();
+        let english = Language::parse("en").unwrap();
+        assert!(german.reminder(Some(&english)).starts_with("Your last answer was in English. Write all of your answer in German"));
+        assert_eq!(german.reminder(None), "Your answer must be written in German, not in any other language.");
+    }
+
+    #[test]
+    fn test_response_text_skips_field_names() {
+        let response = serde_json::json!({"description": "Ein Hund", "tags": ["Hund", "Wiese"], "count": 1});
+        assert_eq!(response_text(&response), "Ein Hund Hund Wiese");
+    }
+
+    #[test]
+    fn test_add_translations() {
+        let translations = serde_json::Map::from_iter([("de".to_string(), serde_json::json!("Ein Hund"))]);
+        assert_eq!(
+            add_translations(serde_json::json!("A dog"), translations.clone()),
+            serde_json::json!({"text": "A dog", "translations": {"de": "Ein Hund"}})
+        );
+        assert_eq!(
+            add_translations(serde_json::json!({"subject": "A dog"}), translations),
+            serde_json::json!({"subject": "A dog", "translations": {"de": "Ein Hund"}})
+        );
+        assert_eq!(Language::parse("German").unwrap().key(), "de");
+        assert_eq!(Language::parse("Klingon").unwrap().key(), "Klingon");
+    }
+}
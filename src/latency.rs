@@ -0,0 +1,274 @@
+//! Per-image request timings, slow-response warnings and the end-of-run latency summary,
+//! with where the server says the time went.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+/// Histogram bucket upper bounds, in seconds; the last bucket catches everything slower.
+const BUCKETS: [f64; 7] = [0.5, 1.0, 2.0, 5.0, 10.0, 30.0, 60.0];
+
+/// Widest histogram bar, in characters.
+const BAR_WIDTH: usize = 30;
+
+/// Recent requests that a new one is compared with to spot a slowing server.
+const RECENT_WINDOW: usize = 20;
+
+/// Don't call anything slow until there is a baseline to compare with.
+const MIN_BASELINE: usize = 5;
+
+/// A request counts as slow when it takes this many times the recent median.
+const SLOW_FACTOR: f64 = 3.0;
+
+/// A reply whose load time is at least this long had to load the model first; Ollama reports
+/// a few milliseconds of load time for a model that is already loaded.
+const MODEL_LOAD_MS: u64 = 500;
+
+/// Where the server says the time went, when it reports it. Ollama reports all three,
+/// llama.cpp reports prompt and generation time, hosted APIs usually nothing.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ServerTiming {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub load_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub generation_ms: Option<u64>,
+}
+
+/// Timing of one image's request, stored with its output record.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Timing {
+    /// Wall time from sending the request to parsing the reply
+    pub wall_ms: u64,
+    #[serde(flatten)]
+    pub server: ServerTiming,
+}
+
+/// The server's own timings added up over a run, to show whether the time goes to loading
+/// the model, processing prompts or generating replies.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ServerTimeTotals {
+    pub load_ms: u64,
+    pub prompt_ms: u64,
+    pub generation_ms: u64,
+    /// Replies with any timings
+    pub replies: usize,
+    /// Replies that had to wait for the model to load
+    pub model_loads: usize,
+}
+
+impl ServerTimeTotals {
+    pub fn add(&mut self, timing: &ServerTiming) {
+        if *timing == ServerTiming::default() {
+            return;
+        }
+        self.replies += 1;
+        self.load_ms += timing.load_ms.unwrap_or_default();
+        self.prompt_ms += timing.prompt_ms.unwrap_or_default();
+        self.generation_ms += timing.generation_ms.unwrap_or_default();
+        self.model_loads += timing.load_ms.is_some_and(|ms| ms >= MODEL_LOAD_MS) as usize;
+    }
+
+    /// One-line summary, e.g. `load 42.0s (30%), prompt 28.0s (20%), generation 70.0s (50%)
+    /// over 120 replies; the model was loaded 3 times`.
+    pub fn summary(&self) -> Option<String> {
+        let total = self.load_ms + self.prompt_ms + self.generation_ms;
+        if total == 0 {
+            return None;
+        }
+        let part = |name: &str, ms: u64| {
+            format!("{} {} ({:.0}%)", name, format_secs(Duration::from_millis(ms)), ms as f64 * 100.0 / total as f64)
+        };
+        let mut summary = format!(
+            "{}, {}, {} over {} replies",
+            part("load", self.load_ms),
+            part("prompt", self.prompt_ms),
+            part("generation", self.generation_ms),
+            self.replies
+        );
+        match self.model_loads {
+            0 => {}
+            1 => summary.push_str("; the model was loaded once"),
+            loads => summary.push_str(&format!("; the model was loaded {} times", loads)),
+        }
+        Some(summary)
+    }
+}
+
+/// Request durations for a run.
+#[derive(Debug, Default)]
+pub struct LatencyStats {
+    samples: Vec<Duration>,
+    recent: VecDeque<Duration>,
+}
+
+impl LatencyStats {
+    /// Record a request's duration, returning the recent median if this one was much
+    /// slower than it.
+    pub fn record(&mut self, duration: Duration) -> Option<Duration> {
+        let baseline = (self.recent.len() >= MIN_BASELINE).then(|| {
+            let mut recent: Vec<Duration> = self.recent.iter().copied().collect();
+            recent.sort();
+            recent[recent.len() / 2]
+        });
+
+        self.samples.push(duration);
+        self.recent.push_back(duration);
+        if self.recent.len() > RECENT_WINDOW {
+            self.recent.pop_front();
+        }
+
+        baseline.filter(|median| duration.as_secs_f64() > median.as_secs_f64() * SLOW_FACTOR)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    /// The `p`th percentile (0-100) by nearest rank.
+    pub fn percentile(&self, p: f64) -> Option<Duration> {
+        let mut sorted = self.samples.clone();
+        sorted.sort();
+        let rank = ((p / 100.0) * sorted.len() as f64).ceil() as usize;
+        sorted.get(rank.saturating_sub(1)).copied()
+    }
+
+    /// One-line summary, e.g. `min 800ms, p50 1.2s, p90 2.9s, p99 4.1s, max 4.3s`.
+    pub fn summary(&self) -> Option<String> {
+        let stat = |p: f64| format_secs(self.percentile(p).unwrap_or_default());
+        (!self.is_empty()).then(|| {
+            format!(
+                "min {}, p50 {}, p90 {}, p99 {}, max {}",
+                stat(0.0),
+                stat(50.0),
+                stat(90.0),
+                stat(99.0),
+                stat(100.0)
+            )
+        })
+    }
+
+    /// Histogram lines from the fastest to the slowest occupied bucket.
+    pub fn histogram(&self) -> Vec<String> {
+        let mut counts = [0usize; BUCKETS.len() + 1];
+        for sample in &self.samples {
+            let secs = sample.as_secs_f64();
+            let bucket = BUCKETS.iter().position(|&bound| secs < bound).unwrap_or(BUCKETS.len());
+            counts[bucket] += 1;
+        }
+
+        let (Some(first), Some(last)) = (counts.iter().position(|&c| c > 0), counts.iter().rposition(|&c| c > 0))
+        else {
+            return Vec::new();
+        };
+        let max = counts.iter().copied().max().unwrap_or(1);
+
+        (first..=last)
+            .map(|i| {
+                let label = match BUCKETS.get(i) {
+                    Some(bound) => format!("<{}s", bound),
+                    None => format!(">={}s", BUCKETS[BUCKETS.len() - 1]),
+                };
+                let bar = "#".repeat((counts[i] * BAR_WIDTH).div_ceil(max));
+                format!("{:>6} {:>6} {}", label, counts[i], bar).trim_end().to_string()
+            })
+            .collect()
+    }
+}
+
+fn format_secs(duration: Duration) -> String {
+    if duration < Duration::from_secs(1) {
+        format!("{}ms", duration.as_millis())
+    } else {
+        format!("{:.1}s", duration.as_secs_f64())
+    }
+}
+
+/// Convert a nanosecond count from a server response to milliseconds.
+pub fn nanos_to_ms(nanos: u64) -> u64 {
+    nanos / 1_000_000
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(s: f64) -> Duration {
+        Duration::from_secs_f64(s)
+    }
+
+    #[test]
+    fn test_percentiles_and_summary() {
+        let mut stats = LatencyStats::default();
+        assert_eq!(stats.summary(), None);
+        for s in [1.0, 2.0, 3.0, 4.0, 10.0] {
+            stats.record(secs(s));
+        }
+        assert_eq!(stats.percentile(50.0), Some(secs(3.0)));
+        assert_eq!(stats.summary().unwrap(), "min 1.0s, p50 3.0s, p90 10.0s, p99 10.0s, max 10.0s");
+    }
+
+    #[test]
+    fn test_slow_request_needs_baseline() {
+        let mut stats = LatencyStats::default();
+        assert_eq!(stats.record(secs(1.0)), None);
+        assert_eq!(stats.record(secs(9.0)), None);
+        for _ in 0..4 {
+            stats.record(secs(1.0));
+        }
+        assert_eq!(stats.record(secs(2.5)), None);
+        assert_eq!(stats.record(secs(4.0)), Some(secs(1.0)));
+    }
+
+    #[test]
+    fn test_histogram_spans_occupied_buckets() {
+        let mut stats = LatencyStats::default();
+        for s in [0.7, 0.8, 1.5, 7.0] {
+            stats.record(secs(s));
+        }
+        let lines = stats.histogram();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], format!("   <1s      2 {}", "#".repeat(30)));
+        assert_eq!(lines[2], "   <5s      0");
+        assert!(lines[3].starts_with("  <10s      1 #"));
+    }
+
+    #[test]
+    fn test_server_time_totals() {
+        let mut totals = ServerTimeTotals::default();
+        assert_eq!(totals.summary(), None);
+        let timing = |load_ms, prompt_ms, generation_ms| ServerTiming {
+            load_ms: Some(load_ms),
+            prompt_ms: Some(prompt_ms),
+            generation_ms: Some(generation_ms),
+        };
+        totals.add(&timing(2990, 500, 1500));
+        totals.add(&timing(5, 500, 1500));
+        totals.add(&timing(5, 1000, 2000));
+        // A hosted API's reply says nothing
+        totals.add(&ServerTiming::default());
+        assert_eq!(totals.replies, 3);
+        assert_eq!(totals.model_loads, 1);
+        assert_eq!(
+            totals.summary().unwrap(),
+            "load 3.0s (30%), prompt 2.0s (20%), generation 5.0s (50%) over 3 replies; the model was loaded once"
+        );
+    }
+
+    #[test]
+    fn test_timing_serialization() {
+        let timing = Timing {
+            wall_ms: 1200,
+            server: ServerTiming {
+                load_ms: None,
+                prompt_ms: Some(300),
+                generation_ms: Some(850),
+            },
+        };
+        let json = serde_json::to_value(timing).unwrap();
+        assert_eq!(json, serde_json::json!({"wall_ms": 1200, "prompt_ms": 300, "generation_ms": 850}));
+        assert_eq!(serde_json::from_value::<Timing>(json).unwrap(), timing);
+    }
+}
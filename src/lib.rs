@@ -0,0 +1,83 @@
+//! The core of 9ladies, for programs that want to describe images without shelling out to
+//! the `9ladies` binary: prompt configs, image validation, model backends, the batch
+//! engine and the output records it produces.
+//!
+//! ```no_run
+//! use nineladies::{load_prompt_config, Api, Backend, BatchHooks, BatchRunner, Described};
+//!
+//! struct Print;
+//!
+//! impl BatchHooks for Print {
+//!     fn described(&mut self, described: &mut Described) -> Result<(), String> {
+//!         println!("{}: {}", described.record.file, described.record.response);
+//!         Ok(())
+//!     }
+//! }
+//!
+//! let config = load_prompt_config("prompts/describe.json")?;
+//! let backend = Backend::new(Api::Ollama, nineladies::DEFAULT_URL, Some("llava:13b".to_string()), None);
+//! let paths = vec!["a.jpg".to_string(), "b.png".to_string()];
+//! let summary = BatchRunner::new(backend, config).run(&paths, &mut Print);
+//! println!("{} described, {} failed", summary.succeeded, summary.failed);
+//! # Ok::<(), String>(())
+//! ```
+
+pub mod assertions;
+pub mod backend;
+pub mod barcode;
+pub mod batch;
+pub mod bundle;
+pub mod caption_report;
+pub mod config;
+pub mod confidence;
+pub mod convert;
+pub mod cost;
+pub mod diff;
+pub mod download;
+pub mod duplicates;
+pub mod exif;
+#[cfg(feature = "faces")]
+pub mod faces;
+pub mod field_map;
+pub mod flatten;
+pub mod followup;
+pub mod grounding;
+pub mod image_stats;
+pub mod images;
+pub mod input_manifest;
+pub mod labels;
+pub mod language;
+pub mod latency;
+pub mod lock;
+pub mod memory;
+pub mod merge;
+pub mod nsfw;
+pub mod ocr;
+pub mod output_check;
+pub mod output_file;
+pub mod oversize;
+pub mod paths;
+pub mod presets;
+pub mod products;
+pub mod prompt;
+pub mod record;
+pub mod replay;
+pub mod report;
+pub mod run_window;
+pub mod schema;
+#[cfg(feature = "lua")]
+pub mod script;
+pub mod search;
+pub mod secrets;
+pub mod sidecar;
+pub mod sinks;
+pub mod store;
+pub mod throttle;
+pub mod trace;
+pub mod video;
+
+pub use backend::{Api, Backend, CallError, ChatReply, ChatTurn, ServerModel, DEFAULT_URL};
+pub use batch::{BatchHooks, BatchRunner, BatchSummary, Described, ItemError, Transform};
+pub use images::validate_image_file;
+pub use prompt::{load_prompt_config, PromptConfig};
+pub use record::{read_output_records, ContextMode, EmbedSource, OutputRecord, ReviewStatus};
@@ -0,0 +1,238 @@
+//! `run --listen-input`: take jobs from a Unix or TCP socket while the run is going, so other
+//! services can feed it work without starting a new process for each batch.
+//!
+//! Clients connect and write one job per line, in the JSON form the queue consumers take
+//! (see `queue`): `{"path": "/data/in/a.jpg", "metadata": {"upload_id": 42}}`, or just a path
+//! as on stdin. Each job gets a result line back on the same connection once it is done,
+//! while its record goes wherever the run writes records.
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use tracing::{debug, info, warn};
+
+use nineladies::paths;
+
+use crate::queue::{self, Job};
+use crate::shutdown::Shutdown;
+
+/// How often a wait for the next job checks for Ctrl+C
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Where to listen: `unix:<path>`, or a TCP address as `tcp:<host>:<port>`, `<host>:<port>`
+/// or `:<port>` for this machine only. Clients are trusted with any path the run can read
+/// and get its record back, so other interfaces are only listened on when named.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ListenAddress {
+    Unix(PathBuf),
+    Tcp(String),
+}
+
+impl ListenAddress {
+    pub fn parse(address: &str) -> Result<ListenAddress, String> {
+        if let Some(path) = address.strip_prefix("unix:") {
+            return match path.is_empty() {
+                true => Err("--listen-input unix: needs a socket path, e.g. unix:/tmp/9ladies.sock".to_string()),
+                false => Ok(ListenAddress::Unix(PathBuf::from(path))),
+            };
+        }
+        let address = address.strip_prefix("tcp:").unwrap_or(address);
+        match address.rsplit_once(':') {
+            Some((host, port)) if port.parse::<u16>().is_ok() => Ok(ListenAddress::Tcp(match host {
+                "" => format!("127.0.0.1:{}", port),
+                _ => address.to_string(),
+            })),
+            _ => Err(format!(
+                "Invalid --listen-input '{}' (expected unix:<path>, <host>:<port> or :<port>)",
+                address
+            )),
+        }
+    }
+}
+
+/// The connection a job came in on, for its result.
+#[derive(Clone)]
+pub struct Connection(Arc<Mutex<Box<dyn Write + Send>>>);
+
+impl Connection {
+    /// Write a line back. The client may have gone by now, which only loses the result line.
+    pub fn send(&self, line: &str) {
+        let mut writer = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        if let Err(e) = writeln!(writer, "{}", line).and_then(|_| writer.flush()) {
+            debug!("Could not send a result to a --listen-input client: {}", e);
+        }
+    }
+}
+
+/// A job and where its result goes.
+pub struct Received {
+    pub job: Job,
+    pub connection: Connection,
+}
+
+/// A bound socket, handing on jobs as clients send them.
+pub struct Listener {
+    jobs: Receiver<Received>,
+    /// A Unix socket file to remove when the run ends
+    socket_path: Option<PathBuf>,
+}
+
+impl Listener {
+    pub fn bind(address: &ListenAddress) -> Result<Listener, String> {
+        let (sender, jobs) = mpsc::channel();
+        let socket_path = match address {
+            ListenAddress::Tcp(address) => {
+                let listener =
+                    TcpListener::bind(address).map_err(|e| format!("Failed to listen on {}: {}", address, e))?;
+                if listener.local_addr().is_ok_and(|local| !local.ip().is_loopback()) {
+                    warn!(
+                        "--listen-input on {} has no authentication: anyone who can reach it can have any file this run can read described and sent back",
+                        address
+                    );
+                }
+                info!("Taking jobs on {}", address);
+                thread::spawn(move || {
+                    for stream in listener.incoming() {
+                        match stream.and_then(|stream| Ok((stream.try_clone()?, stream))) {
+                            Ok((reader, writer)) => accept(reader, Box::new(writer), sender.clone()),
+                            Err(e) => warn!("Failed to accept a --listen-input connection: {}", e),
+                        }
+                    }
+                });
+                None
+            }
+            #[cfg(unix)]
+            ListenAddress::Unix(path) => {
+                // A socket file left by a run that crashed is taken over; a live one isn't
+                if path.exists() && UnixStream::connect(path).is_err() {
+                    let _ = std::fs::remove_file(path);
+                }
+                let listener = UnixListener::bind(path)
+                    .map_err(|e| format!("Failed to listen on '{}': {}", path.display(), e))?;
+                info!("Taking jobs on {}", path.display());
+                thread::spawn(move || {
+                    for stream in listener.incoming() {
+                        match stream.and_then(|stream| Ok((stream.try_clone()?, stream))) {
+                            Ok((reader, writer)) => accept(reader, Box::new(writer), sender.clone()),
+                            Err(e) => warn!("Failed to accept a --listen-input connection: {}", e),
+                        }
+                    }
+                });
+                Some(path.clone())
+            }
+            #[cfg(not(unix))]
+            ListenAddress::Unix(_) => return Err("--listen-input unix: needs a Unix platform; use a TCP address".to_string()),
+        };
+        Ok(Listener { jobs, socket_path })
+    }
+
+    /// The next job, waiting for one to arrive; `None` once a shutdown is requested.
+    pub fn next(&self, shutdown: &Shutdown) -> Option<Received> {
+        while !shutdown.requested() {
+            match self.jobs.recv_timeout(POLL_INTERVAL) {
+                Ok(received) => return Some(received),
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => return None,
+            }
+        }
+        None
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        if let Some(path) = &self.socket_path {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// A job line: a job object, or a path.
+fn parse_line(line: &str) -> Result<Job, String> {
+    let line = line.trim();
+    match line.starts_with('{') {
+        true => queue::parse_job(line.as_bytes(), None),
+        // Not the queue's raw image bytes: a line of text is a path
+        false => Ok(Job {
+            file: paths::normalize(line),
+            data: None,
+            metadata: serde_json::Value::Null,
+        }),
+    }
+}
+
+/// Read jobs from a connection on a thread of its own. Lines that aren't valid jobs are
+/// answered with an error result straight away.
+fn accept(reader: impl Read + Send + 'static, writer: Box<dyn Write + Send>, jobs: Sender<Received>) {
+    let connection = Connection(Arc::new(Mutex::new(writer)));
+    thread::spawn(move || {
+        for line in BufReader::new(reader).lines() {
+            let Ok(line) = line else {
+                break;
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            match parse_line(&line) {
+                Ok(job) => {
+                    let received = Received {
+                        job,
+                        connection: connection.clone(),
+                    };
+                    if jobs.send(received).is_err() {
+                        break;
+                    }
+                }
+                Err(e) => connection.send(&queue::failure_message(line.as_bytes(), None, &e)),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpStream;
+
+    #[test]
+    fn test_parse_address() {
+        assert_eq!(
+            ListenAddress::parse("unix:/tmp/9ladies.sock"),
+            Ok(ListenAddress::Unix(PathBuf::from("/tmp/9ladies.sock")))
+        );
+        assert_eq!(ListenAddress::parse(":7070"), Ok(ListenAddress::Tcp("127.0.0.1:7070".to_string())));
+        assert_eq!(ListenAddress::parse("0.0.0.0:7070"), Ok(ListenAddress::Tcp("0.0.0.0:7070".to_string())));
+        assert_eq!(ListenAddress::parse("tcp:127.0.0.1:7070"), Ok(ListenAddress::Tcp("127.0.0.1:7070".to_string())));
+        assert_eq!(ListenAddress::parse("[::1]:7070"), Ok(ListenAddress::Tcp("[::1]:7070".to_string())));
+        assert!(ListenAddress::parse("unix:").is_err());
+        assert!(ListenAddress::parse("localhost").is_err());
+    }
+
+    #[test]
+    fn test_jobs_arrive_and_bad_lines_are_answered() {
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let listener = Listener::bind(&ListenAddress::Tcp(format!("127.0.0.1:{}", port))).unwrap();
+        let mut client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        client
+            .write_all(b"{\"path\": \"a.jpg\", \"metadata\": {\"id\": 7}}\n\nphotos/b.jpg\n{\"colour\": \"red\"}\n")
+            .unwrap();
+
+        let shutdown = Shutdown::default();
+        let received = listener.next(&shutdown).unwrap();
+        assert_eq!(received.job.file, "a.jpg");
+        assert_eq!(received.job.metadata, serde_json::json!({"id": 7}));
+        assert_eq!(listener.next(&shutdown).unwrap().job.file, "photos/b.jpg");
+
+        let reply = BufReader::new(client).lines().next().unwrap().unwrap();
+        let reply: serde_json::Value = serde_json::from_str(&reply).unwrap();
+        assert_eq!(reply["status"], "error");
+        assert!(reply["error"].as_str().unwrap().contains("colour"), "{}", reply);
+    }
+}
@@ -0,0 +1,95 @@
+//! Locks that stop two runs from working on the same files at once: each holds an OS lock
+//! on `<file>.lock` for as long as it runs.
+//!
+//! The OS lock goes away with the process that held it, even after a crash, so a lockfile
+//! left behind is simply taken over by the next run. The file holds the process ID only so
+//! that the run turned away can say which process has it. It is emptied when the lock is
+//! released but never deleted: a run that opened it just before could then lock the deleted
+//! file while the next run locked a new one, and both would go ahead.
+
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{Read, Seek, Write};
+use std::path::{Path, PathBuf};
+
+/// A lock on a file, held until it is dropped.
+#[derive(Debug)]
+pub struct FileLock {
+    path: PathBuf,
+    file: File,
+}
+
+impl FileLock {
+    /// Lock `target` for this process, through `<target>.lock`. If another process has it,
+    /// the error names it as `what`, e.g. "Output file".
+    pub fn acquire(target: &Path, what: &str) -> Result<FileLock, String> {
+        let mut path = OsString::from(target.as_os_str());
+        path.push(".lock");
+        let path = PathBuf::from(path);
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .map_err(|e| format!("Failed to create lockfile '{}': {}", path.display(), e))?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                let mut holder = String::new();
+                let _ = file.read_to_string(&mut holder);
+                return Err(format!(
+                    "{} '{}' is in use by another run{}; wait for it to finish or use a different one",
+                    what,
+                    target.display(),
+                    match holder.trim() {
+                        "" => String::new(),
+                        pid => format!(" (process {})", pid),
+                    }
+                ));
+            }
+            Err(TryLockError::Error(e)) => return Err(format!("Failed to lock '{}': {}", path.display(), e)),
+        }
+        let _ = file.set_len(0).and_then(|_| file.rewind()).and_then(|_| write!(file, "{}", std::process::id()));
+        Ok(FileLock { path, file })
+    }
+
+    /// The lockfile.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        let _ = self.file.set_len(0);
+        let _ = self.file.unlock();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_acquire() {
+        let dir = std::env::temp_dir().join(format!("nineladies_lock_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let target = dir.join("jobs.db");
+
+        let lock = FileLock::acquire(&target, "Job store").unwrap();
+        assert_eq!(lock.path(), dir.join("jobs.db.lock"));
+        assert_eq!(fs::read_to_string(lock.path()).unwrap(), std::process::id().to_string());
+        let err = FileLock::acquire(&target, "Job store").unwrap_err();
+        assert!(err.starts_with("Job store '"), "{}", err);
+        assert!(err.contains(&format!("in use by another run (process {})", std::process::id())), "{}", err);
+
+        drop(lock);
+        assert_eq!(fs::read_to_string(dir.join("jobs.db.lock")).unwrap(), "");
+        // A lockfile left by a run that crashed doesn't hold anything
+        fs::write(dir.join("jobs.db.lock"), "99999").unwrap();
+        assert!(FileLock::acquire(&target, "Job store").is_ok());
+        fs::remove_dir_all(dir).ok();
+    }
+}
@@ -0,0 +1,373 @@
+//! Diagnostic logging via `tracing`, to stderr or a log file.
+//!
+//! With `--errors json`, each message on stderr is a JSON object instead, with a stable
+//! `code` for the kind of failure, taken from a `code` field on the event, and the `path`
+//! it concerns, from a `path` or `file` field.
+
+use std::fs::OpenOptions;
+use std::io::{self, IsTerminal, Write};
+use std::sync::Mutex;
+
+use clap::builder::BoolishValueParser;
+use clap::{Args, ValueEnum};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::field::RecordFields;
+use tracing_subscriber::filter::{LevelFilter, Targets};
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::{self, FormatFields, MakeWriter};
+use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+use tracing_subscriber::util::SubscriberInitExt;
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl From<LogLevel> for tracing::Level {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Error => tracing::Level::ERROR,
+            LogLevel::Warn => tracing::Level::WARN,
+            LogLevel::Info => tracing::Level::INFO,
+            LogLevel::Debug => tracing::Level::DEBUG,
+            LogLevel::Trace => tracing::Level::TRACE,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum LogFormat {
+    /// Human-readable lines
+    Text,
+    /// One JSON object per event, for log aggregation
+    Json,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum ErrorFormat {
+    /// Plain messages
+    Text,
+    /// One JSON object per message: level, code, path and detail
+    Json,
+}
+
+#[derive(Args)]
+pub struct LogArgs {
+    /// Minimum level of diagnostic messages to log
+    #[arg(long, value_enum, default_value = "info", global = true, env = "NINELADIES_LOG_LEVEL")]
+    pub log_level: LogLevel,
+
+    /// Append log messages to this file instead of stderr
+    #[arg(long, global = true, env = "NINELADIES_LOG_FILE")]
+    pub log_file: Option<String>,
+
+    /// Log message format
+    #[arg(long, value_enum, default_value = "text", global = true, env = "NINELADIES_LOG_FORMAT")]
+    pub log_format: LogFormat,
+
+    /// How messages on stderr are written: plain text, or JSON objects with a stable error code for scripts
+    #[arg(long, value_enum, default_value = "text", global = true, env = "NINELADIES_ERRORS")]
+    pub errors: ErrorFormat,
+
+    /// Only print warnings and errors to stderr (no progress bar or info messages)
+    #[arg(short, long, global = true, env = "NINELADIES_QUIET", value_parser = BoolishValueParser::new())]
+    pub quiet: bool,
+}
+
+impl LogArgs {
+    /// The level actually applied: `--quiet` caps what reaches stderr at warnings, but a
+    /// `--log-file` keeps the full `--log-level` since it isn't what cron mails out.
+    fn effective_level(&self) -> LevelFilter {
+        let level = LevelFilter::from_level(self.log_level.into());
+        if self.quiet && self.log_file.is_none() {
+            level.min(LevelFilter::WARN)
+        } else {
+            level
+        }
+    }
+}
+
+/// Writes log lines to stderr, hiding the progress bar (if one is drawn) while doing so.
+struct ConsoleWriter;
+
+impl Write for ConsoleWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        crate::progress::suspend_active(|| io::stderr().write_all(buf))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()
+    }
+}
+
+struct ConsoleMakeWriter;
+
+/// Event fields written as the default formatter writes them, leaving out `code` and
+/// `path`, which are there for `--errors json`; the message already says what happened.
+struct TextFields;
+
+impl<'w> FormatFields<'w> for TextFields {
+    fn format_fields<R: RecordFields>(&self, writer: Writer<'w>, fields: R) -> std::fmt::Result {
+        let mut visitor = TextVisitor {
+            writer,
+            result: Ok(()),
+            empty: true,
+        };
+        fields.record(&mut visitor);
+        visitor.result
+    }
+}
+
+struct TextVisitor<'w> {
+    writer: Writer<'w>,
+    result: std::fmt::Result,
+    empty: bool,
+}
+
+impl Visit for TextVisitor<'_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if self.result.is_err() || matches!(field.name(), "code" | "path") {
+            return;
+        }
+        let separator = if self.empty { "" } else { " " };
+        self.empty = false;
+        self.result = match field.name() {
+            "message" => write!(self.writer, "{}{:?}", separator, value),
+            name => write!(self.writer, "{}{}={:?}", separator, name, value),
+        };
+    }
+}
+
+/// `--errors json`: each event as one line of stderr, `{"level", "code", "path", "detail"}`
+/// and any other fields it has. Warnings and errors without a code of their own get
+/// `warning` or `error`.
+struct JsonErrors<W> {
+    writer: W,
+}
+
+impl<S: Subscriber, W: for<'a> MakeWriter<'a> + 'static> Layer<S> for JsonErrors<W> {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let level = *event.metadata().level();
+        let mut message = ErrorMessage {
+            level: level.as_str().to_ascii_lowercase(),
+            code: None,
+            path: None,
+            detail: String::new(),
+            fields: Map::new(),
+        };
+        event.record(&mut message);
+        if message.code.is_none() {
+            message.code = match level {
+                Level::ERROR => Some("error".to_string()),
+                Level::WARN => Some("warning".to_string()),
+                _ => None,
+            };
+        }
+        let line = format!("{}\n", serde_json::to_string(&message).expect("messages serialize"));
+        let _ = self.writer.make_writer().write_all(line.as_bytes());
+    }
+}
+
+#[derive(Serialize)]
+struct ErrorMessage {
+    level: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<String>,
+    detail: String,
+    #[serde(flatten)]
+    fields: Map<String, Value>,
+}
+
+impl Visit for ErrorMessage {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        match field.name() {
+            "code" => self.code = Some(value.to_string()),
+            "path" | "file" => self.path = Some(value.to_string()),
+            name => {
+                self.fields.insert(name.to_string(), value.into());
+            }
+        }
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.fields.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.fields.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.fields.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.fields.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        match field.name() {
+            "message" => self.detail = format!("{:?}", value),
+            name => {
+                self.fields.insert(name.to_string(), format!("{:?}", value).into());
+            }
+        }
+    }
+}
+
+impl<'a> MakeWriter<'a> for ConsoleMakeWriter {
+    type Writer = ConsoleWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        ConsoleWriter
+    }
+}
+
+/// Install the global subscriber. Our own messages, from the binary and the library, are
+/// logged at `--log-level`, while dependencies (HTTP client internals) are capped at
+/// warnings. Stderr output stays terse (no timestamps or targets) so it reads like plain
+/// messages; log files get timestamps.
+pub fn init(args: &LogArgs) -> Result<(), String> {
+    let level = args.effective_level();
+    let filter = Targets::new()
+        .with_default(level.min(LevelFilter::WARN))
+        .with_target(env!("CARGO_CRATE_NAME"), level)
+        .with_target("nineladies", level);
+
+    let layer = match (&args.log_file, args.log_format) {
+        (Some(path), format) => {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|e| format!("Failed to open log file '{}': {}", path, e))?;
+            let layer = fmt::layer().with_ansi(false).with_writer(Mutex::new(file));
+            match format {
+                LogFormat::Text => layer.fmt_fields(TextFields).boxed(),
+                LogFormat::Json => layer.json().boxed(),
+            }
+        }
+        (None, _) if args.errors == ErrorFormat::Json => JsonErrors { writer: ConsoleMakeWriter }.boxed(),
+        (None, LogFormat::Text) => fmt::layer()
+            .with_ansi(io::stderr().is_terminal())
+            .without_time()
+            .with_target(false)
+            .fmt_fields(TextFields)
+            .with_writer(ConsoleMakeWriter)
+            .boxed(),
+        (None, LogFormat::Json) => fmt::layer()
+            .json()
+            .with_ansi(false)
+            .with_writer(ConsoleMakeWriter)
+            .boxed(),
+    };
+    // Everything else goes to the log file, but scripts still see what went wrong
+    let errors = (args.log_file.is_some() && args.errors == ErrorFormat::Json)
+        .then(|| JsonErrors { writer: ConsoleMakeWriter }.with_filter(LevelFilter::WARN));
+
+    tracing_subscriber::registry()
+        .with(layer)
+        .with(errors)
+        .with(filter)
+        .try_init()
+        .map_err(|e| format!("Failed to initialize logging: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn log_args(level: LogLevel, log_file: Option<&str>, quiet: bool) -> LogArgs {
+        LogArgs {
+            log_level: level,
+            log_file: log_file.map(str::to_string),
+            log_format: LogFormat::Text,
+            errors: ErrorFormat::Text,
+            quiet,
+        }
+    }
+
+    #[test]
+    fn test_quiet_caps_stderr_at_warnings() {
+        assert_eq!(log_args(LogLevel::Info, None, false).effective_level(), LevelFilter::INFO);
+        assert_eq!(log_args(LogLevel::Info, None, true).effective_level(), LevelFilter::WARN);
+        assert_eq!(log_args(LogLevel::Error, None, true).effective_level(), LevelFilter::ERROR);
+    }
+
+    #[derive(Clone, Default)]
+    struct Buffer(std::sync::Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'a> MakeWriter<'a> for Buffer {
+        type Writer = Buffer;
+
+        fn make_writer(&'a self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    #[test]
+    fn test_json_errors() {
+        let buffer = Buffer::default();
+        let subscriber = tracing_subscriber::registry().with(JsonErrors { writer: buffer.clone() });
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::error!(code = "invalid_image", path = "a.jpg", "File not found: {}", "a.jpg");
+            tracing::warn!(file = "b.jpg", "Slow response");
+            tracing::info!(total = 2, "Run complete");
+        });
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                r#"{"level":"error","code":"invalid_image","path":"a.jpg","detail":"File not found: a.jpg"}"#,
+                r#"{"level":"warn","code":"warning","path":"b.jpg","detail":"Slow response"}"#,
+                r#"{"level":"info","detail":"Run complete","total":2}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_text_fields_leave_out_code_and_path() {
+        let buffer = Buffer::default();
+        let layer = fmt::layer()
+            .without_time()
+            .with_target(false)
+            .with_ansi(false)
+            .fmt_fields(TextFields)
+            .with_writer(buffer.clone());
+        tracing::subscriber::with_default(tracing_subscriber::registry().with(layer), || {
+            tracing::error!(code = "invalid_image", path = "a.jpg", "File not found: a.jpg");
+            tracing::warn!(file = "b.jpg", "Slow response");
+        });
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(output, "ERROR File not found: a.jpg\n WARN Slow response file=\"b.jpg\"\n");
+    }
+
+    #[test]
+    fn test_quiet_leaves_log_file_level_alone() {
+        let args = log_args(LogLevel::Debug, Some("run.log"), true);
+        assert_eq!(args.effective_level(), LevelFilter::DEBUG);
+    }
+}
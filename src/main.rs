@@ -1,277 +1,4457 @@
-use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
-use clap::Parser;
-use serde::{Deserialize, Serialize};
+mod alt_text;
+mod chat;
+mod daemon;
+#[cfg(feature = "serve")]
+mod enqueue;
+mod exec;
+#[cfg(feature = "kafka")]
+mod kafka;
+mod listen;
+mod logging;
+mod mcp;
+mod pretty;
+mod progress;
+mod queue;
+#[cfg(feature = "redis")]
+mod redis;
+mod review;
+#[cfg(feature = "serve")]
+mod serve;
+mod shutdown;
+mod term_image;
+
+use clap::{Args, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use serde::Serialize;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::io::{self, BufRead};
-use std::path::Path;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::{Component, Path, PathBuf};
 use std::process::ExitCode;
-use std::time::Duration;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tracing::{debug, error, info, warn};
+
+use nineladies::backend::{build_client, call_embed, find_model, model_available, parse_model_content, TokenSink};
+use nineladies::assertions::{self, Assertion, AssertionResult};
+use nineladies::batch::{BatchHooks, BatchRunner, Described, Input, ItemError, Transform};
+use nineladies::bundle::{self, Manifest, OutputFile};
+use nineladies::caption_report;
+use nineladies::config::{self, ConfigArgs, Endpoint, Settings};
+use nineladies::confidence::ConfidenceMethod;
+use nineladies::convert::{self, ConvertFormat, Table};
+use nineladies::cost::{self, Prices, TokenUsage};
+use nineladies::diff::{self, MatchBy};
+use nineladies::download::{self, DownloadOptions, Downloader};
+use nineladies::merge::{self, Conflict};
+use nineladies::duplicates;
+use nineladies::exif::ExifData;
+use nineladies::flatten::{Flatten, ListPolicy};
+use nineladies::grounding;
+use nineladies::images::sha256_file;
+use nineladies::input_manifest::InputManifest;
+use nineladies::language::Language;
+use nineladies::labels::LabelSet;
+use nineladies::latency::Timing;
+use nineladies::lock::FileLock;
+use nineladies::memory::MemoryBudget;
+use nineladies::nsfw::{NsfwAction, NsfwFilter};
+use nineladies::ocr::Ocr;
+use nineladies::output_check::{self, Expectations};
+use nineladies::output_file::{LockedOutput, OutputMode};
+use nineladies::oversize::{OversizePolicy, SizeLimit};
+use nineladies::paths::{self, long_path};
+use nineladies::presets::Preset;
+use nineladies::prompt::load_localized_prompt_config;
+use nineladies::products::parse_products;
+use nineladies::replay::Fixtures;
+use nineladies::report;
+use nineladies::run_window::RunWindow;
+use nineladies::schema;
+use nineladies::search::{self, EmbeddingCache};
+use nineladies::secrets;
+use nineladies::sinks::{embedding_text, QdrantSink, RecordSink};
+use nineladies::store::{FileStamp, JobStatus, JobStore};
+use nineladies::throttle::Throttle;
+use nineladies::trace::{utc_timestamp, Tracer};
+use nineladies::video::{self, FrameExtractor};
+use nineladies::{load_prompt_config, read_output_records, validate_image_file};
+use nineladies::{Api, Backend, CallError, ContextMode, EmbedSource, OutputRecord, PromptConfig, DEFAULT_URL};
+use listen::{ListenAddress, Listener, Received};
+use logging::LogArgs;
+use progress::{Progress, ProgressMode};
+use review::ReviewHooks;
+use shutdown::Shutdown;
+use term_image::ImageProtocol;
+
+/// Environment variable holding the Postgres connection string for `--pg-table`
+const PG_URL_ENV: &str = "NINELADIES_PG_URL";
+
+/// Environment variable holding an optional Qdrant API key for `--qdrant`
+const QDRANT_API_KEY_ENV: &str = "NINELADIES_QDRANT_API_KEY";
+
+/// Allowance for the JSON structure around the prompt and image in a request body
+const REQUEST_OVERHEAD_BYTES: usize = 256;
+
+/// Clusters of alike captions warned about after a run; the rest are in the report file
+const CAPTION_CLUSTERS_SHOWN: usize = 3;
 
 #[derive(Parser)]
 #[command(name = "9ladies")]
 #[command(about = "Batch image description tool using VLMs via Ollama")]
-struct Args {
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    log: LogArgs,
+
+    #[command(flatten)]
+    config: ConfigArgs,
+}
+
+impl Cli {
+    /// Running without a subcommand is the same as `9ladies run`, so the run flags are also
+    /// accepted at the top level. Returns the CLI along with those top-level run args when
+    /// no subcommand was given.
+    fn parse_with_default_run<I, T>(args: I) -> Result<(Cli, Option<RunArgs>), clap::Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<std::ffi::OsString> + Clone,
+    {
+        let matches = RunArgs::augment_args(Cli::command()).try_get_matches_from(args)?;
+        let cli = Cli::from_arg_matches(&matches)?;
+        let default_run = match cli.command {
+            Some(_) => None,
+            None => Some(RunArgs::from_arg_matches(&matches)?),
+        };
+        Ok((cli, default_run))
+    }
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Describe images whose paths are read from stdin (the default)
+    Run(Box<RunArgs>),
+    /// Check the prompt file and images from stdin without contacting the model
+    Validate(ValidateArgs),
+    /// Check an output file: records parse, no duplicates, responses follow the schema
+    ValidateOutput(ValidateOutputArgs),
+    /// Summarize an existing output file
+    Report(ReportArgs),
+    /// Compare two output files: files added and removed, and responses that changed
+    Diff(DiffArgs),
+    /// Combine output files into one, keeping one record per image
+    Merge(MergeArgs),
+    /// Convert an output file to CSV, HTML, Markdown or Parquet
+    Convert(ConvertArgs),
+    /// Find images in existing output files whose descriptions match a free-text query
+    Search(SearchArgs),
+    /// Show how many inputs in a job store are pending, in progress, done or failed
+    Jobs(JobsArgs),
+    /// List the models available on the server
+    Models(ModelsArgs),
+    /// List the built-in prompt presets, or print one as a prompt config to start from
+    Presets(PresetsArgs),
+    /// Send an existing output file to Postgres, DuckDB or Qdrant without re-running the model
+    Export(ExportArgs),
+    /// Ask questions about one image interactively, to try out prompts before a batch run
+    Chat(ChatArgs),
+    /// Step through an output file with image previews to accept, edit or flag each record
+    Review(ReviewArgs),
+    /// Write alt text for images in HTML and Markdown files that have none
+    AltText(AltTextArgs),
+    /// Group duplicate and near-duplicate photos, suggesting which copy to keep
+    Duplicates(DuplicatesArgs),
+    /// Ask the prompt about pairs of images, sending both in one request (before/after, spot the difference)
+    Compare(CompareArgs),
+    /// Describe each product once from all of its shots, grouped by SKU
+    Products(ProductsArgs),
+    /// Ask a further question about each image in an output file, given its earlier answer
+    Followup(FollowupArgs),
+    /// Describe frames taken from videos at an interval, then summarize each video from them
+    Video(VideoArgs),
+    /// Check screenshots against expected conditions and values, failing if any don't hold
+    Assert(AssertArgs),
+    /// Describe images posted to an HTTP endpoint, for use as a microservice
+    #[cfg(feature = "serve")]
+    Serve(ServeArgs),
+    /// Offer describe_image and extract_from_image tools over the Model Context Protocol on stdio
+    Mcp(McpArgs),
+    /// Describe images named by jobs on a Kafka topic and publish the results to another
+    #[cfg(feature = "kafka")]
+    Kafka(KafkaArgs),
+    /// Describe images named by jobs on a Redis list and write the results to a list or stream
+    #[cfg(feature = "redis")]
+    Redis(RedisArgs),
+    /// Watch directories and describe new and changed images, tracking each file in a state database
+    Daemon(DaemonArgs),
+}
+
+#[derive(Args)]
+struct RunArgs {
     /// Path to prompt configuration JSON file
+    #[arg(long, env = "NINELADIES_PROMPT", required_unless_present = "preset")]
+    prompt: Option<String>,
+
+    /// Use a built-in prompt config instead of --prompt
+    #[arg(long, value_enum, conflicts_with = "prompt")]
+    preset: Option<Preset>,
+
+    /// Use the prompt file's text for this language code (e.g. de, pt-BR), from its `locales`
+    #[arg(long, env = "NINELADIES_LOCALE", requires = "prompt")]
+    locale: Option<String>,
+
+    /// Server URL (e.g. http://localhost:8080 for llama.cpp) [default: http://localhost:11434]
+    #[arg(long, env = "NINELADIES_URL")]
+    url: Option<String>,
+
+    /// Spread the run over this server, taking up to N requests at once (URL=N, default 1); repeat for more servers
+    #[arg(long, value_parser = Endpoint::parse, conflicts_with = "url")]
+    endpoint: Vec<Endpoint>,
+
+    /// Model name (required for Ollama, e.g. qwen2.5vl:32b or llava:13b)
+    #[arg(long, env = "NINELADIES_MODEL")]
+    model: Option<String>,
+
+    /// Server API: Ollama's native /api/chat, or an OpenAI-compatible /v1/chat/completions
+    #[arg(long, value_enum, env = "NINELADIES_API")]
+    api: Option<Api>,
+
+    /// Request timeout in seconds [default: 120]
+    #[arg(long, env = "NINELADIES_TIMEOUT")]
+    timeout: Option<u64>,
+
+    /// Add this many seconds to --timeout for each megapixel of the image, so big images get longer
+    #[arg(long, value_name = "SECS")]
+    timeout_per_mp: Option<f64>,
+
+    /// Give up on a reply once the server has sent nothing for this many seconds
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    stall_timeout: Option<u64>,
+
+    /// Record what a reply had said when the timeout or --stall-timeout cuts it off, marked truncated, instead of failing the file
+    #[arg(long)]
+    keep_partial: bool,
+
+    /// Send at most this many model requests a minute, however many run at once
+    #[arg(long, env = "NINELADIES_RPM", conflicts_with = "rps")]
+    rpm: Option<f64>,
+
+    /// Send at most this many model requests a second
+    #[arg(long)]
+    rps: Option<f64>,
+
+    /// Write each model request and the server's raw response to a timestamped file in this folder
+    #[arg(long)]
+    trace_dir: Option<PathBuf>,
+
+    /// Keep the image data in traced requests instead of its size and SHA-256
+    #[arg(long, requires = "trace_dir")]
+    trace_images: bool,
+
+    /// Save the server's reply to each model request as a fixture file in this folder, for --replay
+    #[arg(long)]
+    record: Option<PathBuf>,
+
+    /// Answer model requests from the fixture files --record saved in this folder, without a server
+    #[arg(long, conflicts_with = "record")]
+    replay: Option<PathBuf>,
+
+    /// After the run, write a tar archive of what's needed to reproduce it: the config,
+    /// model and digest, seed, 9ladies version and a hash of each input
+    #[arg(long, value_name = "FILE")]
+    repro_bundle: Option<PathBuf>,
+
+    /// Check the inputs against this list of paths and SHA-256 hashes (sha256sum output or JSON)
+    /// before the run, and each file again as it is read
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["listen_input", "retry_failed"])]
+    input_manifest: Option<PathBuf>,
+
+    /// After the run, write clusters of identical or near-identical descriptions given to
+    /// different images to this JSON file
+    #[arg(long, value_name = "FILE")]
+    caption_report: Option<PathBuf>,
+
+    /// Percent of all the distinct words in a pair of captions that both share (Jaccard) to count as near-identical
+    #[arg(long, default_value_t = caption_report::DEFAULT_SIMILARITY, requires = "caption_report", value_parser = clap::value_parser!(u32).range(1..=100))]
+    caption_similarity: u32,
+
+    /// Price in USD per million input tokens, to estimate and report cost on paid APIs
+    #[arg(long, env = "NINELADIES_INPUT_PRICE")]
+    input_price: Option<f64>,
+
+    /// Price in USD per million output tokens
+    #[arg(long, env = "NINELADIES_OUTPUT_PRICE")]
+    output_price: Option<f64>,
+
+    /// Preflight: check the server, model and images and estimate the upload, without sending images
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Write these response fields as Finder tags on each processed file (macOS only, comma-separated)
+    #[arg(long, value_delimiter = ',')]
+    finder_tags: Vec<String>,
+
+    /// Rename each file from a template, e.g. '{date}_{response.subject}.{ext}'
+    #[arg(long)]
+    rename_template: Option<String>,
+
+    /// Move each file into a folder from a template, e.g. 'sorted/{response.category}/'
+    #[arg(long, conflicts_with = "copy_to")]
+    move_to: Option<String>,
+
+    /// Copy each file into a folder from a template, leaving the original in place
+    #[arg(long)]
+    copy_to: Option<String>,
+
+    /// Print planned file actions (rename, move, copy) to stderr without applying them
+    #[arg(long)]
+    preview: bool,
+
+    /// Ask for replies in this language (a name like 'German' or a code like 'de'), retrying once if one comes back in another
+    #[arg(long, value_parser = Language::parse)]
+    language: Option<Language>,
+
+    /// Also translate each response into these languages with a text-only request each, as 'response.translations' (comma-separated)
+    #[arg(long, value_delimiter = ',', value_parser = Language::parse)]
+    translate: Vec<Language>,
+
+    /// Lua script defining transform_prompt(ctx) and/or transform_response(record) hooks
+    #[arg(long)]
+    script: Option<String>,
+
+    /// Read barcodes and QR codes locally and add them to each record as 'barcodes'
+    #[arg(long)]
+    barcodes: bool,
+
+    /// Add each image's width, height, format and file size to its record as 'image', read from the header
+    #[arg(long)]
+    image_info: bool,
+
+    /// Add size, dominant colors and sharpness to each record as 'image_stats', and with 'prompt' to the question too
+    #[arg(long, value_enum, num_args = 0..=1, default_missing_value = "record")]
+    image_stats: Option<ContextMode>,
+
+    /// Add the photo's EXIF capture time, camera, GPS position and orientation as 'exif', and with 'prompt' to the question too
+    #[arg(long, value_enum, num_args = 0..=1, default_missing_value = "record")]
+    exif: Option<ContextMode>,
+
+    /// 'embed' also adds a vector from --embed-model to each record as 'embedding'
+    #[arg(long, value_enum, default_value = "describe")]
+    mode: RunMode,
+
+    /// What --mode embed embeds: the description, or the image itself with a multimodal embedding model (--api openai)
+    #[arg(long, value_enum, default_value = "caption")]
+    embed_input: EmbedSource,
+
+    /// Classify each image as one of these labels instead of describing it: a comma-separated list, or a file with one per line
+    #[arg(long, value_parser = LabelSet::parse)]
+    labels: Option<LabelSet>,
+
+    /// Also record the K likeliest labels, with the model's confidence in each, as 'top_labels'
+    #[arg(long, requires = "labels", value_parser = clap::value_parser!(u64).range(2..))]
+    top_k: Option<u64>,
+
+    /// Score each response from 0 to 1 as 'confidence', by asking again and comparing answers, by asking the model to rate its own, or from the probabilities of its tokens
+    #[arg(long, value_enum)]
+    confidence: Option<ConfidenceMethod>,
+
+    /// Answers to compare in all for --confidence agreement, including the one kept
+    #[arg(long, default_value_t = 3, value_parser = clap::value_parser!(u64).range(2..))]
+    confidence_samples: u64,
+
+    /// Ask for N answers about each image and record them all as 'candidates', for ranking or picking by hand
+    #[arg(long, default_value_t = 1, value_name = "N", conflicts_with = "labels", value_parser = clap::value_parser!(u64).range(1..))]
+    candidates: u64,
+
+    /// Parse bounding boxes in responses (bbox_2d, bbox, box_2d) into a normalized 'detections' field
+    #[arg(long)]
+    detections: bool,
+
+    /// Save a copy of each image with its detections outlined in this folder (implies --detections)
+    #[arg(long)]
+    annotate_dir: Option<PathBuf>,
+
+    #[command(flatten)]
+    ocr: OcrArgs,
+
+    #[command(flatten)]
+    nsfw: NsfwArgs,
+
+    #[command(flatten)]
+    faces: FaceArgs,
+
+    #[command(flatten)]
+    size_limit: SizeLimitArgs,
+
+    #[command(flatten)]
+    download: DownloadArgs,
+
+    /// How records are printed to stdout: JSONL, or wrapped and colorized for reading
+    #[arg(long, value_enum, default_value = "jsonl")]
+    format: OutputFormat,
+
+    /// Write the JSONL records to this file (stdout then only carries --format pretty)
+    #[arg(long, short)]
+    output: Option<String>,
+
+    /// Add the records to the end of an --output file that already has some
+    #[arg(long, requires = "output", conflicts_with = "overwrite")]
+    append: bool,
+
+    /// Replace an --output file that already has records
+    #[arg(long, requires = "output", conflicts_with_all = ["resume", "retry_failed"])]
+    overwrite: bool,
+
+    /// Sort the keys of JSON responses at every level, instead of keeping the model's order
+    #[arg(long)]
+    sort_keys: bool,
+
+    /// Show each reply on stderr as the model writes it, under the file's name (no progress bar)
+    #[arg(long, conflicts_with = "endpoint")]
+    stream: bool,
+
+    /// Progress reporting: a bar on terminals (auto), JSON events, or none
+    #[arg(long, value_enum, default_value = "auto")]
+    progress: ProgressMode,
+
+    /// Write --progress json events to this already-open file descriptor instead of stderr
+    #[arg(long)]
+    progress_fd: Option<i32>,
+
+    /// Stop at the first file that fails
+    #[arg(long, conflicts_with = "max_errors")]
+    fail_fast: bool,
+
+    /// Stop once this many files have failed
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    max_errors: Option<u64>,
+
+    /// Run this shell command after each image, successful or not, e.g. 'thumb {file} {response_json}'
+    #[arg(long, env = "NINELADIES_EXEC")]
+    exec: Option<String>,
+
+    /// Record each input's status in this SQLite job store, for --resume, --retry-failed and `9ladies jobs`
+    #[arg(long, env = "NINELADIES_JOB_STORE")]
+    job_store: Option<String>,
+
+    /// Skip inputs the job store already has as done, appending to --output instead of replacing it
+    #[arg(long, requires = "job_store")]
+    resume: bool,
+
+    /// Describe the inputs the job store has as failed, instead of reading paths from stdin
+    #[arg(long, requires = "job_store", conflicts_with = "resume")]
+    retry_failed: bool,
+
+    /// Take jobs from this socket as they arrive (unix:<path>, <host>:<port>, or :<port> on localhost), instead of paths from stdin
+    #[arg(long, value_parser = ListenAddress::parse, conflicts_with_all = ["resume", "retry_failed", "endpoint"])]
+    listen_input: Option<ListenAddress>,
+
+    /// Only send requests between these local times (HH:MM-HH:MM, e.g. 22:00-06:00), pausing outside them
+    #[arg(long, value_parser = RunWindow::parse)]
+    run_window: Option<RunWindow>,
+
+    /// Hold back images while those in progress are expected to take more than this many MB (URL downloads fetched ahead aren't counted)
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    max_memory_mb: Option<u64>,
+
+    /// POST completed records and/or a run summary as JSON to this URL
+    #[arg(long)]
+    webhook: Option<String>,
+
+    /// Which events to send to the webhook
+    #[arg(long, value_enum, default_value = "all", requires = "webhook")]
+    webhook_events: WebhookEvents,
+
+    #[command(flatten)]
+    sinks: SinkArgs,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum RunMode {
+    /// Ask the prompt about each image
+    Describe,
+    /// Describe and embed each image (or with --embed-input image, only embed it)
+    Embed,
+}
+
+/// The embedding model for `--mode embed`, if it's on, checked against the server API and
+/// `--labels`, which needs a reply to classify.
+fn embedding_model(
+    mode: RunMode,
+    source: EmbedSource,
+    model: Option<&String>,
+    api: Api,
+    labels: bool,
+) -> Result<Option<String>, String> {
+    if mode == RunMode::Describe {
+        return Ok(None);
+    }
+    let model = model.ok_or("--mode embed needs --embed-model")?;
+    if source == EmbedSource::Image && labels {
+        return Err("--labels can't be used with --embed-input image, which doesn't ask the model anything".to_string());
+    }
+    if source == EmbedSource::Image && api == Api::Ollama {
+        return Err(
+            "--embed-input image needs --api openai and a multimodal embedding model; Ollama only embeds text".to_string(),
+        );
+    }
+    Ok(Some(model.clone()))
+}
+
+#[derive(Args)]
+struct OcrArgs {
+    /// Read each image's text with Tesseract first and add it to the prompt for the model to correct
+    #[arg(long)]
+    ocr: bool,
+
+    /// Tesseract language(s) for --ocr, e.g. 'eng+deu'
+    #[arg(long, requires = "ocr")]
+    ocr_lang: Option<String>,
+}
+
+impl OcrArgs {
+    /// The OCR engine to use, checked to be installed. A preset that reads text turns it on
+    /// when Tesseract is installed, and otherwise works from the image alone.
+    fn engine(&self, preset: Option<Preset>) -> Result<Option<Ocr>, String> {
+        if !self.ocr {
+            let Some(preset) = preset.filter(|preset| preset.ocr()) else {
+                return Ok(None);
+            };
+            let ocr = Ocr::new("tesseract", None);
+            if let Err(e) = ocr.check() {
+                warn!("--preset {} reads text more accurately with Tesseract installed ({})", preset_name(preset), e);
+                return Ok(None);
+            }
+            return Ok(Some(ocr));
+        }
+        let ocr = Ocr::new("tesseract", self.ocr_lang.clone());
+        ocr.check()?;
+        Ok(Some(ocr))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum NsfwActionArg {
+    /// Don't send the image to the model; its record has a null response
+    Skip,
+    /// Describe it as usual and mark the record
+    Flag,
+    /// Describe it with --nsfw-prompt, --nsfw-model and/or --nsfw-url instead
+    Route,
+}
+
+#[derive(Args)]
+struct NsfwArgs {
+    /// Score each image with this command (image on stdin, score 0-1 on stdout) before sending it anywhere
+    #[arg(long, env = "NINELADIES_NSFW_COMMAND")]
+    nsfw_command: Option<String>,
+
+    /// Score at or above which an image counts as NSFW
+    #[arg(long, default_value = "0.7", requires = "nsfw_command")]
+    nsfw_threshold: f64,
+
+    /// What to do with images over the threshold
+    #[arg(long, value_enum, default_value = "skip", requires = "nsfw_command")]
+    nsfw_action: NsfwActionArg,
+
+    /// Prompt configuration for images routed with --nsfw-action route
     #[arg(long)]
+    nsfw_prompt: Option<String>,
+
+    /// Model for images routed with --nsfw-action route
+    #[arg(long)]
+    nsfw_model: Option<String>,
+
+    /// Server for images routed with --nsfw-action route, e.g. a local one (same --api)
+    #[arg(long)]
+    nsfw_url: Option<String>,
+}
+
+impl NsfwArgs {
+    fn filter(&self) -> Result<Option<NsfwFilter>, String> {
+        let Some(command) = &self.nsfw_command else {
+            return Ok(None);
+        };
+        let action = match self.nsfw_action {
+            NsfwActionArg::Skip => NsfwAction::Skip,
+            NsfwActionArg::Flag => NsfwAction::Flag,
+            NsfwActionArg::Route => {
+                if self.nsfw_prompt.is_none() && self.nsfw_model.is_none() && self.nsfw_url.is_none() {
+                    return Err("--nsfw-action route needs --nsfw-prompt, --nsfw-model or --nsfw-url".to_string());
+                }
+                NsfwAction::Route {
+                    config: self.nsfw_prompt.as_deref().map(load_prompt_config).transpose()?.map(Box::new),
+                    model: self.nsfw_model.clone(),
+                    url: self.nsfw_url.clone(),
+                }
+            }
+        };
+        Ok(Some(NsfwFilter::new(command, self.nsfw_threshold, action)))
+    }
+}
+
+#[derive(Args)]
+struct FaceArgs {
+    /// Blur faces found by a local detector in the copy of each image sent to the model
+    #[arg(long, requires = "face_model")]
+    blur_faces: bool,
+
+    /// SeetaFace detection model for --blur-faces (seeta_fd_frontal_v1.0.bin)
+    #[arg(long, env = "NINELADIES_FACE_MODEL")]
+    face_model: Option<String>,
+}
+
+#[derive(Args)]
+struct SizeLimitArgs {
+    /// Largest image to send, in megapixels; see --oversized for larger ones
+    #[arg(long)]
+    max_megapixels: Option<f64>,
+
+    /// Largest image file to send, in MB; see --oversized for larger ones
+    #[arg(long)]
+    max_image_mb: Option<f64>,
+
+    /// What to do with images over --max-megapixels or --max-image-mb
+    #[arg(long, value_enum, default_value = "reject")]
+    oversized: OversizePolicy,
+}
+
+impl SizeLimitArgs {
+    fn limit(&self) -> Option<SizeLimit> {
+        (self.max_megapixels.is_some() || self.max_image_mb.is_some()).then(|| SizeLimit {
+            max_megapixels: self.max_megapixels,
+            max_bytes: self.max_image_mb.map(|mb| (mb * 1_000_000.0) as u64),
+            policy: self.oversized,
+        })
+    }
+}
+
+/// Limits on downloading `http://` and `https://` inputs.
+#[derive(Args)]
+struct DownloadArgs {
+    /// URL inputs to download at once, ahead of the image being described
+    #[arg(long, default_value_t = 4, value_parser = clap::value_parser!(u64).range(1..))]
+    download_concurrency: u64,
+
+    /// Largest image to download from a URL input, in MB
+    #[arg(long)]
+    download_max_mb: Option<f64>,
+
+    /// Most MB per second to download, across all downloads
+    #[arg(long)]
+    download_rate_mb: Option<f64>,
+
+    /// Least time between requests to the same host, in seconds
+    #[arg(long)]
+    download_host_delay: Option<f64>,
+}
+
+impl DownloadArgs {
+    fn downloader(&self) -> Result<Downloader, String> {
+        if self.download_rate_mb.is_some_and(|rate| !rate.is_finite() || rate <= 0.0) {
+            return Err("--download-rate-mb must be above 0".to_string());
+        }
+        let host_delay = self
+            .download_host_delay
+            .map(Duration::try_from_secs_f64)
+            .transpose()
+            .map_err(|e| format!("Invalid --download-host-delay: {}", e))?;
+        Downloader::new(DownloadOptions {
+            concurrency: self.download_concurrency as usize,
+            max_bytes: self.download_max_mb.map(|mb| (mb * 1_000_000.0) as u64),
+            bytes_per_sec: self.download_rate_mb.map(|mb| (mb * 1_000_000.0).max(1.0) as u64),
+            host_delay: host_delay.unwrap_or_default(),
+        })
+    }
+}
+
+#[derive(Args)]
+struct SinkArgs {
+    /// Also insert records into this Postgres table (connection string from NINELADIES_PG_URL)
+    #[arg(long)]
+    pg_table: Option<String>,
+
+    /// Also append records to a table in this DuckDB database file
+    #[arg(long)]
+    duckdb: Option<String>,
+
+    /// Table to append to with --duckdb
+    #[arg(long, default_value = "results")]
+    duckdb_table: String,
+
+    /// Embed each response and upsert it into this Qdrant server (e.g. http://localhost:6333)
+    #[arg(long, requires = "embed_model")]
+    qdrant: Option<String>,
+
+    /// Qdrant collection to upsert into (created on first use)
+    #[arg(long, default_value = "9ladies")]
+    qdrant_collection: String,
+
+    /// Embedding model for --qdrant (Ollama) and --mode embed (e.g. nomic-embed-text)
+    #[arg(long)]
+    embed_model: Option<String>,
+
+    #[command(flatten)]
+    flatten: FlattenArgs,
+}
+
+#[derive(Args)]
+struct FlattenArgs {
+    /// Give each nested response field a column of its own, named with dots (vendor.name)
+    #[arg(long)]
+    flatten: bool,
+
+    /// How --flatten handles lists: keep as JSON, join the items, or a column per item
+    #[arg(long, value_enum, default_value = "json", requires = "flatten")]
+    flatten_lists: ListPolicy,
+
+    /// Put between list items joined by --flatten-lists join
+    #[arg(long, default_value = "; ", requires = "flatten")]
+    list_separator: String,
+}
+
+impl FlattenArgs {
+    fn flatten(&self) -> Option<Flatten> {
+        self.flatten.then(|| Flatten {
+            lists: self.flatten_lists,
+            separator: self.list_separator.clone(),
+        })
+    }
+}
+
+#[derive(Args)]
+struct ValidateArgs {
+    /// Path to prompt configuration JSON file
+    #[arg(long, env = "NINELADIES_PROMPT")]
     prompt: String,
+}
+
+#[derive(Args)]
+struct ValidateOutputArgs {
+    /// Output JSONL file to check
+    input: String,
 
-    /// Server URL (e.g. http://localhost:8080 for llama.cpp, http://localhost:11434 for Ollama)
+    /// Prompt config whose schema every response must follow
     #[arg(long)]
-    url: String,
+    prompt: Option<String>,
 
-    /// Model name (required for Ollama, e.g. qwen2.5vl:32b or llava:13b)
+    /// Hold responses to a built-in preset's schema instead
+    #[arg(long, value_enum, conflicts_with = "prompt")]
+    preset: Option<Preset>,
+
+    /// A response field every record must have, as a dotted path (repeatable)
+    #[arg(long = "require", value_name = "FIELD")]
+    required: Vec<String>,
+
+    /// How many records there should be
+    #[arg(long)]
+    count: Option<usize>,
+
+    /// A --repro-bundle archive, or its manifest.json, to check the count and file hash against
+    #[arg(long)]
+    manifest: Option<PathBuf>,
+
+    /// Print the result as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Args)]
+struct JobsArgs {
+    /// Job store written by `run --job-store` or `daemon --state`
+    store: String,
+
+    /// List the inputs with this status (pending, in_progress, done or failed) instead of counting
+    #[arg(long, value_parser = JobStatus::parse)]
+    status: Option<JobStatus>,
+
+    /// Print as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Args)]
+struct ReportArgs {
+    /// Output JSONL file to summarize
+    input: String,
+
+    /// Print the report as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Args)]
+struct DiffArgs {
+    /// The earlier output JSONL file
+    old: String,
+
+    /// The later output JSONL file
+    new: String,
+
+    /// Match records by image path, or by SHA-256 to follow renamed and moved images
+    #[arg(long, value_enum, default_value_t = MatchBy::File)]
+    by: MatchBy,
+
+    /// Print the differences as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Args)]
+struct MergeArgs {
+    /// Output JSONL files to combine, oldest first
+    #[arg(required = true)]
+    inputs: Vec<String>,
+
+    /// Match records by image path, or by SHA-256 to follow renamed and moved images
+    #[arg(long, value_enum, default_value_t = MatchBy::File)]
+    by: MatchBy,
+
+    /// Which record to keep for an image in more than one file
+    #[arg(long, value_enum, default_value_t = Conflict::Newest)]
+    on_conflict: Conflict,
+
+    /// Write the merged records to this file instead of stdout; it may be one of the inputs
+    #[arg(long, short)]
+    output: Option<String>,
+}
+
+#[derive(Args)]
+struct ConvertArgs {
+    /// Output JSONL file to convert
+    input: String,
+
+    /// Format to convert to
+    #[arg(long, value_enum)]
+    to: ConvertFormat,
+
+    /// Write to this file instead of stdout; required for Parquet
+    #[arg(long, short, required_if_eq("to", "parquet"))]
+    output: Option<String>,
+
+    #[command(flatten)]
+    flatten: FlattenArgs,
+}
+
+#[derive(Args)]
+struct SearchArgs {
+    /// What to look for, e.g. "dog on a beach at sunset"
+    query: String,
+
+    /// Output JSONL files to search
+    #[arg(required = true)]
+    inputs: Vec<String>,
+
+    /// Ollama embedding model (e.g. nomic-embed-text)
+    #[arg(long, env = "NINELADIES_EMBED_MODEL")]
+    embed_model: String,
+
+    /// Ollama server URL [default: http://localhost:11434]
+    #[arg(long, env = "NINELADIES_URL")]
+    url: Option<String>,
+
+    /// Number of matches to show
+    #[arg(long, default_value = "10")]
+    limit: usize,
+
+    /// Leave out matches with a lower similarity, from -1 to 1
+    #[arg(long)]
+    min_score: Option<f32>,
+
+    /// Print matches as JSON lines with the full record
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Args)]
+struct ModelsArgs {
+    /// Server URL [default: http://localhost:11434]
+    #[arg(long, env = "NINELADIES_URL")]
+    url: Option<String>,
+
+    /// Server API
+    #[arg(long, value_enum, env = "NINELADIES_API")]
+    api: Option<Api>,
+}
+
+#[derive(Args)]
+struct PresetsArgs {
+    /// Print this preset's prompt config as JSON, e.g. to save and adapt as a --prompt file
+    preset: Option<Preset>,
+}
+
+#[derive(Args)]
+struct ChatArgs {
+    /// Image to ask about
+    image: String,
+
+    /// Take the system prompt, temperature and model from this prompt configuration
+    #[arg(long, env = "NINELADIES_PROMPT")]
+    prompt: Option<String>,
+
+    /// Server URL [default: http://localhost:11434]
+    #[arg(long, env = "NINELADIES_URL")]
+    url: Option<String>,
+
+    /// Model name (required for Ollama)
+    #[arg(long, env = "NINELADIES_MODEL")]
+    model: Option<String>,
+
+    /// Server API
+    #[arg(long, value_enum, env = "NINELADIES_API")]
+    api: Option<Api>,
+
+    /// Request timeout in seconds [default: 120]
+    #[arg(long, env = "NINELADIES_TIMEOUT")]
+    timeout: Option<u64>,
+}
+
+#[derive(Args)]
+struct AltTextArgs {
+    /// HTML or Markdown files, or directories to search for them (.md, .markdown and .mdx are read as Markdown)
+    #[arg(required = true)]
+    files: Vec<String>,
+
+    /// Patch the files in place instead of printing a unified diff
+    #[arg(long, conflicts_with = "audit")]
+    in_place: bool,
+
+    /// Report on every image's alt text as JSONL instead: missing, empty, placeholder, or
+    /// scored by the model against the image, with suggested replacements
+    #[arg(long)]
+    audit: bool,
+
+    /// With --audit, flag alt text the model scores below this, from 0 to 10
+    #[arg(long, default_value = "4", requires = "audit")]
+    mismatch_below: f64,
+
+    /// Directory that image paths starting with `/` are relative to, e.g. the site's public folder
     #[arg(long)]
+    root: Option<PathBuf>,
+
+    /// Use this prompt configuration instead of the built-in alt text prompt
+    #[arg(long, env = "NINELADIES_PROMPT")]
+    prompt: Option<String>,
+
+    /// Server URL [default: http://localhost:11434]
+    #[arg(long, env = "NINELADIES_URL")]
+    url: Option<String>,
+
+    /// Model name (required for Ollama)
+    #[arg(long, env = "NINELADIES_MODEL")]
     model: Option<String>,
 
-    /// Validate inputs without calling the model
-    #[arg(long)]
-    dry_run: bool,
-}
+    /// Server API
+    #[arg(long, value_enum, env = "NINELADIES_API")]
+    api: Option<Api>,
+
+    /// Request timeout in seconds [default: 120]
+    #[arg(long, env = "NINELADIES_TIMEOUT")]
+    timeout: Option<u64>,
+}
+
+#[derive(Args)]
+struct DuplicatesArgs {
+    /// Images or directories to search [default: image paths read from stdin]
+    paths: Vec<String>,
+
+    /// Most perceptual hash bits (of 64) two photos may differ by and still be grouped
+    #[arg(long, default_value = "6", value_parser = clap::value_parser!(u32).range(0..=64))]
+    threshold: u32,
+
+    /// Ask the model whether each photo shows the same scene as the one to keep, and drop it
+    /// from the group if not
+    #[arg(long)]
+    confirm: bool,
+
+    /// Server URL, with --confirm [default: http://localhost:11434]
+    #[arg(long, env = "NINELADIES_URL")]
+    url: Option<String>,
+
+    /// Model name, with --confirm (required for Ollama)
+    #[arg(long, env = "NINELADIES_MODEL")]
+    model: Option<String>,
+
+    /// Server API
+    #[arg(long, value_enum, env = "NINELADIES_API")]
+    api: Option<Api>,
+
+    /// Request timeout in seconds [default: 120]
+    #[arg(long, env = "NINELADIES_TIMEOUT")]
+    timeout: Option<u64>,
+}
+
+#[derive(Args)]
+struct CompareArgs {
+    /// File of image pairs, one pair per line separated by a tab [default: pairs read from stdin]
+    pairs: Option<String>,
+
+    /// Path to a prompt configuration JSON file asking about the two images
+    #[arg(long, env = "NINELADIES_PROMPT")]
+    prompt: String,
+
+    #[command(flatten)]
+    server: ServerArgs,
+}
+
+#[derive(Args)]
+struct ProductsArgs {
+    /// JSONL of {"sku", "file"} lines, or CSV with 'sku' and 'file' columns [default: read from stdin]
+    shots: Option<String>,
+
+    /// Path to a prompt configuration JSON file asking about the product
+    #[arg(long, env = "NINELADIES_PROMPT")]
+    prompt: String,
+
+    /// Send at most this many shots per request, giving each later request the answer so far [default: all at once]
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    shots_per_request: Option<u64>,
+
+    #[command(flatten)]
+    server: ServerArgs,
+}
+
+#[derive(Args)]
+struct FollowupArgs {
+    /// Output JSONL file from an earlier run
+    #[arg(long)]
+    input: String,
+
+    /// Path to a prompt configuration JSON file with the new question
+    #[arg(long, env = "NINELADIES_PROMPT")]
+    prompt: String,
+
+    #[command(flatten)]
+    server: ServerArgs,
+}
+
+#[derive(Args)]
+struct VideoArgs {
+    /// Video files [default: paths read from stdin]
+    videos: Vec<String>,
+
+    /// Path to the prompt configuration JSON file asked about each frame
+    #[arg(long, env = "NINELADIES_PROMPT")]
+    prompt: String,
+
+    /// Seconds between the frames described
+    #[arg(long, default_value_t = 10.0)]
+    every: f64,
+
+    /// Prompt configuration for the summary pass, which gets the frame descriptions appended [default: built in]
+    #[arg(long)]
+    summary_prompt: Option<String>,
+
+    /// Only describe the frames, without the summary pass
+    #[arg(long, conflicts_with = "summary_prompt")]
+    no_summary: bool,
+
+    #[command(flatten)]
+    server: ServerArgs,
+}
+
+#[derive(Args)]
+struct AssertArgs {
+    /// JSONL file of checks, e.g. {"file": "login.png", "condition": "the login button is visible"} [default: stdin]
+    checks: Option<String>,
+
+    /// Prompt configuration asked about images whose checks 'expect' values of the response
+    #[arg(long, env = "NINELADIES_PROMPT")]
+    prompt: Option<String>,
+
+    /// Server URL [default: http://localhost:11434]
+    #[arg(long, env = "NINELADIES_URL")]
+    url: Option<String>,
+
+    /// Model name (required for Ollama)
+    #[arg(long, env = "NINELADIES_MODEL")]
+    model: Option<String>,
+
+    /// Server API
+    #[arg(long, value_enum, env = "NINELADIES_API")]
+    api: Option<Api>,
+
+    /// Request timeout in seconds [default: 120]
+    #[arg(long, env = "NINELADIES_TIMEOUT")]
+    timeout: Option<u64>,
+}
+
+/// The server and output of the subcommands that ask one prompt per item and write its
+/// record: `compare`, `products`, `followup` and `video`.
+#[derive(Args)]
+struct ServerArgs {
+    /// Server URL [default: http://localhost:11434]
+    #[arg(long, env = "NINELADIES_URL")]
+    url: Option<String>,
+
+    /// Model name (required for Ollama); `compare` and `products` need one that accepts more than one image per request
+    #[arg(long, env = "NINELADIES_MODEL")]
+    model: Option<String>,
+
+    /// Server API
+    #[arg(long, value_enum, env = "NINELADIES_API")]
+    api: Option<Api>,
+
+    /// Request timeout in seconds [default: 120]
+    #[arg(long, env = "NINELADIES_TIMEOUT")]
+    timeout: Option<u64>,
+
+    /// Ask for replies in this language, as with `run --language`
+    #[arg(long, value_parser = Language::parse)]
+    language: Option<Language>,
+
+    /// Write the JSONL records to this file instead of stdout
+    #[arg(long, short)]
+    output: Option<String>,
+
+    /// Add the records to the end of an --output file that already has some
+    #[arg(long, requires = "output", conflicts_with = "overwrite")]
+    append: bool,
+
+    /// Replace an --output file that already has records
+    #[arg(long, requires = "output")]
+    overwrite: bool,
+}
+
+impl ServerArgs {
+    /// The backend to ask, with the model from the flags, `config` or the config file, and
+    /// where the records go.
+    fn open(&self, config: &PromptConfig, settings: &Settings) -> Result<(Backend, Box<dyn Write>), String> {
+        let url = self.url.as_deref().or(settings.url.as_deref()).unwrap_or(DEFAULT_URL);
+        let api = self.api.or(settings.api).unwrap_or(Api::Ollama);
+        let model = self.model.clone().or_else(|| config.model.clone()).or_else(|| settings.model.clone());
+        if model.is_none() && api == Api::Ollama {
+            return Err("--model is required (or set 'model' in prompt config)".to_string());
+        }
+        let mode = match (self.append, self.overwrite) {
+            (true, _) => OutputMode::Append,
+            (_, true) => OutputMode::Overwrite,
+            _ => OutputMode::New,
+        };
+        let output: Box<dyn Write> = match &self.output {
+            Some(path) => Box::new(LockedOutput::open(Path::new(path), mode)?),
+            None => Box::new(io::stdout()),
+        };
+        Ok((Backend::new(api, url, model, self.timeout.or(settings.timeout)), output))
+    }
+}
+
+/// Items the `ServerArgs` subcommands wrote a record for, and those that failed.
+#[derive(Default)]
+struct ItemTally {
+    succeeded: usize,
+    failed: usize,
+    connect_failures: usize,
+}
+
+impl ItemTally {
+    /// Write an item's record and count it.
+    fn write(&mut self, output: &mut dyn Write, record: &OutputRecord) -> Result<(), Exit> {
+        write_record(output, record)?;
+        self.succeeded += 1;
+        Ok(())
+    }
+
+    fn fail(&mut self, e: &ItemError) {
+        if matches!(e, ItemError::Call(CallError::Unreachable(_))) {
+            self.connect_failures += 1;
+        }
+        self.failed += 1;
+    }
+
+    fn exit(&self) -> Exit {
+        Exit::for_batch(self.succeeded, self.failed, self.connect_failures > 0)
+    }
+}
+
+/// Write a record as a JSONL line; a failed write ends the run.
+fn write_record(output: &mut dyn Write, record: &OutputRecord) -> Result<(), Exit> {
+    writeln!(output, "{}", serde_json::to_string(record).expect("records serialize")).map_err(|e| {
+        error!("Failed to write record: {}", e);
+        Exit::AllFailed
+    })
+}
+
+/// The describe pipeline for the long-running modes: `serve`, `mcp`, `kafka`, `redis` and `daemon`.
+#[derive(Args)]
+struct PipelineArgs {
+    /// Path to prompt configuration JSON file
+    #[arg(long, env = "NINELADIES_PROMPT", required_unless_present = "preset")]
+    prompt: Option<String>,
+
+    /// Use a built-in prompt config instead of --prompt
+    #[arg(long, value_enum, conflicts_with = "prompt")]
+    preset: Option<Preset>,
+
+    /// Use the prompt file's text for this language code (e.g. de, pt-BR), from its `locales`
+    #[arg(long, env = "NINELADIES_LOCALE", requires = "prompt")]
+    locale: Option<String>,
+
+    /// Server URL [default: http://localhost:11434]
+    #[arg(long, env = "NINELADIES_URL")]
+    url: Option<String>,
+
+    /// Model name (required for Ollama)
+    #[arg(long, env = "NINELADIES_MODEL")]
+    model: Option<String>,
+
+    /// Server API
+    #[arg(long, value_enum, env = "NINELADIES_API")]
+    api: Option<Api>,
+
+    /// Request timeout in seconds [default: 120]
+    #[arg(long, env = "NINELADIES_TIMEOUT")]
+    timeout: Option<u64>,
+
+    /// Send at most this many model requests a minute, as with `run --rpm`
+    #[arg(long, env = "NINELADIES_RPM", conflicts_with = "rps")]
+    rpm: Option<f64>,
+
+    /// Send at most this many model requests a second
+    #[arg(long)]
+    rps: Option<f64>,
+
+    /// Write each model request and the server's raw response to a timestamped file in this folder
+    #[arg(long)]
+    trace_dir: Option<PathBuf>,
+
+    /// Keep the image data in traced requests instead of its size and SHA-256
+    #[arg(long, requires = "trace_dir")]
+    trace_images: bool,
+
+    /// Save the server's reply to each model request as a fixture file in this folder, for --replay
+    #[arg(long)]
+    record: Option<PathBuf>,
+
+    /// Answer model requests from the fixture files --record saved in this folder, without a server
+    #[arg(long, conflicts_with = "record")]
+    replay: Option<PathBuf>,
+
+    /// Ask for replies in this language, as with `run --language`
+    #[arg(long, value_parser = Language::parse)]
+    language: Option<Language>,
+
+    /// Translate each response into these languages, as with `run --translate`
+    #[arg(long, value_delimiter = ',', value_parser = Language::parse)]
+    translate: Vec<Language>,
+
+    /// Lua script with transform hooks, as with `run --script`
+    #[arg(long)]
+    script: Option<String>,
+
+    /// Read barcodes and QR codes locally, as with `run --barcodes`
+    #[arg(long)]
+    barcodes: bool,
+
+    /// Add each image's dimensions, format and file size, as with `run --image-info`
+    #[arg(long)]
+    image_info: bool,
+
+    /// Measure each image, as with `run --image-stats`
+    #[arg(long, value_enum, num_args = 0..=1, default_missing_value = "record")]
+    image_stats: Option<ContextMode>,
+
+    /// Read EXIF metadata, as with `run --exif`
+    #[arg(long, value_enum, num_args = 0..=1, default_missing_value = "record")]
+    exif: Option<ContextMode>,
+
+    /// Add embeddings, as with `run --mode embed`
+    #[arg(long, value_enum, default_value = "describe")]
+    mode: RunMode,
+
+    /// What --mode embed embeds, as with `run --embed-input`
+    #[arg(long, value_enum, default_value = "caption")]
+    embed_input: EmbedSource,
+
+    /// Classify each image as one of these labels, as with `run --labels`
+    #[arg(long, value_parser = LabelSet::parse)]
+    labels: Option<LabelSet>,
+
+    /// Also record the K likeliest labels, as with `run --top-k`
+    #[arg(long, requires = "labels", value_parser = clap::value_parser!(u64).range(2..))]
+    top_k: Option<u64>,
+
+    /// Score each response's confidence, as with `run --confidence`
+    #[arg(long, value_enum)]
+    confidence: Option<ConfidenceMethod>,
+
+    /// Answers to compare for --confidence agreement
+    #[arg(long, default_value_t = 3, value_parser = clap::value_parser!(u64).range(2..))]
+    confidence_samples: u64,
+
+    /// Record N answers about each image, as with `run --candidates`
+    #[arg(long, default_value_t = 1, value_name = "N", conflicts_with = "labels", value_parser = clap::value_parser!(u64).range(1..))]
+    candidates: u64,
+
+    /// Embedding model for --mode embed
+    #[arg(long)]
+    embed_model: Option<String>,
+
+    /// Parse bounding boxes in responses, as with `run --detections`
+    #[arg(long)]
+    detections: bool,
+
+    #[command(flatten)]
+    ocr: OcrArgs,
+
+    #[command(flatten)]
+    nsfw: NsfwArgs,
+
+    #[command(flatten)]
+    faces: FaceArgs,
+
+    #[command(flatten)]
+    size_limit: SizeLimitArgs,
+}
+
+#[cfg(feature = "serve")]
+#[derive(Args)]
+struct ServeArgs {
+    #[command(flatten)]
+    pipeline: PipelineArgs,
+
+    /// Address to listen on, e.g. ':8080' for every interface
+    #[arg(long, default_value = "127.0.0.1:8080", env = "NINELADIES_LISTEN")]
+    listen: String,
+
+    /// Requests handled at once
+    #[arg(long, default_value = "1", value_parser = clap::value_parser!(u64).range(1..))]
+    workers: u64,
+
+    /// Largest upload accepted, in megabytes
+    #[arg(long, default_value = "20")]
+    max_upload_mb: usize,
+
+    /// Allow {"path": ...} requests for files under this directory
+    #[arg(long)]
+    path_root: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct McpArgs {
+    #[command(flatten)]
+    pipeline: PipelineArgs,
+}
+
+#[cfg(feature = "kafka")]
+#[derive(Args)]
+struct KafkaArgs {
+    #[command(flatten)]
+    pipeline: PipelineArgs,
+
+    /// Kafka brokers, comma-separated
+    #[arg(long, value_delimiter = ',', required = true, env = "NINELADIES_KAFKA_BROKERS")]
+    brokers: Vec<String>,
+
+    /// Topic to read jobs from
+    #[arg(long)]
+    input_topic: String,
+
+    /// Topic to publish results to
+    #[arg(long)]
+    output_topic: String,
+
+    /// Consumer group, whose committed offsets record which jobs are done
+    #[arg(long, default_value = "9ladies")]
+    group: String,
+}
+
+#[cfg(feature = "redis")]
+#[derive(Args)]
+struct RedisArgs {
+    #[command(flatten)]
+    pipeline: PipelineArgs,
+
+    /// Redis server, e.g. redis://localhost:6379/0
+    #[arg(long, default_value = "redis://127.0.0.1:6379", env = "NINELADIES_REDIS_URL")]
+    redis_url: String,
+
+    /// List to take jobs from; producers LPUSH onto it
+    #[arg(long)]
+    queue: String,
+
+    /// List (or with --stream, stream) to write results to
+    #[arg(long)]
+    results: String,
+
+    /// Write results with XADD to a stream instead of RPUSH to a list
+    #[arg(long)]
+    stream: bool,
+
+    /// Seconds a worker may hold a job before it is returned to the queue; keep it above --timeout
+    #[arg(long, default_value = "600", value_parser = clap::value_parser!(u64).range(1..))]
+    visibility_timeout: u64,
+
+    /// Tries per job before it is moved to the <queue>:failed list
+    #[arg(long, default_value = "3", value_parser = clap::value_parser!(u64).range(1..))]
+    max_attempts: u64,
+}
+
+#[derive(Args)]
+struct DaemonArgs {
+    #[command(flatten)]
+    pipeline: PipelineArgs,
+
+    /// Directories to watch, including their subdirectories
+    #[arg(required = true)]
+    dirs: Vec<PathBuf>,
+
+    /// SQLite database recording the status of every file seen
+    #[arg(long, default_value = "9ladies-state.db", env = "NINELADIES_STATE")]
+    state: String,
+
+    /// Seconds between scans
+    #[arg(long, default_value = "10", value_parser = clap::value_parser!(u64).range(1..))]
+    interval: u64,
+
+    /// Append the JSONL records to this file instead of printing them
+    #[arg(long)]
+    output: Option<String>,
+
+    /// Also take jobs over HTTP on this address (POST /jobs, GET /jobs/<id>), e.g. ':8081'
+    #[cfg(feature = "serve")]
+    #[arg(long)]
+    listen: Option<String>,
+
+    /// Allow POST /jobs to queue files under this directory by path
+    #[cfg(feature = "serve")]
+    #[arg(long, requires = "listen")]
+    path_root: Option<PathBuf>,
+
+    /// Save images uploaded to POST /jobs in this directory
+    #[cfg(feature = "serve")]
+    #[arg(long, requires = "listen")]
+    upload_dir: Option<PathBuf>,
+
+    /// Largest upload accepted, in megabytes
+    #[cfg(feature = "serve")]
+    #[arg(long, default_value = "20")]
+    max_upload_mb: usize,
+}
+
+#[derive(Args)]
+struct ReviewArgs {
+    /// Output JSONL file to review
+    input: String,
+
+    /// Where to save the reviewed records [default: <input>.reviewed.jsonl]
+    #[arg(long)]
+    output: Option<String>,
+
+    /// Also show records that were already reviewed
+    #[arg(long)]
+    all: bool,
+
+    /// Only show records scored by --confidence below this, from 0 to 1
+    #[arg(long)]
+    below: Option<f64>,
+
+    /// How to draw image previews
+    #[arg(long, value_enum, default_value = "auto")]
+    image_protocol: ImageProtocol,
+}
+
+impl ReviewArgs {
+    /// Reviewing a `.reviewed.jsonl` file again saves back into it, so a session can be
+    /// picked up where it was left.
+    fn output_path(&self) -> PathBuf {
+        match &self.output {
+            Some(output) => PathBuf::from(output),
+            None if self.input.ends_with(".reviewed.jsonl") => PathBuf::from(&self.input),
+            None => {
+                let stem = self.input.strip_suffix(".jsonl").unwrap_or(&self.input);
+                PathBuf::from(format!("{}.reviewed.jsonl", stem))
+            }
+        }
+    }
+}
+
+#[derive(Args)]
+struct ExportArgs {
+    /// Output JSONL file to export
+    input: String,
+
+    /// Server URL, needed to compute embeddings for --qdrant
+    #[arg(long, env = "NINELADIES_URL")]
+    url: Option<String>,
+
+    #[command(flatten)]
+    sinks: SinkArgs,
+}
+
+impl RunArgs {
+    /// Number of failed files after which the run stops, if any.
+    fn error_limit(&self) -> Option<usize> {
+        if self.fail_fast {
+            Some(1)
+        } else {
+            self.max_errors.map(|n| n as usize)
+        }
+    }
+}
+
+/// Process exit statuses, so scripts can tell "fix your flags" from "retry later" from
+/// "some files were bad".
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Exit {
+    Success = 0,
+    /// Some files failed, the rest were processed
+    PartialFailure = 1,
+    /// Invalid flags, prompt file, config file or sink settings, or inputs that don't match
+    /// `--input-manifest` (clap also uses 2 for usage errors)
+    Config = 2,
+    /// The model server (or, for `kafka` and `redis`, the queue) could not be reached
+    Unreachable = 3,
+    /// Every file failed
+    AllFailed = 4,
+    /// `9ladies assert` found a check that doesn't hold, or `validate-output` a problem
+    AssertionsFailed = 5,
+    /// Stopped early by SIGINT or SIGTERM
+    Interrupted = shutdown::INTERRUPTED_STATUS as isize,
+}
+
+impl Exit {
+    /// Status for a batch of items. `unreachable` means every model call that was attempted
+    /// failed to connect.
+    fn for_batch(succeeded: usize, failed: usize, unreachable: bool) -> Exit {
+        match (succeeded, failed) {
+            (_, 0) => Exit::Success,
+            (0, _) if unreachable => Exit::Unreachable,
+            (0, _) => Exit::AllFailed,
+            _ => Exit::PartialFailure,
+        }
+    }
+}
+
+impl From<Exit> for ExitCode {
+    fn from(exit: Exit) -> Self {
+        ExitCode::from(exit as u8)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum OutputFormat {
+    /// One JSON record per line
+    Jsonl,
+    /// Each file name followed by its response, wrapped to the terminal width
+    Pretty,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum WebhookEvents {
+    /// Every completed record plus the run summary
+    All,
+    /// Only completed records
+    Records,
+    /// Only the run-complete summary
+    Summary,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum WebhookPayload<'a> {
+    Record {
+        record: &'a OutputRecord,
+    },
+    RunComplete {
+        total: usize,
+        succeeded: usize,
+        failed: usize,
+    },
+}
+
+/// Collect Finder tag names from the requested fields of a JSON response.
+/// Strings and numbers become tags as-is, `true` booleans use the field name,
+/// and arrays contribute each of their scalar elements.
+fn finder_tags_from_response(response: &serde_json::Value, fields: &[String]) -> Vec<String> {
+    fn push_tag(tags: &mut Vec<String>, field: &str, value: &serde_json::Value) {
+        let tag = match value {
+            serde_json::Value::String(s) => s.trim().to_string(),
+            serde_json::Value::Number(n) => n.to_string(),
+            serde_json::Value::Bool(true) => field.to_string(),
+            _ => return,
+        };
+        if !tag.is_empty() && !tags.contains(&tag) {
+            tags.push(tag);
+        }
+    }
+
+    let mut tags = Vec::new();
+    for field in fields {
+        match response.get(field) {
+            Some(serde_json::Value::Array(items)) => {
+                for item in items {
+                    push_tag(&mut tags, field, item);
+                }
+            }
+            Some(value) => push_tag(&mut tags, field, value),
+            None => {}
+        }
+    }
+    tags
+}
+
+/// Encode tag names as the binary plist stored in `com.apple.metadata:_kMDItemUserTags`.
+#[cfg_attr(not(target_os = "macos"), allow(dead_code))]
+fn encode_finder_tags(tags: &[String]) -> Result<Vec<u8>, String> {
+    let mut buf = Vec::new();
+    plist::to_writer_binary(&mut buf, &tags).map_err(|e| format!("Failed to encode tags: {}", e))?;
+    Ok(buf)
+}
+
+/// Add `tags` to the tags a file already has, from its `_kMDItemUserTags` plist if it has
+/// one, leaving out those it has. Finder stores a tag's color after a newline ("Red\n6"),
+/// so tags are matched by name alone.
+#[cfg_attr(not(target_os = "macos"), allow(dead_code))]
+fn merge_finder_tags(existing: Option<&[u8]>, tags: &[String]) -> Result<Vec<String>, String> {
+    let mut merged: Vec<String> = match existing {
+        Some(data) => plist::from_bytes(data).map_err(|e| format!("Failed to read existing tags: {}", e))?,
+        None => Vec::new(),
+    };
+    let name = |tag: &str| tag.split('\n').next().unwrap_or_default().to_string();
+    let mut names: HashSet<String> = merged.iter().map(|tag| name(tag)).collect();
+    for tag in tags {
+        if names.insert(name(tag)) {
+            merged.push(tag.clone());
+        }
+    }
+    Ok(merged)
+}
+
+#[cfg(target_os = "macos")]
+fn write_finder_tags(path: &Path, tags: &[String]) -> Result<(), String> {
+    const ATTR: &str = "com.apple.metadata:_kMDItemUserTags";
+    let existing = xattr::get(path, ATTR)
+        .map_err(|e| format!("Failed to read Finder tags of '{}': {}", path.display(), e))?;
+    let tags = merge_finder_tags(existing.as_deref(), tags)
+        .map_err(|e| format!("Finder tags of '{}': {}", path.display(), e))?;
+    let data = encode_finder_tags(&tags)?;
+    xattr::set(path, ATTR, &data).map_err(|e| format!("Failed to write Finder tags to '{}': {}", path.display(), e))
+}
+
+#[cfg(not(target_os = "macos"))]
+fn write_finder_tags(_path: &Path, _tags: &[String]) -> Result<(), String> {
+    Err("Finder tags are only supported on macOS".to_string())
+}
+
+/// Format a timestamp as a UTC `YYYY-MM-DD` date.
+fn format_date(time: SystemTime) -> String {
+    utc_timestamp(time)[..10].to_string()
+}
+
+/// Make a template value safe to use inside a single path component. A value of `.`, `..`
+/// or nothing at all would name a folder rather than go in one, so it becomes `_`.
+fn sanitize_component(value: &str) -> String {
+    let value = value.trim();
+    if matches!(value, "" | "." | "..") {
+        return "_".to_string();
+    }
+    value
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect()
+}
+
+fn template_response_value(response: &serde_json::Value, key: &str) -> Result<String, String> {
+    let mut value = response;
+    if let Some(field_path) = key.strip_prefix("response.") {
+        for part in field_path.split('.') {
+            let next = match value {
+                serde_json::Value::Array(items) => part.parse::<usize>().ok().and_then(|i| items.get(i)),
+                _ => value.get(part),
+            };
+            value = next.ok_or_else(|| format!("Response has no field '{}'", key))?;
+        }
+    }
+
+    let scalar = |v: &serde_json::Value| match v {
+        serde_json::Value::String(s) => Some(s.clone()),
+        serde_json::Value::Number(n) => Some(n.to_string()),
+        serde_json::Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    };
+
+    match value {
+        serde_json::Value::Array(items) => {
+            let parts: Option<Vec<String>> = items.iter().map(scalar).collect();
+            parts
+                .map(|p| p.join("_"))
+                .ok_or_else(|| format!("Template field '{}' is not a list of values", key))
+        }
+        v => scalar(v).ok_or_else(|| format!("Template field '{}' is not a text or number value", key)),
+    }
+}
+
+/// Render a `{placeholder}` template for a processed file.
+///
+/// Supported placeholders are `{name}`, `{stem}`, `{ext}`, `{date}` (file modification
+/// date), `{response}`, `{response.field}` (nested with dots) and `{exif.field}` (see
+/// `ExifData::field`; read from the file when `exif` is `None`). Substituted values are
+/// sanitized so they can't introduce path separators.
+fn render_template(
+    template: &str,
+    path: &Path,
+    response: &serde_json::Value,
+    exif: Option<&ExifData>,
+) -> Result<String, String> {
+    let mut file_exif = None;
+    let mut out = String::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| format!("Unclosed placeholder in template '{}'", template))?;
+        let key = rest[start + 1..start + end].trim();
+
+        let value = match key {
+            "name" => path.file_name().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default(),
+            "stem" => path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default(),
+            "ext" => path.extension().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default(),
+            "date" => {
+                let modified = fs::metadata(path)
+                    .and_then(|m| m.modified())
+                    .map_err(|e| format!("Cannot read modification date of '{}': {}", path.display(), e))?;
+                format_date(modified)
+            }
+            k if k == "response" || k.starts_with("response.") => template_response_value(response, k)?,
+            k if k.starts_with("exif.") => {
+                let exif = match exif {
+                    Some(exif) => Some(exif),
+                    None => file_exif.get_or_insert_with(|| fs::read(path).ok().and_then(|data| ExifData::read(&data))).as_ref(),
+                };
+                exif.and_then(|exif| exif.field(&k["exif.".len()..]))
+                    .ok_or_else(|| format!("'{}' has no EXIF field '{}'", path.display(), k))?
+            }
+            k => return Err(format!("Unknown template placeholder '{{{}}}'", k)),
+        };
+
+        out.push_str(&sanitize_component(&value));
+        rest = &rest[start + end + 1..];
+    }
+    out.push_str(rest);
+
+    Ok(out)
+}
+
+/// Pick a destination that doesn't clash with an existing file or one already claimed
+/// earlier in this run, by appending `-1`, `-2`, ... to the stem.
+fn resolve_collision(target: PathBuf, source: &Path, claimed: &HashSet<PathBuf>) -> PathBuf {
+    if target == source || (!target.exists() && !claimed.contains(&target)) {
+        return target;
+    }
+
+    let stem = target.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    let ext = target.extension().map(|s| s.to_string_lossy().into_owned());
+    let parent = target.parent().map(Path::to_path_buf).unwrap_or_default();
+
+    (1..)
+        .map(|n| {
+            let name = match &ext {
+                Some(ext) => format!("{}-{}.{}", stem, n, ext),
+                None => format!("{}-{}", stem, n),
+            };
+            parent.join(name)
+        })
+        .find(|candidate| !candidate.exists() && !claimed.contains(candidate))
+        .expect("unbounded search always finds a free name")
+}
+
+/// Work out the new path for a file from the rename template.
+fn plan_rename(
+    path: &Path,
+    template: &str,
+    response: &serde_json::Value,
+    exif: Option<&ExifData>,
+    claimed: &HashSet<PathBuf>,
+) -> Result<PathBuf, String> {
+    let name = render_template(template, path, response, exif)?;
+    if name.is_empty() || name.starts_with('.') {
+        return Err(format!("Rename template produced an unusable file name '{}'", name));
+    }
+
+    let target = path.parent().unwrap_or(Path::new("")).join(name);
+    Ok(resolve_collision(target, path, claimed))
+}
+
+/// Work out where a file lands in the folder rendered from `dir_template`. The template is
+/// rendered against the original path, while `file_name` is the file's current name.
+fn plan_relocation(
+    path: &Path,
+    file_name: &std::ffi::OsStr,
+    dir_template: &str,
+    response: &serde_json::Value,
+    exif: Option<&ExifData>,
+    claimed: &HashSet<PathBuf>,
+) -> Result<PathBuf, String> {
+    let dir = render_template(dir_template, path, response, exif)?;
+    if dir.trim().is_empty() {
+        return Err("Folder template produced an empty path".to_string());
+    }
+
+    // Whatever the placeholders render to, the file stays under the folder the template
+    // spells out before the first of them
+    let literal = &dir_template[..dir_template.find('{').unwrap_or(dir_template.len())];
+    let base = Path::new(&literal[..literal.rfind(['/', '\\']).map_or(0, |i| i + 1)]);
+    let inside = Path::new(&dir)
+        .strip_prefix(base)
+        .is_ok_and(|rest| rest.components().all(|c| matches!(c, Component::Normal(_) | Component::CurDir)));
+    if !inside {
+        return Err(format!("Folder template produced '{}', outside '{}'", dir, base.display()));
+    }
+
+    Ok(resolve_collision(PathBuf::from(dir).join(file_name), path, claimed))
+}
+
+/// Move a file, falling back to copy-and-delete when a rename crosses filesystems.
+fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    let (from, to) = (long_path(from), long_path(to));
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if fs::rename(&from, &to).is_ok() {
+        return Ok(());
+    }
+    fs::copy(&from, &to)?;
+    fs::remove_file(&from)
+}
+
+fn copy_file(from: &Path, to: &Path) -> io::Result<()> {
+    let (from, to) = (long_path(from), long_path(to));
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::copy(&from, &to).map(|_| ())
+}
+
+/// Run the configured rename/move/copy actions for a successfully described file,
+/// recording where it ended up. With `--preview` the plan is printed instead. A file is only
+/// moved or copied when its response follows `schema`, if the prompt config has one.
+fn apply_file_actions(
+    args: &RunArgs,
+    schema: Option<&serde_json::Value>,
+    path: &Path,
+    record: &mut OutputRecord,
+    claimed: &mut HashSet<PathBuf>,
+) -> Result<(), String> {
+    let mut current = path.to_path_buf();
+
+    if let Some(template) = &args.rename_template {
+        let target = plan_rename(path, template, &record.response, record.exif.as_ref(), claimed)?;
+        if target != current {
+            if args.preview {
+                info!("Would rename '{}' -> '{}'", current.display(), target.display());
+            } else {
+                fs::rename(long_path(&current), long_path(&target))
+                    .map_err(|e| format!("Failed to rename to '{}': {}", target.display(), e))?;
+                record.renamed_to = Some(target.display().to_string());
+            }
+            claimed.insert(target.clone());
+            current = target;
+        }
+    }
+
+    let relocation = match (&args.move_to, &args.copy_to) {
+        (Some(template), _) => Some((template, true)),
+        (None, Some(template)) => Some((template, false)),
+        (None, None) => None,
+    };
+
+    if let Some((template, is_move)) = relocation {
+        let errors = schema.map(|schema| schema::schema_errors(schema, &record.response, "response")).unwrap_or_default();
+        if !errors.is_empty() {
+            return Err(format!(
+                "Not {}: the response doesn't follow the schema: {}",
+                if is_move { "moved" } else { "copied" },
+                errors.join("; ")
+            ));
+        }
+        let file_name = current.file_name().unwrap_or(path.as_os_str()).to_owned();
+        let target = plan_relocation(path, &file_name, template, &record.response, record.exif.as_ref(), claimed)?;
+        if target != current {
+            let verb = if is_move { "move" } else { "copy" };
+            if args.preview {
+                info!("Would {} '{}' -> '{}'", verb, current.display(), target.display());
+            } else {
+                let result = if is_move { move_file(&current, &target) } else { copy_file(&current, &target) };
+                result.map_err(|e| format!("Failed to {} to '{}': {}", verb, target.display(), e))?;
+                let destination = Some(target.display().to_string());
+                if is_move {
+                    record.moved_to = destination;
+                } else {
+                    record.copied_to = destination;
+                }
+            }
+            claimed.insert(target);
+        }
+    }
+
+    Ok(())
+}
+
+fn send_webhook(
+    client: &reqwest::blocking::Client,
+    url: &str,
+    payload: &WebhookPayload,
+) -> Result<(), String> {
+    let response = client
+        .post(url)
+        .json(payload)
+        .send()
+        .map_err(|e| format!("Webhook request failed: {}", e))?;
+
+    if !response.status().is_success() {
+        return Err(format!("Webhook returned {}", response.status()));
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "postgres")]
+fn open_postgres_sink(url: &str, table: &str, flatten: Option<Flatten>) -> Result<Box<dyn RecordSink>, String> {
+    Ok(Box::new(nineladies::sinks::PostgresSink::connect(url, table)?.with_flatten(flatten)))
+}
+
+#[cfg(not(feature = "postgres"))]
+fn open_postgres_sink(_url: &str, _table: &str, _flatten: Option<Flatten>) -> Result<Box<dyn RecordSink>, String> {
+    Err("--pg-table requires 9ladies to be built with the 'postgres' feature".to_string())
+}
+
+#[cfg(feature = "duckdb")]
+fn open_duckdb_sink(path: &str, table: &str, flatten: Option<Flatten>) -> Result<Box<dyn RecordSink>, String> {
+    Ok(Box::new(nineladies::sinks::DuckDbSink::open(path, table)?.with_flatten(flatten)))
+}
+
+#[cfg(not(feature = "duckdb"))]
+fn open_duckdb_sink(_path: &str, _table: &str, _flatten: Option<Flatten>) -> Result<Box<dyn RecordSink>, String> {
+    Err("--duckdb requires 9ladies to be built with the 'duckdb' feature".to_string())
+}
+
+#[cfg(feature = "lua")]
+fn load_script(path: &str) -> Result<Box<dyn Transform>, String> {
+    Ok(Box::new(nineladies::script::LuaScript::load(path)?))
+}
+
+#[cfg(not(feature = "lua"))]
+fn load_script(_path: &str) -> Result<Box<dyn Transform>, String> {
+    Err("--script requires 9ladies to be built with the 'lua' feature".to_string())
+}
+
+#[cfg(feature = "barcode")]
+fn with_barcodes(runner: BatchRunner, barcodes: bool) -> Result<BatchRunner, String> {
+    Ok(runner.with_barcodes(barcodes))
+}
+
+#[cfg(not(feature = "barcode"))]
+fn with_barcodes(runner: BatchRunner, barcodes: bool) -> Result<BatchRunner, String> {
+    if barcodes {
+        return Err("--barcodes requires 9ladies to be built with the 'barcode' feature".to_string());
+    }
+    Ok(runner)
+}
+
+#[cfg(feature = "faces")]
+fn with_face_blur(runner: BatchRunner, args: &FaceArgs) -> Result<BatchRunner, String> {
+    let face_blur = match (&args.face_model, args.blur_faces) {
+        (Some(path), true) => Some(nineladies::faces::FaceBlur::load(path)?),
+        _ => None,
+    };
+    Ok(runner.with_face_blur(face_blur))
+}
+
+#[cfg(not(feature = "faces"))]
+fn with_face_blur(runner: BatchRunner, args: &FaceArgs) -> Result<BatchRunner, String> {
+    if args.blur_faces {
+        return Err("--blur-faces requires 9ladies to be built with the 'faces' feature".to_string());
+    }
+    Ok(runner)
+}
+
+/// Add the inputs to the job store, dropping those already done when resuming. Inputs a run
+/// left in progress when it stopped are returned to pending first.
+fn prepare_job_store(store: &mut JobStore, paths: Vec<String>, resume: bool) -> Result<Vec<String>, String> {
+    let interrupted = store.reset_in_progress()?;
+    if interrupted > 0 {
+        info!("{} input(s) were interrupted by an earlier run and are pending again", interrupted);
+    }
+
+    let paths = remaining_inputs(store, paths, resume)?;
+    store.enqueue(&paths)?;
+    Ok(paths)
+}
+
+/// The inputs a run would describe, leaving out those already done when resuming. Reads the
+/// job store without changing it, as a `--dry-run` does.
+fn remaining_inputs(store: &JobStore, paths: Vec<String>, resume: bool) -> Result<Vec<String>, String> {
+    if !resume {
+        return Ok(paths);
+    }
+    let total = paths.len();
+    let mut remaining = Vec::with_capacity(total);
+    for path in paths {
+        if store.get(&path)?.is_none_or(|state| state.status != JobStatus::Done) {
+            remaining.push(path);
+        }
+    }
+    if remaining.len() < total {
+        info!("Skipping {} input(s) already done", total - remaining.len());
+    }
+    Ok(remaining)
+}
+
+/// Read image paths from stdin, one per line, skipping blank lines.
+fn read_input_paths() -> Vec<String> {
+    io::stdin()
+        .lock()
+        .lines()
+        .map_while(Result::ok)
+        .map(|line| paths::normalize(line.trim()))
+        .filter(|line| !line.is_empty())
+        .collect()
+}
+
+/// Connect to every configured sink. `ollama_url` is used by sinks that need embeddings.
+fn open_sinks(
+    args: &SinkArgs,
+    client: &reqwest::blocking::Client,
+    ollama_url: Option<&str>,
+) -> Result<Vec<Box<dyn RecordSink>>, String> {
+    let mut sinks: Vec<Box<dyn RecordSink>> = Vec::new();
+
+    if let Some(table) = &args.pg_table {
+        let url = std::env::var(PG_URL_ENV)
+            .map_err(|_| format!("--pg-table requires the {} environment variable", PG_URL_ENV))?;
+        sinks.push(open_postgres_sink(&url, table, args.flatten.flatten())?);
+    }
+
+    if let (Some(qdrant_url), Some(embed_model)) = (&args.qdrant, &args.embed_model) {
+        let ollama_url = ollama_url.ok_or("--qdrant needs --url to compute embeddings")?;
+        sinks.push(Box::new(QdrantSink::new(
+            client.clone(),
+            qdrant_url,
+            &args.qdrant_collection,
+            std::env::var(QDRANT_API_KEY_ENV).ok(),
+            ollama_url,
+            embed_model,
+        )));
+    }
+
+    if let Some(path) = &args.duckdb {
+        sinks.push(open_duckdb_sink(path, &args.duckdb_table, args.flatten.flatten())?);
+    }
+
+    Ok(sinks)
+}
+
+fn main() -> ExitCode {
+    let (cli, default_run) = Cli::parse_with_default_run(std::env::args_os()).unwrap_or_else(|e| e.exit());
+
+    if let Err(e) = logging::init(&cli.log) {
+        eprintln!("Error: {}", e);
+        return Exit::Config.into();
+    }
+
+    let settings = match config::load(&cli.config) {
+        Ok(settings) => settings,
+        Err(e) => {
+            error!("{}", e);
+            return Exit::Config.into();
+        }
+    };
+    if let Some(source) = settings.key_source() {
+        secrets::set_key_source(source);
+    }
+
+    let exit = match cli.command {
+        Some(Command::Run(args)) => run(*args, &settings, cli.log.quiet),
+        Some(Command::Validate(args)) => validate(args),
+        Some(Command::ValidateOutput(args)) => validate_output(args),
+        Some(Command::Report(args)) => report(args),
+        Some(Command::Diff(args)) => diff(args),
+        Some(Command::Merge(args)) => merge(args),
+        Some(Command::Convert(args)) => convert(args),
+        Some(Command::Search(args)) => search(args, &settings),
+        Some(Command::Jobs(args)) => jobs(args),
+        Some(Command::Models(args)) => models(args, &settings),
+        Some(Command::Presets(args)) => presets(args),
+        Some(Command::Export(args)) => export(args, &settings),
+        Some(Command::Chat(args)) => chat(args, &settings),
+        Some(Command::Review(args)) => review(args),
+        Some(Command::AltText(args)) => alt_text(args, &settings),
+        Some(Command::Duplicates(args)) => duplicates(args, &settings),
+        Some(Command::Compare(args)) => compare(args, &settings),
+        Some(Command::Products(args)) => products(args, &settings),
+        Some(Command::Followup(args)) => followup(args, &settings),
+        Some(Command::Video(args)) => video(args, &settings),
+        Some(Command::Assert(args)) => assertions(args, &settings),
+        #[cfg(feature = "serve")]
+        Some(Command::Serve(args)) => serve(args, &settings),
+        Some(Command::Mcp(args)) => mcp(args, &settings),
+        #[cfg(feature = "kafka")]
+        Some(Command::Kafka(args)) => kafka(args, &settings),
+        #[cfg(feature = "redis")]
+        Some(Command::Redis(args)) => redis(args, &settings),
+        Some(Command::Daemon(args)) => daemon(args, &settings),
+        None => run(
+            default_run.expect("run args are parsed when there is no subcommand"),
+            &settings,
+            cli.log.quiet,
+        ),
+    };
+    exit.into()
+}
+
+fn validate(args: ValidateArgs) -> Exit {
+    if let Err(e) = load_prompt_config(&args.prompt) {
+        error!(code = "config", "{}", e);
+        return Exit::Config;
+    }
+
+    let (mut valid, mut invalid) = (0, 0);
+    for path_str in read_input_paths() {
+        match validate_image_file(Path::new(&path_str)) {
+            Ok(_) => valid += 1,
+            Err(e) => {
+                error!("{}", e);
+                invalid += 1;
+            }
+        }
+    }
+
+    Exit::for_batch(valid, invalid, false)
+}
+
+fn validate_output(args: ValidateOutputArgs) -> Exit {
+    let config = match (&args.prompt, args.preset) {
+        (None, None) => None,
+        (prompt, preset) => match prompt_config(prompt.as_deref(), preset, None) {
+            Ok(config) => Some(config),
+            Err(e) => {
+                error!(code = "config", "{}", e);
+                return Exit::Config;
+            }
+        },
+    };
+    if config.as_ref().is_some_and(|config| config.schema.is_none()) {
+        warn!("The prompt config has no schema, so responses are only checked for --require fields");
+    }
+    let content = match fs::read_to_string(&args.input) {
+        Ok(content) => content,
+        Err(e) => {
+            error!(code = "config", "Failed to read output file '{}': {}", args.input, e);
+            return Exit::Config;
+        }
+    };
+    let manifest = match &args.manifest {
+        Some(path) => match bundle::read_manifest(path).and_then(|manifest| Ok((manifest, sha256_file(Path::new(&args.input))?))) {
+            Ok(manifest) => Some(manifest),
+            Err(e) => {
+                error!(code = "config", "{}", e);
+                return Exit::Config;
+            }
+        },
+        None => None,
+    };
+
+    let expectations = Expectations {
+        schema: config.as_ref().and_then(|config| config.schema.as_ref()),
+        required: &args.required,
+        count: args.count,
+        manifest: manifest.as_ref().map(|(manifest, sha256)| (manifest, sha256.as_str())),
+    };
+    let check = output_check::check_output(&content, &expectations);
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&check).unwrap());
+    } else {
+        print!("{}", check);
+    }
+
+    if check.passed() {
+        Exit::Success
+    } else {
+        Exit::AssertionsFailed
+    }
+}
+
+fn report(args: ReportArgs) -> Exit {
+    let records = match read_output_records(&args.input) {
+        Ok(records) => records,
+        Err(e) => {
+            error!(code = "config", "{}", e);
+            return Exit::Config;
+        }
+    };
+
+    let summary = report::summarize(&records);
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&summary).unwrap());
+    } else {
+        print!("{}", summary);
+    }
+
+    Exit::Success
+}
+
+fn diff(args: DiffArgs) -> Exit {
+    let (old, new) = match (read_output_records(&args.old), read_output_records(&args.new)) {
+        (Ok(old), Ok(new)) => (old, new),
+        (Err(e), _) | (_, Err(e)) => {
+            error!(code = "config", "{}", e);
+            return Exit::Config;
+        }
+    };
+
+    let diff = diff::diff_outputs(&old, &new, args.by);
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&diff).unwrap());
+    } else {
+        print!("{}", diff);
+    }
+
+    Exit::Success
+}
+
+fn merge(args: MergeArgs) -> Exit {
+    let mut outputs = Vec::new();
+    for input in &args.inputs {
+        match read_output_records(input) {
+            Ok(records) => outputs.push(records),
+            Err(e) => {
+                error!(code = "config", "{}", e);
+                return Exit::Config;
+            }
+        }
+    }
+    let total: usize = outputs.iter().map(Vec::len).sum();
+    let merged = merge::merge_outputs(outputs, args.by, args.on_conflict);
+
+    // Inputs are all read by now, so the output can replace one of them
+    let mut output: Box<dyn Write> = match &args.output {
+        Some(path) => match fs::File::create(path) {
+            Ok(file) => Box::new(io::BufWriter::new(file)),
+            Err(e) => {
+                error!(code = "config", "Failed to create output file '{}': {}", path, e);
+                return Exit::Config;
+            }
+        },
+        None => Box::new(io::stdout().lock()),
+    };
+    for record in &merged.records {
+        if let Err(e) = writeln!(output, "{}", serde_json::to_string(record).expect("records serialize")) {
+            error!(code = "config", "Failed to write record: {}", e);
+            return Exit::Config;
+        }
+    }
+    if let Err(e) = output.flush() {
+        error!(code = "config", "Failed to write records: {}", e);
+        return Exit::Config;
+    }
+
+    info!(
+        "Merged {} record(s) from {} file(s) into {}, dropping {} duplicate(s)",
+        total,
+        args.inputs.len(),
+        merged.records.len(),
+        merged.duplicates
+    );
+    Exit::Success
+}
+
+fn convert(args: ConvertArgs) -> Exit {
+    let records = match read_output_records(&args.input) {
+        Ok(records) => records,
+        Err(e) => {
+            error!(code = "config", "{}", e);
+            return Exit::Config;
+        }
+    };
+
+    let table = Table::from_records(&records, args.flatten.flatten().as_ref());
+    let text = match args.to {
+        ConvertFormat::Csv => table.to_csv(),
+        ConvertFormat::Html => table.to_html(&args.input),
+        ConvertFormat::Md => table.to_markdown(),
+        ConvertFormat::Parquet => {
+            let path = args.output.as_deref().expect("clap requires --output for parquet");
+            if let Err(e) = convert::write_parquet(&table, path) {
+                error!(code = "config", "{}", e);
+                return Exit::Config;
+            }
+            return Exit::Success;
+        }
+    };
+    match &args.output {
+        Some(path) => {
+            if let Err(e) = fs::write(path, text) {
+                error!(code = "config", "Failed to write '{}': {}", path, e);
+                return Exit::Config;
+            }
+        }
+        None => print!("{}", text),
+    }
+
+    Exit::Success
+}
+
+fn search(args: SearchArgs, settings: &Settings) -> Exit {
+    let url = args.url.as_deref().or(settings.url.as_deref()).unwrap_or(DEFAULT_URL);
+    let client = build_client(settings.timeout);
+
+    let mut records = Vec::new();
+    let mut vectors = Vec::new();
+    for input in &args.inputs {
+        let found = match read_output_records(input) {
+            Ok(found) => found,
+            Err(e) => {
+                error!(code = "config", "{}", e);
+                return Exit::Config;
+            }
+        };
+        let mut cache = match EmbeddingCache::open(&EmbeddingCache::path_for(input)) {
+            Ok(cache) => cache,
+            Err(e) => {
+                error!(code = "config", "{}", e);
+                return Exit::Config;
+            }
+        };
+        for record in found.into_iter().filter(|record| !record.response.is_null()) {
+            let text = embedding_text(&record.response);
+            match search::embed_cached(&mut cache, &client, url, &args.embed_model, &text) {
+                Ok(vector) => {
+                    records.push(record);
+                    vectors.push(vector);
+                }
+                Err(e) => {
+                    error!("{}", e);
+                    return Exit::AllFailed;
+                }
+            }
+        }
+    }
+
+    let query = match call_embed(&client, url, &args.embed_model, &args.query) {
+        Ok(query) => query,
+        Err(e) => {
+            error!("{}", e);
+            return Exit::AllFailed;
+        }
+    };
+
+    let vectors: Vec<&[f32]> = vectors.iter().map(Vec::as_slice).collect();
+    let matches = search::rank(&query, &vectors)
+        .into_iter()
+        .filter(|(_, score)| args.min_score.is_none_or(|min| *score >= min))
+        .take(args.limit);
+    for (i, score) in matches {
+        let record = &records[i];
+        if args.json {
+            let line = serde_json::json!({"score": score, "path": record.current_path(), "record": record});
+            println!("{}", line);
+        } else {
+            let text = embedding_text(&record.response).replace('\n', " ");
+            let summary: String = text.chars().take(80).collect();
+            println!("{:.3}\t{}\t{}", score, record.current_path(), summary);
+        }
+    }
+    Exit::Success
+}
+
+fn jobs(args: JobsArgs) -> Exit {
+    if !Path::new(&args.store).exists() {
+        error!(code = "config", "Job store not found: {}", args.store);
+        return Exit::Config;
+    }
+    let store = match JobStore::open(&args.store) {
+        Ok(store) => store,
+        Err(e) => {
+            error!(code = "config", "{}", e);
+            return Exit::Config;
+        }
+    };
+
+    let printed = match args.status {
+        Some(status) => store.list(status).map(|entries| {
+            if args.json {
+                println!("{}", serde_json::to_string_pretty(&entries).unwrap());
+            } else {
+                for entry in entries {
+                    println!("{}\t{}\t{}", entry.path, entry.attempts, entry.error.unwrap_or_default());
+                }
+            }
+        }),
+        None => store.counts().map(|counts| {
+            if args.json {
+                println!("{}", serde_json::to_string_pretty(&counts).unwrap());
+            } else {
+                print!("{}", counts);
+            }
+        }),
+    };
+    match printed {
+        Ok(()) => Exit::Success,
+        Err(e) => {
+            error!("{}", e);
+            Exit::Config
+        }
+    }
+}
+
+fn models(args: ModelsArgs, settings: &Settings) -> Exit {
+    let base_url = args.url.as_deref().or(settings.url.as_deref()).unwrap_or(DEFAULT_URL);
+    let api = args.api.or(settings.api).unwrap_or(Api::Ollama);
+    let backend = Backend::new(api, base_url, None, settings.timeout);
+
+    match backend.list_models() {
+        Ok(models) => {
+            for model in models {
+                match model.size {
+                    Some(size) => println!("{}\t{:.1} GB", model.name, size as f64 / 1e9),
+                    None => println!("{}", model.name),
+                }
+            }
+            Exit::Success
+        }
+        Err(e) => {
+            error!("{}", e);
+            match e {
+                CallError::Unreachable(_) => Exit::Unreachable,
+                CallError::Failed(_) => Exit::AllFailed,
+            }
+        }
+    }
+}
+
+fn presets(args: PresetsArgs) -> Exit {
+    match args.preset {
+        Some(preset) => {
+            println!("{}", serde_json::to_string_pretty(&preset.config()).expect("prompt configs serialize"));
+        }
+        None => {
+            for preset in Preset::value_variants() {
+                let help = preset.to_possible_value().and_then(|value| value.get_help().map(ToString::to_string));
+                println!("{:<12}{}", preset_name(*preset), help.unwrap_or_default());
+            }
+        }
+    }
+    Exit::Success
+}
+
+fn chat(args: ChatArgs, settings: &Settings) -> Exit {
+    let config = match args.prompt.as_deref().map(load_prompt_config).transpose() {
+        Ok(config) => config,
+        Err(e) => {
+            error!(code = "config", "{}", e);
+            return Exit::Config;
+        }
+    };
+
+    let url = args.url.as_deref().or(settings.url.as_deref()).unwrap_or(DEFAULT_URL);
+    let api = args.api.or(settings.api).unwrap_or(Api::Ollama);
+    let model = args
+        .model
+        .or_else(|| config.as_ref().and_then(|c| c.model.clone()))
+        .or_else(|| settings.model.clone());
+    if model.is_none() && api == Api::Ollama {
+        error!(code = "config", "--model is required (or set 'model' in the prompt or config file)");
+        return Exit::Config;
+    }
+
+    let image_data = match validate_image_file(Path::new(&args.image)) {
+        Ok(data) => data,
+        Err(e) => {
+            error!(code = "config", "{}", e);
+            return Exit::Config;
+        }
+    };
+
+    let backend = Backend::new(api, url, model.clone(), args.timeout.or(settings.timeout));
+    let temperature = config.as_ref().map(|c| c.temperature);
+    let mut session = chat::Session::new(config.map(|c| c.system), temperature, model.clone());
+
+    let interactive = io::stdin().is_terminal();
+    if interactive {
+        eprintln!("Chatting about {} (/help for commands)", args.image);
+    }
+
+    let result = chat::repl(&mut session, io::stdin().lock(), io::stdout(), interactive, |turns| {
+        backend.chat(turns, temperature, &image_data).map(|reply| reply.content)
+    });
+
+    match result {
+        Ok(()) => Exit::Success,
+        Err(e) => {
+            error!("{}", e);
+            Exit::AllFailed
+        }
+    }
+}
+
+fn alt_text(args: AltTextArgs, settings: &Settings) -> Exit {
+    let config = match args.prompt.as_deref().map(load_prompt_config).transpose() {
+        Ok(config) => config.unwrap_or_else(alt_text::default_config),
+        Err(e) => {
+            error!(code = "config", "{}", e);
+            return Exit::Config;
+        }
+    };
+
+    let url = args.url.as_deref().or(settings.url.as_deref()).unwrap_or(DEFAULT_URL);
+    let api = args.api.or(settings.api).unwrap_or(Api::Ollama);
+    let model = args.model.clone().or_else(|| config.model.clone()).or_else(|| settings.model.clone());
+    if model.is_none() && api == Api::Ollama {
+        error!(code = "config", "--model is required (or set 'model' in the prompt or config file)");
+        return Exit::Config;
+    }
+    let backend = Backend::new(api, url, model, args.timeout.or(settings.timeout));
+
+    let mut documents = Vec::new();
+    for file in &args.files {
+        let path = PathBuf::from(file);
+        if path.is_dir() {
+            if let Err(e) = alt_text::find_documents(&path, &mut documents) {
+                error!(code = "config", "{}", e);
+                return Exit::Config;
+            }
+        } else {
+            documents.push(path);
+        }
+    }
+
+    if args.audit {
+        return alt_audit(&args, &backend, &config, &documents);
+    }
+
+    // The same image often appears on several pages
+    let mut written: HashMap<PathBuf, String> = HashMap::new();
+    let (mut filled, mut failed, mut unreachable) = (0, 0, true);
+    for path in &documents {
+        let file = &path.display().to_string();
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) => {
+                error!("Failed to read '{}': {}", file, e);
+                failed += 1;
+                unreachable = false;
+                continue;
+            }
+        };
+
+        let missing = alt_text::find_missing(&text, alt_text::is_markdown(path));
+        let mut fills = Vec::new();
+        for image in &missing {
+            let alt = match alt_text::resolve(path, &image.src, args.root.as_deref()) {
+                Ok(image_path) if written.contains_key(&image_path) => Ok(written[&image_path].clone()),
+                Ok(image_path) => validate_image_file(&image_path)
+                    .map_err(CallError::Failed)
+                    .and_then(|data| backend.describe(&config, &data))
+                    .map(|reply| alt_text::clean_alt(&reply.content))
+                    .inspect(|alt| {
+                        written.insert(image_path, alt.clone());
+                    }),
+                Err(e) => Err(CallError::Failed(e)),
+            };
+            match alt {
+                Ok(alt) if !alt.is_empty() => {
+                    debug!(file, line = image.line, src = image.src, alt, "Wrote alt text");
+                    fills.push((image, alt));
+                    unreachable = false;
+                }
+                Ok(_) => {
+                    warn!("{}:{}: the model gave no alt text for '{}'", file, image.line, image.src);
+                    failed += 1;
+                    unreachable = false;
+                }
+                Err(e) => {
+                    warn!("{}:{}: {}", file, image.line, e);
+                    failed += 1;
+                    unreachable &= matches!(e, CallError::Unreachable(_));
+                }
+            }
+        }
+        if fills.is_empty() {
+            continue;
+        }
+        filled += fills.len();
+
+        let patched = alt_text::apply(&text, &fills);
+        if args.in_place {
+            // Write then rename, so an interrupted run never leaves a half-written page
+            let temp = path.with_extension("alt-text.tmp");
+            match fs::write(&temp, &patched).and_then(|_| fs::rename(&temp, path)) {
+                Ok(()) => info!("Added alt text to {} image(s) in {}", fills.len(), file),
+                Err(e) => {
+                    error!("Failed to write '{}': {}", file, e);
+                    failed += fills.len();
+                    filled -= fills.len();
+                }
+            }
+        } else {
+            print!("{}", alt_text::unified_diff(file, &text, &patched));
+        }
+    }
+
+    Exit::for_batch(filled, failed, unreachable)
+}
+
+/// `alt-text --audit`: a JSONL report line for every image, with the model's score and
+/// suggestion, then a summary on stderr.
+fn alt_audit(args: &AltTextArgs, backend: &Backend, config: &PromptConfig, documents: &[PathBuf]) -> Exit {
+    // Keyed by image and alt text, so a logo used on every page is checked once
+    let mut checked: HashMap<(PathBuf, Option<String>), (Option<f64>, String)> = HashMap::new();
+    let mut summary = alt_text::AuditSummary::default();
+    let mut unreachable = true;
+    let mut stdout = io::stdout().lock();
+    for path in documents {
+        let file = path.display().to_string();
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) => {
+                error!("Failed to read '{}': {}", file, e);
+                summary.errors += 1;
+                unreachable = false;
+                continue;
+            }
+        };
+
+        for image in alt_text::find_images(&text, alt_text::is_markdown(path)) {
+            let issue = alt_text::structural_issue(&image);
+            let resolved = alt_text::resolve(path, &image.src, args.root.as_deref()).map_err(CallError::Failed);
+            let checked_image = resolved.and_then(|image_path| {
+                let key = (image_path, image.alt.clone());
+                if let Some(result) = checked.get(&key) {
+                    return Ok(result.clone());
+                }
+                let data = validate_image_file(&key.0).map_err(CallError::Failed)?;
+                let result = match (&image.alt, issue) {
+                    (Some(alt), None) => {
+                        let reply = backend.describe(&alt_text::audit_config(alt, config), &data)?;
+                        let (score, suggested) = alt_text::parse_audit_reply(&reply.content)?;
+                        (Some(score), suggested)
+                    }
+                    _ => (None, alt_text::clean_alt(&backend.describe(config, &data)?.content)),
+                };
+                checked.insert(key, result.clone());
+                Ok(result)
+            });
+
+            let mut entry = alt_text::AuditEntry {
+                file: file.clone(),
+                line: image.line,
+                src: image.src,
+                alt: image.alt,
+                issue,
+                score: None,
+                suggested: None,
+                error: None,
+            };
+            match checked_image {
+                Ok((score, suggested)) => {
+                    if score.is_some_and(|score| score < args.mismatch_below) {
+                        entry.issue = Some(alt_text::AltIssue::Mismatched);
+                    }
+                    entry.score = score;
+                    entry.suggested = Some(suggested);
+                    unreachable = false;
+                }
+                Err(e) => {
+                    warn!("{}:{}: {}", file, entry.line, e);
+                    unreachable &= matches!(e, CallError::Unreachable(_));
+                    entry.error = Some(e.to_string());
+                }
+            }
+            summary.add(&entry);
+            if let Err(e) = writeln!(stdout, "{}", serde_json::to_string(&entry).expect("audit entries serialize")) {
+                error!("Failed to write report: {}", e);
+                return Exit::AllFailed;
+            }
+        }
+    }
+
+    info!(
+        "Audited {} image(s) in {} file(s): {} missing, {} empty, {} placeholder, {} mismatched, {} not checked",
+        summary.images,
+        documents.len(),
+        summary.missing,
+        summary.empty,
+        summary.placeholder,
+        summary.mismatched,
+        summary.errors
+    );
+    Exit::for_batch(summary.images.saturating_sub(summary.errors), summary.errors, unreachable)
+}
+
+fn duplicates(args: DuplicatesArgs, settings: &Settings) -> Exit {
+    let backend = if args.confirm {
+        let url = args.url.as_deref().or(settings.url.as_deref()).unwrap_or(DEFAULT_URL);
+        let api = args.api.or(settings.api).unwrap_or(Api::Ollama);
+        let model = args.model.clone().or_else(|| settings.model.clone());
+        if model.is_none() && api == Api::Ollama {
+            error!(code = "config", "--confirm needs --model (or 'model' in the config file)");
+            return Exit::Config;
+        }
+        Some(Backend::new(api, url, model, args.timeout.or(settings.timeout)))
+    } else {
+        None
+    };
+
+    let mut files = Vec::new();
+    if args.paths.is_empty() {
+        files.extend(read_input_paths().into_iter().map(PathBuf::from));
+    }
+    for path in args.paths.iter().map(PathBuf::from) {
+        if path.is_dir() {
+            if let Err(e) = daemon::find_images(&path, &mut files) {
+                error!(code = "config", "{}", e);
+                return Exit::Config;
+            }
+        } else {
+            files.push(path);
+        }
+    }
+
+    let mut photos = Vec::new();
+    let mut failed = 0;
+    for path in &files {
+        match validate_image_file(path).and_then(|data| duplicates::Photo::read(&path.display().to_string(), &data)) {
+            Ok(photo) => photos.push(photo),
+            Err(e) => {
+                warn!("{}", e);
+                failed += 1;
+            }
+        }
+    }
+
+    let config = duplicates::confirm_config();
+    // Comparisons tried, and how many couldn't reach the server
+    let (mut compared, mut unreachable) = (0, 0);
+    let (mut groups, mut discards) = (0, 0);
+    let mut stdout = io::stdout().lock();
+    for members in duplicates::cluster(&photos, args.threshold) {
+        let keep = duplicates::best(&photos, &members);
+        let mut group = duplicates::DuplicateGroup {
+            group: groups + 1,
+            confirmed: backend.is_some(),
+            photos: Vec::new(),
+        };
+        let keep_data = backend.as_ref().map(|_| fs::read(&photos[keep].file));
+        for i in std::iter::once(keep).chain(members.into_iter().filter(|&i| i != keep)) {
+            let photo = &photos[i];
+            let mut member = duplicates::GroupMember {
+                file: photo.file.clone(),
+                width: photo.width,
+                height: photo.height,
+                bytes: photo.bytes,
+                distance: duplicates::distance(photo.hash, photos[keep].hash),
+                suggestion: if i == keep { duplicates::Suggestion::Keep } else { duplicates::Suggestion::Discard },
+                reason: None,
+            };
+            if let (Some(backend), Some(keep_data), true) = (&backend, &keep_data, i != keep) {
+                let confirmation = keep_data
+                    .as_ref()
+                    .map_err(|e| CallError::Failed(format!("Cannot read '{}': {}", photos[keep].file, e)))
+                    .and_then(|keep_data| {
+                        let data = fs::read(&photo.file).map_err(|e| format!("Cannot read '{}': {}", photo.file, e))?;
+                        let pair = duplicates::side_by_side(keep_data, &data)?;
+                        let reply = backend.describe(&config, &pair)?;
+                        Ok(duplicates::parse_confirmation(&reply.content)?)
+                    });
+                compared += 1;
+                match confirmation {
+                    Ok((true, reason)) => {
+                        member.reason = Some(reason);
+                    }
+                    Ok((false, reason)) => {
+                        debug!(file = photo.file, keep = photos[keep].file, reason, "Not the same scene");
+                        continue;
+                    }
+                    Err(e) => {
+                        // Without the model's word, nothing is suggested for deletion
+                        warn!("Could not compare '{}' with '{}': {}", photo.file, photos[keep].file, e);
+                        if matches!(e, CallError::Unreachable(_)) {
+                            unreachable += 1;
+                        }
+                        failed += 1;
+                        continue;
+                    }
+                }
+            }
+            group.photos.push(member);
+        }
+        if group.photos.len() < 2 {
+            continue;
+        }
+
+        groups += 1;
+        discards += group.photos.len() - 1;
+        if let Err(e) = writeln!(stdout, "{}", serde_json::to_string(&group).expect("groups serialize")) {
+            error!("Failed to write groups: {}", e);
+            return Exit::AllFailed;
+        }
+    }
+
+    info!(
+        "Found {} group(s) of duplicates among {} photo(s); {} could be discarded",
+        groups,
+        photos.len(),
+        discards
+    );
+    if compared > 0 && unreachable == compared {
+        return Exit::Unreachable;
+    }
+    Exit::for_batch(photos.len(), failed, false)
+}
+
+/// Tab-separated image pairs, one per line. Blank lines and `#` comments are skipped; a line
+/// without a tab is an error for that line only.
+fn parse_pairs(content: &str) -> Vec<Result<(String, String), String>> {
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+        .map(|(i, line)| match line.split_once('\t') {
+            Some((first, second)) if !first.trim().is_empty() && !second.trim().is_empty() => {
+                Ok((first.trim().to_string(), second.trim().to_string()))
+            }
+            _ => Err(format!("Line {}: expected two image paths separated by a tab", i + 1)),
+        })
+        .collect()
+}
+
+fn compare(args: CompareArgs, settings: &Settings) -> Exit {
+    let config = match load_prompt_config(&args.prompt) {
+        Ok(config) => config,
+        Err(e) => {
+            error!(code = "config", "{}", e);
+            return Exit::Config;
+        }
+    };
+    let content = match &args.pairs {
+        Some(path) => fs::read_to_string(path).map_err(|e| format!("Failed to read pairs file '{}': {}", path, e)),
+        None => io::read_to_string(io::stdin()).map_err(|e| format!("Failed to read pairs from stdin: {}", e)),
+    };
+    let pairs = match content {
+        Ok(content) => parse_pairs(&content),
+        Err(e) => {
+            error!(code = "config", "{}", e);
+            return Exit::Config;
+        }
+    };
+    let (backend, mut output) = match args.server.open(&config, settings) {
+        Ok(opened) => opened,
+        Err(e) => {
+            error!(code = "config", "{}", e);
+            return Exit::Config;
+        }
+    };
+
+    let runner =
+        BatchRunner::new(backend, config).with_language(args.server.language.clone()).with_field_map(settings.field_map());
+    let mut tally = ItemTally::default();
+    for pair in pairs {
+        let result = pair.map_err(ItemError::Invalid).and_then(|(first, second)| runner.compare(&first, &second));
+        match result {
+            Ok(described) => {
+                if let Err(exit) = tally.write(&mut output, &described.record) {
+                    return exit;
+                }
+            }
+            Err(e) => {
+                error!("{}", e);
+                tally.fail(&e);
+            }
+        }
+    }
+
+    info!("Compared {} pair(s), {} failed", tally.succeeded, tally.failed);
+    tally.exit()
+}
+
+fn products(args: ProductsArgs, settings: &Settings) -> Exit {
+    let config = match load_prompt_config(&args.prompt) {
+        Ok(config) => config,
+        Err(e) => {
+            error!(code = "config", "{}", e);
+            return Exit::Config;
+        }
+    };
+    let content = match &args.shots {
+        Some(path) => fs::read_to_string(path).map_err(|e| format!("Failed to read shots file '{}': {}", path, e)),
+        None => io::read_to_string(io::stdin()).map_err(|e| format!("Failed to read shots from stdin: {}", e)),
+    };
+    let products = match content.and_then(|content| parse_products(&content)) {
+        Ok(products) => products,
+        Err(e) => {
+            error!(code = "config", "{}", e);
+            return Exit::Config;
+        }
+    };
+    let (backend, mut output) = match args.server.open(&config, settings) {
+        Ok(opened) => opened,
+        Err(e) => {
+            error!(code = "config", "{}", e);
+            return Exit::Config;
+        }
+    };
+
+    let runner =
+        BatchRunner::new(backend, config).with_language(args.server.language.clone()).with_field_map(settings.field_map());
+    let per_request = args.shots_per_request.map(|n| n as usize);
+    let mut tally = ItemTally::default();
+    for product in &products {
+        match runner.describe_product(product, per_request) {
+            Ok(described) => {
+                if let Err(exit) = tally.write(&mut output, &described.record) {
+                    return exit;
+                }
+            }
+            Err(e) => {
+                error!("{}: {}", product.sku, e);
+                tally.fail(&e);
+            }
+        }
+    }
+
+    info!("Described {} product(s), {} failed", tally.succeeded, tally.failed);
+    tally.exit()
+}
+
+fn followup(args: FollowupArgs, settings: &Settings) -> Exit {
+    let config = match load_prompt_config(&args.prompt) {
+        Ok(config) => config,
+        Err(e) => {
+            error!(code = "config", "{}", e);
+            return Exit::Config;
+        }
+    };
+    let records = match read_output_records(&args.input) {
+        Ok(records) => records,
+        Err(e) => {
+            error!(code = "config", "{}", e);
+            return Exit::Config;
+        }
+    };
+    let (backend, mut output) = match args.server.open(&config, settings) {
+        Ok(opened) => opened,
+        Err(e) => {
+            error!(code = "config", "{}", e);
+            return Exit::Config;
+        }
+    };
+
+    let runner =
+        BatchRunner::new(backend, config).with_language(args.server.language.clone()).with_field_map(settings.field_map());
+    let mut tally = ItemTally::default();
+    for previous in &records {
+        // Images skipped by the NSFW filter, or embedded only, have no answer to follow up
+        if previous.response.is_null() {
+            if let Err(exit) = write_record(&mut output, previous) {
+                return exit;
+            }
+            continue;
+        }
+        match runner.follow_up(previous) {
+            Ok(described) => {
+                if let Err(exit) = tally.write(&mut output, &described.record) {
+                    return exit;
+                }
+            }
+            Err(e) => {
+                error!("{}: {}", previous.file, e);
+                tally.fail(&e);
+            }
+        }
+    }
+
+    info!("Followed up {} record(s), {} failed", tally.succeeded, tally.failed);
+    tally.exit()
+}
+
+fn video(args: VideoArgs, settings: &Settings) -> Exit {
+    if !args.every.is_finite() || args.every <= 0.0 {
+        error!(code = "config", "--every must be more than 0 seconds");
+        return Exit::Config;
+    }
+    let config = match load_prompt_config(&args.prompt) {
+        Ok(config) => config,
+        Err(e) => {
+            error!(code = "config", "{}", e);
+            return Exit::Config;
+        }
+    };
+    let summary_config = match (&args.summary_prompt, args.no_summary) {
+        (_, true) => None,
+        (Some(path), false) => match load_prompt_config(path) {
+            Ok(config) => Some(config),
+            Err(e) => {
+                error!(code = "config", "{}", e);
+                return Exit::Config;
+            }
+        },
+        (None, false) => Some(video::summary_config()),
+    };
+    let summary_config = summary_config.map(|mut summary| {
+        if let Some(language) = &args.server.language {
+            summary.system = format!("{}\n\n{}", summary.system, language.instruction());
+        }
+        summary
+    });
+    let extractor = FrameExtractor::new("ffmpeg", args.every);
+    if let Err(e) = extractor.check() {
+        error!(code = "config", "{}", e);
+        return Exit::Config;
+    }
+    let (backend, mut output) = match args.server.open(&config, settings) {
+        Ok(opened) => opened,
+        Err(e) => {
+            error!(code = "config", "{}", e);
+            return Exit::Config;
+        }
+    };
+    let videos = if args.videos.is_empty() { read_input_paths() } else { args.videos };
+
+    let runner =
+        BatchRunner::new(backend, config).with_language(args.server.language.clone()).with_field_map(settings.field_map());
+    // Frames and summaries written, and those that failed
+    let mut tally = ItemTally::default();
+    for (index, video) in videos.iter().enumerate() {
+        let dir = std::env::temp_dir().join(format!("nineladies_video_{}_{}", std::process::id(), index));
+        let frames = extractor.extract(Path::new(video), &dir);
+        let frames = match frames {
+            Ok(frames) if frames.is_empty() => Err(format!("No frames could be taken from '{}'", video)),
+            frames => frames,
+        };
+        let frames = match frames {
+            Ok(frames) => frames,
+            Err(e) => {
+                error!("{}", e);
+                tally.failed += 1;
+                fs::remove_dir_all(&dir).ok();
+                continue;
+            }
+        };
+        info!("Describing {} frame(s) of '{}'", frames.len(), video);
+
+        let mut descriptions = Vec::new();
+        for (time, frame) in &frames {
+            let described = validate_image_file(frame)
+                .map_err(ItemError::Invalid)
+                .and_then(|data| runner.describe_data(video, &data));
+            match described {
+                Ok(mut described) => {
+                    described.record.frame_time = Some(*time);
+                    descriptions.push((*time, embedding_text(&described.record.response)));
+                    if let Err(exit) = tally.write(&mut output, &described.record) {
+                        fs::remove_dir_all(&dir).ok();
+                        return exit;
+                    }
+                }
+                Err(e) => {
+                    error!("Error describing '{}' at {}: {}", video, video::format_time(*time), e);
+                    tally.fail(&e);
+                }
+            }
+        }
+        fs::remove_dir_all(&dir).ok();
+
+        let Some(summary_config) = &summary_config else {
+            continue;
+        };
+        if descriptions.is_empty() {
+            error!("No frames of '{}' were described, so it can't be summarized", video);
+            tally.failed += 1;
+            continue;
+        }
+        let summary = PromptConfig {
+            prompt: video::summary_prompt(summary_config, &descriptions),
+            ..summary_config.clone()
+        };
+        let started = std::time::Instant::now();
+        let record = runner
+            .backend()
+            .describe_images(&summary, &[])
+            .map_err(ItemError::Call)
+            .and_then(|reply| {
+                let sha256 = sha256_file(Path::new(video)).map_err(ItemError::Invalid)?;
+                let mut record = OutputRecord::new(video, &sha256, parse_model_content(reply.content));
+                record.frames = Some(descriptions.len());
+                record.timing = Some(Timing {
+                    wall_ms: started.elapsed().as_millis() as u64,
+                    server: reply.timing,
+                });
+                Ok(record)
+            });
+        match record {
+            Ok(record) => {
+                if let Err(exit) = tally.write(&mut output, &record) {
+                    return exit;
+                }
+            }
+            Err(e) => {
+                error!("Error summarizing '{}': {}", video, e);
+                tally.fail(&e);
+            }
+        }
+    }
+
+    info!("Video run complete: {} record(s) written, {} failed", tally.succeeded, tally.failed);
+    tally.exit()
+}
+
+fn assertions(args: AssertArgs, settings: &Settings) -> Exit {
+    let content = match &args.checks {
+        Some(path) => fs::read_to_string(path).map_err(|e| format!("Failed to read checks file '{}': {}", path, e)),
+        None => io::read_to_string(io::stdin()).map_err(|e| format!("Failed to read checks from stdin: {}", e)),
+    };
+    let checks: Result<Vec<Assertion>, String> = match content {
+        Ok(content) => content
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(i, line)| Assertion::parse(line).map_err(|e| format!("Line {}: {}", i + 1, e)))
+            .collect(),
+        Err(e) => Err(e),
+    };
+    let checks = match checks {
+        Ok(checks) => checks,
+        Err(e) => {
+            error!(code = "config", "{}", e);
+            return Exit::Config;
+        }
+    };
+    let config = match args.prompt.as_deref().map(load_prompt_config).transpose() {
+        Ok(config) => config,
+        Err(e) => {
+            error!(code = "config", "{}", e);
+            return Exit::Config;
+        }
+    };
+    if config.is_none() && checks.iter().any(|check| check.expect.is_some()) {
+        error!(code = "config", "Checks with 'expect' need --prompt to ask the question whose response they check");
+        return Exit::Config;
+    }
+
+    let url = args.url.as_deref().or(settings.url.as_deref()).unwrap_or(DEFAULT_URL);
+    let api = args.api.or(settings.api).unwrap_or(Api::Ollama);
+    let model = args
+        .model
+        .or_else(|| config.as_ref().and_then(|config| config.model.clone()))
+        .or_else(|| settings.model.clone());
+    if model.is_none() && api == Api::Ollama {
+        error!(code = "config", "--model is required (or set 'model' in the config file)");
+        return Exit::Config;
+    }
+    let backend = Backend::new(api, url, model, args.timeout.or(settings.timeout));
+
+    let (mut passed, mut failed, mut errors, mut connect_failures) = (0, 0, 0, 0);
+    let mut failures = Vec::new();
+    let mut stdout = io::stdout().lock();
+    for check in checks {
+        let mut result = AssertionResult {
+            file: check.file.clone(),
+            condition: check.condition.clone(),
+            expect: check.expect.clone(),
+            passed: true,
+            reason: None,
+            mismatches: Vec::new(),
+            response: None,
+            error: None,
+        };
+        let outcome = validate_image_file(Path::new(&check.file)).map_err(CallError::Failed).and_then(|image_data| {
+            if let Some(condition) = &check.condition {
+                let reply = backend.describe(&assertions::condition_config(condition), &image_data)?;
+                let (holds, reason) = assertions::parse_verdict(&reply.content)?;
+                result.passed &= holds;
+                result.reason = Some(reason);
+            }
+            if let (Some(expect), Some(config)) = (&check.expect, &config) {
+                let response = parse_model_content(backend.describe(config, &image_data)?.content);
+                result.mismatches = assertions::mismatches(expect, &response, "response");
+                result.passed &= result.mismatches.is_empty();
+                result.response = Some(response);
+            }
+            Ok(())
+        });
+        match outcome {
+            Ok(()) if result.passed => passed += 1,
+            Ok(()) => {
+                failed += 1;
+                let mut what: Vec<String> = check.condition.iter().map(|c| format!("\"{}\"", c)).collect();
+                what.extend(result.mismatches.iter().cloned());
+                failures.push(format!("{}: {}", check.file, what.join("; ")));
+            }
+            Err(e) => {
+                if matches!(e, CallError::Unreachable(_)) {
+                    connect_failures += 1;
+                }
+                errors += 1;
+                result.passed = false;
+                result.error = Some(e.to_string());
+                error!("Could not check '{}': {}", check.file, e);
+            }
+        }
+        if let Err(e) = writeln!(stdout, "{}", serde_json::to_string(&result).expect("results serialize")) {
+            error!("Failed to write results: {}", e);
+            return Exit::AllFailed;
+        }
+    }
+
+    for failure in &failures {
+        error!("Failed: {}", failure);
+    }
+    info!("{} check(s) passed, {} failed, {} could not be made", passed, failed, errors);
+    if failed > 0 {
+        return Exit::AssertionsFailed;
+    }
+    Exit::for_batch(passed, errors, connect_failures > 0)
+}
+
+/// Review hooks for a real terminal: previews drawn inline, edits in `$VISUAL`/`$EDITOR`.
+struct TerminalReview {
+    protocol: ImageProtocol,
+    output: PathBuf,
+    clear_screen: bool,
+}
+
+impl ReviewHooks for TerminalReview {
+    fn show(&mut self, record: &OutputRecord, out: &mut dyn Write) -> io::Result<()> {
+        if self.clear_screen {
+            write!(out, "\x1b[2J\x1b[H")?;
+        }
+        let preview = fs::read(record.current_path())
+            .map_err(|e| format!("cannot read {}: {}", record.current_path(), e))
+            .and_then(|data| term_image::render(self.protocol, &data));
+        match preview {
+            Ok(sequence) => out.write_all(&sequence),
+            Err(e) => writeln!(out, "(no preview: {})", e),
+        }
+    }
+
+    fn edit(&mut self, response: &serde_json::Value) -> Result<serde_json::Value, String> {
+        let editor = std::env::var("VISUAL")
+            .or_else(|_| std::env::var("EDITOR"))
+            .unwrap_or_else(|_| "vi".to_string());
+        let path = std::env::temp_dir().join(format!("9ladies-review-{}.txt", std::process::id()));
+        fs::write(&path, review::editable_text(response))
+            .map_err(|e| format!("Failed to write '{}': {}", path.display(), e))?;
+
+        // EDITOR may carry arguments, e.g. "code --wait"
+        let mut words = editor.split_whitespace();
+        let program = words.next().ok_or("EDITOR is empty")?;
+        let status = std::process::Command::new(program)
+            .args(words)
+            .arg(&path)
+            .status()
+            .map_err(|e| format!("Failed to run editor '{}': {}", editor, e));
+
+        let text = fs::read_to_string(&path).map_err(|e| format!("Failed to read '{}': {}", path.display(), e));
+        fs::remove_file(&path).ok();
+        if !status?.success() {
+            return Err(format!("Editor '{}' exited with an error", editor));
+        }
+        review::parse_edited(response, &text?)
+    }
+
+    fn save(&mut self, records: &[OutputRecord]) -> Result<(), String> {
+        let mut content = String::new();
+        for record in records {
+            content.push_str(&serde_json::to_string(record).expect("records serialize"));
+            content.push('\n');
+        }
+        // Write then rename, so an interrupted save never leaves a half-written file
+        let temp = self.output.with_extension("jsonl.tmp");
+        fs::write(&temp, content)
+            .and_then(|_| fs::rename(&temp, &self.output))
+            .map_err(|e| format!("Failed to save '{}': {}", self.output.display(), e))
+    }
+}
+
+fn review(args: ReviewArgs) -> Exit {
+    let mut records = match read_output_records(&args.input) {
+        Ok(records) => records,
+        Err(e) => {
+            error!(code = "config", "{}", e);
+            return Exit::Config;
+        }
+    };
+
+    let queue: Vec<usize> = (0..records.len())
+        .filter(|&i| args.all || records[i].review.is_none())
+        .filter(|&i| match args.below {
+            Some(below) => records[i].confidence.as_ref().is_some_and(|confidence| confidence.score < below),
+            None => true,
+        })
+        .collect();
+    if queue.is_empty() {
+        info!("Nothing to review in '{}' (use --all to review it again)", args.input);
+        return Exit::Success;
+    }
+
+    let mut hooks = TerminalReview {
+        protocol: args.image_protocol.resolve(),
+        output: args.output_path(),
+        clear_screen: io::stdout().is_terminal(),
+    };
+    let mut stdout = io::stdout();
+    match review::run_review(&mut records, &queue, io::stdin().lock(), &mut stdout, &mut hooks) {
+        Ok(summary) if summary == review::ReviewSummary::default() => {
+            info!("No records were changed");
+            Exit::Success
+        }
+        Ok(summary) => {
+            info!(
+                accepted = summary.accepted,
+                edited = summary.edited,
+                flagged = summary.flagged,
+                "Review saved to '{}'",
+                hooks.output.display()
+            );
+            Exit::Success
+        }
+        Err(e) => {
+            error!("{}", e);
+            Exit::AllFailed
+        }
+    }
+}
+
+/// The tracer for `--trace-dir`, if given.
+fn tracer(dir: Option<&Path>, images: bool) -> Result<Option<Arc<Tracer>>, String> {
+    dir.map(|dir| Tracer::new(dir, images).map(Arc::new)).transpose()
+}
+
+/// The fixture folder for `--record` or `--replay`, if given.
+fn fixtures(record: Option<&Path>, replay: Option<&Path>) -> Result<Option<Arc<Fixtures>>, String> {
+    match (record, replay) {
+        (Some(dir), _) => Fixtures::record(dir).map(|fixtures| Some(Arc::new(fixtures))),
+        (None, Some(dir)) => Fixtures::replay(dir).map(|fixtures| Some(Arc::new(fixtures))),
+        (None, None) => Ok(None),
+    }
+}
+
+/// The digest the server reports for `model`, for `--repro-bundle`. Only Ollama reports one.
+fn model_digest(backend: &Backend, model: &str) -> Option<String> {
+    match backend.list_models() {
+        Ok(models) => find_model(&models, model).and_then(|m| m.digest.clone()),
+        Err(e) => {
+            warn!("Couldn't look up the model's digest for the bundle: {}", e);
+            None
+        }
+    }
+}
+
+/// The request rate limit from `--rpm` or `--rps`, or else the config file's `rpm`.
+fn throttle(rpm: Option<f64>, rps: Option<f64>, settings: &Settings) -> Result<Option<Arc<Throttle>>, String> {
+    let throttle = match (rpm, rps) {
+        (Some(rpm), _) => Throttle::per_minute(rpm).map_err(|e| format!("--rpm: {}", e))?,
+        (None, Some(rps)) => Throttle::per_second(rps).map_err(|e| format!("--rps: {}", e))?,
+        (None, None) => match settings.rpm {
+            Some(rpm) => Throttle::per_minute(rpm).map_err(|e| format!("rpm in the config file: {}", e))?,
+            None => return Ok(None),
+        },
+    };
+    Ok(Some(Arc::new(throttle)))
+}
+
+/// The prompt config from a `--prompt` file, or else the `--preset` one.
+fn prompt_config(prompt: Option<&str>, preset: Option<Preset>, locale: Option<&str>) -> Result<PromptConfig, String> {
+    match (prompt, preset) {
+        (Some(path), _) => load_localized_prompt_config(path, locale),
+        (None, Some(preset)) => Ok(preset.config()),
+        (None, None) => Err("--prompt or --preset is required".to_string()),
+    }
+}
+
+/// A preset's name as given on the command line.
+fn preset_name(preset: Preset) -> String {
+    preset.to_possible_value().map(|value| value.get_name().to_string()).unwrap_or_default()
+}
+
+/// Build the runner for the long-running modes, logging any problem with the arguments.
+fn pipeline_runner(args: PipelineArgs, settings: &Settings) -> Result<BatchRunner, Exit> {
+    let config = prompt_config(args.prompt.as_deref(), args.preset, args.locale.as_deref()).map_err(|e| {
+        error!("{}", e);
+        Exit::Config
+    })?;
+
+    let url = args.url.as_deref().or(settings.url.as_deref()).unwrap_or(DEFAULT_URL);
+    let api = args.api.or(settings.api).unwrap_or(Api::Ollama);
+    let model = args.model.or_else(|| config.model.clone()).or_else(|| settings.model.clone());
+    if model.is_none() && api == Api::Ollama {
+        error!("--model is required (or set 'model' in prompt config)");
+        return Err(Exit::Config);
+    }
+
+    let transform = args.script.as_deref().map(load_script).transpose().map_err(|e| {
+        error!("{}", e);
+        Exit::Config
+    })?;
+    let ocr = args.ocr.engine(args.preset).map_err(|e| {
+        error!("{}", e);
+        Exit::Config
+    })?;
+    let nsfw = args.nsfw.filter().map_err(|e| {
+        error!("{}", e);
+        Exit::Config
+    })?;
+    let embed_model = embedding_model(args.mode, args.embed_input, args.embed_model.as_ref(), api, args.labels.is_some()).map_err(|e| {
+        error!("{}", e);
+        Exit::Config
+    })?;
+
+    let throttle = throttle(args.rpm, args.rps, settings).map_err(|e| {
+        error!("{}", e);
+        Exit::Config
+    })?;
+    let tracer = tracer(args.trace_dir.as_deref(), args.trace_images).map_err(|e| {
+        error!("{}", e);
+        Exit::Config
+    })?;
+    let fixtures = fixtures(args.record.as_deref(), args.replay.as_deref()).map_err(|e| {
+        error!("{}", e);
+        Exit::Config
+    })?;
+    let backend = Backend::new(api, url, model, args.timeout.or(settings.timeout))
+        .with_throttle(throttle)
+        .with_tracer(tracer)
+        .with_fixtures(fixtures)
+        .with_logprobs(args.confidence == Some(ConfidenceMethod::Logprobs));
+    let runner = BatchRunner::new(backend, config)
+        .with_language(args.language)
+        .with_field_map(settings.field_map())
+        .with_transform(transform)
+        .with_ocr(ocr)
+        .with_nsfw(nsfw)
+        .with_size_limit(args.size_limit.limit())
+        .with_image_info(args.image_info)
+        .with_image_stats(args.image_stats)
+        .with_exif(args.exif)
+        .with_embedding(embed_model, args.embed_input)
+        .with_labels(args.labels, args.top_k.map(|k| k as usize))
+        .with_confidence(args.confidence, args.confidence_samples as usize)
+        .with_candidates(args.candidates as usize)
+        .with_translations(args.translate)
+        .with_detections(args.detections);
+    with_barcodes(runner, args.barcodes)
+        .and_then(|runner| with_face_blur(runner, &args.faces))
+        .map_err(|e| {
+            error!("{}", e);
+            Exit::Config
+        })
+}
+
+#[cfg(feature = "serve")]
+fn serve(args: ServeArgs, settings: &Settings) -> Exit {
+    let runner = match pipeline_runner(args.pipeline, settings) {
+        Ok(runner) => runner,
+        Err(exit) => return exit,
+    };
+    let shutdown = match Shutdown::install() {
+        Ok(shutdown) => shutdown,
+        Err(e) => {
+            error!(code = "config", "{}", e);
+            return Exit::Config;
+        }
+    };
+
+    let options = serve::ServeOptions {
+        max_upload_bytes: args.max_upload_mb.saturating_mul(1_000_000),
+        path_root: args.path_root,
+    };
+    match serve::serve(runner, options, &args.listen, args.workers as usize, &shutdown) {
+        Ok(()) => Exit::Success,
+        Err(e) => {
+            error!("{}", e);
+            Exit::Config
+        }
+    }
+}
+
+fn mcp(args: McpArgs, settings: &Settings) -> Exit {
+    let runner = match pipeline_runner(args.pipeline, settings) {
+        Ok(runner) => runner,
+        Err(exit) => return exit,
+    };
+    match mcp::serve(&runner, io::stdin().lock(), io::stdout().lock()) {
+        Ok(()) => Exit::Success,
+        Err(e) => {
+            error!("{}", e);
+            Exit::AllFailed
+        }
+    }
+}
+
+#[cfg(feature = "kafka")]
+fn kafka(args: KafkaArgs, settings: &Settings) -> Exit {
+    let runner = match pipeline_runner(args.pipeline, settings) {
+        Ok(runner) => runner,
+        Err(exit) => return exit,
+    };
+    let shutdown = match Shutdown::install() {
+        Ok(shutdown) => shutdown,
+        Err(e) => {
+            error!(code = "config", "{}", e);
+            return Exit::Config;
+        }
+    };
+
+    let options = kafka::KafkaOptions {
+        brokers: args.brokers,
+        input_topic: args.input_topic,
+        output_topic: args.output_topic,
+        group: args.group,
+    };
+    match kafka::consume(&runner, &options, &shutdown) {
+        Ok(()) => Exit::Success,
+        Err(e) => {
+            error!("{}", e);
+            Exit::Unreachable
+        }
+    }
+}
+
+#[cfg(feature = "redis")]
+fn redis(args: RedisArgs, settings: &Settings) -> Exit {
+    let runner = match pipeline_runner(args.pipeline, settings) {
+        Ok(runner) => runner,
+        Err(exit) => return exit,
+    };
+    let shutdown = match Shutdown::install() {
+        Ok(shutdown) => shutdown,
+        Err(e) => {
+            error!(code = "config", "{}", e);
+            return Exit::Config;
+        }
+    };
+
+    let options = redis::RedisOptions {
+        queue: args.queue,
+        results: args.results,
+        stream: args.stream,
+        visibility_timeout: Duration::from_secs(args.visibility_timeout),
+        max_attempts: args.max_attempts,
+    };
+    match redis::consume(&runner, &args.redis_url, &options, &shutdown) {
+        Ok(()) => Exit::Success,
+        Err(e) => {
+            error!("{}", e);
+            Exit::Unreachable
+        }
+    }
+}
+
+fn daemon(args: DaemonArgs, settings: &Settings) -> Exit {
+    let runner = match pipeline_runner(args.pipeline, settings) {
+        Ok(runner) => runner,
+        Err(exit) => return exit,
+    };
+    let _state_lock = match FileLock::acquire(Path::new(&args.state), "State database") {
+        Ok(lock) => lock,
+        Err(e) => {
+            error!(code = "config", "{}", e);
+            return Exit::Config;
+        }
+    };
+    let store = match JobStore::open(&args.state) {
+        Ok(store) => store,
+        Err(e) => {
+            error!(code = "config", "{}", e);
+            return Exit::Config;
+        }
+    };
+    let mut output: Box<dyn Write> = match &args.output {
+        Some(path) => match LockedOutput::open(Path::new(path), OutputMode::Append) {
+            Ok(output) => Box::new(output),
+            Err(e) => {
+                error!(code = "config", "{}", e);
+                return Exit::Config;
+            }
+        },
+        None => Box::new(io::stdout()),
+    };
+    let shutdown = match Shutdown::install() {
+        Ok(shutdown) => shutdown,
+        Err(e) => {
+            error!(code = "config", "{}", e);
+            return Exit::Config;
+        }
+    };
+
+    #[cfg(feature = "serve")]
+    let queued = match &args.listen {
+        Some(listen) => {
+            let (sender, queued) = std::sync::mpsc::channel();
+            let options = enqueue::EnqueueOptions {
+                state: args.state.clone(),
+                max_upload_bytes: args.max_upload_mb.saturating_mul(1_000_000),
+                path_root: args.path_root,
+                upload_dir: args.upload_dir,
+            };
+            if let Err(e) = enqueue::spawn(listen, options, sender, shutdown.clone()) {
+                error!(code = "config", "{}", e);
+                return Exit::Config;
+            }
+            Some(queued)
+        }
+        None => None,
+    };
+    #[cfg(not(feature = "serve"))]
+    let queued = None;
 
-#[derive(Debug, Deserialize)]
-struct PromptConfig {
-    system: String,
-    prompt: String,
-    temperature: f32,
-    #[serde(default)]
-    model: Option<String>,
+    let options = daemon::DaemonOptions {
+        dirs: args.dirs,
+        interval: Duration::from_secs(args.interval),
+        queued,
+    };
+    match daemon::watch(&runner, &store, &options, &mut output, &shutdown) {
+        Ok(()) => Exit::Success,
+        Err(e) => {
+            error!("{}", e);
+            Exit::Config
+        }
+    }
 }
 
-#[derive(Serialize)]
-struct OutputRecord {
-    file: String,
-    response: serde_json::Value,
-}
+fn export(args: ExportArgs, settings: &Settings) -> Exit {
+    let records = match read_output_records(&args.input) {
+        Ok(records) => records,
+        Err(e) => {
+            error!(code = "config", "{}", e);
+            return Exit::Config;
+        }
+    };
 
-// Ollama native API types
-#[derive(Serialize)]
-struct OllamaChatRequest {
-    model: String,
-    messages: Vec<OllamaChatMessage>,
-    stream: bool,
-    options: OllamaOptions,
-}
+    let client = build_client(settings.timeout);
+    let ollama_url = args.url.as_deref().or(settings.url.as_deref());
+    let mut sinks = match open_sinks(&args.sinks, &client, ollama_url) {
+        Ok(sinks) if sinks.is_empty() => {
+            error!(code = "config", "Nothing to export to (use --pg-table, --duckdb or --qdrant)");
+            return Exit::Config;
+        }
+        Ok(sinks) => sinks,
+        Err(e) => {
+            error!(code = "config", "{}", e);
+            return Exit::Config;
+        }
+    };
 
-#[derive(Serialize)]
-struct OllamaOptions {
-    temperature: f32,
-}
+    let mut failed = 0;
+    for record in &records {
+        let mut record_failed = false;
+        for sink in sinks.iter_mut() {
+            if let Err(e) = sink.write(record) {
+                error!("Error exporting '{}': {}", record.file, e);
+                record_failed = true;
+            }
+        }
+        failed += record_failed as usize;
+    }
 
-#[derive(Serialize)]
-struct OllamaChatMessage {
-    role: String,
-    content: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    images: Option<Vec<String>>,
+    Exit::for_batch(records.len() - failed, failed, false)
 }
 
-#[derive(Deserialize)]
-struct OllamaChatResponse {
-    message: OllamaMessageResponse,
+/// Estimated size of the request body for one image: base64 grows the image by a third.
+fn estimated_request_bytes(image_len: usize, config: &PromptConfig) -> usize {
+    image_len.div_ceil(3) * 4 + config.system.len() + config.prompt.len() + REQUEST_OVERHEAD_BYTES
 }
 
-#[derive(Deserialize)]
-struct OllamaMessageResponse {
-    content: String,
+fn format_bytes(bytes: usize) -> String {
+    match bytes {
+        b if b >= 1_000_000_000 => format!("{:.1} GB", b as f64 / 1e9),
+        b if b >= 1_000_000 => format!("{:.1} MB", b as f64 / 1e6),
+        b if b >= 1_000 => format!("{:.1} kB", b as f64 / 1e3),
+        b => format!("{} B", b),
+    }
 }
 
-fn detect_image_format(data: &[u8]) -> Option<&'static str> {
-    if data.len() < 12 {
-        return None;
+/// `--dry-run`: check the server, the model and every image, estimate the upload volume,
+/// and report every problem found, without sending any images.
+fn preflight(backend: &Backend, config: &PromptConfig, paths: &[String], prices: Option<Prices>) -> Exit {
+    let mut problems = Vec::new();
+    let mut server_exit = None;
+    let url = backend.url();
+
+    match backend.list_models() {
+        Ok(models) => match backend.model() {
+            Some(model) if !model_available(&models, model) => {
+                problems.push(format!("Model '{}' is not available on {} (see `9ladies models`)", model, url));
+                server_exit = Some(Exit::Config);
+            }
+            Some(model) => info!("Server {} is reachable and has model '{}'", url, model),
+            None => info!("Server {} is reachable", url),
+        },
+        Err(e) => {
+            server_exit = Some(match e {
+                CallError::Unreachable(_) => Exit::Unreachable,
+                CallError::Failed(_) => Exit::Config,
+            });
+            problems.push(format!("Server {}: {}", url, e));
+        }
     }
 
-    // JPEG: starts with FF D8 FF
-    if data.starts_with(&[0xFF, 0xD8, 0xFF]) {
-        return Some("jpeg");
+    let text_tokens = cost::estimate_text_tokens(config.system.len() + config.prompt.len());
+    let (mut valid, mut total_bytes) = (0, 0);
+    let mut largest: Option<(usize, &str)> = None;
+    let mut estimated_usage = TokenUsage::default();
+    // Not downloaded just to check them
+    let (urls, paths): (Vec<&String>, Vec<&String>) = paths.iter().partition(|path| download::is_url(path));
+    if !urls.is_empty() {
+        info!("{} URL input(s) will be checked as they are downloaded", urls.len());
     }
+    for path_str in &paths {
+        match validate_image_file(Path::new(path_str)) {
+            Ok(data) => {
+                valid += 1;
+                let bytes = estimated_request_bytes(data.len(), config);
+                total_bytes += bytes;
+                if largest.is_none_or(|(max, _)| bytes > max) {
+                    largest = Some((bytes, path_str));
+                }
 
-    // PNG: starts with 89 50 4E 47 0D 0A 1A 0A
-    if data.starts_with(&[0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A]) {
-        return Some("png");
+                let image_tokens = cost::image_dimensions(&data).map_or(0, |(w, h)| cost::estimate_image_tokens(w, h));
+                let usage = TokenUsage {
+                    input: text_tokens + image_tokens,
+                    output: cost::ESTIMATED_OUTPUT_TOKENS,
+                };
+                estimated_usage += usage;
+                if let Some(prices) = prices {
+                    debug!(file = path_str.as_str(), input_tokens = usage.input, cost_usd = prices.cost(usage), "Estimated cost");
+                }
+            }
+            Err(e) => problems.push(e),
+        }
     }
 
-    // GIF: starts with GIF87a or GIF89a
-    if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
-        return Some("gif");
+    info!("{} of {} files are valid images", valid, paths.len());
+    if let Some((largest_bytes, largest_file)) = largest {
+        info!(
+            "Estimated upload: {} request(s), {} in total, largest {} ({}), ~{} prompt text tokens plus images",
+            valid,
+            format_bytes(total_bytes),
+            format_bytes(largest_bytes),
+            largest_file,
+            text_tokens * valid as u64
+        );
+        if let Some(prices) = prices {
+            let total = prices.cost(estimated_usage);
+            info!(
+                "Estimated cost: ${:.4} in total, ${:.4} per image on average (~{} input tokens, assuming ~{} output tokens per image)",
+                total,
+                total / valid as f64,
+                estimated_usage.input,
+                cost::ESTIMATED_OUTPUT_TOKENS
+            );
+        }
     }
 
-    // WebP: starts with RIFF....WEBP
-    if data.starts_with(b"RIFF") && &data[8..12] == b"WEBP" {
-        return Some("webp");
+    if problems.is_empty() {
+        info!("Preflight passed");
+    } else {
+        error!("Preflight found {} problem(s):", problems.len());
+        for problem in &problems {
+            error!("  {}", problem);
+        }
     }
 
-    None
+    server_exit.unwrap_or_else(|| Exit::for_batch(valid, paths.len() - valid, false))
 }
 
-fn load_prompt_config(path: &str) -> Result<PromptConfig, String> {
-    let content = fs::read_to_string(path)
-        .map_err(|e| format!("Failed to read prompt file '{}': {}", path, e))?;
-
-    let config: PromptConfig = serde_json::from_str(&content)
-        .map_err(|e| format!("Failed to parse prompt file '{}': {}", path, e))?;
+/// Log the tokens the server reported for the run, and their cost when prices are configured.
+fn report_cost(usage: TokenUsage, replies_without_usage: usize, prices: Option<Prices>) {
+    if usage == TokenUsage::default() {
+        if prices.is_some() && replies_without_usage > 0 {
+            warn!("The server did not report token usage, so the cost is unknown");
+        }
+        return;
+    }
 
-    if config.temperature < 0.0 || config.temperature > 2.0 {
-        return Err(format!(
-            "Temperature must be between 0.0 and 2.0, got {}",
-            config.temperature
-        ));
+    match prices {
+        Some(prices) => info!(
+            input_tokens = usage.input,
+            output_tokens = usage.output,
+            cost_usd = prices.cost(usage),
+            "Cost: ${:.4} for {} input and {} output tokens",
+            prices.cost(usage),
+            usage.input,
+            usage.output
+        ),
+        None => info!(input_tokens = usage.input, output_tokens = usage.output, "Token usage"),
+    }
+    if prices.is_some() && replies_without_usage > 0 {
+        warn!("{} replies did not report token usage and are not included in the cost", replies_without_usage);
     }
+}
 
-    Ok(config)
+/// What `run` does with each described image: tags and file actions, the output file or
+/// stdout, sinks, webhooks and the progress display.
+struct RunOutput<'a> {
+    args: &'a RunArgs,
+    client: &'a reqwest::blocking::Client,
+    shutdown: &'a Shutdown,
+    progress: Progress,
+    sinks: Vec<Box<dyn RecordSink>>,
+    output_file: Option<LockedOutput>,
+    pretty_style: Option<pretty::Style>,
+    claimed_paths: HashSet<PathBuf>,
+    exec: Option<String>,
+    store: Option<JobStore>,
+    /// The record for the image in progress, kept for the --exec hook and the job store
+    last_record: Option<OutputRecord>,
+    stream: Option<Arc<pretty::StreamPrinter>>,
+    /// Each file and its caption text, for --caption-report
+    captions: Option<Vec<(String, String)>>,
+    /// The prompt config's schema, which a response must follow for its file to be moved or copied
+    schema: Option<serde_json::Value>,
 }
 
-fn validate_image_file(path: &Path) -> Result<Vec<u8>, String> {
-    if !path.exists() {
-        return Err(format!("File not found: {}", path.display()));
+impl BatchHooks for RunOutput<'_> {
+    fn stop_requested(&self) -> bool {
+        self.shutdown.requested()
+    }
+
+    fn started(&mut self, file: &str) {
+        if let Some(store) = &self.store {
+            if let Err(e) = store.start(file, FileStamp::read(Path::new(file))) {
+                warn!("{}", e);
+            }
+        }
+        self.progress.item_started(file);
+        if let Some(stream) = &self.stream {
+            stream.header(file);
+        }
+    }
+
+    fn described(&mut self, described: &mut Described) -> Result<(), String> {
+        let args = self.args;
+        // An image the NSFW filter kept from the model has no response to act on
+        let skipped = described.skipped();
+        let record = &mut described.record;
+        let file = record.file.clone();
+        let path = Path::new(&file);
+        let mut item_error: Option<String> = None;
+
+        if args.sort_keys {
+            record.response.sort_all_objects();
+        }
+
+        if !args.finder_tags.is_empty() {
+            let tags = finder_tags_from_response(&record.response, &args.finder_tags);
+            if let Err(e) = write_finder_tags(path, &tags) {
+                error!(code = "file_action_failed", path = file, "Error processing '{}': {}", file, e);
+                item_error.get_or_insert(e);
+            }
+        }
+
+        if let (Some(dir), Some(detections)) = (&args.annotate_dir, &record.detections) {
+            let annotated = fs::read(path)
+                .map_err(|e| format!("Cannot read '{}' to annotate: {}", file, e))
+                .and_then(|data| grounding::write_annotated(path, &data, detections, dir));
+            match annotated {
+                Ok(target) => record.annotated = Some(target),
+                Err(e) => {
+                    error!(code = "file_action_failed", path = file, "Error processing '{}': {}", file, e);
+                    item_error.get_or_insert(e);
+                }
+            }
+        }
+
+        if skipped {
+            debug!(path = file, "Skipped by the NSFW filter; no file actions");
+        } else if let Err(e) = apply_file_actions(args, self.schema.as_ref(), path, record, &mut self.claimed_paths) {
+            error!(code = "file_action_failed", path = file, "Error processing '{}': {}", file, e);
+            item_error.get_or_insert(e);
+        }
+
+        if self.exec.is_some() || self.store.is_some() {
+            self.last_record = Some(record.clone());
+        }
+        if let Some(captions) = &mut self.captions {
+            captions.push((file.clone(), caption_report::caption_text(&record.response)));
+        }
+
+        let line = serde_json::to_string(&*record).unwrap();
+        if let Some(output) = &mut self.output_file {
+            // One write per record, so a crash never leaves half a line
+            if let Err(e) = output.write_all(format!("{}\n", line).as_bytes()) {
+                let e = format!("Failed to write to '{}': {}", args.output.as_deref().unwrap_or_default(), e);
+                error!(code = "output_failed", path = file, "{}", e);
+                item_error.get_or_insert(e);
+            }
+        }
+        match &self.pretty_style {
+            Some(style) => self.progress.suspend(|| println!("{}", pretty::render(record, style))),
+            None if self.output_file.is_none() => self.progress.suspend(|| println!("{}", line)),
+            None => {}
+        }
+
+        for sink in self.sinks.iter_mut() {
+            if let Err(e) = sink.write(record) {
+                error!(code = "sink_failed", path = file, "Error processing '{}': {}", file, e);
+                item_error.get_or_insert(e);
+            }
+        }
+
+        if let Some(url) = &args.webhook {
+            if args.webhook_events != WebhookEvents::Summary {
+                let payload = WebhookPayload::Record { record };
+                if let Err(e) = send_webhook(self.client, url, &payload) {
+                    warn!("{}", e);
+                }
+            }
+        }
+
+        item_error.map_or(Ok(()), Err)
     }
 
-    let data = fs::read(path).map_err(|e| format!("Cannot read file '{}': {}", path.display(), e))?;
+    fn finished(&mut self, file: &str, error: Option<&str>) {
+        let record = self.last_record.take();
+        if let Some(store) = &self.store {
+            let saved = match (&record, error) {
+                (Some(record), None) => store.mark_done(file, record),
+                (record, error) => {
+                    let sha256 = record.as_ref().map_or("", |r| r.sha256.as_str());
+                    store.mark_failed(file, sha256, error.unwrap_or("No record was produced"))
+                }
+            };
+            if let Err(e) = saved {
+                warn!("{}", e);
+            }
+        }
+        if let Some(command) = &self.exec {
+            let event = exec::HookEvent {
+                file,
+                record: record.as_ref(),
+                error,
+            };
+            if let Err(e) = self.progress.suspend(|| exec::run(command, &event)) {
+                warn!("{}", e);
+            }
+        }
+        self.progress.item_done(file, error);
+    }
 
-    if detect_image_format(&data).is_none() {
-        return Err(format!(
-            "Not a valid image format (expected JPEG, PNG, WebP, or GIF): {}",
-            path.display()
-        ));
+    fn paused(&mut self, opens: &str) {
+        // Records are written as they come, so everything done so far is already saved
+        if let Some(output) = &mut self.output_file {
+            if let Err(e) = output.flush() {
+                warn!("Failed to flush '{}': {}", self.args.output.as_deref().unwrap_or_default(), e);
+            }
+        }
+        self.progress.paused(opens);
     }
 
-    Ok(data)
+    fn resumed(&mut self) {
+        self.progress.resumed();
+    }
 }
 
-fn call_model(
-    client: &reqwest::blocking::Client,
-    base_url: &str,
-    model: &str,
-    config: &PromptConfig,
-    image_data: &[u8],
-) -> Result<serde_json::Value, String> {
-    let base64_image = BASE64.encode(image_data);
-
-    let request = OllamaChatRequest {
-        model: model.to_string(),
-        messages: vec![
-            OllamaChatMessage {
-                role: "system".to_string(),
-                content: config.system.clone(),
-                images: None,
-            },
-            OllamaChatMessage {
-                role: "user".to_string(),
-                content: config.prompt.clone(),
-                images: Some(vec![base64_image]),
-            },
-        ],
-        stream: false,
-        options: OllamaOptions {
-            temperature: config.temperature,
-        },
-    };
+/// `run --listen-input`: records go where `run` puts them, and each job's result goes back
+/// to the connection it came in on.
+struct ListenHooks<'a, 'b> {
+    output: &'a mut RunOutput<'b>,
+    /// The job being described, put here as it is taken from the socket
+    current: &'a RefCell<Option<Received>>,
+    record: Option<OutputRecord>,
+}
 
-    let url = format!("{}/api/chat", base_url.trim_end_matches('/'));
+impl BatchHooks for ListenHooks<'_, '_> {
+    fn stop_requested(&self) -> bool {
+        self.output.stop_requested()
+    }
 
-    let response = client
-        .post(&url)
-        .json(&request)
-        .send()
-        .map_err(|e| format!("Request failed: {}", e))?;
+    fn started(&mut self, file: &str) {
+        self.output.progress.add_item();
+        self.output.started(file);
+    }
 
-    if !response.status().is_success() {
-        let status = response.status();
-        let body = response.text().unwrap_or_default();
-        return Err(format!("Server returned {}: {}", status, body));
+    fn described(&mut self, described: &mut Described) -> Result<(), String> {
+        let written = self.output.described(described);
+        self.record = Some(described.record.clone());
+        written
     }
 
-    let chat_response: OllamaChatResponse = response
-        .json()
-        .map_err(|e| format!("Failed to parse response: {}", e))?;
+    fn finished(&mut self, file: &str, error: Option<&str>) {
+        self.output.finished(file, error);
+        let record = self.record.take();
+        if let Some(Received { job, connection }) = self.current.borrow_mut().take() {
+            let outcome = match (&record, error) {
+                (Some(record), None) => Ok(record),
+                (_, error) => Err(error.unwrap_or("No record was produced")),
+            };
+            connection.send(&queue::result_message(&job.file, &job.metadata, outcome));
+        }
+    }
 
-    let content = chat_response.message.content;
+    fn paused(&mut self, opens: &str) {
+        self.output.paused(opens);
+    }
 
-    // Try to parse as JSON, otherwise return as string
-    match serde_json::from_str::<serde_json::Value>(&content) {
-        Ok(json) => Ok(json),
-        Err(_) => Ok(serde_json::Value::String(content)),
+    fn resumed(&mut self) {
+        self.output.resumed();
     }
 }
 
-fn main() -> ExitCode {
-    let args = Args::parse();
-
+fn run(args: RunArgs, settings: &Settings, quiet: bool) -> Exit {
     // Load and validate prompt config first
-    let config = match load_prompt_config(&args.prompt) {
+    let config = match prompt_config(args.prompt.as_deref(), args.preset, args.locale.as_deref()) {
         Ok(c) => c,
         Err(e) => {
-            eprintln!("Error: {}", e);
-            return ExitCode::from(1);
+            error!(code = "config", "{}", e);
+            return Exit::Config;
+        }
+    };
+    if let Some(language) = &args.language {
+        if !language.checkable() {
+            warn!("Replies can't be checked for '{}'; the model is asked for it but not held to it", language.name);
         }
+    }
+    if args.candidates > 1 && config.temperature == 0.0 {
+        warn!("At a temperature of 0 the {} candidates will be much the same; raise it in the prompt config", args.candidates);
+    }
+
+    // Endpoints from the config file give way to a --url flag, as a profile's url would
+    let endpoints = match (&args.url, &settings.endpoints) {
+        (None, Some(endpoints)) if args.endpoint.is_empty() => endpoints.clone(),
+        _ => args.endpoint.clone(),
+    };
+    if args.stream && endpoints.iter().map(|endpoint| endpoint.concurrency).sum::<usize>() > 1 {
+        error!(
+            code = "config",
+            "--stream shows one reply at a time, so it can't be used with endpoints that take more than one request at once, whether from --endpoint or the config file; give --url"
+        );
+        return Exit::Config;
+    }
+    // Flags win over the prompt config, which wins over the config file profile
+    let url = match endpoints.first() {
+        Some(endpoint) => endpoint.url.as_str(),
+        None => args.url.as_deref().or(settings.url.as_deref()).unwrap_or(DEFAULT_URL),
     };
+    let api = args.api.or(settings.api).unwrap_or(Api::Ollama);
+    let model = args.model.as_ref().or(config.model.as_ref()).or(settings.model.as_ref());
+    if model.is_none() && api == Api::Ollama {
+        error!(code = "config", "--model is required (or set 'model' in prompt config)");
+        return Exit::Config;
+    }
 
-    // Model can come from CLI or prompt config
-    let model = args.model.as_ref().or(config.model.as_ref());
-    let model = match model {
-        Some(m) => m.clone(),
-        None => {
-            eprintln!("Error: --model is required (or set 'model' in prompt config)");
-            return ExitCode::from(1);
+    if !args.finder_tags.is_empty() && !cfg!(target_os = "macos") {
+        error!(code = "config", "--finder-tags is only supported on macOS");
+        return Exit::Config;
+    }
+
+    let transform = match args.script.as_deref().map(load_script).transpose() {
+        Ok(transform) => transform,
+        Err(e) => {
+            error!(code = "config", "{}", e);
+            return Exit::Config;
+        }
+    };
+
+    let ocr = match args.ocr.engine(args.preset) {
+        Ok(ocr) => ocr,
+        Err(e) => {
+            error!(code = "config", "{}", e);
+            return Exit::Config;
+        }
+    };
+    let nsfw = match args.nsfw.filter() {
+        Ok(nsfw) => nsfw,
+        Err(e) => {
+            error!(code = "config", "{}", e);
+            return Exit::Config;
+        }
+    };
+    let embed_model = match embedding_model(
+        args.mode,
+        args.embed_input,
+        args.sinks.embed_model.as_ref(),
+        api,
+        args.labels.is_some(),
+    ) {
+        Ok(embed_model) => embed_model,
+        Err(e) => {
+            error!(code = "config", "{}", e);
+            return Exit::Config;
+        }
+    };
+
+    // Held for the whole run: a second run on the same job store would put this run's
+    // inputs back to pending and describe them again
+    let _store_lock = match args.job_store.as_deref().map(|path| FileLock::acquire(Path::new(path), "Job store")).transpose() {
+        Ok(lock) => lock,
+        Err(e) => {
+            error!(code = "config", "{}", e);
+            return Exit::Config;
+        }
+    };
+    let mut store = match args.job_store.as_deref().map(JobStore::open).transpose() {
+        Ok(store) => store,
+        Err(e) => {
+            error!(code = "config", "{}", e);
+            return Exit::Config;
+        }
+    };
+
+    let manifest = match args.input_manifest.as_deref().map(InputManifest::load).transpose() {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            error!(code = "config", "{}", e);
+            return Exit::Config;
         }
     };
 
-    // Read paths from stdin
-    let stdin = io::stdin();
-    let paths: Vec<String> = stdin.lock().lines().filter_map(|l| l.ok()).collect();
+    let listener = match args.listen_input.as_ref().map(Listener::bind).transpose() {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!(code = "config", "{}", e);
+            return Exit::Config;
+        }
+    };
 
-    if paths.is_empty() {
-        return ExitCode::from(0);
+    // Read paths from stdin, or from the job store for --retry-failed; with --listen-input
+    // they come from the socket as the run goes
+    let paths = match (&mut store, args.retry_failed) {
+        _ if listener.is_some() => Vec::new(),
+        (Some(store), true) => match store.list(JobStatus::Failed) {
+            Ok(failed) => failed.into_iter().map(|entry| entry.path).collect(),
+            Err(e) => {
+                error!(code = "config", "{}", e);
+                return Exit::Config;
+            }
+        },
+        _ => read_input_paths(),
+    };
+    // Checked before a --resume leaves out the inputs already done
+    if let Some(manifest) = &manifest {
+        let problems = manifest.verify_inputs(&paths);
+        if !problems.is_empty() {
+            for problem in &problems {
+                error!(code = "manifest_mismatch", "{}", problem);
+            }
+            error!(code = "config", "The inputs don't match the input manifest; nothing was described");
+            return Exit::Config;
+        }
+        info!("{} input(s) match the input manifest", manifest.count());
     }
+    // A --dry-run only previews, so it leaves the job store as it is
+    let paths = match &mut store {
+        Some(store) => {
+            let prepared = match args.dry_run {
+                true => remaining_inputs(store, paths, args.resume),
+                false => prepare_job_store(store, paths, args.resume),
+            };
+            match prepared {
+                Ok(paths) => paths,
+                Err(e) => {
+                    error!(code = "config", "{}", e);
+                    return Exit::Config;
+                }
+            }
+        }
+        None => paths,
+    };
 
-    let client = reqwest::blocking::Client::builder()
-        .timeout(Duration::from_secs(120))
-        .build()
-        .expect("Failed to create HTTP client");
-    let mut had_errors = false;
+    if paths.is_empty() && listener.is_none() {
+        return Exit::Success;
+    }
 
-    for path_str in paths {
-        let path_str = path_str.trim();
-        if path_str.is_empty() {
-            continue;
+    let throttle = match throttle(args.rpm, args.rps, settings) {
+        Ok(throttle) => throttle,
+        Err(e) => {
+            error!(code = "config", "{}", e);
+            return Exit::Config;
+        }
+    };
+    let tracer = match tracer(args.trace_dir.as_deref(), args.trace_images) {
+        Ok(tracer) => tracer,
+        Err(e) => {
+            error!(code = "config", "{}", e);
+            return Exit::Config;
+        }
+    };
+    let fixtures = match fixtures(args.record.as_deref(), args.replay.as_deref()) {
+        Ok(fixtures) => fixtures,
+        Err(e) => {
+            error!(code = "config", "{}", e);
+            return Exit::Config;
+        }
+    };
+    let downloader = match args.download.downloader() {
+        Ok(downloader) => downloader,
+        Err(e) => {
+            error!(code = "config", "{}", e);
+            return Exit::Config;
+        }
+    };
+    let urls: Vec<String> = match endpoints.is_empty() {
+        true => vec![url.to_string()],
+        false => endpoints.iter().map(|endpoint| endpoint.url.clone()).collect(),
+    };
+    let per_megapixel = match args.timeout_per_mp.map(Duration::try_from_secs_f64).transpose() {
+        Ok(per_megapixel) => per_megapixel,
+        Err(e) => {
+            error!(code = "config", "Invalid --timeout-per-mp: {}", e);
+            return Exit::Config;
+        }
+    };
+    let stream = args.stream.then(|| Arc::new(pretty::StreamPrinter::for_stderr()));
+    let backend = Backend::new(api, url, model.cloned(), args.timeout.or(settings.timeout))
+        .with_throttle(throttle)
+        .with_tracer(tracer)
+        .with_fixtures(fixtures)
+        .with_stream(stream.clone().map(|stream| stream as Arc<dyn TokenSink>))
+        .with_partial_replies(args.keep_partial, args.stall_timeout.map(Duration::from_secs))
+        .with_timeout_per_megapixel(per_megapixel)
+        .with_logprobs(args.confidence == Some(ConfidenceMethod::Logprobs));
+    let endpoints = endpoints
+        .iter()
+        .map(|endpoint| (backend.at(&endpoint.url, model.cloned()), endpoint.concurrency))
+        .collect();
+    let prices = Prices::configured(
+        args.input_price.or(settings.input_price),
+        args.output_price.or(settings.output_price),
+    );
+    let runner = BatchRunner::new(backend, config)
+        .with_language(args.language.clone())
+        .with_field_map(settings.field_map())
+        .with_error_limit(args.error_limit())
+        .with_run_window(args.run_window)
+        .with_downloader(Some(downloader))
+        .with_memory_budget(args.max_memory_mb.map(|mb| MemoryBudget::new(mb * 1_000_000)))
+        .with_input_manifest(manifest)
+        .with_transform(transform)
+        .with_ocr(ocr)
+        .with_nsfw(nsfw)
+        .with_size_limit(args.size_limit.limit())
+        .with_image_info(args.image_info)
+        .with_image_stats(args.image_stats)
+        .with_exif(args.exif)
+        .with_embedding(embed_model, args.embed_input)
+        .with_labels(args.labels.clone(), args.top_k.map(|k| k as usize))
+        .with_confidence(args.confidence, args.confidence_samples as usize)
+        .with_candidates(args.candidates as usize)
+        .with_translations(args.translate.clone())
+        .with_endpoints(endpoints)
+        .with_detections(args.detections || args.annotate_dir.is_some());
+    let runner = match with_barcodes(runner, args.barcodes).and_then(|runner| with_face_blur(runner, &args.faces)) {
+        Ok(runner) => runner,
+        Err(e) => {
+            error!(code = "config", "{}", e);
+            return Exit::Config;
         }
+    };
+    if args.dry_run {
+        return preflight(runner.backend(), runner.config(), &paths, prices);
+    }
 
-        let path = Path::new(path_str);
+    let client = runner.backend().client();
+    let sinks = match open_sinks(&args.sinks, client, Some(url)) {
+        Ok(sinks) => sinks,
+        Err(e) => {
+            error!(code = "config", "{}", e);
+            return Exit::Config;
+        }
+    };
 
-        // Validate the image file
-        let image_data = match validate_image_file(path) {
-            Ok(data) => data,
+    // A resumed run adds to the records from the runs before it
+    let mode = match args.overwrite {
+        true => OutputMode::Overwrite,
+        false if args.append || args.resume || args.retry_failed => OutputMode::Append,
+        false => OutputMode::New,
+    };
+    let output_file = match &args.output {
+        Some(path) => match LockedOutput::open(Path::new(path), mode) {
+            Ok(output) => Some(output),
             Err(e) => {
-                eprintln!("{}", e);
-                had_errors = true;
-                continue;
+                error!(code = "config", "{}", e);
+                return Exit::Config;
             }
-        };
+        },
+        None => None,
+    };
 
-        // Just validate format is recognized (already done in validate_image_file)
-        if args.dry_run {
-            continue;
+    let shutdown = match Shutdown::install() {
+        Ok(shutdown) => shutdown,
+        Err(e) => {
+            error!(code = "config", "{}", e);
+            return Exit::Config;
+        }
+    };
+    // A bar redrawn under the streamed text would garble it
+    let progress_mode = match args.progress {
+        ProgressMode::Auto if args.stream => ProgressMode::None,
+        mode => mode.quieted(quiet, args.progress_fd),
+    };
+    let progress = match Progress::new(paths.len(), progress_mode, args.progress_fd) {
+        Ok(progress) => progress,
+        Err(e) => {
+            error!(code = "config", "{}", e);
+            return Exit::Config;
         }
+    };
 
-        // Call the model
-        match call_model(&client, &args.url, &model, &config, &image_data) {
-            Ok(response) => {
-                let record = OutputRecord {
-                    file: path_str.to_string(),
-                    response,
+    let mut output = RunOutput {
+        args: &args,
+        client,
+        shutdown: &shutdown,
+        progress,
+        sinks,
+        output_file,
+        pretty_style: (args.format == OutputFormat::Pretty).then(pretty::Style::for_stdout),
+        claimed_paths: HashSet::new(),
+        exec: args.exec.clone().or_else(|| settings.exec.clone()),
+        store,
+        last_record: None,
+        stream,
+        captions: args.caption_report.is_some().then(Vec::new),
+        schema: runner.config().schema.clone(),
+    };
+    let summary = match &listener {
+        Some(listener) => {
+            let current = RefCell::new(None);
+            let inputs = std::iter::from_fn(|| {
+                let mut received = listener.next(&shutdown)?;
+                let input = Input {
+                    file: received.job.file.clone(),
+                    data: received.job.data.take(),
                 };
-                println!("{}", serde_json::to_string(&record).unwrap());
-            }
-            Err(e) => {
-                eprintln!("Error processing '{}': {}", path_str, e);
-                had_errors = true;
+                *current.borrow_mut() = Some(received);
+                Some(input)
+            });
+            let mut hooks = ListenHooks {
+                output: &mut output,
+                current: &current,
+                record: None,
+            };
+            runner.run_inputs(inputs, &mut hooks)
+        }
+        None => runner.run(&paths, &mut output),
+    };
+
+    output.progress.finish();
+    if let Err(e) = io::stdout().flush() {
+        error!("Failed to flush output: {}", e);
+    }
+
+    let interrupted = shutdown.requested();
+    let remaining = paths.get(summary.processed..).unwrap_or_default();
+    if let Some(next) = remaining.first() {
+        let (code, reason) = if interrupted {
+            ("interrupted", "Interrupted".to_string())
+        } else {
+            ("error_limit", format!("Stopped after {} failed file(s)", summary.failed))
+        };
+        error!(
+            code,
+            path = next.as_str(),
+            "{}: {} of {} files were not processed, starting with '{}'",
+            reason,
+            remaining.len(),
+            paths.len(),
+            next
+        );
+    }
+    info!(
+        total = summary.total,
+        succeeded = summary.succeeded,
+        failed = summary.failed,
+        not_processed = remaining.len(),
+        elapsed_secs = summary.elapsed.as_secs_f64(),
+        "Run complete"
+    );
+    if summary.nsfw_skipped > 0 {
+        info!("{} image(s) scored as NSFW were not sent to the model", summary.nsfw_skipped);
+    }
+    report_cost(summary.usage, summary.replies_without_usage, prices);
+    if let Some(latency) = summary.latency.summary() {
+        info!("Latency: {}", latency);
+        for line in summary.latency.histogram() {
+            info!("  {}", line);
+        }
+    }
+    if let Some(server_time) = summary.server_time.summary() {
+        let totals = summary.server_time;
+        info!(
+            load_ms = totals.load_ms,
+            prompt_ms = totals.prompt_ms,
+            generation_ms = totals.generation_ms,
+            model_loads = totals.model_loads,
+            "Server time: {}",
+            server_time
+        );
+    }
+
+    if let Some(url) = &args.webhook {
+        if args.webhook_events != WebhookEvents::Records {
+            let payload = WebhookPayload::RunComplete {
+                total: summary.total,
+                succeeded: summary.succeeded,
+                failed: summary.failed,
+            };
+            if let Err(e) = send_webhook(client, url, &payload) {
+                warn!("{}", e);
             }
         }
     }
 
-    if had_errors {
-        ExitCode::from(1)
-    } else {
-        ExitCode::from(0)
+    if let (Some(path), Some(captions)) = (&args.caption_report, output.captions.take()) {
+        write_caption_report(path, &captions, args.caption_similarity);
+    }
+
+    if let Some(path) = &args.repro_bundle {
+        let manifest = Manifest {
+            nineladies_version: env!("CARGO_PKG_VERSION"),
+            created: utc_timestamp(SystemTime::now()),
+            command: std::env::args().collect(),
+            api,
+            urls,
+            model: runner.backend().model().map(str::to_string),
+            model_digest: runner.backend().model().and_then(|model| model_digest(runner.backend(), model)),
+            preset: args.preset.map(preset_name),
+            temperature: runner.config().temperature,
+            seed: runner.config().seed,
+            files: paths.len(),
+            succeeded: summary.succeeded,
+            failed: summary.failed,
+            output: args.output.as_deref().and_then(|path| {
+                OutputFile::hash(path).map_err(|e| warn!("{}; the bundle has no output hash", e)).ok()
+            }),
+        };
+        match bundle::write_bundle(path, &manifest, runner.config(), &paths) {
+            Ok(()) => info!("Wrote reproducibility bundle to {}", path.display()),
+            Err(e) => error!("{}", e),
+        }
+    }
+
+    if interrupted {
+        return Exit::Interrupted;
+    }
+    Exit::for_batch(summary.succeeded, summary.failed, summary.unreachable())
+}
+
+/// Write the --caption-report file, and warn of the captions given to the most images.
+fn write_caption_report(path: &Path, captions: &[(String, String)], similarity: u32) {
+    let report = caption_report::report(captions, similarity);
+    for cluster in report.clusters.iter().take(CAPTION_CLUSTERS_SHOWN) {
+        let alike = if cluster.exact { "the same" } else { "nearly the same" };
+        warn!(images = cluster.images, "{} images have {} caption: '{}'", cluster.images, alike, cluster.caption);
+    }
+    let json = serde_json::to_string_pretty(&report).expect("caption reports serialize");
+    match fs::write(path, json) {
+        Ok(()) => info!(
+            "Wrote caption report to {}: {} cluster(s) covering {} image(s) of {} described",
+            path.display(),
+            report.clusters.len(),
+            report.images_in_clusters,
+            report.captions
+        ),
+        Err(e) => error!("Failed to write caption report '{}': {}", path.display(), e),
     }
 }
 
@@ -279,261 +4459,433 @@ fn main() -> ExitCode {
 mod tests {
     use super::*;
     use std::path::PathBuf;
+    use std::time::{Duration, UNIX_EPOCH};
 
     fn fixtures_dir() -> PathBuf {
         PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")
     }
 
-    // ==================== Image Format Detection Tests ====================
+    // ==================== Template and Rename Tests ====================
 
     #[test]
-    fn test_detect_png() {
-        let data = fs::read(fixtures_dir().join("red.png")).unwrap();
-        assert_eq!(detect_image_format(&data), Some("png"));
+    fn test_format_date() {
+        assert_eq!(format_date(UNIX_EPOCH), "1970-01-01");
+        assert_eq!(format_date(UNIX_EPOCH + Duration::from_secs(951_782_400)), "2000-02-29");
+        assert_eq!(format_date(UNIX_EPOCH + Duration::from_secs(1_767_225_599)), "2025-12-31");
     }
 
     #[test]
-    fn test_detect_jpeg() {
-        let data = fs::read(fixtures_dir().join("red.jpg")).unwrap();
-        assert_eq!(detect_image_format(&data), Some("jpeg"));
+    fn test_render_template_fields() {
+        let path = fixtures_dir().join("red.png");
+        let response = serde_json::json!({"subject": "red square", "meta": {"tags": ["a", "b"]}});
+
+        let name = render_template("{stem}-{response.subject}.{ext}", &path, &response, None).unwrap();
+        assert_eq!(name, "red-red square.png");
+
+        let nested = render_template("{response.meta.tags}", &path, &response, None).unwrap();
+        assert_eq!(nested, "a_b");
+
+        let dated = render_template("{date}_{name}", &path, &response, None).unwrap();
+        assert!(dated.ends_with("_red.png"));
+        assert_eq!(dated.len(), "YYYY-MM-DD_red.png".len());
     }
 
     #[test]
-    fn test_detect_gif() {
-        let data = fs::read(fixtures_dir().join("red.gif")).unwrap();
-        assert_eq!(detect_image_format(&data), Some("gif"));
+    fn test_render_template_sanitizes_values() {
+        let path = Path::new("photo.jpg");
+        let response = serde_json::Value::String(" cats/dogs: *best* ".to_string());
+        let name = render_template("{response}.{ext}", path, &response, None).unwrap();
+        assert_eq!(name, "cats_dogs_ _best_.jpg");
     }
 
     #[test]
-    fn test_detect_webp() {
-        let data = fs::read(fixtures_dir().join("red.webp")).unwrap();
-        assert_eq!(detect_image_format(&data), Some("webp"));
+    fn test_render_template_errors() {
+        let path = Path::new("photo.jpg");
+        let response = serde_json::json!({"subject": {"nested": true}});
+
+        assert!(render_template("{response.missing}", path, &response, None)
+            .unwrap_err()
+            .contains("no field"));
+        assert!(render_template("{response.subject}", path, &response, None)
+            .unwrap_err()
+            .contains("not a text"));
+        assert!(render_template("{bogus}", path, &response, None)
+            .unwrap_err()
+            .contains("Unknown template placeholder"));
+        assert!(render_template("{stem", path, &response, None)
+            .unwrap_err()
+            .contains("Unclosed"));
     }
 
     #[test]
-    fn test_detect_invalid_format() {
-        let data = b"This is not an image file";
-        assert_eq!(detect_image_format(data), None);
+    fn test_parse_pairs() {
+        let pairs = parse_pairs("# before\tafter\nold.jpg\tnew.jpg\r\n\nlonely.jpg\n a b.png \t c.png\n");
+        assert_eq!(pairs.len(), 3);
+        assert_eq!(pairs[0], Ok(("old.jpg".to_string(), "new.jpg".to_string())));
+        assert_eq!(pairs[1], Err("Line 4: expected two image paths separated by a tab".to_string()));
+        assert_eq!(pairs[2], Ok(("a b.png".to_string(), "c.png".to_string())));
     }
 
     #[test]
-    fn test_detect_too_short() {
-        let data = b"short";
-        assert_eq!(detect_image_format(data), None);
+    fn test_render_template_exif_fields() {
+        let path = fixtures_dir().join("exif.jpg");
+        let response = serde_json::Value::Null;
+
+        // Read from the file when the record has no EXIF
+        let name = render_template("{exif.date}_{exif.camera}.{ext}", &path, &response, None).unwrap();
+        assert_eq!(name, "2023-07-14_Canon EOS R6.jpg");
+
+        let exif = ExifData { camera: Some("Nikon Z6".to_string()), ..ExifData::default() };
+        let name = render_template("{exif.camera}", &path, &response, Some(&exif)).unwrap();
+        assert_eq!(name, "Nikon Z6");
+        assert!(render_template("{exif.date}", &path, &response, Some(&exif))
+            .unwrap_err()
+            .contains("no EXIF field"));
+        assert!(render_template("{exif.date}", &fixtures_dir().join("red.png"), &response, None).is_err());
     }
 
     #[test]
-    fn test_detect_empty() {
-        let data: &[u8] = &[];
-        assert_eq!(detect_image_format(data), None);
-    }
+    fn test_plan_rename_handles_collisions() {
+        let dir = std::env::temp_dir().join("nineladies_rename_test");
+        fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("IMG_0001.jpg");
+        let existing = dir.join("beach.jpg");
+        fs::write(&source, b"x").unwrap();
+        fs::write(&existing, b"x").unwrap();
 
-    // ==================== Prompt Config Loading Tests ====================
+        let response = serde_json::json!({"subject": "beach"});
+        let mut claimed = HashSet::new();
+
+        let first = plan_rename(&source, "{response.subject}.{ext}", &response, None, &claimed).unwrap();
+        assert_eq!(first, dir.join("beach-1.jpg"));
+
+        claimed.insert(first);
+        let second = plan_rename(&source, "{response.subject}.{ext}", &response, None, &claimed).unwrap();
+        assert_eq!(second, dir.join("beach-2.jpg"));
+
+        // Renaming to the current name is a no-op
+        let same = plan_rename(&source, "{stem}.{ext}", &response, None, &claimed).unwrap();
+        assert_eq!(same, source);
+
+        fs::remove_dir_all(&dir).ok();
+    }
 
     #[test]
-    fn test_load_valid_prompt_config() {
-        let path = fixtures_dir().join("test-prompt.json");
-        let config = load_prompt_config(path.to_str().unwrap()).unwrap();
+    fn test_plan_relocation_into_category_folder() {
+        let dir = std::env::temp_dir().join("nineladies_move_plan_test");
+        let source = Path::new("inbox/IMG_0002.jpg");
+        let response = serde_json::json!({"category": "receipts"});
+        let template = format!("{}/{{response.category}}/", dir.display());
 
-        assert_eq!(config.system, "You are a test assistant.");
-        assert_eq!(config.prompt, "Describe this image.");
-        assert!((config.temperature - 0.5).abs() < f32::EPSILON);
+        let target = plan_relocation(
+            source,
+            std::ffi::OsStr::new("IMG_0002.jpg"),
+            &template,
+            &response,
+            None,
+            &HashSet::new(),
+        )
+        .unwrap();
+        assert_eq!(target, dir.join("receipts").join("IMG_0002.jpg"));
     }
 
     #[test]
-    fn test_load_invalid_prompt_config_missing_fields() {
-        let path = fixtures_dir().join("invalid-prompt.json");
-        let result = load_prompt_config(path.to_str().unwrap());
+    fn test_plan_relocation_stays_in_sort_folder() {
+        let source = Path::new("inbox/IMG_0003.jpg");
+        let name = std::ffi::OsStr::new("IMG_0003.jpg");
+        for category in ["..", ".", "", " .. "] {
+            let response = serde_json::json!({"category": category});
+            let target = plan_relocation(source, name, "sorted/{response.category}/", &response, None, &HashSet::new()).unwrap();
+            assert_eq!(target, Path::new("sorted/_/IMG_0003.jpg"));
+        }
 
-        assert!(result.is_err());
-        assert!(result.unwrap_err().contains("Failed to parse"));
+        let response = serde_json::json!({"category": "../../etc"});
+        let target = plan_relocation(source, name, "sorted/{response.category}/", &response, None, &HashSet::new()).unwrap();
+        assert_eq!(target, Path::new("sorted/.._.._etc/IMG_0003.jpg"));
+
+        let err = plan_relocation(source, name, "sorted/{response.category}/../..", &response, None, &HashSet::new()).unwrap_err();
+        assert!(err.contains("outside 'sorted/'"), "{}", err);
     }
 
     #[test]
-    fn test_load_nonexistent_prompt_config() {
-        let result = load_prompt_config("/nonexistent/path/config.json");
+    fn test_file_actions_skip_responses_breaking_the_schema() {
+        let dir = std::env::temp_dir().join(format!("nineladies_move_schema_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("IMG_0004.jpg");
+        fs::write(&source, b"x").unwrap();
+        let template = format!("{}/{{response.category}}/", dir.display());
+        let (_, args) = Cli::parse_with_default_run(["9ladies", "--prompt", "p.json", "--move-to", &template]).unwrap();
+        let args = args.unwrap();
+        let schema = serde_json::json!({
+            "type": "object",
+            "properties": {"category": {"type": "string", "enum": ["receipts", "letters"]}},
+            "required": ["category"]
+        });
+        let file = source.display().to_string();
+
+        let mut record = OutputRecord::new(&file, "", serde_json::json!({"category": "holidays"}));
+        let err = apply_file_actions(&args, Some(&schema), &source, &mut record, &mut HashSet::new()).unwrap_err();
+        assert!(err.starts_with("Not moved: the response doesn't follow the schema"), "{}", err);
+        assert!(source.exists() && record.moved_to.is_none());
 
-        assert!(result.is_err());
-        assert!(result.unwrap_err().contains("Failed to read"));
+        let mut record = OutputRecord::new(&file, "", serde_json::json!({"category": "receipts"}));
+        apply_file_actions(&args, Some(&schema), &source, &mut record, &mut HashSet::new()).unwrap();
+        assert!(!source.exists() && dir.join("receipts/IMG_0004.jpg").exists());
+        fs::remove_dir_all(&dir).ok();
     }
 
     #[test]
-    fn test_load_prompt_config_invalid_temperature() {
-        // Create a temp file with invalid temperature
-        let temp_dir = std::env::temp_dir();
-        let temp_file = temp_dir.join("invalid_temp_config.json");
-        fs::write(&temp_file, r#"{"system": "test", "prompt": "test", "temperature": 3.0}"#).unwrap();
+    fn test_move_and_copy_file() {
+        let dir = std::env::temp_dir().join("nineladies_move_test");
+        fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("a.png");
+        fs::write(&source, b"x").unwrap();
 
-        let result = load_prompt_config(temp_file.to_str().unwrap());
-        assert!(result.is_err());
-        assert!(result.unwrap_err().contains("Temperature must be between"));
+        let copied = dir.join("copies/a.png");
+        copy_file(&source, &copied).unwrap();
+        assert!(source.exists() && copied.exists());
 
-        fs::remove_file(temp_file).ok();
+        let moved = dir.join("sorted/nested/a.png");
+        move_file(&source, &moved).unwrap();
+        assert!(!source.exists() && moved.exists());
+
+        fs::remove_dir_all(&dir).ok();
     }
 
-    // ==================== Image File Validation Tests ====================
+    // ==================== Finder Tag Tests ====================
 
     #[test]
-    fn test_validate_png_image() {
-        let path = fixtures_dir().join("red.png");
-        let result = validate_image_file(&path);
+    fn test_finder_tags_from_fields() {
+        let response = serde_json::json!({
+            "category": "receipt",
+            "labels": ["food", "shop", "food"],
+            "barcode": true,
+            "ingredients": false,
+            "count": 3
+        });
+        let fields: Vec<String> = ["category", "labels", "barcode", "ingredients", "count", "missing"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
 
-        assert!(result.is_ok());
-        let data = result.unwrap();
-        assert!(!data.is_empty());
-        assert_eq!(detect_image_format(&data), Some("png"));
+        let tags = finder_tags_from_response(&response, &fields);
+        assert_eq!(tags, vec!["receipt", "food", "shop", "barcode", "3"]);
     }
 
     #[test]
-    fn test_validate_jpeg_image() {
-        let path = fixtures_dir().join("red.jpg");
-        let result = validate_image_file(&path);
-
-        assert!(result.is_ok());
-        let data = result.unwrap();
-        assert_eq!(detect_image_format(&data), Some("jpeg"));
+    fn test_finder_tags_from_string_response() {
+        let response = serde_json::Value::String("A red image".to_string());
+        let tags = finder_tags_from_response(&response, &["category".to_string()]);
+        assert!(tags.is_empty());
     }
 
     #[test]
-    fn test_validate_gif_image() {
-        let path = fixtures_dir().join("red.gif");
-        let result = validate_image_file(&path);
+    fn test_encode_finder_tags_binary_plist() {
+        let tags = vec!["receipt".to_string(), "food".to_string()];
+        let data = encode_finder_tags(&tags).unwrap();
+        assert!(data.starts_with(b"bplist00"));
 
-        assert!(result.is_ok());
-        let data = result.unwrap();
-        assert_eq!(detect_image_format(&data), Some("gif"));
+        let decoded: Vec<String> = plist::from_bytes(&data).unwrap();
+        assert_eq!(decoded, tags);
     }
 
     #[test]
-    fn test_validate_webp_image() {
-        let path = fixtures_dir().join("red.webp");
-        let result = validate_image_file(&path);
+    fn test_merge_finder_tags_keeps_existing() {
+        let existing = encode_finder_tags(&["Red\n6".to_string(), "receipt".to_string()]).unwrap();
+        let tags = vec!["receipt".to_string(), "food".to_string(), "Red".to_string()];
+        let merged = merge_finder_tags(Some(&existing), &tags).unwrap();
+        assert_eq!(merged, ["Red\n6", "receipt", "food"]);
 
-        assert!(result.is_ok());
-        let data = result.unwrap();
-        assert_eq!(detect_image_format(&data), Some("webp"));
+        assert_eq!(merge_finder_tags(None, &tags).unwrap(), tags);
+        assert!(merge_finder_tags(Some(b"not a plist"), &tags).is_err());
     }
 
+    // ==================== Webhook Payload Tests ====================
+
     #[test]
-    fn test_validate_nonexistent_file() {
-        let path = Path::new("/nonexistent/image.png");
-        let result = validate_image_file(path);
+    fn test_webhook_record_payload() {
+        let record = OutputRecord::new("test.jpg", "abc123", serde_json::json!({"count": 2}));
+        let payload = WebhookPayload::Record { record: &record };
 
-        assert!(result.is_err());
-        assert!(result.unwrap_err().contains("File not found"));
+        let json: serde_json::Value = serde_json::to_value(&payload).unwrap();
+        assert_eq!(json["event"], "record");
+        assert_eq!(json["record"]["file"], "test.jpg");
+        assert_eq!(json["record"]["response"]["count"], 2);
     }
 
     #[test]
-    fn test_validate_non_image_file() {
-        let path = fixtures_dir().join("not-an-image.txt");
-        let result = validate_image_file(&path);
+    fn test_webhook_summary_payload() {
+        let payload = WebhookPayload::RunComplete {
+            total: 5,
+            succeeded: 4,
+            failed: 1,
+        };
 
-        assert!(result.is_err());
-        assert!(result.unwrap_err().contains("Not a valid image format"));
+        let json = serde_json::to_string(&payload).unwrap();
+        assert_eq!(json, r#"{"event":"run_complete","total":5,"succeeded":4,"failed":1}"#);
     }
 
-    // ==================== Output Record Serialization Tests ====================
+    // ==================== CLI and Output File Tests ====================
 
     #[test]
-    fn test_output_record_with_string_response() {
-        let record = OutputRecord {
-            file: "test.jpg".to_string(),
-            response: serde_json::Value::String("A red image".to_string()),
-        };
-
-        let json = serde_json::to_string(&record).unwrap();
-        assert!(json.contains("\"file\":\"test.jpg\""));
-        assert!(json.contains("\"response\":\"A red image\""));
+    fn test_exit_for_batch() {
+        assert_eq!(Exit::for_batch(3, 0, false), Exit::Success);
+        assert_eq!(Exit::for_batch(0, 0, false), Exit::Success);
+        assert_eq!(Exit::for_batch(2, 1, false), Exit::PartialFailure);
+        assert_eq!(Exit::for_batch(0, 3, false), Exit::AllFailed);
+        assert_eq!(Exit::for_batch(0, 3, true), Exit::Unreachable);
+        assert_eq!(ExitCode::from(Exit::Config), ExitCode::from(2));
     }
 
     #[test]
-    fn test_output_record_with_json_response() {
-        let record = OutputRecord {
-            file: "test.jpg".to_string(),
-            response: serde_json::json!({"barcode": true, "ingredients": false}),
-        };
+    fn test_cli_without_subcommand_runs() {
+        let (cli, run) = Cli::parse_with_default_run([
+            "9ladies", "--prompt", "p.json", "--url", "http://localhost:11434", "--pg-table", "t",
+        ])
+        .unwrap();
 
-        let json = serde_json::to_string(&record).unwrap();
-        assert!(json.contains("\"file\":\"test.jpg\""));
-        assert!(json.contains("\"barcode\":true"));
+        assert!(cli.command.is_none());
+        let run = run.unwrap();
+        assert_eq!(run.prompt.as_deref(), Some("p.json"));
+        assert_eq!(run.sinks.pg_table.as_deref(), Some("t"));
+        assert!(!run.stream);
+        assert!(Cli::parse_with_default_run(["9ladies", "--prompt", "p.json", "--stream", "--endpoint", "http://a:11434"]).is_err());
     }
 
-    // ==================== Ollama Request Serialization Tests ====================
-
     #[test]
-    fn test_ollama_request_serialization() {
-        let request = OllamaChatRequest {
-            model: "qwen2.5vl:32b".to_string(),
-            messages: vec![
-                OllamaChatMessage {
-                    role: "system".to_string(),
-                    content: "You are helpful.".to_string(),
-                    images: None,
-                },
-                OllamaChatMessage {
-                    role: "user".to_string(),
-                    content: "Describe this.".to_string(),
-                    images: Some(vec!["abc123".to_string()]),
-                },
-            ],
-            stream: false,
-            options: OllamaOptions { temperature: 0.7 },
-        };
+    fn test_cli_subcommands() {
+        let (cli, run) =
+            Cli::parse_with_default_run(["9ladies", "run", "--prompt", "p.json", "--url", "u"]).unwrap();
+        assert!(matches!(cli.command, Some(Command::Run(_))) && run.is_none());
+
+        let (cli, _) = Cli::parse_with_default_run(["9ladies", "report", "out.jsonl"]).unwrap();
+        assert!(matches!(cli.command, Some(Command::Report(r)) if r.input == "out.jsonl"));
+
+        let cli = Cli::try_parse_from(["9ladies", "diff", "old.jsonl", "new.jsonl", "--by", "sha256"]).unwrap();
+        assert!(matches!(cli.command, Some(Command::Diff(d)) if d.new == "new.jsonl" && d.by == MatchBy::Sha256));
+
+        let cli = Cli::try_parse_from(["9ladies", "merge", "a.jsonl", "b.jsonl", "--on-conflict", "answered"]).unwrap();
+        assert!(matches!(cli.command, Some(Command::Merge(m)) if m.inputs.len() == 2 && m.on_conflict == Conflict::Answered));
+        assert!(Cli::try_parse_from(["9ladies", "merge"]).is_err());
+
+        let cli = Cli::try_parse_from(["9ladies", "validate-output", "out.jsonl", "--preset", "receipt", "--require", "total"]).unwrap();
+        assert!(matches!(cli.command, Some(Command::ValidateOutput(v)) if v.preset == Some(Preset::Receipt) && v.required == ["total"]));
+
+        let cli = Cli::try_parse_from(["9ladies", "followup", "--input", "out.jsonl", "--prompt", "more.json"]).unwrap();
+        assert!(matches!(cli.command, Some(Command::Followup(f)) if f.input == "out.jsonl" && f.prompt == "more.json"));
+        let cli = Cli::try_parse_from(["9ladies", "compare", "--prompt", "p.json", "-o", "out.jsonl", "--append"]).unwrap();
+        assert!(matches!(cli.command, Some(Command::Compare(c)) if c.server.append && !c.server.overwrite));
+        assert!(Cli::try_parse_from(["9ladies", "video", "--prompt", "p.json", "--overwrite"]).is_err());
+        assert!(Cli::try_parse_from(["9ladies", "products", "--prompt", "p.json", "-o", "o", "--append", "--overwrite"]).is_err());
+
+        let cli = Cli::try_parse_from(["9ladies", "convert", "out.jsonl", "--to", "md"]).unwrap();
+        assert!(matches!(cli.command, Some(Command::Convert(c)) if c.to == ConvertFormat::Md && c.output.is_none()));
+        assert!(Cli::try_parse_from(["9ladies", "convert", "out.jsonl", "--to", "parquet"]).is_err());
 
-        let json = serde_json::to_string(&request).unwrap();
+        let (cli, _) =
+            Cli::parse_with_default_run(["9ladies", "models", "--url", "u", "--log-level", "debug"]).unwrap();
+        assert!(matches!(cli.command, Some(Command::Models(_))));
+        assert_eq!(cli.log.log_level, logging::LogLevel::Debug);
 
-        // Verify structure
-        assert!(json.contains("\"model\":\"qwen2.5vl:32b\""));
-        assert!(json.contains("\"role\":\"system\""));
-        assert!(json.contains("\"role\":\"user\""));
-        assert!(json.contains("\"images\":[\"abc123\"]"));
-        assert!(json.contains("\"stream\":false"));
-        assert!(json.contains("\"temperature\":0.7"));
+        assert!(Cli::parse_with_default_run(["9ladies", "--url", "u"]).is_err());
+
+        let (_, run) = Cli::parse_with_default_run(["9ladies", "--preset", "receipt"]).unwrap();
+        assert_eq!(run.unwrap().preset, Some(Preset::Receipt));
+        assert!(Cli::parse_with_default_run(["9ladies", "--preset", "receipt", "--prompt", "p.json"]).is_err());
     }
 
-    // ==================== Integration-style Tests ====================
+    #[test]
+    fn test_cli_env_fallbacks() {
+        // Only variables no other test depends on, since the environment is process-wide
+        std::env::set_var("NINELADIES_MODEL", "env-model");
+        std::env::set_var("NINELADIES_TIMEOUT", "30");
+
+        let (_, run) = Cli::parse_with_default_run(["9ladies", "--prompt", "p.json"]).unwrap();
+        let run = run.unwrap();
+        assert_eq!(run.model.as_deref(), Some("env-model"));
+        assert_eq!(run.timeout, Some(30));
+
+        let (_, run) =
+            Cli::parse_with_default_run(["9ladies", "--prompt", "p.json", "--model", "flag-model"]).unwrap();
+        assert_eq!(run.unwrap().model.as_deref(), Some("flag-model"));
+
+        std::env::remove_var("NINELADIES_MODEL");
+        std::env::remove_var("NINELADIES_TIMEOUT");
+    }
 
     #[test]
-    fn test_full_validation_pipeline_with_known_images() {
-        let fixtures = fixtures_dir();
-        let prompt_path = fixtures.join("test-prompt.json");
+    fn test_cli_error_limits() {
+        let parse = |extra: &[&str]| {
+            let args = ["9ladies", "--prompt", "p.json"].iter().chain(extra).copied();
+            Cli::parse_with_default_run(args).map(|(_, run)| run.unwrap().error_limit())
+        };
 
-        // Load config
-        let config = load_prompt_config(prompt_path.to_str().unwrap()).unwrap();
-        assert_eq!(config.system, "You are a test assistant.");
+        assert_eq!(parse(&[]).unwrap(), None);
+        assert_eq!(parse(&["--fail-fast"]).unwrap(), Some(1));
+        assert_eq!(parse(&["--max-errors", "5"]).unwrap(), Some(5));
+        assert!(parse(&["--max-errors", "0"]).is_err());
+        assert!(parse(&["--fail-fast", "--max-errors", "5"]).is_err());
+    }
 
-        // Validate all test images
-        let test_images = ["red.png", "red.jpg", "red.gif", "red.webp"];
+    #[test]
+    fn test_review_output_path() {
+        let args = |input: &str, output: Option<&str>| ReviewArgs {
+            input: input.to_string(),
+            output: output.map(str::to_string),
+            all: false,
+            below: None,
+            image_protocol: ImageProtocol::None,
+        };
 
-        for image_name in test_images {
-            let image_path = fixtures.join(image_name);
-            let data = validate_image_file(&image_path).unwrap();
-            let format = detect_image_format(&data).unwrap();
+        assert_eq!(args("out.jsonl", None).output_path(), PathBuf::from("out.reviewed.jsonl"));
+        assert_eq!(args("out.reviewed.jsonl", None).output_path(), PathBuf::from("out.reviewed.jsonl"));
+        assert_eq!(args("out.jsonl", Some("curated.jsonl")).output_path(), PathBuf::from("curated.jsonl"));
+    }
 
-            // Verify we can encode to base64 for API call
-            let encoded = BASE64.encode(&data);
-            assert!(!encoded.is_empty());
+    // ==================== Preflight Tests ====================
 
-            // Verify data URL format
-            let data_url = format!("data:image/{};base64,{}", format, encoded);
-            assert!(data_url.starts_with("data:image/"));
-        }
+    #[test]
+    fn test_estimated_request_bytes() {
+        let config = PromptConfig {
+            system: "abc".to_string(),
+            prompt: "de".to_string(),
+            temperature: 0.0,
+            model: None,
+            schema: None,
+            seed: None,
+            think: None,
+            schema_via: None,
+        };
+        assert_eq!(estimated_request_bytes(3000, &config), 4000 + 5 + REQUEST_OVERHEAD_BYTES);
+        assert_eq!(format_bytes(12_345_678), "12.3 MB");
+        assert_eq!(format_bytes(512), "512 B");
     }
 
+    // ==================== Job Store Tests ====================
+
     #[test]
-    fn test_dry_run_detects_invalid_files() {
-        let fixtures = fixtures_dir();
+    fn test_prepare_job_store() {
+        let mut store = JobStore::in_memory().unwrap();
+        let paths = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+
+        store.enqueue(&paths(&["a.jpg", "b.jpg"])).unwrap();
+        store.start("a.jpg", FileStamp::default()).unwrap();
+        store.mark_done("a.jpg", &OutputRecord::new("a.jpg", "", serde_json::json!("A cat"))).unwrap();
+        store.start("b.jpg", FileStamp::default()).unwrap();
 
-        // Valid image should pass
-        let valid_path = fixtures.join("red.png");
-        assert!(validate_image_file(&valid_path).is_ok());
+        // b.jpg was left in progress, so it is pending again and not skipped
+        let remaining = prepare_job_store(&mut store, paths(&["a.jpg", "b.jpg", "c.jpg"]), true).unwrap();
+        assert_eq!(remaining, paths(&["b.jpg", "c.jpg"]));
+        assert_eq!(store.get("c.jpg").unwrap().unwrap().status, JobStatus::Pending);
 
-        // Invalid image should fail
-        let invalid_path = fixtures.join("not-an-image.txt");
-        assert!(validate_image_file(&invalid_path).is_err());
+        let all = prepare_job_store(&mut store, paths(&["a.jpg", "c.jpg"]), false).unwrap();
+        assert_eq!(all, paths(&["a.jpg", "c.jpg"]));
 
-        // Nonexistent file should fail
-        let missing_path = fixtures.join("does-not-exist.png");
-        assert!(validate_image_file(&missing_path).is_err());
+        // What a --dry-run reads leaves the store alone
+        store.start("b.jpg", FileStamp::default()).unwrap();
+        let remaining = remaining_inputs(&store, paths(&["a.jpg", "b.jpg", "d.jpg"]), true).unwrap();
+        assert_eq!(remaining, paths(&["b.jpg", "d.jpg"]));
+        assert_eq!(store.get("b.jpg").unwrap().unwrap().status, JobStatus::InProgress);
+        assert!(store.get("d.jpg").unwrap().is_none());
     }
 }
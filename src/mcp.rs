@@ -0,0 +1,252 @@
+//! `9ladies mcp`: a Model Context Protocol server on stdin/stdout, so agent frameworks and
+//! editors can call the describe pipeline as tools.
+//!
+//! Messages are JSON-RPC 2.0, one per line. Two tools are offered: `describe_image` asks the
+//! configured prompt, and `extract_from_image` asks for named fields or a custom question.
+
+use std::io::{BufRead, Write};
+use std::path::Path;
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use serde::Deserialize;
+use tracing::{debug, info};
+
+use nineladies::{validate_image_file, BatchRunner};
+
+/// The protocol revision implemented
+const PROTOCOL_VERSION: &str = "2024-11-05";
+
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+#[derive(Deserialize)]
+struct RpcRequest {
+    /// Absent for notifications, which get no reply
+    id: Option<serde_json::Value>,
+    method: String,
+    #[serde(default)]
+    params: serde_json::Value,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ImageArgs {
+    path: Option<String>,
+    /// Base64 image bytes, for images that aren't files on this machine
+    image: Option<String>,
+    /// Fields to extract, for `extract_from_image`
+    #[serde(default)]
+    fields: Vec<String>,
+    /// A question to ask instead of the field list or the configured prompt
+    question: Option<String>,
+}
+
+fn image_schema(extra: serde_json::Value) -> serde_json::Value {
+    let mut schema = serde_json::json!({
+        "type": "object",
+        "properties": {
+            "path": {"type": "string", "description": "Path of an image file (JPEG, PNG, WebP or GIF)"},
+            "image": {"type": "string", "description": "Base64 image bytes, instead of a path"}
+        }
+    });
+    if let serde_json::Value::Object(extra) = extra {
+        schema["properties"].as_object_mut().expect("properties is an object").extend(extra);
+    }
+    schema
+}
+
+fn tools() -> serde_json::Value {
+    serde_json::json!([
+        {
+            "name": "describe_image",
+            "description": "Describe an image with the server's configured vision model and prompt. Returns the 9ladies output record as JSON.",
+            "inputSchema": image_schema(serde_json::json!({}))
+        },
+        {
+            "name": "extract_from_image",
+            "description": "Extract named fields from an image (e.g. a receipt's date and total) as a JSON object, or answer a specific question about it.",
+            "inputSchema": image_schema(serde_json::json!({
+                "fields": {"type": "array", "items": {"type": "string"}, "description": "Field names to extract"},
+                "question": {"type": "string", "description": "A question to ask instead of extracting fields"}
+            }))
+        }
+    ])
+}
+
+/// The question `extract_from_image` asks for a list of fields.
+fn extraction_prompt(fields: &[String]) -> String {
+    format!(
+        "Extract the following fields from the image: {}. Reply with only a JSON object with exactly these keys, using null for any field that is not visible.",
+        fields.join(", ")
+    )
+}
+
+/// The file name for the record, and the image bytes.
+fn load_image(args: &ImageArgs) -> Result<(String, Vec<u8>), String> {
+    match (&args.path, &args.image) {
+        (Some(path), None) => Ok((path.clone(), validate_image_file(Path::new(path))?)),
+        (None, Some(image)) => BASE64
+            .decode(image.trim())
+            .map(|data| ("image".to_string(), data))
+            .map_err(|e| format!("'image' is not valid base64: {}", e)),
+        _ => Err("Give exactly one of 'path' or 'image'".to_string()),
+    }
+}
+
+/// Run a tool. `Err` is a protocol error; tool failures are results with `isError` set.
+fn call_tool(runner: &BatchRunner, params: &serde_json::Value) -> Result<serde_json::Value, (i64, String)> {
+    let name = params["name"].as_str().ok_or((INVALID_PARAMS, "Missing tool name".to_string()))?;
+    let args: ImageArgs = serde_json::from_value(params.get("arguments").cloned().unwrap_or_else(|| serde_json::json!({})))
+        .map_err(|e| (INVALID_PARAMS, format!("Invalid arguments for {}: {}", name, e)))?;
+
+    let result = match name {
+        "describe_image" => load_image(&args).and_then(|(file, data)| {
+            runner
+                .describe_data(&file, &data)
+                .map(|described| serde_json::to_string(&described.record).expect("records serialize"))
+                .map_err(|e| e.to_string())
+        }),
+        "extract_from_image" => {
+            let question = match (&args.question, args.fields.is_empty()) {
+                (Some(question), _) => question.clone(),
+                (None, false) => extraction_prompt(&args.fields),
+                (None, true) => {
+                    return Err((INVALID_PARAMS, "extract_from_image needs 'fields' or 'question'".to_string()))
+                }
+            };
+            load_image(&args).and_then(|(file, data)| {
+                runner
+                    .ask(&file, &data, &question)
+                    .map(|described| described.record.response.to_string())
+                    .map_err(|e| e.to_string())
+            })
+        }
+        _ => return Err((INVALID_PARAMS, format!("Unknown tool '{}'", name))),
+    };
+
+    let (text, is_error) = match result {
+        Ok(text) => (text, false),
+        Err(e) => (e, true),
+    };
+    Ok(serde_json::json!({"content": [{"type": "text", "text": text}], "isError": is_error}))
+}
+
+fn dispatch(runner: &BatchRunner, method: &str, params: &serde_json::Value) -> Result<serde_json::Value, (i64, String)> {
+    match method {
+        "initialize" => Ok(serde_json::json!({
+            "protocolVersion": PROTOCOL_VERSION,
+            "capabilities": {"tools": {}},
+            "serverInfo": {"name": "9ladies", "version": env!("CARGO_PKG_VERSION")}
+        })),
+        "ping" => Ok(serde_json::json!({})),
+        "tools/list" => Ok(serde_json::json!({ "tools": tools() })),
+        "tools/call" => call_tool(runner, params),
+        _ => Err((METHOD_NOT_FOUND, format!("Method not found: {}", method))),
+    }
+}
+
+/// Answer one line from the client, or `None` for notifications.
+fn handle_line(runner: &BatchRunner, line: &str) -> Option<String> {
+    let request: RpcRequest = match serde_json::from_str(line) {
+        Ok(request) => request,
+        Err(e) => {
+            let error = serde_json::json!({"code": PARSE_ERROR, "message": format!("Invalid JSON-RPC message: {}", e)});
+            return Some(serde_json::json!({"jsonrpc": "2.0", "id": null, "error": error}).to_string());
+        }
+    };
+    debug!(method = request.method.as_str(), "MCP request");
+
+    let result = dispatch(runner, &request.method, &request.params);
+    let id = request.id?;
+    let reply = match result {
+        Ok(result) => serde_json::json!({"jsonrpc": "2.0", "id": id, "result": result}),
+        Err((code, message)) => {
+            serde_json::json!({"jsonrpc": "2.0", "id": id, "error": {"code": code, "message": message}})
+        }
+    };
+    Some(reply.to_string())
+}
+
+/// Serve MCP requests from `input` until it closes.
+pub fn serve(runner: &BatchRunner, input: impl BufRead, mut output: impl Write) -> Result<(), String> {
+    info!("MCP server ready on stdin/stdout");
+    for line in input.lines() {
+        let line = line.map_err(|e| format!("Failed to read from stdin: {}", e))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(reply) = handle_line(runner, &line) {
+            writeln!(output, "{}", reply)
+                .and_then(|_| output.flush())
+                .map_err(|e| format!("Failed to write to stdout: {}", e))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nineladies::{Api, Backend, PromptConfig};
+
+    fn runner() -> BatchRunner {
+        let config = PromptConfig {
+            system: String::new(),
+            prompt: "Describe this.".to_string(),
+            temperature: 0.0,
+            model: None,
+            schema: None,
+            seed: None,
+            think: None,
+            schema_via: None,
+        };
+        // Nothing listens on port 9; the tests never get as far as a model request
+        BatchRunner::new(Backend::new(Api::Ollama, "http://127.0.0.1:9", None, Some(1)), config)
+    }
+
+    fn reply(line: &str) -> serde_json::Value {
+        serde_json::from_str(&handle_line(&runner(), line).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_initialize_and_list_tools() {
+        let init = reply(r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{"protocolVersion":"2024-11-05"}}"#);
+        assert_eq!(init["id"], 1);
+        assert_eq!(init["result"]["serverInfo"]["name"], "9ladies");
+
+        assert_eq!(handle_line(&runner(), r#"{"jsonrpc":"2.0","method":"notifications/initialized"}"#), None);
+
+        let list = reply(r#"{"jsonrpc":"2.0","id":2,"method":"tools/list"}"#);
+        let names: Vec<&str> = list["result"]["tools"].as_array().unwrap().iter().map(|t| t["name"].as_str().unwrap()).collect();
+        assert_eq!(names, ["describe_image", "extract_from_image"]);
+    }
+
+    #[test]
+    fn test_protocol_errors() {
+        assert_eq!(reply("not json")["error"]["code"], PARSE_ERROR);
+        assert_eq!(reply(r#"{"jsonrpc":"2.0","id":3,"method":"resources/list"}"#)["error"]["code"], METHOD_NOT_FOUND);
+        let unknown = reply(r#"{"jsonrpc":"2.0","id":4,"method":"tools/call","params":{"name":"paint","arguments":{}}}"#);
+        assert_eq!(unknown["error"]["code"], INVALID_PARAMS);
+        let no_fields = reply(
+            r#"{"jsonrpc":"2.0","id":5,"method":"tools/call","params":{"name":"extract_from_image","arguments":{"path":"a.jpg"}}}"#,
+        );
+        assert_eq!(no_fields["error"]["code"], INVALID_PARAMS);
+    }
+
+    #[test]
+    fn test_tool_failure_is_a_result() {
+        let missing = reply(
+            r#"{"jsonrpc":"2.0","id":6,"method":"tools/call","params":{"name":"describe_image","arguments":{"path":"missing.png"}}}"#,
+        );
+        assert_eq!(missing["result"]["isError"], true);
+        assert_eq!(missing["result"]["content"][0]["text"], "File not found: missing.png");
+    }
+
+    #[test]
+    fn test_extraction_prompt() {
+        let prompt = extraction_prompt(&["date".to_string(), "total".to_string()]);
+        assert!(prompt.contains("date, total"));
+        assert!(prompt.contains("JSON object"));
+    }
+}
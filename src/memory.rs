@@ -0,0 +1,105 @@
+//! `--max-memory-mb`: a cap on the memory images take while they are described, so a run
+//! with many workers doesn't end up holding several huge scans and their request bodies at
+//! once. Each image reserves what it is expected to need before it is read, and waits while
+//! the images already in progress leave no room for it. URL inputs are only counted once the
+//! batch takes them; the downloads fetched ahead of it are held outside the budget.
+
+use std::sync::{Condvar, Mutex};
+
+/// Memory shared out between the images in progress.
+#[derive(Debug)]
+pub struct MemoryBudget {
+    limit: u64,
+    used: Mutex<u64>,
+    freed: Condvar,
+}
+
+/// Memory held for one image, given back when dropped.
+#[derive(Debug)]
+pub struct Reservation<'a> {
+    budget: &'a MemoryBudget,
+    bytes: u64,
+}
+
+impl MemoryBudget {
+    /// A budget of `limit` bytes.
+    pub fn new(limit: u64) -> MemoryBudget {
+        MemoryBudget {
+            limit,
+            used: Mutex::new(0),
+            freed: Condvar::new(),
+        }
+    }
+
+    /// Reserve `bytes`, waiting until they fit in what the other reservations leave. More
+    /// than the whole budget waits until nothing else is reserved, so an image over the cap
+    /// is still described, on its own.
+    pub fn reserve(&self, bytes: u64) -> Reservation<'_> {
+        let mut used = self.used.lock().unwrap_or_else(|e| e.into_inner());
+        while *used > 0 && *used + bytes > self.limit {
+            used = self.freed.wait(used).unwrap_or_else(|e| e.into_inner());
+        }
+        *used += bytes;
+        Reservation { budget: self, bytes }
+    }
+
+    /// Bytes reserved now.
+    pub fn used(&self) -> u64 {
+        *self.used.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        let mut used = self.budget.used.lock().unwrap_or_else(|e| e.into_inner());
+        *used -= self.bytes;
+        self.budget.freed.notify_all();
+    }
+}
+
+/// What describing an image of `file_bytes` is expected to take: the file, the request body
+/// with it base64-encoded, and its pixels decoded if it is to be resized first.
+pub fn image_estimate(file_bytes: u64, decoded_pixels: Option<u64>) -> u64 {
+    file_bytes + file_bytes.div_ceil(3) * 4 + decoded_pixels.map_or(0, |pixels| pixels * 4)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_reserve_waits_for_room() {
+        let budget = MemoryBudget::new(100);
+        let first = budget.reserve(60);
+        let (sender, reserved) = mpsc::channel();
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                let _second = budget.reserve(50);
+                sender.send(budget.used()).unwrap();
+            });
+            assert!(reserved.recv_timeout(Duration::from_millis(100)).is_err());
+            drop(first);
+            assert_eq!(reserved.recv_timeout(Duration::from_secs(5)).unwrap(), 50);
+        });
+        assert_eq!(budget.used(), 0);
+    }
+
+    #[test]
+    fn test_reservation_over_the_limit_runs_alone() {
+        let budget = MemoryBudget::new(100);
+        let big = budget.reserve(500);
+        assert_eq!(budget.used(), 500);
+        drop(big);
+        let _small = budget.reserve(10);
+        assert_eq!(budget.used(), 10);
+    }
+
+    #[test]
+    fn test_image_estimate() {
+        assert_eq!(image_estimate(3_000_000, None), 7_000_000);
+        assert_eq!(image_estimate(3_000_000, Some(1_000_000)), 11_000_000);
+    }
+}
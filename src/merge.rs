@@ -0,0 +1,106 @@
+//! Combining output files for `9ladies merge`, e.g. from runs sharded across machines or
+//! a run redone for the files that failed, into one record per image.
+
+use std::collections::HashMap;
+
+use clap::ValueEnum;
+
+use crate::diff::{match_key, MatchBy};
+use crate::OutputRecord;
+
+/// Which record to keep when an image is in more than one output.
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum Conflict {
+    /// The record from the file given last, or the later line within a file
+    #[default]
+    Newest,
+    /// The record from the file given first
+    Oldest,
+    /// The newest record with an answer, over ones without (a skipped or failed image)
+    Answered,
+}
+
+/// The merged records, and how many were dropped as duplicates.
+#[derive(Debug, Default)]
+pub struct Merged {
+    pub records: Vec<OutputRecord>,
+    pub duplicates: usize,
+}
+
+/// Whether a record has a response or an embedding, rather than being written for an image
+/// that was skipped, such as by the NSFW filter.
+pub fn answered(record: &OutputRecord) -> bool {
+    !record.response.is_null() || record.embedding.is_some()
+}
+
+/// Merge outputs given oldest first into one record per image, matched as for `9ladies
+/// diff`. Images keep the place they first appeared in.
+pub fn merge_outputs(outputs: Vec<Vec<OutputRecord>>, by: MatchBy, conflict: Conflict) -> Merged {
+    let mut merged = Merged::default();
+    let mut index: HashMap<String, usize> = HashMap::new();
+
+    for record in outputs.into_iter().flatten() {
+        let key = match_key(&record, by);
+        let Some(&i) = index.get(&key) else {
+            index.insert(key, merged.records.len());
+            merged.records.push(record);
+            continue;
+        };
+        merged.duplicates += 1;
+        let replace = match conflict {
+            Conflict::Newest => true,
+            Conflict::Oldest => false,
+            Conflict::Answered => answered(&record) || !answered(&merged.records[i]),
+        };
+        if replace {
+            merged.records[i] = record;
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn record(file: &str, response: Value) -> OutputRecord {
+        OutputRecord::new(file, "", response)
+    }
+
+    fn responses(merged: &Merged) -> Vec<(&str, &Value)> {
+        merged.records.iter().map(|record| (record.file.as_str(), &record.response)).collect()
+    }
+
+    #[test]
+    fn test_merge_conflict_policies() {
+        let outputs = || {
+            vec![
+                vec![record("a.jpg", json!("first")), record("b.jpg", json!("b"))],
+                vec![record("c.jpg", json!("c")), record("a.jpg", Value::Null)],
+            ]
+        };
+
+        let newest = merge_outputs(outputs(), MatchBy::File, Conflict::Newest);
+        assert_eq!(newest.duplicates, 1);
+        assert_eq!(responses(&newest), [("a.jpg", &Value::Null), ("b.jpg", &json!("b")), ("c.jpg", &json!("c"))]);
+
+        let oldest = merge_outputs(outputs(), MatchBy::File, Conflict::Oldest);
+        assert_eq!(oldest.records[0].response, json!("first"));
+
+        let answered = merge_outputs(outputs(), MatchBy::File, Conflict::Answered);
+        assert_eq!(answered.records[0].response, json!("first"));
+        let retried = vec![vec![record("a.jpg", Value::Null)], vec![record("a.jpg", json!("second"))]];
+        assert_eq!(merge_outputs(retried, MatchBy::File, Conflict::Answered).records[0].response, json!("second"));
+    }
+
+    #[test]
+    fn test_merge_by_hash() {
+        let outputs = vec![
+            vec![OutputRecord::new("IMG_1.jpg", "abc", json!("old"))],
+            vec![OutputRecord::new("beach.jpg", "abc", json!("new"))],
+        ];
+        let merged = merge_outputs(outputs, MatchBy::Sha256, Conflict::Newest);
+        assert_eq!(responses(&merged), [("beach.jpg", &json!("new"))]);
+    }
+}
@@ -0,0 +1,149 @@
+//! NSFW pre-filtering: scoring each image with a local classifier before it is sent
+//! anywhere, so explicit user uploads can be skipped, flagged or sent to a different
+//! (usually local) model instead of a hosted API.
+//!
+//! The classifier is any command that reads an image on stdin and prints a score from 0
+//! (safe) to 1 (explicit), either as a bare number or as JSON with an `nsfw` or `score`
+//! field, e.g. a small script around an ONNX or Falconsai model.
+
+use std::process::Command;
+
+use serde::{Deserialize, Serialize};
+
+use crate::ocr::run_with_input;
+use crate::prompt::PromptConfig;
+
+/// What to do with an image scored at or above the threshold.
+#[derive(Clone, Debug)]
+pub enum NsfwAction {
+    /// Don't send it to the model; its record has a null response
+    Skip,
+    /// Describe it as usual and mark the record
+    Flag,
+    /// Describe it with this prompt config, model and/or server instead
+    Route {
+        config: Option<Box<PromptConfig>>,
+        model: Option<String>,
+        url: Option<String>,
+    },
+}
+
+/// What the filter did with an image, kept in its record.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NsfwOutcome {
+    /// Under the threshold
+    Passed,
+    Skipped,
+    Flagged,
+    Routed,
+}
+
+/// The classifier's verdict on an image.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct NsfwVerdict {
+    pub score: f64,
+    pub outcome: NsfwOutcome,
+}
+
+/// A classifier command and what to do with the images it flags.
+#[derive(Clone, Debug)]
+pub struct NsfwFilter {
+    command: String,
+    threshold: f64,
+    action: NsfwAction,
+}
+
+impl NsfwFilter {
+    pub fn new(command: &str, threshold: f64, action: NsfwAction) -> NsfwFilter {
+        NsfwFilter {
+            command: command.to_string(),
+            threshold,
+            action,
+        }
+    }
+
+    pub fn action(&self) -> &NsfwAction {
+        &self.action
+    }
+
+    /// Score the image and decide what happens to it.
+    pub fn check(&self, image_data: &[u8]) -> Result<NsfwVerdict, String> {
+        let score = self.score(image_data)?;
+        let outcome = match (&self.action, score >= self.threshold) {
+            (_, false) => NsfwOutcome::Passed,
+            (NsfwAction::Skip, true) => NsfwOutcome::Skipped,
+            (NsfwAction::Flag, true) => NsfwOutcome::Flagged,
+            (NsfwAction::Route { .. }, true) => NsfwOutcome::Routed,
+        };
+        Ok(NsfwVerdict { score, outcome })
+    }
+
+    fn score(&self, image_data: &[u8]) -> Result<f64, String> {
+        let mut shell = if cfg!(windows) {
+            let mut shell = Command::new("cmd");
+            shell.arg("/C");
+            shell
+        } else {
+            let mut shell = Command::new("sh");
+            shell.arg("-c");
+            shell
+        };
+        shell.arg(&self.command);
+
+        let output = run_with_input(&mut shell, image_data)
+            .map_err(|e| format!("Failed to run NSFW classifier: {}", e))?;
+        if !output.status.success() {
+            return Err(format!(
+                "NSFW classifier exited with {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        parse_score(&String::from_utf8_lossy(&output.stdout))
+    }
+}
+
+/// A score from the classifier's output: a bare number, or JSON with `nsfw` or `score`.
+pub fn parse_score(output: &str) -> Result<f64, String> {
+    let output = output.trim();
+    let score = output.parse::<f64>().ok().or_else(|| {
+        let json: serde_json::Value = serde_json::from_str(output).ok()?;
+        json.get("nsfw").or_else(|| json.get("score"))?.as_f64()
+    });
+    match score {
+        Some(score) if (0.0..=1.0).contains(&score) => Ok(score),
+        _ => Err(format!("NSFW classifier printed '{}', expected a score from 0 to 1", output)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_score() {
+        assert_eq!(parse_score("0.93\n").unwrap(), 0.93);
+        assert_eq!(parse_score(r#"{"nsfw": 0.1, "sfw": 0.9}"#).unwrap(), 0.1);
+        assert_eq!(parse_score(r#"{"score": 1}"#).unwrap(), 1.0);
+        assert!(parse_score("1.5").is_err());
+        assert!(parse_score("").is_err());
+        assert!(parse_score(r#"{"label": "nsfw"}"#).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_check_applies_threshold() {
+        let flag = NsfwFilter::new("cat >/dev/null; echo 0.8", 0.7, NsfwAction::Flag);
+        assert_eq!(
+            flag.check(b"image").unwrap(),
+            NsfwVerdict { score: 0.8, outcome: NsfwOutcome::Flagged }
+        );
+
+        let skip = NsfwFilter::new("echo 0.2", 0.7, NsfwAction::Skip);
+        assert_eq!(skip.check(b"image").unwrap().outcome, NsfwOutcome::Passed);
+
+        let failing = NsfwFilter::new("exit 2", 0.7, NsfwAction::Skip);
+        assert!(failing.check(b"image").unwrap_err().contains("exited with"));
+    }
+}
@@ -0,0 +1,126 @@
+//! Hybrid OCR: reading an image's text locally with Tesseract and giving it to the model
+//! alongside the image, so it structures and corrects the text instead of reading dense
+//! print (receipts, forms) by eye.
+
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+use std::thread;
+
+/// Runs the `tesseract` command line tool.
+#[derive(Clone, Debug)]
+pub struct Ocr {
+    program: String,
+    /// Tesseract language codes, e.g. `eng+deu`
+    language: Option<String>,
+}
+
+impl Ocr {
+    pub fn new(program: &str, language: Option<String>) -> Ocr {
+        Ocr {
+            program: program.to_string(),
+            language,
+        }
+    }
+
+    /// Check that the program runs, so a missing install is reported once up front rather
+    /// than as a failure for every image.
+    pub fn check(&self) -> Result<(), String> {
+        let output = Command::new(&self.program)
+            .arg("--version")
+            .stdin(Stdio::null())
+            .output()
+            .map_err(|e| format!("Cannot run OCR program '{}': {}", self.program, e))?;
+        if !output.status.success() {
+            return Err(format!("'{} --version' failed with {}", self.program, output.status));
+        }
+        Ok(())
+    }
+
+    /// The text Tesseract finds in the image, trimmed.
+    pub fn recognize(&self, image_data: &[u8]) -> Result<String, String> {
+        let mut command = Command::new(&self.program);
+        command.args(["stdin", "stdout"]);
+        if let Some(language) = &self.language {
+            command.args(["-l", language]);
+        }
+        let output = run_with_input(&mut command, image_data)
+            .map_err(|e| format!("Cannot run OCR program '{}': {}", self.program, e))?;
+        if !output.status.success() {
+            return Err(format!(
+                "OCR failed with {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+}
+
+/// Run `command` with `input` on its stdin, capturing stdout and stderr.
+pub(crate) fn run_with_input(command: &mut Command, input: &[u8]) -> std::io::Result<Output> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    // Write from another thread so a large image can't deadlock against a full stdout pipe.
+    // A program that exits without reading all of it closes the pipe, which isn't an error.
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let input = input.to_vec();
+    let writer = thread::spawn(move || stdin.write_all(&input));
+    let output = child.wait_with_output()?;
+    match writer.join().expect("stdin writer thread panicked") {
+        Err(e) if e.kind() != std::io::ErrorKind::BrokenPipe => Err(e),
+        _ => Ok(output),
+    }
+}
+
+/// The question with the recognized text appended for the model to work from.
+pub fn prompt_with_text(prompt: &str, text: &str) -> String {
+    if text.is_empty() {
+        return format!("{}\n\nOCR found no text in this image.", prompt);
+    }
+    format!(
+        "{}\n\nText recognized in this image by OCR, which may contain mistakes; use the image to correct it:\n\"\"\"\n{}\n\"\"\"",
+        prompt, text
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prompt_with_text() {
+        let prompt = prompt_with_text("Extract the total.", "TOTAL 12.50");
+        assert!(prompt.starts_with("Extract the total.\n\n"));
+        assert!(prompt.ends_with("\"\"\"\nTOTAL 12.50\n\"\"\""));
+
+        assert_eq!(
+            prompt_with_text("Extract the total.", ""),
+            "Extract the total.\n\nOCR found no text in this image."
+        );
+    }
+
+    #[test]
+    fn test_missing_program_is_reported() {
+        let ocr = Ocr::new("nineladies-no-such-ocr", None);
+        assert!(ocr.check().unwrap_err().contains("nineladies-no-such-ocr"));
+        assert!(ocr.recognize(b"image").unwrap_err().contains("Cannot run"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_recognize_reads_program_output() {
+        // A shell script standing in for tesseract
+        let script = std::env::temp_dir().join("nineladies_fake_tesseract.sh");
+        std::fs::write(&script, "#!/bin/sh\ncat >/dev/null\necho \"  TOTAL 12.50 \"\n").unwrap();
+        std::fs::set_permissions(&script, std::os::unix::fs::PermissionsExt::from_mode(0o755)).unwrap();
+
+        let ocr = Ocr::new(script.to_str().unwrap(), Some("eng".to_string()));
+        assert_eq!(ocr.recognize(b"image").unwrap(), "TOTAL 12.50");
+
+        std::fs::remove_file(script).ok();
+    }
+}
@@ -0,0 +1,182 @@
+//! Checking an output file before it goes further down a pipeline, for `9ladies
+//! validate-output`: every line is a record, no image is recorded twice, responses follow
+//! the prompt config's schema and have the fields asked for, and the count matches what was
+//! expected or what a `--repro-bundle` manifest says the run produced.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::diff::{match_key, MatchBy};
+use crate::schema::schema_errors;
+use crate::OutputRecord;
+
+/// What to hold an output file to, beyond every line being a record.
+#[derive(Debug, Default)]
+pub struct Expectations<'a> {
+    /// Schema every response must follow
+    pub schema: Option<&'a Value>,
+    /// Response fields every record must have, as dotted paths like `vendor.name`
+    pub required: &'a [String],
+    /// How many records there should be
+    pub count: Option<usize>,
+    /// The manifest of the run that wrote the file, and the file's SHA-256 to check against it
+    pub manifest: Option<(&'a Value, &'a str)>,
+}
+
+/// A problem found, with the line it is on, if it is on one.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Problem {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    pub message: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OutputCheck {
+    pub records: usize,
+    pub problems: Vec<Problem>,
+}
+
+impl OutputCheck {
+    pub fn passed(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// Check the content of an output file against `expected`.
+pub fn check_output(content: &str, expected: &Expectations) -> OutputCheck {
+    let mut check = OutputCheck {
+        records: 0,
+        problems: Vec::new(),
+    };
+    let mut seen: HashMap<String, usize> = HashMap::new();
+
+    for (i, line) in content.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+        let number = i + 1;
+        let record: OutputRecord = match serde_json::from_str(line) {
+            Ok(record) => record,
+            Err(e) => {
+                check.problems.push(Problem {
+                    line: Some(number),
+                    file: None,
+                    message: format!("not a valid record: {}", e),
+                });
+                continue;
+            }
+        };
+        check.records += 1;
+        let mut problem = |message: String| {
+            check.problems.push(Problem {
+                line: Some(number),
+                file: Some(record.file.clone()),
+                message,
+            })
+        };
+
+        if let Some(first) = seen.insert(match_key(&record, MatchBy::File), number) {
+            problem(format!("duplicate of the record on line {}", first));
+        }
+        if let Some(schema) = expected.schema {
+            schema_errors(schema, &record.response, "response").into_iter().for_each(&mut problem);
+        }
+        for field in expected.required {
+            let mut value = Some(&record.response);
+            for key in field.split('.') {
+                value = value.and_then(|value| value.get(key));
+            }
+            if value.is_none_or(Value::is_null) {
+                problem(format!("response.{}: required, but it is missing", field));
+            }
+        }
+    }
+
+    let mut problem = |message: String| {
+        check.problems.push(Problem {
+            line: None,
+            file: None,
+            message,
+        })
+    };
+    if let Some(count) = expected.count.filter(|&count| count != check.records) {
+        problem(format!("expected {} records, found {}", count, check.records));
+    }
+    if let Some((manifest, sha256)) = expected.manifest {
+        if let Some(succeeded) = manifest["succeeded"].as_u64().filter(|&n| n as usize != check.records) {
+            problem(format!("the manifest says {} files were described, but there are {} records", succeeded, check.records));
+        }
+        if let Some(recorded) = manifest["output"]["sha256"].as_str().filter(|&recorded| recorded != sha256) {
+            problem(format!("the file has changed since the run: the manifest has SHA-256 {}, it now has {}", recorded, sha256));
+        }
+    }
+    check
+}
+
+impl fmt::Display for OutputCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for problem in &self.problems {
+            match (problem.line, &problem.file) {
+                (Some(line), Some(file)) => writeln!(f, "Line {} ({}): {}", line, file, problem.message)?,
+                (Some(line), None) => writeln!(f, "Line {}: {}", line, problem.message)?,
+                _ => writeln!(f, "{}", problem.message)?,
+            }
+        }
+        match self.problems.len() {
+            0 => writeln!(f, "OK: {} records", self.records),
+            n => writeln!(f, "{} problem(s) in {} records", n, self.records),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const CONTENT: &str = r#"{"file":"a.jpg","response":{"total":12.5,"vendor":{"name":"Aldi"}}}
+{"file":"b.jpg","response":{"total":"twelve","vendor":{}}}
+not json
+
+{"file":"a.jpg","response":{"total":3,"vendor":{"name":null}}}
+"#;
+
+    #[test]
+    fn test_check_output() {
+        let schema = json!({"type": "object", "properties": {"total": {"type": "number"}}, "required": ["total"]});
+        let required = ["vendor.name".to_string()];
+        let check = check_output(CONTENT, &Expectations { schema: Some(&schema), required: &required, count: Some(2), manifest: None });
+
+        assert_eq!(check.records, 3);
+        let messages: Vec<(Option<usize>, &str)> = check.problems.iter().map(|p| (p.line, p.message.as_str())).collect();
+        assert_eq!(
+            messages,
+            [
+                (Some(2), "response.total: expected number, got string"),
+                (Some(2), "response.vendor.name: required, but it is missing"),
+                (Some(3), "not a valid record: expected ident at line 1 column 2"),
+                (Some(5), "duplicate of the record on line 1"),
+                (Some(5), "response.vendor.name: required, but it is missing"),
+                (None, "expected 2 records, found 3"),
+            ]
+        );
+        assert!(!check.passed());
+        assert!(check.to_string().starts_with("Line 2 (b.jpg): response.total: expected number, got string\n"));
+    }
+
+    #[test]
+    fn test_check_against_manifest() {
+        let content = "{\"file\":\"a.jpg\",\"response\":\"A cat\"}\n";
+        let manifest = json!({"succeeded": 1, "output": {"sha256": "abc"}});
+        let check = check_output(content, &Expectations { manifest: Some((&manifest, "abc")), ..Expectations::default() });
+        assert!(check.passed());
+        assert_eq!(check.to_string(), "OK: 1 records\n");
+
+        let check = check_output("", &Expectations { manifest: Some((&manifest, "def")), ..Expectations::default() });
+        assert_eq!(check.problems.len(), 2);
+        assert!(check.problems[1].message.contains("has changed since the run"));
+    }
+}
@@ -0,0 +1,97 @@
+//! Opening a run's `--output` file: refusing to write over an earlier run's results unless
+//! asked to, and locking it so two runs can't interleave their records in the same file.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+use crate::lock::FileLock;
+
+/// What to do with an output file that already has records in it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputMode {
+    /// Refuse to start
+    New,
+    /// Add to the end
+    Append,
+    /// Start again from empty
+    Overwrite,
+}
+
+/// An output file opened for a run, locked until it is dropped.
+pub struct LockedOutput {
+    file: File,
+    lock: FileLock,
+}
+
+impl LockedOutput {
+    /// Lock `path` for this process and open it as `mode` says.
+    pub fn open(path: &Path, mode: OutputMode) -> Result<LockedOutput, String> {
+        // Locked first, so another run's file isn't truncated or checked mid-write
+        let lock = FileLock::acquire(path, "Output file")?;
+        let has_records = fs::metadata(path).is_ok_and(|metadata| metadata.len() > 0);
+        if mode == OutputMode::New && has_records {
+            return Err(format!(
+                "Output file '{}' already exists; pass --append to add to it or --overwrite to replace it",
+                path.display()
+            ));
+        }
+        let append = mode == OutputMode::Append;
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .append(append)
+            .truncate(!append)
+            .open(path)
+            .map_err(|e| format!("Failed to create output file '{}': {}", path.display(), e))?;
+        Ok(LockedOutput { file, lock })
+    }
+
+    /// The lockfile held for the output file.
+    pub fn lock_path(&self) -> &Path {
+        self.lock.path()
+    }
+}
+
+impl Write for LockedOutput {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_open_modes() {
+        let dir = std::env::temp_dir().join(format!("nineladies_output_file_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("out.jsonl");
+        fs::write(&path, "{\"file\": \"a.jpg\"}\n").unwrap();
+
+        let err = LockedOutput::open(&path, OutputMode::New).err().unwrap();
+        assert!(err.contains("already exists; pass --append"), "{}", err);
+
+        let mut output = LockedOutput::open(&path, OutputMode::Append).unwrap();
+        assert!(output.lock_path().exists());
+        writeln!(output, "{{\"file\": \"b.jpg\"}}").unwrap();
+        // A second run can't write to it at the same time
+        let err = LockedOutput::open(&path, OutputMode::Overwrite).err().unwrap();
+        assert!(err.contains("in use by another run"), "{}", err);
+        let lock_path = output.lock_path().to_path_buf();
+        drop(output);
+        assert_eq!(fs::read_to_string(&lock_path).unwrap(), "");
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 2);
+
+        drop(LockedOutput::open(&path, OutputMode::Overwrite).unwrap());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "");
+        // An empty file has nothing to lose
+        assert!(LockedOutput::open(&path, OutputMode::New).is_ok());
+        fs::remove_dir_all(dir).ok();
+    }
+}
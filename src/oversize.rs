@@ -0,0 +1,148 @@
+//! `--max-megapixels` and `--max-image-mb`: what to do with images too big to send, rather
+//! than base64-encoding a huge scan and waiting for the request to time out.
+
+use std::io::Cursor;
+
+use clap::ValueEnum;
+use image::imageops::FilterType;
+use image::ImageFormat;
+use tracing::warn;
+
+use crate::grounding::image_dimensions;
+
+/// What to do with an image over a limit.
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum OversizePolicy {
+    /// Fail the file without sending it
+    #[default]
+    Reject,
+    /// Log a warning and send it anyway
+    Warn,
+    /// Send a copy scaled down to fit
+    Resize,
+}
+
+/// The largest image to send, and what to do with larger ones.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SizeLimit {
+    pub max_megapixels: Option<f64>,
+    pub max_bytes: Option<u64>,
+    pub policy: OversizePolicy,
+}
+
+impl SizeLimit {
+    /// Check `image_data` against the limit: an error if it is over and the policy is to
+    /// reject it, otherwise whether a smaller copy should be sent.
+    pub fn check(&self, file: &str, image_data: &[u8]) -> Result<bool, String> {
+        let Some(excess) = self.excess(image_data) else {
+            return Ok(false);
+        };
+        match self.policy {
+            OversizePolicy::Reject => Err(format!("'{}' is too big to send: {}", file, excess)),
+            OversizePolicy::Warn => {
+                warn!(file, "'{}' is {}; sending it anyway", file, excess);
+                Ok(false)
+            }
+            OversizePolicy::Resize => Ok(true),
+        }
+    }
+
+    /// How the image goes over the limit, if it does.
+    fn excess(&self, image_data: &[u8]) -> Option<String> {
+        let bytes = image_data.len() as u64;
+        if let Some(max) = self.max_bytes.filter(|&max| bytes > max) {
+            return Some(format!("{:.1} MB, over the limit of {:.1} MB", mb(bytes), mb(max)));
+        }
+        let max = self.max_megapixels?;
+        let (width, height) = image_dimensions(image_data).ok()?;
+        let megapixels = f64::from(width) * f64::from(height) / 1_000_000.0;
+        (megapixels > max).then(|| format!("{:.1} megapixels, over the limit of {}", megapixels, max))
+    }
+
+    /// A copy of `image_data` scaled down to fit the limit: JPEG, or PNG for images with
+    /// transparency.
+    pub fn downscale(&self, image_data: &[u8]) -> Result<Vec<u8>, String> {
+        let image = image::load_from_memory(image_data).map_err(|e| format!("Failed to decode image to resize it: {}", e))?;
+        let (width, height) = (f64::from(image.width()), f64::from(image.height()));
+        let format = match image.color().has_alpha() {
+            true => ImageFormat::Png,
+            false => ImageFormat::Jpeg,
+        };
+
+        let mut scale: f64 = 1.0;
+        if let Some(max) = self.max_megapixels {
+            scale = scale.min((max * 1_000_000.0 / (width * height)).sqrt());
+        }
+        if let Some(max) = self.max_bytes {
+            scale = scale.min((max as f64 / image_data.len() as f64).sqrt());
+        }
+        // File size doesn't follow pixel count exactly, so shrink further until it fits
+        for _ in 0..8 {
+            let resized = image.resize(
+                ((width * scale).floor() as u32).max(1),
+                ((height * scale).floor() as u32).max(1),
+                FilterType::Triangle,
+            );
+            let resized = match format {
+                ImageFormat::Jpeg => image::DynamicImage::ImageRgb8(resized.to_rgb8()),
+                _ => resized,
+            };
+            let mut data = Vec::new();
+            resized
+                .write_to(&mut Cursor::new(&mut data), format)
+                .map_err(|e| format!("Failed to encode resized image: {}", e))?;
+            if self.max_bytes.is_none_or(|max| data.len() as u64 <= max) {
+                return Ok(data);
+            }
+            scale *= 0.75;
+        }
+        Err(format!("Could not make the image smaller than {:.1} MB", mb(self.max_bytes.unwrap_or_default())))
+    }
+}
+
+fn mb(bytes: u64) -> f64 {
+    bytes as f64 / 1_000_000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn photo(width: u32, height: u32) -> Vec<u8> {
+        let image = image::RgbImage::from_fn(width, height, |x, y| image::Rgb([(x * 7) as u8, (y * 13) as u8, ((x + y) * 3) as u8]));
+        let mut data = Vec::new();
+        image.write_to(&mut Cursor::new(&mut data), ImageFormat::Png).unwrap();
+        data
+    }
+
+    #[test]
+    fn test_check() {
+        let data = photo(2000, 1000);
+        let limit = |policy| SizeLimit {
+            max_megapixels: Some(1.0),
+            max_bytes: None,
+            policy,
+        };
+        let err = limit(OversizePolicy::Reject).check("a.png", &data).unwrap_err();
+        assert_eq!(err, "'a.png' is too big to send: 2.0 megapixels, over the limit of 1");
+        assert!(!limit(OversizePolicy::Warn).check("a.png", &data).unwrap());
+        assert!(limit(OversizePolicy::Resize).check("a.png", &data).unwrap());
+        assert!(!limit(OversizePolicy::Reject).check("a.png", &photo(100, 100)).unwrap());
+    }
+
+    #[test]
+    fn test_downscale() {
+        let data = photo(2000, 1000);
+        let limit = SizeLimit {
+            max_megapixels: Some(0.5),
+            max_bytes: Some(100_000),
+            policy: OversizePolicy::Resize,
+        };
+        let resized = limit.downscale(&data).unwrap();
+        let (width, height) = image_dimensions(&resized).unwrap();
+        assert!(f64::from(width) * f64::from(height) <= 500_000.0, "{}x{}", width, height);
+        assert_eq!(width / height, 2);
+        assert!(resized.len() <= 100_000);
+        assert_eq!(crate::images::detect_image_format(&resized), Some("jpeg"));
+    }
+}
@@ -0,0 +1,75 @@
+//! Windows paths longer than `MAX_PATH` (260 characters) and on UNC shares, which photo
+//! archives on file servers routinely have.
+//!
+//! File system calls go through `long_path`, which gives Windows the `\\?\` form that has no
+//! length limit. Records name files with `normalize`, which takes the prefix off again, so a
+//! file is recorded the same way whether or not the input list used the long form.
+
+use std::borrow::Cow;
+use std::path::Path;
+
+/// `path` as Windows file APIs accept it at any length: absolute, with `\\?\` in front, or
+/// `\\?\UNC\` for a share. Other systems have no such limit and get `path` back as it is.
+pub fn long_path(path: &Path) -> Cow<'_, Path> {
+    if !cfg!(windows) {
+        return Cow::Borrowed(path);
+    }
+    let text = path.to_string_lossy();
+    if text.starts_with(r"\\?\") || text.starts_with(r"\\.\") {
+        return Cow::Borrowed(path);
+    }
+    match std::path::absolute(path) {
+        Ok(absolute) => Cow::Owned(extended(&absolute.to_string_lossy()).into()),
+        Err(_) => Cow::Borrowed(path),
+    }
+}
+
+/// The `\\?\` form of an absolute Windows path. Nothing is resolved in that form, so
+/// separators must already be backslashes.
+fn extended(absolute: &str) -> String {
+    let absolute = absolute.replace('/', r"\");
+    match absolute.strip_prefix(r"\\") {
+        Some(share) => format!(r"\\?\UNC\{}", share),
+        None => format!(r"\\?\{}", absolute),
+    }
+}
+
+/// How `path` is written in records: `\\?\C:\photos` as `C:\photos` and `\\?\UNC\nas\photos`
+/// as `\\nas\photos`. Other paths are unchanged.
+pub fn normalize(path: &str) -> String {
+    if let Some(share) = path.strip_prefix(r"\\?\UNC\") {
+        return format!(r"\\{}", share);
+    }
+    match path.strip_prefix(r"\\?\") {
+        // Only drive paths; `\\?\Volume{...}` names have no shorter form
+        Some(rest) if rest.as_bytes().get(1) == Some(&b':') => rest.to_string(),
+        _ => path.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extended() {
+        assert_eq!(extended(r"C:\photos\2019\a.jpg"), r"\\?\C:\photos\2019\a.jpg");
+        assert_eq!(extended(r"\\nas\archive\a.jpg"), r"\\?\UNC\nas\archive\a.jpg");
+        assert_eq!(extended("C:/photos/a.jpg"), r"\\?\C:\photos\a.jpg");
+    }
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize(r"\\?\C:\photos\a.jpg"), r"C:\photos\a.jpg");
+        assert_eq!(normalize(r"\\?\UNC\nas\archive\a.jpg"), r"\\nas\archive\a.jpg");
+        assert_eq!(normalize(r"\\?\Volume{1234}\a.jpg"), r"\\?\Volume{1234}\a.jpg");
+        assert_eq!(normalize(r"\\nas\archive\a.jpg"), r"\\nas\archive\a.jpg");
+        assert_eq!(normalize("photos/a.jpg"), "photos/a.jpg");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_long_path_is_unchanged_off_windows() {
+        assert_eq!(long_path(Path::new("photos/a.jpg")), Path::new("photos/a.jpg"));
+    }
+}
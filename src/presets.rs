@@ -0,0 +1,224 @@
+//! `--preset`: ready-made prompt configs for common jobs, so they work without writing a
+//! prompt file first.
+//!
+//! A preset is a prompt and a JSON schema for the reply, and may turn on local preprocessing
+//! that suits it, such as OCR for documents.
+
+use clap::ValueEnum;
+use serde_json::{json, Value};
+
+use crate::prompt::PromptConfig;
+
+const CAPTION_PROMPT: &str = "Write a caption for this image: one or two sentences on the main subject, \
+what it is doing and the setting. Describe only what is visible. Reply with only a JSON object: \
+{\"caption\": \"<caption>\"}";
+
+const ALT_TEXT_PROMPT: &str = "Write alt text for this image: one concise sentence, under 125 characters, \
+describing what someone who can't see it needs to know. Don't start with \"Image of\" or \"Picture of\", and \
+don't describe the image's style unless it matters. Reply with only a JSON object: {\"alt_text\": \"<alt text>\"}";
+
+const TAGS_PROMPT: &str = "List 5 to 15 tags for this image, for search: the main subjects, objects, setting, \
+activities and notable colors. Use lowercase singular nouns or short phrases, most important first, with no \
+duplicates. Reply with only a JSON object: {\"tags\": [\"<tag>\", ...]}";
+
+const OCR_PROMPT: &str = "Transcribe all the text in this image exactly as written, keeping line breaks and \
+reading order. Don't correct spelling or translate. Reply with only a JSON object: {\"text\": \"<the \
+text>\", \"language\": \"<ISO 639-1 code of the main language, or null if there is no text>\"}";
+
+const CHART_PROMPT: &str = "Read this chart. Give its type (bar, line, pie, scatter, area or other), its \
+title and axis labels as printed, and the data of each series as points read off the chart, with x as \
+printed on the axis or legend and y as a number. Then summarize what it shows in one or two sentences. Use \
+null for anything not shown. Reply with only a JSON object: {\"chart_type\": ..., \"title\": ..., \
+\"x_axis\": ..., \"y_axis\": ..., \"series\": [{\"name\": ..., \"points\": [{\"x\": ..., \"y\": ...}]}], \
+\"summary\": ...}";
+
+const RECEIPT_PROMPT: &str = "Extract the details of this receipt or invoice. Copy names and item \
+descriptions as printed. Give the date as YYYY-MM-DD and the currency as an ISO 4217 code (EUR, USD, GBP), \
+working it out from the symbol and the vendor's country if it isn't printed. Amounts are plain numbers \
+without currency symbols or thousands separators. List every line item in order, including discounts as \
+negative totals. Use null for anything that isn't on the document rather than guessing. Reply with only \
+a JSON object: {\"vendor\": {\"name\": ..., \"address\": ..., \"tax_id\": ...}, \"document_type\": \
+\"receipt\" or \"invoice\", \"invoice_number\": ..., \"date\": ..., \"currency\": ..., \"line_items\": \
+[{\"description\": ..., \"quantity\": ..., \"unit_price\": ..., \"total\": ...}], \"subtotal\": ..., \
+\"tax\": ..., \"tip\": ..., \"total\": ..., \"payment_method\": ...}";
+
+const PRODUCT_PROMPT: &str = "Write a product listing for the item in this photo. Give a short title, \
+the category, the brand if a logo or label shows it, the main color and material, 3 to 6 notable features, \
+and a two or three sentence description for a shop page. Describe only what is visible; use null for \
+what isn't. Reply with only a JSON object: {\"title\": ..., \"category\": ..., \"brand\": ..., \
+\"color\": ..., \"material\": ..., \"features\": [...], \"description\": ...}";
+
+const MODERATION_PROMPT: &str = "Check this image for content that a general audience platform would not \
+allow: nudity, sexual content, graphic violence, gore, self-harm, hate symbols, weapons, drugs. Mark it \
+safe only if none of these appear. Reply with only a JSON object: {\"safe\": true or false, \
+\"categories\": [<those found, from: nudity, sexual, violence, gore, self_harm, hate_symbols, weapons, \
+drugs>], \"reason\": \"<one short sentence>\"}";
+
+/// Categories the moderation preset reports.
+const MODERATION_CATEGORIES: [&str; 8] =
+    ["nudity", "sexual", "violence", "gore", "self_harm", "hate_symbols", "weapons", "drugs"];
+
+/// A built-in prompt config, chosen with `--preset` instead of `--prompt`.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum Preset {
+    /// A one or two sentence caption
+    Caption,
+    /// Short alt text for accessibility
+    AltText,
+    /// Search tags, most important first
+    Tags,
+    /// The text in the image, transcribed as written
+    Ocr,
+    /// Chart type, titles, data series and a summary
+    Chart,
+    /// Vendor, date, line items, totals and currency from receipts and invoices
+    Receipt,
+    /// A shop listing: title, category, brand, color, material, features
+    Product,
+    /// Whether an image is safe for a general audience, and why not
+    Moderation,
+}
+
+impl Preset {
+    /// The preset's prompt config.
+    pub fn config(self) -> PromptConfig {
+        let (system, prompt, temperature, schema) = match self {
+            Preset::Caption => (
+                "You write accurate, natural image captions.",
+                CAPTION_PROMPT,
+                0.3,
+                object(json!({"caption": {"type": "string"}})),
+            ),
+            Preset::AltText => (
+                "You write alt text for images on websites, following accessibility guidelines.",
+                ALT_TEXT_PROMPT,
+                0.2,
+                object(json!({"alt_text": {"type": "string"}})),
+            ),
+            Preset::Tags => (
+                "You tag images for a searchable photo library.",
+                TAGS_PROMPT,
+                0.2,
+                object(json!({
+                    "tags": {"type": "array", "items": {"type": "string"}, "minItems": 1, "maxItems": 15},
+                })),
+            ),
+            Preset::Ocr => (
+                "You transcribe text from images exactly.",
+                OCR_PROMPT,
+                0.0,
+                object(json!({"text": {"type": "string"}, "language": {"type": ["string", "null"]}})),
+            ),
+            Preset::Chart => (
+                "You read data from charts and graphs precisely.",
+                CHART_PROMPT,
+                0.0,
+                chart_schema(),
+            ),
+            Preset::Receipt => (
+                "You extract structured data from photos and scans of receipts and invoices. \
+                 Accuracy matters more than completeness.",
+                RECEIPT_PROMPT,
+                0.0,
+                receipt_schema(),
+            ),
+            Preset::Product => (
+                "You write accurate product listings for an online shop from product photos.",
+                PRODUCT_PROMPT,
+                0.3,
+                object(json!({
+                    "title": {"type": "string"},
+                    "category": {"type": ["string", "null"]},
+                    "brand": {"type": ["string", "null"]},
+                    "color": {"type": ["string", "null"]},
+                    "material": {"type": ["string", "null"]},
+                    "features": {"type": "array", "items": {"type": "string"}},
+                    "description": {"type": "string"},
+                })),
+            ),
+            Preset::Moderation => (
+                "You review user uploads for a content moderation team. Judge only what is visible.",
+                MODERATION_PROMPT,
+                0.0,
+                object(json!({
+                    "safe": {"type": "boolean"},
+                    "categories": {"type": "array", "items": {"type": "string", "enum": MODERATION_CATEGORIES}},
+                    "reason": {"type": "string"},
+                })),
+            ),
+        };
+        PromptConfig {
+            system: system.to_string(),
+            prompt: prompt.to_string(),
+            temperature,
+            model: None,
+            schema: Some(schema),
+            seed: None,
+            think: None,
+            schema_via: None,
+        }
+    }
+
+    /// Whether the preset reads each image's text with Tesseract first, as with `--ocr`.
+    pub fn ocr(self) -> bool {
+        matches!(self, Preset::Ocr | Preset::Receipt)
+    }
+}
+
+/// A schema for an object with all of `properties` required.
+fn object(properties: Value) -> Value {
+    let required: Vec<&String> = properties.as_object().map(|fields| fields.keys().collect()).unwrap_or_default();
+    json!({"type": "object", "properties": properties, "required": required})
+}
+
+fn chart_schema() -> Value {
+    let text = json!({"type": ["string", "null"]});
+    let point = object(json!({"x": {"type": ["string", "number"]}, "y": {"type": ["number", "null"]}}));
+    let series = object(json!({"name": text, "points": {"type": "array", "items": point}}));
+    object(json!({
+        "chart_type": {"type": "string", "enum": ["bar", "line", "pie", "scatter", "area", "other"]},
+        "title": text,
+        "x_axis": text,
+        "y_axis": text,
+        "series": {"type": "array", "items": series},
+        "summary": {"type": "string"},
+    }))
+}
+
+fn receipt_schema() -> Value {
+    let text = json!({"type": ["string", "null"]});
+    let number = json!({"type": ["number", "null"]});
+    let line_item = object(json!({"description": text, "quantity": number, "unit_price": number, "total": number}));
+    object(json!({
+        "vendor": object(json!({"name": text, "address": text, "tax_id": text})),
+        "document_type": {"type": "string", "enum": ["receipt", "invoice"]},
+        "invoice_number": text,
+        "date": text,
+        "currency": text,
+        "line_items": {"type": "array", "items": line_item},
+        "subtotal": number,
+        "tax": number,
+        "tip": number,
+        "total": number,
+        "payment_method": text,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_presets_ask_for_their_schema_fields() {
+        for preset in Preset::value_variants() {
+            let config = preset.config();
+            let schema = config.schema.unwrap();
+            for field in schema["required"].as_array().unwrap() {
+                let field = field.as_str().unwrap();
+                assert!(config.prompt.contains(&format!("\"{}\"", field)), "{} isn't in the {:?} prompt", field, preset);
+                assert!(schema["properties"].get(field).is_some());
+            }
+        }
+        assert!(Preset::Receipt.ocr() && !Preset::Caption.ocr());
+    }
+}
@@ -0,0 +1,198 @@
+//! `--format pretty`: records rendered for reading in a terminal rather than for parsing.
+
+use std::io::{self, IsTerminal, Write};
+
+use nineladies::backend::TokenSink;
+use nineladies::OutputRecord;
+
+const BOLD: &str = "\x1b[1m";
+const CYAN: &str = "\x1b[36m";
+const DIM: &str = "\x1b[2m";
+const RESET: &str = "\x1b[0m";
+
+/// Used when the terminal width can't be found, e.g. when stdout is a pipe
+const DEFAULT_WIDTH: usize = 80;
+
+/// Narrower than this and wrapping does more harm than good
+const MIN_WIDTH: usize = 40;
+
+/// How records are styled: the wrap width, and whether to use ANSI colors.
+pub struct Style {
+    pub width: usize,
+    pub color: bool,
+}
+
+impl Style {
+    /// Color only when stdout is a terminal and `NO_COLOR` is unset (https://no-color.org).
+    pub fn for_stdout() -> Style {
+        let stdout = io::stdout();
+        Style {
+            width: terminal_width().unwrap_or(DEFAULT_WIDTH).max(MIN_WIDTH),
+            color: stdout.is_terminal() && std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty()),
+        }
+    }
+
+    fn paint(&self, code: &str, text: &str) -> String {
+        if self.color {
+            format!("{}{}{}", code, text, RESET)
+        } else {
+            text.to_string()
+        }
+    }
+}
+
+/// `--stream`: each reply written to stderr as the model writes it, under the name of the
+/// file it is about.
+pub struct StreamPrinter {
+    color: bool,
+}
+
+impl StreamPrinter {
+    pub fn for_stderr() -> StreamPrinter {
+        StreamPrinter {
+            color: io::stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty()),
+        }
+    }
+
+    /// Start the replies about `file`.
+    pub fn header(&self, file: &str) {
+        let header = format!("== {} ==", file);
+        match self.color {
+            true => eprintln!("{}{}{}", BOLD, header, RESET),
+            false => eprintln!("{}", header),
+        }
+    }
+}
+
+impl TokenSink for StreamPrinter {
+    fn token(&self, text: &str) {
+        let mut stderr = io::stderr().lock();
+        let _ = stderr.write_all(text.as_bytes()).and_then(|_| stderr.flush());
+    }
+
+    fn done(&self) {
+        eprintln!();
+    }
+}
+
+#[cfg(unix)]
+fn terminal_width() -> Option<usize> {
+    // SAFETY: TIOCGWINSZ only fills in the winsize struct passed to it
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    if unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } == 0 && size.ws_col > 0 {
+        return Some(size.ws_col as usize);
+    }
+    std::env::var("COLUMNS").ok()?.parse().ok()
+}
+
+#[cfg(not(unix))]
+fn terminal_width() -> Option<usize> {
+    std::env::var("COLUMNS").ok()?.parse().ok()
+}
+
+/// The file name in bold, then the response: text wrapped to the width, or each field of
+/// a JSON object on its own line with its name highlighted.
+pub fn render(record: &OutputRecord, style: &Style) -> String {
+    let mut out = style.paint(BOLD, &record.file);
+    let path = record.current_path();
+    if path != record.file {
+        out.push_str(&style.paint(DIM, &format!(" -> {}", path)));
+    }
+    out.push('\n');
+
+    match &record.response {
+        serde_json::Value::Object(fields) => {
+            let key_width = fields.keys().map(|k| k.chars().count()).max().unwrap_or(0);
+            for (key, value) in fields {
+                let indent = 2 + key_width + 2;
+                let lines = wrap(&value_text(value), style.width.saturating_sub(indent).max(MIN_WIDTH / 2));
+                let label = format!("{:width$}", key, width = key_width);
+                for (i, line) in lines.iter().enumerate() {
+                    if i == 0 {
+                        out.push_str(&format!("  {}  {}\n", style.paint(CYAN, &label), line));
+                    } else {
+                        out.push_str(&format!("{:indent$}{}\n", "", line, indent = indent));
+                    }
+                }
+            }
+        }
+        value => {
+            for line in wrap(&value_text(value), style.width.saturating_sub(2)) {
+                out.push_str(&format!("  {}\n", line));
+            }
+        }
+    }
+    out
+}
+
+/// Field values as plain text: strings unquoted, lists of scalars comma-separated.
+fn value_text(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(s) => s.clone(),
+        serde_json::Value::Array(items) if items.iter().all(|v| !v.is_array() && !v.is_object()) => {
+            items.iter().map(value_text).collect::<Vec<_>>().join(", ")
+        }
+        other => other.to_string(),
+    }
+}
+
+/// Greedy word wrap, keeping the model's own line breaks. Words longer than the width are
+/// left on a line of their own rather than split.
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    for paragraph in text.lines() {
+        let mut line = String::new();
+        for word in paragraph.split_whitespace() {
+            if !line.is_empty() && line.chars().count() + 1 + word.chars().count() > width {
+                lines.push(std::mem::take(&mut line));
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(word);
+        }
+        lines.push(line);
+    }
+    if lines.is_empty() {
+        lines.push(String::new());
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PLAIN: Style = Style { width: 40, color: false };
+
+    #[test]
+    fn test_wrap() {
+        assert_eq!(wrap("a quick brown fox jumps", 11), vec!["a quick", "brown fox", "jumps"]);
+        assert_eq!(wrap("one\n\ntwo", 20), vec!["one", "", "two"]);
+        assert_eq!(wrap("", 20), vec![""]);
+    }
+
+    #[test]
+    fn test_render_fields() {
+        let record = OutputRecord::new(
+            "a.jpg",
+            "",
+            serde_json::json!({"tags": ["cat", "sofa"], "description": "A grey cat asleep on a green sofa in the afternoon sun"}),
+        );
+        assert_eq!(
+            render(&record, &PLAIN),
+            "a.jpg\n  tags         cat, sofa\n  description  A grey cat asleep on a\n               green sofa in the\n               afternoon sun\n"
+        );
+    }
+
+    #[test]
+    fn test_render_text_with_color() {
+        let mut record = OutputRecord::new("a.jpg", "", serde_json::json!("A cat"));
+        record.renamed_to = Some("cat.jpg".to_string());
+        let style = Style { width: 80, color: true };
+        assert_eq!(
+            render(&record, &style),
+            "\x1b[1ma.jpg\x1b[0m\x1b[2m -> cat.jpg\x1b[0m\n  A cat\n"
+        );
+    }
+}
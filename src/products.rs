@@ -0,0 +1,160 @@
+//! `9ladies products`: one consolidated description per product from all of its shots,
+//! for catalogues where each SKU has several photos (front, back, label, detail).
+//!
+//! Shots are listed in JSONL (`{"sku": ..., "file": ...}`) or CSV with `sku` and `file`
+//! columns, and grouped by SKU in the order each first appears.
+
+use serde::Deserialize;
+
+use crate::prompt::PromptConfig;
+
+/// One product and its shots, in the order they were listed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Product {
+    pub sku: String,
+    pub files: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct Shot {
+    #[serde(alias = "asset_id")]
+    sku: String,
+    #[serde(alias = "path")]
+    file: String,
+}
+
+/// Group the shots listed in `content` by SKU. JSONL is recognized by its first line
+/// starting with `{`; anything else is read as CSV with a header row. Blank lines and
+/// lines starting with `#` are skipped, and a file listed twice for a SKU is kept once.
+pub fn parse_products(content: &str) -> Result<Vec<Product>, String> {
+    let mut lines = content
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .peekable();
+
+    let mut shots = Vec::new();
+    if lines.peek().is_some_and(|(_, line)| line.starts_with('{')) {
+        for (number, line) in lines {
+            let shot: Shot = serde_json::from_str(line).map_err(|e| format!("Line {}: {}", number, e))?;
+            shots.push(shot);
+        }
+    } else if let Some((_, header)) = lines.next() {
+        let columns = split_csv_line(header);
+        let column = |names: &[&str]| {
+            columns
+                .iter()
+                .position(|column| names.iter().any(|name| column.eq_ignore_ascii_case(name)))
+                .ok_or_else(|| format!("CSV header has no '{}' column", names[0]))
+        };
+        let (sku, file) = (column(&["sku", "asset_id"])?, column(&["file", "path"])?);
+        for (number, line) in lines {
+            let fields = split_csv_line(line);
+            match (fields.get(sku), fields.get(file)) {
+                (Some(sku), Some(file)) => shots.push(Shot {
+                    sku: sku.clone(),
+                    file: file.clone(),
+                }),
+                _ => return Err(format!("Line {}: expected {} columns, got {}", number, columns.len(), fields.len())),
+            }
+        }
+    }
+
+    let mut products: Vec<Product> = Vec::new();
+    for shot in shots {
+        let (sku, file) = (shot.sku.trim().to_string(), shot.file.trim().to_string());
+        if sku.is_empty() || file.is_empty() {
+            return Err(format!("Every shot needs a SKU and a file, got sku '{}' and file '{}'", sku, file));
+        }
+        match products.iter_mut().find(|product| product.sku == sku) {
+            Some(product) if !product.files.contains(&file) => product.files.push(file),
+            Some(_) => {}
+            None => products.push(Product { sku, files: vec![file] }),
+        }
+    }
+    Ok(products)
+}
+
+/// The fields of a CSV line, with double quotes around fields that contain commas and
+/// `""` for a quote inside one.
+fn split_csv_line(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field).trim().to_string()),
+            c => field.push(c),
+        }
+    }
+    fields.push(field.trim().to_string());
+    fields
+}
+
+/// `config` asked about several shots of one product in a single request.
+pub fn product_config(config: &PromptConfig, shots: usize) -> PromptConfig {
+    PromptConfig {
+        prompt: format!(
+            "The {} images are all photos of the same product, from different angles or details. Answer for \
+             the product as a whole, combining what every photo shows.\n\n{}",
+            shots, config.prompt
+        ),
+        ..config.clone()
+    }
+}
+
+/// `config` asked about further shots of a product, given the answer from its earlier shots,
+/// so products with more photos than one request can take are described in turns.
+pub fn continuation_config(config: &PromptConfig, shots: usize, previous: &str) -> PromptConfig {
+    PromptConfig {
+        prompt: format!(
+            "{}\n\nThis answer was given from earlier photos of the same product:\n\n{}\n\nReply with the \
+             complete answer in the same format, updated with anything these {} further photos add or correct.",
+            product_config(config, shots).prompt,
+            previous,
+            shots
+        ),
+        ..config.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_jsonl_products() {
+        let content = "{\"sku\": \"A1\", \"file\": \"a1-front.jpg\"}\n\n{\"sku\": \"B2\", \"file\": \"b2.jpg\"}\n\
+                       {\"asset_id\": \"A1\", \"path\": \"a1-back.jpg\"}\n{\"sku\": \"A1\", \"file\": \"a1-front.jpg\"}\n";
+        assert_eq!(
+            parse_products(content).unwrap(),
+            vec![
+                Product { sku: "A1".to_string(), files: vec!["a1-front.jpg".to_string(), "a1-back.jpg".to_string()] },
+                Product { sku: "B2".to_string(), files: vec!["b2.jpg".to_string()] },
+            ]
+        );
+        assert!(parse_products("{\"sku\": \"A1\"}").unwrap_err().starts_with("Line 1:"));
+    }
+
+    #[test]
+    fn test_parse_csv_products() {
+        let content = "# exported from the PIM\nFile,SKU,Notes\n\"shots/a, front.jpg\",A1,\"the \"\"good\"\" one\"\nshots/a-back.jpg,A1,\n";
+        assert_eq!(
+            parse_products(content).unwrap(),
+            vec![Product {
+                sku: "A1".to_string(),
+                files: vec!["shots/a, front.jpg".to_string(), "shots/a-back.jpg".to_string()],
+            }]
+        );
+        assert!(parse_products("name,file\nx,y.jpg\n").unwrap_err().contains("'sku'"));
+        assert!(parse_products("sku,file\nA1\n").unwrap_err().starts_with("Line 2:"));
+        assert_eq!(parse_products("").unwrap(), vec![]);
+    }
+}
@@ -0,0 +1,378 @@
+//! Batch progress reporting on stderr.
+
+use std::io::{IsTerminal, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use clap::ValueEnum;
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use serde::Serialize;
+
+const BAR_TEMPLATE: &str =
+    "[{elapsed_precise}] {bar:40.cyan/blue} {pos}/{len} {msg} ({per_sec}, ETA {eta})";
+
+/// The bar currently drawn on stderr, so log output can step around it.
+static ACTIVE_BAR: Mutex<Option<ProgressBar>> = Mutex::new(None);
+
+/// Run `f` (which writes to stderr) with the active progress bar hidden, if there is one.
+pub fn suspend_active<F: FnOnce() -> R, R>(f: F) -> R {
+    let bar = ACTIVE_BAR.lock().unwrap_or_else(|e| e.into_inner()).clone();
+    match bar {
+        Some(bar) => bar.suspend(f),
+        None => f(),
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum ProgressMode {
+    /// Progress bar when stderr is a terminal, nothing otherwise
+    Auto,
+    /// One JSON event per line (started, item_done, item_failed, paused, resumed, finished)
+    Json,
+    /// No progress output
+    None,
+}
+
+impl ProgressMode {
+    /// `--quiet` drops anything that would be drawn on stderr; events sent to a dedicated
+    /// descriptor are kept, since a wrapper asked for them explicitly.
+    pub fn quieted(self, quiet: bool, events_fd: Option<i32>) -> Self {
+        match self {
+            ProgressMode::Json if events_fd.is_some() => ProgressMode::Json,
+            _ if quiet => ProgressMode::None,
+            mode => mode,
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum ProgressEvent<'a> {
+    Started {
+        total: usize,
+    },
+    ItemDone {
+        file: &'a str,
+        index: usize,
+    },
+    ItemFailed {
+        file: &'a str,
+        index: usize,
+        error: &'a str,
+    },
+    Paused {
+        opens: &'a str,
+    },
+    Resumed,
+    Finished {
+        total: usize,
+        succeeded: usize,
+        failed: usize,
+        elapsed_secs: f64,
+    },
+}
+
+/// Counts shared with the SIGUSR1 handler, which may print them mid-request.
+struct RunState {
+    total: usize,
+    done: usize,
+    failed: usize,
+    current: Option<String>,
+    started: Instant,
+}
+
+impl RunState {
+    fn snapshot(&self) -> String {
+        let elapsed = self.started.elapsed();
+        let remaining = self.total - self.done;
+        let eta = match self.done {
+            0 => "unknown".to_string(),
+            done => format_duration(elapsed.mul_f64(remaining as f64 / done as f64)),
+        };
+        format!(
+            "Progress: {}/{} processed, {} failed, {} remaining, current: {}, elapsed {}, ETA {}",
+            self.done,
+            self.total,
+            self.failed,
+            remaining,
+            self.current.as_deref().unwrap_or("none"),
+            format_duration(elapsed),
+            eta
+        )
+    }
+}
+
+/// Format a duration as e.g. `1h02m03s`, `2m05s` or `7s`.
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match (secs / 3600, secs / 60 % 60, secs % 60) {
+        (0, 0, s) => format!("{}s", s),
+        (0, m, s) => format!("{}m{:02}s", m, s),
+        (h, m, s) => format!("{}h{:02}m{:02}s", h, m, s),
+    }
+}
+
+/// Tracks processed and failed items for a run. The bar is only drawn when stderr is a
+/// terminal, so piped or redirected runs produce the same stderr output as before.
+pub struct Progress {
+    bar: ProgressBar,
+    events: Option<Box<dyn Write>>,
+    state: Arc<Mutex<RunState>>,
+    #[cfg(unix)]
+    snapshot_signal: Option<signal_hook::iterator::Handle>,
+}
+
+impl Progress {
+    /// `events_fd` sends JSON events to an already-open file descriptor instead of stderr.
+    pub fn new(total: usize, mode: ProgressMode, events_fd: Option<i32>) -> Result<Self, String> {
+        let show_bar = mode == ProgressMode::Auto && std::io::stderr().is_terminal();
+        let bar = if show_bar {
+            ProgressBar::with_draw_target(Some(total as u64), ProgressDrawTarget::stderr())
+        } else {
+            ProgressBar::hidden()
+        };
+        bar.set_style(
+            ProgressStyle::with_template(BAR_TEMPLATE)
+                .expect("progress template is valid")
+                .progress_chars("=> "),
+        );
+        bar.enable_steady_tick(Duration::from_millis(500));
+        if show_bar {
+            *ACTIVE_BAR.lock().unwrap_or_else(|e| e.into_inner()) = Some(bar.clone());
+        }
+
+        let events: Option<Box<dyn Write>> = match (mode, events_fd) {
+            (ProgressMode::Json, Some(fd)) => Some(open_fd(fd)?),
+            (ProgressMode::Json, None) => Some(Box::new(std::io::stderr())),
+            _ => None,
+        };
+
+        let state = Arc::new(Mutex::new(RunState {
+            total,
+            done: 0,
+            failed: 0,
+            current: None,
+            started: Instant::now(),
+        }));
+
+        let mut progress = Progress {
+            bar,
+            events,
+            #[cfg(unix)]
+            snapshot_signal: print_snapshot_on_sigusr1(Arc::clone(&state))?,
+            state,
+        };
+        progress.emit(&ProgressEvent::Started { total });
+        Ok(progress)
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, RunState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn emit(&mut self, event: &ProgressEvent) {
+        if let Some(out) = self.events.as_mut() {
+            let line = serde_json::to_string(event).expect("progress events serialize");
+            // Progress is best-effort: a closed fd must not abort the batch
+            let _ = writeln!(out, "{}", line).and_then(|_| out.flush());
+        }
+    }
+
+    /// Hide the bar while `f` writes to the terminal.
+    pub fn suspend<F: FnOnce() -> R, R>(&self, f: F) -> R {
+        self.bar.suspend(f)
+    }
+
+    /// Count one more item, for runs that take inputs as they arrive.
+    pub fn add_item(&self) {
+        self.state().total += 1;
+        self.bar.inc_length(1);
+    }
+
+    /// Record which file is being worked on, for progress snapshots.
+    pub fn item_started(&self, file: &str) {
+        self.state().current = Some(file.to_string());
+    }
+
+    /// Record that an item has finished, with the first error it hit if it failed.
+    pub fn item_done(&mut self, file: &str, error: Option<&str>) {
+        let (index, failed) = {
+            let mut state = self.state();
+            state.current = None;
+            state.done += 1;
+            state.failed += error.is_some() as usize;
+            (state.done - 1, state.failed)
+        };
+
+        match error {
+            Some(error) => {
+                self.bar.set_message(format!("{} failed", failed));
+                self.emit(&ProgressEvent::ItemFailed { file, index, error });
+            }
+            None => self.emit(&ProgressEvent::ItemDone { file, index }),
+        }
+        self.bar.inc(1);
+    }
+
+    /// Show that the run is waiting for its run window to open at `opens`.
+    pub fn paused(&mut self, opens: &str) {
+        self.bar.set_message(format!("paused until {}", opens));
+        self.emit(&ProgressEvent::Paused { opens });
+    }
+
+    pub fn resumed(&mut self) {
+        let failed = self.state().failed;
+        self.bar.set_message(match failed {
+            0 => String::new(),
+            failed => format!("{} failed", failed),
+        });
+        self.emit(&ProgressEvent::Resumed);
+    }
+
+    pub fn finish(&mut self) {
+        self.bar.finish_and_clear();
+        ACTIVE_BAR.lock().unwrap_or_else(|e| e.into_inner()).take();
+        #[cfg(unix)]
+        if let Some(handle) = self.snapshot_signal.take() {
+            handle.close();
+        }
+
+        let event = {
+            let state = self.state();
+            ProgressEvent::Finished {
+                total: state.total,
+                succeeded: state.done - state.failed,
+                failed: state.failed,
+                elapsed_secs: state.started.elapsed().as_secs_f64(),
+            }
+        };
+        self.emit(&event);
+    }
+}
+
+/// Print a progress snapshot to stderr whenever the process receives SIGUSR1, so a detached
+/// batch can be checked on with `kill -USR1 <pid>`. This works even with `--quiet` or
+/// `--progress none`, since the operator asked for it.
+#[cfg(unix)]
+fn print_snapshot_on_sigusr1(
+    state: Arc<Mutex<RunState>>,
+) -> Result<Option<signal_hook::iterator::Handle>, String> {
+    use signal_hook::iterator::Signals;
+
+    let mut signals = Signals::new([signal_hook::consts::SIGUSR1])
+        .map_err(|e| format!("Failed to install SIGUSR1 handler: {}", e))?;
+    let handle = signals.handle();
+
+    std::thread::spawn(move || {
+        for _ in signals.forever() {
+            let snapshot = state.lock().unwrap_or_else(|e| e.into_inner()).snapshot();
+            suspend_active(|| eprintln!("{}", snapshot));
+        }
+    });
+    Ok(Some(handle))
+}
+
+#[cfg(unix)]
+fn open_fd(fd: i32) -> Result<Box<dyn Write>, String> {
+    use std::os::fd::{FromRawFd, OwnedFd};
+
+    // SAFETY: F_GETFD only queries descriptor flags
+    if fd < 0 || unsafe { libc::fcntl(fd, libc::F_GETFD) } < 0 {
+        return Err(format!("--progress-fd {} is not an open file descriptor", fd));
+    }
+    // SAFETY: the descriptor was checked to be open and is handed to us by the caller
+    // for exclusive use as the progress stream.
+    let owned = unsafe { OwnedFd::from_raw_fd(fd) };
+    Ok(Box::new(std::fs::File::from(owned)))
+}
+
+#[cfg(not(unix))]
+fn open_fd(_fd: i32) -> Result<Box<dyn Write>, String> {
+    Err("--progress-fd is only supported on Unix".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_progress_counts_failures() {
+        let mut progress = Progress::new(3, ProgressMode::Auto, None).unwrap();
+        progress.item_done("a.jpg", None);
+        progress.item_done("b.jpg", Some("boom"));
+        progress.item_done("c.jpg", Some("boom"));
+
+        assert_eq!(progress.state().failed, 2);
+        assert_eq!(progress.bar.position(), 3);
+        progress.finish();
+    }
+
+    #[test]
+    fn test_progress_json_events() {
+        let buf = SharedBuf::default();
+        let mut progress = Progress::new(2, ProgressMode::None, None).unwrap();
+        progress.events = Some(Box::new(buf.clone()));
+
+        progress.item_done("a.jpg", None);
+        progress.item_done("b.jpg", Some("File not found: b.jpg"));
+        progress.finish();
+
+        let output = String::from_utf8(buf.0.lock().unwrap().clone()).unwrap();
+        let events: Vec<serde_json::Value> =
+            output.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0], serde_json::json!({"event": "item_done", "file": "a.jpg", "index": 0}));
+        assert_eq!(events[1]["event"], "item_failed");
+        assert_eq!(events[1]["error"], "File not found: b.jpg");
+        assert_eq!(events[2]["event"], "finished");
+        assert_eq!(events[2]["succeeded"], 1);
+        assert_eq!(events[2]["failed"], 1);
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let progress = Progress::new(4, ProgressMode::None, None).unwrap();
+        assert!(progress.state().snapshot().contains("0/4 processed, 0 failed, 4 remaining, current: none"));
+
+        progress.item_started("a.jpg");
+        let snapshot = progress.state().snapshot();
+        assert!(snapshot.contains("current: a.jpg"));
+        assert!(snapshot.ends_with("ETA unknown"));
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(Duration::from_secs(7)), "7s");
+        assert_eq!(format_duration(Duration::from_secs(125)), "2m05s");
+        assert_eq!(format_duration(Duration::from_secs(3723)), "1h02m03s");
+    }
+
+    #[test]
+    fn test_quiet_progress_mode() {
+        assert_eq!(ProgressMode::Auto.quieted(true, None), ProgressMode::None);
+        assert_eq!(ProgressMode::Json.quieted(true, None), ProgressMode::None);
+        assert_eq!(ProgressMode::Json.quieted(true, Some(3)), ProgressMode::Json);
+        assert_eq!(ProgressMode::Auto.quieted(false, None), ProgressMode::Auto);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_progress_fd_must_be_open() {
+        assert!(Progress::new(1, ProgressMode::Json, Some(987)).is_err());
+    }
+}
@@ -0,0 +1,214 @@
+//! Prompt configuration files: the system prompt, question and temperature for a run.
+
+use std::collections::BTreeMap;
+use std::fs;
+
+use serde::{Deserialize, Serialize};
+
+/// A prompt config file, e.g. `prompts/describe.json`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PromptConfig {
+    /// System prompt sent before every question
+    pub system: String,
+    /// The question asked about each image
+    pub prompt: String,
+    /// Sampling temperature, 0.0 to 2.0
+    pub temperature: f32,
+    /// Model to use unless `--model` names one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// JSON schema the reply must follow, for servers with structured output (Ollama's
+    /// `format`, OpenAI's `response_format`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schema: Option<serde_json::Value>,
+    /// How to send `schema`: as the structured output format (the default), or as a tool
+    /// for the model to call
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schema_via: Option<SchemaVia>,
+    /// Sampling seed, for repeatable replies from servers that honor it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    /// Turn a reasoning model's thinking on or off, for Ollama's `think`; unset leaves it to
+    /// the model
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub think: Option<bool>,
+}
+
+/// How a prompt config's schema is sent to the server.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SchemaVia {
+    /// As the structured output format the reply must follow
+    Format,
+    /// As the parameters of a tool the model is told to call, with the tool's arguments
+    /// taken as the reply. Some hosted models follow a tool's parameters far more reliably
+    /// than a response format.
+    Tool,
+}
+
+/// Translations of a prompt config's text, under `locales` in the file and keyed by
+/// language code, so one file serves teams working in several languages:
+/// `"locales": {"de": {"system": "...", "prompt": "..."}, "pt-BR": {"prompt": "..."}}`.
+/// Text a variant leaves out is the file's own.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LocalePrompt {
+    system: Option<String>,
+    prompt: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Locales {
+    #[serde(default)]
+    locales: BTreeMap<String, LocalePrompt>,
+}
+
+/// Read and check a prompt config JSON file.
+pub fn load_prompt_config(path: &str) -> Result<PromptConfig, String> {
+    load_localized_prompt_config(path, None)
+}
+
+/// Like `load_prompt_config`, with the system prompt and question of the file's variant for
+/// `locale` (`--locale`). `pt-BR` also finds a variant for `pt`; a locale the file has no
+/// variant for is an error.
+pub fn load_localized_prompt_config(path: &str, locale: Option<&str>) -> Result<PromptConfig, String> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read prompt file '{}': {}", path, e))?;
+
+    let mut config: PromptConfig = serde_json::from_str(&content)
+        .map_err(|e| format!("Failed to parse prompt file '{}': {}", path, e))?;
+    let Locales { locales } = serde_json::from_str(&content)
+        .map_err(|e| format!("Failed to parse the locales in prompt file '{}': {}", path, e))?;
+    if let Some(locale) = locale {
+        let variant = find_locale(&locales, locale).ok_or_else(|| match locales.is_empty() {
+            true => format!("Prompt file '{}' has no locales, so --locale {} can't be used with it", path, locale),
+            false => format!(
+                "Prompt file '{}' has no '{}' locale (it has {})",
+                path,
+                locale,
+                locales.keys().cloned().collect::<Vec<_>>().join(", ")
+            ),
+        })?;
+        if let Some(system) = &variant.system {
+            config.system = system.clone();
+        }
+        if let Some(prompt) = &variant.prompt {
+            config.prompt = prompt.clone();
+        }
+    }
+
+    if config.temperature < 0.0 || config.temperature > 2.0 {
+        return Err(format!(
+            "Temperature must be between 0.0 and 2.0, got {}",
+            config.temperature
+        ));
+    }
+    if config.schema_via.is_some() && config.schema.is_none() {
+        return Err(format!("Prompt file '{}' sets schema_via but has no schema", path));
+    }
+
+    Ok(config)
+}
+
+/// The variant for `locale`, or for its language alone; codes match regardless of case and
+/// of `-` or `_`.
+fn find_locale<'a>(locales: &'a BTreeMap<String, LocalePrompt>, locale: &str) -> Option<&'a LocalePrompt> {
+    let normalize = |code: &str| code.trim().replace('_', "-").to_ascii_lowercase();
+    let wanted = normalize(locale);
+    let language = wanted.split('-').next().unwrap_or_default().to_string();
+    let find = |code: &str| locales.iter().find(|(key, _)| normalize(key) == code).map(|(_, variant)| variant);
+    find(&wanted).or_else(|| find(&language))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn fixtures_dir() -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")
+    }
+
+    #[test]
+    fn test_load_valid_prompt_config() {
+        let path = fixtures_dir().join("test-prompt.json");
+        let config = load_prompt_config(path.to_str().unwrap()).unwrap();
+
+        assert_eq!(config.system, "You are a test assistant.");
+        assert_eq!(config.prompt, "Describe this image.");
+        assert!((config.temperature - 0.5).abs() < f32::EPSILON);
+    }
+
+    #[test]
+    fn test_load_invalid_prompt_config_missing_fields() {
+        let path = fixtures_dir().join("invalid-prompt.json");
+        let result = load_prompt_config(path.to_str().unwrap());
+
+        assert!(result.is_err());
+        assert!(result.unwrap_err().contains("Failed to parse"));
+    }
+
+    #[test]
+    fn test_load_nonexistent_prompt_config() {
+        let result = load_prompt_config("/nonexistent/path/config.json");
+
+        assert!(result.is_err());
+        assert!(result.unwrap_err().contains("Failed to read"));
+    }
+
+    #[test]
+    fn test_load_prompt_config_invalid_temperature() {
+        // Create a temp file with invalid temperature
+        let temp_dir = std::env::temp_dir();
+        let temp_file = temp_dir.join("invalid_temp_config.json");
+        fs::write(&temp_file, r#"{"system": "test", "prompt": "test", "temperature": 3.0}"#).unwrap();
+
+        let result = load_prompt_config(temp_file.to_str().unwrap());
+        assert!(result.is_err());
+        assert!(result.unwrap_err().contains("Temperature must be between"));
+
+        fs::remove_file(temp_file).ok();
+    }
+
+    #[test]
+    fn test_load_prompt_config_schema_via() {
+        let temp_file = std::env::temp_dir().join(format!("schema_via_config_{}.json", std::process::id()));
+        fs::write(&temp_file, r#"{"system": "", "prompt": "p", "temperature": 0, "schema_via": "tool"}"#).unwrap();
+        let result = load_prompt_config(temp_file.to_str().unwrap());
+        assert!(result.unwrap_err().contains("has no schema"));
+
+        fs::write(&temp_file, r#"{"system": "", "prompt": "p", "temperature": 0, "schema": {"type": "object"}, "schema_via": "tool"}"#).unwrap();
+        let config = load_prompt_config(temp_file.to_str().unwrap()).unwrap();
+        assert_eq!(config.schema_via, Some(SchemaVia::Tool));
+
+        fs::remove_file(temp_file).ok();
+    }
+
+    #[test]
+    fn test_load_localized_prompt_config() {
+        let temp_file = std::env::temp_dir().join(format!("locales_config_{}.json", std::process::id()));
+        let path = temp_file.to_str().unwrap();
+        fs::write(
+            &temp_file,
+            r#"{"system": "You catalog photos.", "prompt": "Describe this photo.", "temperature": 0.2,
+                "locales": {"de": {"system": "Du katalogisierst Fotos.", "prompt": "Beschreibe dieses Foto."}, "pt_BR": {"prompt": "Descreva esta foto."}}}"#,
+        )
+        .unwrap();
+
+        let config = load_prompt_config(path).unwrap();
+        assert_eq!(config.prompt, "Describe this photo.");
+        let german = load_localized_prompt_config(path, Some("de-AT")).unwrap();
+        assert_eq!((german.system.as_str(), german.prompt.as_str()), ("Du katalogisierst Fotos.", "Beschreibe dieses Foto."));
+        let brazilian = load_localized_prompt_config(path, Some("pt-br")).unwrap();
+        assert_eq!((brazilian.system.as_str(), brazilian.prompt.as_str()), ("You catalog photos.", "Descreva esta foto."));
+        let missing = load_localized_prompt_config(path, Some("fr")).unwrap_err();
+        assert!(missing.contains("no 'fr' locale (it has de, pt_BR)"), "{}", missing);
+
+        fs::write(&temp_file, r#"{"system": "", "prompt": "p", "temperature": 0, "locales": {"de": {"promt": "x"}}}"#).unwrap();
+        assert!(load_prompt_config(path).unwrap_err().contains("unknown field"));
+        let test_prompt = fixtures_dir().join("test-prompt.json");
+        assert!(load_localized_prompt_config(test_prompt.to_str().unwrap(), Some("de")).unwrap_err().contains("has no locales"));
+
+        fs::remove_file(temp_file).ok();
+    }
+}
@@ -0,0 +1,222 @@
+//! Jobs and results for the queue consumer modes.
+//!
+//! A job message is either a JSON object naming an image and carrying metadata to pass
+//! through:
+//!
+//! ```json
+//! {"path": "/data/in/a.jpg", "metadata": {"upload_id": 42}}
+//! {"image": "<base64>", "name": "a.jpg", "metadata": {"upload_id": 42}}
+//! ```
+//!
+//! or the raw image bytes, named by the message key. Each job gets one result message:
+//! `{"status": "ok", "file", "metadata", "record"}`, or `{"status": "error", "file",
+//! "metadata", "error"}` when the job was malformed or the image couldn't be described.
+
+#[cfg(any(feature = "kafka", feature = "redis"))]
+use std::time::Duration;
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use serde::{Deserialize, Serialize};
+
+#[cfg(any(feature = "kafka", feature = "redis"))]
+use nineladies::{BatchRunner, CallError, ItemError};
+use nineladies::OutputRecord;
+
+/// How long to wait before retrying a job while the model server is down
+#[cfg(any(feature = "kafka", feature = "redis"))]
+pub const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Name for raw image jobs without a message key
+const DEFAULT_JOB_NAME: &str = "message";
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JobJson {
+    path: Option<String>,
+    /// Base64 image bytes
+    image: Option<String>,
+    name: Option<String>,
+    #[serde(default)]
+    metadata: serde_json::Value,
+}
+
+/// An image to describe, and what to copy into its result.
+#[derive(Debug, PartialEq)]
+pub struct Job {
+    pub file: String,
+    /// The image bytes, or `None` to read `file` from disk
+    pub data: Option<Vec<u8>>,
+    pub metadata: serde_json::Value,
+}
+
+/// Parse a job message. Anything that isn't a JSON object is taken as image bytes.
+pub fn parse_job(payload: &[u8], key: Option<&[u8]>) -> Result<Job, String> {
+    let key_name = key.filter(|k| !k.is_empty()).map(|k| String::from_utf8_lossy(k).into_owned());
+    if !payload.trim_ascii_start().starts_with(b"{") {
+        return Ok(Job {
+            file: key_name.unwrap_or_else(|| DEFAULT_JOB_NAME.to_string()),
+            data: Some(payload.to_vec()),
+            metadata: serde_json::Value::Null,
+        });
+    }
+
+    let job: JobJson = serde_json::from_slice(payload).map_err(|e| format!("Invalid job message: {}", e))?;
+    match (job.path, job.image) {
+        (Some(path), None) => Ok(Job {
+            file: path,
+            data: None,
+            metadata: job.metadata,
+        }),
+        (None, Some(image)) => Ok(Job {
+            file: job.name.or(key_name).unwrap_or_else(|| DEFAULT_JOB_NAME.to_string()),
+            data: Some(
+                BASE64
+                    .decode(image.trim())
+                    .map_err(|e| format!("'image' is not valid base64: {}", e))?,
+            ),
+            metadata: job.metadata,
+        }),
+        _ => Err("Job needs exactly one of 'path' or 'image'".to_string()),
+    }
+}
+
+#[derive(Serialize)]
+struct JobResult<'a> {
+    status: &'static str,
+    file: &'a str,
+    #[serde(skip_serializing_if = "serde_json::Value::is_null")]
+    metadata: &'a serde_json::Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    record: Option<&'a OutputRecord>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'a str>,
+}
+
+/// The result message for a job.
+pub fn result_message(file: &str, metadata: &serde_json::Value, outcome: Result<&OutputRecord, &str>) -> String {
+    let result = JobResult {
+        status: if outcome.is_ok() { "ok" } else { "error" },
+        file,
+        metadata,
+        record: outcome.ok(),
+        error: outcome.err(),
+    };
+    serde_json::to_string(&result).expect("results serialize")
+}
+
+/// An error result for a job, with the file and metadata from the message when it parses.
+pub fn failure_message(payload: &[u8], key: Option<&[u8]>, error: &str) -> String {
+    match parse_job(payload, key) {
+        Ok(job) => result_message(&job.file, &job.metadata, Err(error)),
+        Err(_) => {
+            let file = key.map(String::from_utf8_lossy).unwrap_or_default();
+            result_message(&file, &serde_json::Value::Null, Err(error))
+        }
+    }
+}
+
+/// What became of a job message. Each variant but `Unreachable` carries the result to publish.
+#[cfg(any(feature = "kafka", feature = "redis"))]
+#[derive(Debug, PartialEq)]
+pub enum Outcome {
+    Described(String),
+    /// The job can't succeed: a malformed message, an unreadable image, or one a script rejected
+    Rejected(String),
+    /// The model request failed, and trying again might work
+    Failed(String),
+    /// The model server is down; leave the job on the queue and try again later
+    Unreachable(String),
+}
+
+/// Describe the image a job message names.
+#[cfg(any(feature = "kafka", feature = "redis"))]
+pub fn process(runner: &BatchRunner, payload: &[u8], key: Option<&[u8]>) -> Outcome {
+    let job = match parse_job(payload, key) {
+        Ok(job) => job,
+        Err(e) => return Outcome::Rejected(failure_message(payload, key, &e)),
+    };
+
+    let described = match &job.data {
+        Some(data) => runner.describe_data(&job.file, data),
+        None => runner.describe(&job.file),
+    };
+    let failure = |e: &ItemError| result_message(&job.file, &job.metadata, Err(&e.to_string()));
+    match described {
+        Ok(described) => Outcome::Described(result_message(&job.file, &job.metadata, Ok(&described.record))),
+        Err(ItemError::Call(CallError::Unreachable(e))) => Outcome::Unreachable(e),
+        Err(e @ ItemError::Call(_)) => Outcome::Failed(failure(&e)),
+        Err(e) => Outcome::Rejected(failure(&e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(any(feature = "kafka", feature = "redis"))]
+    use nineladies::{Api, Backend, PromptConfig};
+
+    #[test]
+    fn test_parse_job() {
+        let job = parse_job(br#"{"path": "a.jpg", "metadata": {"id": 7}}"#, None).unwrap();
+        assert_eq!(job.file, "a.jpg");
+        assert_eq!(job.data, None);
+        assert_eq!(job.metadata, serde_json::json!({"id": 7}));
+
+        let job = parse_job(br#"{"image": "aGk="}"#, Some(b"b.png")).unwrap();
+        assert_eq!(job.file, "b.png");
+        assert_eq!(job.data.as_deref(), Some(&b"hi"[..]));
+
+        let job = parse_job(b"\xff\xd8\xff\xe0", Some(b"")).unwrap();
+        assert_eq!(job.file, DEFAULT_JOB_NAME);
+        assert_eq!(job.metadata, serde_json::Value::Null);
+
+        assert!(parse_job(br#"{"path": "a.jpg", "image": "aGk="}"#, None).is_err());
+        assert!(parse_job(br#"{"path": "a.jpg", "colour": "red"}"#, None).is_err());
+    }
+
+    #[test]
+    fn test_result_message() {
+        let record = OutputRecord::new("a.jpg", "", serde_json::json!("A cat"));
+        let ok: serde_json::Value =
+            serde_json::from_str(&result_message("a.jpg", &serde_json::json!({"id": 7}), Ok(&record))).unwrap();
+        assert_eq!(ok["status"], "ok");
+        assert_eq!(ok["metadata"]["id"], 7);
+        assert_eq!(ok["record"]["response"], "A cat");
+        assert!(ok.get("error").is_none());
+
+        assert_eq!(
+            result_message("a.jpg", &serde_json::Value::Null, Err("File not found: a.jpg")),
+            r#"{"status":"error","file":"a.jpg","error":"File not found: a.jpg"}"#
+        );
+        assert_eq!(
+            failure_message(br#"{"path": "b.jpg", "metadata": 1}"#, None, "Timed out"),
+            r#"{"status":"error","file":"b.jpg","metadata":1,"error":"Timed out"}"#
+        );
+    }
+
+    #[cfg(any(feature = "kafka", feature = "redis"))]
+    #[test]
+    fn test_process_outcomes() {
+        let config = PromptConfig {
+            system: String::new(),
+            prompt: "Describe this.".to_string(),
+            temperature: 0.0,
+            model: None,
+            schema: None,
+            seed: None,
+            think: None,
+            schema_via: None,
+        };
+        // Nothing listens on port 9
+        let runner = BatchRunner::new(Backend::new(Api::Ollama, "http://127.0.0.1:9", None, Some(1)), config);
+
+        let Outcome::Rejected(missing) = process(&runner, br#"{"path": "missing.png"}"#, None) else {
+            panic!("a missing file can't be retried");
+        };
+        assert!(missing.contains(r#""status":"error""#), "{}", missing);
+
+        let png = BASE64.encode(b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR");
+        let job = format!(r#"{{"image": "{}", "name": "a.png"}}"#, png);
+        assert!(matches!(process(&runner, job.as_bytes(), None), Outcome::Unreachable(_)));
+    }
+}
@@ -0,0 +1,260 @@
+//! Output records, the JSONL lines a run writes.
+
+use std::fs;
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::barcode::Barcode;
+use crate::confidence::Confidence;
+use crate::exif::ExifData;
+use crate::grounding::Detection;
+use crate::image_stats::ImageStats;
+use crate::images::ImageInfo;
+use crate::labels::LabelScore;
+use crate::latency::Timing;
+use crate::nsfw::NsfwVerdict;
+use crate::paths;
+
+/// One line of output: an image and the model's response, plus what was done with it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OutputRecord {
+    /// The image path as it was given
+    pub file: String,
+    /// Hex SHA-256 of the image bytes; empty in files written before it was recorded
+    #[serde(default)]
+    pub sha256: String,
+    /// Parsed JSON when the model replied with JSON, otherwise the reply text
+    pub response: serde_json::Value,
+    /// The second image, for records from `9ladies compare`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compared_with: Option<String>,
+    /// Seconds into the video, for frame records from `9ladies video`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frame_time: Option<f64>,
+    /// How many frame descriptions a `9ladies video` summary merges
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frames: Option<usize>,
+    /// The product, for records from `9ladies products`; `file` is its first shot
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sku: Option<String>,
+    /// Every shot a `9ladies products` description was made from, in order
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shots: Option<Vec<String>>,
+    /// Barcodes and QR codes read locally with `--barcodes`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub barcodes: Option<Vec<Barcode>>,
+    /// Width, height, format and file size, with `--image-info`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image: Option<ImageInfo>,
+    /// Size, colors and sharpness, with `--image-stats`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_stats: Option<ImageStats>,
+    /// Capture time, camera and location, with `--exif`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exif: Option<ExifData>,
+    /// Boxes parsed from the response with `--detections`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detections: Option<Vec<Detection>>,
+    /// The NSFW classifier's score and what was done about it, with `--nsfw-command`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nsfw: Option<NsfwVerdict>,
+    /// Faces blurred in the copy sent to the model, with `--blur-faces`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub faces_blurred: Option<usize>,
+    /// The smaller copy sent in place of an image over `--max-megapixels` or `--max-image-mb`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resized: Option<ImageInfo>,
+    /// Text read locally with `--ocr` and given to the model
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ocr_text: Option<String>,
+    /// The `{stem}.9ladies.json` file whose overrides went into this image's request
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sidecar: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub renamed_to: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub moved_to: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub copied_to: Option<String>,
+    /// The copy with detections drawn on, from `--annotate-dir`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub annotated: Option<String>,
+    /// The likeliest labels, most likely first, with `--labels` and `--top-k`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_labels: Option<Vec<LabelScore>>,
+    /// Every answer with `--candidates`, the first being `response`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub candidates: Option<Vec<serde_json::Value>>,
+    /// How far to trust the response, with `--confidence`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub confidence: Option<Confidence>,
+    /// The reply was cut off by the timeout or a stall and `response` is what had arrived,
+    /// with `--keep-partial`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub truncated: Option<bool>,
+    /// The JSON response was taken out of a reply with other text around it, such as a
+    /// Markdown fence or a sentence introducing it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub json_extracted: Option<bool>,
+    /// What a reasoning model thought before replying, kept out of `response`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thinking: Option<String>,
+    /// The language the response is in, as an ISO 639-1 code, with `--language`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    /// The vector from `--mode embed`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub embedding: Option<Vec<f32>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timing: Option<Timing>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub review: Option<ReviewStatus>,
+}
+
+/// Where per-image metadata (`--image-stats`, `--exif`) goes.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum ContextMode {
+    /// Into each record
+    Record,
+    /// Into each record, and summarized in the question
+    Prompt,
+}
+
+/// What `--mode embed` embeds.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum EmbedSource {
+    /// The model's description of the image
+    Caption,
+    /// The image itself, with a multimodal embedding model; nothing is described
+    Image,
+}
+
+/// The outcome of `9ladies review` for a record.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReviewStatus {
+    Accepted,
+    Edited,
+    Flagged,
+}
+
+impl OutputRecord {
+    pub fn new(file: &str, sha256: &str, response: serde_json::Value) -> Self {
+        OutputRecord {
+            file: paths::normalize(file),
+            sha256: sha256.to_string(),
+            response,
+            compared_with: None,
+            frame_time: None,
+            frames: None,
+            sku: None,
+            shots: None,
+            barcodes: None,
+            image: None,
+            image_stats: None,
+            exif: None,
+            detections: None,
+            nsfw: None,
+            faces_blurred: None,
+            resized: None,
+            ocr_text: None,
+            sidecar: None,
+            renamed_to: None,
+            moved_to: None,
+            copied_to: None,
+            annotated: None,
+            top_labels: None,
+            candidates: None,
+            confidence: None,
+            truncated: None,
+            json_extracted: None,
+            thinking: None,
+            language: None,
+            embedding: None,
+            timing: None,
+            review: None,
+        }
+    }
+
+    /// Where the image is now, after any rename or move.
+    pub fn current_path(&self) -> &str {
+        self.moved_to.as_deref().or(self.renamed_to.as_deref()).unwrap_or(&self.file)
+    }
+}
+
+/// Read output records from a JSONL file written by a previous run.
+pub fn read_output_records(path: &str) -> Result<Vec<OutputRecord>, String> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read output file '{}': {}", path, e))?;
+
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            serde_json::from_str(line)
+                .map_err(|e| format!("Invalid record on line {} of '{}': {}", i + 1, path, e))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_output_record_with_string_response() {
+        let record = OutputRecord::new("test.jpg", "abc123", serde_json::Value::String("A red image".to_string()));
+
+        let json = serde_json::to_string(&record).unwrap();
+        assert!(json.contains("\"file\":\"test.jpg\""));
+        assert!(json.contains("\"sha256\":\"abc123\""));
+        assert!(json.contains("\"response\":\"A red image\""));
+    }
+
+    #[test]
+    fn test_key_order() {
+        let mut record = OutputRecord::new("a.jpg", "abc", serde_json::from_str(r#"{"total": 2, "items": [{"price": 1, "name": "milk"}]}"#).unwrap());
+        record.timing = Some(Timing::default());
+        record.truncated = Some(true);
+        assert_eq!(
+            serde_json::to_string(&record).unwrap(),
+            r#"{"file":"a.jpg","sha256":"abc","response":{"total":2,"items":[{"price":1,"name":"milk"}]},"truncated":true,"timing":{"wall_ms":0}}"#
+        );
+
+        record.response.sort_all_objects();
+        assert_eq!(record.response.to_string(), r#"{"items":[{"name":"milk","price":1}],"total":2}"#);
+    }
+
+    #[test]
+    fn test_output_record_with_json_response() {
+        let record = OutputRecord::new("test.jpg", "abc123", serde_json::json!({"barcode": true, "ingredients": false}));
+
+        let json = serde_json::to_string(&record).unwrap();
+        assert!(json.contains("\"file\":\"test.jpg\""));
+        assert!(json.contains("\"barcode\":true"));
+    }
+
+    #[test]
+    fn test_read_output_records() {
+        let temp_file = std::env::temp_dir().join("nineladies_read_records.jsonl");
+        fs::write(
+            &temp_file,
+            "{\"file\":\"a.jpg\",\"sha256\":\"abc\",\"response\":\"A cat\"}\n\n{\"file\":\"b.jpg\",\"response\":{\"n\":1}}\n",
+        )
+        .unwrap();
+
+        let records = read_output_records(temp_file.to_str().unwrap()).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].sha256, "abc");
+        assert_eq!(records[1].file, "b.jpg");
+        assert_eq!(records[1].sha256, "");
+
+        fs::write(&temp_file, "{\"file\":\"a.jpg\"}\nnot json\n").unwrap();
+        let err = read_output_records(temp_file.to_str().unwrap()).unwrap_err();
+        assert!(err.contains("line 1"));
+
+        fs::remove_file(temp_file).ok();
+    }
+}
@@ -0,0 +1,398 @@
+//! `9ladies redis`: take jobs from a Redis list and write a result for each to a list or
+//! stream, as a lighter-weight alternative to a message broker.
+//!
+//! Producers `LPUSH` job messages (see `queue`) onto the queue list. A worker moves each job
+//! onto `<queue>:processing` as it takes it, giving it an ID from `<queue>:ids` that it keeps
+//! until it is finished, and records a deadline in `<queue>:claims` and an attempt count in
+//! `<queue>:attempts` under that ID. Taking, requeueing and finishing a job each run as one
+//! script, and requeueing or finishing only go ahead while the job is still in the processing
+//! list, so a job requeued by two workers at once isn't doubled and a worker that finishes
+//! after its job was requeued doesn't write a second result.
+//!
+//! A job still in the processing list after the visibility timeout belonged to a worker
+//! that died or hung; any worker puts it back on the queue. Failed model requests are
+//! retried too, and a job that runs out of attempts gets an error result and is moved to
+//! `<queue>:failed`.
+
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use redis::Connection;
+use tracing::{debug, info, warn};
+
+use nineladies::BatchRunner;
+
+use crate::queue::{self, Outcome};
+use crate::shutdown::Shutdown;
+
+/// How long a wait for a job blocks, so a shutdown is noticed promptly
+const POP_TIMEOUT_SECS: u64 = 1;
+
+/// How often the processing list is checked for jobs past their visibility timeout
+const REAP_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Where jobs come from and results go, and how hard to try.
+pub struct RedisOptions {
+    pub queue: String,
+    pub results: String,
+    /// Append results to a stream with `XADD` rather than a list with `RPUSH`
+    pub stream: bool,
+    pub visibility_timeout: Duration,
+    pub max_attempts: u64,
+}
+
+/// The keys a queue uses.
+#[derive(Debug, PartialEq)]
+struct Keys {
+    queue: String,
+    processing: String,
+    claims: String,
+    attempts: String,
+    failed: String,
+    ids: String,
+}
+
+impl Keys {
+    fn new(queue: &str) -> Keys {
+        Keys {
+            queue: queue.to_string(),
+            processing: format!("{}:processing", queue),
+            claims: format!("{}:claims", queue),
+            attempts: format!("{}:attempts", queue),
+            failed: format!("{}:failed", queue),
+            ids: format!("{}:ids", queue),
+        }
+    }
+}
+
+/// Starts a job taken from the queue, followed by its ID and a newline, then the message.
+/// A job put back on the queue keeps it, so its attempts go on being counted under the same
+/// ID, while two identical messages from a producer are told apart.
+const JOB_PREFIX: &str = "9ladies-job:";
+
+/// Move the next job to the processing list, giving it an ID unless it has one, and claim
+/// it. Returns the job as stored there and its attempt number, or nothing if the queue is
+/// empty.
+const TAKE_SCRIPT: &str = r"
+local element = redis.call('RPOP', KEYS[1])
+if not element then return false end
+local prefix = ARGV[2]
+local id
+if string.sub(element, 1, #prefix) == prefix then
+    local newline = string.find(element, '\n', #prefix + 1, true)
+    if newline then id = string.sub(element, #prefix + 1, newline - 1) end
+end
+if not id then
+    id = tostring(redis.call('INCR', KEYS[5]))
+    element = prefix .. id .. '\n' .. element
+end
+redis.call('LPUSH', KEYS[2], element)
+redis.call('HSET', KEYS[3], id, ARGV[1])
+return {element, redis.call('HINCRBY', KEYS[4], id, 1)}
+";
+
+/// Put a job back on the queue if it is still in the processing list, with ARGV[3] the push
+/// command for the end to put it on; with ARGV[4] set, the attempt isn't counted.
+const RELEASE_SCRIPT: &str = r"
+if redis.call('LREM', KEYS[1], 1, ARGV[1]) == 0 then return 0 end
+redis.call(ARGV[3], KEYS[2], ARGV[1])
+redis.call('HDEL', KEYS[3], ARGV[2])
+if ARGV[4] == '1' then redis.call('HINCRBY', KEYS[4], ARGV[2], -1) end
+return 1
+";
+
+/// Write a job's result and drop it, if it is still in the processing list; with ARGV[6]
+/// set, the message in ARGV[5] is kept in the failed list.
+const FINISH_SCRIPT: &str = r"
+if redis.call('LREM', KEYS[1], 1, ARGV[1]) == 0 then return 0 end
+if ARGV[4] == '1' then
+    redis.call('XADD', KEYS[2], '*', 'result', ARGV[3])
+else
+    redis.call('RPUSH', KEYS[2], ARGV[3])
+end
+if ARGV[6] == '1' then redis.call('LPUSH', KEYS[3], ARGV[5]) end
+redis.call('HDEL', KEYS[4], ARGV[2])
+redis.call('HDEL', KEYS[5], ARGV[2])
+return 1
+";
+
+/// A job as it sits in the processing list.
+#[derive(Debug, PartialEq)]
+struct Job {
+    /// The stored element: the prefix, ID and message
+    element: Vec<u8>,
+    id: String,
+    /// The message the producer sent
+    payload: Vec<u8>,
+}
+
+impl Job {
+    /// Split an element of the processing list; `None` for one without an ID.
+    fn parse(element: Vec<u8>) -> Option<Job> {
+        let rest = element.strip_prefix(JOB_PREFIX.as_bytes())?;
+        let newline = rest.iter().position(|&b| b == b'\n')?;
+        let id = String::from_utf8(rest[..newline].to_vec()).ok()?;
+        let payload = rest[newline + 1..].to_vec();
+        Some(Job { element, id, payload })
+    }
+}
+
+/// What happens to a job once it has been tried, or has timed out.
+#[derive(Debug, PartialEq)]
+enum Next {
+    /// Write the result and drop the job; `dead` keeps it in the failed list
+    Finish { result: String, dead: bool },
+    /// Put it back on the queue, at the end taken next when `front`
+    Retry { front: bool },
+    /// The model server is down: put it back at the front without counting the attempt,
+    /// and wait before taking another
+    Wait(String),
+}
+
+/// What to do with a job after attempt number `attempt` ended in `outcome`.
+fn after_attempt(outcome: Outcome, attempt: u64, max_attempts: u64) -> Next {
+    match outcome {
+        Outcome::Described(result) | Outcome::Rejected(result) => Next::Finish { result, dead: false },
+        Outcome::Failed(result) if attempt >= max_attempts => Next::Finish { result, dead: true },
+        Outcome::Failed(_) => Next::Retry { front: false },
+        // Not the job's fault, so the attempt doesn't count
+        Outcome::Unreachable(e) => Next::Wait(e),
+    }
+}
+
+/// What to do with a job held past the visibility timeout after `attempts` attempts.
+fn after_timeout(payload: &[u8], attempts: u64, max_attempts: u64) -> Next {
+    match attempts >= max_attempts {
+        true => {
+            let error = format!("Not finished within the visibility timeout after {} attempts", attempts);
+            Next::Finish {
+                result: queue::failure_message(payload, None, &error),
+                dead: true,
+            }
+        }
+        false => Next::Retry { front: true },
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+fn redis_error(e: redis::RedisError) -> String {
+    format!("Redis command failed: {}", e)
+}
+
+struct Worker<'a> {
+    connection: Connection,
+    keys: Keys,
+    options: &'a RedisOptions,
+}
+
+impl Worker<'_> {
+    /// Carry out `next` for a job. Returns false if the job had already left the processing
+    /// list, requeued by another worker, so nothing was done.
+    fn settle(&mut self, job: &Job, next: &Next) -> Result<bool, String> {
+        let done: u64 = match next {
+            Next::Finish { result, dead } => redis::cmd("EVAL")
+                .arg(FINISH_SCRIPT)
+                .arg(5)
+                .arg(&self.keys.processing)
+                .arg(&self.options.results)
+                .arg(&self.keys.failed)
+                .arg(&self.keys.claims)
+                .arg(&self.keys.attempts)
+                .arg(&job.element)
+                .arg(&job.id)
+                .arg(result)
+                .arg(if self.options.stream { "1" } else { "0" })
+                .arg(&job.payload)
+                .arg(if *dead { "1" } else { "0" })
+                .query(&mut self.connection),
+            Next::Retry { .. } | Next::Wait(_) => {
+                let front = matches!(next, Next::Retry { front: true } | Next::Wait(_));
+                redis::cmd("EVAL")
+                    .arg(RELEASE_SCRIPT)
+                    .arg(4)
+                    .arg(&self.keys.processing)
+                    .arg(&self.keys.queue)
+                    .arg(&self.keys.claims)
+                    .arg(&self.keys.attempts)
+                    .arg(&job.element)
+                    .arg(&job.id)
+                    .arg(if front { "RPUSH" } else { "LPUSH" })
+                    .arg(if matches!(next, Next::Wait(_)) { "1" } else { "0" })
+                    .query(&mut self.connection)
+            }
+        }
+        .map_err(redis_error)?;
+        Ok(done == 1)
+    }
+
+    /// Wait up to a second for a job, claiming it when one arrives. Returns the job and its
+    /// attempt number.
+    fn take(&mut self) -> Result<Option<(Job, u64)>, String> {
+        if let Some(taken) = self.try_take()? {
+            return Ok(Some(taken));
+        }
+        // Moving the list's last job onto its own end leaves it as it was, but blocks until
+        // there is one
+        let _: Option<Vec<u8>> = redis::cmd("BLMOVE")
+            .arg(&self.keys.queue)
+            .arg(&self.keys.queue)
+            .arg("RIGHT")
+            .arg("RIGHT")
+            .arg(POP_TIMEOUT_SECS)
+            .query(&mut self.connection)
+            .map_err(redis_error)?;
+        self.try_take()
+    }
+
+    fn try_take(&mut self) -> Result<Option<(Job, u64)>, String> {
+        let deadline = now_secs() + self.options.visibility_timeout.as_secs();
+        let taken: Option<(Vec<u8>, u64)> = redis::cmd("EVAL")
+            .arg(TAKE_SCRIPT)
+            .arg(5)
+            .arg(&self.keys.queue)
+            .arg(&self.keys.processing)
+            .arg(&self.keys.claims)
+            .arg(&self.keys.attempts)
+            .arg(&self.keys.ids)
+            .arg(deadline)
+            .arg(JOB_PREFIX)
+            .query(&mut self.connection)
+            .map_err(redis_error)?;
+        Ok(taken.and_then(|(element, attempt)| Job::parse(element).map(|job| (job, attempt))))
+    }
+
+    /// Requeue jobs whose workers held them past the visibility timeout.
+    fn reap(&mut self) -> Result<(), String> {
+        let processing: Vec<Vec<u8>> = redis::cmd("LRANGE")
+            .arg(&self.keys.processing)
+            .arg(0)
+            .arg(-1)
+            .query(&mut self.connection)
+            .map_err(redis_error)?;
+        let now = now_secs();
+
+        for job in processing.into_iter().filter_map(Job::parse) {
+            let (deadline, attempts): (Option<u64>, Option<u64>) = redis::pipe()
+                .cmd("HGET")
+                .arg(&self.keys.claims)
+                .arg(&job.id)
+                .cmd("HGET")
+                .arg(&self.keys.attempts)
+                .arg(&job.id)
+                .query(&mut self.connection)
+                .map_err(redis_error)?;
+            // A job without a claim was finished or requeued since the list was read
+            if deadline.is_none_or(|deadline| deadline > now) {
+                continue;
+            }
+            let attempts = attempts.unwrap_or(0);
+            let next = after_timeout(&job.payload, attempts, self.options.max_attempts);
+            match &next {
+                Next::Finish { .. } => {
+                    warn!(id = job.id.as_str(), attempts, "Job timed out on its last attempt; moving it to failed")
+                }
+                _ => warn!(id = job.id.as_str(), attempts, "Job passed its visibility timeout; requeueing it"),
+            }
+            if !self.settle(&job, &next)? {
+                debug!(id = job.id.as_str(), "Job already requeued by another worker");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Take jobs until a shutdown is requested.
+pub fn consume(runner: &BatchRunner, url: &str, options: &RedisOptions, shutdown: &Shutdown) -> Result<(), String> {
+    let connection = redis::Client::open(url)
+        .and_then(|client| client.get_connection())
+        .map_err(|e| format!("Failed to connect to Redis at {}: {}", url, e))?;
+    let mut worker = Worker {
+        connection,
+        keys: Keys::new(&options.queue),
+        options,
+    };
+    info!(
+        queue = options.queue.as_str(),
+        results = options.results.as_str(),
+        "Taking jobs from Redis"
+    );
+
+    let mut last_reap: Option<Instant> = None;
+    while !shutdown.requested() {
+        if last_reap.is_none_or(|t| t.elapsed() >= REAP_INTERVAL) {
+            worker.reap()?;
+            last_reap = Some(Instant::now());
+        }
+
+        let Some((job, attempt)) = worker.take()? else {
+            continue;
+        };
+        let id = job.id.as_str();
+        debug!(id, attempt, "Job taken");
+        let next = after_attempt(queue::process(runner, &job.payload, None), attempt, options.max_attempts);
+        match &next {
+            Next::Finish { dead: true, .. } => {
+                warn!(id, attempt, "Job failed on its last attempt; moving it to the failed list")
+            }
+            Next::Finish { .. } => {}
+            Next::Retry { .. } => warn!(id, attempt, "Job failed; returning it to the queue to retry"),
+            Next::Wait(e) => warn!("{}; retrying in {}s", e, queue::RETRY_DELAY.as_secs()),
+        }
+        if !worker.settle(&job, &next)? {
+            warn!(id, attempt, "Job was requeued after its visibility timeout; dropping this worker's result");
+        }
+        if matches!(next, Next::Wait(_)) {
+            shutdown.wait(queue::RETRY_DELAY);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keys() {
+        let keys = Keys::new("images");
+        assert_eq!(keys.processing, "images:processing");
+        assert_eq!(keys.claims, "images:claims");
+        assert_eq!(keys.attempts, "images:attempts");
+        assert_eq!(keys.failed, "images:failed");
+        assert_eq!(keys.ids, "images:ids");
+    }
+
+    #[test]
+    fn test_job_parse() {
+        let job = Job::parse(b"9ladies-job:17\n{\"path\": \"a\nb.jpg\"}".to_vec()).unwrap();
+        assert_eq!((job.id.as_str(), job.payload.as_slice()), ("17", &b"{\"path\": \"a\nb.jpg\"}"[..]));
+        assert_eq!(Job::parse(b"{\"path\": \"a.jpg\"}".to_vec()), None);
+        assert_eq!(Job::parse(b"9ladies-job:17".to_vec()), None);
+    }
+
+    #[test]
+    fn test_after_attempt() {
+        let finish = |result: &str, dead| Next::Finish {
+            result: result.to_string(),
+            dead,
+        };
+        assert_eq!(after_attempt(Outcome::Described("ok".into()), 1, 3), finish("ok", false));
+        assert_eq!(after_attempt(Outcome::Rejected("bad".into()), 1, 3), finish("bad", false));
+        assert_eq!(after_attempt(Outcome::Failed("err".into()), 2, 3), Next::Retry { front: false });
+        assert_eq!(after_attempt(Outcome::Failed("err".into()), 3, 3), finish("err", true));
+        // Even on the last attempt, a server that is down doesn't use the job up
+        assert_eq!(after_attempt(Outcome::Unreachable("down".into()), 3, 3), Next::Wait("down".to_string()));
+    }
+
+    #[test]
+    fn test_after_timeout() {
+        let payload = br#"{"path": "a.jpg"}"#;
+        assert_eq!(after_timeout(payload, 2, 3), Next::Retry { front: true });
+        let Next::Finish { result, dead: true } = after_timeout(payload, 3, 3) else {
+            panic!("a job out of attempts is finished as failed");
+        };
+        assert!(result.contains("visibility timeout after 3 attempts"), "{}", result);
+    }
+}
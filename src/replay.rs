@@ -0,0 +1,134 @@
+//! `--record` and `--replay`: keep the server's reply to each chat request as a fixture file,
+//! and later answer the same requests from those files without a model server, so prompt
+//! pipelines, exports and reports can be worked on offline and give the same output every
+//! time.
+//!
+//! A fixture is named by the SHA-256 of the request body exactly as sent, images included,
+//! so a request only matches a reply to the same prompt, options, model and images. The
+//! file keeps the request itself with its images redacted, as traces do.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::images::sha256_hex;
+use crate::trace::redact_images;
+
+/// Whether fixtures are being written or read.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReplayMode {
+    Record,
+    Replay,
+}
+
+/// A folder of fixture files.
+#[derive(Debug)]
+pub struct Fixtures {
+    dir: PathBuf,
+    mode: ReplayMode,
+}
+
+/// One request and the reply to it.
+#[derive(Serialize, Deserialize)]
+struct Fixture {
+    url: String,
+    request: Value,
+    status: u16,
+    /// The response body exactly as received
+    response: String,
+}
+
+impl Fixtures {
+    /// Record into `dir`, creating it if needed.
+    pub fn record(dir: &Path) -> Result<Fixtures, String> {
+        fs::create_dir_all(dir).map_err(|e| format!("Failed to create fixture directory '{}': {}", dir.display(), e))?;
+        Ok(Fixtures {
+            dir: dir.to_path_buf(),
+            mode: ReplayMode::Record,
+        })
+    }
+
+    /// Replay from `dir`, which `record` filled.
+    pub fn replay(dir: &Path) -> Result<Fixtures, String> {
+        if !dir.is_dir() {
+            return Err(format!("Fixture directory '{}' not found (fill it with --record first)", dir.display()));
+        }
+        Ok(Fixtures {
+            dir: dir.to_path_buf(),
+            mode: ReplayMode::Replay,
+        })
+    }
+
+    pub fn mode(&self) -> ReplayMode {
+        self.mode
+    }
+
+    fn path(&self, request: &[u8]) -> PathBuf {
+        self.dir.join(format!("{}.json", &sha256_hex(request)[..16]))
+    }
+
+    /// Save the reply to the JSON body `request`, sent to `url` with `images` attached.
+    /// Returns the file written.
+    pub fn save(&self, url: &str, request: &[u8], images: &[&[u8]], status: u16, response: &str) -> Result<PathBuf, String> {
+        let path = self.path(request);
+        let mut redacted: Value =
+            serde_json::from_slice(request).map_err(|e| format!("Failed to record a request that isn't JSON: {}", e))?;
+        redact_images(&mut redacted, images);
+        let fixture = Fixture {
+            url: url.to_string(),
+            request: redacted,
+            status,
+            response: response.to_string(),
+        };
+        let json = serde_json::to_string_pretty(&fixture).expect("fixtures serialize");
+        fs::write(&path, json).map_err(|e| format!("Failed to write fixture '{}': {}", path.display(), e))?;
+        Ok(path)
+    }
+
+    /// The status and body recorded for the JSON body `request`.
+    pub fn load(&self, request: &[u8]) -> Result<(u16, String), String> {
+        let path = self.path(request);
+        let json = fs::read_to_string(&path).map_err(|_| {
+            format!(
+                "No recorded reply to this request in '{}' (record it with --record, using the same prompt, options and images)",
+                self.dir.display()
+            )
+        })?;
+        let fixture: Fixture =
+            serde_json::from_str(&json).map_err(|e| format!("Invalid fixture '{}': {}", path.display(), e))?;
+        Ok((fixture.status, fixture.response))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+    use serde_json::json;
+
+    #[test]
+    fn test_record_then_replay() {
+        let dir = std::env::temp_dir().join(format!("nineladies_replay_{}", std::process::id()));
+        let image: &[u8] = b"image bytes";
+        let request = |prompt: &str| {
+            let body = json!({"model": "llava", "messages": [{"role": "user", "content": prompt, "images": [BASE64.encode(image)]}]});
+            serde_json::to_vec(&body).unwrap()
+        };
+        assert!(Fixtures::replay(&dir).is_err());
+
+        let recorder = Fixtures::record(&dir).unwrap();
+        let path = recorder
+            .save("http://localhost:11434/api/chat", &request("Describe this."), &[image], 200, "{\"message\": {}}")
+            .unwrap();
+        let saved = fs::read_to_string(path).unwrap();
+        assert!(saved.contains("<image: 11 bytes, sha256"), "{}", saved);
+
+        let replayer = Fixtures::replay(&dir).unwrap();
+        assert_eq!(replayer.mode(), ReplayMode::Replay);
+        assert_eq!(replayer.load(&request("Describe this.")).unwrap(), (200, "{\"message\": {}}".to_string()));
+        assert!(replayer.load(&request("Describe that.")).unwrap_err().contains("No recorded reply"));
+        fs::remove_dir_all(dir).ok();
+    }
+}
@@ -0,0 +1,193 @@
+//! Summaries of existing output files for `9ladies report`.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::OutputRecord;
+
+/// How many of the most common values to list per field.
+const TOP_VALUES: usize = 5;
+
+/// Values longer than this are treated as free text and not tallied.
+const MAX_TALLY_LEN: usize = 40;
+
+#[derive(Debug, Default, Serialize)]
+pub struct Report {
+    pub records: usize,
+    pub unique_files: usize,
+    pub json_responses: usize,
+    pub text_responses: usize,
+    pub fields: BTreeMap<String, FieldSummary>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct FieldSummary {
+    pub count: usize,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub top_values: Vec<(String, usize)>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub numeric: Option<NumericSummary>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct NumericSummary {
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+}
+
+/// Tally record counts, response kinds, and for JSON object responses how often each
+/// top-level field appears along with its common values or numeric range.
+pub fn summarize(records: &[OutputRecord]) -> Report {
+    let mut report = Report {
+        records: records.len(),
+        ..Report::default()
+    };
+
+    let mut files = HashSet::new();
+    let mut tallies: HashMap<&str, HashMap<String, usize>> = HashMap::new();
+    let mut numbers: HashMap<&str, Vec<f64>> = HashMap::new();
+
+    for record in records {
+        files.insert(record.file.as_str());
+
+        let object = match &record.response {
+            serde_json::Value::String(_) => {
+                report.text_responses += 1;
+                continue;
+            }
+            serde_json::Value::Object(object) => object,
+            _ => {
+                report.json_responses += 1;
+                continue;
+            }
+        };
+        report.json_responses += 1;
+
+        for (key, value) in object {
+            report.fields.entry(key.clone()).or_default().count += 1;
+
+            let tally_value = match value {
+                serde_json::Value::String(s) if s.chars().count() <= MAX_TALLY_LEN => Some(s.clone()),
+                serde_json::Value::Bool(b) => Some(b.to_string()),
+                serde_json::Value::Number(n) => {
+                    if let Some(n) = n.as_f64() {
+                        numbers.entry(key).or_default().push(n);
+                    }
+                    None
+                }
+                _ => None,
+            };
+            if let Some(v) = tally_value {
+                *tallies.entry(key).or_default().entry(v).or_default() += 1;
+            }
+        }
+    }
+
+    report.unique_files = files.len();
+
+    for (key, counts) in tallies {
+        let mut top: Vec<(String, usize)> = counts.into_iter().collect();
+        top.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        top.truncate(TOP_VALUES);
+        report.fields.get_mut(key).expect("field was counted").top_values = top;
+    }
+
+    for (key, values) in numbers {
+        let min = values.iter().copied().fold(f64::INFINITY, f64::min);
+        let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        let mean = values.iter().sum::<f64>() / values.len() as f64;
+        report.fields.get_mut(key).expect("field was counted").numeric = Some(NumericSummary { min, max, mean });
+    }
+
+    report
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Records:        {}", self.records)?;
+        writeln!(f, "Unique files:   {}", self.unique_files)?;
+        writeln!(f, "JSON responses: {}", self.json_responses)?;
+        writeln!(f, "Text responses: {}", self.text_responses)?;
+
+        if self.fields.is_empty() {
+            return Ok(());
+        }
+
+        let width = self.fields.keys().map(|k| k.len()).max().unwrap_or(0);
+        writeln!(f, "Fields:")?;
+        for (key, field) in &self.fields {
+            write!(f, "  {:width$}  {:>6}", key, field.count, width = width)?;
+            if let Some(n) = &field.numeric {
+                write!(f, "  min {} / max {} / mean {:.2}", n.min, n.max, n.mean)?;
+            }
+            if !field.top_values.is_empty() {
+                let values: Vec<String> = field
+                    .top_values
+                    .iter()
+                    .map(|(value, count)| format!("{} ({})", value, count))
+                    .collect();
+                write!(f, "  {}", values.join(", "))?;
+            }
+            writeln!(f)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(file: &str, response: serde_json::Value) -> OutputRecord {
+        OutputRecord::new(file, "", response)
+    }
+
+    #[test]
+    fn test_summarize_counts_and_fields() {
+        let records = vec![
+            record("a.jpg", serde_json::json!({"barcode": true, "count": 2, "notes": "x"})),
+            record("b.jpg", serde_json::json!({"barcode": false, "count": 4})),
+            record("b.jpg", serde_json::json!({"barcode": true})),
+            record("c.jpg", serde_json::json!("A red square")),
+        ];
+
+        let report = summarize(&records);
+        assert_eq!(report.records, 4);
+        assert_eq!(report.unique_files, 3);
+        assert_eq!(report.json_responses, 3);
+        assert_eq!(report.text_responses, 1);
+
+        let barcode = &report.fields["barcode"];
+        assert_eq!(barcode.count, 3);
+        assert_eq!(barcode.top_values, vec![("true".to_string(), 2), ("false".to_string(), 1)]);
+
+        let count = report.fields["count"].numeric.as_ref().unwrap();
+        assert_eq!((count.min, count.max, count.mean), (2.0, 4.0, 3.0));
+
+        assert_eq!(report.fields["notes"].count, 1);
+    }
+
+    #[test]
+    fn test_summarize_skips_long_text_values() {
+        let long = "word ".repeat(20);
+        let records = vec![record("a.jpg", serde_json::json!({"description": long}))];
+
+        let report = summarize(&records);
+        assert_eq!(report.fields["description"].count, 1);
+        assert!(report.fields["description"].top_values.is_empty());
+    }
+
+    #[test]
+    fn test_report_display() {
+        let records = vec![record("a.jpg", serde_json::json!({"category": "receipt"}))];
+        let text = summarize(&records).to_string();
+
+        assert!(text.contains("Records:        1"));
+        assert!(text.contains("category"));
+        assert!(text.contains("receipt (1)"));
+    }
+}
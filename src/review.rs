@@ -0,0 +1,207 @@
+//! Stepping through an output file to accept, correct or flag records for `9ladies review`.
+
+use std::io::{self, BufRead, Write};
+
+use nineladies::{OutputRecord, ReviewStatus};
+
+const HELP: &str = "[a]ccept  [e]dit  [f]lag  [s]kip  [b]ack  [q]uit";
+
+#[derive(Debug, PartialEq)]
+enum Action {
+    Accept,
+    Edit,
+    Flag,
+    Skip,
+    Back,
+    Quit,
+}
+
+fn parse_action(line: &str) -> Option<Action> {
+    match line.trim().to_ascii_lowercase().as_str() {
+        "a" | "accept" => Some(Action::Accept),
+        "e" | "edit" => Some(Action::Edit),
+        "f" | "flag" => Some(Action::Flag),
+        "s" | "skip" | "" => Some(Action::Skip),
+        "b" | "back" => Some(Action::Back),
+        "q" | "quit" => Some(Action::Quit),
+        _ => None,
+    }
+}
+
+/// The side effects of a review session, so the loop itself can be tested without a
+/// terminal or an editor.
+pub trait ReviewHooks {
+    /// Draw whatever comes before the record text (clearing the screen, the image preview).
+    fn show(&mut self, record: &OutputRecord, out: &mut dyn Write) -> io::Result<()>;
+    /// Let the user change a response, returning the new one.
+    fn edit(&mut self, response: &serde_json::Value) -> Result<serde_json::Value, String>;
+    /// Persist the records after each decision, so quitting or a crash loses nothing.
+    fn save(&mut self, records: &[OutputRecord]) -> Result<(), String>;
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct ReviewSummary {
+    pub accepted: usize,
+    pub edited: usize,
+    pub flagged: usize,
+}
+
+/// Review the records at `queue` (indices into `records`) in order, reading one command
+/// per line from `input`.
+pub fn run_review<R: BufRead>(
+    records: &mut [OutputRecord],
+    queue: &[usize],
+    input: R,
+    out: &mut dyn Write,
+    hooks: &mut dyn ReviewHooks,
+) -> io::Result<ReviewSummary> {
+    let mut summary = ReviewSummary::default();
+    let mut lines = input.lines();
+    let mut position = 0;
+
+    while let Some(&index) = queue.get(position) {
+        let record = &records[index];
+        hooks.show(record, out)?;
+        writeln!(out, "[{}/{}] {}", position + 1, queue.len(), record.file)?;
+        if let Some(status) = record.review {
+            writeln!(out, "Already reviewed: {:?}", status)?;
+        }
+        writeln!(out, "{}", display_response(&record.response))?;
+
+        let action = loop {
+            write!(out, "{} > ", HELP)?;
+            out.flush()?;
+            let Some(line) = lines.next().transpose()? else {
+                return Ok(summary);
+            };
+            match parse_action(&line) {
+                Some(action) => break action,
+                None => writeln!(out, "Unknown command '{}'", line.trim())?,
+            }
+        };
+
+        let status = match action {
+            Action::Accept => Some(ReviewStatus::Accepted),
+            Action::Flag => Some(ReviewStatus::Flagged),
+            Action::Edit => match hooks.edit(&records[index].response) {
+                Ok(response) => {
+                    records[index].response = response;
+                    Some(ReviewStatus::Edited)
+                }
+                Err(e) => {
+                    writeln!(out, "Edit failed: {}", e)?;
+                    continue;
+                }
+            },
+            Action::Skip => None,
+            Action::Back => {
+                position = position.saturating_sub(1);
+                continue;
+            }
+            Action::Quit => break,
+        };
+
+        if let Some(status) = status {
+            records[index].review = Some(status);
+            match status {
+                ReviewStatus::Accepted => summary.accepted += 1,
+                ReviewStatus::Edited => summary.edited += 1,
+                ReviewStatus::Flagged => summary.flagged += 1,
+            }
+            hooks.save(records).map_err(io::Error::other)?;
+        }
+        position += 1;
+    }
+
+    Ok(summary)
+}
+
+fn display_response(response: &serde_json::Value) -> String {
+    match response {
+        serde_json::Value::String(text) => text.clone(),
+        other => serde_json::to_string_pretty(other).expect("JSON values serialize"),
+    }
+}
+
+/// Text to put in the editor: plain text responses are edited as-is rather than as a
+/// quoted JSON string.
+pub fn editable_text(response: &serde_json::Value) -> String {
+    display_response(response) + "\n"
+}
+
+/// Parse edited text back into a response of the same kind as `original`.
+pub fn parse_edited(original: &serde_json::Value, text: &str) -> Result<serde_json::Value, String> {
+    match original {
+        serde_json::Value::String(_) => Ok(serde_json::Value::String(text.trim_end().to_string())),
+        _ => serde_json::from_str(text).map_err(|e| format!("Edited response is not valid JSON: {}", e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct FakeHooks {
+        saves: usize,
+    }
+
+    impl ReviewHooks for FakeHooks {
+        fn show(&mut self, _record: &OutputRecord, _out: &mut dyn Write) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn edit(&mut self, _response: &serde_json::Value) -> Result<serde_json::Value, String> {
+            Ok(serde_json::json!({"category": "fixed"}))
+        }
+
+        fn save(&mut self, _records: &[OutputRecord]) -> Result<(), String> {
+            self.saves += 1;
+            Ok(())
+        }
+    }
+
+    fn records() -> Vec<OutputRecord> {
+        ["a.jpg", "b.jpg", "c.jpg", "d.jpg"]
+            .iter()
+            .map(|file| OutputRecord::new(file, "", serde_json::json!({"category": "cat"})))
+            .collect()
+    }
+
+    #[test]
+    fn test_review_actions() {
+        let mut records = records();
+        let mut hooks = FakeHooks { saves: 0 };
+        let mut out = Vec::new();
+
+        // accept a, bogus input, flag b, back to b, edit it, skip c, quit before d
+        let input = "a\nx\nf\nb\ne\ns\nq\n";
+        let summary = run_review(&mut records, &[0, 1, 2, 3], input.as_bytes(), &mut out, &mut hooks).unwrap();
+
+        assert_eq!(summary, ReviewSummary { accepted: 1, edited: 1, flagged: 1 });
+        assert_eq!(hooks.saves, 3);
+        assert_eq!(records[0].review, Some(ReviewStatus::Accepted));
+        assert_eq!(records[1].review, Some(ReviewStatus::Edited));
+        assert_eq!(records[1].response["category"], "fixed");
+        assert_eq!(records[2].review, None);
+        assert!(String::from_utf8(out).unwrap().contains("Unknown command 'x'"));
+    }
+
+    #[test]
+    fn test_review_stops_at_end_of_input() {
+        let mut records = records();
+        let mut hooks = FakeHooks { saves: 0 };
+        let summary = run_review(&mut records, &[3], "".as_bytes(), &mut Vec::new(), &mut hooks).unwrap();
+        assert_eq!(summary, ReviewSummary::default());
+    }
+
+    #[test]
+    fn test_parse_edited_keeps_response_kind() {
+        let text = serde_json::json!("A cat");
+        assert_eq!(parse_edited(&text, "A dog\n").unwrap(), serde_json::json!("A dog"));
+        assert_eq!(editable_text(&text), "A cat\n");
+
+        let object = serde_json::json!({"n": 1});
+        assert_eq!(parse_edited(&object, "{\"n\": 2}\n").unwrap(), serde_json::json!({"n": 2}));
+        assert!(parse_edited(&object, "{oops").is_err());
+    }
+}
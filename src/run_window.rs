@@ -0,0 +1,102 @@
+//! `--run-window`: the hours of the day a batch may send requests in, e.g. `22:00-06:00` to
+//! leave a shared GPU server to daytime users. Outside the window the run pauses between
+//! images, and picks up where it left off when the window opens again.
+
+use std::fmt;
+
+use chrono::{Local, Timelike};
+
+/// A daily window in local time, which may run past midnight.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RunWindow {
+    /// Minutes after midnight the window opens
+    start: u32,
+    /// Minutes after midnight the window closes
+    end: u32,
+}
+
+impl RunWindow {
+    /// Parse `HH:MM-HH:MM`.
+    pub fn parse(window: &str) -> Result<RunWindow, String> {
+        let invalid = || format!("Invalid --run-window '{}' (expected HH:MM-HH:MM, e.g. 22:00-06:00)", window);
+        let (start, end) = window.split_once('-').ok_or_else(invalid)?;
+        let (start, end) = (parse_time(start).ok_or_else(invalid)?, parse_time(end).ok_or_else(invalid)?);
+        if start == end {
+            return Err(format!("--run-window '{}' opens and closes at the same time", window));
+        }
+        Ok(RunWindow { start, end })
+    }
+
+    /// Whether the window is open `minute` minutes after midnight.
+    pub fn contains(&self, minute: u32) -> bool {
+        match self.start < self.end {
+            true => (self.start..self.end).contains(&minute),
+            false => minute >= self.start || minute < self.end,
+        }
+    }
+
+    /// Whether the window is open now.
+    pub fn is_open(&self) -> bool {
+        let now = Local::now();
+        self.contains(now.hour() * 60 + now.minute())
+    }
+
+    /// When the window opens, as `HH:MM`.
+    pub fn opens(&self) -> String {
+        format_time(self.start)
+    }
+}
+
+impl fmt::Display for RunWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", format_time(self.start), format_time(self.end))
+    }
+}
+
+fn parse_time(time: &str) -> Option<u32> {
+    let (hours, minutes) = time.trim().split_once(':')?;
+    let (hours, minutes) = (hours.parse::<u32>().ok()?, minutes.parse::<u32>().ok()?);
+    // 24:00 is the end of the day, the same as midnight
+    match (hours, minutes) {
+        (24, 0) => Some(0),
+        (0..=23, 0..=59) => Some(hours * 60 + minutes),
+        _ => None,
+    }
+}
+
+fn format_time(minute: u32) -> String {
+    format!("{:02}:{:02}", minute / 60, minute % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let window = RunWindow::parse("22:00-06:30").unwrap();
+        assert_eq!(window.to_string(), "22:00-06:30");
+        assert_eq!(window.opens(), "22:00");
+        assert_eq!(RunWindow::parse("9:00-17:00").unwrap().to_string(), "09:00-17:00");
+        assert_eq!(RunWindow::parse("18:00-24:00").unwrap().to_string(), "18:00-00:00");
+        assert!(RunWindow::parse("22:00").is_err());
+        assert!(RunWindow::parse("25:00-06:00").is_err());
+        assert!(RunWindow::parse("22:60-06:00").is_err());
+        assert!(RunWindow::parse("08:00-08:00").unwrap_err().contains("same time"));
+    }
+
+    #[test]
+    fn test_contains() {
+        let overnight = RunWindow::parse("22:00-06:00").unwrap();
+        assert!(overnight.contains(23 * 60));
+        assert!(overnight.contains(0));
+        assert!(overnight.contains(5 * 60 + 59));
+        assert!(!overnight.contains(6 * 60));
+        assert!(!overnight.contains(12 * 60));
+
+        let daytime = RunWindow::parse("09:00-17:00").unwrap();
+        assert!(daytime.contains(9 * 60));
+        assert!(!daytime.contains(17 * 60));
+        assert!(!daytime.contains(3 * 60));
+    }
+}
@@ -0,0 +1,157 @@
+//! Checking responses against a prompt config's JSON schema, for `9ladies validate-output`
+//! and before `--move-to` or `--copy-to` sorts a file by its response.
+//!
+//! Covers the parts of JSON Schema that reply schemas use: `type`, `enum`, `const`,
+//! `properties`, `required`, `additionalProperties: false`, `items`, `anyOf`, and the length
+//! and range limits. Other keywords are ignored.
+
+use serde_json::Value;
+
+/// Where `value` breaks `schema`, one message per problem, each starting with the path to
+/// the value.
+pub fn schema_errors(schema: &Value, value: &Value, path: &str) -> Vec<String> {
+    let Some(schema) = schema.as_object() else {
+        return Vec::new();
+    };
+    let mut errors = Vec::new();
+
+    if let Some(expected) = schema.get("type") {
+        let types: Vec<&str> = match expected {
+            Value::String(name) => vec![name.as_str()],
+            Value::Array(names) => names.iter().filter_map(Value::as_str).collect(),
+            _ => Vec::new(),
+        };
+        if !types.is_empty() && !types.iter().any(|name| has_type(value, name)) {
+            // Nothing below applies to a value of the wrong type
+            return vec![format!("{}: expected {}, got {}", path, types.join(" or "), type_name(value))];
+        }
+    }
+    if let Some(Value::Array(allowed)) = schema.get("enum") {
+        if !allowed.contains(value) {
+            errors.push(format!("{}: {} is not one of {}", path, value, Value::Array(allowed.clone())));
+        }
+    }
+    if let Some(expected) = schema.get("const") {
+        if expected != value {
+            errors.push(format!("{}: expected {}, got {}", path, expected, value));
+        }
+    }
+    if let Some(Value::Array(options)) = schema.get("anyOf") {
+        if !options.iter().any(|option| schema_errors(option, value, path).is_empty()) {
+            errors.push(format!("{}: matches none of the allowed shapes", path));
+        }
+    }
+
+    match value {
+        Value::Object(fields) => {
+            if let Some(Value::Array(required)) = schema.get("required") {
+                for name in required.iter().filter_map(Value::as_str) {
+                    if !fields.contains_key(name) {
+                        errors.push(format!("{}.{}: required, but it is missing", path, name));
+                    }
+                }
+            }
+            let properties = schema.get("properties").and_then(Value::as_object);
+            for (name, field) in fields {
+                match properties.and_then(|properties| properties.get(name)) {
+                    Some(property) => errors.extend(schema_errors(property, field, &format!("{}.{}", path, name))),
+                    None if schema.get("additionalProperties") == Some(&Value::Bool(false)) => {
+                        errors.push(format!("{}.{}: not allowed by the schema", path, name))
+                    }
+                    None => {}
+                }
+            }
+        }
+        Value::Array(items) => {
+            let count = items.len() as u64;
+            if let Some(min) = schema.get("minItems").and_then(Value::as_u64).filter(|&min| count < min) {
+                errors.push(format!("{}: expected at least {} items, got {}", path, min, count));
+            }
+            if let Some(max) = schema.get("maxItems").and_then(Value::as_u64).filter(|&max| count > max) {
+                errors.push(format!("{}: expected at most {} items, got {}", path, max, count));
+            }
+            if let Some(item) = schema.get("items") {
+                for (i, value) in items.iter().enumerate() {
+                    errors.extend(schema_errors(item, value, &format!("{}[{}]", path, i)));
+                }
+            }
+        }
+        Value::String(text) => {
+            let length = text.chars().count() as u64;
+            if let Some(min) = schema.get("minLength").and_then(Value::as_u64).filter(|&min| length < min) {
+                errors.push(format!("{}: expected at least {} characters, got {}", path, min, length));
+            }
+            if let Some(max) = schema.get("maxLength").and_then(Value::as_u64).filter(|&max| length > max) {
+                errors.push(format!("{}: expected at most {} characters, got {}", path, max, length));
+            }
+        }
+        Value::Number(number) => {
+            let number = number.as_f64().unwrap_or_default();
+            if let Some(min) = schema.get("minimum").and_then(Value::as_f64).filter(|&min| number < min) {
+                errors.push(format!("{}: expected at least {}, got {}", path, min, number));
+            }
+            if let Some(max) = schema.get("maximum").and_then(Value::as_f64).filter(|&max| number > max) {
+                errors.push(format!("{}: expected at most {}, got {}", path, max, number));
+            }
+        }
+        _ => {}
+    }
+    errors
+}
+
+fn has_type(value: &Value, name: &str) -> bool {
+    match name {
+        "integer" => value.as_f64().is_some_and(|n| n.fract() == 0.0),
+        "number" => value.is_number(),
+        other => type_name(value) == other,
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_schema_errors() {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "category": {"type": "string", "enum": ["receipt", "invoice"]},
+                "total": {"type": ["number", "null"], "minimum": 0},
+                "count": {"type": "integer"},
+                "tags": {"type": "array", "items": {"type": "string"}, "maxItems": 2},
+            },
+            "required": ["category", "total"],
+            "additionalProperties": false,
+        });
+        assert!(schema_errors(&schema, &json!({"category": "receipt", "total": null, "count": 3.0}), "response").is_empty());
+        assert_eq!(
+            schema_errors(&schema, &json!({"category": "menu", "count": 1.5, "tags": ["a", 2, "c"], "extra": 1}), "response"),
+            vec![
+                "response.total: required, but it is missing",
+                "response.category: \"menu\" is not one of [\"receipt\",\"invoice\"]",
+                "response.count: expected integer, got number",
+                "response.tags: expected at most 2 items, got 3",
+                "response.tags[1]: expected string, got number",
+                "response.extra: not allowed by the schema",
+            ]
+        );
+        assert_eq!(schema_errors(&schema, &json!("A receipt"), "response"), vec!["response: expected object, got string"]);
+        assert_eq!(
+            schema_errors(&schema, &json!({"category": "invoice", "total": -2}), "response"),
+            vec!["response.total: expected at least 0, got -2"]
+        );
+    }
+}
@@ -0,0 +1,213 @@
+//! `--script`: user Lua scripts that adjust each image's prompt and record.
+//!
+//! A script defines either or both of these global functions:
+//!
+//! ```lua
+//! function transform_prompt(ctx)
+//!   -- ctx.file, ctx.system, ctx.prompt, ctx.temperature
+//!   ctx.prompt = ctx.prompt .. "\nThe file is named " .. ctx.file
+//!   return ctx
+//! end
+//!
+//! function transform_response(record)
+//!   -- record.file, record.sha256, record.response (a table for JSON replies, else a string)
+//!   if type(record.response) == "table" then
+//!     record.response.subject = string.lower(record.response.subject or "")
+//!   end
+//!   return record
+//! end
+//! ```
+//!
+//! Returning nothing keeps the value unchanged, and raising an error (`error("...")`) fails
+//! the image. Only `system`, `prompt` and `temperature` are taken back from the prompt
+//! context, and only `response` from the record.
+
+use std::fs;
+use std::sync::Mutex;
+
+use mlua::{Function, Lua, LuaSerdeExt, Value};
+
+use crate::batch::Transform;
+use crate::prompt::PromptConfig;
+use crate::record::OutputRecord;
+
+const TRANSFORM_PROMPT: &str = "transform_prompt";
+const TRANSFORM_RESPONSE: &str = "transform_response";
+
+/// A loaded Lua script. Calls are serialized, so one script serves a whole run.
+#[derive(Debug)]
+pub struct LuaScript {
+    lua: Mutex<Lua>,
+    path: String,
+}
+
+impl LuaScript {
+    /// Load and run a script file, which must define at least one of the hook functions.
+    pub fn load(path: &str) -> Result<LuaScript, String> {
+        let source = fs::read_to_string(path).map_err(|e| format!("Failed to read script '{}': {}", path, e))?;
+        LuaScript::from_source(&source, path)
+    }
+
+    /// Like `load`, with the source given directly; `name` is used in error messages.
+    pub fn from_source(source: &str, name: &str) -> Result<LuaScript, String> {
+        let lua = Lua::new();
+        lua.load(source)
+            .set_name(name)
+            .exec()
+            .map_err(|e| format!("Error in script '{}': {}", name, e))?;
+
+        let script = LuaScript {
+            lua: Mutex::new(lua),
+            path: name.to_string(),
+        };
+        if !script.defines(TRANSFORM_PROMPT) && !script.defines(TRANSFORM_RESPONSE) {
+            return Err(format!(
+                "Script '{}' defines neither {} nor {}",
+                name, TRANSFORM_PROMPT, TRANSFORM_RESPONSE
+            ));
+        }
+        Ok(script)
+    }
+
+    fn defines(&self, function: &str) -> bool {
+        let lua = self.lua.lock().expect("script lock");
+        matches!(lua.globals().get::<Value>(function), Ok(Value::Function(_)))
+    }
+
+    /// Call a hook with `input`, returning its result as JSON, or `None` when the script
+    /// doesn't define the hook or returned nothing.
+    fn call(&self, function: &str, input: &serde_json::Value) -> Result<Option<serde_json::Value>, String> {
+        let lua = self.lua.lock().expect("script lock");
+        let Ok(hook) = lua.globals().get::<Function>(function) else {
+            return Ok(None);
+        };
+        let error = |e: mlua::Error| format!("{} in '{}' failed: {}", function, self.path, e);
+
+        let argument = lua.to_value(input).map_err(error)?;
+        match hook.call::<Value>(argument).map_err(error)? {
+            Value::Nil => Ok(None),
+            value => lua.from_value(value).map(Some).map_err(error),
+        }
+    }
+}
+
+impl Transform for LuaScript {
+    fn transform_prompt(&self, file: &str, config: PromptConfig) -> Result<PromptConfig, String> {
+        let ctx = serde_json::json!({
+            "file": file,
+            "system": config.system,
+            "prompt": config.prompt,
+            "temperature": config.temperature,
+        });
+        let Some(result) = self.call(TRANSFORM_PROMPT, &ctx)? else {
+            return Ok(config);
+        };
+
+        let text = |field: &str, current: String| match result.get(field) {
+            None | Some(serde_json::Value::Null) => Ok(current),
+            Some(serde_json::Value::String(s)) => Ok(s.clone()),
+            Some(_) => Err(format!("{} in '{}' returned a non-string '{}'", TRANSFORM_PROMPT, self.path, field)),
+        };
+        Ok(PromptConfig {
+            system: text("system", config.system)?,
+            prompt: text("prompt", config.prompt)?,
+            temperature: result
+                .get("temperature")
+                .and_then(serde_json::Value::as_f64)
+                .map_or(config.temperature, |t| t as f32),
+            model: config.model,
+            schema: config.schema,
+            seed: config.seed,
+            think: config.think,
+            schema_via: config.schema_via,
+        })
+    }
+
+    fn transform_response(&self, record: &mut OutputRecord) -> Result<(), String> {
+        let input = serde_json::json!({
+            "file": record.file,
+            "sha256": record.sha256,
+            "response": record.response,
+        });
+        if let Some(mut result) = self.call(TRANSFORM_RESPONSE, &input)? {
+            match result.get_mut("response") {
+                Some(response) => record.response = response.take(),
+                None => {
+                    return Err(format!(
+                        "{} in '{}' returned a record without a response",
+                        TRANSFORM_RESPONSE, self.path
+                    ))
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> PromptConfig {
+        PromptConfig {
+            system: "Be brief.".to_string(),
+            prompt: "Describe this.".to_string(),
+            temperature: 0.2,
+            model: None,
+            schema: None,
+            seed: None,
+            think: None,
+            schema_via: None,
+        }
+    }
+
+    #[test]
+    fn test_transform_prompt() {
+        let script = LuaScript::from_source(
+            r#"function transform_prompt(ctx)
+                 ctx.prompt = ctx.prompt .. " File: " .. ctx.file
+                 ctx.temperature = 0
+                 return ctx
+               end"#,
+            "test.lua",
+        )
+        .unwrap();
+        let config = script.transform_prompt("cat.jpg", config()).unwrap();
+        assert_eq!(config.prompt, "Describe this. File: cat.jpg");
+        assert_eq!(config.system, "Be brief.");
+        assert_eq!(config.temperature, 0.0);
+
+        // The record is left alone when there is no transform_response
+        let mut record = OutputRecord::new("cat.jpg", "", serde_json::json!("A cat"));
+        script.transform_response(&mut record).unwrap();
+        assert_eq!(record.response, "A cat");
+    }
+
+    #[test]
+    fn test_transform_response() {
+        let script = LuaScript::from_source(
+            r#"function transform_response(record)
+                 if record.response.count == 0 then error("nobody in " .. record.file) end
+                 record.response.subject = string.upper(record.response.subject)
+                 record.response.source = record.file
+                 return record
+               end"#,
+            "test.lua",
+        )
+        .unwrap();
+
+        let mut record = OutputRecord::new("cat.jpg", "", serde_json::json!({"subject": "cat", "count": 1}));
+        script.transform_response(&mut record).unwrap();
+        assert_eq!(record.response, serde_json::json!({"subject": "CAT", "count": 1, "source": "cat.jpg"}));
+
+        let mut empty = OutputRecord::new("empty.jpg", "", serde_json::json!({"subject": "", "count": 0}));
+        let e = script.transform_response(&mut empty).unwrap_err();
+        assert!(e.contains("nobody in empty.jpg"), "{}", e);
+    }
+
+    #[test]
+    fn test_script_needs_a_hook() {
+        assert!(LuaScript::from_source("x = 1", "empty.lua").unwrap_err().contains("defines neither"));
+        assert!(LuaScript::from_source("function (", "broken.lua").unwrap_err().contains("broken.lua"));
+    }
+}
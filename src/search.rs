@@ -0,0 +1,163 @@
+//! `9ladies search`: free-text queries over the descriptions in earlier output files, by
+//! embedding each response and the query and ranking by cosine similarity.
+//!
+//! Embeddings are cached next to each output file, keyed by model and by a hash of the
+//! text embedded, so repeated searches only embed the query and any new or edited records.
+
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::images::sha256_hex;
+
+/// Cosine similarity, from -1 to 1. Zero for empty or mismatched vectors.
+pub fn cosine(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() || a.is_empty() {
+        return 0.0;
+    }
+    let (mut dot, mut norm_a, mut norm_b) = (0.0f32, 0.0f32, 0.0f32);
+    for (x, y) in a.iter().zip(b) {
+        dot += x * y;
+        norm_a += x * x;
+        norm_b += y * y;
+    }
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return 0.0;
+    }
+    dot / (norm_a.sqrt() * norm_b.sqrt())
+}
+
+/// Indices of `vectors` ordered by similarity to `query`, best first, with their scores.
+pub fn rank(query: &[f32], vectors: &[&[f32]]) -> Vec<(usize, f32)> {
+    let mut scored: Vec<(usize, f32)> = vectors.iter().enumerate().map(|(i, v)| (i, cosine(query, v))).collect();
+    scored.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+    scored
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedEmbedding {
+    model: String,
+    /// Hex SHA-256 of the text that was embedded
+    text_sha256: String,
+    vector: Vec<f32>,
+}
+
+/// Embeddings saved alongside an output file, e.g. `results.embeddings.jsonl` for
+/// `results.jsonl`. New ones are appended as they are computed.
+pub struct EmbeddingCache {
+    path: PathBuf,
+    vectors: HashMap<(String, String), Vec<f32>>,
+    /// The file ends mid-line, so the next entry must start on a new one
+    truncated: bool,
+}
+
+impl EmbeddingCache {
+    /// Where the cache for an output file lives.
+    pub fn path_for(output: &str) -> PathBuf {
+        let stem = output.strip_suffix(".jsonl").unwrap_or(output);
+        PathBuf::from(format!("{}.embeddings.jsonl", stem))
+    }
+
+    /// Load a cache, or start an empty one if the file doesn't exist. Unreadable lines are
+    /// skipped, so a half-written line from an interrupted search costs one re-embedding.
+    pub fn open(path: &Path) -> Result<EmbeddingCache, String> {
+        let mut vectors = HashMap::new();
+        let mut truncated = false;
+        if path.exists() {
+            let content =
+                fs::read_to_string(path).map_err(|e| format!("Failed to read '{}': {}", path.display(), e))?;
+            truncated = !content.is_empty() && !content.ends_with('\n');
+            for entry in content.lines().filter_map(|line| serde_json::from_str::<CachedEmbedding>(line).ok()) {
+                vectors.insert((entry.model, entry.text_sha256), entry.vector);
+            }
+        }
+        Ok(EmbeddingCache {
+            path: path.to_path_buf(),
+            vectors,
+            truncated,
+        })
+    }
+
+    pub fn get(&self, model: &str, text: &str) -> Option<&[f32]> {
+        self.vectors.get(&(model.to_string(), sha256_hex(text.as_bytes()))).map(Vec::as_slice)
+    }
+
+    /// Remember an embedding and append it to the file.
+    pub fn insert(&mut self, model: &str, text: &str, vector: Vec<f32>) -> Result<(), String> {
+        let entry = CachedEmbedding {
+            model: model.to_string(),
+            text_sha256: sha256_hex(text.as_bytes()),
+            vector,
+        };
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(|e| format!("Failed to open '{}': {}", self.path.display(), e))?;
+        let separator = if self.truncated { "\n" } else { "" };
+        writeln!(file, "{}{}", separator, serde_json::to_string(&entry).expect("embeddings serialize"))
+            .map_err(|e| format!("Failed to write '{}': {}", self.path.display(), e))?;
+        self.truncated = false;
+        self.vectors.insert((entry.model, entry.text_sha256), entry.vector);
+        Ok(())
+    }
+}
+
+/// Embed `text` with an Ollama embedding model, using the cache when it has it.
+pub fn embed_cached(
+    cache: &mut EmbeddingCache,
+    client: &reqwest::blocking::Client,
+    url: &str,
+    model: &str,
+    text: &str,
+) -> Result<Vec<f32>, String> {
+    if let Some(vector) = cache.get(model, text) {
+        return Ok(vector.to_vec());
+    }
+    let vector = crate::backend::call_embed(client, url, model, text)?;
+    cache.insert(model, text, vector.clone())?;
+    Ok(vector)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cosine_and_rank() {
+        assert!((cosine(&[1.0, 0.0], &[2.0, 0.0]) - 1.0).abs() < 1e-6);
+        assert_eq!(cosine(&[1.0, 0.0], &[0.0, 1.0]), 0.0);
+        assert_eq!(cosine(&[1.0], &[1.0, 0.0]), 0.0);
+        assert_eq!(cosine(&[0.0, 0.0], &[1.0, 0.0]), 0.0);
+
+        let vectors: Vec<&[f32]> = vec![&[0.0, 1.0], &[1.0, 0.1], &[-1.0, 0.0]];
+        let ranked: Vec<usize> = rank(&[1.0, 0.0], &vectors).into_iter().map(|(i, _)| i).collect();
+        assert_eq!(ranked, vec![1, 0, 2]);
+    }
+
+    #[test]
+    fn test_cache_round_trip() {
+        let dir = std::env::temp_dir().join(format!("nineladies_search_test_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = EmbeddingCache::path_for(dir.join("results.jsonl").to_str().unwrap());
+        assert!(path.ends_with("results.embeddings.jsonl"));
+        fs::remove_file(&path).ok();
+
+        let mut cache = EmbeddingCache::open(&path).unwrap();
+        cache.insert("nomic-embed-text", "a dog on a beach", vec![0.5, 0.25]).unwrap();
+        fs::OpenOptions::new().append(true).open(&path).unwrap().write_all(b"{\"trunc").unwrap();
+
+        let mut reopened = EmbeddingCache::open(&path).unwrap();
+        assert_eq!(reopened.get("nomic-embed-text", "a dog on a beach"), Some(&[0.5, 0.25][..]));
+        assert_eq!(reopened.get("other-model", "a dog on a beach"), None);
+        assert_eq!(reopened.get("nomic-embed-text", "a cat"), None);
+
+        // Entries after a half-written line are still readable
+        reopened.insert("nomic-embed-text", "a cat", vec![1.0]).unwrap();
+        assert_eq!(EmbeddingCache::open(&path).unwrap().get("nomic-embed-text", "a cat"), Some(&[1.0][..]));
+        fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,157 @@
+//! API keys for hosted backends from somewhere safer than the environment: the OS keychain
+//! (macOS Keychain, Secret Service on Linux, Windows Credential Manager) or a secrets command
+//! such as `pass show openai` or `op read op://work/openai/key`, named in the config file.
+//!
+//! `NINELADIES_API_KEY` still wins when it is set. Otherwise the key is looked up the first
+//! time a request needs one, so runs against Ollama never unlock the keychain.
+
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+
+use tracing::warn;
+
+use crate::backend::API_KEY_ENV;
+
+/// Where to find the API key when `NINELADIES_API_KEY` isn't set.
+#[derive(Clone, Debug, PartialEq)]
+pub enum KeySource {
+    /// A shell command that prints the key
+    Command(String),
+    /// The name (service, or target on Windows) of an entry in the OS keychain
+    Keychain(String),
+}
+
+static SOURCE: OnceLock<KeySource> = OnceLock::new();
+static KEY: OnceLock<Option<String>> = OnceLock::new();
+
+/// Look the API key up in `source` when a request needs one. Only the first call has effect.
+pub fn set_key_source(source: KeySource) {
+    let _ = SOURCE.set(source);
+}
+
+/// The API key to send to OpenAI-compatible servers: `NINELADIES_API_KEY`, or else the key
+/// from the source given to `set_key_source`, read once and kept for the rest of the process.
+pub fn api_key() -> Option<String> {
+    if let Ok(key) = std::env::var(API_KEY_ENV) {
+        return Some(key);
+    }
+    KEY.get_or_init(|| {
+        let source = SOURCE.get()?;
+        read_key(source)
+            .map_err(|e| warn!("{}; sending requests without an API key", e))
+            .ok()
+    })
+    .clone()
+}
+
+/// Read the key from `source` now.
+pub fn read_key(source: &KeySource) -> Result<String, String> {
+    let (mut command, what) = match source {
+        KeySource::Command(line) => (shell(line), format!("api_key_command '{}'", line)),
+        KeySource::Keychain(name) => (keychain_lookup(name), format!("keychain entry '{}'", name)),
+    };
+    let output = command
+        .stdin(Stdio::null())
+        .stderr(Stdio::piped())
+        .output()
+        .map_err(|e| format!("Failed to read the API key from {}: {}", what, e))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!(
+            "Failed to read the API key from {}: exited with {}{}",
+            what,
+            output.status,
+            match stderr.trim() {
+                "" => String::new(),
+                message => format!(": {}", message),
+            }
+        ));
+    }
+    // Secrets commands print the key on the first line; `pass` puts other fields after it
+    let key = String::from_utf8_lossy(&output.stdout).lines().next().unwrap_or_default().trim().to_string();
+    if key.is_empty() {
+        return Err(format!("The API key from {} is empty", what));
+    }
+    Ok(key)
+}
+
+fn shell(line: &str) -> Command {
+    let mut shell = if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+    shell.arg(line);
+    shell
+}
+
+/// The command printing the password of keychain entry `name`, using the tool each OS
+/// ships with.
+fn keychain_lookup(name: &str) -> Command {
+    if cfg!(target_os = "macos") {
+        let mut command = Command::new("security");
+        command.args(["find-generic-password", "-w", "-s", name]);
+        command
+    } else if cfg!(windows) {
+        // Credential Manager has no command-line reader, so call CredRead from PowerShell
+        let mut command = Command::new("powershell");
+        command.args(["-NoProfile", "-NonInteractive", "-Command", WINDOWS_CRED_READ]).env("NINELADIES_CREDENTIAL", name);
+        command
+    } else {
+        let mut command = Command::new("secret-tool");
+        command.args(["lookup", "service", name]);
+        command
+    }
+}
+
+const WINDOWS_CRED_READ: &str = r#"
+$ErrorActionPreference = 'Stop'
+Add-Type -TypeDefinition @'
+using System;
+using System.Runtime.InteropServices;
+public static class NineLadiesCred {
+    [StructLayout(LayoutKind.Sequential, CharSet = CharSet.Unicode)]
+    struct CREDENTIAL {
+        public int Flags; public int Type; public string TargetName; public string Comment;
+        public long LastWritten; public int CredentialBlobSize; public IntPtr CredentialBlob;
+        public int Persist; public int AttributeCount; public IntPtr Attributes;
+        public string TargetAlias; public string UserName;
+    }
+    [DllImport("advapi32.dll", CharSet = CharSet.Unicode, SetLastError = true)]
+    static extern bool CredRead(string target, int type, int flags, out IntPtr credential);
+    [DllImport("advapi32.dll")]
+    static extern void CredFree(IntPtr credential);
+    public static string Read(string target) {
+        IntPtr pointer;
+        if (!CredRead(target, 1, 0, out pointer)) { return null; }
+        try {
+            var credential = (CREDENTIAL)Marshal.PtrToStructure(pointer, typeof(CREDENTIAL));
+            return Marshal.PtrToStringUni(credential.CredentialBlob, credential.CredentialBlobSize / 2);
+        } finally { CredFree(pointer); }
+    }
+}
+'@
+$secret = [NineLadiesCred]::Read($env:NINELADIES_CREDENTIAL)
+if ($secret -eq $null) { [Console]::Error.WriteLine("no such credential"); exit 1 }
+$secret
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_read_key_from_command() {
+        let key = read_key(&KeySource::Command("printf 'sk-test\\nuser: me\\n'".to_string())).unwrap();
+        assert_eq!(key, "sk-test");
+
+        let err = read_key(&KeySource::Command("echo locked >&2; exit 3".to_string())).unwrap_err();
+        assert!(err.contains("exited with") && err.ends_with(": locked"), "{}", err);
+        assert!(read_key(&KeySource::Command("true".to_string())).unwrap_err().contains("is empty"));
+    }
+}
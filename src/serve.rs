@@ -0,0 +1,565 @@
+//! `9ladies serve`: the describe pipeline behind an HTTP endpoint, for use as a microservice.
+//!
+//! `POST /describe` takes the image as the raw request body, as the first file of a
+//! `multipart/form-data` upload, or as JSON (`{"image": "<base64>", "name": "a.jpg"}`, or
+//! `{"path": "a.jpg"}` when `--path-root` allows reading files on the server), and answers
+//! with the output record.
+//!
+//! `GET /healthz` answers as long as the process is up, `GET /readyz` also checks that the
+//! model server is reachable and has the model, and `GET /openapi.json` describes the API.
+
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use serde::Deserialize;
+use tracing::{debug, info, warn};
+
+use nineladies::backend::model_available;
+use nineladies::{BatchRunner, CallError, ItemError};
+
+use crate::shutdown::Shutdown;
+
+/// How often workers wake up to check for Ctrl+C while waiting for requests
+pub const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Name given to uploads that don't carry one
+const DEFAULT_UPLOAD_NAME: &str = "upload";
+
+/// Limits on what requests may ask for.
+pub struct ServeOptions {
+    /// Largest request body accepted
+    pub max_upload_bytes: usize,
+    /// Directory that `{"path": ...}` requests may read from; paths are refused without it
+    pub path_root: Option<PathBuf>,
+}
+
+/// A response: the status code and a JSON body.
+pub type Reply = (u16, String);
+
+pub fn error_reply(status: u16, message: impl Into<String>) -> Reply {
+    (status, serde_json::json!({ "error": message.into() }).to_string())
+}
+
+/// `:8080` listens on every interface, like Go's `net/http`; anything else is used as given.
+pub fn listen_address(listen: &str) -> String {
+    match listen.strip_prefix(':') {
+        Some(port) => format!("0.0.0.0:{}", port),
+        None => listen.to_string(),
+    }
+}
+
+/// What a `/describe` request asks for.
+#[derive(Debug, PartialEq)]
+pub enum ImageSource {
+    Upload { name: String, data: Vec<u8> },
+    Path(String),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DescribeJson {
+    /// Base64 image bytes
+    image: Option<String>,
+    name: Option<String>,
+    path: Option<String>,
+}
+
+/// Decode `%XX` escapes and `+` in a query string value.
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => out.push(b' '),
+            b'%' if i + 2 < bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok();
+                match hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                    Some(byte) => {
+                        out.push(byte);
+                        i += 2;
+                    }
+                    None => out.push(b'%'),
+                }
+            }
+            byte => out.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// The value of a query string parameter in a request URL.
+fn query_param(url: &str, name: &str) -> Option<String> {
+    let (_, query) = url.split_once('?')?;
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| percent_decode(value))
+}
+
+/// The `boundary` parameter of a `multipart/form-data` content type.
+fn multipart_boundary(content_type: &str) -> Option<&str> {
+    let (kind, params) = content_type.split_once(';')?;
+    if !kind.trim().eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+    params
+        .split(';')
+        .filter_map(|param| param.trim().split_once('='))
+        .find(|(key, _)| key.eq_ignore_ascii_case("boundary"))
+        .map(|(_, value)| value.trim_matches('"'))
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+/// The first file in a multipart body, with its file name if it has one.
+fn multipart_file(body: &[u8], boundary: &str) -> Option<(Option<String>, Vec<u8>)> {
+    let delimiter = format!("--{}", boundary);
+    let mut rest = &body[find(body, delimiter.as_bytes())? + delimiter.len()..];
+    loop {
+        if rest.starts_with(b"--") {
+            return None;
+        }
+        let headers_end = find(rest, b"\r\n\r\n")?;
+        let headers = String::from_utf8_lossy(&rest[..headers_end]).to_string();
+        let content = &rest[headers_end + 4..];
+        let content_end = find(content, format!("\r\n{}", delimiter).as_bytes())?;
+
+        let disposition = headers
+            .lines()
+            .find(|line| line.to_ascii_lowercase().starts_with("content-disposition:"))
+            .unwrap_or_default();
+        let filename = disposition
+            .split(';')
+            .filter_map(|param| param.trim().split_once('='))
+            .find(|(key, _)| key.eq_ignore_ascii_case("filename"))
+            .map(|(_, value)| value.trim_matches('"').to_string());
+        if filename.is_some() || disposition.contains("name=\"image\"") {
+            return Some((filename.filter(|f| !f.is_empty()), content[..content_end].to_vec()));
+        }
+        rest = &content[content_end + 2 + delimiter.len()..];
+    }
+}
+
+/// Work out which image a `/describe` request body carries.
+pub fn parse_describe_body(url: &str, content_type: Option<&str>, body: Vec<u8>) -> Result<ImageSource, String> {
+    let name = query_param(url, "name");
+    let content_type = content_type.unwrap_or_default();
+
+    if let Some(boundary) = multipart_boundary(content_type) {
+        let (filename, data) =
+            multipart_file(&body, boundary).ok_or("The multipart upload has no file part")?;
+        let name = name.or(filename).unwrap_or_else(|| DEFAULT_UPLOAD_NAME.to_string());
+        return Ok(ImageSource::Upload { name, data });
+    }
+
+    if content_type.starts_with("application/json") {
+        let request: DescribeJson =
+            serde_json::from_slice(&body).map_err(|e| format!("Invalid JSON request: {}", e))?;
+        return match (request.image, request.path) {
+            (Some(image), None) => {
+                let data = BASE64
+                    .decode(image.trim())
+                    .map_err(|e| format!("'image' is not valid base64: {}", e))?;
+                let name = request.name.or(name).unwrap_or_else(|| DEFAULT_UPLOAD_NAME.to_string());
+                Ok(ImageSource::Upload { name, data })
+            }
+            (None, Some(path)) => Ok(ImageSource::Path(path)),
+            _ => Err("Give exactly one of 'image' (base64) or 'path'".to_string()),
+        };
+    }
+
+    if body.is_empty() {
+        return Err("The request has no image: send it as the body, a multipart file or JSON".to_string());
+    }
+    Ok(ImageSource::Upload {
+        name: name.unwrap_or_else(|| DEFAULT_UPLOAD_NAME.to_string()),
+        data: body,
+    })
+}
+
+/// A requested path, if it is inside `root` once symlinks and `..` are resolved.
+pub fn resolve_path(path: &str, root: Option<&Path>) -> Result<PathBuf, Reply> {
+    let Some(root) = root else {
+        return Err(error_reply(403, "Reading files by path is disabled (start the server with --path-root)"));
+    };
+    let root = root
+        .canonicalize()
+        .map_err(|e| error_reply(500, format!("Cannot resolve --path-root '{}': {}", root.display(), e)))?;
+    let requested = Path::new(path);
+    let requested = if requested.is_absolute() { requested.to_path_buf() } else { root.join(requested) };
+    let resolved = requested
+        .canonicalize()
+        .map_err(|_| error_reply(404, format!("File not found: {}", path)))?;
+    if !resolved.starts_with(&root) {
+        return Err(error_reply(403, format!("'{}' is outside --path-root", path)));
+    }
+    Ok(resolved)
+}
+
+fn describe_reply(result: Result<nineladies::Described, ItemError>) -> Reply {
+    match result {
+        Ok(described) => (200, serde_json::to_string(&described.record).expect("records serialize")),
+        Err(ItemError::Invalid(e)) => error_reply(400, e),
+        Err(ItemError::Transform(e)) => error_reply(422, e),
+        Err(ItemError::Local(e)) => error_reply(500, e),
+        Err(ItemError::Download(e)) => error_reply(502, e),
+        Err(ItemError::Unverified(e)) => error_reply(409, e),
+        Err(ItemError::Call(e @ CallError::Unreachable(_))) => error_reply(503, e.to_string()),
+        Err(ItemError::Call(e)) => error_reply(502, e.to_string()),
+    }
+}
+
+fn describe(runner: &BatchRunner, options: &ServeOptions, url: &str, content_type: Option<&str>, body: Vec<u8>) -> Reply {
+    match parse_describe_body(url, content_type, body) {
+        Ok(ImageSource::Upload { name, data }) => describe_reply(runner.describe_data(&name, &data)),
+        Ok(ImageSource::Path(path)) => match resolve_path(&path, options.path_root.as_deref()) {
+            Ok(resolved) => describe_reply(runner.describe(&resolved.to_string_lossy())),
+            Err(reply) => reply,
+        },
+        Err(e) => error_reply(400, e),
+    }
+}
+
+/// Answer one request. `body` is `None` when it was larger than the upload limit.
+fn route(
+    runner: &BatchRunner,
+    options: &ServeOptions,
+    method: &str,
+    url: &str,
+    content_type: Option<&str>,
+    body: Option<Vec<u8>>,
+) -> Reply {
+    let path = url.split('?').next().unwrap_or_default();
+    match (method, path) {
+        ("POST", "/describe") => match body {
+            Some(body) => describe(runner, options, url, content_type, body),
+            None => error_reply(413, format!("Uploads are limited to {} bytes", options.max_upload_bytes)),
+        },
+        (_, "/describe") => error_reply(405, "Use POST for /describe"),
+        ("GET", "/healthz") => (200, serde_json::json!({"status": "ok"}).to_string()),
+        ("GET", "/readyz") => ready(runner),
+        ("GET", "/openapi.json") => (200, openapi(options).to_string()),
+        (_, "/healthz" | "/readyz" | "/openapi.json") => error_reply(405, format!("Use GET for {}", path)),
+        _ => error_reply(404, format!("No such endpoint: {}", path)),
+    }
+}
+
+/// Ready when the model server answers and has the model (if one was named).
+fn ready(runner: &BatchRunner) -> Reply {
+    let backend = runner.backend();
+    match (backend.list_models(), backend.model()) {
+        (Err(e), _) => (503, serde_json::json!({"status": "unavailable", "error": format!("{}: {}", backend.url(), e)}).to_string()),
+        (Ok(models), Some(model)) if !model_available(&models, model) => (
+            503,
+            serde_json::json!({"status": "unavailable", "error": format!("Model '{}' is not available on {}", model, backend.url())})
+                .to_string(),
+        ),
+        (Ok(_), _) => (200, serde_json::json!({"status": "ready"}).to_string()),
+    }
+}
+
+/// The OpenAPI 3 document for the endpoints above.
+fn openapi(options: &ServeOptions) -> serde_json::Value {
+    let error = |description: &str| {
+        serde_json::json!({
+            "description": description,
+            "content": {"application/json": {"schema": {"$ref": "#/components/schemas/Error"}}}
+        })
+    };
+    let status = |description: &str| {
+        serde_json::json!({
+            "description": description,
+            "content": {"application/json": {"schema": {"$ref": "#/components/schemas/Status"}}}
+        })
+    };
+    let mut json_request = serde_json::json!({
+        "type": "object",
+        "properties": {
+            "image": {"type": "string", "format": "byte", "description": "Base64 image bytes"},
+            "name": {"type": "string", "description": "File name for the record"}
+        }
+    });
+    if options.path_root.is_some() {
+        json_request["properties"]["path"] =
+            serde_json::json!({"type": "string", "description": "A file under the server's --path-root, instead of 'image'"});
+    }
+
+    serde_json::json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "9ladies",
+            "version": env!("CARGO_PKG_VERSION"),
+            "description": "Image descriptions from a vision language model"
+        },
+        "paths": {
+            "/describe": {
+                "post": {
+                    "summary": "Describe an image",
+                    "parameters": [{
+                        "name": "name",
+                        "in": "query",
+                        "required": false,
+                        "schema": {"type": "string"},
+                        "description": "File name for the record, for raw and multipart uploads"
+                    }],
+                    "requestBody": {
+                        "required": true,
+                        "content": {
+                            "image/*": {"schema": {"type": "string", "format": "binary"}},
+                            "multipart/form-data": {
+                                "schema": {
+                                    "type": "object",
+                                    "properties": {"file": {"type": "string", "format": "binary"}}
+                                }
+                            },
+                            "application/json": {"schema": json_request}
+                        }
+                    },
+                    "responses": {
+                        "200": {
+                            "description": "The output record",
+                            "content": {"application/json": {"schema": {"$ref": "#/components/schemas/OutputRecord"}}}
+                        },
+                        "400": error("The request or the image is invalid"),
+                        "413": error(&format!("The upload is over {} bytes", options.max_upload_bytes)),
+                        "422": error("A script hook rejected the image"),
+                        "502": error("The model request failed"),
+                        "503": error("The model server can't be reached")
+                    }
+                }
+            },
+            "/healthz": {
+                "get": {"summary": "Liveness", "responses": {"200": status("The server is running")}}
+            },
+            "/readyz": {
+                "get": {
+                    "summary": "Readiness: the model server is reachable and has the model",
+                    "responses": {"200": status("Ready"), "503": status("The model server or model is unavailable")}
+                }
+            },
+            "/openapi.json": {
+                "get": {"summary": "This document", "responses": {"200": {"description": "OpenAPI 3 document"}}}
+            }
+        },
+        "components": {
+            "schemas": {
+                "OutputRecord": {
+                    "type": "object",
+                    "required": ["file", "sha256", "response"],
+                    "properties": {
+                        "file": {"type": "string"},
+                        "sha256": {"type": "string", "description": "Hex SHA-256 of the image"},
+                        "response": {"description": "Parsed JSON when the model replied with JSON, otherwise the reply text"},
+                        "timing": {
+                            "type": "object",
+                            "properties": {
+                                "wall_ms": {"type": "integer"},
+                                "load_ms": {"type": "integer"},
+                                "prompt_ms": {"type": "integer"},
+                                "generation_ms": {"type": "integer"}
+                            }
+                        }
+                    }
+                },
+                "Error": {
+                    "type": "object",
+                    "required": ["error"],
+                    "properties": {"error": {"type": "string"}}
+                },
+                "Status": {
+                    "type": "object",
+                    "required": ["status"],
+                    "properties": {"status": {"type": "string"}, "error": {"type": "string"}}
+                }
+            }
+        }
+    })
+}
+
+/// Probes and the API document, which are polled often and not worth an info line each.
+fn is_quiet_endpoint(path: &str) -> bool {
+    matches!(path, "/healthz" | "/readyz" | "/openapi.json")
+}
+
+/// Read a request body, or `None` if it is over `limit` bytes.
+pub fn read_body(request: &mut tiny_http::Request, limit: usize) -> std::io::Result<Option<Vec<u8>>> {
+    if request.body_length().is_some_and(|len| len > limit) {
+        return Ok(None);
+    }
+    let mut body = Vec::new();
+    request.as_reader().take(limit as u64 + 1).read_to_end(&mut body)?;
+    Ok((body.len() <= limit).then_some(body))
+}
+
+fn handle(runner: &BatchRunner, options: &ServeOptions, mut request: tiny_http::Request) {
+    let started = Instant::now();
+    let method = request.method().as_str().to_string();
+    let url = request.url().to_string();
+    let content_type = request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv("Content-Type"))
+        .map(|h| h.value.as_str().to_string());
+
+    let (status, body) = match read_body(&mut request, options.max_upload_bytes) {
+        Ok(body) => route(runner, options, &method, &url, content_type.as_deref(), body),
+        Err(e) => error_reply(400, format!("Failed to read the request body: {}", e)),
+    };
+    let elapsed_ms = started.elapsed().as_millis() as u64;
+    if is_quiet_endpoint(url.split('?').next().unwrap_or_default()) && status == 200 {
+        debug!(method = method.as_str(), url = url.as_str(), status, elapsed_ms, "Request handled");
+    } else {
+        info!(method = method.as_str(), url = url.as_str(), status, elapsed_ms, "Request handled");
+    }
+
+    let response = tiny_http::Response::from_string(body)
+        .with_status_code(status)
+        .with_header(tiny_http::Header::from_bytes("Content-Type", "application/json").expect("valid header"));
+    if let Err(e) = request.respond(response) {
+        warn!("Failed to send the response to {} {}: {}", method, url, e);
+    }
+}
+
+/// Serve requests on `listen` with `workers` threads until Ctrl+C.
+pub fn serve(
+    runner: BatchRunner,
+    options: ServeOptions,
+    listen: &str,
+    workers: usize,
+    shutdown: &Shutdown,
+) -> Result<(), String> {
+    let address = listen_address(listen);
+    let server = tiny_http::Server::http(&address).map_err(|e| format!("Cannot listen on {}: {}", address, e))?;
+    info!("Listening on http://{} (POST /describe, API document at /openapi.json)", address);
+
+    let server = Arc::new(server);
+    let shared = Arc::new((runner, options));
+    thread::scope(|scope| {
+        for _ in 0..workers.max(1) {
+            let server = Arc::clone(&server);
+            let shared = Arc::clone(&shared);
+            scope.spawn(move || {
+                while !shutdown.requested() {
+                    match server.recv_timeout(POLL_INTERVAL) {
+                        Ok(Some(request)) => handle(&shared.0, &shared.1, request),
+                        Ok(None) => {}
+                        Err(e) => warn!("Failed to accept a request: {}", e),
+                    }
+                }
+            });
+        }
+    });
+    info!("Server stopped");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nineladies::{Api, Backend, PromptConfig};
+
+    fn runner() -> BatchRunner {
+        let config = PromptConfig {
+            system: String::new(),
+            prompt: "Describe this.".to_string(),
+            temperature: 0.0,
+            model: None,
+            schema: None,
+            seed: None,
+            think: None,
+            schema_via: None,
+        };
+        // Nothing listens on port 9; the tests never get as far as a model request
+        BatchRunner::new(Backend::new(Api::Ollama, "http://127.0.0.1:9", None, Some(1)), config)
+    }
+
+    fn options() -> ServeOptions {
+        ServeOptions {
+            max_upload_bytes: 1024,
+            path_root: None,
+        }
+    }
+
+    #[test]
+    fn test_listen_address() {
+        assert_eq!(listen_address(":8080"), "0.0.0.0:8080");
+        assert_eq!(listen_address("127.0.0.1:9000"), "127.0.0.1:9000");
+    }
+
+    #[test]
+    fn test_parse_describe_body() {
+        let raw = parse_describe_body("/describe?name=my%20cat.jpg", Some("image/jpeg"), b"bytes".to_vec());
+        assert_eq!(raw, Ok(ImageSource::Upload { name: "my cat.jpg".to_string(), data: b"bytes".to_vec() }));
+
+        let json = parse_describe_body("/describe", Some("application/json"), br#"{"image": "aGk=", "name": "a.png"}"#.to_vec());
+        assert_eq!(json, Ok(ImageSource::Upload { name: "a.png".to_string(), data: b"hi".to_vec() }));
+
+        let path = parse_describe_body("/describe", Some("application/json"), br#"{"path": "a.jpg"}"#.to_vec());
+        assert_eq!(path, Ok(ImageSource::Path("a.jpg".to_string())));
+
+        assert!(parse_describe_body("/describe", Some("application/json"), b"{}".to_vec()).is_err());
+        assert!(parse_describe_body("/describe", None, Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_multipart_upload() {
+        let body = b"--XyZ\r\nContent-Disposition: form-data; name=\"note\"\r\n\r\nhello\r\n\
+--XyZ\r\nContent-Disposition: form-data; name=\"file\"; filename=\"red.png\"\r\nContent-Type: image/png\r\n\r\n\
+PNGDATA\r\n--XyZ--\r\n";
+        let source = parse_describe_body("/describe", Some("multipart/form-data; boundary=XyZ"), body.to_vec());
+        assert_eq!(source, Ok(ImageSource::Upload { name: "red.png".to_string(), data: b"PNGDATA".to_vec() }));
+    }
+
+    #[test]
+    fn test_route_errors() {
+        let runner = runner();
+        let options = options();
+
+        assert_eq!(route(&runner, &options, "GET", "/nope", None, Some(Vec::new())).0, 404);
+        assert_eq!(route(&runner, &options, "GET", "/describe", None, Some(Vec::new())).0, 405);
+        assert_eq!(route(&runner, &options, "POST", "/describe", None, None).0, 413);
+
+        let (status, body) = route(&runner, &options, "POST", "/describe", Some("text/plain"), Some(b"not an image".to_vec()));
+        assert_eq!(status, 400);
+        assert!(body.contains("Not a valid image format"), "{}", body);
+
+        let path = br#"{"path": "/etc/passwd"}"#.to_vec();
+        assert_eq!(route(&runner, &options, "POST", "/describe", Some("application/json"), Some(path)).0, 403);
+    }
+
+    #[test]
+    fn test_health_and_openapi() {
+        let runner = runner();
+        let options = options();
+
+        assert_eq!(route(&runner, &options, "GET", "/healthz", None, Some(Vec::new())), (200, r#"{"status":"ok"}"#.to_string()));
+        let (status, body) = route(&runner, &options, "GET", "/readyz", None, Some(Vec::new()));
+        assert_eq!(status, 503);
+        assert!(body.contains("unavailable"), "{}", body);
+
+        let (status, body) = route(&runner, &options, "GET", "/openapi.json", None, Some(Vec::new()));
+        assert_eq!(status, 200);
+        let doc: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(doc["openapi"], "3.0.3");
+        assert!(doc["paths"]["/describe"]["post"]["responses"]["413"].is_object());
+        // Path requests are only documented when they are allowed
+        assert!(doc["paths"]["/describe"]["post"]["requestBody"]["content"]["application/json"]["schema"]["properties"]["path"].is_null());
+    }
+
+    #[test]
+    fn test_resolve_path_stays_in_root() {
+        let root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+        assert!(resolve_path("red.png", Some(&root)).is_ok());
+        assert_eq!(resolve_path("../../Cargo.toml", Some(&root)).unwrap_err().0, 403);
+        assert_eq!(resolve_path("missing.png", Some(&root)).unwrap_err().0, 404);
+    }
+}
@@ -0,0 +1,93 @@
+//! Graceful shutdown on SIGINT/SIGTERM.
+//!
+//! The first signal asks the run to stop once the file in flight is finished, so the output
+//! never ends in a truncated record. A second signal exits immediately.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tracing::warn;
+
+/// Exit status after an interrupt, following the shell convention of 128 + SIGINT.
+pub const INTERRUPTED_STATUS: u8 = 130;
+
+/// How often `wait` checks for a stop request
+const WAIT_STEP: Duration = Duration::from_millis(250);
+
+#[derive(Clone, Default)]
+pub struct Shutdown {
+    requested: Arc<AtomicBool>,
+}
+
+impl Shutdown {
+    /// Install the signal handlers. On platforms without Unix signals this never triggers,
+    /// and Ctrl+C keeps its default behavior.
+    pub fn install() -> Result<Self, String> {
+        let shutdown = Shutdown::default();
+        #[cfg(unix)]
+        shutdown.listen()?;
+        Ok(shutdown)
+    }
+
+    #[cfg(unix)]
+    fn listen(&self) -> Result<(), String> {
+        use signal_hook::consts::{SIGINT, SIGTERM};
+        use signal_hook::iterator::Signals;
+
+        let mut signals = Signals::new([SIGINT, SIGTERM])
+            .map_err(|e| format!("Failed to install signal handlers: {}", e))?;
+        let requested = Arc::clone(&self.requested);
+
+        std::thread::spawn(move || {
+            for _ in signals.forever() {
+                if requested.swap(true, Ordering::SeqCst) {
+                    std::process::exit(INTERRUPTED_STATUS as i32);
+                }
+                warn!("Interrupted: finishing the current file (press Ctrl+C again to stop immediately)");
+            }
+        });
+        Ok(())
+    }
+
+    /// Whether a signal has asked the run to stop.
+    pub fn requested(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+
+    /// Sleep for `delay`, returning early with `false` if a stop is requested meanwhile.
+    pub fn wait(&self, delay: Duration) -> bool {
+        let start = Instant::now();
+        while start.elapsed() < delay {
+            if self.requested() {
+                return false;
+            }
+            std::thread::sleep(WAIT_STEP.min(delay.saturating_sub(start.elapsed())));
+        }
+        !self.requested()
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signal_requests_shutdown() {
+        let shutdown = Shutdown::install().unwrap();
+        assert!(!shutdown.requested());
+
+        signal_hook::low_level::raise(signal_hook::consts::SIGTERM).unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !shutdown.requested() && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert!(shutdown.requested());
+
+        // Waits end early once a stop is requested
+        let start = Instant::now();
+        assert!(!shutdown.wait(Duration::from_secs(5)));
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+}
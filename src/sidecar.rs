@@ -0,0 +1,116 @@
+//! Per-image overrides: a `{stem}.9ladies.json` file next to an image changes the request
+//! for that image alone, so a few images in a big batch can get extra context, a different
+//! prompt or temperature without splitting the batch up.
+//!
+//! ```json
+//! {"context": "The harvest festival in Ashby, 1923.", "temperature": 0.2}
+//! ```
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+use serde::Deserialize;
+
+use crate::presets::Preset;
+use crate::prompt::{load_prompt_config, PromptConfig};
+
+/// What a sidecar file name adds to the image's stem.
+pub const SUFFIX: &str = ".9ladies.json";
+
+/// The overrides in a sidecar file.
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Sidecar {
+    /// A prompt file to use instead of the run's, relative to the sidecar
+    pub prompt: Option<String>,
+    /// A built-in preset to use instead of the run's prompt
+    pub preset: Option<String>,
+    /// Text added to the end of the question
+    pub context: Option<String>,
+    pub temperature: Option<f32>,
+}
+
+/// Where the sidecar for `image` would be.
+pub fn path_for(image: &Path) -> PathBuf {
+    let stem = image.file_stem().unwrap_or_default().to_string_lossy();
+    image.with_file_name(format!("{}{}", stem, SUFFIX))
+}
+
+/// The sidecar next to `image` and where it was, if there is one.
+pub fn find(image: &Path) -> Result<Option<(PathBuf, Sidecar)>, String> {
+    let path = path_for(image);
+    let json = match fs::read_to_string(&path) {
+        Ok(json) => json,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(format!("Failed to read sidecar '{}': {}", path.display(), e)),
+    };
+    let sidecar: Sidecar =
+        serde_json::from_str(&json).map_err(|e| format!("Invalid sidecar '{}': {}", path.display(), e))?;
+    if sidecar.prompt.is_some() && sidecar.preset.is_some() {
+        return Err(format!("Sidecar '{}' sets both 'prompt' and 'preset'; use one", path.display()));
+    }
+    Ok(Some((path, sidecar)))
+}
+
+impl Sidecar {
+    /// The prompt config the sidecar at `path` names in place of the run's, if any.
+    pub fn prompt_config(&self, path: &Path) -> Result<Option<PromptConfig>, String> {
+        if let Some(prompt) = &self.prompt {
+            let prompt = path.parent().unwrap_or(Path::new("")).join(prompt);
+            return load_prompt_config(&prompt.to_string_lossy()).map(Some);
+        }
+        match &self.preset {
+            Some(name) => Preset::from_str(name, true)
+                .map(|preset| Some(preset.config()))
+                .map_err(|_| format!("Unknown preset '{}' in sidecar '{}'", name, path.display())),
+            None => Ok(None),
+        }
+    }
+
+    /// `config` with the sidecar's context and temperature.
+    pub fn apply(&self, mut config: PromptConfig) -> PromptConfig {
+        if let Some(context) = self.context.as_deref().map(str::trim).filter(|context| !context.is_empty()) {
+            config.prompt = format!("{}\n\n{}", config.prompt, context);
+        }
+        if let Some(temperature) = self.temperature {
+            config.temperature = temperature;
+        }
+        config
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_and_apply() {
+        let dir = std::env::temp_dir().join(format!("nineladies_sidecar_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let image = dir.join("festival.v2.jpg");
+        assert_eq!(path_for(&image), dir.join("festival.v2.9ladies.json"));
+        assert_eq!(find(&image), Ok(None));
+
+        fs::write(
+            dir.join("festival.v2.9ladies.json"),
+            r#"{"context": "Ashby, 1923.", "temperature": 0.1, "prompt": "names.json"}"#,
+        )
+        .unwrap();
+        fs::write(dir.join("names.json"), r#"{"system": "", "prompt": "Who is this?", "temperature": 0.5}"#).unwrap();
+        let (path, sidecar) = find(&image).unwrap().unwrap();
+        let config = sidecar.prompt_config(&path).unwrap().unwrap();
+        assert_eq!(config.prompt, "Who is this?");
+        let config = sidecar.apply(config);
+        assert_eq!(config.prompt, "Who is this?\n\nAshby, 1923.");
+        assert_eq!(config.temperature, 0.1);
+
+        fs::write(&path, r#"{"preset": "alt-text"}"#).unwrap();
+        let (path, sidecar) = find(&image).unwrap().unwrap();
+        assert_eq!(sidecar.prompt_config(&path).unwrap().unwrap().prompt, Preset::AltText.config().prompt);
+
+        fs::write(&path, r#"{"temprature": 0.1}"#).unwrap();
+        assert!(find(&image).unwrap_err().contains("unknown field"));
+        fs::remove_dir_all(dir).ok();
+    }
+}
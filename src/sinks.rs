@@ -0,0 +1,493 @@
+//! Destinations that receive each output record in addition to stdout.
+
+#[cfg(any(feature = "postgres", feature = "duckdb"))]
+use crate::flatten::{self, Flatten};
+use crate::OutputRecord;
+
+/// A destination for completed output records.
+pub trait RecordSink {
+    fn write(&mut self, record: &OutputRecord) -> Result<(), String>;
+}
+
+/// Validate a table name (optionally schema-qualified, e.g. `media.descriptions`)
+/// and return it double-quoted for use in SQL.
+pub fn quote_table_name(name: &str) -> Result<String, String> {
+    let parts: Vec<&str> = name.split('.').collect();
+    if parts.len() > 2 {
+        return Err(format!("Invalid table name '{}'", name));
+    }
+
+    let mut quoted = Vec::new();
+    for part in parts {
+        let valid_start = part.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_');
+        if !valid_start || !part.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(format!(
+                "Invalid table name '{}' (use letters, digits and underscores)",
+                name
+            ));
+        }
+        quoted.push(format!("\"{}\"", part));
+    }
+
+    Ok(quoted.join("."))
+}
+
+/// A column name double-quoted for use in SQL. Flattened field names can hold any character,
+/// so quotes in them are doubled.
+#[cfg(any(feature = "postgres", feature = "duckdb"))]
+fn quote_column(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// The columns the SQL sinks create for themselves, which flattened fields must not take.
+#[cfg(any(feature = "postgres", feature = "duckdb"))]
+const OWN_COLUMNS: [&str; 5] = ["id", "file", "response", "record", "created_at"];
+
+/// The fields of `record`'s response as text columns, with `--flatten`. Text responses are
+/// already in the `response` column and give none; a field named like one of the table's
+/// own columns is named `response.<field>` instead.
+#[cfg(any(feature = "postgres", feature = "duckdb"))]
+fn flattened_columns(flatten: &Flatten, record: &OutputRecord) -> Vec<(String, Option<String>)> {
+    if !record.response.is_object() {
+        return Vec::new();
+    }
+    flatten
+        .fields(&record.response)
+        .into_iter()
+        .map(|(field, value)| {
+            let column = match OWN_COLUMNS.contains(&field.as_str()) {
+                true => format!("response.{}", field),
+                false => field,
+            };
+            (column, (!value.is_null()).then(|| flatten::text(&value)))
+        })
+        .collect()
+}
+
+/// Postgres reports server-side failures as a bare "db error"; surface the server's message.
+#[cfg(feature = "postgres")]
+fn describe_pg_error(e: &postgres::Error) -> String {
+    match e.as_db_error() {
+        Some(db) => db.message().to_string(),
+        None => e.to_string(),
+    }
+}
+
+/// Inserts records into a PostgreSQL table, creating it if needed.
+#[cfg(feature = "postgres")]
+pub struct PostgresSink {
+    client: postgres::Client,
+    table: String,
+    insert: postgres::Statement,
+    flatten: Option<Flatten>,
+    /// Flattened columns known to be in the table
+    columns: std::collections::HashSet<String>,
+}
+
+#[cfg(feature = "postgres")]
+impl PostgresSink {
+    pub fn connect(url: &str, table: &str) -> Result<Self, String> {
+        let table = quote_table_name(table)?;
+
+        let connector = native_tls::TlsConnector::new()
+            .map_err(|e| format!("Failed to set up TLS for Postgres: {}", e))?;
+        let tls = postgres_native_tls::MakeTlsConnector::new(connector);
+        let mut client = postgres::Client::connect(url, tls)
+            .map_err(|e| format!("Failed to connect to Postgres: {}", describe_pg_error(&e)))?;
+
+        client
+            .batch_execute(&format!(
+                "CREATE TABLE IF NOT EXISTS {} (
+                    id BIGSERIAL PRIMARY KEY,
+                    file TEXT NOT NULL,
+                    response JSONB NOT NULL,
+                    record JSONB NOT NULL,
+                    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+                )",
+                table
+            ))
+            .map_err(|e| format!("Failed to create Postgres table {}: {}", table, describe_pg_error(&e)))?;
+
+        let insert = client
+            .prepare(&format!(
+                "INSERT INTO {} (file, response, record) VALUES ($1, $2, $3)",
+                table
+            ))
+            .map_err(|e| format!("Failed to prepare Postgres insert: {}", describe_pg_error(&e)))?;
+
+        Ok(PostgresSink {
+            client,
+            table,
+            insert,
+            flatten: None,
+            columns: Default::default(),
+        })
+    }
+
+    /// Also write the response's fields to a text column each, added to the table as they
+    /// first appear.
+    pub fn with_flatten(mut self, flatten: Option<Flatten>) -> Self {
+        self.flatten = flatten;
+        self
+    }
+}
+
+#[cfg(feature = "postgres")]
+impl RecordSink for PostgresSink {
+    fn write(&mut self, record: &OutputRecord) -> Result<(), String> {
+        let full = serde_json::to_value(record).map_err(|e| e.to_string())?;
+        let columns = match &self.flatten {
+            Some(flatten) => flattened_columns(flatten, record),
+            None => Vec::new(),
+        };
+        if columns.is_empty() {
+            self.client
+                .execute(&self.insert, &[&record.file, &record.response, &full])
+                .map_err(|e| format!("Postgres insert failed: {}", describe_pg_error(&e)))?;
+            return Ok(());
+        }
+
+        for (column, _) in &columns {
+            if !self.columns.contains(column) {
+                self.client
+                    .batch_execute(&format!(
+                        "ALTER TABLE {} ADD COLUMN IF NOT EXISTS {} TEXT",
+                        self.table,
+                        quote_column(column)
+                    ))
+                    .map_err(|e| format!("Failed to add column '{}' to Postgres table {}: {}", column, self.table, describe_pg_error(&e)))?;
+                self.columns.insert(column.clone());
+            }
+        }
+        let names: String = columns.iter().map(|(column, _)| format!(", {}", quote_column(column))).collect();
+        let placeholders: String = (4..4 + columns.len()).map(|i| format!(", ${}", i)).collect();
+        let mut params: Vec<&(dyn postgres::types::ToSql + Sync)> = vec![&record.file, &record.response, &full];
+        params.extend(columns.iter().map(|(_, value)| value as &(dyn postgres::types::ToSql + Sync)));
+        self.client
+            .execute(
+                &format!("INSERT INTO {} (file, response, record{}) VALUES ($1, $2, $3{})", self.table, names, placeholders),
+                &params,
+            )
+            .map_err(|e| format!("Postgres insert failed: {}", describe_pg_error(&e)))?;
+        Ok(())
+    }
+}
+
+/// Stable numeric Qdrant point id for a file path (64-bit FNV-1a), so re-running a batch
+/// updates existing points instead of duplicating them.
+pub fn qdrant_point_id(file: &str) -> u64 {
+    file.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// The text that gets embedded for a record: plain responses as-is, JSON responses serialized.
+pub fn embedding_text(response: &serde_json::Value) -> String {
+    match response {
+        serde_json::Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// Embeds each record's response with an Ollama embedding model and upserts the vector,
+/// with the record as payload, into a Qdrant collection.
+pub struct QdrantSink {
+    client: reqwest::blocking::Client,
+    collection_url: String,
+    api_key: Option<String>,
+    ollama_url: String,
+    embed_model: String,
+    collection_ready: bool,
+}
+
+impl QdrantSink {
+    pub fn new(
+        client: reqwest::blocking::Client,
+        qdrant_url: &str,
+        collection: &str,
+        api_key: Option<String>,
+        ollama_url: &str,
+        embed_model: &str,
+    ) -> Self {
+        QdrantSink {
+            client,
+            collection_url: format!("{}/collections/{}", qdrant_url.trim_end_matches('/'), collection),
+            api_key,
+            ollama_url: ollama_url.to_string(),
+            embed_model: embed_model.to_string(),
+            collection_ready: false,
+        }
+    }
+
+    fn request(&self, method: reqwest::Method, url: &str) -> reqwest::blocking::RequestBuilder {
+        let builder = self.client.request(method, url);
+        match &self.api_key {
+            Some(key) => builder.header("api-key", key),
+            None => builder,
+        }
+    }
+
+    /// Create the collection on first write, sized to the embedding model's output.
+    fn ensure_collection(&mut self, dimensions: usize) -> Result<(), String> {
+        if self.collection_ready {
+            return Ok(());
+        }
+
+        let existing = self
+            .request(reqwest::Method::GET, &self.collection_url)
+            .send()
+            .map_err(|e| format!("Qdrant request failed: {}", e))?;
+
+        if existing.status() == reqwest::StatusCode::NOT_FOUND {
+            let body = serde_json::json!({"vectors": {"size": dimensions, "distance": "Cosine"}});
+            let created = self
+                .request(reqwest::Method::PUT, &self.collection_url)
+                .json(&body)
+                .send()
+                .map_err(|e| format!("Qdrant request failed: {}", e))?;
+            if !created.status().is_success() {
+                let status = created.status();
+                let text = created.text().unwrap_or_default();
+                return Err(format!("Qdrant collection create returned {}: {}", status, text));
+            }
+        } else if !existing.status().is_success() {
+            return Err(format!("Qdrant returned {}", existing.status()));
+        }
+
+        self.collection_ready = true;
+        Ok(())
+    }
+}
+
+impl RecordSink for QdrantSink {
+    fn write(&mut self, record: &OutputRecord) -> Result<(), String> {
+        let text = embedding_text(&record.response);
+        let vector = crate::backend::call_embed(&self.client, &self.ollama_url, &self.embed_model, &text)?;
+        self.ensure_collection(vector.len())?;
+
+        let body = serde_json::json!({
+            "points": [{
+                "id": qdrant_point_id(&record.file),
+                "vector": vector,
+                "payload": record,
+            }]
+        });
+        let url = format!("{}/points?wait=true", self.collection_url);
+        let response = self
+            .request(reqwest::Method::PUT, &url)
+            .json(&body)
+            .send()
+            .map_err(|e| format!("Qdrant request failed: {}", e))?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().unwrap_or_default();
+            return Err(format!("Qdrant upsert returned {}: {}", status, text));
+        }
+
+        Ok(())
+    }
+}
+
+/// Appends records to a table in a DuckDB database file, creating it if needed.
+#[cfg(feature = "duckdb")]
+pub struct DuckDbSink {
+    conn: duckdb::Connection,
+    table: String,
+    flatten: Option<Flatten>,
+    /// Flattened columns known to be in the table
+    columns: std::collections::HashSet<String>,
+}
+
+#[cfg(feature = "duckdb")]
+impl DuckDbSink {
+    pub fn open(path: &str, table: &str) -> Result<Self, String> {
+        let table = quote_table_name(table)?;
+
+        let conn = duckdb::Connection::open(path)
+            .map_err(|e| format!("Failed to open DuckDB database '{}': {}", path, e))?;
+
+        conn.execute_batch(&format!(
+            "CREATE TABLE IF NOT EXISTS {} (
+                file VARCHAR NOT NULL,
+                response JSON NOT NULL,
+                record JSON NOT NULL,
+                created_at TIMESTAMP NOT NULL DEFAULT current_timestamp
+            )",
+            table
+        ))
+        .map_err(|e| format!("Failed to create DuckDB table {}: {}", table, e))?;
+
+        Ok(DuckDbSink {
+            conn,
+            table,
+            flatten: None,
+            columns: Default::default(),
+        })
+    }
+
+    /// Also write the response's fields to a text column each, added to the table as they
+    /// first appear.
+    pub fn with_flatten(mut self, flatten: Option<Flatten>) -> Self {
+        self.flatten = flatten;
+        self
+    }
+}
+
+#[cfg(feature = "duckdb")]
+impl RecordSink for DuckDbSink {
+    fn write(&mut self, record: &OutputRecord) -> Result<(), String> {
+        let response = serde_json::to_string(&record.response).map_err(|e| e.to_string())?;
+        let full = serde_json::to_string(record).map_err(|e| e.to_string())?;
+        let columns = match &self.flatten {
+            Some(flatten) => flattened_columns(flatten, record),
+            None => Vec::new(),
+        };
+        for (column, _) in &columns {
+            if !self.columns.contains(column) {
+                self.conn
+                    .execute_batch(&format!(
+                        "ALTER TABLE {} ADD COLUMN IF NOT EXISTS {} VARCHAR",
+                        self.table,
+                        quote_column(column)
+                    ))
+                    .map_err(|e| format!("Failed to add column '{}' to DuckDB table {}: {}", column, self.table, e))?;
+                self.columns.insert(column.clone());
+            }
+        }
+
+        let names: String = columns.iter().map(|(column, _)| format!(", {}", quote_column(column))).collect();
+        let insert = format!(
+            "INSERT INTO {} (file, response, record{}) VALUES (?, ?, ?{})",
+            self.table,
+            names,
+            ", ?".repeat(columns.len())
+        );
+        let mut params = vec![Some(record.file.clone()), Some(response), Some(full)];
+        params.extend(columns.into_iter().map(|(_, value)| value));
+        self.conn
+            .prepare_cached(&insert)
+            .and_then(|mut stmt| stmt.execute(duckdb::params_from_iter(params)))
+            .map_err(|e| format!("DuckDB insert failed: {}", e))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quote_table_name() {
+        assert_eq!(quote_table_name("results").unwrap(), "\"results\"");
+        assert_eq!(quote_table_name("media.descriptions").unwrap(), "\"media\".\"descriptions\"");
+        assert_eq!(quote_table_name("_t1").unwrap(), "\"_t1\"");
+    }
+
+    #[test]
+    fn test_quote_table_name_rejects_injection() {
+        assert!(quote_table_name("results; DROP TABLE x").is_err());
+        assert!(quote_table_name("a.b.c").is_err());
+        assert!(quote_table_name("1results").is_err());
+        assert!(quote_table_name("").is_err());
+        assert!(quote_table_name("bad\"name").is_err());
+    }
+
+    #[cfg(any(feature = "postgres", feature = "duckdb"))]
+    #[test]
+    fn test_flattened_columns() {
+        let flatten = Flatten::default();
+        let record = OutputRecord::new("a.jpg", "1", serde_json::json!({"file": "IMG_1", "vendor": {"name": "Aldi"}, "note": null}));
+        assert_eq!(
+            flattened_columns(&flatten, &record),
+            [
+                ("response.file".to_string(), Some("IMG_1".to_string())),
+                ("vendor.name".to_string(), Some("Aldi".to_string())),
+                ("note".to_string(), None),
+            ]
+        );
+        assert!(flattened_columns(&flatten, &OutputRecord::new("a.jpg", "1", serde_json::json!("A cat"))).is_empty());
+    }
+
+    #[test]
+    fn test_qdrant_point_id_is_stable() {
+        assert_eq!(qdrant_point_id(""), 0xcbf29ce484222325);
+        assert_eq!(qdrant_point_id("a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(qdrant_point_id("photos/a.jpg"), qdrant_point_id("photos/a.jpg"));
+        assert_ne!(qdrant_point_id("photos/a.jpg"), qdrant_point_id("photos/b.jpg"));
+    }
+
+    #[test]
+    fn test_embedding_text() {
+        assert_eq!(embedding_text(&serde_json::json!("A dog on a beach")), "A dog on a beach");
+        assert_eq!(embedding_text(&serde_json::json!({"count": 2})), r#"{"count":2}"#);
+    }
+
+    #[cfg(feature = "duckdb")]
+    #[test]
+    fn test_duckdb_sink_appends_records() {
+        let path = std::env::temp_dir().join("nineladies_sink_test.duckdb");
+        std::fs::remove_file(&path).ok();
+        let path_str = path.to_str().unwrap();
+
+        let mut sink = DuckDbSink::open(path_str, "results").unwrap();
+        let record = OutputRecord::new("a.jpg", "abc123", serde_json::json!({"category": "receipt"}));
+        sink.write(&record).unwrap();
+        sink.write(&record).unwrap();
+        drop(sink);
+
+        // Reopening appends to the existing table
+        let mut sink = DuckDbSink::open(path_str, "results").unwrap();
+        sink.write(&record).unwrap();
+
+        let (count, category): (i64, String) = sink
+            .conn
+            .query_row(
+                "SELECT count(*), any_value(response->>'category') FROM results",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(count, 3);
+        assert_eq!(category, "receipt");
+
+        drop(sink);
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[cfg(feature = "duckdb")]
+    #[test]
+    fn test_duckdb_sink_adds_flattened_columns() {
+        let path = std::env::temp_dir().join("nineladies_sink_flatten_test.duckdb");
+        std::fs::remove_file(&path).ok();
+
+        let flatten = Flatten {
+            lists: crate::flatten::ListPolicy::Join,
+            ..Flatten::default()
+        };
+        let mut sink = DuckDbSink::open(path.to_str().unwrap(), "results").unwrap().with_flatten(Some(flatten));
+        sink.write(&OutputRecord::new("a.jpg", "1", serde_json::json!({"vendor": {"name": "Aldi"}}))).unwrap();
+        sink.write(&OutputRecord::new("b.jpg", "2", serde_json::json!({"tags": ["food", "receipt"]}))).unwrap();
+        sink.write(&OutputRecord::new("c.jpg", "3", serde_json::json!("No receipt"))).unwrap();
+
+        let rows: Vec<(String, Option<String>, Option<String>)> = sink
+            .conn
+            .prepare("SELECT file, \"vendor.name\", tags FROM results ORDER BY file")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(
+            rows,
+            [
+                ("a.jpg".to_string(), Some("Aldi".to_string()), None),
+                ("b.jpg".to_string(), None, Some("food; receipt".to_string())),
+                ("c.jpg".to_string(), None, None),
+            ]
+        );
+
+        drop(sink);
+        std::fs::remove_file(&path).ok();
+    }
+}
@@ -0,0 +1,566 @@
+//! The job store: a SQLite database with one row per input, recording whether it is
+//! pending, in progress, done or failed, with timestamps and attempt counts.
+//!
+//! It is the one source of truth for `run --resume` and `--retry-failed`, for the `daemon`'s
+//! view of which files it has seen, and for `9ladies jobs` progress reports.
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+
+use crate::record::OutputRecord;
+
+/// Where an input stands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    /// Seen, but not yet attempted
+    Pending,
+    /// Being described; left behind by a run that stopped unexpectedly
+    InProgress,
+    Done,
+    Failed,
+}
+
+impl JobStatus {
+    pub const ALL: [JobStatus; 4] = [JobStatus::Pending, JobStatus::InProgress, JobStatus::Done, JobStatus::Failed];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            JobStatus::Pending => "pending",
+            JobStatus::InProgress => "in_progress",
+            JobStatus::Done => "done",
+            JobStatus::Failed => "failed",
+        }
+    }
+
+    pub fn parse(status: &str) -> Result<JobStatus, String> {
+        JobStatus::ALL
+            .into_iter()
+            .find(|s| s.as_str() == status)
+            .ok_or_else(|| format!("Unknown job status '{}' (expected pending, in_progress, done or failed)", status))
+    }
+}
+
+impl fmt::Display for JobStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A file's size and modification time, for spotting changes without reading it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FileStamp {
+    pub size: u64,
+    /// Milliseconds since the Unix epoch
+    pub modified_ms: i64,
+}
+
+impl FileStamp {
+    pub fn of(metadata: &fs::Metadata) -> FileStamp {
+        let modified_ms = metadata
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |d| d.as_millis() as i64);
+        FileStamp {
+            size: metadata.len(),
+            modified_ms,
+        }
+    }
+
+    /// The stamp of the file at `path`, or zeros if it can't be read.
+    pub fn read(path: &Path) -> FileStamp {
+        fs::metadata(path).map(|m| FileStamp::of(&m)).unwrap_or_default()
+    }
+}
+
+/// What the store knows about a file, for deciding whether to describe it again.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileState {
+    pub size: u64,
+    pub modified_ms: i64,
+    /// Empty until the file has been read
+    pub sha256: String,
+    pub status: JobStatus,
+    pub attempts: u32,
+}
+
+/// One row, for reports.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct JobEntry {
+    pub path: String,
+    pub status: JobStatus,
+    pub attempts: u32,
+    pub error: Option<String>,
+    /// Seconds since the Unix epoch
+    pub updated_at: i64,
+}
+
+/// One input looked up by its job ID, with its record once it is done.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct JobDetails {
+    pub id: i64,
+    #[serde(flatten)]
+    pub entry: JobEntry,
+    pub record: Option<serde_json::Value>,
+}
+
+/// How many inputs are in each status.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct StatusCounts {
+    pub pending: usize,
+    pub in_progress: usize,
+    pub done: usize,
+    pub failed: usize,
+}
+
+impl StatusCounts {
+    pub fn total(&self) -> usize {
+        self.pending + self.in_progress + self.done + self.failed
+    }
+}
+
+impl fmt::Display for StatusCounts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "pending      {}", self.pending)?;
+        writeln!(f, "in_progress  {}", self.in_progress)?;
+        writeln!(f, "done         {}", self.done)?;
+        writeln!(f, "failed       {}", self.failed)?;
+        writeln!(f, "total        {}", self.total())
+    }
+}
+
+pub struct JobStore {
+    conn: Connection,
+}
+
+/// The table of inputs. `id` is the job ID `submit` hands out, so it is never reused or
+/// renumbered.
+const FILES_TABLE: &str = "CREATE TABLE IF NOT EXISTS files (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    path TEXT NOT NULL UNIQUE,
+    size INTEGER NOT NULL DEFAULT 0,
+    modified_ms INTEGER NOT NULL DEFAULT 0,
+    sha256 TEXT NOT NULL DEFAULT '',
+    status TEXT NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    error TEXT,
+    record TEXT,
+    first_seen INTEGER NOT NULL,
+    started_at INTEGER,
+    finished_at INTEGER,
+    updated_at INTEGER NOT NULL
+);";
+
+fn now_secs() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs() as i64)
+}
+
+impl JobStore {
+    /// Open the database, creating it and its table if needed.
+    pub fn open(path: &str) -> Result<JobStore, String> {
+        let conn = Connection::open(path).map_err(|e| format!("Failed to open job store '{}': {}", path, e))?;
+        JobStore::init(conn).map_err(|e| format!("Failed to set up job store '{}': {}", path, e))
+    }
+
+    /// A throwaway store, for tests.
+    pub fn in_memory() -> Result<JobStore, String> {
+        let conn = Connection::open_in_memory().map_err(|e| e.to_string())?;
+        JobStore::init(conn).map_err(|e| e.to_string())
+    }
+
+    fn init(conn: Connection) -> rusqlite::Result<JobStore> {
+        // Writers wait for each other instead of failing when two processes share a database
+        conn.busy_timeout(Duration::from_secs(5))?;
+        // Stores from before files had a job ID were keyed by path alone; the rowids clients
+        // were given as job IDs become the IDs
+        let columns: Vec<String> = conn
+            .prepare("SELECT name FROM pragma_table_info('files')")?
+            .query_map([], |row| row.get(0))?
+            .collect::<rusqlite::Result<_>>()?;
+        if !columns.is_empty() && !columns.iter().any(|column| column == "id") {
+            conn.execute_batch(&format!(
+                "BEGIN;
+                ALTER TABLE files RENAME TO files_old;
+                {}
+                INSERT INTO files (id, path, size, modified_ms, sha256, status, attempts, error, record, first_seen,
+                    started_at, finished_at, updated_at)
+                SELECT rowid, path, size, modified_ms, sha256, status, attempts, error, record, first_seen,
+                    started_at, finished_at, updated_at FROM files_old;
+                DROP TABLE files_old;
+                COMMIT;",
+                FILES_TABLE
+            ))?;
+        }
+        conn.execute_batch(&format!("{}\nCREATE INDEX IF NOT EXISTS files_status ON files (status);", FILES_TABLE))?;
+        Ok(JobStore { conn })
+    }
+
+    pub fn get(&self, path: &str) -> Result<Option<FileState>, String> {
+        let row = self
+            .conn
+            .query_row(
+                "SELECT size, modified_ms, sha256, status, attempts FROM files WHERE path = ?1",
+                params![path],
+                |row| {
+                    Ok((
+                        row.get::<_, i64>(0)?,
+                        row.get::<_, i64>(1)?,
+                        row.get::<_, String>(2)?,
+                        row.get::<_, String>(3)?,
+                        row.get::<_, u32>(4)?,
+                    ))
+                },
+            )
+            .optional()
+            .map_err(|e| format!("Failed to look up '{}' in the job store: {}", path, e))?;
+        row.map(|(size, modified_ms, sha256, status, attempts)| {
+            Ok(FileState {
+                size: size as u64,
+                modified_ms,
+                sha256,
+                status: JobStatus::parse(&status)?,
+                attempts,
+            })
+        })
+        .transpose()
+    }
+
+    /// Add inputs as pending, leaving any that are already known alone.
+    pub fn enqueue(&mut self, paths: &[String]) -> Result<(), String> {
+        let error = |e: rusqlite::Error| format!("Failed to add inputs to the job store: {}", e);
+        let tx = self.conn.transaction().map_err(error)?;
+        {
+            let mut insert = tx
+                .prepare(
+                    "INSERT INTO files (path, status, first_seen, updated_at) VALUES (?1, 'pending', ?2, ?2)
+                     ON CONFLICT (path) DO NOTHING",
+                )
+                .map_err(error)?;
+            let now = now_secs();
+            for path in paths {
+                insert.execute(params![path, now]).map_err(error)?;
+            }
+        }
+        tx.commit().map_err(error)
+    }
+
+    /// Queue one input to be described, even if it was before, and return its job ID. An
+    /// input already being described is left to finish.
+    pub fn submit(&self, path: &str) -> Result<i64, String> {
+        let error = |e: rusqlite::Error| format!("Failed to add '{}' to the job store: {}", path, e);
+        self.conn
+            .execute(
+                "INSERT INTO files (path, status, first_seen, updated_at) VALUES (?1, 'pending', ?2, ?2)
+                 ON CONFLICT (path) DO UPDATE SET status = 'pending', error = NULL, updated_at = excluded.updated_at
+                 WHERE files.status != 'in_progress'",
+                params![path, now_secs()],
+            )
+            .map_err(error)?;
+        self.conn
+            .query_row("SELECT id FROM files WHERE path = ?1", params![path], |row| row.get(0))
+            .map_err(error)
+    }
+
+    /// The input with job ID `id`, as `submit` returned it.
+    pub fn job(&self, id: i64) -> Result<Option<JobDetails>, String> {
+        let row = self
+            .conn
+            .query_row(
+                "SELECT path, status, attempts, error, updated_at, record FROM files WHERE id = ?1",
+                params![id],
+                |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, u32>(2)?,
+                        row.get::<_, Option<String>>(3)?,
+                        row.get::<_, i64>(4)?,
+                        row.get::<_, Option<String>>(5)?,
+                    ))
+                },
+            )
+            .optional()
+            .map_err(|e| format!("Failed to look up job {} in the job store: {}", id, e))?;
+        row.map(|(path, status, attempts, error, updated_at, record)| {
+            Ok(JobDetails {
+                id,
+                entry: JobEntry {
+                    path,
+                    status: JobStatus::parse(&status)?,
+                    attempts,
+                    error,
+                    updated_at,
+                },
+                record: record.and_then(|record| serde_json::from_str(&record).ok()),
+            })
+        })
+        .transpose()
+    }
+
+    /// Mark an input as being described, counting the attempt.
+    pub fn start(&self, path: &str, stamp: FileStamp) -> Result<(), String> {
+        self.conn
+            .execute(
+                "INSERT INTO files (path, size, modified_ms, status, attempts, first_seen, started_at, updated_at)
+                 VALUES (?1, ?2, ?3, 'in_progress', 1, ?4, ?4, ?4)
+                 ON CONFLICT (path) DO UPDATE SET
+                    size = excluded.size, modified_ms = excluded.modified_ms, status = 'in_progress',
+                    attempts = files.attempts + 1, started_at = excluded.started_at,
+                    finished_at = NULL, updated_at = excluded.updated_at",
+                params![path, stamp.size as i64, stamp.modified_ms, now_secs()],
+            )
+            .map_err(|e| format!("Failed to update '{}' in the job store: {}", path, e))?;
+        Ok(())
+    }
+
+    fn finish(
+        &self,
+        path: &str,
+        sha256: &str,
+        status: JobStatus,
+        error: Option<&str>,
+        record: Option<&OutputRecord>,
+    ) -> Result<(), String> {
+        let record = record.map(|r| serde_json::to_string(r).expect("records serialize"));
+        let changed = self
+            .conn
+            .execute(
+                "UPDATE files SET sha256 = ?2, status = ?3, error = ?4, record = ?5, finished_at = ?6, updated_at = ?6
+                 WHERE path = ?1",
+                params![path, sha256, status.as_str(), error, record, now_secs()],
+            )
+            .map_err(|e| format!("Failed to update '{}' in the job store: {}", path, e))?;
+        if changed == 0 {
+            return Err(format!("'{}' was finished without being started in the job store", path));
+        }
+        Ok(())
+    }
+
+    /// Record an input that was described.
+    pub fn mark_done(&self, path: &str, record: &OutputRecord) -> Result<(), String> {
+        self.finish(path, &record.sha256, JobStatus::Done, None, Some(record))
+    }
+
+    /// Record an input that couldn't be described; `sha256` is empty if it couldn't be read.
+    pub fn mark_failed(&self, path: &str, sha256: &str, error: &str) -> Result<(), String> {
+        self.finish(path, sha256, JobStatus::Failed, Some(error), None)
+    }
+
+    /// Note a new modification time for a file whose content hasn't changed.
+    pub fn touch(&self, path: &str, stamp: FileStamp) -> Result<(), String> {
+        self.conn
+            .execute(
+                "UPDATE files SET size = ?2, modified_ms = ?3, updated_at = ?4 WHERE path = ?1",
+                params![path, stamp.size as i64, stamp.modified_ms, now_secs()],
+            )
+            .map_err(|e| format!("Failed to update '{}' in the job store: {}", path, e))?;
+        Ok(())
+    }
+
+    /// Return an input being described to pending, without counting the attempt, when it
+    /// couldn't be described for reasons of its own, such as the model server being down.
+    pub fn reset(&self, path: &str) -> Result<(), String> {
+        self.conn
+            .execute(
+                "UPDATE files SET status = 'pending', attempts = MAX(attempts - 1, 0), started_at = NULL, updated_at = ?2
+                 WHERE path = ?1 AND status = 'in_progress'",
+                params![path, now_secs()],
+            )
+            .map_err(|e| format!("Failed to update '{}' in the job store: {}", path, e))?;
+        Ok(())
+    }
+
+    /// Return inputs left in progress by a run that stopped unexpectedly to pending, so they
+    /// are picked up again. Returns how many there were.
+    pub fn reset_in_progress(&self) -> Result<usize, String> {
+        self.conn
+            .execute(
+                "UPDATE files SET status = 'pending', updated_at = ?1 WHERE status = 'in_progress'",
+                params![now_secs()],
+            )
+            .map_err(|e| format!("Failed to reset interrupted jobs: {}", e))
+    }
+
+    pub fn counts(&self) -> Result<StatusCounts, String> {
+        let error = |e: rusqlite::Error| format!("Failed to count jobs: {}", e);
+        let mut stmt = self
+            .conn
+            .prepare("SELECT status, COUNT(*) FROM files GROUP BY status")
+            .map_err(error)?;
+        let rows = stmt
+            .query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?)))
+            .map_err(error)?;
+
+        let mut counts = StatusCounts::default();
+        for row in rows {
+            let (status, count) = row.map_err(error)?;
+            let slot = match JobStatus::parse(&status)? {
+                JobStatus::Pending => &mut counts.pending,
+                JobStatus::InProgress => &mut counts.in_progress,
+                JobStatus::Done => &mut counts.done,
+                JobStatus::Failed => &mut counts.failed,
+            };
+            *slot = count as usize;
+        }
+        Ok(counts)
+    }
+
+    /// Every input with `status`, oldest first.
+    pub fn list(&self, status: JobStatus) -> Result<Vec<JobEntry>, String> {
+        let error = |e: rusqlite::Error| format!("Failed to list {} jobs: {}", status, e);
+        let mut stmt = self
+            .conn
+            .prepare("SELECT path, attempts, error, updated_at FROM files WHERE status = ?1 ORDER BY first_seen, path")
+            .map_err(error)?;
+        let rows = stmt
+            .query_map(params![status.as_str()], |row| {
+                Ok(JobEntry {
+                    path: row.get(0)?,
+                    status,
+                    attempts: row.get(1)?,
+                    error: row.get(2)?,
+                    updated_at: row.get(3)?,
+                })
+            })
+            .map_err(error)?;
+        rows.collect::<Result<_, _>>().map_err(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_job_lifecycle() {
+        let mut store = JobStore::in_memory().unwrap();
+        assert_eq!(store.get("a.jpg").unwrap(), None);
+
+        store.enqueue(&["a.jpg".to_string(), "b.jpg".to_string()]).unwrap();
+        assert_eq!(store.get("a.jpg").unwrap().unwrap().status, JobStatus::Pending);
+
+        let stamp = FileStamp { size: 10, modified_ms: 1000 };
+        store.start("a.jpg", stamp).unwrap();
+        store.mark_failed("a.jpg", "", "Server returned 500").unwrap();
+        let failed = store.get("a.jpg").unwrap().unwrap();
+        assert_eq!(failed.status, JobStatus::Failed);
+        assert_eq!(failed.attempts, 1);
+
+        store.start("a.jpg", stamp).unwrap();
+        let record = OutputRecord::new("a.jpg", "abc", serde_json::json!("A cat"));
+        store.mark_done("a.jpg", &record).unwrap();
+        let done = store.get("a.jpg").unwrap().unwrap();
+        assert_eq!(done.status, JobStatus::Done);
+        assert_eq!(done.sha256, "abc");
+        assert_eq!(done.attempts, 2);
+
+        // Enqueueing again doesn't reset finished inputs
+        store.enqueue(&["a.jpg".to_string()]).unwrap();
+        assert_eq!(store.get("a.jpg").unwrap().unwrap().status, JobStatus::Done);
+
+        store.touch("a.jpg", FileStamp { size: 10, modified_ms: 2000 }).unwrap();
+        assert_eq!(store.get("a.jpg").unwrap().unwrap().modified_ms, 2000);
+        assert!(store.mark_done("c.jpg", &record).is_err());
+    }
+
+    #[test]
+    fn test_counts_and_lists() {
+        let mut store = JobStore::in_memory().unwrap();
+        let paths: Vec<String> = ["a.jpg", "b.jpg", "c.jpg", "d.jpg"].iter().map(|p| p.to_string()).collect();
+        store.enqueue(&paths).unwrap();
+        store.start("a.jpg", FileStamp::default()).unwrap();
+        store.mark_failed("a.jpg", "", "File not found: a.jpg").unwrap();
+        store.start("b.jpg", FileStamp::default()).unwrap();
+
+        let counts = store.counts().unwrap();
+        assert_eq!(
+            counts,
+            StatusCounts {
+                pending: 2,
+                in_progress: 1,
+                done: 0,
+                failed: 1
+            }
+        );
+        assert_eq!(counts.total(), 4);
+
+        let failed = store.list(JobStatus::Failed).unwrap();
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].path, "a.jpg");
+        assert_eq!(failed[0].error.as_deref(), Some("File not found: a.jpg"));
+
+        assert_eq!(store.reset_in_progress().unwrap(), 1);
+        assert_eq!(store.counts().unwrap().pending, 3);
+    }
+
+    #[test]
+    fn test_submit_and_look_up() {
+        let store = JobStore::in_memory().unwrap();
+        let id = store.submit("a.jpg").unwrap();
+        assert_eq!(store.job(id).unwrap().unwrap().entry.status, JobStatus::Pending);
+        assert_eq!(store.job(id + 1).unwrap(), None);
+
+        store.start("a.jpg", FileStamp::default()).unwrap();
+        // Already being described, so it isn't queued again
+        assert_eq!(store.submit("a.jpg").unwrap(), id);
+        assert_eq!(store.get("a.jpg").unwrap().unwrap().status, JobStatus::InProgress);
+
+        store.mark_done("a.jpg", &OutputRecord::new("a.jpg", "abc", serde_json::json!("A cat"))).unwrap();
+        let job = store.job(id).unwrap().unwrap();
+        assert_eq!(job.entry.status, JobStatus::Done);
+        assert_eq!(job.record.unwrap()["response"], "A cat");
+
+        // Submitted again once done, it is described again
+        store.submit("a.jpg").unwrap();
+        assert_eq!(store.get("a.jpg").unwrap().unwrap().status, JobStatus::Pending);
+
+        store.start("a.jpg", FileStamp::default()).unwrap();
+        store.reset("a.jpg").unwrap();
+        let state = store.get("a.jpg").unwrap().unwrap();
+        assert_eq!((state.status, state.attempts), (JobStatus::Pending, 1));
+    }
+
+    #[test]
+    fn test_job_ids_survive_vacuum_and_migration() {
+        let dir = std::env::temp_dir().join(format!("nineladies_store_ids_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("jobs.db");
+        let path = path.to_str().unwrap();
+
+        // A store from before job IDs, whose rowids were handed out
+        let conn = Connection::open(path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE files (path TEXT PRIMARY KEY, size INTEGER NOT NULL DEFAULT 0,
+                modified_ms INTEGER NOT NULL DEFAULT 0, sha256 TEXT NOT NULL DEFAULT '', status TEXT NOT NULL,
+                attempts INTEGER NOT NULL DEFAULT 0, error TEXT, record TEXT, first_seen INTEGER NOT NULL,
+                started_at INTEGER, finished_at INTEGER, updated_at INTEGER NOT NULL);
+            INSERT INTO files (rowid, path, status, first_seen, updated_at) VALUES (7, 'a.jpg', 'done', 0, 0);",
+        )
+        .unwrap();
+        drop(conn);
+
+        let store = JobStore::open(path).unwrap();
+        assert_eq!(store.job(7).unwrap().unwrap().entry.path, "a.jpg");
+        let b = store.submit("b.jpg").unwrap();
+        let c = store.submit("c.jpg").unwrap();
+        store.conn.execute_batch("DELETE FROM files WHERE path = 'b.jpg'; VACUUM;").unwrap();
+        assert_eq!(store.job(c).unwrap().unwrap().entry.path, "c.jpg");
+        // IDs aren't reused
+        assert!(store.submit("d.jpg").unwrap() > c && c > b);
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_parse_status() {
+        for status in JobStatus::ALL {
+            assert_eq!(JobStatus::parse(status.as_str()).unwrap(), status);
+        }
+        assert!(JobStatus::parse("finished").is_err());
+    }
+}
@@ -0,0 +1,197 @@
+//! Inline image previews in the terminal, via the kitty graphics protocol or sixel.
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use clap::ValueEnum;
+use image::{imageops::FilterType, DynamicImage, RgbImage};
+
+/// Largest preview drawn, in pixels, so big photos don't scroll the response off screen.
+const MAX_PREVIEW_WIDTH: u32 = 640;
+const MAX_PREVIEW_HEIGHT: u32 = 480;
+
+/// The kitty protocol limits each escape sequence to 4096 bytes of payload.
+const KITTY_CHUNK: usize = 4096;
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum ImageProtocol {
+    /// Pick from the terminal's environment variables
+    Auto,
+    /// Kitty graphics protocol (kitty, WezTerm, Ghostty, Konsole)
+    Kitty,
+    /// Sixel graphics (foot, mlterm, WezTerm, xterm -ti vt340)
+    Sixel,
+    /// Don't draw images
+    None,
+}
+
+impl ImageProtocol {
+    /// Resolve `Auto` from the environment. Terminals can't be asked reliably without
+    /// reading a reply from stdin, so this errs towards `None`.
+    pub fn resolve(self) -> ImageProtocol {
+        if self != ImageProtocol::Auto {
+            return self;
+        }
+        let var = |name: &str| std::env::var(name).unwrap_or_default();
+        let term = var("TERM");
+        let program = var("TERM_PROGRAM");
+
+        if !var("KITTY_WINDOW_ID").is_empty()
+            || term.contains("kitty")
+            || term.contains("ghostty")
+            || matches!(program.as_str(), "WezTerm" | "ghostty")
+        {
+            ImageProtocol::Kitty
+        } else if term.contains("sixel") || term.starts_with("foot") || term.starts_with("mlterm") {
+            ImageProtocol::Sixel
+        } else {
+            ImageProtocol::None
+        }
+    }
+}
+
+/// Escape sequence drawing `data` (any supported image format) with `protocol`, or an
+/// empty sequence for `None`.
+pub fn render(protocol: ImageProtocol, data: &[u8]) -> Result<Vec<u8>, String> {
+    if matches!(protocol, ImageProtocol::None | ImageProtocol::Auto) {
+        return Ok(Vec::new());
+    }
+
+    let image = image::load_from_memory(data).map_err(|e| format!("Failed to decode image: {}", e))?;
+    let image = if image.width() > MAX_PREVIEW_WIDTH || image.height() > MAX_PREVIEW_HEIGHT {
+        image.resize(MAX_PREVIEW_WIDTH, MAX_PREVIEW_HEIGHT, FilterType::Triangle)
+    } else {
+        image
+    };
+
+    Ok(match protocol {
+        ImageProtocol::Kitty => kitty_sequence(&image),
+        _ => sixel_sequence(&image.to_rgb8()),
+    })
+}
+
+/// Transmit raw RGBA pixels and display them at the cursor, split into protocol-sized chunks.
+fn kitty_sequence(image: &DynamicImage) -> Vec<u8> {
+    let rgba = image.to_rgba8();
+    let encoded = BASE64.encode(rgba.as_raw());
+    let chunks: Vec<&[u8]> = encoded.as_bytes().chunks(KITTY_CHUNK).collect();
+
+    let mut out = Vec::with_capacity(encoded.len() + chunks.len() * 16 + 64);
+    for (i, chunk) in chunks.iter().enumerate() {
+        let more = u8::from(i + 1 < chunks.len());
+        if i == 0 {
+            out.extend_from_slice(
+                format!("\x1b_Ga=T,q=2,f=32,s={},v={},m={};", rgba.width(), rgba.height(), more).as_bytes(),
+            );
+        } else {
+            out.extend_from_slice(format!("\x1b_Gm={};", more).as_bytes());
+        }
+        out.extend_from_slice(chunk);
+        out.extend_from_slice(b"\x1b\\");
+    }
+    out.push(b'\n');
+    out
+}
+
+/// Index into the 6x6x6 color cube used as the sixel palette.
+fn cube_index(pixel: &image::Rgb<u8>) -> usize {
+    let level = |c: u8| (c as usize * 5 + 127) / 255;
+    level(pixel[0]) * 36 + level(pixel[1]) * 6 + level(pixel[2])
+}
+
+/// Encode as sixel with a fixed 216-color palette, which is plenty for a preview and
+/// avoids a quantization pass.
+fn sixel_sequence(image: &RgbImage) -> Vec<u8> {
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    let mut out = format!("\x1bPq\"1;1;{};{}", width, height).into_bytes();
+
+    for index in 0..216 {
+        let percent = |level: usize| level * 100 / 5;
+        out.extend_from_slice(
+            format!("#{};2;{};{};{}", index, percent(index / 36), percent(index / 6 % 6), percent(index % 6)).as_bytes(),
+        );
+    }
+
+    let colors: Vec<usize> = image.pixels().map(cube_index).collect();
+    for band in (0..height).step_by(6) {
+        let rows = (height - band).min(6);
+        let mut used = [false; 216];
+        for y in band..band + rows {
+            for &color in &colors[y * width..(y + 1) * width] {
+                used[color] = true;
+            }
+        }
+
+        for color in (0..216).filter(|&c| used[c]) {
+            out.extend_from_slice(format!("#{}", color).as_bytes());
+            let column = |x: usize| {
+                let bits = (0..rows).filter(|&dy| colors[(band + dy) * width + x] == color);
+                bits.fold(0u8, |acc, dy| acc | (1 << dy)) + 63
+            };
+            push_sixel_runs(&mut out, (0..width).map(column));
+            out.push(b'$');
+        }
+        out.push(b'-');
+    }
+
+    out.extend_from_slice(b"\x1b\\\n");
+    out
+}
+
+/// Append sixel characters, run-length encoding repeats as `!<count><char>`.
+fn push_sixel_runs(out: &mut Vec<u8>, sixels: impl Iterator<Item = u8>) {
+    let flush = |out: &mut Vec<u8>, sixel: u8, count: usize| match count {
+        0 => {}
+        1..=3 => out.extend(std::iter::repeat_n(sixel, count)),
+        n => out.extend_from_slice(format!("!{}{}", n, sixel as char).as_bytes()),
+    };
+
+    let mut current = (0u8, 0usize);
+    for sixel in sixels {
+        if sixel == current.0 {
+            current.1 += 1;
+        } else {
+            flush(out, current.0, current.1);
+            current = (sixel, 1);
+        }
+    }
+    flush(out, current.0, current.1);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sixel_runs() {
+        let mut out = Vec::new();
+        push_sixel_runs(&mut out, [b'~', b'~', b'?', b'?', b'?', b'?', b'?', b'@'].into_iter());
+        assert_eq!(out, b"~~!5?@");
+    }
+
+    #[test]
+    fn test_sixel_solid_image() {
+        let image = RgbImage::from_pixel(10, 7, image::Rgb([255, 0, 0]));
+        let sixel = String::from_utf8(sixel_sequence(&image)).unwrap();
+
+        assert!(sixel.starts_with("\x1bPq\"1;1;10;7"));
+        // Pure red is cube entry 5*36 = 180: a full band of six rows, then one row
+        assert!(sixel.contains("#180!10~$-#180!10@$-"));
+        assert!(sixel.ends_with("\x1b\\\n"));
+    }
+
+    #[test]
+    fn test_kitty_chunks() {
+        let image = DynamicImage::ImageRgba8(image::RgbaImage::new(64, 64));
+        let sequence = String::from_utf8(kitty_sequence(&image)).unwrap();
+
+        // 64*64*4 bytes is 21848 base64 characters: six chunks, only the last has m=0
+        assert!(sequence.starts_with("\x1b_Ga=T,q=2,f=32,s=64,v=64,m=1;"));
+        assert_eq!(sequence.matches("\x1b_G").count(), 6);
+        assert_eq!(sequence.matches("m=0;").count(), 1);
+    }
+
+    #[test]
+    fn test_render_none_is_empty() {
+        assert!(render(ImageProtocol::None, b"not an image").unwrap().is_empty());
+        assert!(render(ImageProtocol::Sixel, b"not an image").is_err());
+    }
+}
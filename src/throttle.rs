@@ -0,0 +1,80 @@
+//! `--rpm` and `--rps`: a cap on how often model requests are sent, whatever the
+//! concurrency, for hosted APIs with per-minute quotas and shared servers.
+//!
+//! Requests are spaced evenly rather than let through in bursts, so a quota counted over
+//! any sliding minute is never exceeded.
+
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Spaces requests at least `interval` apart, across every backend and thread it is shared with.
+#[derive(Debug)]
+pub struct Throttle {
+    interval: Duration,
+    /// When the next request may go
+    next: Mutex<Option<Instant>>,
+}
+
+impl Throttle {
+    /// At most `rate` requests per minute.
+    pub fn per_minute(rate: f64) -> Result<Throttle, String> {
+        if !rate.is_finite() || rate <= 0.0 {
+            return Err(format!("Expected a rate above 0, got {}", rate));
+        }
+        Ok(Throttle {
+            interval: Duration::from_secs_f64(60.0 / rate),
+            next: Mutex::new(None),
+        })
+    }
+
+    /// At most `rate` requests per second.
+    pub fn per_second(rate: f64) -> Result<Throttle, String> {
+        Throttle::per_minute(rate * 60.0)
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Take the next slot, returning how long to wait for it.
+    fn reserve(&self, now: Instant) -> Duration {
+        let mut next = self.next.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let at = next.map_or(now, |next| next.max(now));
+        *next = Some(at + self.interval);
+        at - now
+    }
+
+    /// Block until a request may be sent.
+    pub fn wait(&self) {
+        let delay = self.reserve(Instant::now());
+        if !delay.is_zero() {
+            thread::sleep(delay);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_requests_are_spaced_evenly() {
+        let throttle = Throttle::per_second(2.0).unwrap();
+        assert_eq!(throttle.interval(), Duration::from_millis(500));
+
+        let start = Instant::now();
+        assert_eq!(throttle.reserve(start), Duration::ZERO);
+        assert_eq!(throttle.reserve(start), Duration::from_millis(500));
+        assert_eq!(throttle.reserve(start + Duration::from_millis(200)), Duration::from_millis(800));
+        // After a quiet spell the next request goes straight away
+        assert_eq!(throttle.reserve(start + Duration::from_secs(10)), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_rate_must_be_positive() {
+        assert_eq!(Throttle::per_minute(30.0).unwrap().interval(), Duration::from_secs(2));
+        assert!(Throttle::per_minute(0.0).is_err());
+        assert!(Throttle::per_second(f64::NAN).is_err());
+    }
+}
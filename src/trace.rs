@@ -0,0 +1,191 @@
+//! `--trace-dir`: each model request as sent and the server's raw response, one file per
+//! request, so odd model behavior can be reproduced and reported upstream.
+//!
+//! Image data is replaced by its size and SHA-256 unless `--trace-images` asks for it,
+//! which keeps traces small and free of the images themselves.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::images::sha256_hex;
+
+/// Writes trace files into a directory.
+#[derive(Debug)]
+pub struct Tracer {
+    dir: PathBuf,
+    /// Keep image data in the traced requests
+    images: bool,
+    /// Requests traced so far, to tell apart files written in the same millisecond
+    count: AtomicU64,
+}
+
+/// One traced request and what came back.
+#[derive(Serialize)]
+struct Trace<'a> {
+    time: String,
+    url: &'a str,
+    elapsed_ms: u64,
+    request: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<u16>,
+    /// The response body exactly as received
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response: Option<&'a str>,
+    /// Why no response came back
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'a str>,
+}
+
+impl Tracer {
+    /// Trace into `dir`, creating it if needed.
+    pub fn new(dir: &Path, images: bool) -> Result<Tracer, String> {
+        fs::create_dir_all(dir).map_err(|e| format!("Failed to create trace directory '{}': {}", dir.display(), e))?;
+        Ok(Tracer {
+            dir: dir.to_path_buf(),
+            images,
+            count: AtomicU64::new(0),
+        })
+    }
+
+    /// Write a trace of the JSON body `request` sent to `url` with `images` attached, and the
+    /// status and body of the response, or the error if there was none. Returns the file
+    /// written.
+    pub fn write(
+        &self,
+        url: &str,
+        request: &[u8],
+        images: &[&[u8]],
+        response: Result<(u16, &str), &str>,
+        elapsed: Duration,
+    ) -> Result<PathBuf, String> {
+        let now = SystemTime::now();
+        let mut request: Value =
+            serde_json::from_slice(request).map_err(|e| format!("Failed to trace a request that isn't JSON: {}", e))?;
+        if !self.images {
+            redact_images(&mut request, images);
+        }
+        let (status, response, error) = match response {
+            Ok((status, body)) => (Some(status), Some(body), None),
+            Err(error) => (None, None, Some(error)),
+        };
+        let trace = Trace {
+            time: utc_timestamp(now),
+            url,
+            elapsed_ms: elapsed.as_millis() as u64,
+            request,
+            status,
+            response,
+            error,
+        };
+
+        let count = self.count.fetch_add(1, Ordering::Relaxed) + 1;
+        let stamp: String = trace.time.chars().filter(|c| c.is_ascii_alphanumeric() || *c == '.').collect();
+        let path = self.dir.join(format!("{}-{:06}.json", stamp, count));
+        let json = serde_json::to_string_pretty(&trace).expect("traces serialize");
+        fs::write(&path, json).map_err(|e| format!("Failed to write trace '{}': {}", path.display(), e))?;
+        Ok(path)
+    }
+}
+
+/// Replace each image in `request`, as Ollama's base64 or OpenAI's data URL, with a note
+/// of its size and hash.
+pub(crate) fn redact_images(request: &mut Value, images: &[&[u8]]) {
+    if images.is_empty() {
+        return;
+    }
+    let encoded: Vec<(String, String)> = images
+        .iter()
+        .map(|data| (BASE64.encode(data), format!("<image: {} bytes, sha256 {}>", data.len(), sha256_hex(data))))
+        .collect();
+    redact(request, &encoded);
+}
+
+fn redact(value: &mut Value, encoded: &[(String, String)]) {
+    match value {
+        Value::String(text) => {
+            let data = text.split_once(";base64,").map_or(text.as_str(), |(_, data)| data);
+            if let Some((_, note)) = encoded.iter().find(|(base64, _)| base64 == data) {
+                *text = note.clone();
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(|item| redact(item, encoded)),
+        Value::Object(fields) => fields.values_mut().for_each(|field| redact(field, encoded)),
+        _ => {}
+    }
+}
+
+/// A time as UTC `YYYY-MM-DDTHH:MM:SS.mmmZ`.
+pub fn utc_timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs() as i64;
+
+    // Civil-from-days conversion (Howard Hinnant's algorithm)
+    let z = secs.div_euclid(86_400) + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    let time_of_day = secs.rem_euclid(86_400);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        time_of_day / 3600,
+        time_of_day / 60 % 60,
+        time_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_utc_timestamp() {
+        assert_eq!(utc_timestamp(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+        let leap_day = UNIX_EPOCH + Duration::from_millis(951_867_296_789);
+        assert_eq!(utc_timestamp(leap_day), "2000-02-29T23:34:56.789Z");
+    }
+
+    #[test]
+    fn test_trace_redacts_images() {
+        let dir = std::env::temp_dir().join(format!("nineladies_trace_{}", std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        let image: &[u8] = b"not really a png";
+        let request = json!({"messages": [
+            {"role": "user", "content": "Describe this.", "images": [BASE64.encode(image)]},
+            {"content": [{"type": "image_url", "image_url": {"url": format!("data:image/png;base64,{}", BASE64.encode(image))}}]},
+        ]});
+        let body = serde_json::to_vec(&request).unwrap();
+
+        let tracer = Tracer::new(&dir, false).unwrap();
+        let path = tracer.write("http://x/api/chat", &body, &[image], Ok((200, "{\"raw\": 1}")), Duration::ZERO).unwrap();
+        let trace: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        let note = format!("<image: 16 bytes, sha256 {}>", sha256_hex(image));
+        assert_eq!(trace["request"]["messages"][0]["images"][0], json!(note));
+        assert_eq!(trace["request"]["messages"][1]["content"][0]["image_url"]["url"], json!(note));
+        assert_eq!((trace["status"].clone(), trace["response"].clone()), (json!(200), json!("{\"raw\": 1}")));
+
+        let tracer = Tracer::new(&dir, true).unwrap();
+        let path = tracer.write("http://x/api/chat", &body, &[image], Err("refused"), Duration::ZERO).unwrap();
+        let trace: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(trace["request"], request);
+        assert_eq!(trace["error"], "refused");
+        assert!(trace.get("status").is_none());
+        fs::remove_dir_all(&dir).ok();
+    }
+}
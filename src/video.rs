@@ -0,0 +1,131 @@
+//! `9ladies video`: describing frames taken from a video at a fixed interval, then merging
+//! the frame descriptions into one summary of the whole video with a text-only request.
+//!
+//! Frames are extracted with the `ffmpeg` command line tool into a temporary folder.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::prompt::PromptConfig;
+
+/// The question for the summary pass, followed by the timestamped frame descriptions.
+pub const SUMMARY_PROMPT: &str = "Below are descriptions of frames taken from one video, in order, each \
+with its time in the video. Merge them into a single coherent summary of the whole video: what happens, \
+who or what appears and how the scene changes. Don't describe the frames one by one, and don't invent \
+events between them. Reply with only a JSON object: {\"summary\": \"<a paragraph>\", \"timeline\": \
+[{\"time\": \"<mm:ss>\", \"event\": \"<what happens from then>\"}]}";
+
+/// The summary pass's prompt config when `--summary-prompt` doesn't give one.
+pub fn summary_config() -> PromptConfig {
+    PromptConfig {
+        system: "You summarize videos from descriptions of their frames.".to_string(),
+        prompt: SUMMARY_PROMPT.to_string(),
+        temperature: 0.2,
+        model: None,
+        schema: None,
+        seed: None,
+        think: None,
+        schema_via: None,
+    }
+}
+
+/// `config`'s question with the frame descriptions appended, one per line as `[mm:ss] text`.
+pub fn summary_prompt(config: &PromptConfig, frames: &[(f64, String)]) -> String {
+    let lines: Vec<String> = frames
+        .iter()
+        .map(|(time, description)| format!("[{}] {}", format_time(*time), description.replace('\n', " ")))
+        .collect();
+    format!("{}\n\n{}", config.prompt, lines.join("\n"))
+}
+
+/// Seconds as `mm:ss`, or `h:mm:ss` from an hour in.
+pub fn format_time(seconds: f64) -> String {
+    let total = seconds.max(0.0).round() as u64;
+    let (hours, minutes, seconds) = (total / 3600, total / 60 % 60, total % 60);
+    if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, seconds)
+    } else {
+        format!("{:02}:{:02}", minutes, seconds)
+    }
+}
+
+/// Runs `ffmpeg` to take frames out of videos.
+#[derive(Clone, Debug)]
+pub struct FrameExtractor {
+    program: String,
+    /// Seconds between frames
+    every: f64,
+}
+
+impl FrameExtractor {
+    pub fn new(program: &str, every: f64) -> FrameExtractor {
+        FrameExtractor {
+            program: program.to_string(),
+            every,
+        }
+    }
+
+    /// Check that the program runs, so a missing install is reported once up front.
+    pub fn check(&self) -> Result<(), String> {
+        let output = Command::new(&self.program)
+            .arg("-version")
+            .stdin(Stdio::null())
+            .output()
+            .map_err(|e| format!("Cannot run '{}': {}", self.program, e))?;
+        if !output.status.success() {
+            return Err(format!("'{} -version' failed with {}", self.program, output.status));
+        }
+        Ok(())
+    }
+
+    /// Write a JPEG frame every `every` seconds into `dir`, returning each frame's time in
+    /// the video and path, in order.
+    pub fn extract(&self, video: &Path, dir: &Path) -> Result<Vec<(f64, PathBuf)>, String> {
+        fs::create_dir_all(dir).map_err(|e| format!("Failed to create '{}': {}", dir.display(), e))?;
+        let output = Command::new(&self.program)
+            .args(["-nostdin", "-v", "error", "-i"])
+            .arg(video)
+            .args(["-vf", &format!("fps=1/{}", self.every), "-q:v", "2"])
+            .arg(dir.join("frame_%06d.jpg"))
+            .stdin(Stdio::null())
+            .output()
+            .map_err(|e| format!("Cannot run '{}': {}", self.program, e))?;
+        if !output.status.success() {
+            return Err(format!(
+                "Failed to extract frames from '{}': {}",
+                video.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+
+        let mut frames: Vec<PathBuf> = fs::read_dir(dir)
+            .map_err(|e| format!("Failed to read '{}': {}", dir.display(), e))?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "jpg"))
+            .collect();
+        frames.sort();
+        // The fps filter puts frame n (from 1) at (n - 1) intervals into the video
+        Ok(frames.into_iter().enumerate().map(|(i, path)| (i as f64 * self.every, path)).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_time() {
+        assert_eq!(format_time(0.0), "00:00");
+        assert_eq!(format_time(75.4), "01:15");
+        assert_eq!(format_time(3725.0), "1:02:05");
+    }
+
+    #[test]
+    fn test_summary_prompt_lists_frames() {
+        let frames = vec![(0.0, "A kitchen.".to_string()), (90.0, "Someone\nopens the fridge.".to_string())];
+        let prompt = summary_prompt(&summary_config(), &frames);
+        assert!(prompt.starts_with(SUMMARY_PROMPT));
+        assert!(prompt.ends_with("\n\n[00:00] A kitchen.\n[01:30] Someone opens the fridge."));
+    }
+}